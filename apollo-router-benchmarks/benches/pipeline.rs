@@ -0,0 +1,82 @@
+use apollo_router_core::plugin::profiling::{self, CountingAllocator};
+use apollo_router_core::{BridgeQueryPlanner, QueryPlanOptions, QueryPlanner};
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::future::join_all;
+
+include!("../src/shared.rs");
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of requests sent at once by the load test.
+const CONCURRENT_REQUESTS: usize = 100;
+
+/// Prints what each stage cost per request during the benchmark `name`.
+fn report(name: &str) {
+    let mut profiles = profiling::profiles().into_iter().collect::<Vec<_>>();
+    profiles.sort_by_key(|(stage, _)| stage.as_str());
+    println!("{}: per-stage profile", name);
+    for (stage, profile) in profiles {
+        let calls = profile.calls.max(1);
+        println!(
+            "  {:<15} {:>8} calls {:>12?} polling {:>8} allocations {:>10} bytes",
+            stage.as_str(),
+            profile.calls,
+            profile.poll_time_per_call(),
+            profile.allocations / calls,
+            profile.allocated_bytes / calls,
+        );
+    }
+}
+
+fn query_planning(c: &mut Criterion) {
+    c.bench_function("query_planning", move |b| {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let schema: Arc<Schema> =
+            Arc::new(include_str!("fixtures/supergraph.graphql").parse().unwrap());
+        // Without the caching planner, every iteration plans the operation again.
+        let planner = runtime.block_on(BridgeQueryPlanner::new(schema)).unwrap();
+
+        b.to_async(runtime).iter(|| async {
+            planner
+                .get(
+                    QUERY.to_string(),
+                    Some("TopProducts".to_string()),
+                    QueryPlanOptions::default(),
+                )
+                .await
+                .unwrap()
+        });
+    });
+}
+
+fn execution(c: &mut Criterion) {
+    c.bench_function("execution", move |b| {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (router, _) = runtime.block_on(setup().build()).unwrap();
+        // The first request plans the operation, the following ones hit the cache.
+        runtime.block_on(basic_composition_benchmark(router.clone()));
+
+        profiling::reset();
+        b.to_async(runtime)
+            .iter(|| basic_composition_benchmark(router.clone()));
+    });
+    report("execution");
+}
+
+fn concurrent_load(c: &mut Criterion) {
+    c.bench_function("concurrent_load", move |b| {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (router, _) = runtime.block_on(setup().build()).unwrap();
+        runtime.block_on(basic_composition_benchmark(router.clone()));
+
+        profiling::reset();
+        b.to_async(runtime).iter(|| {
+            join_all((0..CONCURRENT_REQUESTS).map(|_| basic_composition_benchmark(router.clone())))
+        });
+    });
+    report("concurrent_load");
+}
+
+criterion_group!(benches, query_planning, execution, concurrent_load);
+criterion_main!(benches);
@@ -1,7 +1,9 @@
-use crate::{CacheResolver, CacheResolverError};
+use crate::{CacheResolver, CacheResolverError, Context};
 use derivative::Derivative;
 use futures::lock::Mutex;
 use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use std::cmp::Eq;
 use std::collections::HashMap;
 use std::fmt;
@@ -9,6 +11,49 @@ use std::hash::Hash;
 use std::sync::{Arc, Weak};
 use tokio::sync::broadcast::{self, Sender};
 
+/// Context key holding the [`CacheLookups`] made for a request, by cache name.
+pub const CACHE_LOOKUPS: &str = "apollo::cache::lookups";
+
+/// Lookups made in each cache since the router started, by cache name.
+static CACHE_TOTALS: Lazy<std::sync::Mutex<HashMap<String, CacheLookups>>> =
+    Lazy::new(Default::default);
+
+/// Hits and misses of a cache while serving a request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheLookups {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Counts lookups made in `cache` for the request of `context`, so that telemetry can export the
+/// hit ratio of each cache once the request completes. The lookups also add up in
+/// [`cache_statistics`].
+pub fn record_cache_lookups(context: &Context, cache: &str, hits: u64, misses: u64) {
+    {
+        let mut totals = CACHE_TOTALS.lock().expect("lock poisoned");
+        let total = totals.entry(cache.to_string()).or_default();
+        total.hits += hits;
+        total.misses += misses;
+    }
+    if let Err(err) = context.upsert(
+        CACHE_LOOKUPS,
+        |mut lookups: HashMap<String, CacheLookups>| {
+            let entry = lookups.entry(cache.to_string()).or_default();
+            entry.hits += hits;
+            entry.misses += misses;
+            lookups
+        },
+        HashMap::new,
+    ) {
+        tracing::debug!("could not record cache lookups: {}", err);
+    }
+}
+
+/// Lookups made in each cache since the router started, across all requests.
+pub fn cache_statistics() -> HashMap<String, CacheLookups> {
+    CACHE_TOTALS.lock().expect("lock poisoned").clone()
+}
+
 /// A caching map optimised for slow value resolution.
 ///
 /// The CachingMap hold values in an LruCache. Values are loaded into the cache on a cache miss and
@@ -140,6 +185,11 @@ where
         }
     }
 
+    /// Whether a value is cached for `key`, without resolving it or changing its recency.
+    pub async fn contains(&self, key: &K) -> bool {
+        self.cached.lock().await.contains(key)
+    }
+
     /// Get the top 20% of most recently (LRU) used keys
     pub async fn get_hot_keys(&self) -> Vec<K> {
         let locked_cache = self.cached.lock().await;
@@ -229,4 +279,33 @@ mod tests {
         let guard = cache.cm.cached.lock().await;
         assert_eq!(guard.len(), 1);
     }
+
+    #[test(tokio::test)]
+    async fn it_should_tell_cached_keys() {
+        let cache = HasACache::new(Box::new(HasACacheResolver {}), 10);
+        assert!(!cache.cm.contains(&1).await);
+        cache.get(1).await.expect("gets the value");
+        assert!(cache.cm.contains(&1).await);
+    }
+
+    #[test]
+    fn lookups_add_up_by_cache() {
+        let context = Context::new();
+        record_cache_lookups(&context, "entity", 2, 1);
+        record_cache_lookups(&context, "entity", 1, 0);
+        record_cache_lookups(&context, "query_plan", 0, 1);
+        let lookups: HashMap<String, CacheLookups> = context.get(CACHE_LOOKUPS).unwrap().unwrap();
+        assert_eq!(lookups["entity"], CacheLookups { hits: 3, misses: 1 });
+        assert_eq!(lookups["query_plan"], CacheLookups { hits: 0, misses: 1 });
+    }
+
+    #[test]
+    fn lookups_add_up_across_requests() {
+        record_cache_lookups(&Context::new(), "test_totals", 1, 1);
+        record_cache_lookups(&Context::new(), "test_totals", 2, 0);
+        assert_eq!(
+            cache_statistics()["test_totals"],
+            CacheLookups { hits: 3, misses: 1 }
+        );
+    }
 }
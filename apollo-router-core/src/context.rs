@@ -9,6 +9,20 @@ use serde::Serialize;
 use std::sync::Arc;
 use tower::BoxError;
 
+/// Context key holding the name of the client that sent a request, when it identified itself.
+pub const CLIENT_NAME_CONTEXT_KEY: &str = "apollo::client::name";
+
+/// Context key holding the version of the client that sent a request, when it identified itself.
+pub const CLIENT_VERSION_CONTEXT_KEY: &str = "apollo::client::version";
+
+/// Context key holding the correlation ID of a request, taken from its headers or generated by the
+/// HTTP server.
+pub const CORRELATION_ID_CONTEXT_KEY: &str = "apollo::correlation_id";
+
+/// Context key holding the entries added to the `extensions` of the response with
+/// [`Context::insert_extension`].
+pub const RESPONSE_EXTENSIONS_CONTEXT_KEY: &str = "apollo::response::extensions";
+
 /// Holds [`Context`] entries.
 pub type Entries = Arc<DashMap<String, Value>>;
 
@@ -89,6 +103,26 @@ impl Context {
             });
         result.map_err(|e| e.into())
     }
+
+    /// Adds `value` under `name` in the `extensions` of the GraphQL response to the request.
+    ///
+    /// The router adds them once the response is complete, replacing any extension of the same
+    /// name, so plugins can call this at any stage.
+    pub fn insert_extension<V>(&self, name: impl Into<String>, value: V) -> Result<(), BoxError>
+    where
+        V: Serialize,
+    {
+        let name = name.into();
+        let value = serde_json_bytes::to_value(value)?;
+        self.upsert(
+            RESPONSE_EXTENSIONS_CONTEXT_KEY,
+            |mut extensions: Object| {
+                extensions.insert(name.clone(), value.clone());
+                extensions
+            },
+            Object::default,
+        )
+    }
 }
 
 impl Default for Context {
@@ -126,6 +160,22 @@ mod test {
         assert_eq!(c.get("not_present").unwrap(), Some(1));
     }
 
+    #[test]
+    fn test_context_insert_extension() {
+        let c = Context::new();
+        assert!(c.insert_extension("traceId", "abc").is_ok());
+        assert!(c.insert_extension("cost", 12).is_ok());
+        assert!(c.insert_extension("traceId", "def").is_ok());
+        let extensions: crate::Object = c
+            .get(super::RESPONSE_EXTENSIONS_CONTEXT_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json_bytes::Value::Object(extensions),
+            serde_json_bytes::json!({ "traceId": "def", "cost": 12 })
+        );
+    }
+
     #[test]
     fn test_context_marshall_errors() {
         let c = Context::new();
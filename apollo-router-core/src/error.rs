@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::task::JoinError;
+use tower::BoxError;
 use tracing::level_filters::LevelFilter;
 use typed_builder::TypedBuilder;
 
@@ -73,6 +74,54 @@ pub enum FetchError {
         reason: String,
     },
 
+    /// service '{service}' response is larger than the limit of {limit} bytes
+    SubrequestResponseTooLarge {
+        /// The service that responded with the oversized response.
+        service: String,
+
+        /// The limit, in bytes.
+        limit: usize,
+    },
+
+    /// service '{service}' answered with gRPC status {code}: {message}
+    SubrequestGrpcError {
+        /// The gRPC subgraph.
+        service: String,
+
+        /// The name of the status, such as `UNAVAILABLE`.
+        code: String,
+
+        /// The message of the status.
+        message: String,
+    },
+
+    /// service '{service}' has no REST endpoint for '{field}'
+    SubrequestUnmappedField {
+        /// The REST subgraph.
+        service: String,
+
+        /// The root field, or the entity type, without an endpoint.
+        field: String,
+    },
+
+    /// service '{service}' response does not match the schema at '{path}'
+    SubrequestInvalidResponse {
+        /// The service that responded with the invalid response.
+        service: String,
+
+        /// The path of the first invalid value in the response.
+        path: String,
+    },
+
+    /// subscription to service '{service}' failed: {reason}
+    SubrequestSubscriptionError {
+        /// The service the subscription was sent to.
+        service: String,
+
+        /// The reason the subscription failed.
+        reason: String,
+    },
+
     /// subquery requires field '{field}' but it was not found in the current response
     ExecutionFieldNotFound {
         /// The field that is not found.
@@ -175,6 +224,34 @@ impl Error {
             extensions,
         })
     }
+
+    /// The `code` of the extensions of the error.
+    pub fn code(&self) -> Option<&str> {
+        self.extensions.get("code").and_then(|code| code.as_str())
+    }
+
+    /// Converts the failure of a pipeline stage into the error sent to the client.
+    ///
+    /// Fetch errors keep their details in the extensions. Query planning failures get the
+    /// `QUERY_PLANNING_FAILED` code, and errors without a code the `INTERNAL_SERVER_ERROR` one.
+    pub fn from_box_error(error: &BoxError) -> Error {
+        let mut graphql_error = match error.downcast_ref::<FetchError>() {
+            Some(fetch_error) => fetch_error.to_graphql_error(None),
+            None => Error {
+                message: error.to_string(),
+                ..Default::default()
+            },
+        };
+        if graphql_error.code().is_none() {
+            let code = if error.is::<QueryPlannerError>() || error.is::<CacheResolverError>() {
+                "QUERY_PLANNING_FAILED"
+            } else {
+                "INTERNAL_SERVER_ERROR"
+            };
+            graphql_error.extensions.insert("code", code.into());
+        }
+        graphql_error
+    }
 }
 
 /// A location in the request that triggered a graphql error.
@@ -372,3 +449,28 @@ impl ParseErrors {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_errors_are_given_a_code() {
+        let error = Error::from_box_error(&BoxError::from(QueryPlannerError::EmptyPlan));
+        assert_eq!(error.code(), Some("QUERY_PLANNING_FAILED"));
+
+        let error = Error::from_box_error(&BoxError::from(FetchError::SubrequestHttpError {
+            service: "accounts".to_string(),
+            reason: "connection refused".to_string(),
+        }));
+        assert_eq!(error.code(), Some("INTERNAL_SERVER_ERROR"));
+        assert_eq!(
+            error.extensions.get("service"),
+            Some(&Value::from("accounts"))
+        );
+
+        let error = Error::from_box_error(&BoxError::from("timeout"));
+        assert_eq!(error.message, "timeout");
+        assert_eq!(error.code(), Some("INTERNAL_SERVER_ERROR"));
+    }
+}
@@ -2,7 +2,9 @@ use crate::prelude::graphql::*;
 use include_dir::include_dir;
 use once_cell::sync::Lazy;
 use router_bridge::introspect::{self, IntrospectionError};
-use std::collections::HashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use tokio::sync::RwLock;
 
 /// KNOWN_INTROSPECTION_QUERIES we will serve through NaiveIntrospection.
@@ -24,6 +26,41 @@ static KNOWN_INTROSPECTION_QUERIES: Lazy<Vec<String>> = Lazy::new(|| {
         .collect()
 });
 
+/// Clients allowed to introspect the schema while introspection is disabled.
+///
+/// Clients are identified by a claim of their verified token, which the authentication plugin
+/// stores in the request context, so that they cannot claim to be someone else.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IntrospectionAllowlist {
+    /// Claim of the verified token identifying the client. Defaults to `sub`.
+    #[serde(default = "default_client_claim")]
+    pub claim: String,
+    /// Values of the claim of the allowed clients.
+    pub clients: HashSet<String>,
+}
+
+fn default_client_claim() -> String {
+    "sub".to_string()
+}
+
+impl IntrospectionAllowlist {
+    /// Whether the authenticated client of the request of `context` is allowed to introspect.
+    pub fn allows(&self, context: &Context) -> bool {
+        context
+            .get::<_, serde_json::Value>(AUTHENTICATION_CLAIMS_CONTEXT_KEY)
+            .ok()
+            .flatten()
+            .and_then(|claims| {
+                claims
+                    .get(&self.claim)
+                    .and_then(|client| client.as_str())
+                    .map(|client| self.clients.contains(client))
+            })
+            .unwrap_or_default()
+    }
+}
+
 /// A cache containing our well known introspection queries.
 #[derive(Debug)]
 pub struct Introspection {
@@ -164,6 +201,28 @@ mod naive_introspection_tests {
         );
     }
 
+    #[test]
+    fn allowlisted_clients_may_introspect() {
+        let allowlist: IntrospectionAllowlist = serde_yaml::from_str("clients: [studio]").unwrap();
+        let authenticated = |claims: serde_json::Value| {
+            let context = Context::new();
+            context
+                .insert(AUTHENTICATION_CLAIMS_CONTEXT_KEY, claims)
+                .unwrap();
+            context
+        };
+
+        assert!(allowlist.allows(&authenticated(serde_json::json!({ "sub": "studio" }))));
+        assert!(!allowlist.allows(&authenticated(serde_json::json!({ "sub": "web" }))));
+        assert!(!allowlist.allows(&authenticated(serde_json::json!({ "name": "studio" }))));
+        // The client name header is not trusted.
+        let context = Context::new();
+        context
+            .insert(CLIENT_NAME_CONTEXT_KEY, "studio".to_string())
+            .unwrap();
+        assert!(!allowlist.allows(&context));
+    }
+
     #[test]
     fn test_known_introspection_queries() {
         // this only makes sure KNOWN_INTROSPECTION_QUERIES get created correctly.
@@ -4,8 +4,13 @@
 //!  <https://www.apollographql.com/docs/apollo-server/performance/apq/>
 
 use std::ops::ControlFlow;
+use std::sync::Arc;
 
-use crate::{checkpoint::CheckpointService, RouterRequest, RouterResponse};
+use crate::{
+    checkpoint::AsyncCheckpointService, record_cache_lookups, CacheStorage, RouterRequest,
+    RouterResponse,
+};
+use futures::FutureExt;
 use moka::sync::Cache;
 use serde::Deserialize;
 use serde_json_bytes::{json, Value};
@@ -24,11 +29,22 @@ pub struct PersistedQuery {
 #[derive(Clone)]
 pub struct APQLayer {
     cache: Cache<Vec<u8>, String>,
+    storage: Option<Arc<dyn CacheStorage>>,
 }
 
 impl APQLayer {
     pub fn with_cache(cache: Cache<Vec<u8>, String>) -> Self {
-        Self { cache }
+        Self {
+            cache,
+            storage: None,
+        }
+    }
+
+    /// Also keeps the persisted queries in `storage`, so that a query registered through a router
+    /// instance can be used through the other instances sharing the storage.
+    pub fn with_storage(mut self, storage: Arc<dyn CacheStorage>) -> Self {
+        self.storage = Some(storage);
+        self
     }
 }
 
@@ -38,82 +54,139 @@ impl Default for APQLayer {
     }
 }
 
+fn storage_key(query_hash: &[u8]) -> String {
+    format!("apq:{}", hex::encode(query_hash))
+}
+
 impl<S> Layer<S> for APQLayer
 where
-    S: Service<RouterRequest, Response = RouterResponse> + Send + 'static,
+    S: Service<RouterRequest, Response = RouterResponse, Error = BoxError> + Clone + Send + 'static,
     <S as Service<RouterRequest>>::Future: Send + 'static,
-    <S as Service<RouterRequest>>::Error: Into<BoxError> + Send + 'static,
 {
-    type Service = CheckpointService<S, RouterRequest>;
+    type Service = AsyncCheckpointService<S, RouterRequest>;
 
     fn layer(&self, service: S) -> Self::Service {
         let cache = self.cache.clone();
-        CheckpointService::new(
+        let storage = self.storage.clone();
+        AsyncCheckpointService::new(
             move |mut req| {
-                let maybe_query_hash: Option<Vec<u8>> = req
-                    .originating_request
-                    .body()
-                    .extensions
-                    .get("persistedQuery")
-                    .and_then(|value| {
-                        serde_json_bytes::from_value::<PersistedQuery>(value.clone()).ok()
-                    })
-                    .and_then(|persisted_query| {
-                        hex::decode(persisted_query.sha256hash.as_bytes()).ok()
-                    });
-
-                let body_query = req.originating_request.body().query.clone();
-
-                match (maybe_query_hash, body_query) {
-                    (Some(query_hash), Some(query)) => {
-                        if query_matches_hash(query.as_str(), query_hash.as_slice()) {
-                            tracing::trace!("apq: cache insert");
-                            cache.insert(query_hash, query);
-                        } else {
-                            tracing::warn!(
-                                "apq: graphql request doesn't match provided sha256Hash"
-                            );
-                        }
-                        Ok(ControlFlow::Continue(req))
-                    }
-                    (Some(apq_hash), _) => {
-                        if let Some(cached_query) = cache.get(&apq_hash) {
-                            tracing::trace!("apq: cache hit");
-                            req.originating_request.body_mut().query = Some(cached_query);
+                let cache = cache.clone();
+                let storage = storage.clone();
+                async move {
+                    let maybe_query_hash: Option<Vec<u8>> = req
+                        .originating_request
+                        .body()
+                        .extensions
+                        .get("persistedQuery")
+                        .and_then(|value| {
+                            serde_json_bytes::from_value::<PersistedQuery>(value.clone()).ok()
+                        })
+                        .and_then(|persisted_query| {
+                            hex::decode(persisted_query.sha256hash.as_bytes()).ok()
+                        });
+
+                    let body_query = req.originating_request.body().query.clone();
+
+                    match (maybe_query_hash, body_query) {
+                        (Some(query_hash), Some(query)) => {
+                            if query_matches_hash(query.as_str(), query_hash.as_slice()) {
+                                tracing::trace!("apq: cache insert");
+                                if let Some(storage) = &storage {
+                                    if let Err(err) = storage
+                                        .put(
+                                            &storage_key(&query_hash),
+                                            query.clone().into_bytes(),
+                                            None,
+                                        )
+                                        .await
+                                    {
+                                        tracing::error!(
+                                            "apq: could not write to the storage: {}",
+                                            err
+                                        );
+                                    }
+                                }
+                                cache.insert(query_hash, query);
+                            } else {
+                                tracing::warn!(
+                                    "apq: graphql request doesn't match provided sha256Hash"
+                                );
+                            }
                             Ok(ControlFlow::Continue(req))
-                        } else {
-                            tracing::trace!("apq: cache miss");
-                            let errors = vec![crate::Error {
-                                message: "PersistedQueryNotFound".to_string(),
-                                locations: Default::default(),
-                                path: Default::default(),
-                                extensions: serde_json_bytes::from_value(json!({
-                                      "code": "PERSISTED_QUERY_NOT_FOUND",
-                                      "exception": {
-                                      "stacktrace": [
-                                          "PersistedQueryNotFoundError: PersistedQueryNotFound",
-                                      ],
-                                  },
-                                }))
-                                .unwrap(),
-                            }];
-                            let res = RouterResponse::builder()
-                                .data(Value::default())
-                                .errors(errors)
-                                .context(req.context)
-                                .build()
-                                .expect("response is valid");
-
-                            Ok(ControlFlow::Break(res))
                         }
+                        (Some(apq_hash), _) => {
+                            let cached_query = match cache.get(&apq_hash) {
+                                Some(query) => Some(query),
+                                None => match &storage {
+                                    Some(storage) => {
+                                        stored_query(storage.as_ref(), &cache, apq_hash).await
+                                    }
+                                    None => None,
+                                },
+                            };
+                            if let Some(cached_query) = cached_query {
+                                tracing::trace!("apq: cache hit");
+                                record_cache_lookups(&req.context, "apq", 1, 0);
+                                req.originating_request.body_mut().query = Some(cached_query);
+                                Ok(ControlFlow::Continue(req))
+                            } else {
+                                tracing::trace!("apq: cache miss");
+                                record_cache_lookups(&req.context, "apq", 0, 1);
+                                let errors = vec![crate::Error {
+                                    message: "PersistedQueryNotFound".to_string(),
+                                    locations: Default::default(),
+                                    path: Default::default(),
+                                    extensions: serde_json_bytes::from_value(json!({
+                                          "code": "PERSISTED_QUERY_NOT_FOUND",
+                                          "exception": {
+                                          "stacktrace": [
+                                              "PersistedQueryNotFoundError: PersistedQueryNotFound",
+                                          ],
+                                      },
+                                    }))
+                                    .unwrap(),
+                                }];
+                                let res = RouterResponse::builder()
+                                    .data(Value::default())
+                                    .errors(errors)
+                                    .context(req.context)
+                                    .build()
+                                    .expect("response is valid");
+
+                                Ok(ControlFlow::Break(res))
+                            }
+                        }
+                        _ => Ok(ControlFlow::Continue(req)),
                     }
-                    _ => Ok(ControlFlow::Continue(req)),
                 }
+                .boxed()
             },
             service,
         )
     }
 }
+
+/// Looks a query up in the storage, keeping it in the in-memory cache if found.
+async fn stored_query(
+    storage: &dyn CacheStorage,
+    cache: &Cache<Vec<u8>, String>,
+    query_hash: Vec<u8>,
+) -> Option<String> {
+    let bytes = match storage.get(&storage_key(&query_hash)).await {
+        Ok(bytes) => bytes?,
+        Err(err) => {
+            tracing::error!("apq: could not read from the storage: {}", err);
+            return None;
+        }
+    };
+    let query = String::from_utf8(bytes).ok()?;
+    if !query_matches_hash(&query, &query_hash) {
+        return None;
+    }
+    cache.insert(query_hash, query.clone());
+    Some(query)
+}
+
 fn query_matches_hash(query: &str, hash: &[u8]) -> bool {
     let mut digest = Sha256::new();
     digest.update(query.as_bytes());
@@ -347,4 +420,58 @@ mod apq_tests {
             panic!("expected a graphql response");
         }
     }
+
+    #[tokio::test]
+    async fn queries_are_shared_through_the_storage() {
+        let storage: Arc<dyn CacheStorage> = Arc::new(crate::InMemoryStorage::new(10));
+        let extensions = HashMap::from([(
+            "persistedQuery".to_string(),
+            json!({
+                "version" : 1,
+                "sha256Hash" : "ecf4edb46db40b5132295c0291d62fb65d6759a9eedfa4d5d612dd5ec54a6b38"
+            }),
+        )]);
+
+        let mut first_instance = MockRouterService::new();
+        first_instance.expect_call().times(1).returning(|_| {
+            Ok(RouterResponse::fake_builder()
+                .build()
+                .expect("expecting valid request"))
+        });
+        let mut second_instance = MockRouterService::new();
+        second_instance.expect_call().times(1).returning(|req| {
+            assert_eq!(
+                req.originating_request.body().query.as_deref(),
+                Some("{__typename}")
+            );
+            Ok(RouterResponse::fake_builder()
+                .build()
+                .expect("expecting valid request"))
+        });
+
+        APQLayer::default()
+            .with_storage(storage.clone())
+            .layer(first_instance.build())
+            .oneshot(
+                RouterRequest::fake_builder()
+                    .extensions(extensions.clone())
+                    .query("{__typename}".to_string())
+                    .build()
+                    .expect("expecting valid request"),
+            )
+            .await
+            .unwrap();
+
+        APQLayer::default()
+            .with_storage(storage)
+            .layer(second_instance.build())
+            .oneshot(
+                RouterRequest::fake_builder()
+                    .extensions(extensions)
+                    .build()
+                    .expect("expecting valid request"),
+            )
+            .await
+            .unwrap();
+    }
 }
@@ -0,0 +1,160 @@
+//! Fault injection for subgraph requests.
+//!
+//! Delays, fails or truncates a configurable share of the requests sent to a subgraph, to check
+//! how the rest of the router copes. Only compiled with the `chaos` feature.
+
+use crate::{FetchError, SubgraphRequest, SubgraphResponse};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::task::Poll;
+use std::time::Duration;
+use tower::{BoxError, Layer, Service};
+
+/// Probabilities, between 0 and 1, of each fault being injected into a subgraph request.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Chaos {
+    /// Probability of delaying the request by `latency_ms`.
+    #[serde(default)]
+    pub latency_probability: f64,
+    /// Latency added to delayed requests, in milliseconds.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Probability of failing the request without calling the subgraph.
+    #[serde(default)]
+    pub error_probability: f64,
+    /// Probability of dropping the subgraph response as if the connection had been cut.
+    #[serde(default)]
+    pub truncation_probability: f64,
+}
+
+pub struct ChaosLayer {
+    service_name: String,
+    chaos: Chaos,
+}
+
+impl ChaosLayer {
+    pub fn new(service_name: impl Into<String>, chaos: Chaos) -> Self {
+        Self {
+            service_name: service_name.into(),
+            chaos,
+        }
+    }
+}
+
+impl<S> Layer<S> for ChaosLayer {
+    type Service = ChaosService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ChaosService {
+            service_name: self.service_name.clone(),
+            chaos: self.chaos.clone(),
+            inner,
+        }
+    }
+}
+
+pub struct ChaosService<S> {
+    service_name: String,
+    chaos: Chaos,
+    inner: S,
+}
+
+fn happens(probability: f64) -> bool {
+    probability > 0.0 && rand::thread_rng().gen::<f64>() < probability
+}
+
+impl<S> Service<SubgraphRequest> for ChaosService<S>
+where
+    S: Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        let service = self.service_name.clone();
+        if happens(self.chaos.error_probability) {
+            return futures::future::ready(Err(FetchError::SubrequestHttpError {
+                service,
+                reason: "injected fault".to_string(),
+            }
+            .into()))
+            .boxed();
+        }
+
+        let latency = happens(self.chaos.latency_probability)
+            .then(|| Duration::from_millis(self.chaos.latency_ms));
+        let truncate = happens(self.chaos.truncation_probability);
+        let response = self.inner.call(request);
+
+        async move {
+            if let Some(latency) = latency {
+                tokio::time::sleep(latency).await;
+            }
+            let response = response.await?;
+            if truncate {
+                return Err(FetchError::SubrequestMalformedResponse {
+                    service,
+                    reason: "injected truncated response".to_string(),
+                }
+                .into());
+            }
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod chaos_tests {
+    use super::*;
+    use crate::plugin::utils::test::MockSubgraphService;
+    use tower::ServiceExt;
+
+    fn service(
+        expected_calls: usize,
+        chaos: Chaos,
+    ) -> ChaosService<tower_test::mock::Mock<SubgraphRequest, SubgraphResponse>> {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(expected_calls)
+            .returning(|_| Ok(SubgraphResponse::fake_builder().build()));
+        ChaosLayer::new("products", chaos).layer(mock.build())
+    }
+
+    #[tokio::test]
+    async fn always_injected_error_fails_the_call() {
+        let error = service(
+            0,
+            Chaos {
+                error_probability: 1.0,
+                ..Default::default()
+            },
+        )
+        .oneshot(SubgraphRequest::fake_builder().build())
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "HTTP fetch failed from 'products': injected fault"
+        );
+    }
+
+    #[tokio::test]
+    async fn no_fault_passes_through() {
+        service(1, Chaos::default())
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+//! Circuit breaker for subgraph requests.
+//!
+//! After `failure_threshold` failures in a row, the circuit opens and requests to the subgraph fail
+//! right away with a [`CircuitOpen`] error, instead of piling up on a subgraph that is down. Once
+//! `open_duration` has passed, the circuit is half open: a single request is let through as a
+//! probe, and closes the circuit if it succeeds or opens it again if it fails. A probe cancelled
+//! before it completes lets the next request probe the subgraph instead.
+//!
+//! A request fails when it returns an error, when the subgraph answers with a server error status,
+//! or when it takes longer than `slow_call_duration`, if set. The state of the circuit after each
+//! request is stored in the request context, where it can be read with [`circuit_state`] once the
+//! subgraph service has responded.
+
+use crate::{Context, SubgraphRequest, SubgraphResponse};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tower::{BoxError, Layer, Service};
+
+const CIRCUIT_STATE_CONTEXT_KEY_PREFIX: &str = "apollo::circuit_breaker::";
+
+/// State of the circuit breaker of a subgraph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// State of the circuit breaker of `subgraph`, as of the last request the context was used for.
+pub fn circuit_state(context: &Context, subgraph: &str) -> Option<CircuitState> {
+    context
+        .get(format!("{}{}", CIRCUIT_STATE_CONTEXT_KEY_PREFIX, subgraph))
+        .ok()
+        .flatten()
+}
+
+/// Error returned without contacting the subgraph while its circuit is open.
+#[derive(Debug)]
+pub struct CircuitOpen {
+    pub subgraph: String,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit breaker open for subgraph {}", self.subgraph)
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        failures: usize,
+    },
+    Open {
+        until: Instant,
+    },
+    /// The probe is in flight.
+    HalfOpen,
+}
+
+impl State {
+    fn public(&self) -> CircuitState {
+        match self {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CircuitBreakerLayer {
+    subgraph: Arc<String>,
+    failure_threshold: usize,
+    open_duration: Duration,
+    slow_call_duration: Option<Duration>,
+    state: Arc<Mutex<State>>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(
+        subgraph: impl Into<String>,
+        failure_threshold: usize,
+        open_duration: Duration,
+        slow_call_duration: Option<Duration>,
+    ) -> Self {
+        Self {
+            subgraph: Arc::new(subgraph.into()),
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            slow_call_duration,
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+        }
+    }
+
+    /// Whether a request may go through, turning an expired open circuit into a half open one.
+    fn acquire(&self) -> Result<Permit, CircuitOpen> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        match *state {
+            State::Closed { .. } => Ok(Permit {
+                layer: self.clone(),
+                probe: false,
+            }),
+            State::Open { until } if Instant::now() >= until => {
+                tracing::info!("circuit breaker half open for subgraph {}", self.subgraph);
+                *state = State::HalfOpen;
+                Ok(Permit {
+                    layer: self.clone(),
+                    probe: true,
+                })
+            }
+            State::Open { .. } | State::HalfOpen => Err(CircuitOpen {
+                subgraph: self.subgraph.to_string(),
+            }),
+        }
+    }
+
+    fn record(&self, failed: bool) -> CircuitState {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let next = match (&*state, failed) {
+            (State::HalfOpen, false) | (State::Closed { .. }, false) => {
+                State::Closed { failures: 0 }
+            }
+            // A request let through before the circuit opened does not close it.
+            (State::Open { until }, false) => State::Open { until: *until },
+            (State::Closed { failures }, true) if failures + 1 < self.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, true) => State::Open {
+                until: Instant::now() + self.open_duration,
+            },
+        };
+        if next.public() != state.public() {
+            tracing::warn!(
+                "circuit breaker {:?} for subgraph {}",
+                next.public(),
+                self.subgraph
+            );
+        }
+        *state = next;
+        state.public()
+    }
+}
+
+/// A request let through the circuit breaker, until its outcome is recorded.
+struct Permit {
+    layer: CircuitBreakerLayer,
+    probe: bool,
+}
+
+impl Permit {
+    fn record(mut self, failed: bool) -> CircuitState {
+        self.probe = false;
+        self.layer.record(failed)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.probe {
+            // The probe was cancelled: the circuit may be probed again right away.
+            let mut state = self.layer.state.lock().expect("lock poisoned");
+            if let State::HalfOpen = *state {
+                *state = State::Open {
+                    until: Instant::now(),
+                };
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            layer: self.clone(),
+            inner,
+        }
+    }
+}
+
+pub struct CircuitBreakerService<S> {
+    layer: CircuitBreakerLayer,
+    inner: S,
+}
+
+impl<S> Service<SubgraphRequest> for CircuitBreakerService<S>
+where
+    S: Service<SubgraphRequest, Response = SubgraphResponse>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        let context = request.context.clone();
+        let key = format!(
+            "{}{}",
+            CIRCUIT_STATE_CONTEXT_KEY_PREFIX, self.layer.subgraph
+        );
+        let permit = match self.layer.acquire() {
+            Ok(permit) => permit,
+            Err(err) => {
+                let _ = context.insert(key, CircuitState::Open);
+                return futures::future::ready(Err(err.into())).boxed();
+            }
+        };
+
+        let slow_call_duration = self.layer.slow_call_duration;
+        let start = Instant::now();
+        let response = self.inner.call(request);
+        async move {
+            let result = response.await.map_err(Into::into);
+            let slow = slow_call_duration
+                .map(|duration| start.elapsed() > duration)
+                .unwrap_or(false);
+            let failed = match &result {
+                Ok(response) => response.response.status().is_server_error() || slow,
+                Err(_) => true,
+            };
+            let state = permit.record(failed);
+            if let Err(err) = context.insert(key, state) {
+                tracing::error!("could not record the circuit breaker state: {}", err);
+            }
+            result
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod circuit_breaker_tests {
+    use super::*;
+    use http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn failing_subgraphs_are_cut_off_then_probed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let status = Arc::new(Mutex::new(StatusCode::SERVICE_UNAVAILABLE));
+        let subgraph = tower::service_fn({
+            let calls = calls.clone();
+            let status = status.clone();
+            move |_: SubgraphRequest| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let status = *status.lock().unwrap();
+                async move {
+                    Ok::<_, BoxError>(SubgraphResponse::fake_builder().status_code(status).build())
+                }
+            }
+        });
+        let layer = CircuitBreakerLayer::new("products", 2, Duration::from_millis(50), None);
+        let mut service = layer.layer(subgraph);
+
+        let call = |service: &mut CircuitBreakerService<_>| {
+            let context = Context::new();
+            let response = service.call(
+                SubgraphRequest::fake_builder()
+                    .context(context.clone())
+                    .build(),
+            );
+            async move { (response.await, circuit_state(&context, "products")) }
+        };
+
+        for expected in [CircuitState::Closed, CircuitState::Open] {
+            let (response, state) = call(service.ready().await.unwrap()).await;
+            assert!(response.is_ok());
+            assert_eq!(state, Some(expected));
+        }
+        let (response, state) = call(service.ready().await.unwrap()).await;
+        assert!(response.unwrap_err().is::<CircuitOpen>());
+        assert_eq!(state, Some(CircuitState::Open));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        *status.lock().unwrap() = StatusCode::OK;
+        let (response, state) = call(service.ready().await.unwrap()).await;
+        assert!(response.is_ok());
+        assert_eq!(state, Some(CircuitState::Closed));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn failed_probes_open_the_circuit_again() {
+        let layer = CircuitBreakerLayer::new("products", 1, Duration::ZERO, None);
+        assert_eq!(layer.record(true), CircuitState::Open);
+        let probe = layer.acquire().unwrap();
+        assert!(layer.acquire().is_err(), "only one probe at a time");
+        assert_eq!(probe.record(true), CircuitState::Open);
+        let probe = layer.acquire().unwrap();
+        assert_eq!(probe.record(false), CircuitState::Closed);
+    }
+
+    #[test]
+    fn stale_successes_leave_the_circuit_open() {
+        let layer = CircuitBreakerLayer::new("products", 1, Duration::from_secs(60), None);
+        let stale = layer.acquire().unwrap();
+        assert_eq!(layer.record(true), CircuitState::Open);
+        assert_eq!(stale.record(false), CircuitState::Open);
+        assert!(layer.acquire().is_err());
+    }
+
+    #[test]
+    fn cancelled_probes_let_another_one_through() {
+        let layer = CircuitBreakerLayer::new("products", 1, Duration::ZERO, None);
+        assert_eq!(layer.record(true), CircuitState::Open);
+        let probe = layer.acquire().unwrap();
+        assert!(layer.acquire().is_err());
+        drop(probe);
+        assert!(layer.acquire().is_ok());
+    }
+}
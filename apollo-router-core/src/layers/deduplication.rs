@@ -1,5 +1,9 @@
 //! De-duplicate subgraph requests in flight. Implemented as a tower Layer.
 //!
+//! Queries are only shared when their HTTP requests are identical, headers included, so that
+//! requests made on behalf of different users are never merged. Responses shared with a request
+//! that was not sent carry a [`Deduplicated`] marker in their extensions.
+//!
 //! See [`Layer`] and [`tower::Service`] for more details.
 
 use crate::{fetch::OperationKind, http_compat, Request, SubgraphRequest, SubgraphResponse};
@@ -14,6 +18,10 @@ use tower::{BoxError, Layer, ServiceExt};
 #[derive(Default)]
 pub struct QueryDeduplicationLayer;
 
+/// Extension of the responses received by waiting on an identical request in flight.
+#[derive(Clone, Copy, Debug)]
+pub struct Deduplicated;
+
 impl<S> Layer<S> for QueryDeduplicationLayer
 where
     S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError> + Clone,
@@ -61,10 +69,12 @@ where
                         Ok(value) => {
                             return value
                                 .map(|response| {
-                                    SubgraphResponse::new_from_response(
+                                    let mut response = SubgraphResponse::new_from_response(
                                         response.response,
                                         request.context,
-                                    )
+                                    );
+                                    response.response.extensions_mut().insert(Deduplicated);
+                                    response
                                 })
                                 .map_err(|e| e.into())
                         }
@@ -144,3 +154,52 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod deduplication_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tower::Service;
+
+    #[tokio::test]
+    async fn identical_queries_in_flight_are_sent_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let subgraph = tower::service_fn({
+            let calls = calls.clone();
+            move |_: SubgraphRequest| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok::<_, BoxError>(SubgraphResponse::fake_builder().build())
+                }
+            }
+        });
+        let mut service = QueryDeduplicationLayer::default().layer(subgraph);
+
+        let first = service
+            .ready()
+            .await
+            .unwrap()
+            .call(SubgraphRequest::fake_builder().build());
+        let second = service
+            .ready()
+            .await
+            .unwrap()
+            .call(SubgraphRequest::fake_builder().build());
+        let (first, second) = tokio::join!(first, second);
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let deduplicated = [first.unwrap(), second.unwrap()]
+            .iter()
+            .filter(|response| {
+                response
+                    .response
+                    .extensions()
+                    .get::<Deduplicated>()
+                    .is_some()
+            })
+            .count();
+        assert_eq!(deduplicated, 1);
+    }
+}
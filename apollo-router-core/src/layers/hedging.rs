@@ -0,0 +1,256 @@
+//! Hedging of slow subgraph queries.
+//!
+//! The latencies of the last requests to a subgraph are kept, and a query that has not been
+//! answered after the configured percentile of them is sent a second time. The first successful
+//! response of the two is used, and the other request is dropped. Mutations are never hedged.
+//!
+//! Each request adds `budget` to a balance that each hedge takes one from, so that hedges stay
+//! within that fraction of the requests, even when the subgraph slows down as a whole.
+
+use crate::fetch::OperationKind;
+use crate::{SubgraphRequest, SubgraphResponse};
+use futures::future::{select, BoxFuture, Either};
+use futures::FutureExt;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+/// Number of latencies the percentile is computed over.
+const WINDOW: usize = 200;
+/// Requests are only hedged once this many latencies are known.
+const MIN_SAMPLES: usize = 20;
+/// Most hedges that can be saved up while the subgraph is fast.
+const MAX_BALANCE: f64 = 10.0;
+
+#[derive(Clone)]
+pub struct HedgingLayer {
+    state: Arc<State>,
+}
+
+struct State {
+    percentile: f64,
+    budget: f64,
+    latencies: Mutex<VecDeque<Duration>>,
+    balance: Mutex<f64>,
+}
+
+impl HedgingLayer {
+    /// `percentile` is clamped between 0 and 100, and `budget` is the most hedges sent per
+    /// request.
+    pub fn new(percentile: f64, budget: f64) -> Self {
+        Self {
+            state: Arc::new(State {
+                percentile: percentile.clamp(0.0, 100.0),
+                budget: budget.max(0.0),
+                latencies: Default::default(),
+                balance: Mutex::new(0.0),
+            }),
+        }
+    }
+}
+
+impl State {
+    /// The time after which a request is hedged, once enough latencies are known.
+    fn delay(&self) -> Option<Duration> {
+        let latencies = self.latencies.lock().expect("lock poisoned");
+        if latencies.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<_> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let index = ((sorted.len() - 1) as f64 * self.percentile / 100.0).round() as usize;
+        Some(sorted[index])
+    }
+
+    fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().expect("lock poisoned");
+        if latencies.len() == WINDOW {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    fn deposit(&self) {
+        let mut balance = self.balance.lock().expect("lock poisoned");
+        *balance = (*balance + self.budget).min(MAX_BALANCE);
+    }
+
+    fn withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().expect("lock poisoned");
+        if *balance < 1.0 {
+            return false;
+        }
+        *balance -= 1.0;
+        true
+    }
+}
+
+impl<S> Layer<S> for HedgingLayer {
+    type Service = HedgingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HedgingService {
+            state: self.state.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HedgingService<S> {
+    state: Arc<State>,
+    inner: S,
+}
+
+impl<S> Service<SubgraphRequest> for HedgingService<S>
+where
+    S: Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        let state = self.state.clone();
+        state.deposit();
+        let hedge = match (request.operation_kind, state.delay()) {
+            (OperationKind::Query, Some(delay)) => Some((
+                delay,
+                SubgraphRequest {
+                    originating_request: request.originating_request.clone(),
+                    subgraph_request: request.subgraph_request.clone(),
+                    operation_kind: request.operation_kind,
+                    context: request.context.clone(),
+                },
+                self.inner.clone(),
+            )),
+            _ => None,
+        };
+        let first = Box::pin(self.inner.call(request));
+
+        async move {
+            let started = Instant::now();
+            let (delay, hedge, inner) = match hedge {
+                Some(hedge) => hedge,
+                None => {
+                    let result = first.await;
+                    state.record(started.elapsed());
+                    return result;
+                }
+            };
+
+            let first = match select(first, Box::pin(tokio::time::sleep(delay))).await {
+                Either::Left((result, _)) => {
+                    state.record(started.elapsed());
+                    return result;
+                }
+                Either::Right((_, first)) => first,
+            };
+            if !state.withdraw() {
+                let result = first.await;
+                state.record(started.elapsed());
+                return result;
+            }
+
+            tracing::debug!("hedging subgraph request slower than {:?}", delay);
+            let second = inner.oneshot(hedge);
+            let result = match select(first, Box::pin(second)).await {
+                Either::Left((Ok(response), _)) | Either::Right((Ok(response), _)) => Ok(response),
+                Either::Left((Err(_), second)) => second.await,
+                Either::Right((Err(_), first)) => first.await,
+            };
+            state.record(started.elapsed());
+            result
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod hedging_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A subgraph answering every tenth request slowly once the latencies are known, and the
+    /// others right away.
+    #[derive(Clone)]
+    struct SlowTail {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Service<SubgraphRequest> for SlowTail {
+        type Response = SubgraphResponse;
+        type Error = BoxError;
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), BoxError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: SubgraphRequest) -> Self::Future {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let delay = if call >= MIN_SAMPLES && call % 10 == 9 {
+                    200
+                } else {
+                    1
+                };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                Ok(SubgraphResponse::fake_builder().build())
+            }
+            .boxed()
+        }
+    }
+
+    async fn slowest(operation_kind: OperationKind, budget: f64) -> (Duration, usize) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = HedgingLayer::new(90.0, budget).layer(SlowTail {
+            calls: calls.clone(),
+        });
+        let mut slowest = Duration::default();
+        for _ in 0..50 {
+            let started = Instant::now();
+            service
+                .clone()
+                .oneshot(
+                    SubgraphRequest::fake_builder()
+                        .operation_kind(operation_kind)
+                        .build(),
+                )
+                .await
+                .unwrap();
+            slowest = slowest.max(started.elapsed());
+        }
+        (slowest, calls.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn slow_queries_are_hedged() {
+        let (slowest, calls) = slowest(OperationKind::Query, 0.5).await;
+        assert!(slowest < Duration::from_millis(100));
+        assert!(calls > 50);
+    }
+
+    #[tokio::test]
+    async fn mutations_are_not_hedged() {
+        let (slowest, calls) = slowest(OperationKind::Mutation, 0.5).await;
+        assert!(slowest >= Duration::from_millis(200));
+        assert_eq!(calls, 50);
+    }
+
+    #[tokio::test]
+    async fn hedges_stay_within_the_budget() {
+        let (_, calls) = slowest(OperationKind::Query, 0.0).await;
+        assert_eq!(calls, 50);
+    }
+}
@@ -0,0 +1,26 @@
+//! Jitter spreading out the timeouts and retry delays of calls made together.
+//!
+//! Rather than a random source, successive calls walk the range following the golden ratio, so
+//! that calls made close together are spread far apart.
+
+const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+
+/// Factor within `1 ± spread` for the `call`-th call.
+pub(crate) fn jitter(call: usize, spread: f64) -> f64 {
+    let position = (call as f64 * GOLDEN_RATIO_CONJUGATE).fract();
+    1.0 + spread * (2.0 * position - 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn factors_stay_within_the_spread() {
+        for call in 0..100 {
+            let factor = jitter(call, 0.2);
+            assert!((0.8..=1.2).contains(&factor));
+        }
+        assert_eq!(jitter(7, 0.0), 1.0);
+    }
+}
@@ -1,6 +1,15 @@
 pub mod apq;
 pub mod cache;
+// Tests run without the feature, and cover it anyway.
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+pub mod circuit_breaker;
 pub mod deduplication;
 pub mod ensure_query_presence;
 pub mod forbid_http_get_mutations;
+pub mod hedging;
 pub mod instrument;
+pub(crate) mod jitter;
+pub mod rate_limit;
+pub mod retry;
+pub mod timeout;
@@ -0,0 +1,76 @@
+//! Token bucket rate limiting.
+//!
+//! The bucket holds up to `capacity` tokens and is refilled continuously, at `capacity` tokens per
+//! `interval`. Each request takes a token, and requests finding the bucket empty are rejected
+//! rather than delayed, so that a burst of traffic does not pile up in memory.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Error returned for requests exceeding a rate limit.
+#[derive(Debug)]
+pub struct RateLimited;
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limit exceeded")
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+#[derive(Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    interval: Duration,
+    state: Arc<Mutex<(f64, Instant)>>,
+}
+
+impl TokenBucket {
+    /// A full bucket of `capacity` tokens, refilled every `interval`.
+    pub fn new(capacity: u64, interval: Duration) -> Self {
+        Self {
+            capacity: capacity as f64,
+            interval,
+            state: Arc::new(Mutex::new((capacity as f64, Instant::now()))),
+        }
+    }
+
+    /// Takes a token, if one is left.
+    pub fn try_acquire(&self) -> Result<(), RateLimited> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let (tokens, refilled_at) = &mut *state;
+        let now = Instant::now();
+        if !self.interval.is_zero() {
+            let refill =
+                now.duration_since(*refilled_at).as_secs_f64() / self.interval.as_secs_f64();
+            *tokens = (*tokens + refill * self.capacity).min(self.capacity);
+        }
+        *refilled_at = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(RateLimited)
+        }
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn buckets_refill_over_time() {
+        let bucket = TokenBucket::new(2, Duration::from_millis(100));
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_err());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Retries of failed subgraph requests, with an exponential backoff.
+//!
+//! Only queries are retried: a mutation that failed may still have been applied by the subgraph,
+//! and sending it again could apply it twice. A request has failed when it returns an error, like
+//! a timeout or a connection failure, or when the subgraph answers with a server error status.
+//!
+//! The n-th retry waits for `backoff * 2^n`, jittered between half and all of that delay so that
+//! the retries of requests that failed together are spread out. Like the timeout jitter, the
+//! delays follow the golden ratio rather than a random source.
+
+use super::jitter::jitter;
+use crate::fetch::OperationKind;
+use crate::{SubgraphRequest, SubgraphResponse};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::retry::Policy;
+use tower::BoxError;
+
+/// Retry policy for subgraph requests, to be used with [`tower::retry::RetryLayer`].
+#[derive(Clone)]
+pub struct RetryPolicy {
+    remaining: usize,
+    attempt: u32,
+    backoff: Duration,
+    calls: Arc<AtomicUsize>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize, backoff: Duration) -> Self {
+        Self {
+            remaining: max_retries,
+            attempt: 0,
+            backoff,
+            calls: Default::default(),
+        }
+    }
+
+    fn next_delay(&self) -> Duration {
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        // Between half and all of the delay.
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .mul_f64(0.5 + jitter(call, 1.0) / 4.0)
+    }
+}
+
+impl Policy<SubgraphRequest, SubgraphResponse, BoxError> for RetryPolicy {
+    type Future = BoxFuture<'static, Self>;
+
+    fn retry(
+        &self,
+        request: &SubgraphRequest,
+        result: Result<&SubgraphResponse, &BoxError>,
+    ) -> Option<Self::Future> {
+        let failed = match result {
+            Ok(response) => response.response.status().is_server_error(),
+            Err(_) => true,
+        };
+        if !failed || self.remaining == 0 || request.operation_kind != OperationKind::Query {
+            return None;
+        }
+
+        let delay = self.next_delay();
+        let next = RetryPolicy {
+            remaining: self.remaining - 1,
+            attempt: self.attempt + 1,
+            backoff: self.backoff,
+            calls: self.calls.clone(),
+        };
+        tracing::debug!("retrying subgraph request in {:?}", delay);
+        Some(
+            async move {
+                tokio::time::sleep(delay).await;
+                next
+            }
+            .boxed(),
+        )
+    }
+
+    fn clone_request(&self, request: &SubgraphRequest) -> Option<SubgraphRequest> {
+        Some(SubgraphRequest {
+            originating_request: request.originating_request.clone(),
+            subgraph_request: request.subgraph_request.clone(),
+            operation_kind: request.operation_kind,
+            context: request.context.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod retry_tests {
+    use super::*;
+    use crate::plugin::utils::test::MockSubgraphService;
+    use crate::ServiceBuilderExt;
+    use http::StatusCode;
+    use tower::retry::RetryLayer;
+    use tower::{ServiceBuilder, ServiceExt};
+
+    async fn calls(operation_kind: OperationKind, status: StatusCode, expected: usize) {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(expected)
+            .returning(move |_| Ok(SubgraphResponse::fake_builder().status_code(status).build()));
+
+        let service = ServiceBuilder::new()
+            .layer(RetryLayer::new(RetryPolicy::new(
+                2,
+                Duration::from_millis(1),
+            )))
+            .buffered()
+            .service(mock.build());
+        service
+            .oneshot(
+                SubgraphRequest::fake_builder()
+                    .operation_kind(operation_kind)
+                    .build(),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_queries_are_retried() {
+        calls(OperationKind::Query, StatusCode::BAD_GATEWAY, 3).await;
+        calls(OperationKind::Query, StatusCode::OK, 1).await;
+    }
+
+    #[tokio::test]
+    async fn failed_mutations_are_not_retried() {
+        calls(OperationKind::Mutation, StatusCode::BAD_GATEWAY, 1).await;
+    }
+
+    #[test]
+    fn delays_grow_exponentially_within_the_jitter() {
+        let mut policy = RetryPolicy::new(5, Duration::from_millis(100));
+        for attempt in 0..5 {
+            policy.attempt = attempt;
+            let ceiling = Duration::from_millis(100 * 2u64.pow(attempt));
+            let delay = policy.next_delay();
+            assert!(delay >= ceiling / 2);
+            assert!(delay <= ceiling);
+        }
+    }
+}
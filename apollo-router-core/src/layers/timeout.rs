@@ -0,0 +1,125 @@
+//! Timeout for subgraph requests, optionally spread out by a jitter.
+//!
+//! When a subgraph stalls, requests sent to it at the same time would otherwise all time out at
+//! the same time, and be retried together. With a jitter, each call gets a timeout picked within
+//! `timeout ± jitter * timeout`, spread like the retry delays.
+
+use super::jitter::jitter;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tower::timeout::error::Elapsed;
+use tower::{BoxError, Layer, Service};
+
+#[derive(Clone)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+    jitter: f64,
+    calls: Arc<AtomicUsize>,
+}
+
+impl TimeoutLayer {
+    /// `jitter` is a fraction of `timeout`, clamped between 0 and 1.
+    pub fn new(timeout: Duration, jitter: f64) -> Self {
+        Self {
+            timeout,
+            jitter: jitter.clamp(0.0, 1.0),
+            calls: Default::default(),
+        }
+    }
+
+    fn next_timeout(&self) -> Duration {
+        if self.jitter == 0.0 {
+            return self.timeout;
+        }
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        self.timeout.mul_f64(jitter(call, self.jitter))
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = TimeoutService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimeoutService {
+            layer: self.clone(),
+            inner,
+        }
+    }
+}
+
+pub struct TimeoutService<S> {
+    layer: TimeoutLayer,
+    inner: S,
+}
+
+impl<S, Request> Service<Request> for TimeoutService<S>
+where
+    S: Service<Request>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let timeout = self.layer.next_timeout();
+        let response = self.inner.call(request);
+
+        async move {
+            match tokio::time::timeout(timeout, response).await {
+                Ok(result) => result.map_err(Into::into),
+                Err(_) => Err(Elapsed::new().into()),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod timeout_tests {
+    use super::*;
+    use std::time::Instant;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn jittered_calls_to_a_stalled_service_time_out_apart() {
+        let stalled = tower::service_fn(|_: ()| futures::future::pending::<Result<(), BoxError>>());
+        let mut service = TimeoutLayer::new(Duration::from_millis(100), 0.5).layer(stalled);
+
+        let start = Instant::now();
+        let first = service.ready().await.unwrap().call(());
+        let second = service.ready().await.unwrap().call(());
+        let timed_out_after = |call: BoxFuture<'static, Result<(), BoxError>>| async move {
+            assert!(call.await.unwrap_err().is::<Elapsed>());
+            start.elapsed()
+        };
+        let (first, second) = tokio::join!(timed_out_after(first), timed_out_after(second));
+
+        // 50ms and ~112ms: the first two positions of the sequence are far apart.
+        assert!(first >= Duration::from_millis(50));
+        assert!(second >= first + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn timeouts_stay_within_the_jitter() {
+        let layer = TimeoutLayer::new(Duration::from_secs(10), 0.2);
+        for _ in 0..100 {
+            let timeout = layer.next_timeout();
+            assert!(timeout >= Duration::from_millis(7_999));
+            assert!(timeout <= Duration::from_millis(12_001));
+        }
+        assert_eq!(
+            TimeoutLayer::new(Duration::from_secs(10), 0.0).next_timeout(),
+            Duration::from_secs(10)
+        );
+    }
+}
@@ -30,6 +30,7 @@ mod error;
 mod introspection;
 mod json_ext;
 mod layers;
+mod pem;
 pub mod plugin;
 pub mod plugins;
 mod query_cache;
@@ -39,7 +40,9 @@ mod response;
 mod service_registry;
 mod services;
 mod spec;
+mod storage;
 mod traits;
+mod upload;
 
 pub use cache::*;
 pub use context::*;
@@ -47,6 +50,7 @@ pub use error::*;
 pub use introspection::*;
 pub use json_ext::*;
 pub use layers::*;
+pub use pem::*;
 pub use plugin::*;
 pub use plugins::*;
 pub use query_cache::*;
@@ -56,7 +60,9 @@ pub use response::*;
 pub use service_registry::*;
 pub use services::*;
 pub use spec::*;
+pub use storage::*;
 pub use traits::*;
+pub use upload::*;
 
 /// Useful traits.
 pub mod prelude {
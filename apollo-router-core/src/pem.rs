@@ -0,0 +1,45 @@
+//! Certificates and private keys read from PEM files, for the TLS connections of the router.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tower::BoxError;
+
+fn read_pem(path: &Path) -> Result<Vec<rustls_pemfile::Item>, BoxError> {
+    let mut reader = BufReader::new(
+        File::open(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?,
+    );
+    let mut items = Vec::new();
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// The certificates of the PEM file at `path`, failing if there are none.
+pub fn read_certificates(path: &Path) -> Result<Vec<rustls::Certificate>, BoxError> {
+    let certificates: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(rustls::Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        return Err(format!("no certificate in {}", path.display()).into());
+    }
+    Ok(certificates)
+}
+
+/// The first private key of the PEM file at `path`.
+pub fn read_private_key(path: &Path) -> Result<rustls::PrivateKey, BoxError> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(rustls::PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key in {}", path.display()).into())
+}
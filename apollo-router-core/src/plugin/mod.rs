@@ -10,16 +10,23 @@
 //!  - subgraph (multiple in parallel if multiple subgraphs are accessed)
 //!  stages.
 //!
+//! Subscriptions also go through the subscription stage with every event sent to the client.
+//!
 //! A plugin can choose to interact with the flow of requests at any or all of these stages of
 //! processing. At each stage a [`Service`] is provided which provides an appropriate
 //! mechanism for interacting with the request and response.
 
+// Also built for tests, which run without the feature.
+#[cfg(any(test, feature = "bench"))]
+pub mod profiling;
+pub mod timing;
 pub mod utils;
 
 use crate::services::ServiceBuilderExt;
 use crate::{
-    http_compat, ExecutionRequest, ExecutionResponse, QueryPlannerRequest, QueryPlannerResponse,
-    ResponseBody, RouterRequest, RouterResponse, SubgraphRequest, SubgraphResponse,
+    http_compat, ExecutionRequest, ExecutionResponse, HealthCheck, QueryPlannerRequest,
+    QueryPlannerResponse, ResponseBody, RouterRequest, RouterResponse, Schema, SubgraphRequest,
+    SubgraphResponse, SubscriptionRequest, SubscriptionResponse,
 };
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -29,7 +36,7 @@ use schemars::gen::SchemaGenerator;
 use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Deserialize};
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use tower::buffer::future::ResponseFuture;
 use tower::buffer::Buffer;
@@ -98,10 +105,33 @@ pub trait Plugin: Send + Sync + 'static + Sized {
     /// plugins are registered.
     async fn new(config: Self::Config) -> Result<Self, BoxError>;
 
+    /// This is invoked once all plugins have been created, before their services are built, on
+    /// startup and on every reload.
+    /// Define `startup` to prepare what the plugin needs, for example to open connections or warm
+    /// caches. If it fails, the router is not created, and keeps its previous configuration when
+    /// reloading. What only depends on the configuration of the plugin belongs in `new`.
+    async fn startup(&mut self) -> Result<(), BoxError> {
+        Ok(())
+    }
+
     /// This is invoked after all plugins have been created and we're ready to go live.
     /// This method MUST not panic.
     fn activate(&mut self) {}
 
+    /// This is invoked when the router reloads with a new supergraph schema, once the plugins are
+    /// activated and before they serve requests.
+    /// Define `schema_changed` to react to schema updates, for example to invalidate what the plugin
+    /// derived from the previous schema.
+    fn schema_changed(&mut self, _previous: &Schema, _schema: &Schema) {}
+
+    /// This is invoked when the router shuts down, once the in-flight requests are answered or the
+    /// drain timeout has passed, and on reload once the connections still served by the previous
+    /// plugins are closed or the drain timeout has passed.
+    /// Define `shutdown` to release what the plugin holds, for example to flush buffered data.
+    /// Unlike `Drop`, which remains the place to release what needs no waiting, it can await, and
+    /// it runs at a known point: after the last request the plugin took part in.
+    async fn shutdown(&mut self) {}
+
     /// This service runs at the very beginning and very end of the request lifecycle.
     /// Define router_service if your customization needs to interact at the earliest or latest point possible.
     /// For example, this is a good opportunity to perform JWT verification before allowing a request to proceed further.
@@ -141,12 +171,27 @@ pub trait Plugin: Send + Sync + 'static + Sized {
         service
     }
 
+    /// This service handles the events of subscriptions, before they are sent to the client.
+    /// Define `subscription_service` to change or filter them (for example, to add extensions to every event).
+    fn subscription_service(
+        &mut self,
+        service: BoxService<SubscriptionRequest, SubscriptionResponse, BoxError>,
+    ) -> BoxService<SubscriptionRequest, SubscriptionResponse, BoxError> {
+        service
+    }
+
     /// The `custom_endpoint` method lets you declare a new endpoint exposed for your plugin.
     /// For now it's only accessible for official `apollo.` plugins and for `experimental.`. This endpoint will be accessible via `/plugins/group.plugin_name`
     fn custom_endpoint(&self) -> Option<Handler> {
         None
     }
 
+    /// The `health_checks` method lets you declare checks of the dependencies of your plugin,
+    /// like the connectivity to a Redis server. The router is only ready while they all pass.
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+        Vec::new()
+    }
+
     fn name(&self) -> &'static str {
         get_type_of(self)
     }
@@ -163,10 +208,23 @@ fn get_type_of<T>(_: &T) -> &'static str {
 /// For more information about the plugin lifecycle please check this documentation <https://www.apollographql.com/docs/router/customizations/native/#plugin-lifecycle>
 #[async_trait]
 pub trait DynPlugin: Send + Sync + 'static {
+    /// This is invoked once all plugins have been created, before their services are built, on
+    /// startup and on every reload.
+    async fn startup(&mut self) -> Result<(), BoxError>;
+
     /// This is invoked after all plugins have been created and we're ready to go live.
     /// This method MUST not panic.
     fn activate(&mut self);
 
+    /// This is invoked when the router reloads with a new supergraph schema, once the plugins are
+    /// activated and before they serve requests.
+    fn schema_changed(&mut self, previous: &Schema, schema: &Schema);
+
+    /// This is invoked when the router shuts down, once the in-flight requests are answered or the
+    /// drain timeout has passed, and on reload once the connections still served by the previous
+    /// plugins are closed or the drain timeout has passed.
+    async fn shutdown(&mut self);
+
     /// This service runs at the very beginning and very end of the request lifecycle.
     /// It's the entrypoint of every requests and also the last hook before sending the response.
     /// Define router_service if your customization needs to interact at the earliest or latest point possible.
@@ -199,10 +257,21 @@ pub trait DynPlugin: Send + Sync + 'static {
         service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
     ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError>;
 
+    /// This service handles the events of subscriptions, before they are sent to the client.
+    /// Define `subscription_service` to change or filter them (for example, to add extensions to every event).
+    fn subscription_service(
+        &mut self,
+        service: BoxService<SubscriptionRequest, SubscriptionResponse, BoxError>,
+    ) -> BoxService<SubscriptionRequest, SubscriptionResponse, BoxError>;
+
     /// The `custom_endpoint` method lets you declare a new endpoint exposed for your plugin.
     /// For now it's only accessible for official `apollo.` plugins and for `experimental.`. This endpoint will be accessible via `/plugins/group.plugin_name`
     fn custom_endpoint(&self) -> Option<Handler>;
 
+    /// The `health_checks` method lets you declare checks of the dependencies of your plugin,
+    /// like the connectivity to a Redis server. The router is only ready while they all pass.
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>>;
+
     fn name(&self) -> &'static str;
 }
 
@@ -212,11 +281,23 @@ where
     T: Plugin,
     for<'de> <T as Plugin>::Config: Deserialize<'de>,
 {
+    async fn startup(&mut self) -> Result<(), BoxError> {
+        self.startup().await
+    }
+
     #[allow(deprecated)]
     fn activate(&mut self) {
         self.activate()
     }
 
+    fn schema_changed(&mut self, previous: &Schema, schema: &Schema) {
+        self.schema_changed(previous, schema)
+    }
+
+    async fn shutdown(&mut self) {
+        self.shutdown().await
+    }
+
     fn router_service(
         &mut self,
         service: BoxService<RouterRequest, RouterResponse, BoxError>,
@@ -246,10 +327,21 @@ where
         self.subgraph_service(name, service)
     }
 
+    fn subscription_service(
+        &mut self,
+        service: BoxService<SubscriptionRequest, SubscriptionResponse, BoxError>,
+    ) -> BoxService<SubscriptionRequest, SubscriptionResponse, BoxError> {
+        self.subscription_service(service)
+    }
+
     fn custom_endpoint(&self) -> Option<Handler> {
         self.custom_endpoint()
     }
 
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+        self.health_checks()
+    }
+
     fn name(&self) -> &'static str {
         self.name()
     }
@@ -258,8 +350,12 @@ where
 /// Register a plugin with a group and a name
 /// Grouping prevent name clashes for plugins, so choose something unique, like your domain name.
 /// Plugins will appear in the configuration as a layer property called: {group}.{name}
+/// The qualified name may also be given at once, as in `register_plugin!("mycorp.auth", MyPlugin)`.
 #[macro_export]
 macro_rules! register_plugin {
+    ($qualified_name: literal, $value: ident) => {
+        $crate::register_plugin!("", $qualified_name, $value);
+    };
     ($group: literal, $name: literal, $value: ident) => {
         $crate::reexports::startup::on_startup! {
             let qualified_name = if $group == "" {
@@ -0,0 +1,214 @@
+//! Per-stage profiling of the pipeline, for benchmarks only.
+//!
+//! Enabled by the `bench` feature, it wraps the service of each [`Stage`] built by
+//! [`crate::PluggableRouterServiceBuilder`] with a probe recording, for every poll of its
+//! futures, the wall time the poll took and the memory allocated. That time includes the time
+//! the thread was descheduled or blocked, so it is not CPU time. The figures of a stage include
+//! those of the stages it calls, and of the plugins applied to it.
+//!
+//! Allocations are only counted by binaries installing [`CountingAllocator`] as their global
+//! allocator.
+
+use super::timing::Stage;
+use futures::future::{poll_fn, BoxFuture};
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tower::util::BoxService;
+use tower::{BoxError, Service, ServiceExt};
+
+static PROFILES: Lazy<Mutex<HashMap<Stage, StageProfile>>> = Lazy::new(Default::default);
+
+thread_local! {
+    /// Allocations made by the current thread, and their total size in bytes.
+    static ALLOCATIONS: Cell<(u64, u64)> = Cell::new((0, 0));
+}
+
+/// A global allocator counting the allocations of each thread, on top of the system allocator.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator = CountingAllocator;
+/// ```
+#[derive(Debug, Default)]
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count(size: usize) {
+    // The thread local is gone while the thread is torn down.
+    let _ = ALLOCATIONS.try_with(|allocations| {
+        let (count, bytes) = allocations.get();
+        allocations.set((count + 1, bytes + size as u64));
+    });
+}
+
+fn allocations() -> (u64, u64) {
+    ALLOCATIONS
+        .try_with(|allocations| allocations.get())
+        .unwrap_or_default()
+}
+
+/// What the requests going through one stage cost, since the last [`reset`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StageProfile {
+    /// Number of requests that went through the stage.
+    pub calls: u64,
+    /// Wall time spent polling the futures of the stage.
+    pub poll_time: Duration,
+    /// Number of allocations made while polling the futures of the stage.
+    pub allocations: u64,
+    /// Bytes allocated while polling the futures of the stage.
+    pub allocated_bytes: u64,
+}
+
+impl StageProfile {
+    /// Mean wall time spent polling the futures of the stage per request.
+    pub fn poll_time_per_call(&self) -> Duration {
+        self.poll_time
+            .checked_div(self.calls as u32)
+            .unwrap_or_default()
+    }
+}
+
+/// The profile of each stage requests went through since the last [`reset`].
+pub fn profiles() -> HashMap<Stage, StageProfile> {
+    PROFILES.lock().expect("lock poisoned").clone()
+}
+
+/// Forgets the profiles recorded so far.
+pub fn reset() {
+    PROFILES.lock().expect("lock poisoned").clear();
+}
+
+/// Wraps `service` with a probe profiling it as `stage`.
+pub(crate) fn profiled<Req, Res>(
+    stage: Stage,
+    service: BoxService<Req, Res, BoxError>,
+) -> BoxService<Req, Res, BoxError>
+where
+    Req: Send + 'static,
+    Res: Send + 'static,
+{
+    Probe {
+        stage,
+        inner: service,
+    }
+    .boxed()
+}
+
+struct Probe<Req, Res> {
+    stage: Stage,
+    inner: BoxService<Req, Res, BoxError>,
+}
+
+impl<Req, Res> Service<Req> for Probe<Req, Res>
+where
+    Req: Send + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let stage = self.stage;
+        let mut profile = StageProfile {
+            calls: 1,
+            ..Default::default()
+        };
+        let mut future = measure(&mut profile, || self.inner.call(request));
+
+        poll_fn(move |cx| {
+            let poll = measure(&mut profile, || future.poll_unpin(cx));
+            if poll.is_ready() {
+                let mut profiles = PROFILES.lock().expect("lock poisoned");
+                let total = profiles.entry(stage).or_default();
+                total.calls += profile.calls;
+                total.poll_time += profile.poll_time;
+                total.allocations += profile.allocations;
+                total.allocated_bytes += profile.allocated_bytes;
+            }
+            poll
+        })
+        .boxed()
+    }
+}
+
+/// Runs `f`, adding its duration and allocations to `profile`.
+fn measure<T>(profile: &mut StageProfile, f: impl FnOnce() -> T) -> T {
+    let (count_before, bytes_before) = allocations();
+    let start = Instant::now();
+    let result = f();
+    profile.poll_time += start.elapsed();
+    let (count_after, bytes_after) = allocations();
+    profile.allocations += count_after - count_before;
+    profile.allocated_bytes += bytes_after - bytes_before;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockExecutionService;
+    use crate::{ExecutionRequest, ExecutionResponse};
+
+    #[tokio::test]
+    async fn calls_are_profiled_by_stage() {
+        let mut mock_service = MockExecutionService::new();
+        mock_service
+            .expect_call()
+            .times(2)
+            .returning(move |req: ExecutionRequest| {
+                std::thread::sleep(Duration::from_millis(10));
+                Ok(ExecutionResponse::fake_builder()
+                    .context(req.context)
+                    .build())
+            });
+        let mut service = profiled(Stage::Execution, BoxService::new(mock_service.build()));
+
+        reset();
+        for _ in 0..2 {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(ExecutionRequest::fake_builder().build())
+                .await
+                .unwrap();
+        }
+
+        // Other tests may go through the execution stage concurrently.
+        let profile = &profiles()[&Stage::Execution];
+        assert!(profile.calls >= 2);
+        assert!(profile.poll_time >= Duration::from_millis(20));
+    }
+}
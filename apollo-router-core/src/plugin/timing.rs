@@ -0,0 +1,440 @@
+//! Per-plugin latency accounting.
+//!
+//! When enabled with
+//! [`PluggableRouterServiceBuilder::with_plugin_timings`](crate::PluggableRouterServiceBuilder::with_plugin_timings),
+//! every plugin hook is bracketed by two probes: one around the service handed to the plugin and
+//! one around the service the plugin returns. The time spent between the two probes is the time
+//! spent in the plugin itself. It is appended to the request [`crate::Context`] under
+//! [`PLUGIN_TIMINGS`], and the timings of a request are handed to the recorder set with
+//! [`set_timings_recorder`] once every plugin is done with it, so that telemetry can export them.
+//!
+//! The router also breaks the processing of each operation down into [`OperationTimings`], kept
+//! under [`OPERATION_TIMINGS`], so that plugins can look at how long planning, each subgraph fetch
+//! and the formatting of the response took, from the response of their router service.
+
+use crate::{
+    Context, ExecutionRequest, QueryPlannerRequest, RouterRequest, SubgraphRequest,
+    SubscriptionRequest,
+};
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::task::Poll;
+use std::time::Instant;
+use tower::util::BoxService;
+use tower::{BoxError, Service, ServiceExt};
+
+/// Context key holding the list of [`PluginTiming`] recorded for a request.
+pub const PLUGIN_TIMINGS: &str = "apollo::plugin::timings";
+
+/// The stage of the pipeline a plugin hook was applied to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Router,
+    QueryPlanning,
+    Execution,
+    Subgraph,
+    Subscription,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Router => "router",
+            Stage::QueryPlanning => "query_planning",
+            Stage::Execution => "execution",
+            Stage::Subgraph => "subgraph",
+            Stage::Subscription => "subscription",
+        }
+    }
+}
+
+/// Time spent by one plugin in one stage of a request, in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PluginTiming {
+    pub plugin: String,
+    pub stage: Stage,
+    pub duration: f64,
+}
+
+/// Receives the plugin timings of each request, once every plugin is done with it.
+pub type TimingsRecorder = Arc<dyn Fn(&[PluginTiming]) + Send + Sync>;
+
+static RECORDER: Lazy<RwLock<Option<TimingsRecorder>>> = Lazy::new(Default::default);
+
+/// Sets where the plugin timings of completed requests go, replacing the previous recorder.
+pub fn set_timings_recorder(recorder: TimingsRecorder) {
+    *RECORDER.write().expect("lock poisoned") = Some(recorder);
+}
+
+/// Hands the plugin timings of the request of `context` to the recorder. Called outside of every
+/// plugin, once the router service call is complete.
+pub(crate) fn record_completed(context: &Context) {
+    let recorder = RECORDER.read().expect("lock poisoned").clone();
+    if let Some(recorder) = recorder {
+        let timings: Vec<PluginTiming> = context
+            .get(PLUGIN_TIMINGS)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        recorder(&timings);
+    }
+}
+
+/// Context key holding the [`OperationTimings`] of a request.
+pub const OPERATION_TIMINGS: &str = "apollo::operation::timings";
+
+/// Where the time of an operation went, in seconds.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OperationTimings {
+    /// Time spent planning the operation, cache lookup included.
+    pub planning: Option<f64>,
+    /// Time spent executing the query plan.
+    pub execution: Option<f64>,
+    /// Time of each subgraph fetch of the query plan, in the order they completed.
+    pub fetches: Vec<FetchTiming>,
+    /// Time spent shaping the data of the response to the operation.
+    pub response_formatting: Option<f64>,
+}
+
+/// Time of one subgraph fetch, in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FetchTiming {
+    pub subgraph: String,
+    pub duration: f64,
+}
+
+impl OperationTimings {
+    /// Applies `update` to the timings of the request of `context`.
+    pub(crate) fn record(context: &Context, update: impl Fn(&mut OperationTimings)) {
+        if let Err(err) = context.upsert(
+            OPERATION_TIMINGS,
+            |mut timings: OperationTimings| {
+                update(&mut timings);
+                timings
+            },
+            OperationTimings::default,
+        ) {
+            tracing::debug!("could not record operation timing: {}", err);
+        }
+    }
+}
+
+/// Gives the probes access to the context of the request going through them.
+pub(crate) trait WithContext {
+    fn context(&self) -> &Context;
+}
+
+macro_rules! impl_with_context {
+    ($($request:ty),+) => {
+        $(
+            impl WithContext for $request {
+                fn context(&self) -> &Context {
+                    &self.context
+                }
+            }
+        )+
+    };
+}
+
+impl_with_context!(
+    RouterRequest,
+    QueryPlannerRequest,
+    ExecutionRequest,
+    SubgraphRequest,
+    SubscriptionRequest
+);
+
+/// Time spent downstream of a plugin, keyed by request.
+///
+/// The counter tracks how many calls of the outer probe are in flight so that concurrent subgraph
+/// fetches made for the same request share the entry until the last one ends.
+type Downstream = Arc<DashMap<usize, (usize, f64)>>;
+
+/// Identifies the request of `context` by its context entries, shared by every stage of a request
+/// and by no other request while it is in flight. Unlike correlation IDs, which clients choose,
+/// they cannot be shared by concurrent requests.
+fn request_id(context: &Context) -> usize {
+    Arc::as_ptr(&context.entries) as usize
+}
+
+/// Applies `hook` to `service`, recording the time spent by the plugin named `plugin` in `stage`
+/// when `enabled`.
+pub(crate) fn timed<Req, Res>(
+    enabled: bool,
+    plugin: &str,
+    stage: Stage,
+    service: BoxService<Req, Res, BoxError>,
+    hook: impl FnOnce(BoxService<Req, Res, BoxError>) -> BoxService<Req, Res, BoxError>,
+) -> BoxService<Req, Res, BoxError>
+where
+    Req: WithContext + Send + 'static,
+    Res: Send + 'static,
+{
+    if !enabled {
+        return hook(service);
+    }
+    let downstream = Downstream::default();
+    let inner = InnerProbe {
+        downstream: downstream.clone(),
+        inner: service,
+    }
+    .boxed();
+
+    OuterProbe {
+        plugin: plugin.to_string(),
+        stage,
+        downstream,
+        inner: hook(inner),
+    }
+    .boxed()
+}
+
+struct InnerProbe<Req, Res> {
+    downstream: Downstream,
+    inner: BoxService<Req, Res, BoxError>,
+}
+
+impl<Req, Res> Service<Req> for InnerProbe<Req, Res>
+where
+    Req: WithContext + Send + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let id = request_id(request.context());
+        let downstream = self.downstream.clone();
+        let start = Instant::now();
+
+        self.inner
+            .call(request)
+            .map(move |result| {
+                if let Some(mut entry) = downstream.get_mut(&id) {
+                    entry.1 += start.elapsed().as_secs_f64();
+                }
+                result
+            })
+            .boxed()
+    }
+}
+
+struct OuterProbe<Req, Res> {
+    plugin: String,
+    stage: Stage,
+    downstream: Downstream,
+    inner: BoxService<Req, Res, BoxError>,
+}
+
+impl<Req, Res> Service<Req> for OuterProbe<Req, Res>
+where
+    Req: WithContext + Send + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let context = request.context().clone();
+        let id = request_id(&context);
+        let downstream_before = {
+            let mut entry = self.downstream.entry(id).or_insert((0, 0.0));
+            entry.0 += 1;
+            entry.1
+        };
+
+        let downstream = self.downstream.clone();
+        let plugin = self.plugin.clone();
+        let stage = self.stage;
+        let start = Instant::now();
+
+        self.inner
+            .call(request)
+            .map(move |result| {
+                let elapsed = start.elapsed().as_secs_f64();
+                let downstream_after = match downstream.get_mut(&id) {
+                    Some(mut entry) => {
+                        entry.0 -= 1;
+                        entry.1
+                    }
+                    None => downstream_before,
+                };
+                downstream.remove_if(&id, |_, (in_flight, _)| *in_flight == 0);
+
+                let timing = PluginTiming {
+                    plugin,
+                    stage,
+                    duration: (elapsed - (downstream_after - downstream_before)).max(0.0),
+                };
+                if let Err(err) = context.upsert(
+                    PLUGIN_TIMINGS,
+                    |mut timings: Vec<PluginTiming>| {
+                        timings.push(timing.clone());
+                        timings
+                    },
+                    Vec::new,
+                ) {
+                    tracing::debug!("could not record plugin timing: {}", err);
+                }
+                result
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockExecutionService;
+    use crate::{ExecutionResponse, CORRELATION_ID_CONTEXT_KEY};
+    use std::time::Duration;
+
+    fn slow(
+        service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+    ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        service
+            .and_then(|response| async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok::<_, BoxError>(response)
+            })
+            .boxed()
+    }
+
+    #[tokio::test]
+    async fn slow_plugin_is_told_apart_from_fast_plugin() {
+        let mut mock_service = MockExecutionService::new();
+        mock_service
+            .expect_call()
+            .times(1)
+            .returning(move |req: ExecutionRequest| {
+                Ok(ExecutionResponse::fake_builder()
+                    .context(req.context)
+                    .build())
+            });
+
+        // The fast plugin wraps the slow one, so its own duration must not include the slow one's.
+        let service = timed(
+            true,
+            "slow",
+            Stage::Execution,
+            BoxService::new(mock_service.build()),
+            slow,
+        );
+        let service = timed(true, "fast", Stage::Execution, service, |service| service);
+
+        let context = Context::new();
+        service
+            .oneshot(
+                ExecutionRequest::fake_builder()
+                    .context(context.clone())
+                    .build(),
+            )
+            .await
+            .unwrap();
+
+        let timings: Vec<PluginTiming> = context.get(PLUGIN_TIMINGS).unwrap().unwrap();
+        let duration = |plugin: &str| {
+            timings
+                .iter()
+                .find(|timing| timing.plugin == plugin)
+                .map(|timing| {
+                    assert_eq!(timing.stage, Stage::Execution);
+                    timing.duration
+                })
+                .unwrap()
+        };
+
+        assert_eq!(timings.len(), 2);
+        assert!(duration("slow") >= 0.05);
+        assert!(duration("fast") < 0.05);
+    }
+
+    #[test]
+    fn operation_timings_add_up_in_the_context() {
+        let context = Context::new();
+        OperationTimings::record(&context, |timings| timings.planning = Some(0.5));
+        for subgraph in ["accounts", "reviews"] {
+            OperationTimings::record(&context, |timings| {
+                timings.fetches.push(FetchTiming {
+                    subgraph: subgraph.to_string(),
+                    duration: 0.25,
+                })
+            });
+        }
+
+        let timings: OperationTimings = context.get(OPERATION_TIMINGS).unwrap().unwrap();
+        assert_eq!(timings.planning, Some(0.5));
+        assert_eq!(timings.execution, None);
+        assert_eq!(
+            timings
+                .fetches
+                .iter()
+                .map(|fetch| fetch.subgraph.as_str())
+                .collect::<Vec<_>>(),
+            ["accounts", "reviews"]
+        );
+    }
+
+    #[test]
+    fn requests_sharing_a_correlation_id_are_kept_apart() {
+        let context = Context::new();
+        let other = Context::new();
+        for context in [&context, &other] {
+            context
+                .insert(CORRELATION_ID_CONTEXT_KEY, "abc".to_string())
+                .unwrap();
+        }
+        assert_ne!(request_id(&context), request_id(&other));
+        // Every stage of a request shares its context.
+        assert_eq!(request_id(&context), request_id(&context.clone()));
+    }
+
+    #[tokio::test]
+    async fn plugins_are_not_timed_unless_enabled() {
+        let mut mock_service = MockExecutionService::new();
+        mock_service
+            .expect_call()
+            .times(1)
+            .returning(move |req: ExecutionRequest| {
+                Ok(ExecutionResponse::fake_builder()
+                    .context(req.context)
+                    .build())
+            });
+        let service = timed(
+            false,
+            "slow",
+            Stage::Execution,
+            BoxService::new(mock_service.build()),
+            slow,
+        );
+
+        let context = Context::new();
+        service
+            .oneshot(
+                ExecutionRequest::fake_builder()
+                    .context(context.clone())
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert!(context
+            .get::<_, Vec<PluginTiming>>(PLUGIN_TIMINGS)
+            .unwrap()
+            .is_none());
+    }
+}
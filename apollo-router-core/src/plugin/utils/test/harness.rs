@@ -0,0 +1,243 @@
+//! Runs the whole request pipeline against mock subgraphs.
+
+use super::mock::subgraph::MockSubgraph;
+use crate::{
+    DynPlugin, Object, PluggableRouterServiceBuilder, Response, ResponseBody, RouterRequest,
+    RouterResponse, Schema, ServiceBuildError, Value,
+};
+use http::StatusCode;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower::util::BoxCloneService;
+use tower::{BoxError, ServiceExt};
+
+/// Builds a router with plugins and mock subgraphs, for integration tests of plugins.
+///
+/// Subgraphs of the schema without a mock answer every request with an error.
+///
+/// ```ignore
+/// let router = TestHarness::new(schema)
+///     .subgraph("products", MockSubgraph::default().with_matcher(|_| true, response))
+///     .plugin("experimental.my_plugin", plugin)
+///     .build()
+///     .await?;
+/// router.query("{ topProducts { upc } }").await?.assert_no_errors();
+/// ```
+pub struct TestHarness {
+    schema: Arc<Schema>,
+    subgraphs: HashMap<String, MockSubgraph>,
+    plugins: Vec<(String, Box<dyn DynPlugin>)>,
+}
+
+impl TestHarness {
+    pub fn new(schema: Arc<Schema>) -> Self {
+        Self {
+            schema,
+            subgraphs: HashMap::new(),
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Answers the requests to the `name` subgraph with `mock`.
+    pub fn subgraph(mut self, name: impl Into<String>, mock: MockSubgraph) -> Self {
+        self.subgraphs.insert(name.into(), mock);
+        self
+    }
+
+    /// Adds a plugin, the first one added seeing requests first.
+    pub fn plugin(mut self, name: impl Into<String>, plugin: impl DynPlugin) -> Self {
+        self.plugins.push((name.into(), Box::new(plugin)));
+        self
+    }
+
+    /// Adds a plugin created from the registry, for example with
+    /// [`PluginFactory::create_instance`](crate::PluginFactory::create_instance).
+    pub fn dyn_plugin(mut self, name: impl Into<String>, plugin: Box<dyn DynPlugin>) -> Self {
+        self.plugins.push((name.into(), plugin));
+        self
+    }
+
+    pub async fn build(mut self) -> Result<TestRouter, ServiceBuildError> {
+        let mut builder = PluggableRouterServiceBuilder::new(self.schema.clone());
+        for (name, _) in self.schema.subgraphs() {
+            let mock = self.subgraphs.remove(name).unwrap_or_default();
+            builder = builder.with_subgraph_service(name, mock);
+        }
+        for (name, plugin) in self.plugins {
+            builder = builder.with_dyn_plugin(name, plugin);
+        }
+        let (service, _) = builder.build().await?;
+        Ok(TestRouter { service })
+    }
+}
+
+/// A router built by a [`TestHarness`].
+#[derive(Clone)]
+pub struct TestRouter {
+    service: BoxCloneService<RouterRequest, RouterResponse, BoxError>,
+}
+
+impl TestRouter {
+    pub async fn execute(&self, request: RouterRequest) -> Result<TestResponse, BoxError> {
+        let response = self.service.clone().oneshot(request).await?;
+        Ok(TestResponse { response })
+    }
+
+    /// Executes `query`, without variables.
+    pub async fn query(&self, query: &str) -> Result<TestResponse, BoxError> {
+        self.execute(
+            RouterRequest::fake_builder()
+                .query(query.to_string())
+                .build()?,
+        )
+        .await
+    }
+}
+
+/// A response of a [`TestRouter`], with assertions on it.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub response: RouterResponse,
+}
+
+impl TestResponse {
+    /// The GraphQL body of the response.
+    ///
+    /// Panics if the body is not a GraphQL response.
+    pub fn graphql(&self) -> &Response {
+        match self.response.response.body() {
+            ResponseBody::GraphQL(response) => response,
+            body => panic!("expected a GraphQL response, got {:?}", body),
+        }
+    }
+
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(self.response.response.status(), status);
+        self
+    }
+
+    pub fn assert_data(&self, data: serde_json::Value) -> &Self {
+        let data = serde_json_bytes::to_value(data).expect("JSON values are serializable; qed");
+        assert_eq!(self.graphql().data.as_ref(), Some(&data));
+        self
+    }
+
+    pub fn assert_no_errors(&self) -> &Self {
+        let errors = &self.graphql().errors;
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        self
+    }
+
+    /// Asserts that one of the errors has `code` as its `code` extension.
+    pub fn assert_error_code(&self, code: &str) -> &Self {
+        let errors = &self.graphql().errors;
+        let expected = Value::from(code);
+        assert!(
+            errors
+                .iter()
+                .any(|error| error.extensions.get("code") == Some(&expected)),
+            "no error with the {} code in {:?}",
+            code,
+            errors
+        );
+        self
+    }
+
+    /// The extensions of the GraphQL body.
+    pub fn extensions(&self) -> &Object {
+        &self.graphql().extensions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Arc<Schema> {
+        Arc::new(
+            include_str!(
+                "../../../../../apollo-router-benchmarks/benches/fixtures/supergraph.graphql"
+            )
+            .parse()
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn queries_are_answered_by_the_mocks() {
+        let products = MockSubgraph::default().with_matcher(
+            |request| {
+                request
+                    .query
+                    .as_deref()
+                    .map_or(false, |query| query.contains("topProducts"))
+            },
+            serde_json::from_value(json!({ "data": { "topProducts": [{ "upc": "1" }] } })).unwrap(),
+        );
+        let router = TestHarness::new(schema())
+            .subgraph("products", products)
+            .build()
+            .await
+            .unwrap();
+
+        router
+            .query("{ topProducts { upc } }")
+            .await
+            .unwrap()
+            .assert_status(StatusCode::OK)
+            .assert_no_errors()
+            .assert_data(json!({ "topProducts": [{ "upc": "1" }] }));
+    }
+
+    struct TraceIds;
+
+    #[async_trait::async_trait]
+    impl crate::Plugin for TraceIds {
+        type Config = ();
+
+        async fn new(_config: Self::Config) -> Result<Self, BoxError> {
+            Ok(TraceIds)
+        }
+
+        fn execution_service(
+            &mut self,
+            service: tower::util::BoxService<
+                crate::ExecutionRequest,
+                crate::ExecutionResponse,
+                BoxError,
+            >,
+        ) -> tower::util::BoxService<crate::ExecutionRequest, crate::ExecutionResponse, BoxError>
+        {
+            service
+                .map_request(|request: crate::ExecutionRequest| {
+                    request.context.insert_extension("traceId", "abc").unwrap();
+                    request
+                })
+                .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn extensions_inserted_by_plugins_are_added_to_the_response() {
+        let router = TestHarness::new(schema())
+            .plugin("test.trace_ids", TraceIds)
+            .build()
+            .await
+            .unwrap();
+
+        let response = router.query("{ me { name } }").await.unwrap();
+        assert_eq!(
+            response.extensions().get("traceId"),
+            Some(&Value::from("abc"))
+        );
+    }
+
+    #[tokio::test]
+    async fn subgraphs_without_mocks_answer_with_errors() {
+        let router = TestHarness::new(schema()).build().await.unwrap();
+
+        let response = router.query("{ me { name } }").await.unwrap();
+        assert!(!response.graphql().errors.is_empty());
+    }
+}
@@ -8,10 +8,14 @@ use tower::{BoxError, Service};
 
 type MockResponses = HashMap<Request, Response>;
 
+/// Tells whether a canned response answers a subgraph request.
+type RequestMatcher = Arc<dyn Fn(&Request) -> bool + Send + Sync>;
+
 #[derive(Clone, Default)]
 pub struct MockSubgraph {
     // using an arc to improve efficiency when service is cloned
     mocks: Arc<MockResponses>,
+    matchers: Vec<(RequestMatcher, Response)>,
     extensions: Option<Object>,
 }
 
@@ -19,10 +23,24 @@ impl MockSubgraph {
     pub fn new(mocks: MockResponses) -> Self {
         Self {
             mocks: Arc::new(mocks),
+            matchers: Vec::new(),
             extensions: None,
         }
     }
 
+    /// Answers the requests `matcher` accepts with `response`.
+    ///
+    /// Matchers are tried in the order they are added, after the exact requests given to
+    /// [`MockSubgraph::new`].
+    pub fn with_matcher(
+        mut self,
+        matcher: impl Fn(&Request) -> bool + Send + Sync + 'static,
+        response: Response,
+    ) -> Self {
+        self.matchers.push((Arc::new(matcher), response));
+        self
+    }
+
     pub fn with_extensions(mut self, extensions: Object) -> Self {
         self.extensions = Some(extensions);
         self
@@ -41,7 +59,13 @@ impl Service<SubgraphRequest> for MockSubgraph {
     }
 
     fn call(&mut self, req: SubgraphRequest) -> Self::Future {
-        let response = if let Some(response) = self.mocks.get(req.subgraph_request.body()) {
+        let body = req.subgraph_request.body();
+        let response = if let Some(response) = self.mocks.get(body).or_else(|| {
+            self.matchers
+                .iter()
+                .find(|(matcher, _)| matcher(body))
+                .map(|(_, response)| response)
+        }) {
             // Build an http Response
             let http_response = http::Response::builder()
                 .status(StatusCode::OK)
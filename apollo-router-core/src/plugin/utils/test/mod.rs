@@ -1,8 +1,10 @@
 //! Utilities which make it easy to test with [`crate::plugin`].
 
+pub mod harness;
 pub mod mock;
 pub mod service;
 
+pub use harness::{TestHarness, TestResponse, TestRouter};
 pub use service::{
     MockExecutionService, MockQueryPlanningService, MockRouterService, MockSubgraphService,
 };
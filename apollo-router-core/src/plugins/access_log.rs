@@ -0,0 +1,406 @@
+//! Logs one structured line per client request.
+//!
+//! Each line is a JSON object holding the configured `fields`, or all of them, logged at the `INFO`
+//! level with the `apollo_router::access_log` target. With a `sampling` rate below 1, only that
+//! share of the requests is logged, evenly spread over the traffic.
+
+use crate::plugin::Plugin;
+use crate::{
+    register_plugin, OperationSignature, ResponseBody, RouterRequest, RouterResponse,
+    SubgraphRequest, SubgraphResponse, CLIENT_NAME_CONTEXT_KEY, CLIENT_VERSION_CONTEXT_KEY,
+    OPERATION_SIGNATURE_CONTEXT_KEY,
+};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Instant;
+use tower::util::BoxService;
+use tower::{BoxError, Service, ServiceExt};
+
+/// Context key holding the number of fetches made to each subgraph for a request.
+const SUBGRAPH_FETCHES_CONTEXT_KEY: &str = "apollo::access_log::subgraph_fetches";
+
+/// A field of the log lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Field {
+    /// Name of the operation, if the client gave one.
+    OperationName,
+    /// Signature of the operation, set by the `experimental.operation_signature` plugin.
+    OperationSignature,
+    /// Name of the client, as identified by `server.client_awareness`.
+    ClientName,
+    /// Version of the client, as identified by `server.client_awareness`.
+    ClientVersion,
+    /// HTTP status of the response.
+    Status,
+    /// Time taken to answer, in seconds.
+    Duration,
+    /// Number of fetches made to each subgraph.
+    SubgraphFetches,
+    /// Messages of the errors of the response.
+    Errors,
+}
+
+impl Field {
+    fn name(&self) -> &'static str {
+        match self {
+            Field::OperationName => "operation_name",
+            Field::OperationSignature => "operation_signature",
+            Field::ClientName => "client_name",
+            Field::ClientVersion => "client_version",
+            Field::Status => "status",
+            Field::Duration => "duration",
+            Field::SubgraphFetches => "subgraph_fetches",
+            Field::Errors => "errors",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Fields of each line. Defaults to all of them.
+    #[serde(default = "all_fields")]
+    fields: Vec<Field>,
+    /// Share of the requests logged, from 0 to 1. Defaults to 1.
+    #[serde(default = "default_sampling")]
+    sampling: f64,
+}
+
+fn all_fields() -> Vec<Field> {
+    vec![
+        Field::OperationName,
+        Field::OperationSignature,
+        Field::ClientName,
+        Field::ClientVersion,
+        Field::Status,
+        Field::Duration,
+        Field::SubgraphFetches,
+        Field::Errors,
+    ]
+}
+
+fn default_sampling() -> f64 {
+    1.0
+}
+
+/// What is known of a request before it is answered.
+struct RequestInfo {
+    operation_name: Option<String>,
+    client_name: Option<String>,
+    client_version: Option<String>,
+    start: Instant,
+}
+
+impl Config {
+    fn line(
+        &self,
+        info: &RequestInfo,
+        result: &Result<RouterResponse, BoxError>,
+    ) -> Map<String, Value> {
+        let mut line = Map::new();
+        for field in &self.fields {
+            let value = match field {
+                Field::OperationName => json!(info.operation_name),
+                Field::OperationSignature => json!(result
+                    .as_ref()
+                    .ok()
+                    .and_then(|response| {
+                        response
+                            .context
+                            .get::<_, OperationSignature>(OPERATION_SIGNATURE_CONTEXT_KEY)
+                            .ok()
+                    })
+                    .flatten()),
+                Field::ClientName => json!(info.client_name),
+                Field::ClientVersion => json!(info.client_version),
+                Field::Status => match result {
+                    Ok(response) => json!(response.response.status().as_u16()),
+                    Err(_) => json!(500),
+                },
+                Field::Duration => json!(info.start.elapsed().as_secs_f64()),
+                Field::SubgraphFetches => {
+                    let fetches: HashMap<String, u64> = result
+                        .as_ref()
+                        .ok()
+                        .and_then(|response| {
+                            response.context.get(SUBGRAPH_FETCHES_CONTEXT_KEY).ok()
+                        })
+                        .flatten()
+                        .unwrap_or_default();
+                    json!(fetches)
+                }
+                Field::Errors => match result {
+                    Ok(response) => match response.response.body() {
+                        ResponseBody::GraphQL(body) => json!(body
+                            .errors
+                            .iter()
+                            .map(|error| error.message.as_str())
+                            .collect::<Vec<_>>()),
+                        _ => json!([]),
+                    },
+                    Err(err) => json!([err.to_string()]),
+                },
+            };
+            line.insert(field.name().to_string(), value);
+        }
+        line
+    }
+}
+
+/// Picks requests evenly spread over the traffic: the n-th request is picked when `n * rate`
+/// reaches the next integer.
+#[derive(Debug)]
+struct Sampler {
+    rate: f64,
+    seen: AtomicU64,
+}
+
+impl Sampler {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    fn sample(&self) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * self.rate).floor() > (seen * self.rate).floor()
+    }
+}
+
+#[derive(Debug)]
+struct AccessLog {
+    config: Arc<Config>,
+    sampler: Arc<Sampler>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for AccessLog {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        if !(0.0..=1.0).contains(&config.sampling) {
+            return Err("access log sampling must be between 0 and 1".into());
+        }
+        Ok(AccessLog {
+            sampler: Arc::new(Sampler::new(config.sampling)),
+            config: Arc::new(config),
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        AccessLogService {
+            config: self.config.clone(),
+            sampler: self.sampler.clone(),
+            inner: service,
+        }
+        .boxed()
+    }
+
+    fn subgraph_service(
+        &mut self,
+        name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        if !self.config.fields.contains(&Field::SubgraphFetches) {
+            return service;
+        }
+        let name = name.to_string();
+        service
+            .map_request(move |request: SubgraphRequest| {
+                if let Err(err) = request.context.upsert(
+                    SUBGRAPH_FETCHES_CONTEXT_KEY,
+                    |mut fetches: HashMap<String, u64>| {
+                        *fetches.entry(name.clone()).or_default() += 1;
+                        fetches
+                    },
+                    HashMap::new,
+                ) {
+                    tracing::debug!("could not count the subgraph fetch: {}", err);
+                }
+                request
+            })
+            .boxed()
+    }
+}
+
+struct AccessLogService {
+    config: Arc<Config>,
+    sampler: Arc<Sampler>,
+    inner: BoxService<RouterRequest, RouterResponse, BoxError>,
+}
+
+impl Service<RouterRequest> for AccessLogService {
+    type Response = RouterResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: RouterRequest) -> Self::Future {
+        if !self.sampler.sample() {
+            return self.inner.call(request);
+        }
+
+        let client = |key: &str| request.context.get::<_, String>(key).ok().flatten();
+        let info = RequestInfo {
+            operation_name: request.originating_request.body().operation_name.clone(),
+            client_name: client(CLIENT_NAME_CONTEXT_KEY),
+            client_version: client(CLIENT_VERSION_CONTEXT_KEY),
+            start: Instant::now(),
+        };
+        let config = self.config.clone();
+        let response = self.inner.call(request);
+        async move {
+            let result = response.await;
+            tracing::info!(
+                target: "apollo_router::access_log",
+                "{}",
+                Value::Object(config.line(&info, &result))
+            );
+            result
+        }
+        .boxed()
+    }
+}
+
+register_plugin!("experimental", "access_log", AccessLog);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockSubgraphService;
+    use crate::Context;
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn lines_hold_the_selected_fields() {
+        let context = Context::new();
+        context
+            .insert(
+                SUBGRAPH_FETCHES_CONTEXT_KEY,
+                HashMap::from([("products".to_string(), 2u64)]),
+            )
+            .unwrap();
+        context
+            .insert(
+                OPERATION_SIGNATURE_CONTEXT_KEY,
+                OperationSignature {
+                    signature: "query TopProducts { topProducts(first: 0) { upc } }".to_string(),
+                    hash: "7a1c".to_string(),
+                    variables: None,
+                },
+            )
+            .unwrap();
+        let response = RouterResponse::fake_builder()
+            .error(crate::Error {
+                message: "cannot query field".to_string(),
+                ..Default::default()
+            })
+            .context(context)
+            .build()
+            .unwrap();
+        let info = RequestInfo {
+            operation_name: Some("TopProducts".to_string()),
+            client_name: Some("web".to_string()),
+            client_version: None,
+            start: Instant::now(),
+        };
+
+        let line = config("fields: [operation_name, operation_signature, client_name, client_version, status, subgraph_fetches, errors]")
+            .line(&info, &Ok(response));
+        assert_eq!(
+            Value::Object(line),
+            json!({
+                "operation_name": "TopProducts",
+                "operation_signature": {
+                    "signature": "query TopProducts { topProducts(first: 0) { upc } }",
+                    "hash": "7a1c",
+                    "variables": null,
+                },
+                "client_name": "web",
+                "client_version": null,
+                "status": 200,
+                "subgraph_fetches": { "products": 2 },
+                "errors": ["cannot query field"],
+            })
+        );
+
+        let line = config("fields: [status, errors]").line(&info, &Err("timeout".into()));
+        assert_eq!(
+            Value::Object(line),
+            json!({ "status": 500, "errors": ["timeout"] })
+        );
+        assert!(config("{}")
+            .line(&info, &Err("timeout".into()))
+            .contains_key("duration"));
+    }
+
+    #[test]
+    fn sampled_requests_are_evenly_spread() {
+        let sampler = Sampler::new(0.25);
+        let sampled: Vec<bool> = (0..8).map(|_| sampler.sample()).collect();
+        assert_eq!(
+            sampled,
+            [false, false, false, true, false, false, false, true]
+        );
+        assert!((0..8).all(|_| Sampler::new(1.0).sample()));
+        assert!((0..8).all(|_| !Sampler::new(0.0).sample()));
+    }
+
+    #[tokio::test]
+    async fn subgraph_fetches_are_counted() {
+        let mut mock_service = MockSubgraphService::new();
+        mock_service.expect_call().times(2).returning(|request| {
+            Ok(SubgraphResponse::fake_builder()
+                .context(request.context)
+                .build())
+        });
+        let mock = mock_service.build();
+
+        let mut plugin = AccessLog::new(config("{}")).await.unwrap();
+        let mut service = plugin.subgraph_service("products", mock.boxed());
+        let context = Context::new();
+        for _ in 0..2 {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(
+                    SubgraphRequest::fake_builder()
+                        .context(context.clone())
+                        .build(),
+                )
+                .await
+                .unwrap();
+        }
+        let fetches: HashMap<String, u64> =
+            context.get(SUBGRAPH_FETCHES_CONTEXT_KEY).unwrap().unwrap();
+        assert_eq!(fetches, HashMap::from([("products".to_string(), 2)]));
+    }
+
+    #[tokio::test]
+    async fn sampling_is_validated() {
+        assert!(AccessLog::new(config("sampling: 1.5")).await.is_err());
+    }
+}
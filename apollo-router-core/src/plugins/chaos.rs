@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+use crate::chaos::{Chaos, ChaosLayer};
+use crate::plugin::Plugin;
+use crate::{register_plugin, SubgraphRequest, SubgraphResponse};
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Faults injected into every subgraph without its own entry in `subgraphs`.
+    #[serde(default)]
+    all: Option<Chaos>,
+    #[serde(default)]
+    subgraphs: HashMap<String, Chaos>,
+}
+
+struct ChaosPlugin {
+    config: Config,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ChaosPlugin {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        for chaos in config.all.iter().chain(config.subgraphs.values()) {
+            for probability in [
+                chaos.latency_probability,
+                chaos.error_probability,
+                chaos.truncation_probability,
+            ] {
+                if !(0.0..=1.0).contains(&probability) {
+                    return Err(BoxError::from(format!(
+                        "chaos probabilities must be between 0 and 1, got {}",
+                        probability
+                    )));
+                }
+            }
+        }
+        tracing::warn!("fault injection is enabled, subgraph requests will be disrupted");
+        Ok(Self { config })
+    }
+
+    fn subgraph_service(
+        &mut self,
+        name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        match self
+            .config
+            .subgraphs
+            .get(name)
+            .or_else(|| self.config.all.as_ref())
+        {
+            Some(chaos) => ServiceBuilder::new()
+                .layer(ChaosLayer::new(name, chaos.clone()))
+                .service(service)
+                .boxed(),
+            None => service,
+        }
+    }
+}
+
+register_plugin!("experimental", "chaos", ChaosPlugin);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockSubgraphService;
+    use crate::DynPlugin;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn subgraph_config_overrides_all() {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|_| Ok(SubgraphResponse::fake_builder().build()));
+        let mock = mock.build();
+
+        let mut dyn_plugin: Box<dyn DynPlugin> = crate::plugins()
+            .get("experimental.chaos")
+            .expect("Plugin not found")
+            .create_instance(&json!({
+                "all": { "error_probability": 1.0 },
+                "subgraphs": { "products": { "error_probability": 0.0 } }
+            }))
+            .await
+            .unwrap();
+
+        dyn_plugin
+            .subgraph_service("products", BoxService::new(mock.clone()))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+        assert!(dyn_plugin
+            .subgraph_service("reviews", BoxService::new(mock))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn probabilities_out_of_range_are_rejected() {
+        assert!(crate::plugins()
+            .get("experimental.chaos")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "all": { "latency_probability": 1.5 } }))
+            .await
+            .is_err());
+    }
+}
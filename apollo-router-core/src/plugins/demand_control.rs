@@ -0,0 +1,390 @@
+//! Limits the total cost of the operations of each client over a sliding window.
+//!
+//! The cost of an operation is estimated from the `@cost` and `@listSize` directives of the
+//! schema. Clients are told apart by their name, or by the value of the `client_header` when one
+//! is configured, and each of them may spend its `budget` over the last `window`. At most
+//! `max_clients` clients are tracked at once, the others sharing the budget of unidentified
+//! clients. In `measure` mode, operations over budget are only logged and flagged in the context,
+//! so that budgets can be tuned on real traffic before being enforced in `reject` mode.
+
+use crate::plugin::Plugin;
+use crate::{
+    register_plugin, ExecutionRequest, ExecutionResponse, Object, RouterRequest, RouterResponse,
+    ServiceBuilderExt, CLIENT_NAME_CONTEXT_KEY, ESTIMATED_COST_CONTEXT_KEY,
+};
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+/// Context key holding the [`DemandControlResult`] of a request.
+pub const DEMAND_CONTROL_RESULT_CONTEXT_KEY: &str = "apollo::demand_control::result";
+
+/// What is done with operations over budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    /// Operations over budget are logged but executed.
+    Measure,
+    /// Operations over budget are rejected.
+    Reject,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Measure
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Whether operations over budget are rejected or only measured. Defaults to `measure`.
+    #[serde(default)]
+    mode: Mode,
+    /// Header identifying the client. Defaults to the client name of the server's client
+    /// awareness.
+    #[serde(default)]
+    client_header: Option<String>,
+    /// Total cost each client may spend over the window.
+    budget: u64,
+    /// Budgets of specific clients, by client name, in place of `budget`.
+    #[serde(default)]
+    clients: HashMap<String, u64>,
+    /// Length of the sliding window budgets are spent over.
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    window: Duration,
+    /// Most clients whose spending is tracked at once. Defaults to 10000. Clients beyond it share
+    /// the budget of unidentified clients.
+    #[serde(default = "default_max_clients")]
+    max_clients: usize,
+}
+
+fn default_max_clients() -> usize {
+    10_000
+}
+
+/// Outcome of the demand control of a request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DemandControlResult {
+    /// Estimated cost of the operation.
+    pub estimated_cost: u64,
+    /// Cost already spent by the client over the window, before this operation.
+    pub spent: u64,
+    /// Budget of the client.
+    pub budget: u64,
+    /// Whether the operation went over budget, and was rejected in `reject` mode.
+    pub over_budget: bool,
+}
+
+/// Costs spent by each client, with the time they were spent at.
+#[derive(Debug)]
+struct Budgets {
+    spent: Mutex<HashMap<String, VecDeque<(Instant, u64)>>>,
+    max_clients: usize,
+}
+
+impl Budgets {
+    fn new(max_clients: usize) -> Self {
+        Self {
+            spent: Default::default(),
+            max_clients,
+        }
+    }
+
+    /// Spends `cost` for `client` if it fits in its `budget`, returning what was already spent
+    /// over the `window`.
+    fn spend(
+        &self,
+        client: &str,
+        cost: u64,
+        budget: u64,
+        window: Duration,
+        now: Instant,
+    ) -> Result<u64, u64> {
+        let mut spent = self.spent.lock().expect("lock poisoned");
+        let mut client = client;
+        if !spent.contains_key(client) && spent.len() >= self.max_clients {
+            // Forgets the clients that spent nothing over the window before giving up on this one.
+            spent.retain(|_, costs| {
+                matches!(costs.back(), Some((at, _)) if now.duration_since(*at) < window)
+            });
+            if spent.len() >= self.max_clients {
+                client = "";
+            }
+        }
+        let costs = spent.entry(client.to_string()).or_default();
+        while matches!(costs.front(), Some((at, _)) if now.duration_since(*at) >= window) {
+            costs.pop_front();
+        }
+        let total = costs
+            .iter()
+            .fold(0u64, |total, (_, cost)| total.saturating_add(*cost));
+        if total.saturating_add(cost) > budget {
+            Err(total)
+        } else {
+            costs.push_back((now, cost));
+            Ok(total)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DemandControl {
+    config: Arc<Config>,
+    budgets: Arc<Budgets>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for DemandControl {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        if config.window.is_zero() {
+            return Err("demand control window must not be zero".into());
+        }
+        Ok(DemandControl {
+            budgets: Arc::new(Budgets::new(config.max_clients)),
+            config: Arc::new(config),
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        service
+            .map_request(|request: RouterRequest| {
+                // Asks the router service to estimate the cost of the operation.
+                if let Err(err) = request.context.insert(ESTIMATED_COST_CONTEXT_KEY, 0u64) {
+                    tracing::debug!("could not request the estimated cost: {}", err);
+                }
+                request
+            })
+            .boxed()
+    }
+
+    fn execution_service(
+        &mut self,
+        service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+    ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        let config = self.config.clone();
+        let budgets = self.budgets.clone();
+
+        ServiceBuilder::new()
+            .checkpoint(move |req: ExecutionRequest| {
+                let estimated_cost = match req.context.get::<_, u64>(ESTIMATED_COST_CONTEXT_KEY)? {
+                    Some(cost) => cost,
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+                let client = match &config.client_header {
+                    Some(header) => req
+                        .originating_request
+                        .headers()
+                        .get(header)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string),
+                    None => req.context.get::<_, String>(CLIENT_NAME_CONTEXT_KEY)?,
+                }
+                .unwrap_or_default();
+                let budget = config
+                    .clients
+                    .get(&client)
+                    .copied()
+                    .unwrap_or(config.budget);
+
+                let (spent, over_budget) = match budgets.spend(
+                    &client,
+                    estimated_cost,
+                    budget,
+                    config.window,
+                    Instant::now(),
+                ) {
+                    Ok(spent) => (spent, false),
+                    Err(spent) => (spent, true),
+                };
+                req.context.insert(
+                    DEMAND_CONTROL_RESULT_CONTEXT_KEY,
+                    DemandControlResult {
+                        estimated_cost,
+                        spent,
+                        budget,
+                        over_budget,
+                    },
+                )?;
+                if !over_budget {
+                    return Ok(ControlFlow::Continue(req));
+                }
+                if config.mode == Mode::Measure {
+                    tracing::warn!(
+                        "client {:?} is over its cost budget: {} spent, {} estimated, {} allowed",
+                        client,
+                        spent,
+                        estimated_cost,
+                        budget
+                    );
+                    return Ok(ControlFlow::Continue(req));
+                }
+
+                let mut extensions = Object::default();
+                extensions.insert("code", "COST_BUDGET_EXCEEDED".into());
+                extensions.insert("estimatedCost", estimated_cost.into());
+                extensions.insert("spent", spent.into());
+                extensions.insert("budget", budget.into());
+                let res = ExecutionResponse::builder()
+                    .error(crate::Error {
+                        message: "the cost of the operation exceeds the remaining budget"
+                            .to_string(),
+                        extensions,
+                        ..Default::default()
+                    })
+                    .extensions(Object::new())
+                    .status_code(StatusCode::TOO_MANY_REQUESTS)
+                    .context(req.context)
+                    .build();
+                Ok(ControlFlow::Break(res))
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("experimental", "demand_control", DemandControl);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockExecutionService;
+    use crate::Context;
+    use tower::Service;
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn budgets_are_spent_over_a_sliding_window() {
+        let budgets = Budgets::new(10);
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+
+        assert_eq!(budgets.spend("web", 60, 100, window, start), Ok(0));
+        assert_eq!(
+            budgets.spend("web", 30, 100, window, start + Duration::from_secs(5)),
+            Ok(60)
+        );
+        assert_eq!(
+            budgets.spend("web", 20, 100, window, start + Duration::from_secs(6)),
+            Err(90)
+        );
+        assert_eq!(budgets.spend("ios", 20, 100, window, start), Ok(0));
+        assert_eq!(
+            budgets.spend("web", 20, 100, window, start + Duration::from_secs(10)),
+            Ok(30)
+        );
+    }
+
+    #[test]
+    fn clients_beyond_the_limit_share_a_budget() {
+        let budgets = Budgets::new(2);
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+
+        assert_eq!(budgets.spend("web", 60, 100, window, start), Ok(0));
+        assert_eq!(budgets.spend("ios", 60, 100, window, start), Ok(0));
+        assert_eq!(budgets.spend("android", 60, 100, window, start), Ok(0));
+        assert_eq!(budgets.spend("desktop", 60, 100, window, start), Err(60));
+        assert_eq!(budgets.spend("web", 20, 100, window, start), Ok(60));
+
+        // Once their spending is out of the window, clients are forgotten to make room.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(budgets.spend("desktop", 60, 100, window, later), Ok(0));
+        assert_eq!(budgets.spend("desktop", 20, 100, window, later), Ok(60));
+    }
+
+    async fn call(
+        service: &mut BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+        client: &str,
+        cost: u64,
+    ) -> (StatusCode, DemandControlResult) {
+        let context = Context::new();
+        context.insert(ESTIMATED_COST_CONTEXT_KEY, cost).unwrap();
+        context
+            .insert(CLIENT_NAME_CONTEXT_KEY, client.to_string())
+            .unwrap();
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(ExecutionRequest::fake_builder().context(context).build())
+            .await
+            .unwrap();
+        let result = response
+            .context
+            .get(DEMAND_CONTROL_RESULT_CONTEXT_KEY)
+            .unwrap()
+            .unwrap();
+        (response.response.status(), result)
+    }
+
+    #[tokio::test]
+    async fn operations_over_budget_are_rejected() {
+        let mut mock = MockExecutionService::new();
+        mock.expect_call().times(2).returning(|request| {
+            Ok(ExecutionResponse::fake_builder()
+                .context(request.context)
+                .build())
+        });
+        let mut plugin = DemandControl::new(config(
+            "mode: reject\nbudget: 100\nclients:\n  batch: 500\nwindow: 1m",
+        ))
+        .await
+        .unwrap();
+        let mut service = plugin.execution_service(mock.build().boxed());
+
+        let (status, result) = call(&mut service, "web", 80).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!result.over_budget);
+
+        let (status, result) = call(&mut service, "web", 80).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            result,
+            DemandControlResult {
+                estimated_cost: 80,
+                spent: 80,
+                budget: 100,
+                over_budget: true,
+            }
+        );
+
+        let (status, result) = call(&mut service, "batch", 400).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result.budget, 500);
+    }
+
+    #[tokio::test]
+    async fn operations_over_budget_are_only_measured() {
+        let mut mock = MockExecutionService::new();
+        mock.expect_call().times(2).returning(|request| {
+            Ok(ExecutionResponse::fake_builder()
+                .context(request.context)
+                .build())
+        });
+        let mut plugin = DemandControl::new(config("budget: 100\nwindow: 1m"))
+            .await
+            .unwrap();
+        let mut service = plugin.execution_service(mock.build().boxed());
+
+        call(&mut service, "web", 80).await;
+        let (status, result) = call(&mut service, "web", 80).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(result.over_budget);
+    }
+}
@@ -0,0 +1,80 @@
+//! Merges the entity fetches a query plan runs in parallel against the same subgraph into a single
+//! subgraph request, saving round-trips when a subgraph resolves entities at several places of an
+//! operation.
+
+use crate::{
+    register_plugin, EntityBatching, ExecutionRequest, ExecutionResponse, Plugin,
+    ENTITY_BATCHING_CONTEXT_KEY,
+};
+use tower::util::BoxService;
+use tower::{BoxError, ServiceExt};
+
+#[derive(Debug)]
+struct EntityBatchingPlugin {
+    config: EntityBatching,
+}
+
+#[async_trait::async_trait]
+impl Plugin for EntityBatchingPlugin {
+    type Config = EntityBatching;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(EntityBatchingPlugin { config })
+    }
+
+    fn execution_service(
+        &mut self,
+        service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+    ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        let config = self.config.clone();
+        service
+            .map_request(move |request: ExecutionRequest| {
+                if let Err(err) = request
+                    .context
+                    .insert(ENTITY_BATCHING_CONTEXT_KEY, config.clone())
+                {
+                    tracing::debug!("could not enable entity batching: {}", err);
+                }
+                request
+            })
+            .boxed()
+    }
+}
+
+register_plugin!("experimental", "entity_batching", EntityBatchingPlugin);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockExecutionService;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn settings_are_given_to_the_execution() {
+        let mut mock = MockExecutionService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|request: ExecutionRequest| {
+                let settings = request
+                    .context
+                    .get::<_, EntityBatching>(ENTITY_BATCHING_CONTEXT_KEY)
+                    .unwrap()
+                    .unwrap();
+                assert!(settings.enabled("reviews"));
+                assert!(!settings.enabled("accounts"));
+                Ok(ExecutionResponse::fake_builder().build())
+            });
+
+        let mut plugin = crate::plugins()
+            .get("experimental.entity_batching")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "subgraphs": { "reviews": true } }))
+            .await
+            .unwrap();
+        plugin
+            .execution_service(BoxService::new(mock.build()))
+            .oneshot(ExecutionRequest::fake_builder().build())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,701 @@
+//! Caches the entities fetched from subgraphs.
+//!
+//! Entity fetches, the `_entities` queries the router sends to resolve the fields a subgraph
+//! contributes to a type, are split by representation: the entities found in the cache are not
+//! requested again, and only the missing ones are fetched, then cached for the TTL configured for
+//! their type in `types`, or for `ttl`. Nothing is cached from responses carrying errors.
+//!
+//! Entities are keyed by subgraph and representation, that is by the fields of their `@key`, with
+//! one entry per selection set they were fetched with. Entities fetched for a client can be made
+//! private to it by keying them on the headers and context entries listed in `private`, like its
+//! `authorization` header or the claims of its token. Requests carrying credentials, in an
+//! `authorization` or `cookie` header, are not cached unless the key includes them.
+//!
+//! With `invalidation` configured, an entity can be removed from the cache, for all the clients
+//! and selections it was fetched with, by a `POST` to `/plugins/experimental.entity_cache/invalidate`
+//! sending the shared key as a bearer token, with a body like
+//! `{"subgraph": "products", "representation": {"__typename": "Product", "upc": "1"}}`.
+
+use crate::fetch::OperationKind;
+use crate::plugin::{Handler, Plugin};
+use crate::{
+    http_compat, record_cache_lookups, register_plugin, CacheStorage, CacheStorageConfig,
+    HealthCheck, Object, PathElement, Response, ResponseBody, ServiceBuilderExt,
+    StorageHealthCheck, SubgraphRequest, SubgraphResponse, Value,
+};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::header::{AUTHORIZATION, COOKIE};
+use http::{Method, StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower::buffer::Buffer;
+use tower::util::BoxService;
+use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(30);
+
+/// Headers carrying the credentials of a client.
+const CREDENTIALS: [http::header::HeaderName; 2] = [AUTHORIZATION, COOKIE];
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Where entities are cached. Defaults to the memory of the router.
+    #[serde(default)]
+    storage: CacheStorageConfig,
+    /// TTL of the cached entities. Defaults to 30s.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    ttl: Option<Duration>,
+    /// TTL of the cached entities of each type, by type name, overriding `ttl`.
+    #[serde(default)]
+    #[schemars(with = "HashMap<String, String>")]
+    types: HashMap<String, humantime_serde::Serde<Duration>>,
+    /// What the cached entities are private to.
+    #[serde(default)]
+    private: Private,
+    /// Lets entities be removed from the cache through the plugin endpoint.
+    #[serde(default)]
+    invalidation: Option<InvalidationConfig>,
+}
+
+/// Parts of the client request entities are keyed on, so that clients only get their own.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Private {
+    /// Headers of the client request, like `authorization`.
+    #[serde(default)]
+    headers: Vec<String>,
+    /// Context entries, like the claims put there by the authentication plugin.
+    #[serde(default)]
+    context: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct InvalidationConfig {
+    /// Secret invalidation requests send in their `authorization` header, as `Bearer <key>`.
+    shared_key: String,
+}
+
+impl Config {
+    fn ttl(&self, representation: &Value) -> Duration {
+        representation
+            .as_object()
+            .and_then(|representation| representation.get("__typename"))
+            .and_then(|typename| typename.as_str())
+            .and_then(|typename| self.types.get(typename))
+            .map(|ttl| **ttl)
+            .or(self.ttl)
+            .unwrap_or(DEFAULT_TTL)
+    }
+
+    /// The longest an entity can stay cached.
+    fn max_ttl(&self) -> Duration {
+        self.types
+            .values()
+            .map(|ttl| **ttl)
+            .chain(Some(self.ttl.unwrap_or(DEFAULT_TTL)))
+            .max()
+            .unwrap_or(DEFAULT_TTL)
+    }
+
+    /// The hash of what makes the entities fetched for `request` private, or `None` if they must
+    /// not be cached because the client sent credentials the key would not include.
+    fn private_key(&self, request: &SubgraphRequest) -> Option<String> {
+        let headers = request.originating_request.headers();
+        // Context entries do not stand in for credentials: whatever else the key includes, the
+        // credentials the client sent must be part of it.
+        let keyed_on_credentials = CREDENTIALS
+            .iter()
+            .filter(|credentials| headers.contains_key(*credentials))
+            .all(|credentials| {
+                self.private
+                    .headers
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(credentials.as_str()))
+            });
+        if !keyed_on_credentials {
+            return None;
+        }
+
+        let mut private = Vec::new();
+        for name in &self.private.headers {
+            for value in headers.get_all(name.as_str()) {
+                private.extend_from_slice(name.as_bytes());
+                private.push(b':');
+                private.extend_from_slice(value.as_bytes());
+                private.push(b'\n');
+            }
+        }
+        for key in &self.private.context {
+            let value = request
+                .context
+                .get::<_, Value>(key)
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            private.extend_from_slice(key.as_bytes());
+            private.push(b'=');
+            private.extend(serde_json::to_vec(&value).expect("JSON values serialize; qed"));
+            private.push(b'\n');
+        }
+        Some(hash(&private))
+    }
+}
+
+/// An entity, as fetched with one selection set for one client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedEntity {
+    /// Milliseconds since the Unix epoch at which the entity was fetched, as instances sharing a
+    /// storage do not share an `Instant`.
+    fetched_at: u64,
+    entity: Value,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// The key of an entity, under which the time it was last invalidated is stored.
+fn entity_key(subgraph: &str, representation: &Value) -> String {
+    format!(
+        "entity:{}:{}",
+        subgraph,
+        hash(&serde_json::to_vec(representation).expect("JSON values serialize; qed"))
+    )
+}
+
+/// The key of an entity fetched with the query hashed as `query_hash`, for a client.
+fn selection_key(entity_key: &str, query_hash: &str, private_key: &str) -> String {
+    format!("{}:{}:{}", entity_key, query_hash, private_key)
+}
+
+/// Looks the entities up, reading their selections and invalidations at once.
+async fn lookup(
+    storage: &dyn CacheStorage,
+    entity_keys: &[String],
+    selection_keys: &[String],
+) -> Vec<Option<Value>> {
+    let keys: Vec<String> = entity_keys.iter().chain(selection_keys).cloned().collect();
+    let mut values = match storage.get_multiple(&keys).await {
+        Ok(values) if values.len() == keys.len() => values,
+        Ok(_) => return vec![None; entity_keys.len()],
+        Err(err) => {
+            tracing::error!("could not read from the entity cache: {}", err);
+            return vec![None; entity_keys.len()];
+        }
+    };
+    let selections = values.split_off(entity_keys.len());
+    values
+        .into_iter()
+        .zip(selections)
+        .map(|(invalidated_at, selection)| {
+            let cached: CachedEntity = serde_json::from_slice(&selection?).ok()?;
+            let invalidated_at: Option<u64> =
+                invalidated_at.and_then(|bytes| serde_json::from_slice(&bytes).ok());
+            match invalidated_at {
+                Some(invalidated_at) if invalidated_at >= cached.fetched_at => None,
+                _ => Some(cached.entity),
+            }
+        })
+        .collect()
+}
+
+fn entities_data(entities: Vec<Value>) -> Value {
+    let mut data = Object::default();
+    data.insert("_entities", Value::Array(entities));
+    Value::Object(data)
+}
+
+#[derive(Deserialize)]
+struct Invalidation {
+    subgraph: String,
+    representation: Value,
+}
+
+struct EntityCache {
+    config: Arc<Config>,
+    storage: Arc<dyn CacheStorage>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for EntityCache {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(EntityCache {
+            storage: config.storage.build().await?,
+            config: Arc::new(config),
+        })
+    }
+
+    fn subgraph_service(
+        &mut self,
+        name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        EntityCacheService {
+            subgraph: Arc::new(name.to_string()),
+            config: self.config.clone(),
+            storage: self.storage.clone(),
+            inner: ServiceBuilder::new().buffered().service(service),
+        }
+        .boxed()
+    }
+
+    fn custom_endpoint(&self) -> Option<Handler> {
+        // The key is compared by hash, so that comparing it takes the same time whatever is sent.
+        let shared_key =
+            hash(format!("Bearer {}", self.config.invalidation.as_ref()?.shared_key).as_bytes());
+        let invalidated_for = self.config.max_ttl();
+        let storage = self.storage.clone();
+        let service = tower::service_fn(move |request: http_compat::Request<Bytes>| {
+            let storage = storage.clone();
+            let authorized = request
+                .headers()
+                .get(AUTHORIZATION)
+                .map(|authorization| hash(authorization.as_bytes()) == shared_key)
+                .unwrap_or_default();
+            async move {
+                let status = if request.method() != Method::POST
+                    || !request.uri().path().ends_with("/invalidate")
+                {
+                    StatusCode::NOT_FOUND
+                } else if !authorized {
+                    StatusCode::UNAUTHORIZED
+                } else {
+                    match serde_json::from_slice::<Invalidation>(request.body()) {
+                        Ok(invalidation) => {
+                            // Selections fetched until now are ignored, until they all expired.
+                            storage
+                                .put(
+                                    &entity_key(
+                                        &invalidation.subgraph,
+                                        &invalidation.representation,
+                                    ),
+                                    serde_json::to_vec(&now())?,
+                                    Some(invalidated_for),
+                                )
+                                .await?;
+                            StatusCode::OK
+                        }
+                        Err(_) => StatusCode::BAD_REQUEST,
+                    }
+                };
+                Ok::<_, BoxError>(http_compat::Response {
+                    inner: http::Response::builder()
+                        .status(status)
+                        .body(ResponseBody::Text(
+                            status.canonical_reason().unwrap_or_default().to_string(),
+                        ))?,
+                })
+            }
+        });
+        Some(Handler::new(service.boxed()))
+    }
+
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+        vec![Arc::new(StorageHealthCheck::new(
+            "entity_cache",
+            self.storage.clone(),
+        ))]
+    }
+}
+
+struct EntityCacheService {
+    subgraph: Arc<String>,
+    config: Arc<Config>,
+    storage: Arc<dyn CacheStorage>,
+    inner: Buffer<BoxService<SubgraphRequest, SubgraphResponse, BoxError>, SubgraphRequest>,
+}
+
+impl Service<SubgraphRequest> for EntityCacheService {
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: SubgraphRequest) -> Self::Future {
+        let body = request.subgraph_request.body();
+        let fetch = match (
+            request.operation_kind,
+            body.query.as_deref(),
+            body.variables.get("representations"),
+        ) {
+            (OperationKind::Query, Some(query), Some(Value::Array(representations)))
+                if query.contains("_entities") =>
+            {
+                Some((hash(query.as_bytes()), representations.clone()))
+            }
+            _ => None,
+        };
+        let ((query_hash, representations), private_key) =
+            match fetch.zip(self.config.private_key(&request)) {
+                Some(fetch) => fetch,
+                None => return self.inner.call(request).boxed(),
+            };
+
+        // The ready service goes with the request, leaving a clone to be polled for the next one.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let subgraph = self.subgraph.clone();
+        let config = self.config.clone();
+        let storage = self.storage.clone();
+        async move {
+            let now = now();
+            let entity_keys: Vec<String> = representations
+                .iter()
+                .map(|representation| entity_key(&subgraph, representation))
+                .collect();
+            let keys: Vec<String> = entity_keys
+                .iter()
+                .map(|entity_key| selection_key(entity_key, &query_hash, &private_key))
+                .collect();
+            let mut entities = lookup(&*storage, &entity_keys, &keys).await;
+            let misses: Vec<usize> = (0..entities.len())
+                .filter(|index| entities[*index].is_none())
+                .collect();
+            record_cache_lookups(
+                &request.context,
+                "entity",
+                (entities.len() - misses.len()) as u64,
+                misses.len() as u64,
+            );
+
+            if misses.is_empty() {
+                let data = entities_data(entities.into_iter().flatten().collect());
+                return Ok(SubgraphResponse::new_from_response(
+                    http_compat::Response {
+                        inner: http::Response::builder()
+                            .status(StatusCode::OK)
+                            .body(Response::builder().data(data).build())?,
+                    },
+                    request.context,
+                ));
+            }
+            if misses.len() < representations.len() {
+                let body = request.subgraph_request.body_mut();
+                let mut variables = (*body.variables).clone();
+                variables.insert(
+                    "representations",
+                    Value::Array(
+                        misses
+                            .iter()
+                            .map(|index| representations[*index].clone())
+                            .collect(),
+                    ),
+                );
+                body.variables = Arc::new(variables);
+            }
+
+            let mut response = inner.call(request).await?;
+            let body = response.response.body_mut();
+            // Errors point into the entities that were fetched, not into all the requested ones.
+            for error in &mut body.errors {
+                if let Some(path) = &mut error.path {
+                    if let [PathElement::Key(key), PathElement::Index(index), ..] =
+                        path.0.as_mut_slice()
+                    {
+                        if key.as_str() == "_entities" {
+                            if let Some(requested) = misses.get(*index) {
+                                *index = *requested;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let fetched = match body
+                .data
+                .as_ref()
+                .and_then(|data| data.as_object())
+                .and_then(|data| data.get("_entities"))
+                .and_then(|fetched| fetched.as_array())
+            {
+                Some(fetched) if fetched.len() == misses.len() => fetched.clone(),
+                _ => return Ok(response),
+            };
+            if body.errors.is_empty() {
+                for (index, entity) in misses.iter().zip(&fetched) {
+                    let cached = CachedEntity {
+                        fetched_at: now,
+                        entity: entity.clone(),
+                    };
+                    let ttl = config.ttl(&representations[*index]);
+                    if let Err(err) = storage
+                        .put(&keys[*index], serde_json::to_vec(&cached)?, Some(ttl))
+                        .await
+                    {
+                        tracing::error!("could not write to the entity cache: {}", err);
+                    }
+                }
+            }
+            for (index, entity) in misses.into_iter().zip(fetched) {
+                entities[index] = Some(entity);
+            }
+            body.data = Some(entities_data(entities.into_iter().flatten().collect()));
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+register_plugin!("experimental", "entity_cache", EntityCache);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockSubgraphService;
+    use crate::{DynPlugin, Request};
+    use serde_json::json;
+    use serde_json_bytes::json as bjson;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const QUERY: &str = "query($representations:[_Any!]!){_entities(representations:$representations){...on Product{name}}}";
+
+    fn product(upc: &str) -> Value {
+        bjson!({ "__typename": "Product", "upc": upc })
+    }
+
+    fn entities_request(
+        representations: Vec<Value>,
+        authorization: Option<&str>,
+    ) -> SubgraphRequest {
+        let mut variables = Object::default();
+        variables.insert("representations", Value::Array(representations));
+        let mut originating_request = http_compat::Request::mock();
+        if let Some(authorization) = authorization {
+            originating_request
+                .headers_mut()
+                .insert(AUTHORIZATION, authorization.parse().unwrap());
+        }
+        SubgraphRequest::fake_builder()
+            .originating_request(Arc::new(originating_request))
+            .subgraph_request(
+                http_compat::Request::fake_builder()
+                    .body(
+                        Request::builder()
+                            .query(Some(QUERY.to_string()))
+                            .variables(Arc::new(variables))
+                            .build(),
+                    )
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+    }
+
+    async fn entity_cache(
+        config: serde_json::Value,
+        fetched: Arc<AtomicUsize>,
+    ) -> (
+        Box<dyn DynPlugin>,
+        BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .returning(move |request: SubgraphRequest| {
+                let body = request.subgraph_request.body();
+                let representations = body.variables.get("representations").unwrap();
+                let representations = representations.as_array().unwrap();
+                fetched.fetch_add(representations.len(), Ordering::SeqCst);
+                let entities = representations
+                    .iter()
+                    .map(|representation| {
+                        let upc = representation.as_object().unwrap().get("upc").unwrap();
+                        let mut entity = Object::default();
+                        entity.insert("name", format!("product {}", upc.as_str().unwrap()).into());
+                        Value::Object(entity)
+                    })
+                    .collect();
+                Ok(SubgraphResponse::fake_builder()
+                    .data(entities_data(entities))
+                    .build())
+            });
+
+        let mut plugin: Box<dyn DynPlugin> = crate::plugins()
+            .get("experimental.entity_cache")
+            .expect("Plugin not found")
+            .create_instance(&config)
+            .await
+            .unwrap();
+        let service = plugin.subgraph_service("products", BoxService::new(mock.build()));
+        (plugin, service)
+    }
+
+    async fn names(
+        service: &mut BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+        upcs: &[&str],
+    ) -> Value {
+        names_for(service, upcs, None).await
+    }
+
+    async fn names_for(
+        service: &mut BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+        upcs: &[&str],
+        authorization: Option<&str>,
+    ) -> Value {
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(entities_request(
+                upcs.iter().map(|upc| product(upc)).collect(),
+                authorization,
+            ))
+            .await
+            .unwrap();
+        response.response.body().data.clone().unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_missing_entities_are_fetched() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let (_plugin, mut service) = entity_cache(json!({}), fetched.clone()).await;
+
+        names(&mut service, &["1", "2"]).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+
+        let data = names(&mut service, &["2", "3", "1"]).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            data,
+            bjson!({ "_entities": [
+                { "name": "product 2" },
+                { "name": "product 3" },
+                { "name": "product 1" }
+            ] })
+        );
+    }
+
+    #[test]
+    fn entities_expire_by_type() {
+        let config: Config = serde_json::from_value(json!({
+            "ttl": "1m",
+            "types": { "Product": "5s" }
+        }))
+        .unwrap();
+        assert_eq!(config.ttl(&product("1")), Duration::from_secs(5));
+        assert_eq!(
+            config.ttl(&bjson!({ "__typename": "User", "id": "1" })),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[tokio::test]
+    async fn authenticated_requests_are_not_cached_by_default() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let (_plugin, mut service) = entity_cache(json!({}), fetched.clone()).await;
+
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn private_entities_are_cached_per_client() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let (_plugin, mut service) = entity_cache(
+            json!({ "private": { "headers": ["authorization"] } }),
+            fetched.clone(),
+        )
+        .await;
+
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+
+        names_for(&mut service, &["1"], Some("Bearer bob")).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn context_keys_do_not_cover_credentials() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let (_plugin, mut service) = entity_cache(
+            json!({ "private": { "context": ["user_id"] } }),
+            fetched.clone(),
+        )
+        .await;
+
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+    }
+
+    async fn invalidate(plugin: &dyn DynPlugin, authorization: &str) -> StatusCode {
+        plugin
+            .custom_endpoint()
+            .unwrap()
+            .oneshot(
+                http_compat::Request::fake_builder()
+                    .method(Method::POST)
+                    .uri(http::Uri::from_static(
+                        "http://localhost/plugins/experimental.entity_cache/invalidate",
+                    ))
+                    .header(AUTHORIZATION, authorization)
+                    .body(Bytes::from(
+                        json!({ "subgraph": "products", "representation": product("1") })
+                            .to_string(),
+                    ))
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn entities_can_be_invalidated() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let (plugin, mut service) = entity_cache(
+            json!({
+                "invalidation": { "shared_key": "secret" },
+                "private": { "headers": ["authorization"] }
+            }),
+            fetched.clone(),
+        )
+        .await;
+
+        names(&mut service, &["1"]).await;
+        names(&mut service, &["1"]).await;
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+
+        assert_eq!(
+            invalidate(&*plugin, "Bearer guess").await,
+            StatusCode::UNAUTHORIZED
+        );
+        names(&mut service, &["1"]).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+
+        assert_eq!(invalidate(&*plugin, "Bearer secret").await, StatusCode::OK);
+        names(&mut service, &["1"]).await;
+        names_for(&mut service, &["1"], Some("Bearer alice")).await;
+        assert_eq!(fetched.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn invalidation_requires_a_shared_key() {
+        assert!(serde_json::from_value::<Config>(json!({ "invalidation": {} })).is_err());
+    }
+}
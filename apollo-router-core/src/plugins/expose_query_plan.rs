@@ -0,0 +1,156 @@
+//! Returns the query plan of an operation in the extensions of its response, when asked to with a
+//! header, so that plans can be looked at without going through the logs.
+//!
+//! Plans show the shape of the subgraphs, so the plugin is meant for development environments.
+
+use crate::{
+    register_plugin, Plugin, QueryPlannerRequest, QueryPlannerResponse, ResponseBody,
+    RouterRequest, RouterResponse, Value,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceExt};
+
+/// Context key set on requests asking for their query plan.
+const ENABLED_CONTEXT_KEY: &str = "apollo::expose_query_plan::enabled";
+/// Context key holding the serialized query plan of the request.
+const QUERY_PLAN_CONTEXT_KEY: &str = "apollo::expose_query_plan::plan";
+/// Key of the query plan in the extensions of the response.
+const QUERY_PLAN_EXTENSION: &str = "apolloQueryPlan";
+
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Header that requests the query plan when its value is `true`. Defaults to
+    /// `apollo-expose-query-plan`.
+    #[serde(default = "default_header")]
+    header: String,
+}
+
+fn default_header() -> String {
+    "apollo-expose-query-plan".to_string()
+}
+
+#[derive(Debug)]
+struct ExposeQueryPlan {
+    config: Config,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ExposeQueryPlan {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        http::header::HeaderName::from_bytes(config.header.as_bytes())?;
+        Ok(ExposeQueryPlan { config })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let header = self.config.header.clone();
+        service
+            .map_request(move |request: RouterRequest| {
+                let enabled = request
+                    .originating_request
+                    .headers()
+                    .get(&header)
+                    .map_or(false, |value| value.as_bytes() == b"true");
+                if enabled {
+                    if let Err(err) = request.context.insert(ENABLED_CONTEXT_KEY, true) {
+                        tracing::debug!("could not request the query plan: {}", err);
+                    }
+                }
+                request
+            })
+            .map_response(|mut response: RouterResponse| {
+                let plan = match response.context.get::<_, Value>(QUERY_PLAN_CONTEXT_KEY) {
+                    Ok(Some(plan)) => plan,
+                    _ => return response,
+                };
+                if let ResponseBody::GraphQL(body) = response.response.body_mut() {
+                    body.extensions.insert(QUERY_PLAN_EXTENSION, plan);
+                }
+                response
+            })
+            .boxed()
+    }
+
+    fn query_planning_service(
+        &mut self,
+        service: BoxService<QueryPlannerRequest, QueryPlannerResponse, BoxError>,
+    ) -> BoxService<QueryPlannerRequest, QueryPlannerResponse, BoxError> {
+        service
+            .map_response(|response: QueryPlannerResponse| {
+                if let Ok(Some(true)) = response.context.get(ENABLED_CONTEXT_KEY) {
+                    let stored = serde_json_bytes::to_value(&*response.query_plan)
+                        .map_err(BoxError::from)
+                        .and_then(|plan| response.context.insert(QUERY_PLAN_CONTEXT_KEY, plan));
+                    if let Err(err) = stored {
+                        tracing::debug!("could not expose the query plan: {}", err);
+                    }
+                }
+                response
+            })
+            .boxed()
+    }
+}
+
+register_plugin!("experimental", "expose_query_plan", ExposeQueryPlan);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::mock::subgraph::MockSubgraph;
+    use crate::plugin::utils::test::{TestHarness, TestRouter};
+    use crate::Schema;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    async fn router() -> TestRouter {
+        let schema: Schema =
+            include_str!("../../../apollo-router-benchmarks/benches/fixtures/supergraph.graphql")
+                .parse()
+                .unwrap();
+        let products = MockSubgraph::default().with_matcher(
+            |_| true,
+            serde_json::from_value(json!({ "data": { "topProducts": [{ "upc": "1" }] } })).unwrap(),
+        );
+        TestHarness::new(Arc::new(schema))
+            .subgraph("products", products)
+            .plugin(
+                "experimental.expose_query_plan",
+                ExposeQueryPlan {
+                    config: Config {
+                        header: default_header(),
+                    },
+                },
+            )
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn query_plans_are_exposed_on_demand() {
+        let router = router().await;
+
+        let response = router
+            .execute(
+                RouterRequest::fake_builder()
+                    .header("apollo-expose-query-plan", "true")
+                    .query("{ topProducts { upc } }")
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        response.assert_no_errors();
+        assert!(response.extensions().get(QUERY_PLAN_EXTENSION).is_some());
+
+        let response = router.query("{ topProducts { upc } }").await.unwrap();
+        assert!(response.extensions().get(QUERY_PLAN_EXTENSION).is_none());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::error::Error as SubgraphError;
 use crate::plugin::Plugin;
-use crate::{register_plugin, SubgraphRequest, SubgraphResponse};
+use crate::{register_plugin, Object, SubgraphRequest, SubgraphResponse};
 use once_cell::sync::Lazy;
 use schemars::JsonSchema;
 use serde::Deserialize;
@@ -26,10 +26,98 @@ register_plugin!(
 #[derive(Clone, Debug, JsonSchema, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 struct Config {
+    /// Policy of the subgraphs missing from `subgraphs`. Errors are redacted by default.
     #[serde(default)]
-    all: bool,
+    all: Policy,
+    /// Policy of each subgraph, by subgraph name.
     #[serde(default)]
-    subgraphs: HashMap<String, bool>,
+    subgraphs: HashMap<String, Policy>,
+}
+
+/// How the errors of a subgraph reach clients: `true` to forward them, `false` to redact them,
+/// or a detailed policy.
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(untagged)]
+enum Policy {
+    Include(bool),
+    Detailed(ErrorPolicy),
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Include(false)
+    }
+}
+
+/// A detailed error policy.
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ErrorPolicy {
+    /// What clients get of the errors.
+    mode: Mode,
+    /// Message of the errors in the `replace` mode.
+    #[serde(default = "default_message")]
+    message: String,
+    /// Extension keys kept on the errors in the `forward` and `replace` modes. When missing, every
+    /// extension is kept by `forward` and none by `replace`.
+    #[serde(default)]
+    allowed_extensions: Option<Vec<String>>,
+}
+
+fn default_message() -> String {
+    "Subgraph error".to_string()
+}
+
+#[derive(Clone, Copy, Debug, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    /// The errors are forwarded.
+    Forward,
+    /// The errors are replaced by a single error saying they were redacted.
+    Redact,
+    /// The message of each error is replaced by a generic one, keeping its path and locations.
+    Replace,
+}
+
+impl ErrorPolicy {
+    fn apply(&self, errors: Vec<SubgraphError>) -> Vec<SubgraphError> {
+        match self.mode {
+            Mode::Redact => REDACTED_ERROR_MESSAGE.clone(),
+            Mode::Forward => match &self.allowed_extensions {
+                Some(allowed) => errors
+                    .into_iter()
+                    .map(|error| SubgraphError {
+                        extensions: allowed_extensions(error.extensions, allowed),
+                        ..error
+                    })
+                    .collect(),
+                None => errors,
+            },
+            Mode::Replace => errors
+                .into_iter()
+                .map(|error| SubgraphError {
+                    message: self.message.clone(),
+                    locations: error.locations,
+                    path: error.path,
+                    extensions: match &self.allowed_extensions {
+                        Some(allowed) => allowed_extensions(error.extensions, allowed),
+                        None => Object::new(),
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+fn allowed_extensions(extensions: Object, allowed: &[String]) -> Object {
+    extensions
+        .into_iter()
+        .filter(|(key, _)| {
+            allowed
+                .iter()
+                .any(|allowed| allowed.as_str() == key.as_str())
+        })
+        .collect()
 }
 
 struct IncludeSubgraphErrors {
@@ -51,17 +139,24 @@ impl Plugin for IncludeSubgraphErrors {
     ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
         // Search for subgraph in our configured subgraph map.
         // If we can't find it, use the "all" value
-        if !*self.config.subgraphs.get(name).unwrap_or(&self.config.all) {
-            return service
-                .map_response(move |mut response: SubgraphResponse| {
-                    if !response.response.body().errors.is_empty() {
-                        response.response.body_mut().errors = REDACTED_ERROR_MESSAGE.clone();
-                    }
-                    response
-                })
-                .boxed();
-        }
+        let policy = match self.config.subgraphs.get(name).unwrap_or(&self.config.all) {
+            Policy::Include(true) => return service,
+            Policy::Include(false) => ErrorPolicy {
+                mode: Mode::Redact,
+                message: default_message(),
+                allowed_extensions: None,
+            },
+            Policy::Detailed(policy) => policy.clone(),
+        };
         service
+            .map_response(move |mut response: SubgraphResponse| {
+                let body = response.response.body_mut();
+                if !body.errors.is_empty() {
+                    body.errors = policy.apply(std::mem::take(&mut body.errors));
+                }
+                response
+            })
+            .boxed()
     }
 }
 
@@ -70,7 +165,7 @@ mod test {
     use super::*;
     use crate::plugin::utils::test::mock::subgraph::MockSubgraph;
     use crate::{
-        DynPlugin, Object, PluggableRouterServiceBuilder, Response, ResponseBody, RouterRequest,
+        DynPlugin, PluggableRouterServiceBuilder, Response, ResponseBody, RouterRequest,
         RouterResponse, Schema,
     };
     use bytes::Bytes;
@@ -96,6 +191,14 @@ mod test {
     )
     });
 
+    static REPLACED_PRODUCT_RESPONSE: Lazy<ResponseBody> = Lazy::new(|| {
+        ResponseBody::GraphQL(serde_json::from_str(r#"{"data": {"topProducts":null}, "errors":[{"message": "Subgraph error", "locations": [], "path": null, "extensions": { "test": "value" }}]}"#).unwrap())
+    });
+
+    static FILTERED_PRODUCT_RESPONSE: Lazy<ResponseBody> = Lazy::new(|| {
+        ResponseBody::GraphQL(serde_json::from_str(r#"{"data": {"topProducts":null}, "errors":[{"message": "couldn't find mock for query", "locations": [], "path": null, "extensions": {}}]}"#).unwrap())
+    });
+
     static EXPECTED_RESPONSE: Lazy<ResponseBody> = Lazy::new(|| {
         ResponseBody::GraphQL(serde_json::from_str(r#"{"data":{"topProducts":[{"upc":"1","name":"Table","reviews":[{"id":"1","product":{"name":"Table"},"author":{"id":"1","name":"Ada Lovelace"}},{"id":"4","product":{"name":"Table"},"author":{"id":"2","name":"Alan Turing"}}]},{"upc":"2","name":"Couch","reviews":[{"id":"2","product":{"name":"Couch"},"author":{"id":"1","name":"Ada Lovelace"}}]}]}}"#).unwrap())
     });
@@ -284,4 +387,26 @@ mod test {
         let router = build_mock_router(plugin).await;
         execute_router_test(ERROR_ACCOUNT_QUERY, &*REDACTED_ACCOUNT_RESPONSE, router).await;
     }
+
+    #[tokio::test]
+    async fn it_replaces_messages_keeping_allowed_extensions() {
+        let plugin = get_redacting_plugin(&serde_json::json!({
+            "subgraphs": {
+                "products": { "mode": "replace", "allowed_extensions": ["test"] }
+            }
+        }))
+        .await;
+        let router = build_mock_router(plugin).await;
+        execute_router_test(ERROR_PRODUCT_QUERY, &*REPLACED_PRODUCT_RESPONSE, router).await;
+    }
+
+    #[tokio::test]
+    async fn it_forwards_messages_without_extensions_outside_the_allowlist() {
+        let plugin = get_redacting_plugin(&serde_json::json!({
+            "all": { "mode": "forward", "allowed_extensions": [] }
+        }))
+        .await;
+        let router = build_mock_router(plugin).await;
+        execute_router_test(ERROR_PRODUCT_QUERY, &*FILTERED_PRODUCT_RESPONSE, router).await;
+    }
 }
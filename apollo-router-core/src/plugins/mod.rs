@@ -2,8 +2,23 @@
 //!
 //! These plugins are compiled into the router and configured via YAML configuration.
 
+mod access_log;
+#[cfg(any(test, feature = "chaos"))]
+mod chaos;
+mod demand_control;
+mod entity_batching;
+mod entity_cache;
+mod expose_query_plan;
 mod forbid_mutations;
 mod headers;
 mod include_subgraph_errors;
+mod operation_limits;
+mod operation_signature;
+mod parallelism;
+mod partial_results;
+mod pipeline_retry;
+mod response_cache;
+mod safelist;
 pub mod serde_utils;
+mod subgraph_response_validation;
 mod traffic_shaping;
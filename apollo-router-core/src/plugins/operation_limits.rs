@@ -0,0 +1,440 @@
+//! Rejects operations too large or too expensive to be worth executing.
+//!
+//! The operation is measured on its parsed document, before any planning:
+//! * its depth, root fields being at depth 1,
+//! * the number of aliased fields,
+//! * the number of fields selected on the root type,
+//! * a cost estimate, where each field costs 1 for every time it may be resolved. Fields taking a
+//!   `first`, `last` or `limit` argument are expected to return that many items, multiplying the
+//!   cost of their selections.
+//!
+//! The measurements are kept in the context of every request, under
+//! [`OPERATION_MEASUREMENTS_CONTEXT_KEY`], whether or not limits are configured.
+
+use crate::plugin::Plugin;
+use crate::{
+    register_plugin, FragmentWalk, Object, RouterRequest, RouterResponse, ServiceBuilderExt,
+};
+use apollo_parser::ast;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+/// Context key holding the [`OperationMeasurements`] of the operation of a request.
+pub const OPERATION_MEASUREMENTS_CONTEXT_KEY: &str = "apollo::operation_limits::measurements";
+
+/// Arguments taken as the number of items a list field returns.
+const LIST_SIZE_ARGUMENTS: [&str; 3] = ["first", "last", "limit"];
+
+#[derive(Clone, Debug, Default, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Maximum depth of an operation.
+    max_depth: Option<u64>,
+    /// Maximum number of aliased fields in an operation.
+    max_aliases: Option<u64>,
+    /// Maximum number of fields selected on the root type.
+    max_root_fields: Option<u64>,
+    /// Maximum estimated cost of an operation.
+    max_cost: Option<u64>,
+}
+
+/// Measurements of an operation, compared to the configured limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationMeasurements {
+    pub depth: u64,
+    pub aliases: u64,
+    pub root_fields: u64,
+    pub cost: u64,
+}
+
+impl OperationMeasurements {
+    /// Measures the operation named `operation_name` of `query`, or its first operation.
+    ///
+    /// Returns `None` if the operation cannot be found, leaving the error to query planning.
+    fn new(query: &str, operation_name: Option<&str>, variables: &Object) -> Option<Self> {
+        let document = apollo_parser::Parser::new(query).parse().document();
+
+        let mut fragments = HashMap::new();
+        let mut operation = None;
+        for definition in document.definitions() {
+            match definition {
+                ast::Definition::FragmentDefinition(fragment) => {
+                    if let Some(name) = fragment
+                        .fragment_name()
+                        .and_then(|fragment_name| fragment_name.name())
+                    {
+                        fragments.insert(name.text().to_string(), fragment);
+                    }
+                }
+                ast::Definition::OperationDefinition(definition) if operation.is_none() => {
+                    let name = definition.name().map(|name| name.text().to_string());
+                    if operation_name.is_none() || name.as_deref() == operation_name {
+                        operation = Some(definition);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut measure = Measure {
+            fragments,
+            variables,
+            summaries: HashMap::new(),
+        };
+        let measured = measure.selection_set(operation?.selection_set()?);
+        Some(OperationMeasurements {
+            depth: measured.depth,
+            aliases: measured.aliases,
+            root_fields: measured.root_fields,
+            cost: measured.cost,
+        })
+    }
+
+    /// The first limit of `config` exceeded by these measurements, as an error code, the limit and
+    /// the measured value.
+    fn exceeded(&self, config: &Config) -> Option<(&'static str, u64, u64)> {
+        [
+            ("MAX_DEPTH_LIMIT", config.max_depth, self.depth),
+            ("MAX_ALIASES_LIMIT", config.max_aliases, self.aliases),
+            (
+                "MAX_ROOT_FIELDS_LIMIT",
+                config.max_root_fields,
+                self.root_fields,
+            ),
+            ("MAX_COST_LIMIT", config.max_cost, self.cost),
+        ]
+        .into_iter()
+        .find_map(|(code, limit, measured)| match limit {
+            Some(limit) if measured > limit => Some((code, limit, measured)),
+            _ => None,
+        })
+    }
+}
+
+struct Measure<'a> {
+    fragments: HashMap<String, ast::FragmentDefinition>,
+    variables: &'a Object,
+    summaries: HashMap<String, Option<Measured>>,
+}
+
+/// Measurements of a selection set, as if its fields were at the root of the operation.
+#[derive(Clone, Copy, Debug, Default)]
+struct Measured {
+    depth: u64,
+    aliases: u64,
+    root_fields: u64,
+    cost: u64,
+}
+
+impl Measured {
+    /// Adds the measurements of selections found at the same depth.
+    fn add(&mut self, other: Measured) {
+        self.depth = self.depth.max(other.depth);
+        self.aliases = self.aliases.saturating_add(other.aliases);
+        self.root_fields = self.root_fields.saturating_add(other.root_fields);
+        self.cost = self.cost.saturating_add(other.cost);
+    }
+}
+
+impl<'a> FragmentWalk for Measure<'a> {
+    type Summary = Measured;
+
+    fn summaries(&mut self) -> &mut HashMap<String, Option<Measured>> {
+        &mut self.summaries
+    }
+}
+
+impl<'a> Measure<'a> {
+    fn selection_set(&mut self, selection_set: ast::SelectionSet) -> Measured {
+        let mut measured = Measured::default();
+        for selection in selection_set.selections() {
+            match selection {
+                ast::Selection::Field(field) => {
+                    let mut field_measured = Measured {
+                        depth: 1,
+                        aliases: field.alias().is_some() as u64,
+                        root_fields: 1,
+                        cost: 1,
+                    };
+                    if let Some(selection_set) = field.selection_set() {
+                        let selections = self.selection_set(selection_set);
+                        field_measured.depth = selections.depth.saturating_add(1);
+                        field_measured.aliases =
+                            field_measured.aliases.saturating_add(selections.aliases);
+                        field_measured.cost = selections
+                            .cost
+                            .saturating_mul(self.list_size(&field))
+                            .saturating_add(1);
+                    }
+                    measured.add(field_measured);
+                }
+                ast::Selection::InlineFragment(fragment) => {
+                    if let Some(selection_set) = fragment.selection_set() {
+                        let fragment_measured = self.selection_set(selection_set);
+                        measured.add(fragment_measured);
+                    }
+                }
+                ast::Selection::FragmentSpread(spread) => {
+                    let name = match spread
+                        .fragment_name()
+                        .and_then(|fragment_name| fragment_name.name())
+                    {
+                        Some(name) => name.text().to_string(),
+                        None => continue,
+                    };
+                    let selection_set = match self
+                        .fragments
+                        .get(&name)
+                        .and_then(|fragment| fragment.selection_set())
+                    {
+                        Some(selection_set) => selection_set,
+                        None => continue,
+                    };
+                    if let Some(fragment_measured) =
+                        self.fragment_summary(&name, |measure| measure.selection_set(selection_set))
+                    {
+                        measured.add(fragment_measured);
+                    }
+                }
+            }
+        }
+        measured
+    }
+
+    /// Number of items the field is expected to return, from its list size argument.
+    fn list_size(&self, field: &ast::Field) -> u64 {
+        field
+            .arguments()
+            .into_iter()
+            .flat_map(|arguments| arguments.arguments())
+            .find(|argument| {
+                argument
+                    .name()
+                    .map(|name| LIST_SIZE_ARGUMENTS.contains(&name.text().as_str()))
+                    .unwrap_or_default()
+            })
+            .and_then(|argument| match argument.value()? {
+                ast::Value::IntValue(value) => value.to_string().trim().parse().ok(),
+                ast::Value::Variable(variable) => self
+                    .variables
+                    .get(variable.name()?.text().as_str())
+                    .and_then(|value| value.as_u64()),
+                _ => None,
+            })
+            .unwrap_or(1)
+    }
+}
+
+#[derive(Debug)]
+struct OperationLimits {
+    config: Config,
+}
+
+#[async_trait::async_trait]
+impl Plugin for OperationLimits {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(OperationLimits { config })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let config = self.config.clone();
+
+        ServiceBuilder::new()
+            .checkpoint(move |req: RouterRequest| {
+                let body = req.originating_request.body();
+                let measurements = match body.query.as_deref().and_then(|query| {
+                    OperationMeasurements::new(
+                        query,
+                        body.operation_name.as_deref(),
+                        &body.variables,
+                    )
+                }) {
+                    Some(measurements) => measurements,
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+                req.context
+                    .insert(OPERATION_MEASUREMENTS_CONTEXT_KEY, measurements)?;
+
+                let (code, limit, measured) = match measurements.exceeded(&config) {
+                    Some(exceeded) => exceeded,
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+                let mut extensions = Object::default();
+                extensions.insert("code", code.into());
+                extensions.insert("limit", limit.into());
+                extensions.insert("measured", measured.into());
+                let res = RouterResponse::builder()
+                    .errors(vec![crate::Error {
+                        message: format!(
+                            "operation exceeds the {} limit: {} > {}",
+                            code.trim_start_matches("MAX_")
+                                .trim_end_matches("_LIMIT")
+                                .to_lowercase()
+                                .replace('_', " "),
+                            measured,
+                            limit
+                        ),
+                        extensions,
+                        ..Default::default()
+                    }])
+                    .status_code(StatusCode::BAD_REQUEST)
+                    .context(req.context)
+                    .build()?;
+                Ok(ControlFlow::Break(res))
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("experimental", "operation_limits", OperationLimits);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockRouterService;
+    use crate::ResponseBody;
+    use serde_json_bytes::json;
+    use tower::Service;
+
+    fn measure(query: &str) -> OperationMeasurements {
+        OperationMeasurements::new(query, None, &Object::default()).unwrap()
+    }
+
+    #[test]
+    fn operations_are_measured() {
+        assert_eq!(
+            measure("{ me { name reviews { body } } topProducts { upc } }"),
+            OperationMeasurements {
+                depth: 3,
+                aliases: 0,
+                root_fields: 2,
+                cost: 6,
+            }
+        );
+        assert_eq!(
+            measure(
+                "{ a: me { ...User } b: me { ...User } }
+                fragment User on User { name ... on User { username } }"
+            ),
+            OperationMeasurements {
+                depth: 2,
+                aliases: 2,
+                root_fields: 2,
+                cost: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn list_sizes_multiply_the_cost() {
+        assert_eq!(
+            measure("{ topProducts(first: 10) { upc reviews(first: 5) { body } } }").cost,
+            1 + 10 * (1 + 1 + 5)
+        );
+
+        let variables = json!({ "first": 3 });
+        let measurements = OperationMeasurements::new(
+            "query A { me { name } } query B($first: Int) { topProducts(first: $first) { upc } }",
+            Some("B"),
+            variables.as_object().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(measurements.cost, 4);
+    }
+
+    #[test]
+    fn recursive_fragments_are_measured_once() {
+        assert_eq!(
+            measure(
+                "{ me { ...A } }
+                fragment A on User { name ...B }
+                fragment B on User { username ...A }"
+            )
+            .cost,
+            3
+        );
+    }
+
+    #[test]
+    fn fragments_spread_many_times_are_measured_once() {
+        let mut query = "{ me { ...F0 } }".to_string();
+        for level in 0..30 {
+            query.push_str(&format!(
+                " fragment F{} on User {{ ...F{} ...F{} }}",
+                level,
+                level + 1,
+                level + 1
+            ));
+        }
+        query.push_str(" fragment F30 on User { name }");
+
+        assert_eq!(measure(&query).cost, 1 + (1 << 30));
+    }
+
+    #[tokio::test]
+    async fn operations_over_the_limits_are_rejected() {
+        let mut mock = MockRouterService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|_| Ok(RouterResponse::fake_builder().build().unwrap()));
+        let mock = mock.build();
+
+        let config: Config = serde_yaml::from_str("max_depth: 2\nmax_aliases: 1").unwrap();
+        let mut plugin = OperationLimits::new(config).await.unwrap();
+        let mut service = plugin.router_service(BoxService::new(mock));
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(
+                RouterRequest::fake_builder()
+                    .query("{ me { name } }".to_string())
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
+        let measurements: OperationMeasurements = response
+            .context
+            .get(OPERATION_MEASUREMENTS_CONTEXT_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(measurements.cost, 2);
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(
+                RouterRequest::fake_builder()
+                    .query("{ me { reviews { body } } }".to_string())
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+        match response.response.into_body() {
+            ResponseBody::GraphQL(body) => {
+                let extensions = &body.errors[0].extensions;
+                assert_eq!(extensions.get("code"), Some(&"MAX_DEPTH_LIMIT".into()));
+                assert_eq!(extensions.get("limit"), Some(&2.into()));
+                assert_eq!(extensions.get("measured"), Some(&3.into()));
+            }
+            _ => panic!("expected a GraphQL response"),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Computes the signature of the operation of each request, for logs and telemetry.
+//!
+//! The signature is stored in the request context under
+//! [`OPERATION_SIGNATURE_CONTEXT_KEY`](crate::OPERATION_SIGNATURE_CONTEXT_KEY), where the access
+//! log and other plugins find it, so that operations can be told apart without logging the
+//! values they carry. Signatures are kept for the most recent operations, so that the query of a
+//! request is only parsed again when its operation was not signed lately; the hashes of the
+//! variables are made for each request.
+
+use crate::{
+    register_plugin, OperationSanitizer, OperationSignature, Plugin, RouterRequest, RouterResponse,
+    OPERATION_SIGNATURE_CONTEXT_KEY,
+};
+use lru::LruCache;
+use std::sync::{Arc, Mutex};
+use tower::util::BoxService;
+use tower::{BoxError, ServiceExt};
+
+/// Number of operations whose signature is kept.
+const SIGNATURE_CACHE_SIZE: usize = 512;
+
+/// Signatures by query and operation name.
+type Signatures = LruCache<(String, Option<String>), Option<OperationSignature>>;
+
+#[derive(Debug)]
+struct OperationSignaturePlugin {
+    sanitizer: Arc<OperationSanitizer>,
+    signatures: Arc<Mutex<Signatures>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for OperationSignaturePlugin {
+    type Config = OperationSanitizer;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        if config.hash_variables && config.variables_secret.is_none() {
+            return Err("hashing variables needs a variables_secret".into());
+        }
+        Ok(OperationSignaturePlugin {
+            sanitizer: Arc::new(config),
+            signatures: Arc::new(Mutex::new(LruCache::new(SIGNATURE_CACHE_SIZE))),
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let sanitizer = self.sanitizer.clone();
+        let signatures = self.signatures.clone();
+        service
+            .map_request(move |request: RouterRequest| {
+                let body = request.originating_request.body();
+                let signature = body.query.as_ref().and_then(|query| {
+                    let key = (query.clone(), body.operation_name.clone());
+                    let cached = signatures.lock().expect("lock poisoned").get(&key).cloned();
+                    let mut signature = match cached {
+                        Some(signature) => signature,
+                        None => {
+                            let signature =
+                                sanitizer.signature(query, body.operation_name.as_deref());
+                            signatures
+                                .lock()
+                                .expect("lock poisoned")
+                                .put(key, signature.clone());
+                            signature
+                        }
+                    }?;
+                    signature.variables = sanitizer.variable_hashes(&body.variables);
+                    Some(signature)
+                });
+                if let Some(signature) = signature {
+                    if let Err(err) = request
+                        .context
+                        .insert(OPERATION_SIGNATURE_CONTEXT_KEY, signature)
+                    {
+                        tracing::debug!("could not store the operation signature: {}", err);
+                    }
+                }
+                request
+            })
+            .boxed()
+    }
+}
+
+register_plugin!(
+    "experimental",
+    "operation_signature",
+    OperationSignaturePlugin
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockRouterService;
+
+    #[tokio::test]
+    async fn signatures_are_stored_in_the_context() {
+        let mut mock_service = MockRouterService::new();
+        mock_service.expect_call().times(1).returning(|request| {
+            let signature: OperationSignature = request
+                .context
+                .get(OPERATION_SIGNATURE_CONTEXT_KEY)
+                .unwrap()
+                .unwrap();
+            assert_eq!(signature.signature, "query Me { me(id: \"\") { name } }");
+            assert!(signature.variables.unwrap().contains_key("verbose"));
+            Ok(RouterResponse::fake_builder().build().unwrap())
+        });
+
+        assert!(OperationSignaturePlugin::new(OperationSanitizer::default())
+            .await
+            .is_err());
+        let mut plugin = OperationSignaturePlugin::new(OperationSanitizer {
+            variables_secret: Some("secret".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        plugin
+            .router_service(mock_service.build().boxed())
+            .oneshot(
+                RouterRequest::fake_builder()
+                    .query("query Me { me(id: \"42\") { name } }".to_string())
+                    .operation_name("Me".to_string())
+                    .variable("verbose", true)
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+}
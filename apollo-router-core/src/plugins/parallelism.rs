@@ -0,0 +1,159 @@
+//! Controls how many subgraph fetches run at once, for each request and across requests.
+//!
+//! The limits of a request are given to the execution of its query plan, which waits for a fetch
+//! to finish before starting one over them. The global limit is shared by the requests to every
+//! subgraph, so that a burst of client requests cannot flood small subgraphs.
+
+use crate::{
+    register_plugin, ExecutionRequest, ExecutionResponse, Parallelism, Plugin, SubgraphRequest,
+    SubgraphResponse, PARALLELISM_CONTEXT_KEY,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Most subgraph fetches in flight at once for a single request. Unlimited by default.
+    #[serde(default)]
+    max_concurrent_fetches: Option<usize>,
+    /// Run the nodes of parallel plan nodes one after the other. Disabled by default.
+    #[serde(default)]
+    sequential: bool,
+    /// Most subgraph fetches in flight at once across all requests, others waiting for their
+    /// turn. Unlimited by default.
+    #[serde(default)]
+    max_concurrent_fetches_global: Option<usize>,
+}
+
+#[derive(Debug)]
+struct ParallelismPlugin {
+    settings: Parallelism,
+    global: Option<Arc<Semaphore>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ParallelismPlugin {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(ParallelismPlugin {
+            settings: Parallelism {
+                max_concurrent_fetches: config.max_concurrent_fetches,
+                sequential: config.sequential,
+            },
+            global: config
+                .max_concurrent_fetches_global
+                .map(|permits| Arc::new(Semaphore::new(permits))),
+        })
+    }
+
+    fn execution_service(
+        &mut self,
+        service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+    ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        if self.settings == Parallelism::default() {
+            return service;
+        }
+        let settings = self.settings.clone();
+        service
+            .map_request(move |request: ExecutionRequest| {
+                if let Err(err) = request
+                    .context
+                    .insert(PARALLELISM_CONTEXT_KEY, settings.clone())
+                {
+                    tracing::debug!("could not set the parallelism of the request: {}", err);
+                }
+                request
+            })
+            .boxed()
+    }
+
+    fn subgraph_service(
+        &mut self,
+        _name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        match &self.global {
+            Some(semaphore) => ServiceBuilder::new()
+                .layer(GlobalConcurrencyLimitLayer::with_semaphore(
+                    semaphore.clone(),
+                ))
+                .service(service)
+                .boxed(),
+            None => service,
+        }
+    }
+}
+
+register_plugin!("experimental", "parallelism", ParallelismPlugin);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::{MockExecutionService, MockSubgraphService};
+    use crate::DynPlugin;
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn plugin(config: serde_json::Value) -> Box<dyn DynPlugin> {
+        crate::plugins()
+            .get("experimental.parallelism")
+            .expect("Plugin not found")
+            .create_instance(&config)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn settings_are_given_to_the_execution() {
+        let mut mock = MockExecutionService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|request: ExecutionRequest| {
+                let settings = request
+                    .context
+                    .get::<_, Parallelism>(PARALLELISM_CONTEXT_KEY)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(settings.max_concurrent_fetches, Some(4));
+                assert!(settings.sequential);
+                Ok(ExecutionResponse::fake_builder().build())
+            });
+
+        plugin(json!({ "max_concurrent_fetches": 4, "sequential": true }))
+            .await
+            .execution_service(BoxService::new(mock.build()))
+            .oneshot(ExecutionRequest::fake_builder().build())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_global_limit_is_shared_by_subgraphs() {
+        let mut plugin = plugin(json!({ "max_concurrent_fetches_global": 1 })).await;
+        let service = |plugin: &mut Box<dyn DynPlugin>, name: &str| {
+            let mut mock = MockSubgraphService::new();
+            mock.expect_call()
+                .returning(|_| Ok(SubgraphResponse::fake_builder().build()));
+            plugin.subgraph_service(name, BoxService::new(mock.build()))
+        };
+        let mut accounts = service(&mut plugin, "accounts");
+        let mut products = service(&mut plugin, "products");
+
+        // A fetch to `accounts` holds the only permit until it is sent.
+        accounts.ready().await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), products.ready())
+                .await
+                .is_err()
+        );
+        drop(accounts);
+        assert!(products.ready().await.is_ok());
+    }
+}
@@ -0,0 +1,104 @@
+//! Controls whether a response may carry partial data when some of its subgraph fetches failed.
+//!
+//! By default, the data of the fetches that succeeded is returned along with the errors of the
+//! failed ones, at their path, and the fields they could not resolve are nulled out up to the
+//! closest nullable parent. With `all_or_nothing`, a single error turns the whole `data` to `null`.
+
+use crate::{register_plugin, ExecutionRequest, ExecutionResponse, Plugin, Value};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceExt};
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Return `null` data when any part of the execution failed, instead of partial data.
+    #[serde(default)]
+    all_or_nothing: bool,
+}
+
+#[derive(Debug)]
+struct PartialResults {
+    config: Config,
+}
+
+#[async_trait::async_trait]
+impl Plugin for PartialResults {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(PartialResults { config })
+    }
+
+    fn execution_service(
+        &mut self,
+        service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+    ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        if !self.config.all_or_nothing {
+            return service;
+        }
+        service
+            .map_response(|mut response: ExecutionResponse| {
+                let body = response.response.body_mut();
+                if !body.errors.is_empty() {
+                    body.data = Some(Value::Null);
+                }
+                response
+            })
+            .boxed()
+    }
+}
+
+register_plugin!("experimental", "partial_results", PartialResults);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockExecutionService;
+    use crate::Error;
+    use serde_json::json;
+    use serde_json_bytes::json as bjson;
+
+    async fn execute(config: serde_json::Value) -> Option<Value> {
+        let mut mock = MockExecutionService::new();
+        mock.expect_call().times(1).returning(|_| {
+            Ok(ExecutionResponse::fake_builder()
+                .data(bjson!({ "me": { "name": "Ada" }, "topProducts": null }))
+                .errors(vec![Error {
+                    message: "products unavailable".to_string(),
+                    ..Default::default()
+                }])
+                .build())
+        });
+
+        let mut plugin = crate::plugins()
+            .get("experimental.partial_results")
+            .expect("Plugin not found")
+            .create_instance(&config)
+            .await
+            .unwrap();
+        let response = plugin
+            .execution_service(BoxService::new(mock.build()))
+            .oneshot(ExecutionRequest::fake_builder().build())
+            .await
+            .unwrap();
+        response.response.into_body().data
+    }
+
+    #[tokio::test]
+    async fn partial_data_is_kept_by_default() {
+        assert_eq!(
+            execute(json!({})).await,
+            Some(bjson!({ "me": { "name": "Ada" }, "topProducts": null }))
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_null_the_data_when_all_or_nothing() {
+        assert_eq!(
+            execute(json!({ "all_or_nothing": true })).await,
+            Some(Value::Null)
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Retries a whole router request when it fails before reaching any subgraph.
+//!
+//! Such failures, like the query planner being briefly unavailable during a reload, have no side
+//! effect and are safe to retry. As soon as a subgraph request goes out, which is recorded in the
+//! request context, the request is never retried again so that mutations are not sent twice.
+//!
+//! Every attempt starts from the context the request came with: the entries added by a failed
+//! attempt are removed before the next one.
+
+use crate::plugin::Plugin;
+use crate::{
+    register_plugin, Context, RouterRequest, RouterResponse, ServiceBuilderExt, SubgraphRequest,
+    SubgraphResponse, Value,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::util::BoxService;
+use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+
+/// Context key set once a subgraph request has been sent for the router request.
+pub const SUBGRAPH_CONTACTED: &str = "apollo::pipeline_retry::subgraph_contacted";
+
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Number of times a request failing before any subgraph is contacted is retried.
+    max_retries: usize,
+}
+
+struct PipelineRetry {
+    config: Config,
+}
+
+#[async_trait::async_trait]
+impl Plugin for PipelineRetry {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(PipelineRetry { config })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let max_retries = self.config.max_retries;
+        //Buffer is required because every attempt needs its own clone of the service.
+        let service = ServiceBuilder::new().buffered().service(service);
+        tower::service_fn(move |request: RouterRequest| {
+            call_with_retries(service.clone(), request, max_retries)
+        })
+        .boxed()
+    }
+
+    fn subgraph_service(
+        &mut self,
+        _name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        service
+            .map_request(|request: SubgraphRequest| {
+                if let Err(err) = request.context.insert(SUBGRAPH_CONTACTED, true) {
+                    tracing::error!("could not record the subgraph request: {}", err);
+                }
+                request
+            })
+            .boxed()
+    }
+}
+
+fn subgraph_contacted(context: &Context) -> bool {
+    context
+        .get::<_, bool>(SUBGRAPH_CONTACTED)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Calls `service` until the request succeeds, reaches a subgraph, or runs out of retries.
+///
+/// The context is shared by all the attempts, so that the layers wrapping this one see the
+/// entries of the last one, and is reset to its initial entries before each retry.
+async fn call_with_retries<S>(
+    mut service: S,
+    request: RouterRequest,
+    max_retries: usize,
+) -> Result<RouterResponse, BoxError>
+where
+    S: Service<RouterRequest, Response = RouterResponse, Error = BoxError>,
+{
+    let RouterRequest {
+        originating_request,
+        context,
+    } = request;
+    let initial_entries: Vec<(String, Value)> = context
+        .entries
+        .iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+
+    let mut remaining = max_retries;
+    loop {
+        let result = service
+            .ready()
+            .await?
+            .call(RouterRequest {
+                originating_request: originating_request.clone(),
+                context: context.clone(),
+            })
+            .await;
+        let failed = match &result {
+            Ok(response) => response.response.status().is_server_error(),
+            Err(_) => true,
+        };
+        if !failed || remaining == 0 || subgraph_contacted(&context) {
+            return result;
+        }
+
+        tracing::info!("retrying request that failed before reaching any subgraph");
+        remaining -= 1;
+        context.entries.clear();
+        for (key, value) in &initial_entries {
+            context.entries.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+register_plugin!("experimental", "pipeline_retry", PipelineRetry);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::{MockRouterService, MockSubgraphService};
+    use crate::DynPlugin;
+    use http::StatusCode;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    async fn plugin() -> Box<dyn DynPlugin> {
+        crate::plugins()
+            .get("experimental.pipeline_retry")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "max_retries": 2 }))
+            .await
+            .unwrap()
+    }
+
+    fn response(status_code: StatusCode, context: Context) -> RouterResponse {
+        RouterResponse::fake_builder()
+            .status_code(status_code)
+            .context(context)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn failure_before_subgraphs_is_retried() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut mock = MockRouterService::new();
+        mock.expect_call().times(2).returning({
+            let calls = calls.clone();
+            move |request: RouterRequest| {
+                let status_code = match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::OK,
+                };
+                Ok(response(status_code, request.context))
+            }
+        });
+
+        let response = plugin()
+            .await
+            .router_service(BoxService::new(mock.build()))
+            .oneshot(RouterRequest::fake_builder().build().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_start_from_the_initial_context() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut mock = MockRouterService::new();
+        mock.expect_call().times(2).returning({
+            let calls = calls.clone();
+            move |request: RouterRequest| {
+                let status_code = match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => {
+                        request
+                            .context
+                            .insert("attempt", "failed".to_string())
+                            .unwrap();
+                        StatusCode::SERVICE_UNAVAILABLE
+                    }
+                    _ => {
+                        assert_eq!(request.context.get::<_, String>("attempt").unwrap(), None);
+                        assert_eq!(
+                            request.context.get::<_, String>("client").unwrap(),
+                            Some("ios".to_string())
+                        );
+                        StatusCode::OK
+                    }
+                };
+                Ok(response(status_code, request.context))
+            }
+        });
+
+        let context = Context::new();
+        context.insert("client", "ios".to_string()).unwrap();
+        let response = plugin()
+            .await
+            .router_service(BoxService::new(mock.build()))
+            .oneshot(
+                RouterRequest::fake_builder()
+                    .context(context)
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failure_after_a_subgraph_request_is_not_retried() {
+        let mut mock = MockRouterService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|request: RouterRequest| {
+                request.context.insert(SUBGRAPH_CONTACTED, true).unwrap();
+                Ok(response(StatusCode::INTERNAL_SERVER_ERROR, request.context))
+            });
+
+        let response = plugin()
+            .await
+            .router_service(BoxService::new(mock.build()))
+            .oneshot(RouterRequest::fake_builder().build().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.response.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn subgraph_requests_are_recorded() {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|_| Ok(SubgraphResponse::fake_builder().build()));
+
+        let context = Context::new();
+        plugin()
+            .await
+            .subgraph_service("products", BoxService::new(mock.build()))
+            .oneshot(
+                SubgraphRequest::fake_builder()
+                    .context(context.clone())
+                    .build(),
+            )
+            .await
+            .unwrap();
+
+        assert!(subgraph_contacted(&context));
+    }
+}
@@ -0,0 +1,894 @@
+//! Caches subgraph responses according to the `Cache-Control` header they were served with.
+//!
+//! The TTL of a response is, by order of precedence:
+//! - the TTL configured for the operation in `operations`, or the `maxAge` of a `@cacheControl`
+//!   directive on the operation,
+//! - the lowest of the `max-age` advertised by the subgraph and of the `maxAge` of the
+//!   `cacheControl` hints in the response extensions,
+//! - the configured `default_ttl`,
+//!
+//! and is then clamped to the configured `[min_ttl, max_ttl]` range.
+//! Responses carrying GraphQL errors, non successful status codes, `no-store`/`no-cache` directives
+//! or marked as private, by a `private` directive or a `PRIVATE` hint, are never cached.
+//!
+//! The policies of the subgraph responses fetched for a client request are combined into the
+//! `Cache-Control` header of the client response, which may be cached for as long as the shortest
+//! of them, and is private if any of them is. With `full_responses`, whole client responses are
+//! also cached, keyed by their query, operation name, variables and `vary_headers`. Requests
+//! carrying credentials in a header left out of the key are never served from or added to the
+//! cache.
+//!
+//! Responses are kept in the configured `storage`, which router instances may share.
+
+use crate::fetch::OperationKind;
+use crate::plugin::Plugin;
+use crate::{
+    http_compat, record_cache_lookups, register_plugin, CacheStorage, CacheStorageConfig,
+    HealthCheck, Request, Response, ResponseBody, RouterRequest, RouterResponse, ServiceBuilderExt,
+    StorageHealthCheck, SubgraphRequest, SubgraphResponse,
+};
+use apollo_parser::ast;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::header::{HeaderName, AUTHORIZATION, CACHE_CONTROL, COOKIE, PROXY_AUTHORIZATION};
+use http::HeaderValue;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower::buffer::Buffer;
+use tower::util::BoxService;
+use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+
+/// Context key holding the combined [`CachePolicy`] of the subgraph responses of a request.
+pub const CACHE_POLICY_CONTEXT_KEY: &str = "apollo::response_cache::policy";
+
+/// Headers carrying the credentials of a client.
+const CREDENTIALS: [HeaderName; 3] = [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION];
+
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+struct Config {
+    /// Lowest TTL, in seconds, given to a cacheable subgraph response.
+    #[serde(default)]
+    min_ttl: u64,
+    /// Highest TTL, in seconds, given to a cacheable subgraph response.
+    max_ttl: u64,
+    /// TTL, in seconds, of cacheable responses served without a `max-age`.
+    #[serde(default)]
+    default_ttl: Option<u64>,
+    /// TTL, in seconds, of the responses fetched for each named operation, overriding the
+    /// `max-age` of the subgraphs.
+    #[serde(default)]
+    operations: HashMap<String, u64>,
+    /// Whether whole client responses are cached as well as subgraph responses.
+    #[serde(default)]
+    full_responses: bool,
+    /// Headers of client requests whose values are part of the key of cached client responses.
+    /// Defaults to `authorization` and `cookie`.
+    #[serde(default = "default_vary_headers")]
+    vary_headers: Vec<String>,
+    /// Where responses are cached. Defaults to the memory of the router.
+    #[serde(default)]
+    storage: CacheStorageConfig,
+}
+
+fn default_vary_headers() -> Vec<String> {
+    vec![AUTHORIZATION.to_string(), COOKIE.to_string()]
+}
+
+/// How long a response may be cached for, and whether it is specific to the user it was fetched
+/// for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachePolicy {
+    pub max_age: u64,
+    pub private: bool,
+}
+
+impl CachePolicy {
+    fn merge(self, other: CachePolicy) -> CachePolicy {
+        CachePolicy {
+            max_age: self.max_age.min(other.max_age),
+            private: self.private || other.private,
+        }
+    }
+
+    fn header_value(&self) -> HeaderValue {
+        match (self.max_age, self.private) {
+            (0, _) => HeaderValue::from_static("no-store"),
+            (max_age, private) => HeaderValue::from_str(&format!(
+                "max-age={}, {}",
+                max_age,
+                if private { "private" } else { "public" }
+            ))
+            .expect("header value is valid; qed"),
+        }
+    }
+}
+
+fn record_policy(context: &crate::Context, policy: CachePolicy) {
+    if let Err(err) = context.upsert(
+        CACHE_POLICY_CONTEXT_KEY,
+        |recorded: CachePolicy| recorded.merge(policy),
+        || policy,
+    ) {
+        tracing::error!("could not record the cache policy: {}", err);
+    }
+}
+
+impl Config {
+    /// The TTL overriding the subgraph hints for the responses fetched for a request.
+    fn operation_ttl(&self, request: &Request) -> Option<u64> {
+        request
+            .operation_name
+            .as_ref()
+            .and_then(|name| self.operations.get(name).copied())
+            .or_else(|| {
+                request
+                    .query
+                    .as_deref()
+                    .and_then(|query| operation_max_age(query, request.operation_name.as_deref()))
+            })
+    }
+
+    /// Computes how long a subgraph response may be cached for, if at all.
+    fn ttl(
+        &self,
+        operation_ttl: Option<u64>,
+        response: &http_compat::Response<Response>,
+    ) -> Option<Duration> {
+        let policy = self.policy(operation_ttl, response);
+        (!policy.private && policy.max_age > 0).then(|| Duration::from_secs(policy.max_age))
+    }
+
+    /// Computes the cache policy of a subgraph response.
+    fn policy(
+        &self,
+        operation_ttl: Option<u64>,
+        response: &http_compat::Response<Response>,
+    ) -> CachePolicy {
+        let uncacheable = CachePolicy::default();
+        if !response.status().is_success() || !response.body().errors.is_empty() {
+            return uncacheable;
+        }
+
+        let mut private = false;
+        let max_age = response
+            .headers()
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_ascii_lowercase())
+            .try_fold(None, |max_age, directive| {
+                match directive.as_str() {
+                    "no-store" | "no-cache" => return Err(()),
+                    "private" => private = true,
+                    _ => {}
+                }
+                Ok(directive
+                    .strip_prefix("max-age=")
+                    .and_then(|seconds| seconds.trim_matches('"').parse::<u64>().ok())
+                    .or(max_age))
+            });
+        let max_age = match max_age {
+            Ok(max_age) => max_age,
+            Err(()) => return uncacheable,
+        };
+
+        let hints = cache_hints(response.body());
+        private |= hints.private;
+        let max_age = match (max_age, hints.max_age) {
+            (Some(header), Some(hint)) => Some(header.min(hint)),
+            (header, hint) => header.or(hint),
+        };
+
+        match operation_ttl.or(max_age).or(self.default_ttl) {
+            Some(ttl) => CachePolicy {
+                max_age: ttl.clamp(self.min_ttl, self.max_ttl.max(self.min_ttl)),
+                private,
+            },
+            None => uncacheable,
+        }
+    }
+}
+
+struct CacheHints {
+    max_age: Option<u64>,
+    private: bool,
+}
+
+/// The lowest `maxAge` and the scope of the `cacheControl` hints of a subgraph response.
+fn cache_hints(response: &Response) -> CacheHints {
+    let hints = response
+        .extensions
+        .get("cacheControl")
+        .and_then(|cache_control| cache_control.as_object())
+        .and_then(|cache_control| cache_control.get("hints"))
+        .and_then(|hints| hints.as_array());
+
+    let mut result = CacheHints {
+        max_age: None,
+        private: false,
+    };
+    for hint in hints
+        .into_iter()
+        .flatten()
+        .filter_map(|hint| hint.as_object())
+    {
+        if let Some(max_age) = hint.get("maxAge").and_then(|max_age| max_age.as_u64()) {
+            result.max_age = Some(result.max_age.map_or(max_age, |lowest| lowest.min(max_age)));
+        }
+        if hint.get("scope").and_then(|scope| scope.as_str()) == Some("PRIVATE") {
+            result.private = true;
+        }
+    }
+    result
+}
+
+/// The `maxAge` of a `@cacheControl` directive set on the executed operation.
+fn operation_max_age(query: &str, operation_name: Option<&str>) -> Option<u64> {
+    if !query.contains("@cacheControl") {
+        return None;
+    }
+
+    let document = apollo_parser::Parser::new(query).parse().document();
+    let operation = document
+        .definitions()
+        .find_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation)
+                if operation_name.is_none()
+                    || operation
+                        .name()
+                        .map(|name| name.text().to_string())
+                        .as_deref()
+                        == operation_name =>
+            {
+                Some(operation)
+            }
+            _ => None,
+        })?;
+
+    let directives = operation.directives()?;
+    let max_age = directives
+        .directives()
+        .filter(|directive| {
+            directive
+                .name()
+                .map(|name| name.text().to_string() == "cacheControl")
+                .unwrap_or(false)
+        })
+        .flat_map(|directive| directive.arguments())
+        .flat_map(|arguments| arguments.arguments())
+        .find(|argument| {
+            argument
+                .name()
+                .map(|name| name.text().to_string() == "maxAge")
+                .unwrap_or(false)
+        })?
+        .value();
+    match max_age {
+        Some(ast::Value::IntValue(max_age)) => max_age.to_string().trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// A response, as kept in the storage.
+#[derive(Serialize, Deserialize)]
+struct CachedResponse<T> {
+    /// Expiration, in seconds since the Unix epoch.
+    expires_at: u64,
+    private: bool,
+    status: u16,
+    body: T,
+}
+
+impl<T: Serialize + DeserializeOwned> CachedResponse<T> {
+    fn new(policy: CachePolicy, response: &http_compat::Response<T>) -> Self
+    where
+        T: Clone,
+    {
+        CachedResponse {
+            expires_at: now() + policy.max_age,
+            private: policy.private,
+            status: response.status().as_u16(),
+            body: response.body().clone(),
+        }
+    }
+
+    async fn load(storage: &dyn CacheStorage, key: &str) -> Option<Self> {
+        let bytes = match storage.get(key).await {
+            Ok(bytes) => bytes?,
+            Err(err) => {
+                tracing::error!("could not read from the response cache: {}", err);
+                return None;
+            }
+        };
+        let cached: Self = serde_json::from_slice(&bytes).ok()?;
+        (cached.expires_at > now()).then(|| cached)
+    }
+
+    async fn store(&self, storage: &dyn CacheStorage, key: &str) {
+        let ttl = Duration::from_secs(self.expires_at.saturating_sub(now()));
+        let result = match serde_json::to_vec(self) {
+            Ok(bytes) => storage.put(key, bytes, Some(ttl)).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            tracing::error!("could not write to the response cache: {}", err);
+        }
+    }
+
+    /// The policy of the response, for the time it has left in the cache.
+    fn policy(&self) -> CachePolicy {
+        CachePolicy {
+            max_age: self.expires_at.saturating_sub(now()),
+            private: self.private,
+        }
+    }
+
+    fn into_response(self) -> http_compat::Response<T> {
+        http_compat::Response {
+            inner: http::Response::builder()
+                .status(self.status)
+                .body(self.body)
+                .expect("the status was taken from a response; qed"),
+        }
+    }
+}
+
+struct ResponseCache {
+    config: Config,
+    storage: Arc<dyn CacheStorage>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ResponseCache {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        if config.max_ttl < config.min_ttl {
+            return Err(BoxError::from(format!(
+                "max_ttl ({}s) must not be lower than min_ttl ({}s)",
+                config.max_ttl, config.min_ttl
+            )));
+        }
+        Ok(ResponseCache {
+            storage: config.storage.build().await?,
+            config,
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        FullResponseCacheService {
+            storage: self.config.full_responses.then(|| self.storage.clone()),
+            vary_headers: Arc::new(self.config.vary_headers.clone()),
+            inner: ServiceBuilder::new().buffered().service(service),
+        }
+        .boxed()
+    }
+
+    fn subgraph_service(
+        &mut self,
+        name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        ResponseCacheService {
+            subgraph: name.to_string(),
+            config: self.config.clone(),
+            storage: self.storage.clone(),
+            inner: ServiceBuilder::new().buffered().service(service),
+        }
+        .boxed()
+    }
+
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+        vec![Arc::new(StorageHealthCheck::new(
+            "response_cache",
+            self.storage.clone(),
+        ))]
+    }
+}
+
+/// Sets the `Cache-Control` header of client responses, and caches them if enabled.
+struct FullResponseCacheService {
+    storage: Option<Arc<dyn CacheStorage>>,
+    vary_headers: Arc<Vec<String>>,
+    inner: Buffer<BoxService<RouterRequest, RouterResponse, BoxError>, RouterRequest>,
+}
+
+/// The cache key of a client request, and whether it is specific to an authorized user.
+///
+/// Requests carrying credentials in headers the key does not vary on have no key.
+fn full_response_key(request: &RouterRequest, vary_headers: &[String]) -> Option<(String, bool)> {
+    let body = request.originating_request.body();
+    body.query.as_ref()?;
+    let headers = request.originating_request.headers();
+    let credentials = CREDENTIALS
+        .iter()
+        .filter(|name| headers.contains_key(*name))
+        .collect::<Vec<_>>();
+    if credentials.iter().any(|name| {
+        !vary_headers
+            .iter()
+            .any(|vary| vary.eq_ignore_ascii_case(name.as_str()))
+    }) {
+        return None;
+    }
+
+    let mut digest = Sha256::new();
+    digest.update(serde_json::to_vec(body).ok()?);
+    for name in vary_headers {
+        digest.update(b"\0");
+        digest.update(name.to_ascii_lowercase().as_bytes());
+        for value in headers.get_all(name.as_str()) {
+            digest.update(b":");
+            digest.update(value.as_bytes());
+        }
+    }
+    Some((
+        format!("response:{}", hex::encode(digest.finalize())),
+        !credentials.is_empty(),
+    ))
+}
+
+/// The cache key of a subgraph request.
+fn subgraph_response_key(subgraph: &str, request: &http_compat::Request<Request>) -> String {
+    let mut digest = Sha256::new();
+    digest.update(request.method().as_str().as_bytes());
+    digest.update(b"\0");
+    digest.update(request.uri().to_string().as_bytes());
+    for (name, value) in request.headers() {
+        digest.update(b"\0");
+        digest.update(name.as_str().as_bytes());
+        digest.update(b":");
+        digest.update(value.as_bytes());
+    }
+    digest.update(b"\0");
+    digest.update(serde_json::to_vec(request.body()).expect("requests serialize; qed"));
+    format!("response:{}:{}", subgraph, hex::encode(digest.finalize()))
+}
+
+impl Service<RouterRequest> for FullResponseCacheService {
+    type Response = RouterResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: RouterRequest) -> Self::Future {
+        let storage = self.storage.clone();
+        let key = storage
+            .as_ref()
+            .and_then(|_| full_response_key(&request, &self.vary_headers));
+        // The ready service goes with the request, leaving a clone to be polled for the next one.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        async move {
+            if let (Some(storage), Some((key, _))) = (&storage, &key) {
+                let cached = CachedResponse::<ResponseBody>::load(storage.as_ref(), key).await;
+                record_cache_lookups(
+                    &request.context,
+                    "full_response",
+                    cached.is_some() as u64,
+                    cached.is_none() as u64,
+                );
+                if let Some(cached) = cached {
+                    let policy = cached.policy();
+                    let mut response = RouterResponse {
+                        response: cached.into_response(),
+                        context: request.context,
+                    };
+                    response
+                        .response
+                        .headers_mut()
+                        .insert(CACHE_CONTROL, policy.header_value());
+                    return Ok(response);
+                }
+            }
+
+            let mut response = inner.call(request).await?;
+            let policy = match response
+                .context
+                .get::<_, CachePolicy>(CACHE_POLICY_CONTEXT_KEY)
+            {
+                Ok(Some(policy)) => policy,
+                // Nothing was fetched from the subgraphs.
+                _ => return Ok(response),
+            };
+            let failed = match response.response.body() {
+                ResponseBody::GraphQL(body) => !body.errors.is_empty(),
+                _ => false,
+            };
+            let policy = if failed || !response.response.status().is_success() {
+                CachePolicy::default()
+            } else {
+                policy
+            };
+            response
+                .response
+                .headers_mut()
+                .insert(CACHE_CONTROL, policy.header_value());
+
+            if let (Some(storage), Some((key, authorized))) = (storage, key) {
+                if policy.max_age > 0 && (!policy.private || authorized) {
+                    CachedResponse::new(policy, &response.response)
+                        .store(storage.as_ref(), &key)
+                        .await;
+                }
+            }
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+struct ResponseCacheService {
+    subgraph: String,
+    config: Config,
+    storage: Arc<dyn CacheStorage>,
+    inner: Buffer<BoxService<SubgraphRequest, SubgraphResponse, BoxError>, SubgraphRequest>,
+}
+
+impl Service<SubgraphRequest> for ResponseCacheService {
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        if request.operation_kind != OperationKind::Query {
+            record_policy(&request.context, CachePolicy::default());
+            return self.inner.call(request).boxed();
+        }
+
+        let key = subgraph_response_key(&self.subgraph, &request.subgraph_request);
+        let config = self.config.clone();
+        let storage = self.storage.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        async move {
+            let cached = CachedResponse::<Response>::load(storage.as_ref(), &key).await;
+            record_cache_lookups(
+                &request.context,
+                "subgraph_response",
+                cached.is_some() as u64,
+                cached.is_none() as u64,
+            );
+            if let Some(cached) = cached {
+                record_policy(&request.context, cached.policy());
+                return Ok(SubgraphResponse::new_from_response(
+                    cached.into_response(),
+                    request.context,
+                ));
+            }
+
+            let context = request.context.clone();
+            let operation_ttl = config.operation_ttl(request.originating_request.body());
+            let result = inner.call(request).await;
+            let policy = match &result {
+                Ok(response) => config.policy(operation_ttl, &response.response),
+                Err(_) => CachePolicy::default(),
+            };
+            record_policy(&context, policy);
+            if let Ok(response) = &result {
+                if !policy.private && policy.max_age > 0 {
+                    CachedResponse::new(policy, &response.response)
+                        .store(storage.as_ref(), &key)
+                        .await;
+                }
+            }
+            result
+        }
+        .boxed()
+    }
+}
+
+register_plugin!("experimental", "response_cache", ResponseCache);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::{MockRouterService, MockSubgraphService};
+    use crate::DynPlugin;
+    use http::HeaderValue;
+    use serde_json::json;
+
+    fn config() -> Config {
+        serde_yaml::from_str::<Config>(
+            r#"
+        min_ttl: 10
+        max_ttl: 60
+        "#,
+        )
+        .unwrap()
+    }
+
+    fn response_with_max_age(max_age: u64, errors: Vec<crate::Error>) -> SubgraphResponse {
+        let mut response = SubgraphResponse::fake_builder().errors(errors).build();
+        response.response.headers_mut().insert(
+            CACHE_CONTROL,
+            HeaderValue::from_str(&format!("public, max-age={}", max_age)).unwrap(),
+        );
+        response
+    }
+
+    async fn call_twice(expected_calls: usize, response: SubgraphResponse) {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(expected_calls)
+            .returning(move |_| Ok(response.clone()));
+
+        let mut dyn_plugin: Box<dyn DynPlugin> = crate::plugins()
+            .get("experimental.response_cache")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "min_ttl": 10, "max_ttl": 60 }))
+            .await
+            .unwrap();
+        let mut service = dyn_plugin.subgraph_service("products", BoxService::new(mock.build()));
+
+        for _ in 0..2 {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(SubgraphRequest::fake_builder().build())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn ttl_above_the_cap_is_clamped() {
+        let response = response_with_max_age(3600, Vec::new());
+        assert_eq!(
+            config().ttl(None, &response.response),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn ttl_below_the_floor_is_raised() {
+        let response = response_with_max_age(1, Vec::new());
+        assert_eq!(
+            config().ttl(None, &response.response),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn uncacheable_directives_are_honored() {
+        let mut response = response_with_max_age(30, Vec::new());
+        response.response.headers_mut().insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("max-age=30, no-store"),
+        );
+        assert_eq!(config().ttl(None, &response.response), None);
+    }
+
+    #[test]
+    fn operation_ttl_overrides_the_subgraph_hint() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        min_ttl: 10
+        max_ttl: 60
+        default_ttl: 30
+        operations:
+          TopProducts: 15
+        "#,
+        )
+        .unwrap();
+        let response = response_with_max_age(45, Vec::new());
+
+        let request = Request::builder()
+            .query(Some(
+                "query TopProducts { topProducts { upc } }".to_string(),
+            ))
+            .operation_name(Some("TopProducts".to_string()))
+            .build();
+        let operation_ttl = config.operation_ttl(&request);
+        assert_eq!(operation_ttl, Some(15));
+        assert_eq!(
+            config.ttl(operation_ttl, &response.response),
+            Some(Duration::from_secs(15))
+        );
+
+        let request = Request::builder()
+            .query(Some(
+                "query Me @cacheControl(maxAge: 20) { me { id } }".to_string(),
+            ))
+            .build();
+        let operation_ttl = config.operation_ttl(&request);
+        assert_eq!(operation_ttl, Some(20));
+        assert_eq!(
+            config.ttl(operation_ttl, &response.response),
+            Some(Duration::from_secs(20))
+        );
+
+        // Without an override, the subgraph hint is used, then the default.
+        assert_eq!(
+            config.ttl(None, &response.response),
+            Some(Duration::from_secs(45))
+        );
+        let mut response = response;
+        response.response.headers_mut().remove(CACHE_CONTROL);
+        assert_eq!(
+            config.ttl(None, &response.response),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[tokio::test]
+    async fn cacheable_response_is_served_from_cache() {
+        call_twice(1, response_with_max_age(30, Vec::new())).await;
+    }
+
+    #[tokio::test]
+    async fn error_response_is_not_cached() {
+        let response = response_with_max_age(
+            30,
+            vec![crate::Error {
+                message: "subgraph failed".to_string(),
+                ..Default::default()
+            }],
+        );
+        assert_eq!(config().ttl(None, &response.response), None);
+
+        call_twice(2, response).await;
+    }
+
+    #[test]
+    fn cache_hints_lower_the_ttl_and_scope_it() {
+        let mut response = response_with_max_age(45, Vec::new());
+        response.response.body_mut().extensions.insert(
+            "cacheControl",
+            serde_json_bytes::json!({
+                "version": 1,
+                "hints": [
+                    { "path": ["me"], "maxAge": 20 },
+                    { "path": ["me", "email"], "maxAge": 30, "scope": "PRIVATE" }
+                ]
+            }),
+        );
+
+        assert_eq!(
+            config().policy(None, &response.response),
+            CachePolicy {
+                max_age: 20,
+                private: true
+            }
+        );
+        assert_eq!(config().ttl(None, &response.response), None);
+    }
+
+    #[tokio::test]
+    async fn client_responses_with_credentials_left_out_of_the_key_are_not_cached() {
+        let mut mock = MockRouterService::new();
+        mock.expect_call()
+            .times(3)
+            .returning(|request: RouterRequest| {
+                record_policy(
+                    &request.context,
+                    CachePolicy {
+                        max_age: 30,
+                        private: true,
+                    },
+                );
+                Ok(RouterResponse::fake_builder()
+                    .context(request.context)
+                    .build()
+                    .unwrap())
+            });
+
+        let mut dyn_plugin: Box<dyn DynPlugin> = crate::plugins()
+            .get("experimental.response_cache")
+            .expect("Plugin not found")
+            .create_instance(&json!({
+                "max_ttl": 60,
+                "full_responses": true,
+                "vary_headers": ["authorization"],
+            }))
+            .await
+            .unwrap();
+        let mut service = dyn_plugin.router_service(BoxService::new(mock.build()));
+
+        // The cookie is not part of the key: both requests reach the subgraphs.
+        // The same authorization header is: the second request is served from the cache.
+        for (header, value) in [
+            ("cookie", "session=a"),
+            ("cookie", "session=a"),
+            ("authorization", "Bearer a"),
+            ("authorization", "Bearer a"),
+        ] {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(
+                    RouterRequest::fake_builder()
+                        .query("{ me { name } }".to_string())
+                        .header(header, value)
+                        .build()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn client_responses_carry_the_combined_policy() {
+        let mut mock = MockRouterService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|request: RouterRequest| {
+                record_policy(
+                    &request.context,
+                    CachePolicy {
+                        max_age: 30,
+                        private: false,
+                    },
+                );
+                record_policy(
+                    &request.context,
+                    CachePolicy {
+                        max_age: 20,
+                        private: false,
+                    },
+                );
+                Ok(RouterResponse::fake_builder()
+                    .context(request.context)
+                    .build()
+                    .unwrap())
+            });
+
+        let mut dyn_plugin: Box<dyn DynPlugin> = crate::plugins()
+            .get("experimental.response_cache")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "max_ttl": 60, "full_responses": true }))
+            .await
+            .unwrap();
+        let mut service = dyn_plugin.router_service(BoxService::new(mock.build()));
+
+        for _ in 0..2 {
+            let response = service
+                .ready()
+                .await
+                .unwrap()
+                .call(
+                    RouterRequest::fake_builder()
+                        .query("{ topProducts { upc } }".to_string())
+                        .build()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let cache_control = response.response.headers().get(CACHE_CONTROL).unwrap();
+            assert!(
+                cache_control.to_str().unwrap().starts_with("max-age=")
+                    && cache_control.to_str().unwrap().ends_with(", public")
+            );
+        }
+    }
+}
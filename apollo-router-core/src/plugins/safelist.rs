@@ -0,0 +1,361 @@
+//! Only lets through the operations listed in a persisted query manifest.
+//!
+//! The manifest is a JSON object mapping operation ids to their documents. A request sending the
+//! id of its operation as a persisted query hash must send the document listed under that id,
+//! other requests any document of the manifest. Documents are compared with the whitespace
+//! collapsed, so that clients are free to format them.
+//!
+//! The manifest is read again periodically, so that operations can be added without a reload.
+
+use crate::apq::PersistedQuery;
+use crate::plugin::Plugin;
+use crate::{register_plugin, Object, RouterRequest, RouterResponse, ServiceBuilderExt};
+use apollo_parser::ast;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Path to the manifest, a JSON object mapping operation ids to their documents.
+    manifest: PathBuf,
+    /// Message of the error returned for operations missing from the manifest.
+    #[serde(default = "default_error_message")]
+    error_message: String,
+    /// Whether introspection queries are let through even when missing from the manifest.
+    #[serde(default)]
+    allow_introspection: bool,
+    /// How often the manifest is read again. Defaults to 10s.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    reload_interval: Option<Duration>,
+}
+
+const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+fn default_error_message() -> String {
+    String::from("operation is not in the safelist")
+}
+
+/// The operations of a manifest, by id and by normalized document.
+#[derive(Default)]
+struct Manifest {
+    by_id: HashMap<String, String>,
+    documents: HashSet<String>,
+}
+
+impl Manifest {
+    async fn read(path: &Path) -> Result<Self, BoxError> {
+        let manifest = tokio::fs::read_to_string(path).await.map_err(|err| {
+            format!(
+                "could not read the safelist manifest {}: {}",
+                path.display(),
+                err
+            )
+        })?;
+        let by_id: HashMap<String, String> =
+            serde_json::from_str::<HashMap<String, String>>(&manifest)?
+                .into_iter()
+                .map(|(id, query)| (id, normalize(&query)))
+                .collect();
+        tracing::debug!("safelisting {} operations", by_id.len());
+
+        Ok(Manifest {
+            documents: by_id.values().cloned().collect(),
+            by_id,
+        })
+    }
+
+    /// Whether the manifest lists `query`, under `id` when the request sent one.
+    fn allows(&self, id: Option<&str>, query: &str) -> bool {
+        let query = normalize(query);
+        match id {
+            Some(id) => self.by_id.get(id) == Some(&query),
+            None => self.documents.contains(&query),
+        }
+    }
+}
+
+struct Safelist {
+    config: Config,
+    manifest: Arc<RwLock<Manifest>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for Safelist {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        let manifest = Arc::new(RwLock::new(Manifest::read(&config.manifest).await?));
+
+        // The task only holds a weak reference, and stops once the plugin is dropped on reload.
+        let reloaded = Arc::downgrade(&manifest);
+        let path = config.manifest.clone();
+        let interval = config.reload_interval.unwrap_or(DEFAULT_RELOAD_INTERVAL);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let read = Manifest::read(&path).await;
+                let manifest = match reloaded.upgrade() {
+                    Some(manifest) => manifest,
+                    None => break,
+                };
+                match read {
+                    Ok(read) => *manifest.write().expect("lock poisoned") = read,
+                    // The operations read before are kept until the manifest can be read again.
+                    Err(err) => tracing::error!("could not reload the safelist: {}", err),
+                }
+            }
+        });
+
+        Ok(Safelist { config, manifest })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let manifest = self.manifest.clone();
+        let error_message = self.config.error_message.clone();
+        let allow_introspection = self.config.allow_introspection;
+
+        ServiceBuilder::new()
+            .checkpoint(move |req: RouterRequest| {
+                let body = req.originating_request.body();
+                let id = body
+                    .extensions
+                    .get("persistedQuery")
+                    .and_then(|value| {
+                        serde_json_bytes::from_value::<PersistedQuery>(value.clone()).ok()
+                    })
+                    .map(|persisted_query| persisted_query.sha256hash);
+                let allowed = match body.query.as_deref() {
+                    Some(query) => {
+                        manifest
+                            .read()
+                            .expect("lock poisoned")
+                            .allows(id.as_deref(), query)
+                            || (allow_introspection && is_introspection(query))
+                    }
+                    None => false,
+                };
+                if allowed {
+                    return Ok(ControlFlow::Continue(req));
+                }
+
+                let mut extensions = Object::default();
+                extensions.insert("code", "OPERATION_NOT_IN_SAFELIST".into());
+                let res = RouterResponse::builder()
+                    .errors(vec![crate::Error {
+                        message: error_message.clone(),
+                        extensions,
+                        ..Default::default()
+                    }])
+                    .status_code(StatusCode::FORBIDDEN)
+                    .context(req.context)
+                    .build()?;
+                Ok(ControlFlow::Break(res))
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether every operation of the document only selects introspection fields.
+fn is_introspection(query: &str) -> bool {
+    let tree = apollo_parser::Parser::new(query).parse();
+    if tree.errors().next().is_some() {
+        return false;
+    }
+
+    let mut operations = tree
+        .document()
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        })
+        .peekable();
+    operations.peek().is_some()
+        && operations.all(|operation| {
+            operation
+                .selection_set()
+                .map(|selection_set| {
+                    selection_set.selections().all(|selection| match selection {
+                        ast::Selection::Field(field) => field
+                            .name()
+                            .map(|name| name.text().starts_with("__"))
+                            .unwrap_or(false),
+                        _ => false,
+                    })
+                })
+                .unwrap_or(false)
+        })
+}
+
+register_plugin!("experimental", "safelist", Safelist);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockRouterService;
+    use crate::DynPlugin;
+    use serde_json::json;
+
+    async fn plugin(config: serde_json::Value) -> Box<dyn DynPlugin> {
+        crate::plugins()
+            .get("experimental.safelist")
+            .expect("Plugin not found")
+            .create_instance(&config)
+            .await
+            .unwrap()
+    }
+
+    fn manifest(test: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("safelist-{}-{}.json", test, std::process::id()));
+        std::fs::write(&path, r#"{ "me": "query Me { me { name } }" }"#).unwrap();
+        path
+    }
+
+    async fn call(plugin: &mut Box<dyn DynPlugin>, request: RouterRequest) -> StatusCode {
+        let mut mock = MockRouterService::new();
+        mock.expect_call()
+            .returning(|_| Ok(RouterResponse::fake_builder().build().unwrap()));
+
+        plugin
+            .router_service(BoxService::new(mock.build()))
+            .oneshot(request)
+            .await
+            .unwrap()
+            .response
+            .status()
+    }
+
+    async fn status(plugin: &mut Box<dyn DynPlugin>, query: &str) -> StatusCode {
+        call(
+            plugin,
+            RouterRequest::fake_builder()
+                .query(query.to_string())
+                .build()
+                .unwrap(),
+        )
+        .await
+    }
+
+    async fn status_with_id(plugin: &mut Box<dyn DynPlugin>, id: &str, query: &str) -> StatusCode {
+        call(
+            plugin,
+            RouterRequest::fake_builder()
+                .query(query.to_string())
+                .extension(
+                    "persistedQuery",
+                    serde_json_bytes::json!({ "version": 1, "sha256Hash": id }),
+                )
+                .build()
+                .unwrap(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn only_listed_operations_are_executed() {
+        let manifest = manifest("listed");
+        let mut plugin = plugin(json!({ "manifest": manifest })).await;
+
+        assert_eq!(
+            status(&mut plugin, "query Me {\n  me {\n    name\n  }\n}").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&mut plugin, "query Me { me { name id } }").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&mut plugin, "{ __schema { queryType { name } } }").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn operations_are_looked_up_by_id() {
+        let manifest = manifest("by-id");
+        let mut plugin = plugin(json!({ "manifest": manifest })).await;
+
+        assert_eq!(
+            status_with_id(&mut plugin, "me", "query Me { me { name } }").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status_with_id(&mut plugin, "me", "query Me { me { name id } }").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status_with_id(&mut plugin, "you", "query Me { me { name } }").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn manifest_changes_are_reloaded() {
+        let manifest = manifest("reload");
+        let mut plugin = plugin(json!({ "manifest": manifest, "reload_interval": "10ms" })).await;
+        assert_eq!(
+            status(&mut plugin, "query You { you { name } }").await,
+            StatusCode::FORBIDDEN
+        );
+
+        std::fs::write(&manifest, r#"{ "you": "query You { you { name } }" }"#).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            status(&mut plugin, "query You { you { name } }").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&mut plugin, "query Me { me { name } }").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn introspection_can_bypass_the_safelist() {
+        let manifest = manifest("introspection");
+        let mut plugin = plugin(json!({
+            "manifest": manifest,
+            "allow_introspection": true
+        }))
+        .await;
+
+        assert_eq!(
+            status(&mut plugin, "{ __schema { queryType { name } } }").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&mut plugin, "{ __typename me { name } }").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_manifests_are_reported() {
+        assert!(crate::plugins()
+            .get("experimental.safelist")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "manifest": "does/not/exist.json" }))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! Checks the responses of the subgraphs against the operations they were sent, logging or
+//! rejecting those that do not match the schema.
+
+use crate::{
+    register_plugin, ExecutionRequest, ExecutionResponse, Plugin, SubgraphResponseValidation,
+    SUBGRAPH_RESPONSE_VALIDATION_CONTEXT_KEY,
+};
+use tower::util::BoxService;
+use tower::{BoxError, ServiceExt};
+
+#[derive(Debug)]
+struct SubgraphResponseValidationPlugin {
+    config: SubgraphResponseValidation,
+}
+
+#[async_trait::async_trait]
+impl Plugin for SubgraphResponseValidationPlugin {
+    type Config = SubgraphResponseValidation;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(SubgraphResponseValidationPlugin { config })
+    }
+
+    fn execution_service(
+        &mut self,
+        service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+    ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        let config = self.config.clone();
+        service
+            .map_request(move |request: ExecutionRequest| {
+                if let Err(err) = request
+                    .context
+                    .insert(SUBGRAPH_RESPONSE_VALIDATION_CONTEXT_KEY, config.clone())
+                {
+                    tracing::debug!("could not enable subgraph response validation: {}", err);
+                }
+                request
+            })
+            .boxed()
+    }
+}
+
+register_plugin!(
+    "experimental",
+    "subgraph_response_validation",
+    SubgraphResponseValidationPlugin
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::utils::test::MockExecutionService;
+    use crate::ValidationMode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn settings_are_given_to_the_execution() {
+        let mut mock = MockExecutionService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|request: ExecutionRequest| {
+                let settings = request
+                    .context
+                    .get::<_, SubgraphResponseValidation>(SUBGRAPH_RESPONSE_VALIDATION_CONTEXT_KEY)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(settings.mode, ValidationMode::Reject);
+                assert_eq!(settings.skip, ["legacy"]);
+                Ok(ExecutionResponse::fake_builder().build())
+            });
+
+        let mut plugin = crate::plugins()
+            .get("experimental.subgraph_response_validation")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "mode": "reject", "skip": ["legacy"] }))
+            .await
+            .unwrap();
+        plugin
+            .execution_service(BoxService::new(mock.build()))
+            .oneshot(ExecutionRequest::fake_builder().build())
+            .await
+            .unwrap();
+    }
+}
@@ -1,17 +1,120 @@
 use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::time::Duration;
 
+use http::StatusCode;
 use schemars::JsonSchema;
 use serde::Deserialize;
+use tower::limit::ConcurrencyLimitLayer;
+use tower::retry::RetryLayer;
 use tower::util::BoxService;
 use tower::{BoxError, ServiceBuilder, ServiceExt};
 
+use crate::circuit_breaker::CircuitBreakerLayer;
 use crate::deduplication::QueryDeduplicationLayer;
+use crate::hedging::HedgingLayer;
 use crate::plugin::Plugin;
-use crate::{register_plugin, ServiceBuilderExt, SubgraphRequest, SubgraphResponse};
+use crate::rate_limit::TokenBucket;
+use crate::retry::RetryPolicy;
+use crate::timeout::TimeoutLayer;
+use crate::{
+    register_plugin, Object, RouterRequest, RouterResponse, ServiceBuilderExt, SubgraphRequest,
+    SubgraphResponse, CLIENT_NAME_CONTEXT_KEY,
+};
 
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 struct Shaping {
     dedup: Option<bool>,
+    /// Timeout for requests to the subgraph.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    timeout: Option<Duration>,
+    /// Fraction of the timeout by which each request's timeout may be shortened or extended, so
+    /// that requests to a stalled subgraph do not all time out at once.
+    timeout_jitter: Option<f64>,
+    /// Number of times a failed query is retried. Mutations are never retried.
+    retries: Option<usize>,
+    /// Delay before the first retry, doubled on each following one. Defaults to 100ms.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    retry_backoff: Option<Duration>,
+    /// Stops sending requests to the subgraph for a while after consecutive failures.
+    circuit_breaker: Option<CircuitBreaker>,
+    /// Requests to the subgraph over this rate fail right away.
+    rate_limit: Option<RateLimit>,
+    /// Most requests in flight to the subgraph, others waiting for their turn.
+    concurrency_limit: Option<usize>,
+    /// Queries slower than most are sent a second time, and the first response is used.
+    hedging: Option<Hedging>,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct RouterShaping {
+    /// Client requests over this rate are rejected with a 429 status.
+    rate_limit: Option<RateLimit>,
+    /// Rate limits of specific clients, by client name, in place of `rate_limit`. Each client
+    /// has its own limit.
+    #[serde(default)]
+    clients: HashMap<String, RateLimit>,
+    /// Most client requests processed at once, others waiting for their turn.
+    concurrency_limit: Option<usize>,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct RateLimit {
+    /// Number of requests allowed per interval, which is also the largest burst let through.
+    capacity: u64,
+    /// Interval over which the capacity is refilled.
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    interval: Duration,
+}
+
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct CircuitBreaker {
+    /// Number of failures in a row opening the circuit. Defaults to 5.
+    #[serde(default = "default_failure_threshold")]
+    failure_threshold: usize,
+    /// How long requests fail right away once the circuit is open, before a probe is let through.
+    /// Defaults to 10s.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    open_duration: Option<Duration>,
+    /// Requests taking longer than this are counted as failures.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    slow_call_duration: Option<Duration>,
+}
+
+const DEFAULT_OPEN_DURATION: Duration = Duration::from_secs(10);
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Hedging {
+    /// Percentile of the latencies of the subgraph after which a query is sent again, between
+    /// 0 and 100. Defaults to 95.
+    #[serde(default = "default_hedging_percentile")]
+    percentile: f64,
+    /// Most extra requests sent, as a fraction of the requests to the subgraph. Defaults to 0.1.
+    #[serde(default = "default_hedging_budget")]
+    budget: f64,
+}
+
+fn default_hedging_percentile() -> f64 {
+    95.0
+}
+
+fn default_hedging_budget() -> f64 {
+    0.1
+}
+
+fn default_failure_threshold() -> usize {
+    5
 }
 
 impl Shaping {
@@ -20,6 +123,20 @@ impl Shaping {
             None => self.clone(),
             Some(fallback) => Shaping {
                 dedup: self.dedup.or(fallback.dedup),
+                timeout: self.timeout.or(fallback.timeout),
+                timeout_jitter: self.timeout_jitter.or(fallback.timeout_jitter),
+                retries: self.retries.or(fallback.retries),
+                retry_backoff: self.retry_backoff.or(fallback.retry_backoff),
+                circuit_breaker: self
+                    .circuit_breaker
+                    .clone()
+                    .or_else(|| fallback.circuit_breaker.clone()),
+                rate_limit: self
+                    .rate_limit
+                    .clone()
+                    .or_else(|| fallback.rate_limit.clone()),
+                concurrency_limit: self.concurrency_limit.or(fallback.concurrency_limit),
+                hedging: self.hedging.clone().or_else(|| fallback.hedging.clone()),
             },
         }
     }
@@ -27,6 +144,11 @@ impl Shaping {
 
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 struct Config {
+    /// Limits applied to client requests.
+    #[serde(default)]
+    router: Option<RouterShaping>,
+    /// Applied to each subgraph, unless overridden in `subgraphs`. Limits are counted separately
+    /// for each subgraph.
     #[serde(default)]
     all: Option<Shaping>,
     #[serde(default)]
@@ -45,6 +167,64 @@ impl Plugin for TrafficShaping {
         Ok(Self { config })
     }
 
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let config = match &self.config.router {
+            Some(config) => config,
+            None => return service,
+        };
+
+        let bucket = config
+            .rate_limit
+            .as_ref()
+            .map(|rate_limit| TokenBucket::new(rate_limit.capacity, rate_limit.interval));
+        let client_buckets: HashMap<String, TokenBucket> = config
+            .clients
+            .iter()
+            .map(|(client, rate_limit)| {
+                (
+                    client.clone(),
+                    TokenBucket::new(rate_limit.capacity, rate_limit.interval),
+                )
+            })
+            .collect();
+        let rate_limited = bucket.is_some() || !client_buckets.is_empty();
+
+        ServiceBuilder::new()
+            .option_layer(rate_limited.then(|| {
+                ServiceBuilder::new().checkpoint(move |req: RouterRequest| {
+                    let client = req
+                        .context
+                        .get::<_, String>(CLIENT_NAME_CONTEXT_KEY)
+                        .ok()
+                        .flatten();
+                    let bucket = client
+                        .and_then(|client| client_buckets.get(&client))
+                        .or(bucket.as_ref());
+                    if let Some(Err(err)) = bucket.map(TokenBucket::try_acquire) {
+                        let mut extensions = Object::default();
+                        extensions.insert("code", "RATE_LIMITED".into());
+                        let res = RouterResponse::builder()
+                            .errors(vec![crate::Error {
+                                message: err.to_string(),
+                                extensions,
+                                ..Default::default()
+                            }])
+                            .status_code(StatusCode::TOO_MANY_REQUESTS)
+                            .context(req.context)
+                            .build()?;
+                        return Ok(ControlFlow::Break(res));
+                    }
+                    Ok(ControlFlow::Continue(req))
+                })
+            }))
+            .option_layer(config.concurrency_limit.map(ConcurrencyLimitLayer::new))
+            .service(service)
+            .boxed()
+    }
+
     fn subgraph_service(
         &mut self,
         name: &str,
@@ -57,6 +237,45 @@ impl Plugin for TrafficShaping {
 
         if let Some(config) = final_config {
             ServiceBuilder::new()
+                .option_layer(config.rate_limit.as_ref().map(|rate_limit| {
+                    let bucket = TokenBucket::new(rate_limit.capacity, rate_limit.interval);
+                    ServiceBuilder::new().checkpoint(move |req: SubgraphRequest| {
+                        bucket.try_acquire()?;
+                        Ok(ControlFlow::Continue(req))
+                    })
+                }))
+                .option_layer(config.concurrency_limit.map(ConcurrencyLimitLayer::new))
+                .option_layer(config.circuit_breaker.as_ref().map(|breaker| {
+                    CircuitBreakerLayer::new(
+                        name,
+                        breaker.failure_threshold,
+                        breaker.open_duration.unwrap_or(DEFAULT_OPEN_DURATION),
+                        breaker.slow_call_duration,
+                    )
+                }))
+                .option_layer(
+                    config
+                        .retries
+                        .filter(|retries| *retries > 0)
+                        .map(|retries| {
+                            //Buffer is required because the retry layer requires a clone service.
+                            ServiceBuilder::new()
+                                .layer(RetryLayer::new(RetryPolicy::new(
+                                    retries,
+                                    config.retry_backoff.unwrap_or(DEFAULT_RETRY_BACKOFF),
+                                )))
+                                .buffered()
+                        }),
+                )
+                .option_layer(config.hedging.as_ref().map(|hedging| {
+                    //Buffer is required because the hedging layer requires a clone service.
+                    ServiceBuilder::new()
+                        .layer(HedgingLayer::new(hedging.percentile, hedging.budget))
+                        .buffered()
+                }))
+                .option_layer(config.timeout.map(|timeout| {
+                    TimeoutLayer::new(timeout, config.timeout_jitter.unwrap_or_default())
+                }))
                 .option_layer(config.dedup.unwrap_or_default().then(|| {
                     //Buffer is required because dedup layer requires a clone service.
                     ServiceBuilder::new()
@@ -86,6 +305,9 @@ register_plugin!("experimental", "traffic_shaping", TrafficShaping);
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::plugin::utils::test::MockRouterService;
+    use crate::ResponseBody;
+    use tower::Service;
 
     #[test]
     fn test_merge_config() {
@@ -116,4 +338,190 @@ mod test {
             config.subgraphs.get("products")
         );
     }
+
+    #[test]
+    fn test_merge_timeout_config() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+          timeout: 1s
+          timeout_jitter: 0.1
+        subgraphs:
+          products:
+            timeout: 500ms
+        "#,
+        )
+        .unwrap();
+
+        let merged =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("products"))
+                .unwrap();
+        assert_eq!(merged.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(merged.timeout_jitter, Some(0.1));
+    }
+
+    #[test]
+    fn test_merge_retry_config() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+          retries: 2
+          retry_backoff: 50ms
+        subgraphs:
+          products:
+            retries: 0
+        "#,
+        )
+        .unwrap();
+
+        let merged =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("products"))
+                .unwrap();
+        assert_eq!(merged.retries, Some(0));
+        assert_eq!(merged.retry_backoff, Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn test_merge_circuit_breaker_config() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+          circuit_breaker:
+            open_duration: 30s
+        subgraphs:
+          products:
+            dedup: true
+          reviews:
+            circuit_breaker:
+              failure_threshold: 2
+        "#,
+        )
+        .unwrap();
+
+        let products =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("products"))
+                .unwrap()
+                .circuit_breaker
+                .unwrap();
+        assert_eq!(products.failure_threshold, 5);
+        assert_eq!(products.open_duration, Some(Duration::from_secs(30)));
+
+        let reviews =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("reviews"))
+                .unwrap()
+                .circuit_breaker
+                .unwrap();
+        assert_eq!(reviews.failure_threshold, 2);
+        assert_eq!(reviews.open_duration, None);
+    }
+
+    #[test]
+    fn test_merge_hedging_config() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+          hedging:
+            percentile: 99
+        subgraphs:
+          products:
+            dedup: true
+        "#,
+        )
+        .unwrap();
+
+        let products =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("products"))
+                .unwrap()
+                .hedging
+                .unwrap();
+        assert_eq!(products.percentile, 99.0);
+        assert_eq!(products.budget, 0.1);
+    }
+
+    #[tokio::test]
+    async fn router_requests_over_the_rate_limit_are_rejected() {
+        let mut mock = MockRouterService::new();
+        mock.expect_call()
+            .times(2)
+            .returning(|_| Ok(RouterResponse::fake_builder().build().unwrap()));
+        let mut plugin = crate::plugins()
+            .get("experimental.traffic_shaping")
+            .expect("Plugin not found")
+            .create_instance(&serde_json::json!({
+                "router": { "rate_limit": { "capacity": 2, "interval": "1h" } }
+            }))
+            .await
+            .unwrap();
+        let mut service = plugin.router_service(BoxService::new(mock.build()));
+
+        let mut statuses = Vec::new();
+        let mut codes = Vec::new();
+        for _ in 0..3 {
+            let response = service
+                .ready()
+                .await
+                .unwrap()
+                .call(RouterRequest::fake_builder().build().unwrap())
+                .await
+                .unwrap();
+            statuses.push(response.response.status());
+            if let ResponseBody::GraphQL(body) = response.response.body() {
+                codes.extend(
+                    body.errors
+                        .iter()
+                        .map(|error| error.extensions.get("code").cloned()),
+                );
+            }
+        }
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::TOO_MANY_REQUESTS
+            ]
+        );
+        assert_eq!(codes, [Some("RATE_LIMITED".into())]);
+    }
+
+    #[tokio::test]
+    async fn clients_have_their_own_rate_limit() {
+        let mut mock = MockRouterService::new();
+        mock.expect_call()
+            .times(3)
+            .returning(|_| Ok(RouterResponse::fake_builder().build().unwrap()));
+        let mut plugin = crate::plugins()
+            .get("experimental.traffic_shaping")
+            .expect("Plugin not found")
+            .create_instance(&serde_json::json!({
+                "router": {
+                    "rate_limit": { "capacity": 1, "interval": "1h" },
+                    "clients": { "ios": { "capacity": 2, "interval": "1h" } }
+                }
+            }))
+            .await
+            .unwrap();
+        let mut service = plugin.router_service(BoxService::new(mock.build()));
+
+        let mut statuses = Vec::new();
+        for client in ["ios", "ios", "ios", "web", "web"] {
+            let request = RouterRequest::fake_builder().build().unwrap();
+            request
+                .context
+                .insert(CLIENT_NAME_CONTEXT_KEY, client.to_string())
+                .unwrap();
+            let response = service.ready().await.unwrap().call(request).await.unwrap();
+            statuses.push(response.response.status());
+        }
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::OK,
+                StatusCode::TOO_MANY_REQUESTS
+            ]
+        );
+    }
 }
@@ -2,6 +2,7 @@ use crate::prelude::graphql::*;
 use crate::CacheResolver;
 use async_trait::async_trait;
 use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::task;
@@ -10,29 +11,113 @@ type PlanResult = Result<Arc<QueryPlan>, QueryPlannerError>;
 
 /// A query planner wrapper that caches results.
 ///
-/// The query planner performs LRU caching.
+/// The query planner performs LRU caching. Plans missing from it are looked up in the
+/// [`CacheStorage`], if any, before being computed, so that router instances sharing a storage
+/// only plan each query once.
 #[derive(Debug)]
 pub struct CachingQueryPlanner<T: QueryPlanner> {
     cm: Arc<CachingMap<QueryKey, Arc<QueryPlan>>>,
+    journal: Option<PlanJournal>,
     phantom: PhantomData<T>,
 }
 
 /// A resolver for cache misses
 struct CachingQueryPlannerResolver<T: QueryPlanner> {
     delegate: T,
+    storage: Option<PlanStorage>,
+}
+
+/// Storage of the plans, with the hash of the schema they were planned against.
+struct PlanStorage {
+    storage: Arc<dyn CacheStorage>,
+    schema_hash: String,
+}
+
+impl PlanStorage {
+    fn key(&self, key: &QueryKey) -> String {
+        let mut digest = Sha256::new();
+        digest.update(key.0.as_bytes());
+        digest.update(b"\0");
+        digest.update(key.1.as_deref().unwrap_or_default().as_bytes());
+        format!(
+            "plan:{}:{}",
+            self.schema_hash,
+            hex::encode(digest.finalize())
+        )
+    }
+
+    async fn get(&self, key: &str) -> Option<QueryPlan> {
+        match self.storage.get(key).await {
+            Ok(bytes) => serde_json::from_slice(&bytes?).ok(),
+            Err(err) => {
+                tracing::error!("could not read the query plan storage: {}", err);
+                None
+            }
+        }
+    }
+
+    async fn put(&self, key: &str, plan: &QueryPlan) {
+        let result = match serde_json::to_vec(plan) {
+            Ok(bytes) => self.storage.put(key, bytes, None).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            tracing::error!("could not write to the query plan storage: {}", err);
+        }
+    }
 }
 
 impl<T: QueryPlanner + 'static> CachingQueryPlanner<T> {
     /// Creates a new query planner that caches the results of another [`QueryPlanner`].
     pub fn new(delegate: T, plan_cache_limit: usize) -> CachingQueryPlanner<T> {
-        let resolver = CachingQueryPlannerResolver { delegate };
+        Self::with_resolver(
+            CachingQueryPlannerResolver {
+                delegate,
+                storage: None,
+            },
+            plan_cache_limit,
+        )
+    }
+
+    /// Creates a new query planner that caches the results of another [`QueryPlanner`], in
+    /// memory and in `storage`.
+    pub fn with_storage(
+        delegate: T,
+        plan_cache_limit: usize,
+        storage: Arc<dyn CacheStorage>,
+        schema: &Schema,
+    ) -> CachingQueryPlanner<T> {
+        let schema_hash = schema.hash();
+        Self::with_resolver(
+            CachingQueryPlannerResolver {
+                delegate,
+                storage: Some(PlanStorage {
+                    storage,
+                    schema_hash,
+                }),
+            },
+            plan_cache_limit,
+        )
+    }
+
+    fn with_resolver(
+        resolver: CachingQueryPlannerResolver<T>,
+        plan_cache_limit: usize,
+    ) -> CachingQueryPlanner<T> {
         let cm = Arc::new(CachingMap::new(Box::new(resolver), plan_cache_limit));
         Self {
             cm,
+            journal: None,
             phantom: PhantomData,
         }
     }
 
+    /// Records the operations planned for clients in `journal`.
+    pub fn with_journal(mut self, journal: PlanJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
     pub async fn get_hot_keys(&self) -> Vec<QueryKey> {
         self.cm.get_hot_keys().await
     }
@@ -41,10 +126,24 @@ impl<T: QueryPlanner + 'static> CachingQueryPlanner<T> {
 #[async_trait]
 impl<T: QueryPlanner> CacheResolver<QueryKey, Arc<QueryPlan>> for CachingQueryPlannerResolver<T> {
     async fn retrieve(&self, key: QueryKey) -> Result<Arc<QueryPlan>, CacheResolverError> {
-        self.delegate
-            .get(key.0, key.1, key.2)
-            .await
-            .map_err(|err| err.into())
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => {
+                return self
+                    .delegate
+                    .get(key.0, key.1, key.2)
+                    .await
+                    .map_err(|err| err.into())
+            }
+        };
+
+        let storage_key = storage.key(&key);
+        if let Some(plan) = storage.get(&storage_key).await {
+            return Ok(Arc::new(plan));
+        }
+        let plan = self.delegate.get(key.0, key.1, key.2).await?;
+        storage.put(&storage_key, &plan).await;
+        Ok(plan)
     }
 }
 
@@ -89,11 +188,16 @@ where
             QueryPlanOptions::default(),
         );
         let cm = self.cm.clone();
+        let journal = self.journal.clone();
         Box::pin(async move {
-            cm.get(key)
-                .await
-                .map_err(|err| err.into())
-                .map(|query_plan| QueryPlannerResponse::new(query_plan, request.context))
+            let hit = cm.contains(&key).await;
+            record_cache_lookups(&request.context, "query_plan", hit as u64, !hit as u64);
+            let entry = journal.map(|journal| (journal, key.0.clone(), key.1.clone()));
+            let query_plan = cm.get(key).await.map_err(tower::BoxError::from)?;
+            if let Some((journal, query, operation_name)) = entry {
+                journal.record(&query, operation_name.as_deref());
+            }
+            Ok(QueryPlannerResponse::new(query_plan, request.context))
         })
     }
 }
@@ -157,4 +261,32 @@ mod tests {
             .await
             .is_err());
     }
+
+    #[test(tokio::test)]
+    async fn plans_are_shared_through_the_storage() {
+        let schema: Schema = include_str!("../testdata/contract_schema.graphql")
+            .parse()
+            .unwrap();
+        let storage: Arc<dyn CacheStorage> = Arc::new(InMemoryStorage::new(10));
+
+        let mut delegate = MockMyQueryPlanner::new();
+        delegate
+            .expect_sync_get()
+            .times(1)
+            .returning(|_, _, _| Ok(Arc::new(QueryPlan::default())));
+        let planner = CachingQueryPlanner::with_storage(delegate, 10, storage.clone(), &schema);
+        planner
+            .get("query1".into(), None, QueryPlanOptions::default())
+            .await
+            .unwrap();
+
+        let mut delegate = MockMyQueryPlanner::new();
+        delegate.expect_sync_get().times(0);
+        let planner = CachingQueryPlanner::with_storage(delegate, 10, storage, &schema);
+        let plan = planner
+            .get("query1".into(), None, QueryPlanOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(plan.root, QueryPlan::default().root);
+    }
 }
@@ -0,0 +1,393 @@
+//! Merging of the entity fetches that run in parallel against the same subgraph, so that they are
+//! sent in a single request.
+//!
+//! The `_entities` field of each fetch is aliased to `_entities_<index>` in the merged operation,
+//! with its representations in the `$representations_<index>` variable, and the response is split
+//! back so that each fetch sees the response it would have received on its own.
+
+use super::fetch::{FetchNode, Variables};
+use super::{FlattenNode, PlanNode};
+use crate::prelude::graphql::*;
+use apollo_parser::ast::{self, AstNode};
+use futures::future::join_all;
+use indexmap::IndexMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Context key holding the [`EntityBatching`] settings of a request.
+pub const ENTITY_BATCHING_CONTEXT_KEY: &str = "apollo::entity_batching::settings";
+
+/// Context key counting the fetches of a request that were merged into another one.
+pub const COALESCED_FETCHES_CONTEXT_KEY: &str = "apollo::entity_batching::coalesced_fetches";
+
+/// The subgraphs whose parallel entity fetches are merged.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EntityBatching {
+    /// Merge the entity fetches of every subgraph, except those disabled in `subgraphs`.
+    #[serde(default)]
+    pub all: bool,
+
+    /// Merging of the entity fetches of each subgraph, by subgraph name, over `all`.
+    #[serde(default)]
+    pub subgraphs: HashMap<String, bool>,
+}
+
+impl EntityBatching {
+    pub fn enabled(&self, subgraph: &str) -> bool {
+        self.subgraphs.get(subgraph).copied().unwrap_or(self.all)
+    }
+}
+
+/// An entity fetch of a parallel node, under its flatten node.
+pub(crate) struct EntityFetch<'a> {
+    path: &'a Path,
+    fetch: &'a FetchNode,
+}
+
+/// Splits the nodes of a parallel node into groups of entity fetches to merge, and the nodes to
+/// execute as usual.
+pub(crate) fn group<'a>(
+    nodes: &'a [PlanNode],
+    context: &Context,
+) -> (Vec<Vec<EntityFetch<'a>>>, Vec<&'a PlanNode>) {
+    let settings = match context.get::<_, EntityBatching>(ENTITY_BATCHING_CONTEXT_KEY) {
+        Ok(Some(settings)) => settings,
+        _ => return (Vec::new(), nodes.iter().collect()),
+    };
+
+    let mut fetches: IndexMap<&str, Vec<(&PlanNode, EntityFetch)>> = IndexMap::new();
+    let mut others = Vec::new();
+    for node in nodes {
+        match node {
+            PlanNode::Flatten(FlattenNode { path, node: child }) => match child.as_ref() {
+                PlanNode::Fetch(fetch)
+                    if fetch.is_entity_fetch() && settings.enabled(fetch.service_name()) =>
+                {
+                    fetches
+                        .entry(fetch.service_name())
+                        .or_default()
+                        .push((node, EntityFetch { path, fetch }));
+                }
+                _ => others.push(node),
+            },
+            _ => others.push(node),
+        }
+    }
+
+    let mut groups = Vec::new();
+    for (_, group) in fetches {
+        if group.len() > 1 {
+            groups.push(group.into_iter().map(|(_, fetch)| fetch).collect());
+        } else {
+            others.extend(group.into_iter().map(|(node, _)| node));
+        }
+    }
+    (groups, others)
+}
+
+/// Executes the entity fetches of `group` with a single subgraph request, when their operations
+/// can be merged.
+pub(crate) async fn fetch_together(
+    group: &[EntityFetch<'_>],
+    current_dir: &Path,
+    context: &Context,
+    service_registry: &ServiceRegistry,
+    schema: &Schema,
+    originating_request: http_compat::Request<Request>,
+    data: &Value,
+) -> (Value, Vec<Error>) {
+    let mut value = Value::default();
+    let mut errors = Vec::new();
+
+    let mut fetches = Vec::with_capacity(group.len());
+    for EntityFetch { path, fetch } in group {
+        let current_dir = current_dir.join(path);
+        match fetch
+            .variables(data, &current_dir, originating_request.clone(), schema)
+            .await
+        {
+            Some(variables) => fetches.push((*fetch, current_dir, variables)),
+            None => value.deep_merge(Value::from_path(&current_dir, Value::Null)),
+        }
+    }
+
+    let operation = if fetches.len() > 1 {
+        merge_operations(fetches.iter().map(|(fetch, _, _)| fetch.operation()))
+    } else {
+        None
+    };
+    let operation = match operation {
+        Some(operation) => operation,
+        None => {
+            // Nothing to merge, or operations of an unexpected shape: the fetches go on their own.
+            let results = join_all(fetches.iter().map(|(fetch, current_dir, _)| {
+                fetch.fetch_node(
+                    data,
+                    current_dir,
+                    context,
+                    service_registry,
+                    originating_request.clone(),
+                    schema,
+                )
+            }))
+            .await;
+            for (result, (_, current_dir, _)) in results.into_iter().zip(&fetches) {
+                match result {
+                    Ok((v, err)) => {
+                        value.deep_merge(v);
+                        errors.extend(err);
+                    }
+                    Err(err) => errors.push(err.to_graphql_error(Some(current_dir.clone()))),
+                }
+            }
+            return (value, errors);
+        }
+    };
+
+    let mut variables = Object::new();
+    for (index, (_, _, fetch_variables)) in fetches.iter().enumerate() {
+        for (key, variable) in fetch_variables.variables.iter() {
+            if key.as_str() == "representations" {
+                variables.insert(format!("representations_{}", index), variable.clone());
+            } else {
+                variables.insert(key.clone(), variable.clone());
+            }
+        }
+    }
+
+    let (first, _, _) = &fetches[0];
+    let response = match first
+        .send(
+            &operation,
+            None,
+            &variables,
+            context,
+            service_registry,
+            originating_request,
+            schema,
+        )
+        .await
+    {
+        Ok(response) => response,
+        Err(err) => {
+            for (_, current_dir, _) in &fetches {
+                errors.push(err.to_graphql_error(Some(current_dir.clone())));
+            }
+            return (value, errors);
+        }
+    };
+    if let Err(err) = context.upsert(
+        COALESCED_FETCHES_CONTEXT_KEY,
+        |count: usize| count + fetches.len() - 1,
+        || 0,
+    ) {
+        tracing::debug!("could not count the coalesced fetches: {}", err);
+    }
+
+    let mut responses = split_response(response, fetches.len());
+    for ((fetch, current_dir, Variables { variables, paths }), response) in
+        fetches.into_iter().zip(responses.drain(..))
+    {
+        let result = fetch
+            .validate_response(&response, &variables, context, schema)
+            .and_then(|()| fetch.integrate(&current_dir, paths, response));
+        match result {
+            Ok((v, err)) => {
+                value.deep_merge(v);
+                errors.extend(err);
+            }
+            Err(err) => errors.push(err.to_graphql_error(Some(current_dir))),
+        }
+    }
+    (value, errors)
+}
+
+/// Splits the response to a merged operation into the responses of its `count` fetches.
+///
+/// Errors are given to the fetch of their path, or to the first fetch when they have none.
+fn split_response(response: Response, count: usize) -> Vec<Response> {
+    let mut data = match response.data {
+        Some(Value::Object(data)) => data,
+        _ => Object::new(),
+    };
+    let mut responses: Vec<Response> = (0..count)
+        .map(|index| {
+            let mut entities = Object::new();
+            if let Some(value) = data.remove(format!("_entities_{}", index).as_str()) {
+                entities.insert("_entities", value);
+            }
+            Response::builder().data(Value::Object(entities)).build()
+        })
+        .collect();
+
+    for mut error in response.errors {
+        let index = match error.path.as_mut().and_then(|path| path.0.first_mut()) {
+            Some(PathElement::Key(key)) => match key
+                .strip_prefix("_entities_")
+                .and_then(|index| index.parse::<usize>().ok())
+                .filter(|index| *index < count)
+            {
+                Some(index) => {
+                    *key = "_entities".to_string();
+                    index
+                }
+                None => 0,
+            },
+            _ => 0,
+        };
+        responses[index].errors.push(error);
+    }
+    responses
+}
+
+/// Merges entity operations into one, with an aliased `_entities` field for each of them.
+///
+/// Returns `None` when one of them is not a query selecting `_entities` alone, or when they
+/// define different fragments with the same name.
+fn merge_operations<'a>(operations: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut variables: IndexMap<String, String> = IndexMap::new();
+    let mut fields = Vec::new();
+    let mut fragments: IndexMap<String, String> = IndexMap::new();
+
+    for (index, operation) in operations.enumerate() {
+        let document = apollo_parser::Parser::new(operation).parse().document();
+        let mut operations = 0;
+        for definition in document.definitions() {
+            match definition {
+                ast::Definition::OperationDefinition(operation) => {
+                    operations += 1;
+                    if let Some(operation_type) = operation.operation_type() {
+                        operation_type.query_token()?;
+                    }
+                    for variable in operation
+                        .variable_definitions()
+                        .into_iter()
+                        .flat_map(|definitions| definitions.variable_definitions())
+                    {
+                        let name = variable.variable()?.name()?.text().to_string();
+                        if name != "representations" {
+                            variables
+                                .entry(name)
+                                .or_insert_with(|| variable.syntax().to_string());
+                        }
+                    }
+                    variables.insert(
+                        format!("representations_{}", index),
+                        format!("$representations_{}:[_Any!]!", index),
+                    );
+
+                    let mut selections = operation.selection_set()?.selections();
+                    let field = match (selections.next(), selections.next()) {
+                        (Some(ast::Selection::Field(field)), None) => field,
+                        _ => return None,
+                    };
+                    if field.alias().is_some() || field.name()?.text() != "_entities" {
+                        return None;
+                    }
+                    fields.push(format!(
+                        "_entities_{index}:_entities(representations:$representations_{index}){}",
+                        field.selection_set()?.syntax(),
+                        index = index
+                    ));
+                }
+                ast::Definition::FragmentDefinition(fragment) => {
+                    let name = fragment.fragment_name()?.name()?.text().to_string();
+                    let text = fragment.syntax().to_string();
+                    match fragments.get(&name) {
+                        Some(existing) if *existing != text => return None,
+                        Some(_) => {}
+                        None => {
+                            fragments.insert(name, text);
+                        }
+                    }
+                }
+                _ => return None,
+            }
+        }
+        if operations != 1 {
+            return None;
+        }
+    }
+
+    Some(format!(
+        "query({}){{{}}}{}",
+        variables.values().cloned().collect::<Vec<_>>().join(" "),
+        fields.join(" "),
+        fragments.values().cloned().collect::<Vec<_>>().join(" ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json_bytes::json;
+
+    #[test]
+    fn entity_operations_are_merged_with_aliases() {
+        let merged = merge_operations(
+            [
+                "query($representations:[_Any!]!){_entities(representations:$representations){...on User{name}}}",
+                "query($representations:[_Any!]!$withBio:Boolean!){_entities(representations:$representations){...on User{bio@include(if:$withBio)}}}",
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            merged,
+            "query($representations_0:[_Any!]! $withBio:Boolean! $representations_1:[_Any!]!)\
+            {_entities_0:_entities(representations:$representations_0){...on User{name}} \
+            _entities_1:_entities(representations:$representations_1){...on User{bio@include(if:$withBio)}}}"
+        );
+    }
+
+    #[test]
+    fn operations_of_other_shapes_are_not_merged() {
+        assert!(merge_operations(
+            [
+                "query($representations:[_Any!]!){_entities(representations:$representations){...on User{name}}}",
+                "{me{name}}",
+            ]
+            .into_iter(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn responses_are_split_between_the_fetches() {
+        let response = Response::builder()
+            .data(json!({
+                "_entities_0": [{ "name": "Ada" }],
+                "_entities_1": [{ "bio": "..." }],
+            }))
+            .errors(vec![Error {
+                message: "bio unavailable".to_string(),
+                path: Some(Path::from("_entities_1/0/bio")),
+                ..Default::default()
+            }])
+            .build();
+
+        let responses = split_response(response, 2);
+        assert_eq!(
+            responses[0].data,
+            Some(json!({ "_entities": [{ "name": "Ada" }] }))
+        );
+        assert!(responses[0].errors.is_empty());
+        assert_eq!(
+            responses[1].errors[0].path,
+            Some(Path::from("_entities/0/bio"))
+        );
+    }
+
+    #[test]
+    fn subgraphs_can_be_enabled_one_by_one() {
+        let settings: EntityBatching = serde_json::from_value(
+            serde_json::json!({ "all": true, "subgraphs": { "reviews": false } }),
+        )
+        .unwrap();
+        assert!(settings.enabled("accounts"));
+        assert!(!settings.enabled("reviews"));
+    }
+}
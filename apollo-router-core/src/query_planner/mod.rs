@@ -1,13 +1,26 @@
 mod bridge_query_planner;
 mod caching_query_planner;
+mod entity_batching;
+mod parallelism;
+mod plan_journal;
+mod response_validation;
 mod selection;
 use crate::prelude::graphql::*;
 pub use bridge_query_planner::*;
 pub use caching_query_planner::*;
+pub use entity_batching::{
+    EntityBatching, COALESCED_FETCHES_CONTEXT_KEY, ENTITY_BATCHING_CONTEXT_KEY,
+};
 use fetch::OperationKind;
 use futures::prelude::*;
 use opentelemetry::trace::SpanKind;
-use serde::Deserialize;
+use parallelism::Scheduler;
+pub use parallelism::{Parallelism, PARALLELISM_CONTEXT_KEY};
+pub use plan_journal::{JournalEntry, PlanJournal};
+pub use response_validation::{
+    SubgraphResponseValidation, ValidationMode, SUBGRAPH_RESPONSE_VALIDATION_CONTEXT_KEY,
+};
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use tracing::Instrument;
 /// Query planning options.
@@ -15,7 +28,7 @@ use tracing::Instrument;
 pub struct QueryPlanOptions {}
 
 /// A plan for a [`crate::Query`]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct QueryPlan {
     pub(crate) root: PlanNode,
 }
@@ -30,8 +43,19 @@ impl Default for QueryPlan {
     }
 }
 
+/// Size of a [`QueryPlan`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryPlanStats {
+    /// Number of subgraph fetches.
+    pub fetch_count: usize,
+    /// Length of the longest chain of fetches that have to run one after the other.
+    pub depth: usize,
+    /// Number of distinct subgraphs fetched from.
+    pub subgraph_count: usize,
+}
+
 /// Query plans are composed of a set of nodes.
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase", tag = "kind")]
 pub(crate) enum PlanNode {
     /// These nodes must be executed in order.
@@ -92,6 +116,7 @@ impl QueryPlan {
 
         log::trace_query_plan(&self.root);
 
+        let scheduler = Scheduler::new(context);
         let (value, errors) = self
             .root
             .execute_recursively(
@@ -99,6 +124,7 @@ impl QueryPlan {
                 context,
                 service_registry,
                 schema,
+                &scheduler,
                 originating_request,
                 &Value::default(),
             )
@@ -110,15 +136,25 @@ impl QueryPlan {
     pub fn contains_mutations(&self) -> bool {
         self.root.contains_mutations()
     }
+
+    pub fn stats(&self) -> QueryPlanStats {
+        QueryPlanStats {
+            fetch_count: self.root.service_usage().count(),
+            depth: self.root.depth(),
+            subgraph_count: self.root.service_usage().collect::<HashSet<_>>().len(),
+        }
+    }
 }
 
 impl PlanNode {
+    #[allow(clippy::too_many_arguments)]
     fn execute_recursively<'a>(
         &'a self,
         current_dir: &'a Path,
         context: &'a Context,
         service_registry: &'a ServiceRegistry,
         schema: &'a Schema,
+        scheduler: &'a Scheduler,
         originating_request: http_compat::Request<Request>,
         parent_value: &'a Value,
     ) -> future::BoxFuture<(Value, Vec<Error>)> {
@@ -139,6 +175,7 @@ impl PlanNode {
                                 context,
                                 service_registry,
                                 schema,
+                                scheduler,
                                 originating_request.clone(),
                                 &value,
                             )
@@ -154,29 +191,56 @@ impl PlanNode {
                     errors = Vec::new();
 
                     let span = tracing::info_span!("parallel");
-                    let mut stream: stream::FuturesUnordered<_> = nodes
-                        .iter()
+                    let (groups, nodes) = entity_batching::group(nodes, context);
+                    let mut futures: Vec<_> = nodes
+                        .into_iter()
                         .map(|plan| {
                             plan.execute_recursively(
                                 current_dir,
                                 context,
                                 service_registry,
                                 schema,
+                                scheduler,
                                 originating_request.clone(),
                                 parent_value,
                             )
                             .instrument(span.clone())
                         })
                         .collect();
+                    for group in &groups {
+                        futures.push(
+                            scheduler
+                                .fetch(entity_batching::fetch_together(
+                                    group,
+                                    current_dir,
+                                    context,
+                                    service_registry,
+                                    schema,
+                                    originating_request.clone(),
+                                    parent_value,
+                                ))
+                                .boxed()
+                                .instrument(span.clone()),
+                        );
+                    }
 
-                    while let Some((v, err)) = stream
-                        .next()
-                        .instrument(span.clone())
-                        .in_current_span()
-                        .await
-                    {
-                        value.deep_merge(v);
-                        errors.extend(err.into_iter());
+                    if scheduler.sequential() {
+                        for future in futures {
+                            let (v, err) = future.in_current_span().await;
+                            value.deep_merge(v);
+                            errors.extend(err.into_iter());
+                        }
+                    } else {
+                        let mut stream: stream::FuturesUnordered<_> = futures.into_iter().collect();
+                        while let Some((v, err)) = stream
+                            .next()
+                            .instrument(span.clone())
+                            .in_current_span()
+                            .await
+                        {
+                            value.deep_merge(v);
+                            errors.extend(err.into_iter());
+                        }
                     }
                 }
                 PlanNode::Flatten(FlattenNode { path, node }) => {
@@ -187,6 +251,7 @@ impl PlanNode {
                             context,
                             service_registry,
                             schema,
+                            scheduler,
                             originating_request,
                             parent_value,
                         )
@@ -197,15 +262,15 @@ impl PlanNode {
                     errors = err;
                 }
                 PlanNode::Fetch(fetch_node) => {
-                    match fetch_node
-                        .fetch_node(
+                    match scheduler
+                        .fetch(fetch_node.fetch_node(
                             parent_value,
                             current_dir,
                             context,
                             service_registry,
                             originating_request,
                             schema,
-                        )
+                        ))
                         .instrument(tracing::info_span!(
                             "fetch",
                             "otel.kind" = %SpanKind::Internal
@@ -242,6 +307,16 @@ impl PlanNode {
         }
     }
 
+    /// Length of the longest chain of fetches that have to run one after the other.
+    fn depth(&self) -> usize {
+        match self {
+            Self::Sequence { nodes } => nodes.iter().map(|node| node.depth()).sum(),
+            Self::Parallel { nodes } => nodes.iter().map(|node| node.depth()).max().unwrap_or(0),
+            Self::Fetch(_) => 1,
+            Self::Flatten(flatten) => flatten.node.depth(),
+        }
+    }
+
     /// Recursively validate a query plan node making sure that all services are known before we go
     /// for execution.
     ///
@@ -268,13 +343,15 @@ impl PlanNode {
 
 pub(crate) mod fetch {
     use super::selection::{select_object, Selection};
+    use crate::plugin::timing::{FetchTiming, OperationTimings};
     use crate::prelude::graphql::*;
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use std::sync::Arc;
+    use std::time::Instant;
     use tower::ServiceExt;
     use tracing::{instrument, Instrument};
 
-    #[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub enum OperationKind {
         Query,
@@ -283,7 +360,7 @@ pub(crate) mod fetch {
     }
 
     /// A fetch node.
-    #[derive(Debug, PartialEq, Deserialize)]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub(crate) struct FetchNode {
         /// The name of the service or subgraph that the fetch is querying.
@@ -307,9 +384,9 @@ pub(crate) mod fetch {
         operation_kind: OperationKind,
     }
 
-    struct Variables {
-        variables: Object,
-        paths: Vec<Path>,
+    pub(crate) struct Variables {
+        pub(crate) variables: Object,
+        pub(crate) paths: Vec<Path>,
     }
 
     impl Variables {
@@ -376,31 +453,85 @@ pub(crate) mod fetch {
             originating_request: http_compat::Request<Request>,
             schema: &'a Schema,
         ) -> Result<(Value, Vec<Error>), FetchError> {
-            let FetchNode {
-                operation,
-                operation_kind,
-                operation_name,
-                service_name,
-                ..
-            } = self;
-
-            let Variables { variables, paths } = match Variables::new(
+            let Variables { variables, paths } = match self
+                .variables(data, current_dir, originating_request.clone(), schema)
+                .await
+            {
+                Some(variables) => variables,
+                None => {
+                    return Ok((Value::from_path(current_dir, Value::Null), Vec::new()));
+                }
+            };
+
+            let response = self
+                .send(
+                    &self.operation,
+                    self.operation_name.clone(),
+                    &variables,
+                    context,
+                    service_registry,
+                    originating_request,
+                    schema,
+                )
+                .await?;
+            self.validate_response(&response, &variables, context, schema)?;
+
+            self.integrate(current_dir, paths, response)
+        }
+
+        /// Checks `response` against the operation of the fetch, when the request asks for it.
+        pub(crate) fn validate_response(
+            &self,
+            response: &Response,
+            variables: &Object,
+            context: &Context,
+            schema: &Schema,
+        ) -> Result<(), FetchError> {
+            super::response_validation::validate(
+                &self.service_name,
+                &self.operation,
+                self.operation_name.as_deref(),
+                variables,
+                response,
+                context,
+                schema,
+            )
+        }
+
+        /// The variables of the fetch, with the representations of the entities it requires
+        /// from `data`, or `None` when there are none to fetch.
+        pub(crate) async fn variables(
+            &self,
+            data: &Value,
+            current_dir: &Path,
+            originating_request: http_compat::Request<Request>,
+            schema: &Schema,
+        ) -> Option<Variables> {
+            Variables::new(
                 &self.requires,
                 self.variable_usages.as_ref(),
                 data,
                 current_dir,
                 // Needs the original request here
-                originating_request.clone(),
+                originating_request,
                 schema,
             )
             .await
-            {
-                Some(variables) => variables,
-                None => {
-                    return Ok((Value::from_path(current_dir, Value::Null), Vec::new()));
-                }
-            };
+        }
 
+        /// Sends `operation` to the subgraph of the fetch.
+        #[allow(clippy::too_many_arguments)]
+        pub(crate) async fn send(
+            &self,
+            operation: &str,
+            operation_name: Option<String>,
+            variables: &Object,
+            context: &Context,
+            service_registry: &ServiceRegistry,
+            originating_request: http_compat::Request<Request>,
+            schema: &Schema,
+        ) -> Result<Response, FetchError> {
+            let service_name = &self.service_name;
             let subgraph_request = SubgraphRequest::builder()
                 .originating_request(Arc::new(originating_request))
                 .subgraph_request(
@@ -421,7 +552,7 @@ pub(crate) mod fetch {
                         .body(
                             Request::builder()
                                 .query(Some(operation.to_string()))
-                                .operation_name(operation_name.clone())
+                                .operation_name(operation_name)
                                 .variables(Arc::new(variables.clone()))
                                 .build(),
                         )
@@ -430,7 +561,7 @@ pub(crate) mod fetch {
                             "it won't fail because the url is correct and already checked; qed",
                         ),
                 )
-                .operation_kind(*operation_kind)
+                .operation_kind(self.operation_kind)
                 .context(context.clone())
                 .build();
 
@@ -438,11 +569,21 @@ pub(crate) mod fetch {
                 .get(service_name)
                 .expect("we already checked that the service exists during planning; qed");
 
-            // TODO not sure if we need a RouterReponse here as we don't do anything with it
-            let (_parts, response) = service
+            let start = Instant::now();
+            let result = service
                 .oneshot(subgraph_request)
                 .instrument(tracing::trace_span!("subfetch_stream"))
-                .await
+                .await;
+            let duration = start.elapsed().as_secs_f64();
+            OperationTimings::record(context, |timings| {
+                timings.fetches.push(FetchTiming {
+                    subgraph: service_name.to_string(),
+                    duration,
+                })
+            });
+
+            // TODO not sure if we need a RouterReponse here as we don't do anything with it
+            let (_parts, response) = result
                 .map_err(|e| FetchError::SubrequestHttpError {
                     service: service_name.to_string(),
                     reason: e.to_string(),
@@ -450,14 +591,23 @@ pub(crate) mod fetch {
                 .response
                 .into_parts();
 
-            super::log::trace_subfetch(service_name, operation, &variables, &response);
+            super::log::trace_subfetch(service_name, operation, variables, &response);
 
             if !response.is_primary() {
                 return Err(FetchError::SubrequestUnexpectedPatchResponse {
                     service: service_name.to_owned(),
                 });
             }
+            Ok(response)
+        }
 
+        /// Places the data of `response` at `current_dir`, or at the `paths` of the entities.
+        pub(crate) fn integrate(
+            &self,
+            current_dir: &Path,
+            paths: Vec<Path>,
+            response: Response,
+        ) -> Result<(Value, Vec<Error>), FetchError> {
             // fix error path and erase subgraph error messages (we cannot expose subgraph information
             // to the client)
             let errors = response
@@ -516,6 +666,15 @@ pub(crate) mod fetch {
             &self.service_name
         }
 
+        pub(crate) fn operation(&self) -> &str {
+            &self.operation
+        }
+
+        /// Whether the fetch is of entities, found with representations of the data it requires.
+        pub(crate) fn is_entity_fetch(&self) -> bool {
+            !self.requires.is_empty()
+        }
+
         pub(crate) fn operation_kind(&self) -> &OperationKind {
             &self.operation_kind
         }
@@ -523,7 +682,7 @@ pub(crate) mod fetch {
 }
 
 /// A flatten node.
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct FlattenNode {
     /// The path when result should be merged.
@@ -598,6 +757,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn stats() {
+        let query_plan = QueryPlan {
+            root: serde_json::from_str(test_query_plan!()).unwrap(),
+        };
+        assert_eq!(
+            query_plan.stats(),
+            QueryPlanStats {
+                fetch_count: 5,
+                depth: 3,
+                subgraph_count: 2,
+            }
+        );
+
+        let query_plan = QueryPlan {
+            root: serde_json::from_value(serde_json::json!({
+                "kind": "Sequence",
+                "nodes": [
+                    {
+                        "kind": "Fetch",
+                        "serviceName": "product",
+                        "variableUsages": [],
+                        "operation": "{topProducts{__typename upc}}",
+                        "operationKind": "query"
+                    },
+                    {
+                        "kind": "Flatten",
+                        "path": ["topProducts", "@"],
+                        "node": {
+                            "kind": "Fetch",
+                            "serviceName": "reviews",
+                            "variableUsages": [],
+                            "operation": "query($representations:[_Any!]!){_entities(representations:$representations){...on Product{reviews{id}}}}",
+                            "operationKind": "query"
+                        }
+                    }
+                ]
+            }))
+            .unwrap(),
+        };
+        assert_eq!(
+            query_plan.stats(),
+            QueryPlanStats {
+                fetch_count: 2,
+                depth: 2,
+                subgraph_count: 2,
+            }
+        );
+    }
+
     /// This test panics in the product subgraph. HOWEVER, this does not result in a panic in the
     /// test, since the buffer() functionality in the tower stack "loses" the panic and we end up
     /// with a closed service.
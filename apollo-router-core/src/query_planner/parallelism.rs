@@ -0,0 +1,111 @@
+//! Scheduling of the fetches of a query plan.
+//!
+//! By default, the nodes of a parallel node run concurrently, and a request has as many subgraph
+//! fetches in flight as its plan allows. The settings of a request can cap that number, or run the
+//! nodes of parallel nodes one after the other, in the order of the plan.
+
+use crate::prelude::graphql::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Context key holding the [`Parallelism`] settings of a request.
+pub const PARALLELISM_CONTEXT_KEY: &str = "apollo::parallelism::settings";
+
+/// How the fetches of a request are scheduled.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Parallelism {
+    /// Most subgraph fetches in flight at once for a single request. Unlimited by default.
+    #[serde(default)]
+    pub max_concurrent_fetches: Option<usize>,
+
+    /// Run the nodes of parallel plan nodes one after the other. Disabled by default.
+    #[serde(default)]
+    pub sequential: bool,
+}
+
+/// The scheduling of the fetches of a request, shared by the nodes of its plan.
+pub(crate) struct Scheduler {
+    sequential: bool,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl Scheduler {
+    pub(crate) fn new(context: &Context) -> Self {
+        let settings = context
+            .get::<_, Parallelism>(PARALLELISM_CONTEXT_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Self {
+            sequential: settings.sequential,
+            permits: settings
+                .max_concurrent_fetches
+                .map(|permits| Arc::new(Semaphore::new(permits.max(1)))),
+        }
+    }
+
+    pub(crate) fn sequential(&self) -> bool {
+        self.sequential
+    }
+
+    /// Runs `fetch` once the request is under its limit of fetches in flight.
+    pub(crate) async fn fetch<F: Future>(&self, fetch: F) -> F::Output {
+        let _permit = match &self.permits {
+            Some(permits) => Some(
+                permits
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        fetch.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn fetches_in_flight_are_limited() {
+        let context = Context::new();
+        context
+            .insert(
+                PARALLELISM_CONTEXT_KEY,
+                Parallelism {
+                    max_concurrent_fetches: Some(2),
+                    sequential: false,
+                },
+            )
+            .unwrap();
+        let scheduler = Scheduler::new(&context);
+        let in_flight = AtomicUsize::new(0);
+        let most_in_flight = AtomicUsize::new(0);
+
+        futures::future::join_all((0..6).map(|_| {
+            scheduler.fetch(async {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most_in_flight.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            })
+        }))
+        .await;
+
+        assert_eq!(most_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn requests_without_settings_are_not_limited() {
+        let scheduler = Scheduler::new(&Context::new());
+        assert!(!scheduler.sequential());
+        assert!(scheduler.permits.is_none());
+    }
+}
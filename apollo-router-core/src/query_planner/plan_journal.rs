@@ -0,0 +1,74 @@
+//! Journal of the operations recently planned.
+//!
+//! A router instance built after a schema or configuration reload starts with empty caches. The
+//! journal outlives router instances, so that the operations clients sent most recently can be
+//! planned again before the new instance takes traffic.
+
+use lru::LruCache;
+use std::sync::{Arc, Mutex};
+
+/// Number of operations kept in the journal.
+const JOURNAL_CAPACITY: usize = 1000;
+
+/// Query and operation name of a planned operation.
+pub type JournalEntry = (String, Option<String>);
+
+/// The operations recently planned, most recent first. Clones share the same journal.
+#[derive(Clone, Debug)]
+pub struct PlanJournal {
+    entries: Arc<Mutex<LruCache<JournalEntry, ()>>>,
+}
+
+impl Default for PlanJournal {
+    fn default() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(LruCache::new(JOURNAL_CAPACITY))),
+        }
+    }
+}
+
+impl PlanJournal {
+    /// Records that the operation `operation_name` of `query` was planned.
+    pub fn record(&self, query: &str, operation_name: Option<&str>) {
+        self.entries
+            .lock()
+            .expect("lock poisoned")
+            .put((query.to_string(), operation_name.map(str::to_string)), ());
+    }
+
+    /// The `count` operations planned most recently, most recent first.
+    pub fn recent(&self, count: usize) -> Vec<JournalEntry> {
+        self.entries
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .take(count)
+            .map(|(entry, _)| entry.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_operations_come_first() {
+        let journal = PlanJournal::default();
+        journal.record("{ me { id } }", None);
+        journal.record("query A { topProducts { upc } }", Some("A"));
+        journal.clone().record("{ me { id } }", None);
+
+        assert_eq!(
+            journal.recent(5),
+            vec![
+                ("{ me { id } }".to_string(), None),
+                (
+                    "query A { topProducts { upc } }".to_string(),
+                    Some("A".to_string())
+                ),
+            ]
+        );
+        assert_eq!(journal.recent(1).len(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+//! Validation of subgraph responses against the selections of their fetch.
+//!
+//! A subgraph deployed with a schema that no longer matches the supergraph may send values of
+//! the wrong type, `null` for non-null fields, or fields that were never asked for. Checking each
+//! response against the operation of its fetch catches those before their data reaches clients.
+
+use crate::prelude::graphql::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Context key holding the [`SubgraphResponseValidation`] settings of a request.
+pub const SUBGRAPH_RESPONSE_VALIDATION_CONTEXT_KEY: &str =
+    "apollo::subgraph_response_validation::settings";
+
+/// What is done with the subgraph responses that do not match the schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationMode {
+    /// Invalid responses are logged, and their data is used as usual.
+    Log,
+    /// The fetches with an invalid response fail, with an error at their path.
+    Reject,
+}
+
+impl Default for ValidationMode {
+    fn default() -> Self {
+        ValidationMode::Log
+    }
+}
+
+/// Validation of the subgraph responses.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphResponseValidation {
+    /// Whether invalid responses are logged or rejected. Defaults to `log`.
+    #[serde(default)]
+    pub mode: ValidationMode,
+
+    /// Subgraphs whose responses are trusted and not validated.
+    #[serde(default)]
+    pub skip: Vec<String>,
+}
+
+/// Checks the response of `service` to `operation`, when the request asks for it.
+///
+/// Returns an error only for invalid responses in `reject` mode.
+pub(crate) fn validate(
+    service: &str,
+    operation: &str,
+    operation_name: Option<&str>,
+    variables: &Object,
+    response: &Response,
+    context: &Context,
+    schema: &Schema,
+) -> Result<(), FetchError> {
+    let settings = match context
+        .get::<_, SubgraphResponseValidation>(SUBGRAPH_RESPONSE_VALIDATION_CONTEXT_KEY)
+    {
+        Ok(Some(settings)) if !settings.skip.iter().any(|skipped| skipped == service) => settings,
+        _ => return Ok(()),
+    };
+    let query = match Query::parse(operation, schema) {
+        Some(query) => query,
+        None => {
+            tracing::debug!("could not parse the operation sent to {}", service);
+            return Ok(());
+        }
+    };
+
+    let path = match query.validate_subgraph_response(response, operation_name, variables, schema) {
+        Ok(()) => return Ok(()),
+        Err(path) => path,
+    };
+    match settings.mode {
+        ValidationMode::Log => {
+            tracing::warn!(
+                "the response of subgraph {} does not match the schema at {}",
+                service,
+                path
+            );
+            Ok(())
+        }
+        ValidationMode::Reject => Err(FetchError::SubrequestInvalidResponse {
+            service: service.to_string(),
+            path: path.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json_bytes::json;
+
+    const SCHEMA: &str = "type Query { me: User } type User { id: ID! name: String }";
+
+    fn check(data: Value, mode: ValidationMode) -> Result<(), FetchError> {
+        let schema: Schema = SCHEMA.parse().unwrap();
+        let context = Context::new();
+        context
+            .insert(
+                SUBGRAPH_RESPONSE_VALIDATION_CONTEXT_KEY,
+                SubgraphResponseValidation {
+                    mode,
+                    skip: Vec::new(),
+                },
+            )
+            .unwrap();
+        validate(
+            "accounts",
+            "{ me { id name } }",
+            None,
+            &Object::new(),
+            &Response::builder().data(data).build(),
+            &context,
+            &schema,
+        )
+    }
+
+    #[test]
+    fn valid_responses_pass() {
+        assert!(check(
+            json!({ "me": { "id": "1", "name": null } }),
+            ValidationMode::Reject
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn non_null_violations_are_rejected() {
+        assert!(matches!(
+            check(json!({ "me": { "id": null, "name": "Ada" } }), ValidationMode::Reject),
+            Err(FetchError::SubrequestInvalidResponse { service, path })
+                if service == "accounts" && path == "/me/id"
+        ));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(matches!(
+            check(
+                json!({ "me": { "id": "1", "name": "Ada", "email": "ada@example.com" } }),
+                ValidationMode::Reject
+            ),
+            Err(FetchError::SubrequestInvalidResponse { path, .. }) if path == "/me/email"
+        ));
+    }
+
+    #[test]
+    fn invalid_responses_are_only_logged_by_default() {
+        assert!(check(json!({ "me": { "id": null } }), ValidationMode::default()).is_ok());
+    }
+}
@@ -96,7 +96,21 @@ impl Request {
     }
 
     pub fn from_bytes(b: Bytes) -> Result<Request, serde_json::error::Error> {
-        let value = Value::from_bytes(b)?;
+        Self::from_value(Value::from_bytes(b)?)
+    }
+
+    /// Parses a batch of requests, sent as a JSON array.
+    pub fn batch_from_bytes(b: Bytes) -> Result<Vec<Request>, serde_json::error::Error> {
+        match Value::from_bytes(b)? {
+            Value::Array(values) => values.into_iter().map(Self::from_value).collect(),
+            _ => Err(serde::de::Error::custom(
+                "a batch must be an array of requests",
+            )),
+        }
+    }
+
+    /// Reads a request from its JSON value, as [`Request::from_bytes`] does.
+    pub fn from_value(value: Value) -> Result<Request, serde_json::error::Error> {
         let mut object = ensure_object!(value).map_err(serde::de::Error::custom)?;
 
         let variables = extract_key_value_from_object!(object, "variables", Value::Object(o) => o)
@@ -237,4 +251,15 @@ mod tests {
 
         assert_eq!(expected_result, req);
     }
+
+    #[test]
+    fn batches_are_arrays_of_requests() {
+        let batch = json!([{ "query": "{ a }" }, { "query": "{ b }", "variables": { "c": 1 } }]);
+        let requests = Request::batch_from_bytes(batch.to_string().into()).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].query.as_deref(), Some("{ b }"));
+        assert_eq!(requests[1].variables.get("c"), Some(&bjson!(1)));
+
+        assert!(Request::batch_from_bytes(json!({ "query": "{ a }" }).to_string().into()).is_err());
+    }
 }
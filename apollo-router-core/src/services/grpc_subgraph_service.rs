@@ -0,0 +1,536 @@
+//! Fetcher for subgraphs reached over gRPC.
+//!
+//! GraphQL requests and responses are sent in the JSON `body` of a protobuf envelope:
+//!
+//! ```protobuf
+//! message GraphQLMessage {
+//!   bytes body = 1;
+//! }
+//!
+//! service GraphQL {
+//!   rpc Execute(GraphQLMessage) returns (GraphQLMessage);
+//! }
+//! ```
+//!
+//! The headers of the subgraph request are sent as metadata, and the deadline of the fetch as
+//! `grpc-timeout`: the configured timeout, shortened to the time left before the deadline of the
+//! client request when it sent a `grpc-timeout` of its own. Statuses other than `OK` become
+//! GraphQL errors of the fetch.
+
+use super::tower_subgraph_service::{http_error, ClientTls, Pool};
+use super::{SubgraphClientConfig, SubgraphTls};
+use crate::prelude::*;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::future::BoxFuture;
+use http::header::{ACCEPT, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, TE};
+use http::{HeaderMap, HeaderValue, Uri};
+use hyper::body::HttpBody;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower::BoxError;
+use tracing::Instrument;
+
+/// Names of the gRPC status codes, by code.
+const STATUS_CODES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+const DEADLINE_EXCEEDED: u32 = 4;
+
+/// Context key of the deadline of the client request, in milliseconds since the Unix epoch, set
+/// when it has a `grpc-timeout` header.
+pub const GRPC_DEADLINE_CONTEXT_KEY: &str = "apollo::grpc::deadline";
+
+/// gRPC transport of a subgraph.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcSubgraphConfig {
+    /// Path of the method executing GraphQL requests. Defaults to
+    /// `/apollo.graphql.v1.GraphQL/Execute`.
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// Deadline of each fetch, sent to the subgraph as `grpc-timeout`, and shortened to the time
+    /// left before the deadline of the client request when it has a `grpc-timeout` header.
+    /// Fetches going over it fail with `DEADLINE_EXCEEDED`. None by default.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String")]
+    pub timeout: Option<Duration>,
+}
+
+fn default_method() -> String {
+    "/apollo.graphql.v1.GraphQL/Execute".to_string()
+}
+
+impl Default for GrpcSubgraphConfig {
+    fn default() -> Self {
+        Self {
+            method: default_method(),
+            timeout: None,
+        }
+    }
+}
+
+/// Client for subgraphs reached over gRPC, a peer of [`TowerSubgraphService`](super::TowerSubgraphService).
+#[derive(Clone)]
+pub struct GrpcSubgraphService {
+    pool: Arc<Pool>,
+    service: Arc<String>,
+    config: Arc<GrpcSubgraphConfig>,
+    max_response_bytes: Option<usize>,
+}
+
+impl GrpcSubgraphService {
+    pub fn new(service: impl Into<String>, config: GrpcSubgraphConfig) -> Self {
+        Self {
+            pool: Arc::new(Pool::new(http2_only(SubgraphClientConfig::default()), None)),
+            service: Arc::new(service.into()),
+            config: Arc::new(config),
+            max_response_bytes: None,
+        }
+    }
+
+    /// Pools connections with `config`, always over HTTP/2 as gRPC requires.
+    pub fn with_client_config(mut self, config: SubgraphClientConfig) -> Self {
+        self.pool = Arc::new(Pool::new(http2_only(config), None));
+        self
+    }
+
+    /// Connects to the subgraph with `tls`, reading its files right away.
+    pub fn with_tls(mut self, tls: &SubgraphTls) -> Result<Self, BoxError> {
+        self.pool = Arc::new(Pool::new(
+            self.pool.config.clone(),
+            Some(ClientTls::new(tls)?),
+        ));
+        Ok(self)
+    }
+
+    /// Fails fetches whose response body is larger than `max_response_bytes`.
+    pub fn with_max_response_bytes(mut self, max_response_bytes: Option<usize>) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    async fn execute(
+        &self,
+        uri: &Uri,
+        headers: &HeaderMap,
+        request: &graphql::Request,
+        deadline: Option<u64>,
+    ) -> Result<graphql::Response, graphql::FetchError> {
+        let timeout = match (self.config.timeout, deadline.map(time_left)) {
+            (Some(timeout), Some(left)) => Some(timeout.min(left)),
+            (timeout, left) => timeout.or(left),
+        };
+        let exchange = self.exchange(uri, headers, request, timeout);
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange)
+                .await
+                .unwrap_or_else(|_| Err((DEADLINE_EXCEEDED, "deadline exceeded".to_string()))),
+            None => exchange.await,
+        };
+        match result {
+            Ok(body) => graphql::Response::from_bytes(&self.service, body),
+            Err((code, message)) => {
+                let error = graphql::FetchError::SubrequestGrpcError {
+                    service: self.service.to_string(),
+                    code: status_name(code),
+                    message,
+                }
+                .to_graphql_error(None);
+                Ok(graphql::Response::builder().errors(vec![error]).build())
+            }
+        }
+    }
+
+    /// Calls the method with `request`, returning the body of the answer, or the status of the
+    /// failure.
+    async fn exchange(
+        &self,
+        uri: &Uri,
+        headers: &HeaderMap,
+        request: &graphql::Request,
+        timeout: Option<Duration>,
+    ) -> Result<Bytes, (u32, String)> {
+        let internal = |err: graphql::FetchError| (2, err.to_string());
+
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(
+            self.config
+                .method
+                .parse()
+                .map_err(|err| internal(http_error(&self.service, err)))?,
+        );
+        let uri = Uri::from_parts(parts).map_err(|err| internal(http_error(&self.service, err)))?;
+
+        let mut builder = http::Request::builder().method(http::Method::POST).uri(uri);
+        for (name, value) in headers {
+            if ![HOST, CONTENT_TYPE, CONTENT_LENGTH, ACCEPT, CONNECTION, TE].contains(name)
+                && name != "grpc-timeout"
+            {
+                builder = builder.header(name, value);
+            }
+        }
+        builder = builder
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/grpc"))
+            .header(TE, HeaderValue::from_static("trailers"));
+        if let Some(timeout) = timeout {
+            builder = builder.header("grpc-timeout", grpc_timeout(timeout));
+        }
+        let body = serde_json::to_vec(request).expect("JSON serialization should not fail");
+        let request = builder
+            .body(hyper::Body::from(encode_message(&body)))
+            .map_err(|err| internal(http_error(&self.service, err)))?;
+
+        let response = self
+            .pool
+            .client()
+            .request(request)
+            .instrument(tracing::debug_span!("grpc_call"))
+            .await
+            .map_err(|err| (14, err.to_string()))?;
+        if !response.status().is_success() {
+            return Err((14, format!("HTTP status {}", response.status())));
+        }
+        // Failures without a message are answered with their status in the headers.
+        if let Some(status) = status(response.headers()) {
+            return Err(status);
+        }
+
+        let mut body = response.into_body();
+        let mut buffer = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| (14, err.to_string()))?;
+            if let Some(max_bytes) = self.max_response_bytes {
+                if buffer.len() + chunk.len() > max_bytes {
+                    return Err((
+                        8,
+                        graphql::FetchError::SubrequestResponseTooLarge {
+                            service: self.service.to_string(),
+                            limit: max_bytes,
+                        }
+                        .to_string(),
+                    ));
+                }
+            }
+            buffer.extend_from_slice(&chunk);
+        }
+        let trailers = body.trailers().await.map_err(|err| (14, err.to_string()))?;
+        match trailers.as_ref().and_then(status) {
+            Some(status) => Err(status),
+            None if trailers.is_none() => Err((13, "missing the grpc-status trailer".to_string())),
+            None => decode_message(buffer.freeze()).map_err(|reason| (13, reason)),
+        }
+    }
+}
+
+impl tower::Service<graphql::SubgraphRequest> for GrpcSubgraphService {
+    type Response = graphql::SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: graphql::SubgraphRequest) -> Self::Future {
+        let graphql::SubgraphRequest {
+            subgraph_request,
+            context,
+            ..
+        } = request;
+        let service = self.clone();
+
+        Box::pin(async move {
+            let deadline = context.get(GRPC_DEADLINE_CONTEXT_KEY).unwrap_or_default();
+            let (parts, body) = subgraph_request.into_parts();
+            let response = service
+                .execute(&parts.uri, &parts.headers, &body, deadline)
+                .await?;
+            Ok(graphql::SubgraphResponse::new_from_response(
+                http::Response::builder()
+                    .body(response)
+                    .expect("no argument can fail to parse or converted to the internal representation here; qed")
+                    .into(),
+                context,
+            ))
+        })
+    }
+}
+
+fn http2_only(config: SubgraphClientConfig) -> SubgraphClientConfig {
+    SubgraphClientConfig {
+        http2_only: true,
+        ..config
+    }
+}
+
+fn status_name(code: u32) -> String {
+    STATUS_CODES
+        .get(code as usize)
+        .map_or_else(|| code.to_string(), |name| name.to_string())
+}
+
+/// The status in `headers`, unless it is `OK`.
+fn status(headers: &HeaderMap) -> Option<(u32, String)> {
+    let code = headers
+        .get("grpc-status")?
+        .to_str()
+        .ok()
+        .and_then(|code| code.parse::<u32>().ok())
+        .unwrap_or(2);
+    if code == 0 {
+        return None;
+    }
+    let message = headers
+        .get("grpc-message")
+        .and_then(|message| message.to_str().ok())
+        .and_then(|message| urlencoding::decode(message).ok())
+        .map(|message| message.into_owned())
+        .unwrap_or_else(|| status_name(code));
+    Some((code, message))
+}
+
+/// The `grpc-timeout` header of `timeout`, in milliseconds, or seconds when too long for the
+/// 8 digits allowed.
+fn grpc_timeout(timeout: Duration) -> HeaderValue {
+    let millis = timeout.as_millis();
+    let value = if millis < 100_000_000 {
+        format!("{}m", millis.max(1))
+    } else {
+        format!("{}S", timeout.as_secs().min(99_999_999))
+    };
+    HeaderValue::from_str(&value).expect("digits and a unit are a valid header value; qed")
+}
+
+/// The deadline of a client request with the `grpc-timeout` header of `headers`, in milliseconds
+/// since the Unix epoch.
+pub fn grpc_deadline(headers: &HeaderMap) -> Option<u64> {
+    let timeout = parse_grpc_timeout(headers.get("grpc-timeout")?.to_str().ok()?)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some((now + timeout).as_millis() as u64)
+}
+
+/// The duration of a `grpc-timeout` header: up to 8 digits followed by a unit.
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = amount.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// The time left before `deadline`, in milliseconds since the Unix epoch.
+fn time_left(deadline: u64) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Duration::from_millis(deadline).saturating_sub(now)
+}
+
+/// A length-prefixed gRPC message holding an envelope with `body`.
+fn encode_message(body: &[u8]) -> Bytes {
+    let mut envelope = BytesMut::with_capacity(body.len() + 11);
+    envelope.put_u8(0x0a); // field 1, length-delimited
+    put_varint(&mut envelope, body.len() as u64);
+    envelope.extend_from_slice(body);
+
+    let mut message = BytesMut::with_capacity(envelope.len() + 5);
+    message.put_u8(0); // not compressed
+    message.put_u32(envelope.len() as u32);
+    message.extend_from_slice(&envelope);
+    message.freeze()
+}
+
+/// The `body` of the envelope in the first length-prefixed gRPC message of `data`.
+fn decode_message(mut data: Bytes) -> Result<Bytes, String> {
+    if data.len() < 5 {
+        return Err("the answer has no message".to_string());
+    }
+    if data.get_u8() != 0 {
+        return Err("compressed messages are not supported".to_string());
+    }
+    let len = data.get_u32() as usize;
+    if data.len() < len {
+        return Err("the message is truncated".to_string());
+    }
+    let mut envelope = data.split_to(len);
+
+    let mut body = Bytes::new();
+    while envelope.has_remaining() {
+        let key = get_varint(&mut envelope)?;
+        match key & 0x7 {
+            0 => {
+                get_varint(&mut envelope)?;
+            }
+            1 | 5 => {
+                let size = if key & 0x7 == 1 { 8 } else { 4 };
+                if envelope.remaining() < size {
+                    return Err("the message is truncated".to_string());
+                }
+                envelope.advance(size);
+            }
+            2 => {
+                let len = get_varint(&mut envelope)? as usize;
+                if envelope.remaining() < len {
+                    return Err("the message is truncated".to_string());
+                }
+                let field = envelope.split_to(len);
+                if key >> 3 == 1 {
+                    body = field;
+                }
+            }
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        }
+    }
+    Ok(body)
+}
+
+fn put_varint(buffer: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buffer.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.put_u8(value as u8);
+}
+
+fn get_varint(buffer: &mut Bytes) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        if !buffer.has_remaining() {
+            break;
+        }
+        let byte = buffer.get_u8();
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err("invalid varint".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bodies_are_sent_in_an_envelope() {
+        let body = vec![b'x'; 300];
+        let message = encode_message(&body);
+        // The length of the body takes two bytes.
+        assert_eq!(&message[..8], &[0, 0, 0, 1, 47, 0x0a, 0xac, 0x02]);
+        assert_eq!(decode_message(message).unwrap(), Bytes::from(body));
+
+        // Unknown fields are skipped.
+        let message = Bytes::from_static(&[0, 0, 0, 0, 6, 0x10, 0x01, 0x0a, 0x02, b'{', b'}', 0]);
+        assert_eq!(decode_message(message).unwrap(), Bytes::from_static(b"{}"));
+
+        assert!(decode_message(Bytes::from_static(&[1, 0, 0, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn statuses_are_read_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(status(&headers), None);
+
+        headers.insert("grpc-status", HeaderValue::from_static("0"));
+        assert_eq!(status(&headers), None);
+
+        headers.insert("grpc-status", HeaderValue::from_static("14"));
+        assert_eq!(status(&headers), Some((14, "UNAVAILABLE".to_string())));
+
+        headers.insert("grpc-message", HeaderValue::from_static("no%20backend"));
+        assert_eq!(status(&headers), Some((14, "no backend".to_string())));
+    }
+
+    #[test]
+    fn deadlines_are_sent_in_milliseconds() {
+        assert_eq!(grpc_timeout(Duration::from_millis(1500)), "1500m");
+        assert_eq!(grpc_timeout(Duration::from_secs(200_000)), "200000S");
+    }
+
+    #[test]
+    fn client_deadlines_are_read_from_their_timeout() {
+        assert_eq!(
+            parse_grpc_timeout("1500m"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("10x"), None);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(grpc_deadline(&headers), None);
+        headers.insert("grpc-timeout", HeaderValue::from_static("10S"));
+        let left = time_left(grpc_deadline(&headers).unwrap());
+        assert!(left > Duration::from_secs(9) && left <= Duration::from_secs(10));
+        assert_eq!(time_left(0), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn fetches_end_at_the_client_deadline() {
+        // A subgraph accepting connections without ever answering.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+
+        let service = GrpcSubgraphService::new(
+            "products",
+            GrpcSubgraphConfig {
+                timeout: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        );
+        let uri: Uri = format!("http://{}/", address).parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-timeout", HeaderValue::from_static("100m"));
+        let started = std::time::Instant::now();
+        let response = service
+            .execute(
+                &uri,
+                &HeaderMap::new(),
+                &graphql::Request::builder()
+                    .query(Some("{ me }".to_string()))
+                    .build(),
+                grpc_deadline(&headers),
+            )
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0].message.contains("DEADLINE_EXCEEDED"));
+    }
+}
@@ -2,15 +2,20 @@
 //!
 //! To improve their usability.
 
-use axum::{body::boxed, response::IntoResponse};
-use bytes::Bytes;
+use axum::{
+    body::{boxed, StreamBody},
+    response::IntoResponse,
+};
+use bytes::{Bytes, BytesMut};
 use http::{header::HeaderName, request::Parts, uri::InvalidUri, HeaderValue, Method};
 use multimap::MultiMap;
 use std::{
     cmp::PartialEq,
     hash::Hash,
+    io,
     ops::{Deref, DerefMut},
 };
+use tokio::sync::mpsc;
 
 use crate::ResponseBody;
 
@@ -189,9 +194,21 @@ impl<T: Clone> Clone for Request<T> {
                 .map(|(name, value)| (name.clone(), value.clone())),
         );
 
-        let req = req
+        let mut req = req
             .body(self.inner.body().clone())
             .expect("cloning a valid request creates a valid request");
+        // the files of multipart requests are sent on by subgraph requests
+        if let Some(uploads) = self.inner.extensions().get::<crate::Uploads>() {
+            req.extensions_mut().insert(uploads.clone());
+        }
+        // as is the payload of the WebSocket connection of subscriptions
+        if let Some(connection) = self
+            .inner
+            .extensions()
+            .get::<crate::SubscriptionConnection>()
+        {
+            req.extensions_mut().insert(connection.clone());
+        }
         Self { inner: req }
     }
 }
@@ -313,7 +330,6 @@ impl<T: Clone> Clone for Response<T> {
 
 impl IntoResponse for Response<ResponseBody> {
     fn into_response(self) -> axum::response::Response {
-        // todo: chunks?
         let (parts, body) = self.into_parts();
         let json_body_bytes =
             Bytes::from(serde_json::to_vec(&body).expect("body should be serializable; qed"));
@@ -322,9 +338,97 @@ impl IntoResponse for Response<ResponseBody> {
     }
 }
 
+/// Size of the chunks of streamed response bodies.
+const STREAMED_CHUNK_BYTES: usize = 16 * 1024;
+/// Chunks serialized ahead of those sent to the client.
+const STREAMED_CHUNKS_AHEAD: usize = 4;
+
+impl Response<ResponseBody> {
+    /// Converts to a response whose body is serialized as it is sent, in chunks, instead of
+    /// being serialized whole first.
+    ///
+    /// Only the serialization is streamed: the response is complete when this is called, as the
+    /// bodies of subgraphs are read whole to be parsed and merged into it.
+    ///
+    /// The serialization runs on a blocking thread, waiting for the client to read the previous
+    /// chunks, and stops if the client goes away. The status is sent before the body is
+    /// serialized, so a serialization that fails midway fails the body instead, which clients
+    /// cannot take for a complete one.
+    pub fn into_streaming_response(self) -> axum::response::Response {
+        let (parts, body) = self.into_parts();
+        let (sender, receiver) = mpsc::channel(STREAMED_CHUNKS_AHEAD);
+        tokio::task::spawn_blocking(move || {
+            let mut writer = ChunkWriter {
+                buffer: BytesMut::with_capacity(STREAMED_CHUNK_BYTES),
+                sender,
+            };
+            let written = serde_json::to_writer(&mut writer, &body)
+                .map_err(io::Error::from)
+                .and_then(|()| io::Write::flush(&mut writer));
+            // An empty chunk marks the end of the body.
+            let _ = writer.sender.blocking_send(written.map(|()| Bytes::new()));
+        });
+
+        axum::response::Response::from_parts(parts, boxed(StreamBody::new(chunks(receiver))))
+    }
+}
+
+/// The chunks of a streamed body, ending at the empty chunk sent once it is fully written, and
+/// failing if the writer stops before.
+fn chunks(
+    receiver: mpsc::Receiver<io::Result<Bytes>>,
+) -> impl futures::Stream<Item = io::Result<Bytes>> {
+    futures::stream::unfold(Some(receiver), |receiver| async move {
+        let mut receiver = receiver?;
+        match receiver.recv().await {
+            Some(Ok(chunk)) if chunk.is_empty() => None,
+            Some(Ok(chunk)) => Some((Ok(chunk), Some(receiver))),
+            Some(Err(err)) => Some((Err(err), None)),
+            None => Some((
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the serialization of the response stopped",
+                )),
+                None,
+            )),
+        }
+    })
+}
+
+/// Sends what is written to it in chunks, blocking while the channel is full.
+struct ChunkWriter {
+    buffer: BytesMut,
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl ChunkWriter {
+    fn send(&mut self) -> io::Result<()> {
+        let chunk = self.buffer.split().freeze();
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the client went away"))
+    }
+}
+
+impl io::Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= STREAMED_CHUNK_BYTES {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send()
+    }
+}
+
 impl IntoResponse for Response<Bytes> {
     fn into_response(self) -> axum::response::Response {
-        // todo: chunks?
         let (parts, body) = self.into_parts();
 
         axum::response::Response::from_parts(parts, boxed(http_body::Full::new(body)))
@@ -333,8 +437,8 @@ impl IntoResponse for Response<Bytes> {
 
 #[cfg(test)]
 mod test {
-    use crate::http_compat::Request;
-    use http::{HeaderValue, Method, Uri};
+    use super::*;
+    use http::Uri;
 
     #[test]
     fn builder() {
@@ -358,4 +462,47 @@ mod test {
         assert_eq!(request.method(), Method::POST);
         assert_eq!(request.body(), &"test");
     }
+
+    #[tokio::test]
+    async fn streamed_bodies_match_buffered_ones() {
+        let items: Vec<_> = (0..10_000)
+            .map(|id| serde_json_bytes::json!({ "id": id, "name": "Ada" }))
+            .collect();
+        let body = || {
+            ResponseBody::GraphQL(
+                crate::Response::builder()
+                    .data(serde_json_bytes::json!({ "items": items.clone() }))
+                    .build(),
+            )
+        };
+        let response = |body| Response::from(http::Response::new(body));
+
+        let buffered = hyper::body::to_bytes(response(body()).into_response().into_body())
+            .await
+            .unwrap();
+        let streamed =
+            hyper::body::to_bytes(response(body()).into_streaming_response().into_body())
+                .await
+                .unwrap();
+        assert!(buffered.len() > STREAMED_CHUNK_BYTES);
+        assert_eq!(streamed, buffered);
+    }
+
+    #[tokio::test]
+    async fn interrupted_serializations_fail_the_body() {
+        let (sender, receiver) = mpsc::channel(2);
+        sender
+            .send(Ok(Bytes::from_static(b"{\"data\":")))
+            .await
+            .unwrap();
+        drop(sender);
+        let body = StreamBody::new(chunks(receiver));
+        assert!(hyper::body::to_bytes(body).await.is_err());
+
+        let (sender, receiver) = mpsc::channel(2);
+        sender.send(Ok(Bytes::from_static(b"{}"))).await.unwrap();
+        sender.send(Ok(Bytes::new())).await.unwrap();
+        let body = StreamBody::new(chunks(receiver));
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "{}");
+    }
 }
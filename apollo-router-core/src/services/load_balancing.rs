@@ -0,0 +1,376 @@
+//! Load balancing of the requests of a subgraph over several endpoints, with health checks.
+//!
+//! Requests go round-robin to the healthy endpoints. An endpoint failing `unhealthy_threshold`
+//! fetches in a row is ejected for an `interval`, after which it gets requests again until it
+//! fails once more. With a health check path, every endpoint is also probed at each interval, so
+//! that ejected endpoints come back without failing requests. When every endpoint is ejected,
+//! requests are spread over all of them rather than failed.
+//!
+//! Endpoints resolved to several addresses get one client per address, connecting to it whatever
+//! the host name: requests keep the host name of the endpoint in their URI and `Host` header, and
+//! TLS connections use it as server name.
+
+use super::tower_subgraph_service::{Client, Pool};
+use http::uri::PathAndQuery;
+use http::Uri;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tower::BoxError;
+
+/// Endpoints serving a subgraph, and the checks of their health.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LoadBalancing {
+    /// URLs of the endpoints, used instead of the subgraph URL in the supergraph.
+    pub endpoints: Vec<String>,
+    /// Balance over every address the host of each endpoint resolves to, looked up again at
+    /// each `interval`. Requests are still sent for the host name. Disabled by default.
+    #[serde(default)]
+    pub resolve: bool,
+    /// Path requested with `GET` on each endpoint at every `interval`, answered with a 2xx
+    /// status by healthy endpoints. None by default.
+    #[serde(default)]
+    pub health_check_path: Option<String>,
+    /// Interval of the health checks and lookups, and time for which endpoints are ejected.
+    /// Defaults to 10s.
+    #[serde(with = "humantime_serde", default = "default_interval")]
+    #[schemars(with = "String")]
+    pub interval: Duration,
+    /// Number of failed fetches in a row ejecting an endpoint. Defaults to 3.
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+/// Where a request is sent: the URI of an endpoint, and the address its host resolved to if the
+/// endpoint is resolved by the balancer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Target {
+    pub(super) uri: Uri,
+    address: Option<SocketAddr>,
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "{} ({})", self.uri, address),
+            None => write!(f, "{}", self.uri),
+        }
+    }
+}
+
+struct Endpoint {
+    target: Target,
+    /// Client connecting to the address of the target, if it has one.
+    client: Option<Client>,
+    failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl Endpoint {
+    fn new(target: Target, client: Option<Client>) -> Self {
+        Self {
+            target,
+            client,
+            failures: 0,
+            ejected_until: None,
+        }
+    }
+}
+
+/// The endpoints of a subgraph, with their health.
+pub(super) struct Balancer {
+    service: String,
+    config: LoadBalancing,
+    configured: Vec<Uri>,
+    endpoints: Mutex<Vec<Endpoint>>,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub(super) fn new(service: &str, config: &LoadBalancing) -> Result<Self, BoxError> {
+        let configured = config
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                Uri::from_str(endpoint).map_err(|err| {
+                    BoxError::from(format!("invalid endpoint {}: {}", endpoint, err))
+                })
+            })
+            .collect::<Result<Vec<_>, BoxError>>()?;
+        if configured.is_empty() {
+            return Err("load balancing needs at least one endpoint".into());
+        }
+        Ok(Self {
+            service: service.to_string(),
+            config: config.clone(),
+            endpoints: Mutex::new(
+                configured
+                    .iter()
+                    .map(|uri| {
+                        let target = Target {
+                            uri: uri.clone(),
+                            address: None,
+                        };
+                        Endpoint::new(target, None)
+                    })
+                    .collect(),
+            ),
+            configured,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// The endpoint the next request is sent to, with the client connecting to its address if it
+    /// was resolved.
+    pub(super) fn pick(&self) -> (Target, Option<Client>) {
+        let endpoints = self.endpoints.lock().expect("lock poisoned");
+        let now = Instant::now();
+        let healthy: Vec<_> = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.ejected_until.map_or(true, |until| until <= now))
+            .collect();
+        let candidates = if healthy.is_empty() {
+            endpoints.iter().collect()
+        } else {
+            healthy
+        };
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+        let endpoint = candidates[index];
+        (endpoint.target.clone(), endpoint.client.clone())
+    }
+
+    /// Records the outcome of a request sent to `target`.
+    pub(super) fn report(&self, target: &Target, healthy: bool) {
+        let mut endpoints = self.endpoints.lock().expect("lock poisoned");
+        let endpoint = match endpoints
+            .iter_mut()
+            .find(|endpoint| &endpoint.target == target)
+        {
+            Some(endpoint) => endpoint,
+            None => return,
+        };
+        if healthy {
+            if endpoint.ejected_until.take().is_some() {
+                tracing::info!("endpoint {} of subgraph {} is back", target, self.service);
+            }
+            endpoint.failures = 0;
+            return;
+        }
+        endpoint.failures += 1;
+        if endpoint.failures >= self.config.unhealthy_threshold {
+            if endpoint.ejected_until.is_none() {
+                tracing::warn!(
+                    "endpoint {} of subgraph {} is ejected",
+                    target,
+                    self.service
+                );
+            }
+            endpoint.ejected_until = Some(Instant::now() + self.config.interval);
+        }
+    }
+
+    /// Replaces the endpoints with the addresses their hosts resolve to, keeping the health and
+    /// the clients of those already known. Nothing changes when a lookup fails or finds no
+    /// address.
+    async fn resolve(&self, pool: &Pool) {
+        let mut resolved = Vec::new();
+        for uri in &self.configured {
+            let host = uri.host().unwrap_or_default();
+            let port = uri.port_u16().unwrap_or_else(|| {
+                if uri.scheme_str() == Some("https") {
+                    443
+                } else {
+                    80
+                }
+            });
+            let addresses = match tokio::net::lookup_host((host, port)).await {
+                Ok(addresses) => addresses,
+                Err(err) => {
+                    tracing::warn!(
+                        "could not resolve {} of subgraph {}: {}",
+                        host,
+                        self.service,
+                        err
+                    );
+                    return;
+                }
+            };
+            resolved.extend(addresses.map(|address| Target {
+                uri: uri.clone(),
+                address: Some(address),
+            }));
+        }
+
+        if resolved.is_empty() {
+            return;
+        }
+        let mut endpoints = self.endpoints.lock().expect("lock poisoned");
+        let mut previous: Vec<_> = endpoints.drain(..).collect();
+        for target in resolved {
+            let endpoint = match previous
+                .iter()
+                .position(|endpoint| endpoint.target == target)
+            {
+                Some(index) => previous.swap_remove(index),
+                None => {
+                    let client = target.address.map(|address| pool.client_to(address));
+                    Endpoint::new(target, client)
+                }
+            };
+            endpoints.push(endpoint);
+        }
+    }
+
+    /// Requests the health check path of every endpoint.
+    async fn probe(&self, pool: &Pool, path: &str) {
+        let targets: Vec<_> = self
+            .endpoints
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .map(|endpoint| (endpoint.target.clone(), endpoint.client.clone()))
+            .collect();
+        for (target, client) in targets {
+            let mut parts = target.uri.clone().into_parts();
+            parts.path_and_query = PathAndQuery::from_str(path).ok();
+            let probe = match Uri::from_parts(parts) {
+                Ok(probe) => probe,
+                Err(_) => continue,
+            };
+            let client = client.unwrap_or_else(|| pool.client());
+            let healthy = match tokio::time::timeout(self.config.interval, client.get(probe)).await
+            {
+                Ok(Ok(response)) => response.status().is_success(),
+                _ => false,
+            };
+            self.report(&target, healthy);
+        }
+    }
+}
+
+/// Resolves and probes the endpoints at every interval, for as long as `balancer` is used.
+pub(super) fn spawn_checks(balancer: &Arc<Balancer>, pool: Arc<Pool>) {
+    if !balancer.config.resolve && balancer.config.health_check_path.is_none() {
+        return;
+    }
+    let interval = balancer.config.interval;
+    let balancer: Weak<Balancer> = Arc::downgrade(balancer);
+    tokio::spawn(async move {
+        loop {
+            let balancer = match balancer.upgrade() {
+                Some(balancer) => balancer,
+                None => break,
+            };
+            if balancer.config.resolve {
+                balancer.resolve(&pool).await;
+            }
+            if let Some(path) = &balancer.config.health_check_path {
+                balancer.probe(&pool, path).await;
+            }
+            drop(balancer);
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::tower_subgraph_service::SubgraphClientConfig;
+
+    fn balancer(config: serde_json::Value) -> Balancer {
+        Balancer::new("accounts", &serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    fn target(uri: &'static str) -> Target {
+        Target {
+            uri: Uri::from_static(uri),
+            address: None,
+        }
+    }
+
+    fn pick(balancer: &Balancer) -> Target {
+        balancer.pick().0
+    }
+
+    #[test]
+    fn failing_endpoints_are_ejected() {
+        let balancer = balancer(serde_json::json!({
+            "endpoints": ["http://a:4001/graphql", "http://b:4001/graphql"],
+            "unhealthy_threshold": 2,
+            "interval": "50ms"
+        }));
+        let a = target("http://a:4001/graphql");
+        let b = target("http://b:4001/graphql");
+        assert_eq!(pick(&balancer), a);
+        assert_eq!(pick(&balancer), b);
+
+        balancer.report(&a, false);
+        assert_eq!(pick(&balancer), a);
+        balancer.report(&a, false);
+        for _ in 0..4 {
+            assert_eq!(pick(&balancer), b);
+        }
+
+        // Once the interval is over, the endpoint gets requests again.
+        std::thread::sleep(Duration::from_millis(60));
+        let picked: Vec<_> = (0..2).map(|_| pick(&balancer)).collect();
+        assert!(picked.contains(&a));
+        balancer.report(&a, true);
+        balancer.report(&a, false);
+        assert!((0..2).any(|_| pick(&balancer) == a));
+    }
+
+    #[test]
+    fn requests_are_spread_when_every_endpoint_is_ejected() {
+        let balancer = balancer(serde_json::json!({
+            "endpoints": ["http://a:4001/graphql", "http://b:4001/graphql"],
+            "unhealthy_threshold": 1
+        }));
+        let a = target("http://a:4001/graphql");
+        let b = target("http://b:4001/graphql");
+        balancer.report(&a, false);
+        balancer.report(&b, false);
+
+        let picked: Vec<_> = (0..2).map(|_| pick(&balancer)).collect();
+        assert!(picked.contains(&a) && picked.contains(&b));
+    }
+
+    #[tokio::test]
+    async fn hosts_are_resolved_to_their_addresses() {
+        let balancer = balancer(serde_json::json!({
+            "endpoints": ["http://localhost:4001/graphql"],
+            "resolve": true
+        }));
+        let pool = Pool::new(SubgraphClientConfig::default(), None);
+        balancer.resolve(&pool).await;
+
+        let mut expected: Vec<_> = tokio::net::lookup_host(("localhost", 4001))
+            .await
+            .unwrap()
+            .collect();
+        let mut resolved = Vec::new();
+        for endpoint in balancer.endpoints.lock().unwrap().iter() {
+            // Requests are still sent for the host name, to the address it resolved to.
+            assert_eq!(endpoint.target.uri, "http://localhost:4001/graphql");
+            assert!(endpoint.client.is_some());
+            resolved.extend(endpoint.target.address);
+        }
+        expected.sort();
+        resolved.sort();
+        assert_eq!(resolved, expected);
+    }
+}
@@ -13,11 +13,13 @@ use http_compat::IntoHeaderName;
 use http_compat::IntoHeaderValue;
 use moka::sync::Cache;
 use multimap::MultiMap;
+use serde::ser::SerializeStruct;
 use serde::{Deserialize, Serialize};
 use serde_json_bytes::ByteString;
 use static_assertions::assert_impl_all;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::Infallible;
+use std::fmt;
 use std::ops::ControlFlow;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -30,11 +32,35 @@ use tracing::Span;
 
 pub mod checkpoint;
 mod execution_service;
+mod grpc_subgraph_service;
 pub mod http_compat;
+mod load_balancing;
+mod rest_subgraph_service;
 mod router_service;
+mod subgraph_auth;
+mod subgraph_routing;
+mod subscription_callback;
+mod subscription_service;
 mod tower_subgraph_service;
 use crate::instrument::InstrumentLayer;
-pub use tower_subgraph_service::TowerSubgraphService;
+pub use grpc_subgraph_service::{
+    grpc_deadline, GrpcSubgraphConfig, GrpcSubgraphService, GRPC_DEADLINE_CONTEXT_KEY,
+};
+pub use load_balancing::LoadBalancing;
+pub use rest_subgraph_service::{
+    RestEndpoint, RestMethod, RestSubgraphConfig, RestSubgraphService,
+};
+pub use subgraph_auth::{SubgraphAuth, SubgraphAuthConfig};
+pub use subgraph_routing::{RoutedTo, SubgraphRouting, SubgraphTarget};
+pub use subscription_callback::{handle_callback, CallbackMessage};
+pub use subscription_service::{
+    ProtocolMessage, SubscriptionConnection, SubscriptionEvents, Subscriptions,
+    GRAPHQL_TRANSPORT_WS,
+};
+pub(crate) use subscription_service::{SubscriptionCallback, SubscriptionService};
+pub use tower_subgraph_service::{
+    PoolUsage, SubgraphClientConfig, SubgraphTls, SubgraphTlsConfig, TowerSubgraphService,
+};
 
 pub const DEFAULT_BUFFER_SIZE: usize = 20_000;
 
@@ -332,6 +358,51 @@ impl RouterResponse {
     }
 }
 
+/// Headers that may carry credentials, whose values never show in logs or snapshots.
+const REDACTED_HEADERS: [HeaderName; 4] = [
+    http::header::AUTHORIZATION,
+    http::header::COOKIE,
+    http::header::PROXY_AUTHORIZATION,
+    http::header::SET_COOKIE,
+];
+
+/// Headers in a stable order, with the values of [`REDACTED_HEADERS`] hidden.
+fn redacted_headers(headers: &http::HeaderMap) -> BTreeMap<&str, Vec<&str>> {
+    let mut redacted: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, value) in headers {
+        let value = if REDACTED_HEADERS.contains(name) {
+            "<redacted>"
+        } else {
+            value.to_str().unwrap_or("<binary>")
+        };
+        redacted.entry(name.as_str()).or_default().push(value);
+    }
+    redacted
+}
+
+/// Serializes the status, headers and body, so that responses can be compared with snapshots.
+///
+/// The context is left out, as plugins may keep anything in it.
+impl Serialize for RouterResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RouterResponse", 3)?;
+        state.serialize_field("status", &self.response.status().as_u16())?;
+        state.serialize_field("headers", &redacted_headers(self.response.headers()))?;
+        state.serialize_field("body", self.response.body())?;
+        state.end()
+    }
+}
+
+impl fmt::Debug for RouterResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RouterResponse")
+            .field("status", &self.response.status())
+            .field("headers", &redacted_headers(self.response.headers()))
+            .field("body", self.response.body())
+            .finish_non_exhaustive()
+    }
+}
+
 assert_impl_all!(QueryPlannerRequest: Send);
 /// [`Context`] for the request.
 pub struct QueryPlannerRequest {
@@ -378,6 +449,21 @@ impl QueryPlannerResponse {
     }
 }
 
+/// Serializes the query plan alone, like [`RouterResponse`] leaves its context out.
+impl Serialize for QueryPlannerResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.query_plan.serialize(serializer)
+    }
+}
+
+impl fmt::Debug for QueryPlannerResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryPlannerResponse")
+            .field("query_plan", &self.query_plan)
+            .finish_non_exhaustive()
+    }
+}
+
 assert_impl_all!(SubgraphRequest: Send);
 /// [`Context`], [`OperationKind`] and [`http_compat::Request<Request>`] for the request.
 pub struct SubgraphRequest {
@@ -654,6 +740,73 @@ impl ExecutionResponse {
     }
 }
 
+assert_impl_all!(SubscriptionRequest: Send);
+/// [`Context`] and event of a subscription, for the request.
+pub struct SubscriptionRequest {
+    /// Original request to the Router.
+    pub originating_request: Arc<http_compat::Request<Request>>,
+
+    /// Event sent by the subgraph, shaped to the subscription.
+    pub event: Response,
+
+    pub context: Context,
+}
+
+#[buildstructor::builder]
+impl SubscriptionRequest {
+    /// This is the constructor (or builder) to use when constructing a real SubscriptionRequest.
+    ///
+    /// Required parameters are required in non-testing code to create a SubscriptionRequest.
+    pub fn new(
+        originating_request: Arc<http_compat::Request<Request>>,
+        event: Response,
+        context: Context,
+    ) -> SubscriptionRequest {
+        Self {
+            originating_request,
+            event,
+            context,
+        }
+    }
+
+    /// This is the constructor (or builder) to use when constructing a "fake" SubscriptionRequest.
+    ///
+    /// This does not enforce the provision of the data that is required for a fully functional
+    /// SubscriptionRequest. It's usually enough for testing, when a fully constructed
+    /// SubscriptionRequest is difficult to construct and not required for the purposes of the test.
+    pub fn fake_new(
+        originating_request: Option<Arc<http_compat::Request<Request>>>,
+        event: Option<Response>,
+        context: Option<Context>,
+    ) -> SubscriptionRequest {
+        SubscriptionRequest::new(
+            originating_request.unwrap_or_else(|| Arc::new(http_compat::Request::mock())),
+            event.unwrap_or_else(|| Response::builder().build()),
+            context.unwrap_or_default(),
+        )
+    }
+}
+
+assert_impl_all!(SubscriptionResponse: Send);
+/// [`Context`] and event of a subscription, for the response.
+#[derive(Clone, Debug)]
+pub struct SubscriptionResponse {
+    /// Event sent to the client.
+    pub event: Response,
+
+    pub context: Context,
+}
+
+#[buildstructor::builder]
+impl SubscriptionResponse {
+    /// This is the constructor (or builder) to use when constructing a real SubscriptionResponse.
+    ///
+    /// Required parameters are required in non-testing code to create a SubscriptionResponse.
+    pub fn new(event: Response, context: Context) -> SubscriptionResponse {
+        Self { event, context }
+    }
+}
+
 impl AsRef<Request> for http_compat::Request<Request> {
     fn as_ref(&self) -> &Request {
         self.body()
@@ -847,4 +1000,28 @@ mod test {
             )
         );
     }
+
+    #[test]
+    fn router_response_serialization() {
+        let response = RouterResponse::builder()
+            .header("content-type", "application/json")
+            .header("set-cookie", "session=secret")
+            .context(Context::new())
+            .data(json!({ "me": { "name": "Ada" } }))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({
+                "status": 200,
+                "headers": {
+                    "content-type": ["application/json"],
+                    "set-cookie": ["<redacted>"],
+                },
+                "body": { "data": { "me": { "name": "Ada" } } },
+            })
+        );
+        assert!(!format!("{:?}", response).contains("secret"));
+    }
 }
@@ -0,0 +1,974 @@
+//! Fetcher for subgraphs served by REST APIs.
+//!
+//! Fetches are answered by calling the endpoint mapped to each of their root fields, or to the type
+//! of each of their entities, and by selecting the fields of the operation from the JSON returned.
+//! Endpoints answering `404 Not Found` resolve to `null`. The root fields of queries are resolved
+//! concurrently, and those of mutations one after the other, in the order of the operation.
+//!
+//! Entity endpoints with a `key` are called once per fetch for all the entities of their type,
+//! rather than once per entity.
+
+use super::tower_subgraph_service::{http_error, read_body, ClientTls, Pool};
+use super::{SubgraphClientConfig, SubgraphTls};
+use crate::prelude::*;
+use crate::{Object, Path, Value};
+use apollo_parser::ast;
+use futures::future::{join_all, BoxFuture};
+use http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, HOST};
+use http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::Poll;
+use tower::BoxError;
+use tracing::Instrument;
+
+/// Mapping of the fetches of a subgraph to the endpoints of its REST API.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RestSubgraphConfig {
+    /// Endpoint answering each root field, by `Type.field` name, such as `Query.topProducts`.
+    #[serde(default)]
+    pub fields: HashMap<String, RestEndpoint>,
+    /// Endpoint answering each entity type, by type name. It is called for every representation,
+    /// with the fields of the representation as parameters, or once for all of them when its
+    /// `key` is set.
+    #[serde(default)]
+    pub entities: HashMap<String, RestEndpoint>,
+}
+
+/// A call to a REST endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RestEndpoint {
+    /// Defaults to `GET`.
+    #[serde(default)]
+    pub method: RestMethod,
+    /// Path of the endpoint, appended to the URL of the subgraph, such as `/products/{upc}`.
+    /// Each `{name}` placeholder is replaced with the parameter of that name, URL-encoded. Lists
+    /// are joined with commas.
+    pub path: String,
+    /// JSON body of the call. Strings made of a single placeholder are replaced with the JSON
+    /// value of the parameter, and placeholders within longer strings with its text.
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+    /// JSON pointer of the result in the response body, such as `/data/items`. Defaults to the
+    /// whole body.
+    #[serde(default)]
+    pub result: Option<String>,
+    /// Property of the result holding each GraphQL field, by field name, for the fields named
+    /// differently in the API.
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+    /// For entity endpoints, field of the representations identifying the entities, such as
+    /// `upc`. When set, the entities of a type are fetched in a single call, the parameter of that
+    /// name being the list of their keys, and are matched by that field in the list returned.
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RestMethod {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+impl Default for RestMethod {
+    fn default() -> Self {
+        RestMethod::Get
+    }
+}
+
+impl From<RestMethod> for Method {
+    fn from(method: RestMethod) -> Self {
+        match method {
+            RestMethod::Get => Method::GET,
+            RestMethod::Post => Method::POST,
+            RestMethod::Put => Method::PUT,
+            RestMethod::Patch => Method::PATCH,
+            RestMethod::Delete => Method::DELETE,
+        }
+    }
+}
+
+/// Client for subgraphs served by REST APIs, a peer of [`TowerSubgraphService`](super::TowerSubgraphService).
+#[derive(Clone)]
+pub struct RestSubgraphService {
+    pool: Arc<Pool>,
+    service: Arc<String>,
+    config: Arc<RestSubgraphConfig>,
+    max_response_bytes: Option<usize>,
+}
+
+impl RestSubgraphService {
+    pub fn new(service: impl Into<String>, config: RestSubgraphConfig) -> Self {
+        Self {
+            pool: Arc::new(Pool::new(SubgraphClientConfig::default(), None)),
+            service: Arc::new(service.into()),
+            config: Arc::new(config),
+            max_response_bytes: None,
+        }
+    }
+
+    pub fn with_client_config(mut self, config: SubgraphClientConfig) -> Self {
+        self.pool = Arc::new(Pool::new(config, None));
+        self
+    }
+
+    /// Connects to the API with `tls`, reading its files right away.
+    pub fn with_tls(mut self, tls: &SubgraphTls) -> Result<Self, BoxError> {
+        self.pool = Arc::new(Pool::new(
+            self.pool.config.clone(),
+            Some(ClientTls::new(tls)?),
+        ));
+        Ok(self)
+    }
+
+    /// Fails the calls whose response body is larger than `max_response_bytes`.
+    pub fn with_max_response_bytes(mut self, max_response_bytes: Option<usize>) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    async fn execute(
+        &self,
+        base: &Uri,
+        headers: &HeaderMap,
+        request: &graphql::Request,
+    ) -> graphql::Response {
+        let (root_type, selections) = match parse_operation(request) {
+            Ok(operation) => operation,
+            Err(reason) => {
+                return graphql::Response::builder()
+                    .errors(vec![graphql::FetchError::ValidationPlanningError {
+                        reason,
+                    }
+                    .to_graphql_error(None)])
+                    .build()
+            }
+        };
+
+        let fields = fields_of(&selections, Some(&root_type));
+        let results = if root_type == "Mutation" {
+            let mut results = Vec::with_capacity(fields.len());
+            for field in &fields {
+                results.push(self.root_field(&root_type, field, base, headers).await);
+            }
+            results
+        } else {
+            join_all(
+                fields
+                    .iter()
+                    .map(|field| self.root_field(&root_type, field, base, headers)),
+            )
+            .await
+        };
+
+        let mut data = Object::new();
+        let mut errors = Vec::new();
+        for (field, (value, field_errors)) in fields.iter().zip(results) {
+            data.insert(field.response_key(), value);
+            errors.extend(field_errors);
+        }
+        graphql::Response::builder()
+            .data(Value::Object(data))
+            .errors(errors)
+            .build()
+    }
+
+    async fn root_field(
+        &self,
+        root_type: &str,
+        field: &Field,
+        base: &Uri,
+        headers: &HeaderMap,
+    ) -> (Value, Vec<graphql::Error>) {
+        let path = Path::from(field.response_key());
+        match field.name.as_str() {
+            "__typename" => (Value::from(root_type), Vec::new()),
+            "_entities" => {
+                let representations = match field.arguments.get("representations") {
+                    Some(Value::Array(representations)) => representations.as_slice(),
+                    _ => &[],
+                };
+                let results = self.entities(representations, field, base, headers).await;
+
+                let mut entities = Vec::with_capacity(results.len());
+                let mut errors = Vec::new();
+                for (index, result) in results.into_iter().enumerate() {
+                    match result {
+                        Ok(entity) => entities.push(entity),
+                        Err(err) => {
+                            entities.push(Value::Null);
+                            errors.push(err.to_graphql_error(Some(Path::from(format!(
+                                "_entities/{}",
+                                index
+                            )))));
+                        }
+                    }
+                }
+                (Value::Array(entities), errors)
+            }
+            name => {
+                let name = format!("{}.{}", root_type, name);
+                let result = match self.config.fields.get(&name) {
+                    Some(endpoint) => self
+                        .call(endpoint, &field.arguments, base, headers)
+                        .await
+                        .map(|result| project(&result, &field.selections, None, &endpoint.fields)),
+                    None => Err(graphql::FetchError::SubrequestUnmappedField {
+                        service: self.service.to_string(),
+                        field: name,
+                    }),
+                };
+                match result {
+                    Ok(value) => (value, Vec::new()),
+                    Err(err) => (Value::Null, vec![err.to_graphql_error(Some(path))]),
+                }
+            }
+        }
+    }
+
+    /// Fetches the entities of `representations`, with a single call for all those of a type
+    /// with a batch endpoint.
+    async fn entities(
+        &self,
+        representations: &[Value],
+        field: &Field,
+        base: &Uri,
+        headers: &HeaderMap,
+    ) -> Vec<Result<Value, graphql::FetchError>> {
+        let mut batches: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut singles = Vec::new();
+        for (index, representation) in representations.iter().enumerate() {
+            let typename = typename_of(representation);
+            match self.config.entities.get(typename) {
+                Some(endpoint) if endpoint.key.is_some() => {
+                    batches.entry(typename).or_default().push(index)
+                }
+                _ => singles.push(index),
+            }
+        }
+        let batches: Vec<(&str, Vec<usize>)> = batches.into_iter().collect();
+
+        let (single_results, batch_results) = futures::future::join(
+            join_all(
+                singles
+                    .iter()
+                    .map(|index| self.entity(&representations[*index], field, base, headers)),
+            ),
+            join_all(batches.iter().map(|(typename, indices)| {
+                let representations = indices
+                    .iter()
+                    .map(|index| &representations[*index])
+                    .collect();
+                self.entity_batch(typename, representations, field, base, headers)
+            })),
+        )
+        .await;
+
+        let mut results = vec![Ok(Value::Null); representations.len()];
+        for (index, result) in singles.into_iter().zip(single_results) {
+            results[index] = result;
+        }
+        for ((_, indices), batch) in batches.into_iter().zip(batch_results) {
+            match batch {
+                Ok(entities) => {
+                    for (index, entity) in indices.into_iter().zip(entities) {
+                        results[index] = Ok(entity);
+                    }
+                }
+                Err(err) => {
+                    for index in indices {
+                        results[index] = Err(err.clone());
+                    }
+                }
+            }
+        }
+        results
+    }
+
+    /// Fetches the entities of `representations`, all of type `typename`, with one call to its
+    /// batch endpoint.
+    async fn entity_batch(
+        &self,
+        typename: &str,
+        representations: Vec<&Value>,
+        field: &Field,
+        base: &Uri,
+        headers: &HeaderMap,
+    ) -> Result<Vec<Value>, graphql::FetchError> {
+        let endpoint = &self.config.entities[typename];
+        let key = endpoint
+            .key
+            .as_deref()
+            .expect("only endpoints with a key are batched");
+        let keys: Vec<Value> = representations
+            .iter()
+            .map(|representation| representation.get(key).cloned().unwrap_or_default())
+            .collect();
+        let mut unique_keys = Vec::with_capacity(keys.len());
+        for key in &keys {
+            if !unique_keys.contains(key) {
+                unique_keys.push(key.clone());
+            }
+        }
+        let mut parameters = Object::new();
+        parameters.insert(key, Value::Array(unique_keys));
+        let result = self.call(endpoint, &parameters, base, headers).await?;
+
+        let property = endpoint.fields.get(key).map(String::as_str).unwrap_or(key);
+        let entities = match &result {
+            Value::Array(entities) => entities.as_slice(),
+            _ => &[],
+        };
+        Ok(keys
+            .iter()
+            .map(|key| {
+                entities
+                    .iter()
+                    .find(|entity| entity.get(property) == Some(key))
+                    .map(|entity| {
+                        project(entity, &field.selections, Some(typename), &endpoint.fields)
+                    })
+                    .unwrap_or_default()
+            })
+            .collect())
+    }
+
+    async fn entity(
+        &self,
+        representation: &Value,
+        field: &Field,
+        base: &Uri,
+        headers: &HeaderMap,
+    ) -> Result<Value, graphql::FetchError> {
+        let typename = typename_of(representation);
+        let endpoint = self.config.entities.get(typename).ok_or_else(|| {
+            graphql::FetchError::SubrequestUnmappedField {
+                service: self.service.to_string(),
+                field: typename.to_string(),
+            }
+        })?;
+        let parameters = representation.as_object().cloned().unwrap_or_default();
+        let result = self.call(endpoint, &parameters, base, headers).await?;
+        Ok(project(
+            &result,
+            &field.selections,
+            Some(typename),
+            &endpoint.fields,
+        ))
+    }
+
+    /// Calls `endpoint`, returning its result.
+    async fn call(
+        &self,
+        endpoint: &RestEndpoint,
+        parameters: &Object,
+        base: &Uri,
+        headers: &HeaderMap,
+    ) -> Result<Value, graphql::FetchError> {
+        let method = Method::from(endpoint.method);
+        let path = expand(&endpoint.path, parameters, true);
+        let uri: Uri = format!("{}{}", base.to_string().trim_end_matches('/'), path)
+            .parse()
+            .map_err(|err| http_error(&self.service, err))?;
+
+        let mut request = http::Request::builder().method(method.clone()).uri(uri);
+        for (name, value) in headers {
+            if name != HOST && name != CONTENT_TYPE && name != CONTENT_LENGTH && name != ACCEPT {
+                request = request.header(name, value);
+            }
+        }
+        let body = match &endpoint.body {
+            Some(body) => {
+                request =
+                    request.header(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                hyper::Body::from(
+                    serde_json::to_vec(&fill(body, parameters))
+                        .expect("JSON serialization should not fail"),
+                )
+            }
+            None => hyper::Body::empty(),
+        };
+        let request = request
+            .header(ACCEPT, HeaderValue::from_static("application/json"))
+            .body(body)
+            .map_err(|err| http_error(&self.service, err))?;
+
+        let response = self
+            .pool
+            .client()
+            .request(request)
+            .instrument(tracing::debug_span!("rest_call", %method, path = %path))
+            .await
+            .map_err(|err| http_error(&self.service, err))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(Value::Null),
+            status if !status.is_success() => {
+                return Err(graphql::FetchError::SubrequestHttpError {
+                    service: self.service.to_string(),
+                    reason: format!("{} {} answered with {}", method, path, status),
+                })
+            }
+            _ => {}
+        }
+
+        let body = read_body(response.into_body(), self.max_response_bytes, &self.service).await?;
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        let value: Value = serde_json::from_slice(&body).map_err(|err| {
+            graphql::FetchError::SubrequestMalformedResponse {
+                service: self.service.to_string(),
+                reason: err.to_string(),
+            }
+        })?;
+        Ok(match &endpoint.result {
+            Some(pointer) => select_pointer(value, pointer),
+            None => value,
+        })
+    }
+}
+
+impl tower::Service<graphql::SubgraphRequest> for RestSubgraphService {
+    type Response = graphql::SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: graphql::SubgraphRequest) -> Self::Future {
+        let graphql::SubgraphRequest {
+            subgraph_request,
+            context,
+            ..
+        } = request;
+        let service = self.clone();
+
+        Box::pin(async move {
+            let (parts, body) = subgraph_request.into_parts();
+            let response = service.execute(&parts.uri, &parts.headers, &body).await;
+            Ok(graphql::SubgraphResponse::new_from_response(
+                http::Response::builder()
+                    .body(response)
+                    .expect("no argument can fail to parse or converted to the internal representation here; qed")
+                    .into(),
+                context,
+            ))
+        })
+    }
+}
+
+/// A field of the operation, with its arguments resolved against the variables.
+#[derive(Debug)]
+struct Field {
+    alias: Option<String>,
+    name: String,
+    arguments: Object,
+    selections: Vec<Selection>,
+}
+
+impl Field {
+    fn response_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug)]
+enum Selection {
+    Field(Field),
+    /// An inline fragment, or a fragment spread replaced with the fragment it names.
+    Fragment {
+        type_condition: Option<String>,
+        selections: Vec<Selection>,
+    },
+}
+
+/// The root type and the selections of the operation of `request`.
+///
+/// Syntax trees are not `Send`, so operations are turned into [`Selection`]s before any call.
+fn parse_operation(request: &graphql::Request) -> Result<(String, Vec<Selection>), String> {
+    let query = request.query.as_deref().unwrap_or_default();
+    let document = apollo_parser::Parser::new(query).parse().document();
+
+    let mut fragments = HashMap::new();
+    let mut operation = None;
+    for definition in document.definitions() {
+        match definition {
+            ast::Definition::FragmentDefinition(fragment) => {
+                if let Some(name) = fragment.fragment_name().and_then(|name| name.name()) {
+                    fragments.insert(name.text().to_string(), fragment);
+                }
+            }
+            ast::Definition::OperationDefinition(definition) => {
+                let name = definition.name().map(|name| name.text().to_string());
+                if request.operation_name.is_none() || name == request.operation_name {
+                    operation = Some(definition);
+                }
+            }
+            _ => {}
+        }
+    }
+    let operation = operation.ok_or_else(|| "the operation was not found".to_string())?;
+
+    let root_type = match operation.operation_type() {
+        Some(operation_type) if operation_type.mutation_token().is_some() => "Mutation",
+        Some(operation_type) if operation_type.subscription_token().is_some() => {
+            return Err("subscriptions are not supported by REST subgraphs".to_string())
+        }
+        _ => "Query",
+    };
+    let selections = selections(operation.selection_set(), &fragments, &request.variables);
+    Ok((root_type.to_string(), selections))
+}
+
+fn selections(
+    selection_set: Option<ast::SelectionSet>,
+    fragments: &HashMap<String, ast::FragmentDefinition>,
+    variables: &Object,
+) -> Vec<Selection> {
+    let selection_set = match selection_set {
+        Some(selection_set) => selection_set,
+        None => return Vec::new(),
+    };
+    selection_set
+        .selections()
+        .filter_map(|selection| match selection {
+            ast::Selection::Field(field) => {
+                let arguments = field
+                    .arguments()
+                    .into_iter()
+                    .flat_map(|arguments| arguments.arguments())
+                    .filter_map(|argument| {
+                        let name = argument.name()?.text().to_string();
+                        let value = match argument.value()? {
+                            ast::Value::Variable(variable) => variables
+                                .get(variable.name()?.text().as_str())
+                                .cloned()
+                                .unwrap_or_default(),
+                            value => crate::spec::parse_value(&value).unwrap_or_default(),
+                        };
+                        Some((name.into(), value))
+                    })
+                    .collect();
+                Some(Selection::Field(Field {
+                    alias: field
+                        .alias()
+                        .and_then(|alias| alias.name())
+                        .map(|name| name.text().to_string()),
+                    name: field.name()?.text().to_string(),
+                    arguments,
+                    selections: selections(field.selection_set(), fragments, variables),
+                }))
+            }
+            ast::Selection::InlineFragment(fragment) => Some(Selection::Fragment {
+                type_condition: type_condition(fragment.type_condition()),
+                selections: selections(fragment.selection_set(), fragments, variables),
+            }),
+            ast::Selection::FragmentSpread(spread) => {
+                let name = spread.fragment_name()?.name()?.text().to_string();
+                let fragment = fragments.get(&name)?;
+                Some(Selection::Fragment {
+                    type_condition: type_condition(fragment.type_condition()),
+                    selections: selections(fragment.selection_set(), fragments, variables),
+                })
+            }
+        })
+        .collect()
+}
+
+fn type_condition(condition: Option<ast::TypeCondition>) -> Option<String> {
+    condition
+        .and_then(|condition| condition.named_type())
+        .and_then(|named_type| named_type.name())
+        .map(|name| name.text().to_string())
+}
+
+/// The fields of `selections` applying to an object of type `typename`, through fragments.
+///
+/// When the type is not known, the fields of every fragment apply.
+fn fields_of<'a>(selections: &'a [Selection], typename: Option<&str>) -> Vec<&'a Field> {
+    let mut fields = Vec::new();
+    for selection in selections {
+        match selection {
+            Selection::Field(field) => fields.push(field),
+            Selection::Fragment {
+                type_condition,
+                selections,
+            } => {
+                let applies = match (type_condition, typename) {
+                    (Some(condition), Some(typename)) => condition == typename,
+                    _ => true,
+                };
+                if applies {
+                    fields.extend(fields_of(selections, typename));
+                }
+            }
+        }
+    }
+    fields
+}
+
+/// Selects the fields of `selections` from `value`, taking them from the properties named in
+/// `renames`.
+fn project(
+    value: &Value,
+    selections: &[Selection],
+    typename: Option<&str>,
+    renames: &HashMap<String, String>,
+) -> Value {
+    match value {
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| project(value, selections, typename, renames))
+                .collect(),
+        ),
+        Value::Object(object) if !selections.is_empty() => {
+            let typename = object
+                .get("__typename")
+                .and_then(|typename| typename.as_str())
+                .or(typename);
+            let mut projected = Object::new();
+            for field in fields_of(selections, typename) {
+                let value = if field.name == "__typename" {
+                    typename.map(Value::from).unwrap_or_default()
+                } else {
+                    let property = renames.get(&field.name).unwrap_or(&field.name);
+                    match object.get(property.as_str()) {
+                        Some(value) => project(value, &field.selections, None, renames),
+                        None => Value::Null,
+                    }
+                };
+                projected.insert(field.response_key().to_string(), value);
+            }
+            Value::Object(projected)
+        }
+        value => value.clone(),
+    }
+}
+
+fn typename_of(representation: &Value) -> &str {
+    representation
+        .get("__typename")
+        .and_then(|typename| typename.as_str())
+        .unwrap_or_default()
+}
+
+/// Replaces the `{name}` placeholders of `template` with the parameters of that name.
+fn expand(template: &str, parameters: &Object, url_encoded: bool) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        expanded.push_str(&rest[..start]);
+        let text = match parameters.get(&rest[start + 1..end]) {
+            Some(Value::Array(values)) => values
+                .iter()
+                .map(|value| text(Some(value), url_encoded))
+                .collect::<Vec<_>>()
+                .join(","),
+            value => text(value, url_encoded),
+        };
+        expanded.push_str(&text);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The text of a parameter within a longer string.
+fn text(value: Option<&Value>, url_encoded: bool) -> String {
+    let text = match value {
+        Some(Value::String(text)) => text.as_str().to_string(),
+        Some(Value::Null) | None => String::new(),
+        Some(value) => serde_json::to_string(value).expect("JSON serialization should not fail"),
+    };
+    if url_encoded {
+        urlencoding::encode(&text).into_owned()
+    } else {
+        text
+    }
+}
+
+/// The body template with its placeholders replaced.
+fn fill(template: &serde_json::Value, parameters: &Object) -> Value {
+    match template {
+        serde_json::Value::String(text) => {
+            let name = text
+                .strip_prefix('{')
+                .and_then(|name| name.strip_suffix('}'))
+                .filter(|name| !name.contains(|c| c == '{' || c == '}'));
+            match name {
+                Some(name) => parameters.get(name).cloned().unwrap_or_default(),
+                None => Value::from(expand(text, parameters, false)),
+            }
+        }
+        serde_json::Value::Array(values) => {
+            Value::Array(values.iter().map(|value| fill(value, parameters)).collect())
+        }
+        serde_json::Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| (key.as_str().into(), fill(value, parameters)))
+                .collect(),
+        ),
+        value => value.clone().into(),
+    }
+}
+
+/// The value at the JSON `pointer` of `value`, or `null` if there is none.
+fn select_pointer(mut value: Value, pointer: &str) -> Value {
+    for token in pointer.split('/').skip(1) {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        value = match value {
+            Value::Object(mut object) => object.remove(token.as_str()).unwrap_or_default(),
+            Value::Array(mut values) => match token.parse::<usize>() {
+                Ok(index) if index < values.len() => values.swap_remove(index),
+                _ => Value::Null,
+            },
+            _ => Value::Null,
+        };
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json_bytes::json;
+
+    fn request(query: &str, variables: serde_json::Value) -> graphql::Request {
+        graphql::Request::builder()
+            .query(Some(query.to_string()))
+            .variables(Arc::new(
+                serde_json_bytes::to_value(variables)
+                    .unwrap()
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ))
+            .build()
+    }
+
+    #[test]
+    fn placeholders_are_replaced_with_parameters() {
+        let parameters = json!({ "upc": "a b", "first": 2, "name": null });
+        let parameters = parameters.as_object().unwrap();
+
+        assert_eq!(
+            expand(
+                "/products/{upc}?first={first}&name={name}",
+                parameters,
+                true
+            ),
+            "/products/a%20b?first=2&name="
+        );
+        assert_eq!(
+            expand(
+                "/products?upc={upcs}",
+                json!({ "upcs": ["1", "a b"] }).as_object().unwrap(),
+                true
+            ),
+            "/products?upc=1,a%20b"
+        );
+        assert_eq!(
+            fill(
+                &serde_json::json!({ "upc": "{upc}", "label": "product {upc}", "first": "{first}" }),
+                parameters
+            ),
+            json!({ "upc": "a b", "label": "product a b", "first": 2 })
+        );
+    }
+
+    #[test]
+    fn results_are_projected_on_the_selections() {
+        let request = request(
+            "query($first:Int){all:topProducts(first:$first){__typename ...P}} fragment P on Product{upc price:cost}",
+            serde_json::json!({ "first": 2 }),
+        );
+        let (root_type, selections) = parse_operation(&request).unwrap();
+        assert_eq!(root_type, "Query");
+        let fields = fields_of(&selections, Some("Query"));
+        assert_eq!(fields[0].response_key(), "all");
+        assert_eq!(
+            fields[0].arguments,
+            *json!({ "first": 2 }).as_object().unwrap()
+        );
+
+        let renames = [("cost".to_string(), "price_cents".to_string())]
+            .into_iter()
+            .collect();
+        let result = select_pointer(
+            json!({ "items": [{ "upc": "1", "price_cents": 10, "stock": 3 }] }),
+            "/items",
+        );
+        assert_eq!(
+            project(&result, &fields[0].selections, Some("Product"), &renames),
+            json!([{ "__typename": "Product", "upc": "1", "price": 10 }])
+        );
+    }
+
+    #[tokio::test]
+    async fn entities_are_fetched_from_their_endpoints() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = axum::Router::new().route(
+            "/products/:upc",
+            axum::routing::get(
+                |axum::extract::Path(upc): axum::extract::Path<String>| async move {
+                    if upc == "missing" {
+                        return Err(StatusCode::NOT_FOUND);
+                    }
+                    Ok(axum::Json(
+                        serde_json::json!({ "upc": upc, "name": format!("Product {}", upc) }),
+                    ))
+                },
+            ),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let config: RestSubgraphConfig = serde_json::from_value(serde_json::json!({
+            "entities": { "Product": { "path": "/products/{upc}" } }
+        }))
+        .unwrap();
+        let service = RestSubgraphService::new("products", config);
+        let request = request(
+            "query($representations:[_Any!]!){_entities(representations:$representations){...on Product{name}}}",
+            serde_json::json!({ "representations": [
+                { "__typename": "Product", "upc": "1" },
+                { "__typename": "Product", "upc": "missing" },
+                { "__typename": "Review", "id": "1" },
+            ] }),
+        );
+        let base: Uri = format!("http://{}/", address).parse().unwrap();
+        let response = service.execute(&base, &HeaderMap::new(), &request).await;
+
+        assert_eq!(
+            response.data,
+            Some(json!({ "_entities": [{ "name": "Product 1" }, null, null] }))
+        );
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].path, Some(Path::from("_entities/2")));
+    }
+
+    #[tokio::test]
+    async fn mutation_fields_are_resolved_in_order() {
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (slow, fast) = (calls.clone(), calls.clone());
+        let app = axum::Router::new()
+            .route(
+                "/slow",
+                axum::routing::post(move || async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    slow.lock().unwrap().push("slow");
+                    axum::Json(serde_json::json!(true))
+                }),
+            )
+            .route(
+                "/fast",
+                axum::routing::post(move || async move {
+                    fast.lock().unwrap().push("fast");
+                    axum::Json(serde_json::json!(true))
+                }),
+            );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let config: RestSubgraphConfig = serde_json::from_value(serde_json::json!({
+            "fields": {
+                "Mutation.slow": { "method": "POST", "path": "/slow" },
+                "Mutation.fast": { "method": "POST", "path": "/fast" },
+            }
+        }))
+        .unwrap();
+        let service = RestSubgraphService::new("products", config);
+        let request = request("mutation{slow fast}", serde_json::json!({}));
+        let base: Uri = format!("http://{}/", address).parse().unwrap();
+        let response = service.execute(&base, &HeaderMap::new(), &request).await;
+
+        assert_eq!(response.data, Some(json!({ "slow": true, "fast": true })));
+        assert_eq!(*calls.lock().unwrap(), vec!["slow", "fast"]);
+    }
+
+    #[tokio::test]
+    async fn entities_with_a_key_are_fetched_in_a_single_call() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let counted = calls.clone();
+        let app = axum::Router::new().route(
+            "/products",
+            axum::routing::get(
+                move |axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>| async move {
+                    counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let products: Vec<_> = query["upc"]
+                        .split(',')
+                        .filter(|upc| *upc != "missing")
+                        .map(|upc| serde_json::json!({ "id": upc, "name": format!("Product {}", upc) }))
+                        .collect();
+                    axum::Json(serde_json::json!({ "items": products }))
+                },
+            ),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let config: RestSubgraphConfig = serde_json::from_value(serde_json::json!({
+            "entities": { "Product": {
+                "path": "/products?upc={upc}",
+                "result": "/items",
+                "key": "upc",
+                "fields": { "upc": "id" },
+            } }
+        }))
+        .unwrap();
+        let service = RestSubgraphService::new("products", config);
+        let request = request(
+            "query($representations:[_Any!]!){_entities(representations:$representations){...on Product{upc name}}}",
+            serde_json::json!({ "representations": [
+                { "__typename": "Product", "upc": "2" },
+                { "__typename": "Product", "upc": "missing" },
+                { "__typename": "Product", "upc": "1" },
+                { "__typename": "Product", "upc": "2" },
+            ] }),
+        );
+        let base: Uri = format!("http://{}/", address).parse().unwrap();
+        let response = service.execute(&base, &HeaderMap::new(), &request).await;
+
+        assert_eq!(
+            response.data,
+            Some(json!({ "_entities": [
+                { "upc": "2", "name": "Product 2" },
+                null,
+                { "upc": "1", "name": "Product 1" },
+                { "upc": "2", "name": "Product 2" },
+            ] }))
+        );
+        assert!(response.errors.is_empty());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
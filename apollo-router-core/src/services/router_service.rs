@@ -3,18 +3,27 @@
 use crate::apq::APQLayer;
 use crate::ensure_query_presence::EnsureQueryPresence;
 use crate::forbid_http_get_mutations::ForbidHttpGetMutationsLayer;
+#[cfg(feature = "bench")]
+use crate::plugin::profiling::profiled;
+use crate::plugin::timing::{self, timed, OperationTimings, Stage};
 use crate::services::execution_service::ExecutionService;
 use crate::{
-    BridgeQueryPlanner, CachingQueryPlanner, DynPlugin, ExecutionRequest, ExecutionResponse,
-    Introspection, Plugin, QueryCache, QueryPlannerRequest, QueryPlannerResponse, ResponseBody,
-    RouterRequest, RouterResponse, Schema, ServiceBuildError, ServiceBuilderExt, SubgraphRequest,
-    SubgraphResponse, DEFAULT_BUFFER_SIZE,
+    BridgeQueryPlanner, CacheStorage, CachingQueryPlanner, DynPlugin, ExecutionRequest,
+    ExecutionResponse, Introspection, IntrospectionAllowlist, JournalEntry, Object, PlanJournal,
+    Plugin, Query, QueryCache, QueryPlanOptions, QueryPlanner, QueryPlannerRequest,
+    QueryPlannerResponse, ResponseBody, RouterRequest, RouterResponse, Schema, ServiceBuildError,
+    ServiceBuilderExt, SubgraphRequest, SubgraphResponse, SubscriptionCallback,
+    SubscriptionConnection, SubscriptionService, Subscriptions, Value,
+    AUTHENTICATION_CLAIMS_CONTEXT_KEY, DEFAULT_BUFFER_SIZE, ESTIMATED_COST_CONTEXT_KEY,
+    FIELD_USAGE_CONTEXT_KEY, RESPONSE_EXTENSIONS_CONTEXT_KEY, TRACE_ROOT_CONTEXT_KEY,
 };
 use futures::{future::BoxFuture, TryFutureExt};
-use http::StatusCode;
+use http::{StatusCode, Uri};
 use indexmap::IndexMap;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::{Duration, Instant};
 use tower::buffer::Buffer;
 use tower::util::{BoxCloneService, BoxService};
 use tower::{BoxError, ServiceBuilder, ServiceExt};
@@ -23,6 +32,24 @@ use typed_builder::TypedBuilder;
 
 pub type Plugins = IndexMap<String, Box<dyn DynPlugin>>;
 
+/// Adds the extensions inserted with [`Context::insert_extension`](crate::Context::insert_extension)
+/// to the GraphQL body of `response`.
+fn merge_extensions(mut response: RouterResponse) -> RouterResponse {
+    let extensions: Option<Object> = response
+        .context
+        .get(RESPONSE_EXTENSIONS_CONTEXT_KEY)
+        .ok()
+        .flatten();
+    if let (Some(extensions), ResponseBody::GraphQL(body)) =
+        (extensions, response.response.body_mut())
+    {
+        for (name, value) in extensions {
+            body.extensions.insert(name, value);
+        }
+    }
+    response
+}
+
 /// Containing [`Service`] in the request lifecyle.
 #[derive(TypedBuilder, Clone)]
 pub struct RouterService<QueryPlannerService, ExecutionService> {
@@ -35,6 +62,14 @@ pub struct RouterService<QueryPlannerService, ExecutionService> {
     schema: Arc<Schema>,
     query_cache: Arc<QueryCache>,
     introspection: Option<Arc<Introspection>>,
+    /// Only these clients may introspect, when set.
+    #[builder(default)]
+    introspection_allowlist: Option<Arc<IntrospectionAllowlist>>,
+    #[builder(default)]
+    validate_final_response: bool,
+    /// Subscriptions are only answered when set, and only on WebSockets.
+    #[builder(default)]
+    subscriptions: Option<Arc<Subscriptions>>,
 }
 
 impl<QueryPlannerService, ExecutionService> Service<RouterRequest>
@@ -80,7 +115,14 @@ where
         // Consume our cloned services and allow ownership to be transferred to the async block.
         let mut planning = self.ready_query_planner_service.take().unwrap();
         let mut execution = self.ready_query_execution_service.take().unwrap();
-        let naive_introspection = self.introspection.clone();
+        let introspection_allowed = self
+            .introspection_allowlist
+            .as_ref()
+            .map(|allowlist| allowlist.allows(&req.context))
+            .unwrap_or(true);
+        let naive_introspection = self.introspection.clone().filter(|_| introspection_allowed);
+        let validate_final_response = self.validate_final_response;
+        let subscriptions = self.subscriptions.clone();
 
         let schema = self.schema.clone();
         let query_cache = self.query_cache.clone();
@@ -106,8 +148,7 @@ where
 
                 let context = req.context;
                 let body = req.originating_request.body();
-                let variables = body.variables.clone();
-                let query = query_cache
+                let mut query = query_cache
                     .get(
                         body.query
                             .as_ref()
@@ -116,6 +157,24 @@ where
                     )
                     .await;
 
+                if query.is_none() {
+                    let errors =
+                        Query::syntax_errors(body.query.as_ref().expect(
+                            "apollo.ensure-query-is-present has checked this already; qed",
+                        ));
+                    if !errors.is_empty() {
+                        let mut resp = http::Response::new(ResponseBody::GraphQL(
+                            crate::Response::builder().errors(errors).build(),
+                        ));
+                        *resp.status_mut() = StatusCode::BAD_REQUEST;
+
+                        return Ok(RouterResponse {
+                            response: resp.into(),
+                            context,
+                        });
+                    }
+                }
+
                 // Check if it's an introspection query
                 if let Some(current_query) = query.as_ref().filter(|q| q.contains_introspection()) {
                     match naive_introspection.as_ref() {
@@ -152,35 +211,301 @@ where
                     }
                 }
 
-                if let Some(err) = query
+                let is_subscription = query
+                    .as_ref()
+                    .map(|q| q.is_subscription(body.operation_name.as_deref()))
+                    .unwrap_or_default();
+                let on_websocket = req
+                    .originating_request
+                    .extensions()
+                    .get::<SubscriptionConnection>()
+                    .is_some();
+                if is_subscription && (subscriptions.is_none() || !on_websocket) {
+                    let message = if subscriptions.is_some() {
+                        "subscriptions must be sent on a WebSocket"
+                    } else {
+                        "subscriptions are not supported by this router"
+                    };
+                    let mut extensions = Object::default();
+                    extensions.insert("code", "OPERATION_NOT_SUPPORTED".into());
+                    let mut resp = http::Response::new(ResponseBody::GraphQL(
+                        crate::Response::builder()
+                            .errors(vec![crate::Error {
+                                message: message.to_string(),
+                                extensions,
+                                ..Default::default()
+                            }])
+                            .build(),
+                    ));
+                    *resp.status_mut() = StatusCode::BAD_REQUEST;
+
+                    return Ok(RouterResponse {
+                        response: resp.into(),
+                        context,
+                    });
+                }
+
+                // Fields the client may not query are removed from the query, and reported as
+                // errors next to the data of the others.
+                let mut unauthorized_errors = Vec::new();
+                let mut authorized_body = None;
+                if let Some(current_query) = query.clone() {
+                    let claims = context
+                        .get::<_, Value>(AUTHENTICATION_CLAIMS_CONTEXT_KEY)
+                        .ok()
+                        .flatten();
+                    let unauthorized = current_query.unauthorized_paths(
+                        body.operation_name.as_deref(),
+                        &schema,
+                        claims.as_ref(),
+                    );
+                    if !unauthorized.is_empty() {
+                        unauthorized_errors = unauthorized
+                            .into_iter()
+                            .map(|path| {
+                                let mut extensions = Object::default();
+                                extensions.insert("code", "UNAUTHORIZED_FIELD_OR_TYPE".into());
+                                crate::Error {
+                                    message: "Unauthorized field or type".to_string(),
+                                    path: Some(path),
+                                    extensions,
+                                    ..Default::default()
+                                }
+                            })
+                            .collect();
+                        match current_query.authorized_query(
+                            body.operation_name.as_deref(),
+                            &schema,
+                            claims.as_ref(),
+                        ) {
+                            Some(authorized) => {
+                                query = query_cache.get(&authorized).await;
+                                let mut authorized_request = body.clone();
+                                authorized_request.query = Some(authorized);
+                                authorized_body = Some(authorized_request);
+                            }
+                            None => {
+                                let mut resp = http::Response::new(ResponseBody::GraphQL(
+                                    crate::Response::builder()
+                                        .errors(unauthorized_errors)
+                                        .build(),
+                                ));
+                                *resp.status_mut() = if claims.is_some() {
+                                    StatusCode::FORBIDDEN
+                                } else {
+                                    StatusCode::UNAUTHORIZED
+                                };
+
+                                return Ok(RouterResponse {
+                                    response: resp.into(),
+                                    context,
+                                });
+                            }
+                        }
+                    }
+                }
+                let body = authorized_body.as_ref().unwrap_or(body);
+
+                if let Some(query) = query.as_ref() {
+                    let cost_requested = context
+                        .get::<_, Value>(ESTIMATED_COST_CONTEXT_KEY)
+                        .ok()
+                        .flatten()
+                        .is_some();
+                    if cost_requested {
+                        let cost = query.estimated_cost(
+                            body.operation_name.as_deref(),
+                            &schema,
+                            &body.variables,
+                        );
+                        if let Err(err) = context.insert(ESTIMATED_COST_CONTEXT_KEY, cost) {
+                            tracing::debug!("could not record the estimated cost: {}", err);
+                        }
+                    }
+                }
+
+                // Arguments bound to the context get their values from it, whatever the client
+                // sent for their variables.
+                let mut bound_body = None;
+                if let Some(query) = query.as_ref() {
+                    let bound = query
+                        .context_variables(body.operation_name.as_deref(), &schema)
+                        .map_err(|paths| {
+                            paths
+                                .into_iter()
+                                .map(|path| {
+                                    let mut extensions = Object::default();
+                                    extensions
+                                        .insert("code", "CONTEXT_ARGUMENT_NOT_A_VARIABLE".into());
+                                    crate::Error {
+                                        message: "an argument set from the request context must be given a variable".to_string(),
+                                        path: Some(path),
+                                        extensions,
+                                        ..Default::default()
+                                    }
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                        .and_then(|bindings| {
+                            if bindings.is_empty() {
+                                return Ok(None);
+                            }
+                            let mut bound = body.clone();
+                            let variables = Arc::make_mut(&mut bound.variables);
+                            for (variable, argument) in bindings {
+                                let value = argument.resolve(&context).ok_or_else(|| {
+                                    let mut extensions = Object::default();
+                                    extensions.insert("code", "CONTEXT_ARGUMENT_MISSING".into());
+                                    vec![crate::Error {
+                                        message: format!(
+                                            "the request context has no value for the variable ${}",
+                                            variable
+                                        ),
+                                        extensions,
+                                        ..Default::default()
+                                    }]
+                                })?;
+                                variables.insert(variable, value);
+                            }
+                            Ok(Some(bound))
+                        });
+                    match bound {
+                        Ok(bound) => bound_body = bound,
+                        Err(errors) => {
+                            let mut resp = http::Response::new(ResponseBody::GraphQL(
+                                crate::Response::builder().errors(errors).build(),
+                            ));
+                            *resp.status_mut() = StatusCode::BAD_REQUEST;
+
+                            return Ok(RouterResponse {
+                                response: resp.into(),
+                                context,
+                            });
+                        }
+                    }
+                }
+                let body = bound_body.as_ref().unwrap_or(body);
+
+                let coerced = query
                     .as_ref()
-                    .and_then(|q| q.validate_variables(body, &schema).err())
-                {
+                    .map(|q| q.coerce_variables(body, &schema))
+                    .transpose();
+                if let Err(err) = coerced {
                     Ok(RouterResponse {
                         response: http::Response::new(ResponseBody::GraphQL(err)).into(),
                         context,
                     })
                 } else {
                     let operation_name = body.operation_name.clone();
+                    // Subgraph fetches pick their variables from the originating request, so it
+                    // carries the coerced values from here on.
+                    let mut originating_request = req.originating_request.clone();
+                    originating_request.body_mut().query = body.query.clone();
+                    if let Ok(Some(coerced)) = coerced {
+                        originating_request.body_mut().variables = Arc::new(coerced);
+                    }
+                    if let (true, Some(subscriptions), Some(query)) =
+                        (is_subscription, subscriptions, query.clone())
+                    {
+                        return subscriptions
+                            .subscribe(
+                                query,
+                                schema,
+                                Arc::new(originating_request),
+                                unauthorized_errors,
+                                context,
+                            )
+                            .await;
+                    }
+                    let variables = originating_request.body().variables.clone();
+                    let timings_context = context.clone();
+                    let start = Instant::now();
                     let planned_query = planning
                         .call(
                             QueryPlannerRequest::builder()
-                                .originating_request(req.originating_request.clone())
+                                .originating_request(originating_request.clone())
                                 .context(context)
                                 .build(),
                         )
                         .await?;
+                    let planning_duration = start.elapsed().as_secs_f64();
+                    OperationTimings::record(&timings_context, |timings| {
+                        timings.planning = Some(planning_duration)
+                    });
+
+                    let start = Instant::now();
                     let mut response = execution
                         .call(
                             ExecutionRequest::builder()
-                                .originating_request(req.originating_request.clone())
+                                .originating_request(originating_request)
                                 .query_plan(planned_query.query_plan)
                                 .context(planned_query.context)
                                 .build(),
                         )
                         .await?;
+                    let execution_duration = start.elapsed().as_secs_f64();
+                    OperationTimings::record(&timings_context, |timings| {
+                        timings.execution = Some(execution_duration)
+                    });
 
                     if let Some(query) = query {
+                        if validate_final_response {
+                            if let Err(path) = query.validate_response(
+                                response.response.body(),
+                                operation_name.as_deref(),
+                                &variables,
+                                schema.api_schema(),
+                            ) {
+                                tracing::error!("the response data is invalid at {}", path);
+                                let mut extensions = Object::default();
+                                extensions.insert("code", "INTERNAL_RESPONSE_INVALID".into());
+                                let body = response.response.body_mut();
+                                body.data = None;
+                                body.errors.push(crate::Error {
+                                    message: "the response does not match the query".to_string(),
+                                    path: Some(path),
+                                    extensions,
+                                    ..Default::default()
+                                });
+                                *response.response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                            }
+                        }
+
+                        // Counted on the data as executed, with the `__typename` fields the
+                        // planner adds to tell the types of abstract fields.
+                        let requested = |key: &str| {
+                            response
+                                .context
+                                .get::<_, Value>(key)
+                                .ok()
+                                .flatten()
+                                .is_some()
+                        };
+                        let usage_requested = requested(FIELD_USAGE_CONTEXT_KEY);
+                        let trace_requested = requested(TRACE_ROOT_CONTEXT_KEY);
+                        if usage_requested || trace_requested {
+                            let (usage, root) = query.field_usage(
+                                operation_name.as_deref(),
+                                response.response.body(),
+                                schema.api_schema(),
+                            );
+                            if usage_requested {
+                                if let Err(err) =
+                                    response.context.insert(FIELD_USAGE_CONTEXT_KEY, usage)
+                                {
+                                    tracing::debug!("could not record the field usage: {}", err);
+                                }
+                            }
+                            if trace_requested {
+                                if let Err(err) =
+                                    response.context.insert(TRACE_ROOT_CONTEXT_KEY, root)
+                                {
+                                    tracing::debug!("could not record the trace nodes: {}", err);
+                                }
+                            }
+                        }
+
+                        let start = Instant::now();
                         tracing::debug_span!("format_response").in_scope(|| {
                             query.format_response(
                                 response.response.body_mut(),
@@ -189,7 +514,16 @@ where
                                 schema.api_schema(),
                             )
                         });
+                        let formatting_duration = start.elapsed().as_secs_f64();
+                        OperationTimings::record(&timings_context, |timings| {
+                            timings.response_formatting = Some(formatting_duration)
+                        });
                     }
+                    response
+                        .response
+                        .body_mut()
+                        .errors
+                        .extend(unauthorized_errors);
 
                     Ok(RouterResponse {
                         context: response.context,
@@ -198,13 +532,15 @@ where
                 }
             }
             .or_else(|error: BoxError| async move {
-                let errors = vec![crate::Error {
-                    message: error.to_string(),
-                    ..Default::default()
-                }];
+                let error = crate::Error::from_box_error(&error);
+                let status_code = if error.code() == Some("QUERY_PLANNING_FAILED") {
+                    StatusCode::BAD_REQUEST
+                } else {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                };
                 RouterResponse::builder()
-                    .errors(errors)
-                    .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                    .errors(vec![error])
+                    .status_code(status_code)
                     .context(context_cloned)
                     .build()
             });
@@ -227,6 +563,16 @@ pub struct PluggableRouterServiceBuilder {
         BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
     )>,
     introspection: bool,
+    introspection_allowlist: Option<IntrospectionAllowlist>,
+    validate_final_response: bool,
+    plugin_timings: bool,
+    plan_cache_limit: Option<usize>,
+    cache_storage: Option<Arc<dyn CacheStorage>>,
+    plan_journal: Option<PlanJournal>,
+    warm_up: Vec<JournalEntry>,
+    warm_up_introspection: bool,
+    subscriptions: Option<(HashMap<String, Uri>, Duration)>,
+    subscription_callback: Option<SubscriptionCallback>,
 }
 
 impl PluggableRouterServiceBuilder {
@@ -236,6 +582,16 @@ impl PluggableRouterServiceBuilder {
             plugins: Default::default(),
             subgraph_services: Default::default(),
             introspection: false,
+            introspection_allowlist: None,
+            validate_final_response: false,
+            plugin_timings: false,
+            plan_cache_limit: None,
+            cache_storage: None,
+            plan_journal: None,
+            warm_up: Vec::new(),
+            warm_up_introspection: false,
+            subscriptions: None,
+            subscription_callback: None,
         }
     }
 
@@ -282,6 +638,103 @@ impl PluggableRouterServiceBuilder {
         self
     }
 
+    /// Lets the clients of the allowlist introspect the schema when introspection is disabled.
+    pub fn with_introspection_allowlist(
+        mut self,
+        allowlist: IntrospectionAllowlist,
+    ) -> PluggableRouterServiceBuilder {
+        self.introspection_allowlist = Some(allowlist);
+        self
+    }
+
+    /// Checks the data of every response against its query before it is formatted.
+    ///
+    /// Data that does not match is replaced by an `INTERNAL_RESPONSE_INVALID` error pointing to
+    /// the first invalid value. Disabled by default.
+    pub fn validate_final_response(mut self, validate: bool) -> PluggableRouterServiceBuilder {
+        self.validate_final_response = validate;
+        self
+    }
+
+    /// Records the time spent by each plugin in each stage, in the context of the requests and
+    /// with the recorder set by [`crate::plugin::timing::set_timings_recorder`]. Disabled by
+    /// default.
+    pub fn with_plugin_timings(mut self, enabled: bool) -> PluggableRouterServiceBuilder {
+        self.plugin_timings = enabled;
+        self
+    }
+
+    /// Number of query plans kept in cache, overriding the `ROUTER_PLAN_CACHE_LIMIT` environment
+    /// variable.
+    pub fn with_plan_cache_limit(mut self, limit: usize) -> PluggableRouterServiceBuilder {
+        self.plan_cache_limit = Some(limit);
+        self
+    }
+
+    /// Storage shared with other router instances, where persisted queries and query plans are
+    /// kept in addition to the in-memory caches.
+    pub fn with_cache_storage(
+        mut self,
+        storage: Arc<dyn CacheStorage>,
+    ) -> PluggableRouterServiceBuilder {
+        self.cache_storage = Some(storage);
+        self
+    }
+
+    /// Journal where the operations planned for clients are recorded, to warm up the caches of
+    /// the next router.
+    pub fn with_plan_journal(mut self, journal: PlanJournal) -> PluggableRouterServiceBuilder {
+        self.plan_journal = Some(journal);
+        self
+    }
+
+    /// Operations planned before the router is built, so that their first requests are answered
+    /// from the cache.
+    ///
+    /// With `introspection`, introspection queries among them are executed as well, when the
+    /// router answers them. Operations failing against the schema are skipped.
+    pub fn with_warm_up(
+        mut self,
+        operations: Vec<JournalEntry>,
+        introspection: bool,
+    ) -> PluggableRouterServiceBuilder {
+        self.warm_up = operations;
+        self.warm_up_introspection = introspection;
+        self
+    }
+
+    /// Answers subscriptions sent on WebSockets, by subscribing to the subgraph resolving their
+    /// root field.
+    ///
+    /// Subgraphs are subscribed to at their URL with the `ws` or `wss` scheme, unless `endpoints`
+    /// has another URL for them. They have `connection_init_timeout` to acknowledge the connection.
+    pub fn with_subscriptions(
+        mut self,
+        endpoints: HashMap<String, Uri>,
+        connection_init_timeout: Duration,
+    ) -> PluggableRouterServiceBuilder {
+        self.subscriptions = Some((endpoints, connection_init_timeout));
+        self
+    }
+
+    /// Has the subscriptions to `subgraphs`, or to all subgraphs when empty, posted back to `url`
+    /// rather than sent on a WebSocket.
+    ///
+    /// The subgraphs are asked for a heartbeat every `heartbeat_interval`.
+    pub fn with_subscription_callback(
+        mut self,
+        url: Uri,
+        subgraphs: Vec<String>,
+        heartbeat_interval: Duration,
+    ) -> PluggableRouterServiceBuilder {
+        self.subscription_callback = Some(SubscriptionCallback {
+            url,
+            subgraphs,
+            heartbeat_interval,
+        });
+        self
+    }
+
     pub async fn build(
         mut self,
     ) -> Result<
@@ -298,9 +751,13 @@ impl PluggableRouterServiceBuilder {
         // various iterators that we create for folding and leave
         // the plugins in their original order.
 
-        let plan_cache_limit = std::env::var("ROUTER_PLAN_CACHE_LIMIT")
-            .ok()
-            .and_then(|x| x.parse().ok())
+        let plan_cache_limit = self
+            .plan_cache_limit
+            .or_else(|| {
+                std::env::var("ROUTER_PLAN_CACHE_LIMIT")
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+            })
             .unwrap_or(100);
 
         // QueryPlannerService takes an UnplannedRequest and outputs PlannedRequest
@@ -308,16 +765,69 @@ impl PluggableRouterServiceBuilder {
         let bridge_query_planner = BridgeQueryPlanner::new(self.schema.clone())
             .await
             .map_err(ServiceBuildError::QueryPlannerError)?;
-        let query_planner_service =
-            ServiceBuilder::new()
-                .buffered()
-                .service(self.plugins.iter_mut().rev().fold(
-                    CachingQueryPlanner::new(bridge_query_planner, plan_cache_limit).boxed(),
-                    |acc, (_, e)| e.query_planning_service(acc),
-                ));
+        let caching_query_planner = match &self.cache_storage {
+            Some(storage) => CachingQueryPlanner::with_storage(
+                bridge_query_planner,
+                plan_cache_limit,
+                storage.clone(),
+                &self.schema,
+            ),
+            None => CachingQueryPlanner::new(bridge_query_planner, plan_cache_limit),
+        };
+        let caching_query_planner = match self.plan_journal.take() {
+            Some(journal) => caching_query_planner.with_journal(journal),
+            None => caching_query_planner,
+        };
+
+        // The caches are warmed up before the router is built rather than in the background:
+        // the previous router keeps serving until then, and the first requests of this one are
+        // not held up by planning.
+        let (introspection_queries, operations): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.warm_up)
+                .into_iter()
+                .partition(|(query, _)| {
+                    Query::parse(query.as_str(), &self.schema)
+                        .map(|query| query.contains_introspection())
+                        .unwrap_or_default()
+                });
+        let mut warmed_up = 0;
+        for (query, operation_name) in operations {
+            // Operations recorded against a previous schema may not be valid anymore.
+            if caching_query_planner
+                .get(query, operation_name, QueryPlanOptions::default())
+                .await
+                .is_ok()
+            {
+                warmed_up += 1;
+            }
+        }
+        if warmed_up > 0 {
+            tracing::info!(
+                "warmed up the query plan cache with {} operations",
+                warmed_up
+            );
+        }
+        let plugin_timings = self.plugin_timings;
+        let query_planner_service = self.plugins.iter_mut().rev().fold(
+            caching_query_planner.boxed(),
+            |acc, (plugin_name, e)| {
+                timed(
+                    plugin_timings,
+                    plugin_name,
+                    Stage::QueryPlanning,
+                    acc,
+                    |acc| e.query_planning_service(acc),
+                )
+            },
+        );
+        #[cfg(feature = "bench")]
+        let query_planner_service = profiled(Stage::QueryPlanning, query_planner_service);
+        let query_planner_service = ServiceBuilder::new()
+            .buffered()
+            .service(query_planner_service);
 
         // SubgraphService takes a SubgraphRequest and outputs a RouterResponse
-        let subgraphs = self
+        let subgraphs: HashMap<_, _> = self
             .subgraph_services
             .into_iter()
             .map(|(name, s)| {
@@ -325,7 +835,13 @@ impl PluggableRouterServiceBuilder {
                     .plugins
                     .iter_mut()
                     .rev()
-                    .fold(s, |acc, (_, e)| e.subgraph_service(&name, acc));
+                    .fold(s, |acc, (plugin_name, e)| {
+                        timed(plugin_timings, plugin_name, Stage::Subgraph, acc, |acc| {
+                            e.subgraph_service(&name, acc)
+                        })
+                    });
+                #[cfg(feature = "bench")]
+                let service = profiled(Stage::Subgraph, service);
 
                 let service = ServiceBuilder::new().buffered().service(service);
 
@@ -334,31 +850,69 @@ impl PluggableRouterServiceBuilder {
             .collect();
 
         // ExecutionService takes a PlannedRequest and outputs a RouterResponse
+        let execution_service = self.plugins.iter_mut().rev().fold(
+            ExecutionService::builder()
+                .schema(self.schema.clone())
+                .subgraph_services(subgraphs.clone())
+                .build()
+                .boxed(),
+            |acc, (plugin_name, e)| {
+                timed(plugin_timings, plugin_name, Stage::Execution, acc, |acc| {
+                    e.execution_service(acc)
+                })
+            },
+        );
+        #[cfg(feature = "bench")]
+        let execution_service = profiled(Stage::Execution, execution_service);
         // NB: Cannot use .buffer() here or the code won't compile...
         let execution_service = Buffer::new(
             ServiceBuilder::new()
                 .layer(ForbidHttpGetMutationsLayer::default())
-                .service(
-                    self.plugins.iter_mut().rev().fold(
-                        ExecutionService::builder()
-                            .schema(self.schema.clone())
-                            .subgraph_services(subgraphs)
-                            .build()
-                            .boxed(),
-                        |acc, (_, e)| e.execution_service(acc),
-                    ),
-                )
+                .service(execution_service)
                 .boxed(),
             DEFAULT_BUFFER_SIZE,
         );
 
+        // SubscriptionService takes the events of a subscription and outputs them to the client
+        let subscriptions = match self.subscriptions.take() {
+            Some((endpoints, connection_init_timeout)) => {
+                let event_service = self.plugins.iter_mut().rev().fold(
+                    SubscriptionService.boxed(),
+                    |acc, (plugin_name, e)| {
+                        timed(
+                            plugin_timings,
+                            plugin_name,
+                            Stage::Subscription,
+                            acc,
+                            |acc| e.subscription_service(acc),
+                        )
+                    },
+                );
+                Some(Arc::new(Subscriptions::new(
+                    &self.schema,
+                    &endpoints,
+                    connection_init_timeout,
+                    self.subscription_callback.take(),
+                    subgraphs,
+                    Buffer::new(event_service, DEFAULT_BUFFER_SIZE),
+                )))
+            }
+            None => None,
+        };
+
         let query_cache_limit = std::env::var("ROUTER_QUERY_CACHE_LIMIT")
             .ok()
             .and_then(|x| x.parse().ok())
             .unwrap_or(100);
         let query_cache = Arc::new(QueryCache::new(query_cache_limit, self.schema.clone()));
 
-        let introspection = if self.introspection {
+        // Allowlisted clients still introspect when introspection is disabled for everyone else.
+        let introspection_allowlist = if self.introspection {
+            None
+        } else {
+            self.introspection_allowlist.take().map(Arc::new)
+        };
+        let introspection = if self.introspection || introspection_allowlist.is_some() {
             // Introspection instantiation can potentially block for some time
             // We don't need to use the api schema here because on the deno side we always convert to API schema
 
@@ -372,44 +926,58 @@ impl PluggableRouterServiceBuilder {
             None
         };
 
-        /*FIXME
-        // Start warming up the cache
-        //
-        // We don't need to do this in background because the old server will keep running until
-        // this one is ready.
-        //
-        // If we first warm up the cache in foreground, then switch to the new config, the next
-        // queries will benefit from the warmed up cache. While if we switch and warm up in
-        // background, the next queries might be blocked until the cache is primed, so there'll be
-        // a perf hit.
-        if let Some(previous_router) = previous_router {
-            for (query, operation, options) in previous_router.query_planner.get_hot_keys().await {
-                // We can ignore errors because some of the queries that were previously in the
-                // cache might not work with the new schema
-                let _ = query_planner.get(query, operation, options).await;
+        if let Some(introspection) = introspection
+            .as_ref()
+            .filter(|_| self.warm_up_introspection)
+        {
+            for (query, _) in introspection_queries {
+                if let Err(err) = introspection.execute(self.schema.as_str(), &query).await {
+                    tracing::debug!("could not warm up an introspection query: {}", err);
+                }
             }
         }
-        */
+
+        let apq = match &self.cache_storage {
+            Some(storage) => APQLayer::default().with_storage(storage.clone()),
+            None => APQLayer::default(),
+        };
 
         // Router service takes a graphql::Request and outputs a graphql::Response
+        let router_service = self.plugins.iter_mut().rev().fold(
+            RouterService::builder()
+                .query_planner_service(query_planner_service)
+                .query_execution_service(execution_service)
+                .schema(self.schema)
+                .query_cache(query_cache)
+                .introspection(introspection)
+                .introspection_allowlist(introspection_allowlist)
+                .validate_final_response(self.validate_final_response)
+                .subscriptions(subscriptions)
+                .build()
+                .boxed(),
+            |acc, (plugin_name, e)| {
+                timed(plugin_timings, plugin_name, Stage::Router, acc, |acc| {
+                    e.router_service(acc)
+                })
+            },
+        );
+        #[cfg(feature = "bench")]
+        let router_service = profiled(Stage::Router, router_service);
         // NB: Cannot use .buffer() here or the code won't compile...
         let router_service = Buffer::new(
             ServiceBuilder::new()
-                .layer(APQLayer::default())
+                // Outside of every plugin, so that the time spent by each of them is known.
+                .map_response(|response: RouterResponse| {
+                    timing::record_completed(&response.context);
+                    response
+                })
+                // Outside of every plugin, so that the extensions they insert at any stage are kept.
+                .map_response(merge_extensions)
+                .layer(apq)
+                // Persisted queries may be looked up asynchronously, with a clone of the service.
+                .buffered()
                 .layer(EnsureQueryPresence::default())
-                .service(
-                    self.plugins.iter_mut().rev().fold(
-                        RouterService::builder()
-                            .query_planner_service(query_planner_service)
-                            .query_execution_service(execution_service)
-                            .schema(self.schema)
-                            .query_cache(query_cache)
-                            .introspection(introspection)
-                            .build()
-                            .boxed(),
-                        |acc, (_, e)| e.router_service(acc),
-                    ),
-                )
+                .service(router_service)
                 .boxed(),
             DEFAULT_BUFFER_SIZE,
         );
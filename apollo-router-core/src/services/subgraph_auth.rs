@@ -0,0 +1,431 @@
+//! Credentials of the router, sent along with the requests of subgraphs served over GraphQL.
+//!
+//! A subgraph can be sent a static bearer token, requests signed with AWS Signature Version 4 for
+//! subgraphs behind AWS services, or an access token obtained from an OAuth2 authorization server
+//! with the client credentials grant. Access tokens are kept until shortly before they expire, and
+//! requests wait for the new one while it is fetched.
+
+use super::tower_subgraph_service::Client;
+use hmac::{Hmac, Mac};
+use http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HOST};
+use http::HeaderValue;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tower::BoxError;
+
+/// How long before their expiry access tokens are fetched again.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// Lifetime of access tokens given without `expires_in`.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Authentication of the router with subgraphs, by subgraph name.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphAuthConfig {
+    /// Applied to each subgraph, unless overridden in `subgraphs`.
+    #[serde(default)]
+    pub all: Option<SubgraphAuth>,
+    #[serde(default)]
+    pub subgraphs: HashMap<String, SubgraphAuth>,
+}
+
+impl SubgraphAuthConfig {
+    /// The authentication of the `name` subgraph, if any.
+    pub fn subgraph(&self, name: &str) -> Option<&SubgraphAuth> {
+        self.subgraphs.get(name).or(self.all.as_ref())
+    }
+}
+
+/// How the router authenticates with a subgraph.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum SubgraphAuth {
+    /// A static token, sent as `Authorization: Bearer <token>`.
+    Bearer {
+        /// The token.
+        token: String,
+    },
+    /// Requests signed with AWS Signature Version 4.
+    AwsSigV4 {
+        /// Region of the service, like `us-east-1`.
+        region: String,
+        /// Name of the service, like `execute-api` or `lambda`.
+        service_name: String,
+        /// Access key ID of the credentials.
+        access_key_id: String,
+        /// Secret access key of the credentials.
+        secret_access_key: String,
+        /// Session token of temporary credentials. None by default.
+        #[serde(default)]
+        session_token: Option<String>,
+    },
+    /// An access token obtained with the OAuth2 client credentials grant, sent as
+    /// `Authorization: Bearer <token>`.
+    Oauth2 {
+        /// URL of the token endpoint of the authorization server.
+        token_url: String,
+        /// Client ID of the router.
+        client_id: String,
+        /// Client secret of the router.
+        client_secret: String,
+        /// Scopes requested with the token. None by default.
+        #[serde(default)]
+        scopes: Vec<String>,
+    },
+}
+
+/// Adds the credentials of the router to the requests of a subgraph.
+pub(super) struct Authenticator {
+    auth: SubgraphAuth,
+    /// The current access token, with the time from which it is fetched again.
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl Authenticator {
+    pub(super) fn new(auth: SubgraphAuth) -> Self {
+        Self {
+            auth,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Authenticates `request`, whose body is `payload` unless it is streamed.
+    pub(super) async fn authenticate(
+        &self,
+        request: &mut http::Request<hyper::Body>,
+        payload: Option<&[u8]>,
+        client: &Client,
+    ) -> Result<(), BoxError> {
+        match &self.auth {
+            SubgraphAuth::Bearer { token } => {
+                request.headers_mut().insert(AUTHORIZATION, bearer(token)?);
+            }
+            SubgraphAuth::AwsSigV4 { .. } => {
+                sign(&self.auth, request, payload, SystemTime::now())?;
+            }
+            SubgraphAuth::Oauth2 { .. } => {
+                let mut token = self.token.lock().await;
+                let current = match token.as_ref() {
+                    Some((current, refresh_at)) if Instant::now() < *refresh_at => current.clone(),
+                    _ => {
+                        let (fetched, refresh_at) = fetch_token(&self.auth, client).await?;
+                        *token = Some((fetched.clone(), refresh_at));
+                        fetched
+                    }
+                };
+                drop(token);
+                request
+                    .headers_mut()
+                    .insert(AUTHORIZATION, bearer(&current)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn bearer(token: &str) -> Result<HeaderValue, BoxError> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// Fetches an access token, returning it with the time from which it should be fetched again.
+async fn fetch_token(auth: &SubgraphAuth, client: &Client) -> Result<(String, Instant), BoxError> {
+    let (token_url, client_id, client_secret, scopes) = match auth {
+        SubgraphAuth::Oauth2 {
+            token_url,
+            client_id,
+            client_secret,
+            scopes,
+        } => (token_url, client_id, client_secret, scopes),
+        _ => return Err("not an OAuth2 authentication".into()),
+    };
+    let mut form = vec![
+        ("grant_type", "client_credentials".to_string()),
+        ("client_id", client_id.clone()),
+        ("client_secret", client_secret.clone()),
+    ];
+    if !scopes.is_empty() {
+        form.push(("scope", scopes.join(" ")));
+    }
+    let request = http::Request::post(token_url.as_str())
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(ACCEPT, "application/json")
+        .body(hyper::Body::from(serde_urlencoded::to_string(&form)?))?;
+
+    let requested_at = Instant::now();
+    let response = client
+        .request(request)
+        .await
+        .map_err(|err| format!("could not fetch an access token: {}", err))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        return Err(format!("the token endpoint answered with status {}", status).into());
+    }
+    let token: TokenResponse = serde_json::from_slice(&body)
+        .map_err(|err| format!("invalid response of the token endpoint: {}", err))?;
+    let lifetime = token
+        .expires_in
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+    Ok((
+        token.access_token,
+        requested_at + lifetime.saturating_sub(TOKEN_REFRESH_MARGIN),
+    ))
+}
+
+/// Signs `request` with AWS Signature Version 4, as of `now`.
+fn sign(
+    auth: &SubgraphAuth,
+    request: &mut http::Request<hyper::Body>,
+    payload: Option<&[u8]>,
+    now: SystemTime,
+) -> Result<(), BoxError> {
+    let (region, service_name, access_key_id, secret_access_key, session_token) = match auth {
+        SubgraphAuth::AwsSigV4 {
+            region,
+            service_name,
+            access_key_id,
+            secret_access_key,
+            session_token,
+        } => (
+            region,
+            service_name,
+            access_key_id,
+            secret_access_key,
+            session_token,
+        ),
+        _ => return Err("not an AWS SigV4 authentication".into()),
+    };
+    let timestamp = amz_date(now);
+    let date = &timestamp[..8];
+    let payload_hash = payload
+        .map(|payload| hex::encode(Sha256::digest(payload)))
+        .unwrap_or_else(|| "UNSIGNED-PAYLOAD".to_string());
+
+    let host = request
+        .uri()
+        .authority()
+        .ok_or("the subgraph URL has no host")?
+        .to_string();
+    let headers = request.headers_mut();
+    headers.insert(HOST, HeaderValue::from_str(&host)?);
+    headers.insert("x-amz-date", HeaderValue::from_str(&timestamp)?);
+    headers.insert(
+        "x-amz-content-sha256",
+        HeaderValue::from_str(&payload_hash)?,
+    );
+    let mut signed = vec![
+        ("host", host),
+        ("x-amz-content-sha256", payload_hash.clone()),
+        ("x-amz-date", timestamp.clone()),
+    ];
+    if let Some(session_token) = session_token {
+        let mut value = HeaderValue::from_str(session_token)?;
+        value.set_sensitive(true);
+        headers.insert("x-amz-security-token", value);
+        signed.push(("x-amz-security-token", session_token.clone()));
+    }
+
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = signed
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method(),
+        canonical_path(request.uri().path()),
+        canonical_query(request.uri().query().unwrap_or_default()),
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service_name);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(secret_access_key, date, region, service_name);
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    let mut authorization = HeaderValue::from_str(&format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key_id, scope, signed_headers, signature
+    ))?;
+    authorization.set_sensitive(true);
+    request.headers_mut().insert(AUTHORIZATION, authorization);
+    Ok(())
+}
+
+fn canonical_path(path: &str) -> String {
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    path.split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn canonical_query(query: &str) -> String {
+    let mut pairs: Vec<(String, String)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let encode = |part: &str| {
+                let decoded = urlencoding::decode(part)
+                    .map(|decoded| decoded.into_owned())
+                    .unwrap_or_else(|_| part.to_string());
+                urlencoding::encode(&decoded).into_owned()
+            };
+            (encode(name), encode(value))
+        })
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service_name: &str) -> Vec<u8> {
+    let key = hmac_sha256(
+        format!("AWS4{}", secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service_name.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// HMAC-SHA256 of `message`, keyed by `key`.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size; qed");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `time` in the basic ISO 8601 format of AWS, like `20150830T123600Z`.
+fn amz_date(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .expect("dates after 1970 can be formatted; qed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn dates_use_the_aws_format() {
+        assert_eq!(amz_date(UNIX_EPOCH), "19700101T000000Z");
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(1_440_938_160)),
+            "20150830T123600Z"
+        );
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(951_825_600)),
+            "20000229T120000Z"
+        );
+    }
+
+    #[test]
+    fn signing_keys_are_derived_from_the_secret() {
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex::encode(signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn requests_are_signed() {
+        let auth = SubgraphAuth::AwsSigV4 {
+            region: "us-east-1".to_string(),
+            service_name: "execute-api".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let mut request = http::Request::post("https://api.example.com/graphql?b=2&a=1")
+            .body(hyper::Body::empty())
+            .unwrap();
+        sign(
+            &auth,
+            &mut request,
+            Some(b"{\"query\":\"{ me { id } }\"}"),
+            UNIX_EPOCH + Duration::from_secs(1_440_938_160),
+        )
+        .unwrap();
+
+        assert_eq!(request.headers()["x-amz-date"], "20150830T123600Z");
+        assert_eq!(request.headers()[HOST], "api.example.com");
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/execute-api/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=b18369683f12aca428213b010c633c663e4f7ef6f9449f6c103abad56fb0f5a4"
+        );
+    }
+
+    #[test]
+    fn subgraphs_override_the_default_authentication() {
+        let config: SubgraphAuthConfig = serde_json::from_value(serde_json::json!({
+            "all": { "bearer": { "token": "shared" } },
+            "subgraphs": {
+                "products": {
+                    "oauth2": {
+                        "token_url": "https://auth.example.com/token",
+                        "client_id": "router",
+                        "client_secret": "secret",
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        assert!(matches!(
+            config.subgraph("products"),
+            Some(SubgraphAuth::Oauth2 { scopes, .. }) if scopes.is_empty()
+        ));
+        assert!(matches!(
+            config.subgraph("reviews"),
+            Some(SubgraphAuth::Bearer { token }) if token == "shared"
+        ));
+    }
+}
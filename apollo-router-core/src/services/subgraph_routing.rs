@@ -0,0 +1,160 @@
+//! Weighted routing of the requests of a subgraph between several endpoints.
+//!
+//! A subgraph can be served by a stable deployment and a canary taking a small share of its
+//! traffic. Targets are picked with a smooth weighted round-robin, so that the shares hold over
+//! any window of requests, and a header can pin a request to a target by name.
+
+use http::{HeaderMap, Uri};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tower::BoxError;
+
+/// Endpoints serving a subgraph, and the share of its requests each of them gets.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphRouting {
+    /// Endpoints of the subgraph, used instead of its URL in the supergraph.
+    pub targets: Vec<SubgraphTarget>,
+    /// Header whose value is the name of the target a request is sent to, overriding the
+    /// weights. Requests naming an unknown target are routed by weight.
+    #[serde(default)]
+    pub override_header: Option<String>,
+}
+
+/// An endpoint of a subgraph.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphTarget {
+    /// Name of the target, in metrics and in the `override_header`.
+    pub name: String,
+    /// URL of the endpoint.
+    pub url: String,
+    /// Share of the requests sent to this target, relative to the weights of the others.
+    pub weight: u32,
+}
+
+/// Extension of subgraph responses, with the name of the target that answered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutedTo(pub String);
+
+/// The targets of a subgraph, with their state in the round-robin.
+pub(super) struct Router {
+    targets: Vec<(String, Uri, i64)>,
+    total_weight: i64,
+    override_header: Option<String>,
+    current: Mutex<Vec<i64>>,
+}
+
+impl Router {
+    pub(super) fn new(routing: &SubgraphRouting) -> Result<Self, BoxError> {
+        let targets = routing
+            .targets
+            .iter()
+            .map(|target| {
+                let uri = Uri::from_str(&target.url)
+                    .map_err(|err| format!("invalid URL of target {}: {}", target.name, err))?;
+                Ok((target.name.clone(), uri, i64::from(target.weight)))
+            })
+            .collect::<Result<Vec<_>, BoxError>>()?;
+        let total_weight = targets.iter().map(|(_, _, weight)| weight).sum();
+        if total_weight == 0 {
+            return Err("routing needs at least one target with a weight".into());
+        }
+        Ok(Self {
+            current: Mutex::new(vec![0; targets.len()]),
+            targets,
+            total_weight,
+            override_header: routing.override_header.clone(),
+        })
+    }
+
+    /// The name and URL of the target of a request with `headers`.
+    pub(super) fn route(&self, headers: &HeaderMap) -> (&str, &Uri) {
+        let pinned = self
+            .override_header
+            .as_ref()
+            .and_then(|header| headers.get(header.as_str()))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|name| self.targets.iter().position(|(target, ..)| target == name));
+        let index = match pinned {
+            Some(index) => index,
+            None => self.next(),
+        };
+        let (name, uri, _) = &self.targets[index];
+        (name, uri)
+    }
+
+    fn next(&self) -> usize {
+        let mut current = self.current.lock().expect("lock poisoned");
+        for (current, (_, _, weight)) in current.iter_mut().zip(&self.targets) {
+            *current += weight;
+        }
+        let (index, _) = current
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, current)| (**current, std::cmp::Reverse(*index)))
+            .expect("there is at least one target");
+        current[index] -= self.total_weight;
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn router() -> Router {
+        let routing: SubgraphRouting = serde_json::from_value(serde_json::json!({
+            "targets": [
+                { "name": "stable", "url": "http://stable:4001", "weight": 3 },
+                { "name": "canary", "url": "http://canary:4001", "weight": 1 }
+            ],
+            "override_header": "x-subgraph-target"
+        }))
+        .unwrap();
+        Router::new(&routing).unwrap()
+    }
+
+    #[test]
+    fn requests_are_shared_by_weight() {
+        let router = router();
+        let targets: Vec<_> = (0..8)
+            .map(|_| router.route(&HeaderMap::new()).0.to_string())
+            .collect();
+        assert_eq!(
+            targets,
+            ["stable", "stable", "canary", "stable", "stable", "stable", "canary", "stable"]
+        );
+    }
+
+    #[test]
+    fn the_override_header_pins_the_target() {
+        let router = router();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-subgraph-target", HeaderValue::from_static("canary"));
+        for _ in 0..4 {
+            let (name, uri) = router.route(&headers);
+            assert_eq!(name, "canary");
+            assert_eq!(uri, &Uri::from_static("http://canary:4001"));
+        }
+
+        headers.insert("x-subgraph-target", HeaderValue::from_static("unknown"));
+        assert_eq!(router.route(&headers).0, "stable");
+    }
+
+    #[test]
+    fn targets_need_a_weight() {
+        let routing = SubgraphRouting {
+            targets: vec![SubgraphTarget {
+                name: "stable".to_string(),
+                url: "http://stable:4001".to_string(),
+                weight: 0,
+            }],
+            override_header: None,
+        };
+        assert!(Router::new(&routing).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! Subscriptions whose subgraph posts the events to the router, with the HTTP callback protocol.
+//!
+//! The subscription is sent to the subgraph as any request, with the URL to call back, the id of
+//! the subscription and a verifier in its `subscription` extension. The subgraph checks the
+//! callback URL before answering, then posts the events of the subscription, heartbeats and its
+//! completion there. Calls for an unknown subscription or with the wrong verifier are answered
+//! with a 404 status, telling the subgraph the subscription is over.
+
+use crate::prelude::graphql::*;
+use http::{StatusCode, Uri};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json_bytes::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Events of a subscription waiting to be sent to the client.
+const EVENT_BUFFER: usize = 16;
+
+/// Heartbeats a subgraph may miss before its subscription is ended.
+const MISSED_HEARTBEATS: u32 = 3;
+
+/// The subscriptions waiting for callbacks, by id. They outlive the router that sent them, so that
+/// a reload does not end them.
+static CALLBACKS: Lazy<Mutex<HashMap<String, Callback>>> = Lazy::new(Default::default);
+
+struct Callback {
+    verifier: String,
+    events: mpsc::Sender<CallbackEvent>,
+}
+
+enum CallbackEvent {
+    Next(Response),
+    Complete(Vec<Error>),
+    Heartbeat,
+}
+
+/// A call of a subgraph to the callback URL of a subscription.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum CallbackMessage {
+    /// Sent before the subgraph answers the subscription, to check the callback URL.
+    Check { id: String, verifier: String },
+    Next {
+        id: String,
+        verifier: String,
+        payload: Response,
+    },
+    Complete {
+        id: String,
+        verifier: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<Error>,
+    },
+    /// Sent every heartbeat interval, for the subscriptions of `ids`.
+    Heartbeat {
+        id: String,
+        verifier: String,
+        #[serde(default)]
+        ids: Vec<String>,
+    },
+}
+
+impl CallbackMessage {
+    /// The id of the subscription the message is sent for.
+    pub fn id(&self) -> &str {
+        match self {
+            CallbackMessage::Check { id, .. }
+            | CallbackMessage::Next { id, .. }
+            | CallbackMessage::Complete { id, .. }
+            | CallbackMessage::Heartbeat { id, .. } => id,
+        }
+    }
+}
+
+/// Answers a call of a subgraph to the callback URL of a subscription, with the status the
+/// protocol expects.
+pub async fn handle_callback(message: CallbackMessage) -> StatusCode {
+    let (id, verifier, event, status) = match message {
+        CallbackMessage::Check { id, verifier } => {
+            return match registered(&id, &verifier) {
+                Some(_) => StatusCode::NO_CONTENT,
+                None => StatusCode::NOT_FOUND,
+            };
+        }
+        CallbackMessage::Heartbeat {
+            id,
+            verifier,
+            mut ids,
+        } => {
+            if ids.is_empty() {
+                ids.push(id);
+            }
+            let mut status = StatusCode::NO_CONTENT;
+            for id in ids {
+                match registered(&id, &verifier) {
+                    Some(events) => {
+                        // A full buffer already tells the subscription the subgraph is alive.
+                        let _ = events.try_send(CallbackEvent::Heartbeat);
+                    }
+                    None => status = StatusCode::NOT_FOUND,
+                }
+            }
+            return status;
+        }
+        CallbackMessage::Next {
+            id,
+            verifier,
+            payload,
+        } => (id, verifier, CallbackEvent::Next(payload), StatusCode::OK),
+        CallbackMessage::Complete {
+            id,
+            verifier,
+            errors,
+        } => (
+            id,
+            verifier,
+            CallbackEvent::Complete(errors),
+            StatusCode::ACCEPTED,
+        ),
+    };
+    match registered(&id, &verifier) {
+        // The subscription is gone once its client stopped listening.
+        Some(events) if events.send(event).await.is_ok() => status,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+/// Where the events of the subscription `id` go, if `verifier` is its own.
+fn registered(id: &str, verifier: &str) -> Option<mpsc::Sender<CallbackEvent>> {
+    CALLBACKS
+        .lock()
+        .expect("lock poisoned")
+        .get(id)
+        .filter(|callback| callback.verifier == verifier)
+        .map(|callback| callback.events.clone())
+}
+
+/// A subscription whose events are posted by the subgraph, unregistered when dropped.
+pub(crate) struct CallbackSubscription {
+    id: String,
+    service: String,
+    events: mpsc::Receiver<CallbackEvent>,
+    heartbeat_timeout: Option<Duration>,
+    complete: bool,
+}
+
+impl CallbackSubscription {
+    /// Registers a subscription to `service`, adding the `subscription` extension the subgraph
+    /// calls back with to `request`.
+    ///
+    /// The subgraph is asked for a heartbeat every `heartbeat_interval`, and the subscription ends
+    /// when it misses a few. Heartbeats are not sent with a zero interval.
+    pub(crate) fn register(
+        service: &str,
+        request: &mut Request,
+        callback_url: &Uri,
+        heartbeat_interval: Duration,
+    ) -> Self {
+        let id = Uuid::new_v4().to_string();
+        let verifier = Uuid::new_v4().to_string();
+        let (sender, events) = mpsc::channel(EVENT_BUFFER);
+        CALLBACKS.lock().expect("lock poisoned").insert(
+            id.clone(),
+            Callback {
+                verifier: verifier.clone(),
+                events: sender,
+            },
+        );
+        let callback_url = format!("{}/{}", callback_url.to_string().trim_end_matches('/'), id);
+        request.extensions.insert(
+            "subscription",
+            json!({
+                "callbackUrl": callback_url,
+                "subscriptionId": id,
+                "verifier": verifier,
+                "heartbeatIntervalMs": heartbeat_interval.as_millis() as u64,
+            }),
+        );
+        Self {
+            id,
+            service: service.to_string(),
+            events,
+            heartbeat_timeout: (!heartbeat_interval.is_zero())
+                .then(|| heartbeat_interval * MISSED_HEARTBEATS),
+            complete: false,
+        }
+    }
+
+    /// The next event of the subscription, or `None` once it is complete.
+    ///
+    /// Errors ending the subscription are returned as its last event.
+    pub(crate) async fn next(&mut self) -> Option<Result<Response, FetchError>> {
+        while !self.complete {
+            let event = match self.heartbeat_timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, self.events.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        self.complete = true;
+                        return Some(Err(FetchError::SubrequestSubscriptionError {
+                            service: self.service.clone(),
+                            reason: "the subgraph stopped sending heartbeats".to_string(),
+                        }));
+                    }
+                },
+                None => self.events.recv().await,
+            };
+            match event {
+                Some(CallbackEvent::Next(response)) => return Some(Ok(response)),
+                Some(CallbackEvent::Complete(errors)) => {
+                    self.complete = true;
+                    if !errors.is_empty() {
+                        return Some(Ok(Response::builder().errors(errors).build()));
+                    }
+                }
+                Some(CallbackEvent::Heartbeat) => {}
+                None => self.complete = true,
+            }
+        }
+        None
+    }
+}
+
+impl Drop for CallbackSubscription {
+    fn drop(&mut self) {
+        CALLBACKS.lock().expect("lock poisoned").remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn callbacks_are_verified() {
+        let mut request = Request::builder().build();
+        let mut subscription = CallbackSubscription::register(
+            "reviews",
+            &mut request,
+            &Uri::from_static("http://router:4005/callback/"),
+            Duration::from_secs(5),
+        );
+        let extension = request.extensions.get("subscription").unwrap();
+        let id = extension["subscriptionId"].as_str().unwrap().to_string();
+        let verifier = extension["verifier"].as_str().unwrap().to_string();
+        assert_eq!(
+            extension["callbackUrl"].as_str().unwrap(),
+            format!("http://router:4005/callback/{}", id)
+        );
+
+        let check = |verifier: &str| CallbackMessage::Check {
+            id: id.clone(),
+            verifier: verifier.to_string(),
+        };
+        assert_eq!(
+            handle_callback(check("forged")).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            handle_callback(check(&verifier)).await,
+            StatusCode::NO_CONTENT
+        );
+
+        let event = Response::builder()
+            .data(json!({ "reviewAdded": { "body": "great" } }))
+            .build();
+        let next = CallbackMessage::Next {
+            id: id.clone(),
+            verifier: verifier.clone(),
+            payload: event.clone(),
+        };
+        assert_eq!(handle_callback(next).await, StatusCode::OK);
+        let complete = CallbackMessage::Complete {
+            id: id.clone(),
+            verifier: verifier.clone(),
+            errors: Vec::new(),
+        };
+        assert_eq!(handle_callback(complete).await, StatusCode::ACCEPTED);
+        assert_eq!(subscription.next().await.unwrap().unwrap(), event);
+        assert!(subscription.next().await.is_none());
+
+        drop(subscription);
+        assert_eq!(
+            handle_callback(check(&verifier)).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
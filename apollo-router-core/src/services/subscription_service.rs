@@ -0,0 +1,555 @@
+//! Subscriptions, sent on to the subgraph resolving their root field.
+//!
+//! Clients subscribe on a WebSocket speaking the `graphql-transport-ws` protocol. Each of their
+//! subscriptions is sent on to its subgraph over a WebSocket of its own, opened with the
+//! `connection_init` payload of the client, so the whole selection must be resolvable by that
+//! subgraph. The events of the subgraph are shaped to the operation, then go through the
+//! subscription service of the plugins before they are sent to the client.
+//!
+//! Subgraphs may post their events to the router instead, with the callback protocol of
+//! [`super::subscription_callback`].
+
+use super::subscription_callback::CallbackSubscription;
+use crate::fetch::OperationKind;
+use crate::prelude::graphql::*;
+use futures::future::{self, Either};
+use futures::{SinkExt, StreamExt};
+use http::header::SEC_WEBSOCKET_PROTOCOL;
+use http::uri::Scheme;
+use http::{HeaderValue, StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tower::buffer::Buffer;
+use tower::util::BoxService;
+use tower::{BoxError, Service, ServiceExt};
+
+/// The WebSocket subprotocol of subscriptions.
+pub const GRAPHQL_TRANSPORT_WS: &str = "graphql-transport-ws";
+
+/// Id of the only subscription sent on each subgraph connection.
+const SUBSCRIPTION_ID: &str = "1";
+
+/// Events of a subscription waiting to be sent to the client.
+const EVENT_BUFFER: usize = 16;
+
+/// A message of the `graphql-transport-ws` protocol.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProtocolMessage {
+    ConnectionInit {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<serde_json::Value>,
+    },
+    ConnectionAck {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<serde_json::Value>,
+    },
+    Ping {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<serde_json::Value>,
+    },
+    Pong {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<serde_json::Value>,
+    },
+    Subscribe {
+        id: String,
+        payload: Request,
+    },
+    Next {
+        id: String,
+        payload: Response,
+    },
+    Error {
+        id: String,
+        payload: Vec<Error>,
+    },
+    Complete {
+        id: String,
+    },
+}
+
+/// Marks the requests received on the WebSocket of a client, with the payload of its
+/// `connection_init` message.
+///
+/// Found in the extensions of the originating request.
+#[derive(Clone, Debug, Default)]
+pub struct SubscriptionConnection {
+    pub init_payload: Option<serde_json::Value>,
+}
+
+/// The events of a subscription, found in the extensions of the response answering it.
+///
+/// The subscription is complete once the receiver is empty and closed, and is cancelled when the
+/// receiver is dropped.
+pub struct SubscriptionEvents(pub mpsc::Receiver<Response>);
+
+/// A subscription sent to a subgraph.
+struct SubgraphSubscription {
+    service: String,
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    complete: bool,
+}
+
+impl SubgraphSubscription {
+    /// Connects to `url` and sends `request` once the subgraph acknowledged the connection.
+    async fn open(
+        service: &str,
+        url: &Uri,
+        init_payload: Option<serde_json::Value>,
+        request: Request,
+        connection_init_timeout: Duration,
+    ) -> Result<Self, FetchError> {
+        let error = |reason: String| FetchError::SubrequestSubscriptionError {
+            service: service.to_string(),
+            reason,
+        };
+        let mut handshake = url
+            .to_string()
+            .into_client_request()
+            .map_err(|err| error(err.to_string()))?;
+        handshake.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(GRAPHQL_TRANSPORT_WS),
+        );
+        let (socket, _) = tokio_tungstenite::connect_async(handshake)
+            .await
+            .map_err(|err| error(err.to_string()))?;
+
+        let mut subscription = Self {
+            service: service.to_string(),
+            socket,
+            complete: false,
+        };
+        subscription
+            .send(ProtocolMessage::ConnectionInit {
+                payload: init_payload,
+            })
+            .await?;
+        tokio::time::timeout(connection_init_timeout, subscription.acknowledged())
+            .await
+            .map_err(|_| error("the connection was not acknowledged in time".to_string()))??;
+        subscription
+            .send(ProtocolMessage::Subscribe {
+                id: SUBSCRIPTION_ID.to_string(),
+                payload: request,
+            })
+            .await?;
+        Ok(subscription)
+    }
+
+    async fn acknowledged(&mut self) -> Result<(), FetchError> {
+        loop {
+            match self.receive().await? {
+                Some(ProtocolMessage::ConnectionAck { .. }) => return Ok(()),
+                Some(ProtocolMessage::Ping { .. }) => {
+                    self.send(ProtocolMessage::Pong { payload: None }).await?
+                }
+                Some(ProtocolMessage::Pong { .. }) => {}
+                Some(message) => {
+                    return Err(self.error(format!(
+                        "unexpected message before the connection was acknowledged: {:?}",
+                        message
+                    )))
+                }
+                None => return Err(self.error("the connection was closed")),
+            }
+        }
+    }
+
+    /// The next event of the subscription, or `None` once it is complete.
+    ///
+    /// Errors ending the subscription are returned as its last event.
+    async fn next(&mut self) -> Option<Result<Response, FetchError>> {
+        while !self.complete {
+            let message = match self.receive().await {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    self.complete = true;
+                    return Some(Err(self.error("the connection was closed")));
+                }
+                Err(err) => {
+                    self.complete = true;
+                    return Some(Err(err));
+                }
+            };
+            match message {
+                ProtocolMessage::Next { id, payload } if id == SUBSCRIPTION_ID => {
+                    return Some(Ok(payload))
+                }
+                ProtocolMessage::Error { id, payload } if id == SUBSCRIPTION_ID => {
+                    self.complete = true;
+                    return Some(Ok(Response::builder().errors(payload).build()));
+                }
+                ProtocolMessage::Complete { id } if id == SUBSCRIPTION_ID => self.complete = true,
+                ProtocolMessage::Ping { .. } => {
+                    if let Err(err) = self.send(ProtocolMessage::Pong { payload: None }).await {
+                        self.complete = true;
+                        return Some(Err(err));
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Completes the subscription if the subgraph did not, and closes the connection.
+    async fn close(mut self) {
+        if !self.complete {
+            let _ = self
+                .send(ProtocolMessage::Complete {
+                    id: SUBSCRIPTION_ID.to_string(),
+                })
+                .await;
+        }
+        let _ = self.socket.close(None).await;
+    }
+
+    async fn receive(&mut self) -> Result<Option<ProtocolMessage>, FetchError> {
+        while let Some(message) = self.socket.next().await {
+            match message.map_err(|err| self.error(err))? {
+                Message::Text(text) => {
+                    return serde_json::from_str(&text)
+                        .map(Some)
+                        .map_err(|err| self.error(err))
+                }
+                Message::Close(_) => return Ok(None),
+                // WebSocket pings are answered by the socket itself.
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    async fn send(&mut self, message: ProtocolMessage) -> Result<(), FetchError> {
+        let text = serde_json::to_string(&message).expect("protocol messages are serializable");
+        self.socket
+            .send(Message::Text(text))
+            .await
+            .map_err(|err| self.error(err))
+    }
+
+    fn error(&self, reason: impl fmt::Display) -> FetchError {
+        FetchError::SubrequestSubscriptionError {
+            service: self.service.clone(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// A subscription sent to a subgraph, on a WebSocket or with a callback URL.
+enum Upstream {
+    WebSocket(SubgraphSubscription),
+    Callback(CallbackSubscription),
+}
+
+impl Upstream {
+    async fn next(&mut self) -> Option<Result<Response, FetchError>> {
+        match self {
+            Upstream::WebSocket(subscription) => subscription.next().await,
+            Upstream::Callback(subscription) => subscription.next().await,
+        }
+    }
+
+    async fn close(self) {
+        match self {
+            Upstream::WebSocket(subscription) => subscription.close().await,
+            // The subgraph is told the subscription is over at its next call.
+            Upstream::Callback(_) => {}
+        }
+    }
+}
+
+/// The subgraphs posting the events of their subscriptions to the router.
+pub(crate) struct SubscriptionCallback {
+    /// The URL the subgraphs call back, followed by the id of the subscription.
+    pub(crate) url: Uri,
+    /// All subgraphs when empty.
+    pub(crate) subgraphs: Vec<String>,
+    pub(crate) heartbeat_interval: Duration,
+}
+
+/// Sends the subscriptions of clients to their subgraphs.
+pub struct Subscriptions {
+    endpoints: HashMap<String, Uri>,
+    connection_init_timeout: Duration,
+    callback: Option<SubscriptionCallback>,
+    subgraph_services: HashMap<
+        String,
+        Buffer<BoxService<SubgraphRequest, SubgraphResponse, BoxError>, SubgraphRequest>,
+    >,
+    event_service: Buffer<
+        BoxService<SubscriptionRequest, SubscriptionResponse, BoxError>,
+        SubscriptionRequest,
+    >,
+}
+
+impl Subscriptions {
+    /// Subgraphs are subscribed to at their URL with the `ws` or `wss` scheme, unless `overrides`
+    /// has another URL for them, or through their `subgraph_services` with the `callback`.
+    pub(crate) fn new(
+        schema: &Schema,
+        overrides: &HashMap<String, Uri>,
+        connection_init_timeout: Duration,
+        callback: Option<SubscriptionCallback>,
+        subgraph_services: HashMap<
+            String,
+            Buffer<BoxService<SubgraphRequest, SubgraphResponse, BoxError>, SubgraphRequest>,
+        >,
+        event_service: Buffer<
+            BoxService<SubscriptionRequest, SubscriptionResponse, BoxError>,
+            SubscriptionRequest,
+        >,
+    ) -> Self {
+        let endpoints = schema
+            .subgraphs()
+            .map(|(name, url)| {
+                let url = overrides
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| websocket_url(url));
+                (name.clone(), url)
+            })
+            .collect();
+        Self {
+            endpoints,
+            connection_init_timeout,
+            callback,
+            subgraph_services,
+            event_service,
+        }
+    }
+
+    /// Subscribes to the subgraph resolving the root field of `query`.
+    ///
+    /// The response has an empty body, and the events of the subscription in its extensions.
+    pub(crate) async fn subscribe(
+        &self,
+        query: Arc<Query>,
+        schema: Arc<Schema>,
+        originating_request: Arc<http_compat::Request<Request>>,
+        unauthorized_errors: Vec<Error>,
+        context: Context,
+    ) -> Result<RouterResponse, BoxError> {
+        let body = originating_request.body();
+        let operation_name = body.operation_name.clone();
+        let service = match query.subscription_subgraph(operation_name.as_deref(), &schema) {
+            Ok(service) => service.to_string(),
+            Err(reason) => {
+                let mut extensions = Object::default();
+                extensions.insert("code", "QUERY_PLANNING_FAILED".into());
+                return RouterResponse::builder()
+                    .errors(vec![Error {
+                        message: reason,
+                        extensions,
+                        ..Default::default()
+                    }])
+                    .status_code(StatusCode::BAD_REQUEST)
+                    .context(context)
+                    .build();
+            }
+        };
+        let mut upstream = match &self.callback {
+            Some(callback)
+                if callback.subgraphs.is_empty() || callback.subgraphs.contains(&service) =>
+            {
+                Upstream::Callback(
+                    self.subscribe_with_callback(
+                        callback,
+                        &service,
+                        &schema,
+                        originating_request.clone(),
+                        context.clone(),
+                    )
+                    .await?,
+                )
+            }
+            _ => {
+                let url = self.endpoints.get(&service).ok_or_else(|| {
+                    FetchError::ValidationUnknownServiceError {
+                        service: service.clone(),
+                    }
+                })?;
+                let init_payload = originating_request
+                    .extensions()
+                    .get::<SubscriptionConnection>()
+                    .and_then(|connection| connection.init_payload.clone());
+                Upstream::WebSocket(
+                    SubgraphSubscription::open(
+                        &service,
+                        url,
+                        init_payload,
+                        body.clone(),
+                        self.connection_init_timeout,
+                    )
+                    .await?,
+                )
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        let mut event_service = self.event_service.clone();
+        let event_context = context.clone();
+        let variables = body.variables.clone();
+        tokio::spawn(async move {
+            loop {
+                // The subgraph is unsubscribed from once the client is not listening anymore.
+                let event = {
+                    let next = upstream.next();
+                    let closed = sender.closed();
+                    futures::pin_mut!(next, closed);
+                    match future::select(next, closed).await {
+                        Either::Left((event, _)) => event,
+                        Either::Right(_) => break,
+                    }
+                };
+                let mut event = match event {
+                    Some(Ok(event)) => event,
+                    Some(Err(err)) => err.to_response(),
+                    None => break,
+                };
+                query.format_response(
+                    &mut event,
+                    operation_name.as_deref(),
+                    (*variables).clone(),
+                    schema.api_schema(),
+                );
+                event.errors.extend(unauthorized_errors.iter().cloned());
+
+                let request = SubscriptionRequest::new(
+                    originating_request.clone(),
+                    event,
+                    event_context.clone(),
+                );
+                let event = match event_service.ready().await {
+                    Ok(service) => service.call(request).await,
+                    Err(err) => Err(err),
+                };
+                let event = match event {
+                    Ok(response) => response.event,
+                    Err(err) => Response::builder()
+                        .errors(vec![Error::from_box_error(&err)])
+                        .build(),
+                };
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+            upstream.close().await;
+        });
+
+        let mut response = RouterResponse::builder().context(context).build()?;
+        response
+            .response
+            .extensions_mut()
+            .insert(SubscriptionEvents(receiver));
+        Ok(response)
+    }
+
+    /// Sends the subscription to `service` as any request, through its subgraph service, with the
+    /// URL it calls back with the events.
+    async fn subscribe_with_callback(
+        &self,
+        callback: &SubscriptionCallback,
+        service: &str,
+        schema: &Schema,
+        originating_request: Arc<http_compat::Request<Request>>,
+        context: Context,
+    ) -> Result<CallbackSubscription, FetchError> {
+        let error = |reason: String| FetchError::SubrequestSubscriptionError {
+            service: service.to_string(),
+            reason,
+        };
+        let unknown = || FetchError::ValidationUnknownServiceError {
+            service: service.to_string(),
+        };
+        let url = schema
+            .subgraphs()
+            .find_map(|(name, url)| (name == service).then(|| url.clone()))
+            .ok_or_else(unknown)?;
+        let subgraph_service = self.subgraph_services.get(service).ok_or_else(unknown)?;
+
+        let mut body = originating_request.body().clone();
+        // Registered before it is sent, as the subgraph checks the callback URL first.
+        let subscription = CallbackSubscription::register(
+            service,
+            &mut body,
+            &callback.url,
+            callback.heartbeat_interval,
+        );
+        let request = SubgraphRequest::builder()
+            .originating_request(originating_request)
+            .subgraph_request(
+                http_compat::Request::builder()
+                    .method(http::Method::POST)
+                    .uri(url)
+                    .body(body)
+                    .build()
+                    .map_err(|err| error(err.to_string()))?,
+            )
+            .operation_kind(OperationKind::Subscription)
+            .context(context)
+            .build();
+        let response = subgraph_service
+            .clone()
+            .oneshot(request)
+            .await
+            .map_err(|err| error(err.to_string()))?;
+        let errors = &response.response.body().errors;
+        if !errors.is_empty() {
+            let messages = errors
+                .iter()
+                .map(|error| error.message.as_str())
+                .collect::<Vec<_>>();
+            return Err(error(messages.join(", ")));
+        }
+        Ok(subscription)
+    }
+}
+
+/// `url` with the WebSocket scheme matching its own.
+fn websocket_url(url: &Uri) -> Uri {
+    let scheme = match url.scheme_str() {
+        Some("https") => "wss",
+        _ => "ws",
+    };
+    let mut parts = url.clone().into_parts();
+    parts.scheme = Some(
+        scheme
+            .parse::<Scheme>()
+            .expect("ws and wss are valid schemes"),
+    );
+    Uri::from_parts(parts).unwrap_or_else(|_| url.clone())
+}
+
+/// The innermost subscription service, sending the events on as they are.
+pub(crate) struct SubscriptionService;
+
+impl tower::Service<SubscriptionRequest> for SubscriptionService {
+    type Response = SubscriptionResponse;
+    type Error = BoxError;
+    type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: SubscriptionRequest) -> Self::Future {
+        std::future::ready(Ok(SubscriptionResponse::new(
+            request.event,
+            request.context,
+        )))
+    }
+}
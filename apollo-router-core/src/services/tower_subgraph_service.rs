@@ -1,73 +1,571 @@
 //! Tower fetcher for subgraphs.
+//!
+//! Each subgraph gets a client of its own, keeping a pool of connections to it. HTTP/2 is used
+//! with subgraphs that negotiate it, so that requests are multiplexed on a few connections.
 
+use super::load_balancing::{spawn_checks, Balancer, LoadBalancing, Target};
+use super::subgraph_auth::{Authenticator, SubgraphAuth};
+use super::subgraph_routing::{RoutedTo, Router, SubgraphRouting};
 use crate::prelude::*;
+use crate::{read_certificates, read_private_key};
+use bytes::{Bytes, BytesMut};
 use futures::future::BoxFuture;
 use global::get_text_map_propagator;
 use http::{
     header::{ACCEPT, CONTENT_TYPE},
-    HeaderValue,
+    HeaderValue, Uri,
 };
+use hyper::body::HttpBody;
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::client::connect::{Connected, Connection};
 use hyper::client::HttpConnector;
-use hyper_rustls::HttpsConnector;
+use hyper_rustls::{ConfigBuilderExt, HttpsConnector};
 use opentelemetry::global;
-use std::sync::Arc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::task::Poll;
-use tower::{BoxError, ServiceBuilder};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tower::{BoxError, ServiceExt};
+use tower_http::decompression::Decompression;
 use tracing::{Instrument, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+/// Connection pooling of the subgraph clients.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphClientConfig {
+    /// Maximum number of idle connections kept open to each subgraph. Unlimited by default.
+    #[serde(default)]
+    pub max_idle_connections: Option<usize>,
+    /// Time after which idle connections are closed. Defaults to 90s.
+    #[serde(with = "humantime_serde", default = "default_idle_timeout")]
+    #[schemars(with = "String")]
+    pub idle_timeout: Duration,
+    /// Use HTTP/2 with every subgraph, without negotiating it, including on `http` URLs.
+    /// Subgraphs on `https` URLs negotiate HTTP/2 regardless. Disabled by default.
+    #[serde(default)]
+    pub http2_only: bool,
+    /// Interval of the TCP keep-alive probes and HTTP/2 pings keeping connections open, even
+    /// while idle. Disabled by default.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String")]
+    pub keep_alive_interval: Option<Duration>,
+    /// Time after which the client starts a new pool, resolving the subgraph host names again.
+    /// The connections of the previous pool close once their requests are answered.
+    /// By default, host names are only resolved when a connection is opened.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String")]
+    pub dns_refresh: Option<Duration>,
+}
+
+fn default_idle_timeout() -> Duration {
+    Duration::from_secs(90)
+}
+
+impl Default for SubgraphClientConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_connections: None,
+            idle_timeout: default_idle_timeout(),
+            http2_only: false,
+            keep_alive_interval: None,
+            dns_refresh: None,
+        }
+    }
+}
+
+/// TLS settings of the connections to subgraphs on `https` URLs.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphTlsConfig {
+    /// Applied to each subgraph, unless overridden in `subgraphs`.
+    #[serde(default)]
+    pub all: Option<SubgraphTls>,
+    #[serde(default)]
+    pub subgraphs: HashMap<String, SubgraphTls>,
+}
+
+impl SubgraphTlsConfig {
+    /// The settings of the `name` subgraph, if any.
+    pub fn subgraph(&self, name: &str) -> Option<&SubgraphTls> {
+        self.subgraphs.get(name).or(self.all.as_ref())
+    }
+}
+
+/// TLS settings of the connections to a subgraph, with PEM files.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphTls {
+    /// Path of the certificates of the authorities trusted to sign the certificate of the
+    /// subgraph, instead of the roots of the system.
+    #[serde(default)]
+    pub certificate_authorities: Option<PathBuf>,
+    /// Path of the certificate chain presented to the subgraph, for mutual TLS.
+    #[serde(default)]
+    pub client_certificate: Option<PathBuf>,
+    /// Path of the private key of `client_certificate`.
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    /// Name sent with SNI and checked against the certificate of the subgraph, instead of the
+    /// host of its URL.
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+impl SubgraphTls {
+    fn client_config(&self) -> Result<rustls::ClientConfig, BoxError> {
+        let builder = rustls::ClientConfig::builder().with_safe_defaults();
+        let builder = match &self.certificate_authorities {
+            Some(path) => {
+                let mut roots = rustls::RootCertStore::empty();
+                for certificate in read_certificates(path)? {
+                    roots.add(&certificate).map_err(|err| {
+                        format!("invalid certificate in {}: {}", path.display(), err)
+                    })?;
+                }
+                builder.with_root_certificates(roots)
+            }
+            None => builder.with_native_roots(),
+        };
+        match (&self.client_certificate, &self.client_key) {
+            (Some(certificate), Some(key)) => Ok(builder
+                .with_single_cert(read_certificates(certificate)?, read_private_key(key)?)
+                .map_err(|err| format!("invalid client certificate: {}", err))?),
+            (None, None) => Ok(builder.with_no_client_auth()),
+            _ => Err("client_certificate and client_key must be set together".into()),
+        }
+    }
+}
+
+/// Extension of subgraph responses, with the usage of the connection pool once they were received.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolUsage {
+    /// Connections open to the subgraph, idle or not.
+    pub open_connections: usize,
+    /// Requests sent to the subgraph and not answered yet.
+    pub in_flight_requests: usize,
+}
+
+pub(super) type Client = hyper::Client<CountingConnector>;
+
 /// Client for interacting with subgraphs.
 #[derive(Clone)]
 pub struct TowerSubgraphService {
-    client: hyper::Client<HttpsConnector<HttpConnector>>,
+    pool: Arc<Pool>,
     service: Arc<String>,
+    max_response_bytes: Option<usize>,
+    compression: bool,
+    router: Option<Arc<Router>>,
+    balancer: Option<Arc<Balancer>>,
+    authenticator: Option<Arc<Authenticator>>,
 }
 
 impl TowerSubgraphService {
     pub fn new(service: impl Into<String>) -> Self {
-        let connector = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_or_http()
-            .enable_http1()
-            .enable_http2()
-            .build();
+        Self::with_config(service, SubgraphClientConfig::default())
+    }
 
+    pub fn with_config(service: impl Into<String>, config: SubgraphClientConfig) -> Self {
         Self {
-            client: ServiceBuilder::new().service(hyper::Client::builder().build(connector)),
+            pool: Arc::new(Pool::new(config, None)),
             service: Arc::new(service.into()),
+            max_response_bytes: None,
+            compression: false,
+            router: None,
+            balancer: None,
+            authenticator: None,
+        }
+    }
+
+    /// Connects to the subgraph with `tls`, reading its files right away.
+    pub fn with_tls(mut self, tls: &SubgraphTls) -> Result<Self, BoxError> {
+        self.pool = Arc::new(Pool::new(
+            self.pool.config.clone(),
+            Some(ClientTls::new(tls)?),
+        ));
+        Ok(self)
+    }
+
+    /// Authenticates the requests with `auth`.
+    pub fn with_auth(mut self, auth: &SubgraphAuth) -> Self {
+        self.authenticator = Some(Arc::new(Authenticator::new(auth.clone())));
+        self
+    }
+
+    /// Fails fetches whose response body is larger than `max_response_bytes` once decompressed,
+    /// without reading the rest of it.
+    pub fn with_max_response_bytes(mut self, max_response_bytes: Option<usize>) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Asks the subgraph for compressed responses, with gzip, brotli or deflate.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sends the requests to the targets of `routing` instead of the subgraph URL.
+    pub fn with_routing(mut self, routing: &SubgraphRouting) -> Result<Self, BoxError> {
+        self.router = Some(Arc::new(Router::new(routing)?));
+        Ok(self)
+    }
+
+    /// Balances the requests over the endpoints of `load_balancing`, instead of the subgraph URL.
+    /// Their health is checked in the background for as long as the service is used.
+    pub fn with_load_balancing(mut self, load_balancing: &LoadBalancing) -> Result<Self, BoxError> {
+        let balancer = Arc::new(Balancer::new(&self.service, load_balancing)?);
+        spawn_checks(&balancer, self.pool.clone());
+        self.balancer = Some(balancer);
+        Ok(self)
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct ClientTls {
+    config: rustls::ClientConfig,
+    server_name: Option<String>,
+}
+
+impl ClientTls {
+    pub(super) fn new(tls: &SubgraphTls) -> Result<Self, BoxError> {
+        Ok(Self {
+            config: tls.client_config()?,
+            server_name: tls.server_name.clone(),
+        })
+    }
+}
+
+/// The client of a subgraph, replaced when its DNS refresh is due.
+pub(super) struct Pool {
+    pub(super) config: SubgraphClientConfig,
+    tls: Option<ClientTls>,
+    client: Mutex<(Instant, Client)>,
+    open_connections: Arc<AtomicUsize>,
+    in_flight_requests: Arc<AtomicUsize>,
+}
+
+impl Pool {
+    pub(super) fn new(config: SubgraphClientConfig, tls: Option<ClientTls>) -> Self {
+        let open_connections = Arc::new(AtomicUsize::new(0));
+        let client = build_client(&config, tls.as_ref(), &open_connections, Resolver::system());
+        Self {
+            config,
+            tls,
+            client: Mutex::new((Instant::now(), client)),
+            open_connections,
+            in_flight_requests: Default::default(),
+        }
+    }
+
+    pub(super) fn client(&self) -> Client {
+        let mut client = self.client.lock().expect("lock poisoned");
+        if let Some(refresh) = self.config.dns_refresh {
+            if client.0.elapsed() >= refresh {
+                *client = (
+                    Instant::now(),
+                    build_client(
+                        &self.config,
+                        self.tls.as_ref(),
+                        &self.open_connections,
+                        Resolver::system(),
+                    ),
+                );
+            }
+        }
+        client.1.clone()
+    }
+
+    /// A client connecting to `address`, whatever the host of the requests, which is still the
+    /// one they are sent for and the server name of their TLS connections.
+    pub(super) fn client_to(&self, address: SocketAddr) -> Client {
+        build_client(
+            &self.config,
+            self.tls.as_ref(),
+            &self.open_connections,
+            Resolver::Fixed(address),
+        )
+    }
+
+    fn usage(&self) -> PoolUsage {
+        PoolUsage {
+            open_connections: self.open_connections.load(Ordering::Relaxed),
+            in_flight_requests: self.in_flight_requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn build_client(
+    config: &SubgraphClientConfig,
+    tls: Option<&ClientTls>,
+    open_connections: &Arc<AtomicUsize>,
+    resolver: Resolver,
+) -> Client {
+    let mut http = HttpConnector::new_with_resolver(resolver);
+    http.enforce_http(false);
+    http.set_keepalive(config.keep_alive_interval);
+    let builder = match tls {
+        Some(tls) => hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(tls.config.clone()),
+        None => hyper_rustls::HttpsConnectorBuilder::new().with_native_roots(),
+    }
+    .https_or_http();
+    let builder = match tls.and_then(|tls| tls.server_name.clone()) {
+        Some(server_name) => builder.with_server_name(server_name),
+        None => builder,
+    };
+    let connector = builder.enable_http1().enable_http2().wrap_connector(http);
+
+    let mut builder = hyper::Client::builder();
+    builder
+        .pool_idle_timeout(config.idle_timeout)
+        .http2_only(config.http2_only);
+    if let Some(max_idle_connections) = config.max_idle_connections {
+        builder.pool_max_idle_per_host(max_idle_connections);
+    }
+    if let Some(interval) = config.keep_alive_interval {
+        builder
+            .http2_keep_alive_interval(interval)
+            .http2_keep_alive_while_idle(true);
+    }
+    builder.build(CountingConnector {
+        inner: connector,
+        open_connections: open_connections.clone(),
+    })
+}
+
+/// Resolves the hosts of the subgraphs, unless the connections go to a known address.
+#[derive(Clone)]
+pub(super) enum Resolver {
+    System(GaiResolver),
+    Fixed(SocketAddr),
+}
+
+impl Resolver {
+    fn system() -> Self {
+        Resolver::System(GaiResolver::new())
+    }
+}
+
+impl tower::Service<Name> for Resolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            Resolver::System(resolver) => resolver.poll_ready(cx),
+            Resolver::Fixed(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        match self {
+            Resolver::System(resolver) => {
+                let resolving = resolver.call(name);
+                Box::pin(async move { Ok(resolving.await?.collect::<Vec<_>>().into_iter()) })
+            }
+            Resolver::Fixed(address) => {
+                let address = *address;
+                Box::pin(async move { Ok(vec![address].into_iter()) })
+            }
         }
     }
 }
 
+/// Connector counting the connections it opened that are still open.
+#[derive(Clone)]
+pub(super) struct CountingConnector {
+    inner: HttpsConnector<HttpConnector<Resolver>>,
+    open_connections: Arc<AtomicUsize>,
+}
+
+impl tower::Service<Uri> for CountingConnector {
+    type Response = CountedConnection<
+        <HttpsConnector<HttpConnector<Resolver>> as tower::Service<Uri>>::Response,
+    >;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.inner.call(uri);
+        let open_connections = self.open_connections.clone();
+        Box::pin(async move {
+            let stream = connecting.await?;
+            open_connections.fetch_add(1, Ordering::Relaxed);
+            Ok(CountedConnection {
+                stream,
+                open_connections,
+            })
+        })
+    }
+}
+
+pub(super) struct CountedConnection<S> {
+    stream: S,
+    open_connections: Arc<AtomicUsize>,
+}
+
+impl<S> Drop for CountedConnection<S> {
+    fn drop(&mut self) {
+        self.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<S: Connection> Connection for CountedConnection<S> {
+    fn connected(&self) -> Connected {
+        // Carries the protocol negotiated with ALPN.
+        self.stream.connected()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountedConnection<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountedConnection<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Counts a request as in flight until dropped.
+struct InFlight(Arc<Pool>);
+
+impl InFlight {
+    fn new(pool: &Arc<Pool>) -> Self {
+        pool.in_flight_requests.fetch_add(1, Ordering::Relaxed);
+        Self(pool.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight_requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
     type Response = graphql::SubgraphResponse;
     type Error = BoxError;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
-    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.client
-            .poll_ready(cx)
-            .map(|res| res.map_err(|e| Box::new(e) as BoxError))
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The pool opens connections as requests need them.
+        Poll::Ready(Ok(()))
     }
 
     fn call(&mut self, request: graphql::SubgraphRequest) -> Self::Future {
         let graphql::SubgraphRequest {
+            originating_request,
             subgraph_request,
             context,
             ..
         } = request;
 
-        let mut client = self.client.clone();
+        let pool = self.pool.clone();
         let service_name = (*self.service).to_owned();
+        let max_response_bytes = self.max_response_bytes;
+        let compression = self.compression;
+        let router = self.router.clone();
+        let balancer = self.balancer.clone();
+        let authenticator = self.authenticator.clone();
 
         Box::pin(async move {
-            let (parts, body) = subgraph_request.into_parts();
+            let (mut parts, body) = subgraph_request.into_parts();
+            let routed_to = router.as_ref().map(|router| {
+                let (name, uri) = router.route(originating_request.headers());
+                parts.uri = uri.clone();
+                RoutedTo(name.to_string())
+            });
+            let mut client = pool.client();
+            let balanced = balancer.map(|balancer| {
+                let (target, target_client) = balancer.pick();
+                parts.uri = target.uri.clone();
+                if let Some(target_client) = target_client {
+                    client = target_client;
+                }
+                (balancer, target)
+            });
+
+            let uploads = originating_request
+                .extensions()
+                .get::<graphql::Uploads>()
+                .map(|uploads| (uploads, uploads.map_of(&body.variables)))
+                .filter(|(_, map)| !map.is_empty());
 
-            let body = serde_json::to_string(&body).expect("JSON serialization should not fail");
+            let body =
+                Bytes::from(serde_json::to_vec(&body).expect("JSON serialization should not fail"));
+            // multipart bodies are streamed, and cannot be signed
+            let payload = uploads.is_none().then(|| body.clone());
 
-            let mut request = http::request::Request::from_parts(parts, body.into());
             let app_json: HeaderValue = "application/json".parse().unwrap();
-            request.headers_mut().insert(CONTENT_TYPE, app_json.clone());
+            let mut request = match uploads {
+                // The files used by the variables of this request are sent along.
+                Some((uploads, map)) => {
+                    let (content_type, body) = uploads.body(body, map);
+                    let mut request = http::request::Request::from_parts(parts, body);
+                    request.headers_mut().insert(CONTENT_TYPE, content_type);
+                    // Multipart requests are otherwise rejected as potential CSRF by subgraphs.
+                    request
+                        .headers_mut()
+                        .insert("apollo-require-preflight", HeaderValue::from_static("true"));
+                    request
+                }
+                None => {
+                    let mut request = http::request::Request::from_parts(parts, body.into());
+                    request.headers_mut().insert(CONTENT_TYPE, app_json.clone());
+                    request
+                }
+            };
             request.headers_mut().insert(ACCEPT, app_json);
 
             get_text_map_propagator(|propagator| {
@@ -77,26 +575,38 @@ impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
                 )
             });
 
-            let response = client.call(request).await.map_err(|err| {
-                tracing::error!(fetch_error = format!("{:?}", err).as_str());
+            if let Some(authenticator) = &authenticator {
+                authenticator
+                    .authenticate(&mut request, payload.as_deref(), &client)
+                    .await
+                    .map_err(|err| http_error(&service_name, err))?;
+            }
 
-                graphql::FetchError::SubrequestHttpError {
-                    service: service_name.clone(),
-                    reason: err.to_string(),
-                }
-            })?;
-
-            let body = hyper::body::to_bytes(response.into_body())
-                .instrument(tracing::debug_span!("aggregate_response_data"))
-                .await
-                .map_err(|err| {
-                    tracing::error!(fetch_error = format!("{:?}", err).as_str());
-
-                    graphql::FetchError::SubrequestHttpError {
-                        service: service_name.clone(),
-                        reason: err.to_string(),
-                    }
-                })?;
+            let in_flight = InFlight::new(&pool);
+            let body = if compression {
+                // Sends `Accept-Encoding`, and decompresses the body as it is read.
+                let response = Decompression::new(client).oneshot(request).await;
+                report_health(
+                    &balanced,
+                    response.as_ref().map(|response| response.status()),
+                );
+                let response = response.map_err(|err| http_error(&service_name, err))?;
+                read_body(response.into_body(), max_response_bytes, &service_name)
+                    .instrument(tracing::debug_span!("aggregate_response_data"))
+                    .await?
+            } else {
+                let response = client.request(request).await;
+                report_health(
+                    &balanced,
+                    response.as_ref().map(|response| response.status()),
+                );
+                let response = response.map_err(|err| http_error(&service_name, err))?;
+                read_body(response.into_body(), max_response_bytes, &service_name)
+                    .instrument(tracing::debug_span!("aggregate_response_data"))
+                    .await?
+            };
+            drop(in_flight);
+            let usage = pool.usage();
 
             let graphql: graphql::Response = tracing::debug_span!("parse_subgraph_response")
                 .in_scope(|| {
@@ -108,10 +618,182 @@ impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
                     })
                 })?;
 
+            let mut response = http::Response::builder().extension(usage);
+            if let Some(routed_to) = routed_to {
+                response = response.extension(routed_to);
+            }
             Ok(graphql::SubgraphResponse::new_from_response(
-                http::Response::builder().body(graphql).expect("no argument can fail to parse or converted to the internal representation here; qed").into(),
+                response.body(graphql).expect("no argument can fail to parse or converted to the internal representation here; qed").into(),
                 context,
             ))
         })
     }
 }
+
+/// Tells the balancer whether the endpoint of a request answered without a server error.
+fn report_health<E>(
+    balanced: &Option<(Arc<Balancer>, Target)>,
+    status: Result<http::StatusCode, E>,
+) {
+    if let Some((balancer, target)) = balanced {
+        balancer.report(
+            target,
+            matches!(status, Ok(status) if !status.is_server_error()),
+        );
+    }
+}
+
+pub(super) fn http_error(
+    service_name: &str,
+    err: impl fmt::Debug + fmt::Display,
+) -> graphql::FetchError {
+    tracing::error!(fetch_error = format!("{:?}", err).as_str());
+
+    graphql::FetchError::SubrequestHttpError {
+        service: service_name.to_string(),
+        reason: err.to_string(),
+    }
+}
+
+/// Reads a subgraph response body, failing as soon as it goes over `max_bytes`.
+pub(super) async fn read_body<B>(
+    body: B,
+    max_bytes: Option<usize>,
+    service_name: &str,
+) -> Result<Bytes, graphql::FetchError>
+where
+    B: HttpBody<Data = Bytes>,
+    B::Error: fmt::Debug + fmt::Display,
+{
+    let max_bytes = match max_bytes {
+        Some(max_bytes) => max_bytes,
+        None => {
+            return hyper::body::to_bytes(body)
+                .await
+                .map_err(|err| http_error(service_name, err))
+        }
+    };
+    let too_large = || graphql::FetchError::SubrequestResponseTooLarge {
+        service: service_name.to_string(),
+        limit: max_bytes,
+    };
+
+    let mut body = Box::pin(body);
+    // A `Content-Length` over the limit fails the fetch before anything is read.
+    if body.size_hint().lower() > max_bytes as u64 {
+        return Err(too_large());
+    }
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| http_error(service_name, err))?;
+        if buffer.len() + chunk.len() > max_bytes {
+            return Err(too_large());
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn response_bodies_over_the_limit_fail_the_fetch() {
+        let body = || hyper::Body::from(r#"{"data":{"me":{"name":"Ada"}}}"#);
+
+        assert!(read_body(body(), None, "accounts").await.is_ok());
+        assert!(read_body(body(), Some(64), "accounts").await.is_ok());
+        assert!(matches!(
+            read_body(body(), Some(16), "accounts").await,
+            Err(graphql::FetchError::SubrequestResponseTooLarge { limit: 16, .. })
+        ));
+
+        let (mut sender, streamed) = hyper::Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..4 {
+                if sender
+                    .send_data(Bytes::from_static(b"xxxxxxxx"))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        assert!(matches!(
+            read_body(streamed, Some(16), "accounts").await,
+            Err(graphql::FetchError::SubrequestResponseTooLarge { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn connections_are_pooled() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                axum::Json(serde_json::json!({ "data": { "me": { "name": "Ada" } } }))
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let mut service = TowerSubgraphService::new("accounts");
+        for _ in 0..3 {
+            let request = graphql::SubgraphRequest::fake_builder()
+                .subgraph_request(
+                    graphql::http_compat::Request::fake_builder()
+                        .uri(Uri::from_str(&format!("http://{}/", address)).unwrap())
+                        .method(http::Method::POST)
+                        .body(
+                            graphql::Request::builder()
+                                .query(Some("{ me { name } }".to_string()))
+                                .build(),
+                        )
+                        .build()
+                        .unwrap(),
+                )
+                .build();
+            let response = tower::Service::call(&mut service, request).await.unwrap();
+
+            assert_eq!(
+                response.response.extensions().get::<PoolUsage>(),
+                Some(&PoolUsage {
+                    open_connections: 1,
+                    in_flight_requests: 0,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn subgraph_tls_files_are_read() {
+        let testdata = |name: &str| {
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../apollo-router/src/testdata/tls")
+                .join(name)
+        };
+        let tls = SubgraphTls {
+            certificate_authorities: Some(testdata("ca.crt")),
+            client_certificate: Some(testdata("server.crt")),
+            client_key: Some(testdata("server.key")),
+            server_name: Some("localhost".to_string()),
+        };
+        assert!(TowerSubgraphService::new("accounts").with_tls(&tls).is_ok());
+
+        let tls = SubgraphTls {
+            client_key: None,
+            ..tls
+        };
+        assert!(TowerSubgraphService::new("accounts")
+            .with_tls(&tls)
+            .is_err());
+    }
+}
@@ -0,0 +1,579 @@
+//! Access requirements declared in the schema with `@authenticated` and `@requiresScopes`.
+//!
+//! `@authenticated` restricts a type or field to clients with verified claims, and
+//! `@requiresScopes(scopes: [[String!]!]!)` further requires all the scopes of one of the listed
+//! sets to be granted by the space separated `scope` claim.
+//!
+//! Fields of interfaces carry the requirements of the fields implementing them, and abstract types
+//! those of their possible types, so that nothing is reached through an interface that could not
+//! be queried directly. The fields a client may not query are removed from its query, which is
+//! executed without them.
+
+use super::directives::argument;
+use crate::{operation_kind, FragmentWalk, Fragments, Path, PathElement, Schema, Selection, Value};
+use apollo_parser::ast::{self, AstNode};
+use std::collections::{HashMap, HashSet};
+
+/// Context key holding the verified claims of the client, which the authorization directives of
+/// the schema are checked against.
+pub const AUTHENTICATION_CLAIMS_CONTEXT_KEY: &str = "apollo::authentication::jwt_claims";
+
+/// What a client needs to access a type or a field.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Requirements {
+    authenticated: bool,
+    /// Sets of scopes, any of which is enough when fully granted.
+    scopes: Vec<Vec<String>>,
+}
+
+impl Requirements {
+    fn from_directives(directives: Option<ast::Directives>) -> Option<Self> {
+        let mut requirements = Requirements::default();
+        for directive in directives
+            .iter()
+            .flat_map(|directives| directives.directives())
+        {
+            match directive
+                .name()
+                .map(|name| name.text().to_string())
+                .as_deref()
+            {
+                Some("authenticated") => requirements.authenticated = true,
+                Some("requiresScopes") => {
+                    requirements.authenticated = true;
+                    requirements.scopes.extend(scopes(&directive));
+                }
+                _ => {}
+            }
+        }
+        if requirements == Requirements::default() {
+            None
+        } else {
+            Some(requirements)
+        }
+    }
+
+    pub(crate) fn are_met(&self, claims: Option<&Value>) -> bool {
+        let claims = match claims {
+            Some(claims) => claims,
+            None => return !self.authenticated,
+        };
+        if self.scopes.is_empty() {
+            return true;
+        }
+
+        let granted: HashSet<&str> = claims
+            .as_object()
+            .and_then(|claims| claims.get("scope"))
+            .and_then(|scope| scope.as_str())
+            .map(|scope| scope.split_whitespace().collect())
+            .unwrap_or_default();
+        self.scopes
+            .iter()
+            .any(|set| set.iter().all(|scope| granted.contains(scope.as_str())))
+    }
+}
+
+/// The `scopes` argument of a `@requiresScopes` directive.
+fn scopes(directive: &ast::Directive) -> Vec<Vec<String>> {
+    match argument(directive, "scopes") {
+        Some(ast::Value::ListValue(sets)) => sets
+            .values()
+            .map(|set| match set {
+                ast::Value::ListValue(set) => set
+                    .values()
+                    .filter_map(|scope| match scope {
+                        ast::Value::StringValue(scope) => Some(scope.into()),
+                        _ => None,
+                    })
+                    .collect(),
+                ast::Value::StringValue(scope) => vec![scope.into()],
+                _ => Vec::new(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Requirements of the types and fields of a schema.
+#[derive(Debug, Default)]
+pub(crate) struct Authorization {
+    types: HashMap<String, Requirements>,
+    fields: HashMap<String, HashMap<String, Requirements>>,
+}
+
+impl Authorization {
+    pub(crate) fn from_document(document: &ast::Document) -> Self {
+        let mut authorization = Authorization::default();
+        for definition in document.definitions() {
+            let (name, directives, fields) = match definition {
+                ast::Definition::ObjectTypeDefinition(object) => (
+                    object.name(),
+                    object.directives(),
+                    object.fields_definition(),
+                ),
+                ast::Definition::InterfaceTypeDefinition(interface) => (
+                    interface.name(),
+                    interface.directives(),
+                    interface.fields_definition(),
+                ),
+                ast::Definition::ScalarTypeDefinition(scalar) => {
+                    (scalar.name(), scalar.directives(), None)
+                }
+                ast::Definition::EnumTypeDefinition(enum_type) => {
+                    (enum_type.name(), enum_type.directives(), None)
+                }
+                _ => continue,
+            };
+            let name = match name {
+                Some(name) => name.text().to_string(),
+                None => continue,
+            };
+
+            if let Some(requirements) = Requirements::from_directives(directives) {
+                authorization.types.insert(name.clone(), requirements);
+            }
+            for field in fields.iter().flat_map(|fields| fields.field_definitions()) {
+                if let (Some(field_name), Some(requirements)) = (
+                    field.name(),
+                    Requirements::from_directives(field.directives()),
+                ) {
+                    authorization
+                        .fields
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(field_name.text().to_string(), requirements);
+                }
+            }
+        }
+        authorization
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.types.is_empty() && self.fields.is_empty()
+    }
+
+    /// Whether `claims` meet the requirements of the field `field` of `parent_type`, and of its
+    /// type `ty`, including those of the types implementing them.
+    fn allows(
+        &self,
+        schema: &Schema,
+        parent_type: &str,
+        field: &str,
+        ty: Option<&str>,
+        claims: Option<&Value>,
+    ) -> bool {
+        let field_allowed = self
+            .fields
+            .iter()
+            .filter(|(name, _)| {
+                name.as_str() == parent_type || schema.is_subtype(parent_type, name)
+            })
+            .filter_map(|(_, fields)| fields.get(field))
+            .all(|requirements| requirements.are_met(claims));
+        let type_allowed = match ty {
+            Some(ty) => self
+                .types
+                .iter()
+                .filter(|(name, _)| name.as_str() == ty || schema.is_subtype(ty, name))
+                .all(|(_, requirements)| requirements.are_met(claims)),
+            None => true,
+        };
+        field_allowed && type_allowed
+    }
+}
+
+/// Walks the selections of an operation, collecting the paths the client may not query.
+pub(crate) struct UnauthorizedPaths<'a> {
+    schema: &'a Schema,
+    fragments: &'a Fragments,
+    claims: Option<&'a Value>,
+    pub(crate) paths: Vec<Path>,
+    summaries: HashMap<String, Option<Vec<Path>>>,
+}
+
+impl<'a> FragmentWalk for UnauthorizedPaths<'a> {
+    type Summary = Vec<Path>;
+
+    fn summaries(&mut self) -> &mut HashMap<String, Option<Vec<Path>>> {
+        &mut self.summaries
+    }
+}
+
+impl<'a> UnauthorizedPaths<'a> {
+    pub(crate) fn new(
+        schema: &'a Schema,
+        fragments: &'a Fragments,
+        claims: Option<&'a Value>,
+    ) -> Self {
+        Self {
+            schema,
+            fragments,
+            claims,
+            paths: Vec::new(),
+            summaries: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn visit(&mut self, selection_set: &'a [Selection], parent_type: &str, path: &Path) {
+        let mut paths = std::mem::take(&mut self.paths);
+        self.collect(selection_set, parent_type, path, &mut paths);
+        dedup(&mut paths);
+        self.paths = paths;
+    }
+
+    fn collect(
+        &mut self,
+        selection_set: &'a [Selection],
+        parent_type: &str,
+        path: &Path,
+        paths: &mut Vec<Path>,
+    ) {
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    field_type,
+                    ..
+                } => {
+                    let key = alias.as_ref().unwrap_or(name).as_str().to_string();
+                    let path = path.join(Path(vec![PathElement::Key(key)]));
+                    let ty = field_type.inner_type_name();
+                    let schema = self.schema;
+                    if !schema.authorization.allows(
+                        schema,
+                        parent_type,
+                        name.as_str(),
+                        ty,
+                        self.claims,
+                    ) {
+                        paths.push(path);
+                        continue;
+                    }
+                    if let (Some(selection_set), Some(ty)) = (selection_set, ty) {
+                        self.collect(selection_set, ty, &path, paths);
+                    }
+                }
+                Selection::InlineFragment { fragment, .. } => {
+                    self.collect(
+                        &fragment.selection_set,
+                        &fragment.type_condition,
+                        path,
+                        paths,
+                    );
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    let fragments = self.fragments;
+                    let fragment = match fragments.get(name) {
+                        Some(fragment) => fragment,
+                        None => continue,
+                    };
+                    let summary = self.fragment_summary(name, |unauthorized| {
+                        let mut fragment_paths = Vec::new();
+                        unauthorized.collect(
+                            &fragment.selection_set,
+                            &fragment.type_condition,
+                            &Path::empty(),
+                            &mut fragment_paths,
+                        );
+                        dedup(&mut fragment_paths);
+                        fragment_paths
+                    });
+                    paths.extend(
+                        summary
+                            .into_iter()
+                            .flatten()
+                            .map(|fragment_path| path.join(fragment_path)),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Removes the paths found several times, as when a fragment is spread twice at the same place.
+fn dedup(paths: &mut Vec<Path>) {
+    let mut seen = HashSet::new();
+    paths.retain(|path| seen.insert(path.clone()));
+}
+
+/// The query without the selections the client may not query, or `None` if nothing is left of the
+/// operation `operation_name`.
+///
+/// Selections left without fields are removed as well, and so are the fragments and variables
+/// nothing uses anymore. A fragment loses its unauthorized fields wherever it is spread, as whether
+/// a field is allowed only depends on the type it is selected on.
+pub(crate) fn filter_unauthorized(
+    query: &str,
+    operation_name: Option<&str>,
+    schema: &Schema,
+    claims: Option<&Value>,
+) -> Option<String> {
+    let tree = apollo_parser::Parser::new(query).parse();
+    let document = tree.document();
+    let mut operations = Vec::new();
+    let mut fragments = HashMap::new();
+    for definition in document.definitions() {
+        match definition {
+            ast::Definition::OperationDefinition(operation) => operations.push(operation),
+            ast::Definition::FragmentDefinition(fragment) => {
+                if let Some(name) = fragment_name(fragment.fragment_name()) {
+                    fragments.insert(name, fragment);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut filter = AuthorizationFilter {
+        schema,
+        claims,
+        fragments: &fragments,
+        removed: Vec::new(),
+        summaries: HashMap::new(),
+    };
+    let mut kept_operations = Vec::new();
+    for operation in operations {
+        let root_type = schema.root_operation_type(operation_kind(&operation));
+        let emptied = operation
+            .selection_set()
+            .map(|selection_set| filter.selection_set(&selection_set, root_type))
+            .unwrap_or(false);
+        if !emptied {
+            kept_operations.push(operation);
+            continue;
+        }
+        let name = operation.name().map(|name| name.text().to_string());
+        if operation_name.is_none() || name.as_deref() == operation_name {
+            return None;
+        }
+        filter.removed.push(text_range(&operation));
+    }
+    let mut removed = merge(filter.removed);
+
+    // What is left of the operations tells which fragments and variables are still used.
+    let mut used_fragments = HashSet::new();
+    for operation in kept_operations {
+        let mut spreads = Vec::new();
+        let mut variables = HashSet::new();
+        uses(&operation, &removed, &mut spreads, &mut variables);
+        let mut reached = HashSet::new();
+        while let Some(name) = spreads.pop() {
+            if let Some(fragment) = fragments.get(&name) {
+                if reached.insert(name) {
+                    uses(fragment, &removed, &mut spreads, &mut variables);
+                }
+            }
+        }
+        used_fragments.extend(reached);
+
+        if let Some(definitions) = operation.variable_definitions() {
+            let all = definitions.variable_definitions().count();
+            let unused = definitions
+                .variable_definitions()
+                .filter(|definition| {
+                    let name = definition
+                        .variable()
+                        .and_then(|variable| variable.name())
+                        .map(|name| name.text().to_string());
+                    !name.map(|name| variables.contains(&name)).unwrap_or(true)
+                })
+                .map(|definition| text_range(&definition))
+                .collect::<Vec<_>>();
+            if unused.len() == all && all > 0 {
+                removed.push(text_range(&definitions));
+            } else {
+                removed.extend(unused);
+            }
+        }
+    }
+    removed.extend(
+        fragments
+            .iter()
+            .filter(|(name, _)| !used_fragments.contains(*name))
+            .map(|(_, fragment)| text_range(fragment)),
+    );
+
+    // Removed nodes are replaced by a space, which keeps the tokens around them apart.
+    let mut filtered = String::with_capacity(query.len());
+    let mut kept_from = 0;
+    for (start, end) in merge(removed) {
+        filtered.push_str(&query[kept_from..start]);
+        filtered.push(' ');
+        kept_from = end;
+    }
+    filtered.push_str(&query[kept_from..]);
+    Some(filtered)
+}
+
+/// Walks the syntax tree of a query, collecting the text ranges of what the client may not query.
+struct AuthorizationFilter<'a> {
+    schema: &'a Schema,
+    claims: Option<&'a Value>,
+    fragments: &'a HashMap<String, ast::FragmentDefinition>,
+    /// Ranges of the removed nodes, some of which may be nested in others.
+    removed: Vec<(usize, usize)>,
+    /// Whether each fragment spread so far is left without any field.
+    summaries: HashMap<String, Option<bool>>,
+}
+
+impl<'a> FragmentWalk for AuthorizationFilter<'a> {
+    type Summary = bool;
+
+    fn summaries(&mut self) -> &mut HashMap<String, Option<bool>> {
+        &mut self.summaries
+    }
+}
+
+impl<'a> AuthorizationFilter<'a> {
+    /// Removes the selections the client may not query, returning whether none is left.
+    fn selection_set(&mut self, selection_set: &ast::SelectionSet, parent_type: &str) -> bool {
+        let mut emptied = true;
+        for selection in selection_set.selections() {
+            let (removed, range) = match selection {
+                ast::Selection::Field(field) => {
+                    (self.field(&field, parent_type), text_range(&field))
+                }
+                ast::Selection::InlineFragment(fragment) => {
+                    let type_condition = fragment
+                        .type_condition()
+                        .and_then(|condition| condition.named_type())
+                        .and_then(|named_type| named_type.name())
+                        .map(|name| name.text().to_string())
+                        .unwrap_or_else(|| parent_type.to_string());
+                    let removed = fragment
+                        .selection_set()
+                        .map(|selection_set| self.selection_set(&selection_set, &type_condition))
+                        .unwrap_or(false);
+                    (removed, text_range(&fragment))
+                }
+                ast::Selection::FragmentSpread(spread) => {
+                    let removed = fragment_name(spread.fragment_name())
+                        .map(|name| self.fragment(&name))
+                        .unwrap_or(false);
+                    (removed, text_range(&spread))
+                }
+            };
+            if removed {
+                self.removed.push(range);
+            } else {
+                emptied = false;
+            }
+        }
+        emptied
+    }
+
+    /// Whether the field is removed, either because the client may not query it or because none
+    /// of its selections is left.
+    fn field(&mut self, field: &ast::Field, parent_type: &str) -> bool {
+        let name = match field.name() {
+            Some(name) => name.text().to_string(),
+            None => return false,
+        };
+        if name.starts_with("__") {
+            return false;
+        }
+        let schema = self.schema;
+        let ty = schema
+            .object_types
+            .get(parent_type)
+            .and_then(|ty| ty.field(&name))
+            .or_else(|| {
+                schema
+                    .interfaces
+                    .get(parent_type)
+                    .and_then(|ty| ty.field(&name))
+            })
+            .and_then(|field_type| field_type.inner_type_name());
+        if !schema
+            .authorization
+            .allows(schema, parent_type, &name, ty, self.claims)
+        {
+            return true;
+        }
+        match (field.selection_set(), ty) {
+            (Some(selection_set), Some(ty)) => self.selection_set(&selection_set, ty),
+            _ => false,
+        }
+    }
+
+    /// Whether the fragment `name` is left without any field.
+    fn fragment(&mut self, name: &str) -> bool {
+        let fragments = self.fragments;
+        let fragment = match fragments.get(name) {
+            Some(fragment) => fragment,
+            None => return false,
+        };
+        self.fragment_summary(name, |filter| {
+            let type_condition = fragment
+                .type_condition()
+                .and_then(|condition| condition.named_type())
+                .and_then(|named_type| named_type.name())
+                .map(|name| name.text().to_string());
+            match (fragment.selection_set(), type_condition) {
+                (Some(selection_set), Some(type_condition)) => {
+                    filter.selection_set(&selection_set, &type_condition)
+                }
+                _ => false,
+            }
+        })
+        .unwrap_or(false)
+    }
+}
+
+fn fragment_name(name: Option<ast::FragmentName>) -> Option<String> {
+    Some(name?.name()?.text().to_string())
+}
+
+/// Collects the fragments spread and the variables used in `node`, outside of the `removed` ranges
+/// and of variable definitions.
+fn uses(
+    node: &impl AstNode,
+    removed: &[(usize, usize)],
+    spreads: &mut Vec<String>,
+    variables: &mut HashSet<String>,
+) {
+    for descendant in node.syntax().descendants() {
+        if let Some(spread) = ast::FragmentSpread::cast(descendant.clone()) {
+            if !is_removed(removed, &spread) {
+                spreads.extend(fragment_name(spread.fragment_name()));
+            }
+        } else if let Some(variable) = ast::Variable::cast(descendant) {
+            let defined = variable
+                .syntax()
+                .parent()
+                .and_then(ast::VariableDefinition::cast)
+                .is_some();
+            if !defined && !is_removed(removed, &variable) {
+                variables.extend(variable.name().map(|name| name.text().to_string()));
+            }
+        }
+    }
+}
+
+fn text_range(node: &impl AstNode) -> (usize, usize) {
+    let range = node.syntax().text_range();
+    (usize::from(range.start()), usize::from(range.end()))
+}
+
+/// Sorts ranges, leaving out those nested in others.
+fn merge(mut ranges: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Whether `node` is within one of the sorted, disjoint `removed` ranges.
+fn is_removed(removed: &[(usize, usize)], node: &impl AstNode) -> bool {
+    let (start, end) = text_range(node);
+    let after = removed.partition_point(|(removed_start, _)| *removed_start <= start);
+    after > 0 && end <= removed[after - 1].1
+}
@@ -0,0 +1,281 @@
+//! Arguments set from the request context, declared in the schema with `@fromContext`.
+//!
+//! `@fromContext(key: String!, path: String)` on an argument definition binds the argument to the
+//! value stored in the request context under `key`, or to the member of that value found at the
+//! dot separated `path`, such as `sub` in the claims of
+//! [`AUTHENTICATION_CLAIMS_CONTEXT_KEY`](crate::AUTHENTICATION_CLAIMS_CONTEXT_KEY).
+//!
+//! Operations pass such arguments as variables, whose values the router replaces before the
+//! variables are validated and sent to subgraphs. Operations leaving such an argument out or
+//! writing its value themselves are refused, since they would bypass the context, and so are
+//! requests whose context has no value for it.
+
+use super::directives::{argument, directive};
+use crate::{
+    Argument, Context, FragmentWalk, Fragments, Path, PathElement, Schema, Selection, Value,
+};
+use apollo_parser::ast;
+use std::collections::HashMap;
+
+/// Where the value of an argument comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextArgument {
+    key: String,
+    path: Vec<String>,
+}
+
+impl ContextArgument {
+    /// The value of the argument for a request, if the context has one.
+    pub fn resolve(&self, context: &Context) -> Option<Value> {
+        let value = context.get::<_, Value>(self.key.as_str()).ok().flatten()?;
+        self.path.iter().try_fold(value, |value, member| {
+            value.as_object()?.get(member.as_str()).cloned()
+        })
+    }
+
+    fn from_directive(directive: &ast::Directive) -> Option<Self> {
+        let string = |name: &str| match argument(directive, name) {
+            Some(ast::Value::StringValue(value)) => Some(String::from(value)),
+            _ => None,
+        };
+        let path = string("path")
+            .map(|path| {
+                path.split('.')
+                    .filter(|member| !member.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            key: string("key")?,
+            path,
+        })
+    }
+}
+
+/// Arguments bound to the context, by type, field and argument name.
+#[derive(Debug, Default)]
+pub(crate) struct ContextArguments {
+    fields: HashMap<String, HashMap<String, HashMap<String, ContextArgument>>>,
+}
+
+impl ContextArguments {
+    pub(crate) fn from_document(document: &ast::Document) -> Self {
+        let mut arguments = ContextArguments::default();
+        for definition in document.definitions() {
+            let (name, fields) = match definition {
+                ast::Definition::ObjectTypeDefinition(object) => {
+                    (object.name(), object.fields_definition())
+                }
+                ast::Definition::InterfaceTypeDefinition(interface) => {
+                    (interface.name(), interface.fields_definition())
+                }
+                _ => continue,
+            };
+            let name = match name {
+                Some(name) => name.text().to_string(),
+                None => continue,
+            };
+
+            for field in fields.iter().flat_map(|fields| fields.field_definitions()) {
+                let field_name = match field.name() {
+                    Some(field_name) => field_name.text().to_string(),
+                    None => continue,
+                };
+                for argument in field
+                    .arguments_definition()
+                    .iter()
+                    .flat_map(|arguments| arguments.input_value_definitions())
+                {
+                    let bound = directive(argument.directives().as_ref(), "fromContext")
+                        .and_then(|directive| ContextArgument::from_directive(&directive));
+                    if let (Some(argument_name), Some(bound)) = (argument.name(), bound) {
+                        arguments
+                            .fields
+                            .entry(name.clone())
+                            .or_default()
+                            .entry(field_name.clone())
+                            .or_default()
+                            .insert(argument_name.text().to_string(), bound);
+                    }
+                }
+            }
+        }
+        arguments
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Arguments of the field `name` of `parent_type` bound to the context, along with those of
+    /// the types implementing it when it is an interface.
+    fn of_field(
+        &self,
+        schema: &Schema,
+        parent_type: &str,
+        name: &str,
+    ) -> Vec<(&String, &ContextArgument)> {
+        self.fields
+            .iter()
+            .filter(|(ty, _)| ty.as_str() == parent_type || schema.is_subtype(parent_type, ty))
+            .filter_map(|(_, fields)| fields.get(name))
+            .flatten()
+            .collect()
+    }
+}
+
+/// Variables bound to the context, and paths of the fields not given a variable for an argument
+/// bound to the context, found in a selection set.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Bindings {
+    pub(crate) variables: Vec<(String, ContextArgument)>,
+    pub(crate) unbound: Vec<Path>,
+}
+
+impl Bindings {
+    fn bind(&mut self, variable: String, argument: ContextArgument) {
+        let variable = (variable, argument);
+        if !self.variables.contains(&variable) {
+            self.variables.push(variable);
+        }
+    }
+
+    fn unbound_at(&mut self, path: Path) {
+        if !self.unbound.contains(&path) {
+            self.unbound.push(path);
+        }
+    }
+
+    /// Adds the bindings of a selection set found at `path`.
+    fn add(&mut self, path: &Path, bindings: Bindings) {
+        for (variable, argument) in bindings.variables {
+            self.bind(variable, argument);
+        }
+        for unbound in bindings.unbound {
+            self.unbound_at(path.join(unbound));
+        }
+    }
+}
+
+/// Walks the selections of an operation, collecting the variables bound to the context.
+pub(crate) struct ContextBindings<'a> {
+    schema: &'a Schema,
+    fragments: &'a Fragments,
+    summaries: HashMap<String, Option<Bindings>>,
+    pub(crate) bindings: Bindings,
+}
+
+impl<'a> FragmentWalk for ContextBindings<'a> {
+    type Summary = Bindings;
+
+    fn summaries(&mut self) -> &mut HashMap<String, Option<Bindings>> {
+        &mut self.summaries
+    }
+}
+
+impl<'a> ContextBindings<'a> {
+    pub(crate) fn new(fragments: &'a Fragments, schema: &'a Schema) -> Self {
+        Self {
+            schema,
+            fragments,
+            summaries: HashMap::new(),
+            bindings: Bindings::default(),
+        }
+    }
+
+    pub(crate) fn visit(&mut self, selection_set: &'a [Selection], parent_type: &str) {
+        let mut bindings = std::mem::take(&mut self.bindings);
+        self.collect(selection_set, parent_type, &Path::empty(), &mut bindings);
+        self.bindings = bindings;
+    }
+
+    fn collect(
+        &mut self,
+        selection_set: &'a [Selection],
+        parent_type: &str,
+        path: &Path,
+        bindings: &mut Bindings,
+    ) {
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    field_type,
+                    arguments,
+                    ..
+                } => {
+                    let name = name.as_str();
+                    if name.starts_with("__") {
+                        continue;
+                    }
+                    let key = alias.as_ref().map(|alias| alias.as_str()).unwrap_or(name);
+                    let path = path.join(Path(vec![PathElement::Key(key.to_string())]));
+                    self.field(name, arguments, parent_type, &path, bindings);
+
+                    if let (Some(selection_set), Some(ty)) =
+                        (selection_set, field_type.inner_type_name())
+                    {
+                        self.collect(selection_set, ty, &path, bindings);
+                    }
+                }
+                Selection::InlineFragment { fragment, .. } => {
+                    self.collect(
+                        &fragment.selection_set,
+                        &fragment.type_condition,
+                        path,
+                        bindings,
+                    );
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    let fragments = self.fragments;
+                    let fragment = match fragments.get(name) {
+                        Some(fragment) => fragment,
+                        None => continue,
+                    };
+                    let summary = self.fragment_summary(name, |context_bindings| {
+                        let mut fragment_bindings = Bindings::default();
+                        context_bindings.collect(
+                            &fragment.selection_set,
+                            &fragment.type_condition,
+                            &Path::empty(),
+                            &mut fragment_bindings,
+                        );
+                        fragment_bindings
+                    });
+                    if let Some(summary) = summary {
+                        bindings.add(path, summary);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Binds the variables given to the arguments of a field bound to the context.
+    fn field(
+        &self,
+        name: &str,
+        arguments: &[(String, Argument)],
+        parent_type: &str,
+        path: &Path,
+        bindings: &mut Bindings,
+    ) {
+        let schema = self.schema;
+        for (argument_name, context_argument) in
+            schema.context_arguments.of_field(schema, parent_type, name)
+        {
+            let value = arguments
+                .iter()
+                .find(|(name, _)| name == argument_name)
+                .map(|(_, value)| value);
+            match value {
+                Some(Argument::Variable(variable)) => {
+                    bindings.bind(variable.clone(), context_argument.clone())
+                }
+                _ => bindings.unbound_at(path.clone()),
+            }
+        }
+    }
+}
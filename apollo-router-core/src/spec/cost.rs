@@ -0,0 +1,259 @@
+//! Static cost of operations, declared in the schema with `@cost` and `@listSize`.
+//!
+//! `@cost(weight: Int!)` sets the cost of a field, or of every field returning a type. Without it,
+//! fields returning objects, interfaces or unions cost 1 and the other fields cost 0.
+//! `@listSize(assumedSize: Int, slicingArguments: [String!])` tells how many items a list field
+//! returns: the value of its first slicing argument given by the operation, or else the assumed
+//! size. Each item of a list counts with the cost of its selections.
+
+use super::directives::{argument, directive};
+use crate::{Argument, FieldType, FragmentWalk, Fragments, Object, Schema, Selection};
+use apollo_parser::ast;
+use std::collections::HashMap;
+
+/// Context key holding the estimated cost of the operation of a request.
+///
+/// The router service only fills it in when the key is already present, so that requests nobody
+/// checks the cost of do not pay for it.
+pub const ESTIMATED_COST_CONTEXT_KEY: &str = "apollo::demand_control::estimated_cost";
+
+/// Size of a list field, as declared with `@listSize`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ListSize {
+    assumed_size: Option<u64>,
+    slicing_arguments: Vec<String>,
+}
+
+/// Costs declared by the types and fields of a schema.
+#[derive(Debug, Default)]
+pub(crate) struct Costs {
+    types: HashMap<String, u64>,
+    fields: HashMap<String, HashMap<String, u64>>,
+    list_sizes: HashMap<String, HashMap<String, ListSize>>,
+}
+
+impl Costs {
+    pub(crate) fn from_document(document: &ast::Document) -> Self {
+        let mut costs = Costs::default();
+        for definition in document.definitions() {
+            let (name, directives, fields) = match definition {
+                ast::Definition::ObjectTypeDefinition(object) => (
+                    object.name(),
+                    object.directives(),
+                    object.fields_definition(),
+                ),
+                ast::Definition::InterfaceTypeDefinition(interface) => (
+                    interface.name(),
+                    interface.directives(),
+                    interface.fields_definition(),
+                ),
+                ast::Definition::ScalarTypeDefinition(scalar) => {
+                    (scalar.name(), scalar.directives(), None)
+                }
+                ast::Definition::EnumTypeDefinition(enum_type) => {
+                    (enum_type.name(), enum_type.directives(), None)
+                }
+                _ => continue,
+            };
+            let name = match name {
+                Some(name) => name.text().to_string(),
+                None => continue,
+            };
+
+            if let Some(weight) = directive(directives.as_ref(), "cost")
+                .and_then(|cost| int_argument(&cost, "weight"))
+            {
+                costs.types.insert(name.clone(), weight);
+            }
+            for field in fields.iter().flat_map(|fields| fields.field_definitions()) {
+                let field_name = match field.name() {
+                    Some(field_name) => field_name.text().to_string(),
+                    None => continue,
+                };
+                let directives = field.directives();
+                if let Some(weight) = directive(directives.as_ref(), "cost")
+                    .and_then(|cost| int_argument(&cost, "weight"))
+                {
+                    costs
+                        .fields
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(field_name.clone(), weight);
+                }
+                if let Some(list_size) = directive(directives.as_ref(), "listSize") {
+                    costs.list_sizes.entry(name.clone()).or_default().insert(
+                        field_name,
+                        ListSize {
+                            assumed_size: int_argument(&list_size, "assumedSize"),
+                            slicing_arguments: string_list_argument(&list_size, "slicingArguments"),
+                        },
+                    );
+                }
+            }
+        }
+        costs
+    }
+}
+
+fn int_argument(directive: &ast::Directive, name: &str) -> Option<u64> {
+    match argument(directive, name)? {
+        ast::Value::IntValue(value) => value.to_string().trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn string_list_argument(directive: &ast::Directive, name: &str) -> Vec<String> {
+    match argument(directive, name) {
+        Some(ast::Value::ListValue(values)) => values
+            .values()
+            .filter_map(|value| match value {
+                ast::Value::StringValue(value) => Some(value.into()),
+                _ => None,
+            })
+            .collect(),
+        Some(ast::Value::StringValue(value)) => vec![value.into()],
+        _ => Vec::new(),
+    }
+}
+
+fn is_list(field_type: &FieldType) -> bool {
+    match field_type {
+        FieldType::List(_) => true,
+        FieldType::NonNull(inner) => is_list(inner),
+        _ => false,
+    }
+}
+
+/// Walks the selections of an operation, adding up the cost of its fields.
+pub(crate) struct CostEstimator<'a> {
+    schema: &'a Schema,
+    fragments: &'a Fragments,
+    variables: &'a Object,
+    summaries: HashMap<String, Option<u64>>,
+}
+
+impl<'a> FragmentWalk for CostEstimator<'a> {
+    type Summary = u64;
+
+    fn summaries(&mut self) -> &mut HashMap<String, Option<u64>> {
+        &mut self.summaries
+    }
+}
+
+impl<'a> CostEstimator<'a> {
+    pub(crate) fn new(fragments: &'a Fragments, schema: &'a Schema, variables: &'a Object) -> Self {
+        Self {
+            schema,
+            fragments,
+            variables,
+            summaries: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn estimate(&mut self, selection_set: &'a [Selection], parent_type: &str) -> u64 {
+        let mut cost = 0u64;
+        for selection in selection_set {
+            let selection_cost = match selection {
+                Selection::Field {
+                    name,
+                    selection_set,
+                    field_type,
+                    arguments,
+                    ..
+                } => self.field(
+                    name.as_str(),
+                    field_type,
+                    selection_set.as_deref(),
+                    arguments,
+                    parent_type,
+                ),
+                Selection::InlineFragment { fragment, .. } => {
+                    self.estimate(&fragment.selection_set, &fragment.type_condition)
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    let fragments = self.fragments;
+                    let fragment = match fragments.get(name) {
+                        Some(fragment) => fragment,
+                        None => continue,
+                    };
+                    self.fragment_summary(name, |estimator| {
+                        estimator.estimate(&fragment.selection_set, &fragment.type_condition)
+                    })
+                    .unwrap_or_default()
+                }
+            };
+            cost = cost.saturating_add(selection_cost);
+        }
+        cost
+    }
+
+    fn field(
+        &mut self,
+        name: &str,
+        field_type: &FieldType,
+        selection_set: Option<&'a [Selection]>,
+        arguments: &[(String, Argument)],
+        parent_type: &str,
+    ) -> u64 {
+        if name.starts_with("__") {
+            return 0;
+        }
+        let schema = self.schema;
+        let inner_type = field_type.inner_type_name();
+        let costs = &schema.costs;
+
+        let weight = costs
+            .fields
+            .get(parent_type)
+            .and_then(|fields| fields.get(name))
+            .or_else(|| inner_type.and_then(|ty| costs.types.get(ty)))
+            .copied()
+            .unwrap_or_else(|| match inner_type {
+                Some(ty)
+                    if !schema.custom_scalars.contains(ty) && !schema.enums.contains_key(ty) =>
+                {
+                    1
+                }
+                _ => 0,
+            });
+        let selections = match (selection_set, inner_type) {
+            (Some(selection_set), Some(ty)) => self.estimate(selection_set, ty),
+            _ => 0,
+        };
+        let item_cost = weight.saturating_add(selections);
+
+        if is_list(field_type) {
+            let size = self.list_size(arguments, parent_type, name);
+            item_cost.saturating_mul(size)
+        } else {
+            item_cost
+        }
+    }
+
+    /// Number of items a list field is expected to return, 1 without `@listSize`.
+    fn list_size(&self, arguments: &[(String, Argument)], parent_type: &str, name: &str) -> u64 {
+        let list_size = match self
+            .schema
+            .costs
+            .list_sizes
+            .get(parent_type)
+            .and_then(|fields| fields.get(name))
+        {
+            Some(list_size) => list_size,
+            None => return 1,
+        };
+        arguments
+            .iter()
+            .filter(|(argument_name, _)| list_size.slicing_arguments.contains(argument_name))
+            .find_map(|(_, value)| match value {
+                Argument::Int(value) => Some(*value),
+                Argument::Variable(variable) => self
+                    .variables
+                    .get(variable.as_str())
+                    .and_then(|value| value.as_u64()),
+                Argument::Literal => None,
+            })
+            .or(list_size.assumed_size)
+            .unwrap_or(1)
+    }
+}
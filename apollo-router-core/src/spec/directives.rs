@@ -0,0 +1,35 @@
+//! Lookups of the directives of a schema and of their arguments.
+
+use apollo_parser::ast;
+
+/// The directive named `name` among `directives`.
+pub(crate) fn directive(
+    directives: Option<&ast::Directives>,
+    name: &str,
+) -> Option<ast::Directive> {
+    directives?
+        .directives()
+        .find(|directive| is_named(directive, name))
+}
+
+pub(crate) fn is_named(directive: &ast::Directive, name: &str) -> bool {
+    directive
+        .name()
+        .map(|directive_name| directive_name.text().to_string() == name)
+        .unwrap_or(false)
+}
+
+/// The value of the argument `name` of `directive`.
+pub(crate) fn argument(directive: &ast::Directive, name: &str) -> Option<ast::Value> {
+    directive
+        .arguments()
+        .iter()
+        .flat_map(|arguments| arguments.arguments())
+        .find(|argument| {
+            argument
+                .name()
+                .map(|argument_name| argument_name.text().to_string() == name)
+                .unwrap_or(false)
+        })
+        .and_then(|argument| argument.value())
+}
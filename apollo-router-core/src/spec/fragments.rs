@@ -98,3 +98,36 @@ pub(crate) struct Fragment {
     pub(crate) skip: Skip,
     pub(crate) include: Include,
 }
+
+/// A walk over the selections of an operation, summarising each of its fragments once.
+///
+/// Fragments can spread other fragments several times, which can spread others several times in
+/// turn, so that a walk expanding every spread takes a time exponential in the size of the
+/// operation. Walks implementing this trait summarise a fragment the first time it is spread, and
+/// reuse the summary wherever else it is.
+pub(crate) trait FragmentWalk: Sized {
+    /// What the walk tells of a fragment, independently of where it is spread.
+    type Summary: Clone;
+
+    /// Summaries of the fragments spread so far, `None` for those being summarised.
+    fn summaries(&mut self) -> &mut HashMap<String, Option<Self::Summary>>;
+
+    /// The summary of the fragment `name`, made with `summarise` the first time it is spread.
+    ///
+    /// Returns `None` for a fragment spread while it is being summarised.
+    fn fragment_summary(
+        &mut self,
+        name: &str,
+        summarise: impl FnOnce(&mut Self) -> Self::Summary,
+    ) -> Option<Self::Summary> {
+        // Guards against fragments spreading each other in an invalid query.
+        if let Some(summary) = self.summaries().get(name) {
+            return summary.clone();
+        }
+        self.summaries().insert(name.to_string(), None);
+        let summary = summarise(self);
+        self.summaries()
+            .insert(name.to_string(), Some(summary.clone()));
+        Some(summary)
+    }
+}
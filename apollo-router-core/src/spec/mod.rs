@@ -1,11 +1,29 @@
+mod authorization;
+mod context_arguments;
+mod cost;
+mod directives;
 mod field_type;
 mod fragments;
 mod query;
 mod schema;
 mod selection;
+mod signature;
+mod subscription;
+mod usage;
 
+pub use authorization::AUTHENTICATION_CLAIMS_CONTEXT_KEY;
+pub(crate) use authorization::{filter_unauthorized, Authorization, UnauthorizedPaths};
+pub use context_arguments::ContextArgument;
+pub(crate) use context_arguments::{ContextArguments, ContextBindings};
+pub use cost::ESTIMATED_COST_CONTEXT_KEY;
+pub(crate) use cost::{CostEstimator, Costs};
 pub(crate) use field_type::*;
 pub(crate) use fragments::*;
 pub use query::*;
+pub(crate) use query::{operation_kind, parse_value};
 pub use schema::*;
 pub(crate) use selection::*;
+pub use signature::{OperationSanitizer, OperationSignature, OPERATION_SIGNATURE_CONTEXT_KEY};
+pub(crate) use subscription::SubscriptionFields;
+pub(crate) use usage::ResponseUsage;
+pub use usage::{FieldUsage, TraceNode, FIELD_USAGE_CONTEXT_KEY, TRACE_ROOT_CONTEXT_KEY};
@@ -20,6 +20,10 @@ pub struct Query {
     fragments: Fragments,
     #[derivative(PartialEq = "ignore", Hash = "ignore")]
     operations: Vec<Operation>,
+    /// Names of the subscription operations, including those that could not be parsed against
+    /// the schema.
+    #[derivative(PartialEq = "ignore", Hash = "ignore")]
+    subscriptions: Vec<Option<String>>,
 }
 
 impl Query {
@@ -89,6 +93,320 @@ impl Query {
 
         response.data = Some(Value::default());
     }
+    /// Checks that the response data matches the shape and types expected by this query.
+    ///
+    /// Unlike [`Query::format_response`], which replaces invalid values with `null`, this returns
+    /// the path of the first invalid value found.
+    pub fn validate_response(
+        &self,
+        response: &Response,
+        operation_name: Option<&str>,
+        variables: &Object,
+        schema: &Schema,
+    ) -> Result<(), Path> {
+        self.validate_data(response, operation_name, variables, schema, false)
+    }
+
+    /// Checks that the response of a subgraph to this query matches its shape and types, and
+    /// does not hold fields that were not selected.
+    pub(crate) fn validate_subgraph_response(
+        &self,
+        response: &Response,
+        operation_name: Option<&str>,
+        variables: &Object,
+        schema: &Schema,
+    ) -> Result<(), Path> {
+        self.validate_data(response, operation_name, variables, schema, true)
+    }
+
+    /// With `strict`, fields of the response that are not selected are invalid.
+    fn validate_data(
+        &self,
+        response: &Response,
+        operation_name: Option<&str>,
+        variables: &Object,
+        schema: &Schema,
+        strict: bool,
+    ) -> Result<(), Path> {
+        let operation = match operation_name {
+            Some(name) => self
+                .operations
+                .iter()
+                .find(|op| op.name.as_deref() == Some(name)),
+            None => self.operations.get(0),
+        };
+        let operation = match operation {
+            Some(operation) => operation,
+            None => return Ok(()),
+        };
+        let input = match &response.data {
+            None | Some(Value::Null) => return Ok(()),
+            Some(Value::Object(input)) => input,
+            Some(_) => return Err(Path::empty()),
+        };
+
+        let all_variables: Object = operation
+            .variables
+            .iter()
+            .filter_map(|(k, (_, opt))| opt.as_ref().map(|v| (k, v)))
+            .chain(variables.iter())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut path = Vec::new();
+        self.validate_object(
+            &operation.selection_set,
+            &all_variables,
+            input,
+            schema,
+            &mut path,
+            strict,
+        )
+        .map_err(|InvalidValue| Path(path))
+    }
+
+    /// On error, `path` is left pointing to the invalid value.
+    #[allow(clippy::too_many_arguments)]
+    fn validate_value(
+        &self,
+        field_type: &FieldType,
+        variables: &Object,
+        input: &Value,
+        selection_set: &[Selection],
+        schema: &Schema,
+        path: &mut Vec<PathElement>,
+        strict: bool,
+    ) -> Result<(), InvalidValue> {
+        let valid = match field_type {
+            FieldType::NonNull(inner_type) => {
+                if input.is_null() {
+                    return Err(InvalidValue);
+                }
+                return self.validate_value(
+                    inner_type,
+                    variables,
+                    input,
+                    selection_set,
+                    schema,
+                    path,
+                    strict,
+                );
+            }
+            _ if input.is_null() => true,
+            FieldType::List(inner_type) => {
+                let input_array = input.as_array().ok_or(InvalidValue)?;
+                for (i, element) in input_array.iter().enumerate() {
+                    path.push(PathElement::Index(i));
+                    self.validate_value(
+                        inner_type,
+                        variables,
+                        element,
+                        selection_set,
+                        schema,
+                        path,
+                        strict,
+                    )?;
+                    path.pop();
+                }
+                true
+            }
+            FieldType::Named(type_name) | FieldType::Introspection(type_name) => {
+                if schema.custom_scalars.contains(type_name) {
+                    true
+                } else if let Some(enum_type) = schema.enums.get(type_name) {
+                    input
+                        .as_str()
+                        .map(|s| enum_type.contains(s))
+                        .unwrap_or(false)
+                } else {
+                    let input_object = input.as_object().ok_or(InvalidValue)?;
+                    self.validate_object(
+                        selection_set,
+                        variables,
+                        input_object,
+                        schema,
+                        path,
+                        strict,
+                    )?;
+                    true
+                }
+            }
+            FieldType::Int => input.as_i64().and_then(|i| i32::try_from(i).ok()).is_some(),
+            FieldType::Float => input.as_f64().is_some(),
+            FieldType::Boolean => input.as_bool().is_some(),
+            FieldType::String => input.as_str().is_some(),
+            FieldType::Id => {
+                input.is_string() || input.is_i64() || input.is_u64() || input.is_f64()
+            }
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(InvalidValue)
+        }
+    }
+
+    fn validate_object(
+        &self,
+        selection_set: &[Selection],
+        variables: &Object,
+        input: &Object,
+        schema: &Schema,
+        path: &mut Vec<PathElement>,
+        strict: bool,
+    ) -> Result<(), InvalidValue> {
+        let mut selected = HashSet::new();
+        self.validate_selection_set(
+            selection_set,
+            variables,
+            input,
+            schema,
+            path,
+            strict,
+            &mut selected,
+        )?;
+        if strict {
+            if let Some(key) = input
+                .keys()
+                .find(|key| key.as_str() != TYPENAME && !selected.contains(key.as_str()))
+            {
+                path.push(PathElement::Key(key.as_str().to_string()));
+                return Err(InvalidValue);
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the fields of `input` selected by `selection_set`, adding their keys to
+    /// `selected`.
+    #[allow(clippy::too_many_arguments)]
+    fn validate_selection_set<'a>(
+        &'a self,
+        selection_set: &'a [Selection],
+        variables: &Object,
+        input: &Object,
+        schema: &Schema,
+        path: &mut Vec<PathElement>,
+        strict: bool,
+        selected: &mut HashSet<&'a str>,
+    ) -> Result<(), InvalidValue> {
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    field_type,
+                    skip,
+                    include,
+                    ..
+                } => {
+                    if skip.should_skip(variables).unwrap_or(false)
+                        || !include.should_include(variables).unwrap_or(true)
+                    {
+                        continue;
+                    }
+
+                    let field_name = alias.as_ref().unwrap_or(name);
+                    selected.insert(field_name.as_str());
+                    path.push(PathElement::Key(field_name.as_str().to_string()));
+                    match input.get(field_name.as_str()) {
+                        Some(input_value) if field_name.as_str() == TYPENAME => {
+                            if !input_value.is_string() {
+                                return Err(InvalidValue);
+                            }
+                        }
+                        Some(input_value) => self.validate_value(
+                            field_type,
+                            variables,
+                            input_value,
+                            selection_set.as_deref().unwrap_or_default(),
+                            schema,
+                            path,
+                            strict,
+                        )?,
+                        None if field_type.is_non_null() => return Err(InvalidValue),
+                        None => {}
+                    }
+                    path.pop();
+                }
+                Selection::InlineFragment {
+                    fragment:
+                        Fragment {
+                            type_condition,
+                            selection_set,
+                            skip,
+                            include,
+                        },
+                    known_type,
+                } => {
+                    if skip.should_skip(variables).unwrap_or(false)
+                        || !include.should_include(variables).unwrap_or(true)
+                    {
+                        continue;
+                    }
+
+                    if input
+                        .get(TYPENAME)
+                        .map(|val| val.as_str() == Some(type_condition.as_str()))
+                        .unwrap_or(*known_type)
+                    {
+                        self.validate_selection_set(
+                            selection_set,
+                            variables,
+                            input,
+                            schema,
+                            path,
+                            strict,
+                            selected,
+                        )?;
+                    }
+                }
+                Selection::FragmentSpread {
+                    name,
+                    known_type,
+                    skip,
+                    include,
+                } => {
+                    if skip.should_skip(variables).unwrap_or(false)
+                        || !include.should_include(variables).unwrap_or(true)
+                    {
+                        continue;
+                    }
+
+                    if let Some(fragment) = self.fragments.get(name) {
+                        if fragment.skip.should_skip(variables).unwrap_or(false)
+                            || !fragment.include.should_include(variables).unwrap_or(true)
+                        {
+                            continue;
+                        }
+
+                        if input
+                            .get(TYPENAME)
+                            .map(|val| val.as_str() == Some(fragment.type_condition.as_str()))
+                            .unwrap_or_else(|| {
+                                known_type.as_deref() == Some(fragment.type_condition.as_str())
+                            })
+                        {
+                            self.validate_selection_set(
+                                &fragment.selection_set,
+                                variables,
+                                input,
+                                schema,
+                                path,
+                                strict,
+                                selected,
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn parse(query: impl Into<String>, schema: &Schema) -> Option<Self> {
         let string = query.into();
 
@@ -107,10 +425,18 @@ impl Query {
         let document = tree.document();
         let fragments = Fragments::from_ast(&document, schema)?;
 
+        let mut subscriptions = Vec::new();
         let operations = document
             .definitions()
             .filter_map(|definition| {
                 if let ast::Definition::OperationDefinition(operation) = definition {
+                    if operation
+                        .operation_type()
+                        .and_then(|op| op.subscription_token())
+                        .is_some()
+                    {
+                        subscriptions.push(operation.name().map(|x| x.text().to_string()));
+                    }
                     Operation::from_ast(operation, schema)
                 } else {
                     None
@@ -122,6 +448,7 @@ impl Query {
             string,
             fragments,
             operations,
+            subscriptions,
         })
     }
 
@@ -314,6 +641,7 @@ impl Query {
                     field_type,
                     skip,
                     include,
+                    ..
                 } => {
                     let field_name = alias.as_ref().unwrap_or(name);
                     if skip
@@ -485,6 +813,7 @@ impl Query {
                     field_type,
                     skip,
                     include,
+                    ..
                 } => {
                     if skip
                         .should_skip(variables)
@@ -542,12 +871,8 @@ impl Query {
                     known_type: _,
                 } => {
                     // top level objects will not provide a __typename field
-                    match (type_condition.as_str(), operation.kind) {
-                        ("Query", OperationKind::Query) | ("Mutation", OperationKind::Mutation) => {
-                        }
-                        _ => {
-                            return Err(InvalidValue);
-                        }
+                    if *type_condition != operation.root_type {
+                        return Err(InvalidValue);
                     }
                     self.apply_selection_set(selection_set, variables, input, output, schema)?;
                 }
@@ -559,12 +884,8 @@ impl Query {
                 } => {
                     if let Some(fragment) = self.fragments.get(name) {
                         // top level objects will not provide a __typename field
-                        match (fragment.type_condition.as_str(), operation.kind) {
-                            ("Query", OperationKind::Query)
-                            | ("Mutation", OperationKind::Mutation) => {}
-                            _ => {
-                                return Err(InvalidValue);
-                            }
+                        if fragment.type_condition != operation.root_type {
+                            return Err(InvalidValue);
                         }
                         self.apply_selection_set(
                             &fragment.selection_set,
@@ -585,8 +906,17 @@ impl Query {
     }
 
     /// Validate a [`Request`]'s variables against this [`Query`] using a provided [`Schema`].
-    #[tracing::instrument(skip_all, level = "trace")]
     pub fn validate_variables(&self, request: &Request, schema: &Schema) -> Result<(), Response> {
+        self.coerce_variables(request, schema).map(|_| ())
+    }
+
+    /// Coerces a [`Request`]'s variables to the types of this [`Query`]'s variables, then
+    /// validates them.
+    ///
+    /// Missing variables take their default value, numbers and booleans sent as strings are
+    /// parsed, and single values given for a list are wrapped in one.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn coerce_variables(&self, request: &Request, schema: &Schema) -> Result<Object, Response> {
         let operation_name = request.operation_name.as_deref();
         let operation_variable_types =
             self.operations
@@ -616,21 +946,30 @@ impl Query {
             }
         }
 
-        let errors = operation_variable_types
-            .iter()
-            .filter_map(|(name, (ty, _))| {
-                let value = request.variables.get(*name).unwrap_or(&Value::Null);
-                ty.validate_value(value, schema).err().map(|_| {
+        let mut variables = (*request.variables).clone();
+        let mut errors = Vec::new();
+        for (name, (ty, default)) in operation_variable_types {
+            match variables.get_mut(name) {
+                Some(value) => *value = coerce_value(ty, std::mem::take(value), schema),
+                None => {
+                    if let Some(default) = default {
+                        variables.insert(name, default.clone());
+                    }
+                }
+            }
+            let value = variables.get(name).unwrap_or(&Value::Null);
+            if ty.validate_value(value, schema).is_err() {
+                errors.push(
                     FetchError::ValidationInvalidTypeVariable {
                         name: name.to_string(),
                     }
-                    .to_graphql_error(None)
-                })
-            })
-            .collect::<Vec<_>>();
+                    .to_graphql_error(None),
+                );
+            }
+        }
 
         if errors.is_empty() {
-            Ok(())
+            Ok(variables)
         } else {
             Err(Response::builder().errors(errors).build())
         }
@@ -639,12 +978,254 @@ impl Query {
     pub fn contains_introspection(&self) -> bool {
         self.operations.iter().any(Operation::is_introspection)
     }
+
+    /// The syntax errors of a query, with the `GRAPHQL_PARSE_FAILED` code.
+    pub fn syntax_errors(query: &str) -> Vec<Error> {
+        apollo_parser::Parser::new(query)
+            .parse()
+            .errors()
+            .map(|err| {
+                let before = &query[..err.index().min(query.len())];
+                let line = before.matches('\n').count() + 1;
+                let column = before
+                    .rsplit('\n')
+                    .next()
+                    .map(|line| line.chars().count())
+                    .unwrap_or_default()
+                    + 1;
+                let mut extensions = Object::default();
+                extensions.insert("code", "GRAPHQL_PARSE_FAILED".into());
+                Error {
+                    message: format!("syntax error: {}", err.message()),
+                    locations: vec![Location {
+                        line: line as i32,
+                        column: column as i32,
+                    }],
+                    extensions,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the operation to execute is a subscription.
+    pub fn is_subscription(&self, operation_name: Option<&str>) -> bool {
+        match operation_name {
+            Some(name) => self
+                .subscriptions
+                .iter()
+                .any(|subscription| subscription.as_deref() == Some(name)),
+            None => match self.operations.first() {
+                Some(operation) => operation.kind == OperationKind::Subscription,
+                None => !self.subscriptions.is_empty(),
+            },
+        }
+    }
+
+    /// The subgraph resolving the subscription to execute, which is the one resolving its root
+    /// field. Its events are shaped by the subgraph, so all of its fields must be resolved there.
+    pub(crate) fn subscription_subgraph<'a>(
+        &self,
+        operation_name: Option<&str>,
+        schema: &'a Schema,
+    ) -> Result<&'a str, String> {
+        let (operation, _) = self
+            .operation_with_root_type(operation_name)
+            .ok_or_else(|| "the subscription could not be found in the query".to_string())?;
+        let mut fields = Vec::new();
+        self.root_fields(&operation.selection_set, &mut fields);
+        match fields.as_slice() {
+            [field] => schema
+                .subscription_fields
+                .subgraph(field)
+                .ok_or_else(|| format!("no subgraph resolves the subscription field {}", field)),
+            _ => Err("a subscription must select a single root field".to_string()),
+        }
+    }
+
+    /// Adds the names of the fields of `selection_set` to `fields`, looking into its fragments.
+    fn root_fields<'a>(&'a self, selection_set: &'a [Selection], fields: &mut Vec<&'a str>) {
+        for selection in selection_set {
+            match selection {
+                Selection::Field { name, .. } => {
+                    if name.as_str() != TYPENAME && !fields.contains(&name.as_str()) {
+                        fields.push(name.as_str());
+                    }
+                }
+                Selection::InlineFragment { fragment, .. } => {
+                    self.root_fields(&fragment.selection_set, fields)
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    if let Some(fragment) = self.fragments.get(name) {
+                        self.root_fields(&fragment.selection_set, fields)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Paths of the fields the client is not allowed to query, according to the `@authenticated`
+    /// and `@requiresScopes` directives of the schema and to the claims of the client.
+    pub fn unauthorized_paths(
+        &self,
+        operation_name: Option<&str>,
+        schema: &Schema,
+        claims: Option<&Value>,
+    ) -> Vec<Path> {
+        if schema.authorization.is_empty() {
+            return Vec::new();
+        }
+        let (operation, root_type) = match self.operation_with_root_type(operation_name) {
+            Some(operation) => operation,
+            None => return Vec::new(),
+        };
+
+        let mut unauthorized = UnauthorizedPaths::new(schema, &self.fragments, claims);
+        unauthorized.visit(&operation.selection_set, root_type, &Path::empty());
+        unauthorized.paths
+    }
+
+    /// The query without the fields the client is not allowed to query, nor the fragments and
+    /// variables only they used, or `None` if nothing is left of the operation `operation_name`.
+    pub fn authorized_query(
+        &self,
+        operation_name: Option<&str>,
+        schema: &Schema,
+        claims: Option<&Value>,
+    ) -> Option<String> {
+        filter_unauthorized(&self.string, operation_name, schema, claims)
+    }
+
+    /// Fields resolved by the `response` of the operation, by type and field name, with the root
+    /// of the tree of its nodes.
+    pub fn field_usage(
+        &self,
+        operation_name: Option<&str>,
+        response: &Response,
+        schema: &Schema,
+    ) -> (HashMap<String, HashMap<String, FieldUsage>>, TraceNode) {
+        let mut root = TraceNode::default();
+        let (operation, root_type) = match self.operation_with_root_type(operation_name) {
+            Some(operation) => operation,
+            None => return (HashMap::new(), root),
+        };
+
+        let mut visitor = ResponseUsage::new(&self.fragments, schema);
+        if let Some(Value::Object(data)) = &response.data {
+            visitor.visit_object(
+                &operation.selection_set,
+                root_type,
+                data,
+                &mut root.children,
+            );
+        }
+        for error in &response.errors {
+            let path = error.path.as_ref().map(|path| path.0.as_slice());
+            root.add_error(path.unwrap_or_default(), &error.message);
+        }
+        (visitor.usage, root)
+    }
+
+    /// Estimated cost of the operation, from the `@cost` and `@listSize` directives of the schema.
+    pub fn estimated_cost(
+        &self,
+        operation_name: Option<&str>,
+        schema: &Schema,
+        variables: &Object,
+    ) -> u64 {
+        let (operation, root_type) = match self.operation_with_root_type(operation_name) {
+            Some(operation) => operation,
+            None => return 0,
+        };
+
+        CostEstimator::new(&self.fragments, schema, variables)
+            .estimate(&operation.selection_set, root_type)
+    }
+
+    /// Variables of the operation passed to arguments bound to the request context with
+    /// `@fromContext`, and where their values come from.
+    ///
+    /// Fails with the paths of the fields not given a variable for such an argument.
+    pub fn context_variables(
+        &self,
+        operation_name: Option<&str>,
+        schema: &Schema,
+    ) -> Result<Vec<(String, ContextArgument)>, Vec<Path>> {
+        if schema.context_arguments.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (operation, root_type) = match self.operation_with_root_type(operation_name) {
+            Some(operation) => operation,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut context_bindings = ContextBindings::new(&self.fragments, schema);
+        context_bindings.visit(&operation.selection_set, root_type);
+        let bindings = context_bindings.bindings;
+        if bindings.unbound.is_empty() {
+            Ok(bindings.variables)
+        } else {
+            Err(bindings.unbound)
+        }
+    }
+
+    fn operation_with_root_type(&self, operation_name: Option<&str>) -> Option<(&Operation, &str)> {
+        let operation = match operation_name {
+            Some(name) => self
+                .operations
+                .iter()
+                .find(|op| op.name.as_deref() == Some(name)),
+            None => self.operations.get(0),
+        }?;
+        Some((operation, operation.root_type.as_str()))
+    }
+}
+
+/// Coerces a variable value to `ty`, leaving the values that cannot be coerced for the
+/// validation to reject.
+fn coerce_value(ty: &FieldType, value: Value, schema: &Schema) -> Value {
+    match (ty, value) {
+        (FieldType::NonNull(inner), value) => coerce_value(inner, value, schema),
+        (_, Value::Null) => Value::Null,
+        (FieldType::List(inner), Value::Array(values)) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| coerce_value(inner, value, schema))
+                .collect(),
+        ),
+        (FieldType::List(inner), value) => Value::Array(vec![coerce_value(inner, value, schema)]),
+        (FieldType::Named(name), Value::Object(mut object)) => {
+            if let Some(input_type) = schema.input_types.get(name) {
+                for (field, ty) in input_type.fields() {
+                    if let Some(value) = object.get_mut(field.as_str()) {
+                        *value = coerce_value(ty, std::mem::take(value), schema);
+                    }
+                }
+            }
+            Value::Object(object)
+        }
+        (_, value) => value,
+    }
+}
+
+/// The kind of an operation, a query when it is written in the shorthand form.
+pub(crate) fn operation_kind(operation: &ast::OperationDefinition) -> OperationKind {
+    operation
+        .operation_type()
+        .and_then(|op| {
+            op.query_token()
+                .map(|_| OperationKind::Query)
+                .or_else(|| op.mutation_token().map(|_| OperationKind::Mutation))
+                .or_else(|| op.subscription_token().map(|_| OperationKind::Subscription))
+        })
+        .unwrap_or(OperationKind::Query)
 }
 
 #[derive(Debug)]
 struct Operation {
     name: Option<String>,
     kind: OperationKind,
+    root_type: String,
     selection_set: Vec<Selection>,
     variables: HashMap<ByteString, (FieldType, Option<Value>)>,
 }
@@ -657,21 +1238,9 @@ impl Operation {
     fn from_ast(operation: ast::OperationDefinition, schema: &Schema) -> Option<Self> {
         let name = operation.name().map(|x| x.text().to_string());
 
-        let kind = operation
-            .operation_type()
-            .and_then(|op| {
-                op.query_token()
-                    .map(|_| OperationKind::Query)
-                    .or_else(|| op.mutation_token().map(|_| OperationKind::Mutation))
-                    .or_else(|| op.subscription_token().map(|_| OperationKind::Subscription))
-            })
-            .unwrap_or(OperationKind::Query);
-
-        let current_field_type = match kind {
-            OperationKind::Query => FieldType::Named("Query".to_string()),
-            OperationKind::Mutation => FieldType::Named("Mutation".to_string()),
-            OperationKind::Subscription => return None,
-        };
+        let kind = operation_kind(&operation);
+        let root_type = schema.root_operation_type(kind).to_string();
+        let current_field_type = FieldType::Named(root_type.clone());
 
         let selection_set = operation
             .selection_set()
@@ -708,8 +1277,9 @@ impl Operation {
         Some(Operation {
             selection_set,
             name,
-            variables,
             kind,
+            variables,
+            root_type,
         })
     }
 
@@ -753,7 +1323,7 @@ fn parse_default_value(definition: &ast::VariableDefinition) -> Option<Value> {
         .and_then(|value| parse_value(&value))
 }
 
-fn parse_value(value: &ast::Value) -> Option<Value> {
+pub(crate) fn parse_value(value: &ast::Value) -> Option<Value> {
     match value {
         ast::Value::Variable(_) => None,
         ast::Value::StringValue(s) => Some(s.to_string().into()),
@@ -838,6 +1408,383 @@ mod tests {
         }};
     }
 
+    #[test]
+    fn syntax_errors_are_located() {
+        assert!(Query::syntax_errors("{ me { id } }").is_empty());
+
+        let errors = Query::syntax_errors("{\n  me { id ! }\n}");
+        assert!(!errors.is_empty());
+        assert!(errors[0].message.starts_with("syntax error: "));
+        assert_eq!(errors[0].locations[0].line, 2);
+        assert_eq!(
+            errors[0].extensions.get("code"),
+            Some(&"GRAPHQL_PARSE_FAILED".into())
+        );
+    }
+
+    #[test]
+    fn unauthorized_paths() {
+        let schema: Schema = "
+            directive @authenticated on OBJECT | FIELD_DEFINITION | INTERFACE | SCALAR | ENUM
+            directive @requiresScopes(scopes: [[String!]!]!) on OBJECT | FIELD_DEFINITION
+
+            type Query {
+                me: User @authenticated
+                products: [Product]
+            }
+            type User {
+                name: String
+                email: String @requiresScopes(scopes: [[\"read:email\"], [\"admin\"]])
+            }
+            type Product {
+                name: String
+                secret: Secret
+            }
+            type Secret @requiresScopes(scopes: [[\"admin\"]]) {
+                value: String
+            }"
+        .parse()
+        .expect("could not parse schema");
+        let query = Query::parse(
+            "{ me { name email } products { name ...on Product { secret { value } } } }",
+            &schema,
+        )
+        .unwrap();
+        let paths = |claims: Option<Value>| {
+            query
+                .unauthorized_paths(None, &schema, claims.as_ref())
+                .into_iter()
+                .map(|path| path.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(None), vec!["/me", "/products/secret"]);
+        assert_eq!(
+            paths(Some(json!({ "sub": "alice" }))),
+            vec!["/me/email", "/products/secret"]
+        );
+        assert_eq!(
+            paths(Some(json!({ "scope": "profile read:email" }))),
+            vec!["/products/secret"]
+        );
+        assert!(paths(Some(json!({ "scope": "admin" }))).is_empty());
+    }
+
+    #[test]
+    fn authorized_query() {
+        let schema: Schema = "
+            directive @authenticated on OBJECT | FIELD_DEFINITION | INTERFACE | SCALAR | ENUM
+            directive @requiresScopes(scopes: [[String!]!]!) on OBJECT | FIELD_DEFINITION
+
+            schema {
+                query: Root
+            }
+            type Root {
+                me: User
+                products(first: Int): [Product]
+                named: [Named]
+            }
+            interface Named {
+                name: String
+                secret: String
+            }
+            type User @authenticated {
+                name: String
+                email: String @requiresScopes(scopes: [[\"read:email\"]])
+            }
+            type Product implements Named {
+                name: String
+                secret: String @requiresScopes(scopes: [[\"admin\"]])
+            }"
+        .parse()
+        .expect("could not parse schema");
+        let compact = |query: &str| {
+            query
+                .chars()
+                .filter(|c| !c.is_whitespace() && *c != ',')
+                .collect::<String>()
+        };
+        let authorized = |query: &str, operation_name: Option<&str>, claims: Value| {
+            let claims = Some(claims).filter(|claims| !claims.is_null());
+            Query::parse(query, &schema)
+                .unwrap()
+                .authorized_query(operation_name, &schema, claims.as_ref())
+                .map(|query| compact(&query))
+        };
+
+        let query = "query Q($email: Boolean!, $first: Int) {
+                me { ...UserFields }
+                products(first: $first) { name }
+                named { secret }
+            }
+            fragment UserFields on User { name email @include(if: $email) }";
+        assert_eq!(
+            authorized(query, None, Value::Null),
+            Some(compact(
+                "query Q($first: Int) { products(first: $first) { name } }"
+            ))
+        );
+        assert_eq!(
+            authorized(query, None, json!({ "sub": "alice" })),
+            Some(compact(
+                "query Q($first: Int) {
+                    me { ...UserFields }
+                    products(first: $first) { name }
+                }
+                fragment UserFields on User { name }"
+            ))
+        );
+        assert_eq!(
+            authorized(query, None, json!({ "scope": "read:email admin" })),
+            Some(compact(query))
+        );
+
+        assert_eq!(authorized("{ me { name } }", None, Value::Null), None);
+        assert_eq!(
+            authorized(
+                "query A { me { name } } query B { products { name } }",
+                Some("B"),
+                Value::Null
+            ),
+            Some(compact("query B { products { name } }"))
+        );
+
+        let paths = Query::parse("{ named { name secret } }", &schema)
+            .unwrap()
+            .unauthorized_paths(None, &schema, None);
+        assert_eq!(
+            paths
+                .into_iter()
+                .map(|path| path.to_string())
+                .collect::<Vec<_>>(),
+            vec!["/named/secret"]
+        );
+    }
+
+    #[test]
+    fn estimated_cost() {
+        let schema: Schema = "
+            directive @cost(weight: Int!) on OBJECT | FIELD_DEFINITION | SCALAR | ENUM
+            directive @listSize(assumedSize: Int, slicingArguments: [String!]) on FIELD_DEFINITION
+
+            type Query {
+                me: User
+                products(first: Int): [Product!]! @listSize(assumedSize: 100, slicingArguments: [\"first\"])
+            }
+            type User {
+                name: String
+            }
+            type Product @cost(weight: 2) {
+                name: String
+                price: Int @cost(weight: 3)
+                reviews: [Review]
+            }
+            type Review {
+                body: String
+            }"
+        .parse()
+        .expect("could not parse schema");
+        let query = Query::parse(
+            "query Me { me { __typename name } }
+            query Top($first: Int) { products(first: $first) { name ...Price } }
+            query All { products { reviews { body } } }
+            fragment Price on Product { price }",
+            &schema,
+        )
+        .unwrap();
+        let cost = |operation_name, variables: Value| {
+            query.estimated_cost(
+                Some(operation_name),
+                &schema,
+                variables.as_object().unwrap(),
+            )
+        };
+
+        assert_eq!(cost("Me", json!({})), 1);
+        assert_eq!(cost("Top", json!({ "first": 10 })), 10 * (2 + 3));
+        assert_eq!(cost("All", json!({})), 100 * (2 + 1));
+    }
+
+    #[test]
+    fn context_variables() {
+        let schema: Schema = "
+            directive @fromContext(key: String!, path: String) on ARGUMENT_DEFINITION
+
+            type Query {
+                me: User
+            }
+            type User {
+                orders(owner: ID @fromContext(key: \"apollo::authentication::jwt_claims\", path: \"sub\"), first: Int): [Order]
+            }
+            type Order {
+                id: ID
+            }"
+        .parse()
+        .expect("could not parse schema");
+        let query = Query::parse(
+            "
+            query Bound($owner: ID, $first: Int) { me { ...Orders } }
+            query Literal { me { mine: orders(owner: \"alice\") { id } } }
+            query Omitted { me { ... on User { orders(first: 10) { id } } } }
+            fragment Orders on User { orders(owner: $owner, first: $first) { id } }",
+            &schema,
+        )
+        .unwrap();
+
+        let variables = query.context_variables(Some("Bound"), &schema).unwrap();
+        assert_eq!(variables.len(), 1);
+        let (variable, argument) = &variables[0];
+        assert_eq!(variable, "owner");
+        let context = Context::new();
+        assert_eq!(argument.resolve(&context), None);
+        context
+            .insert(
+                AUTHENTICATION_CLAIMS_CONTEXT_KEY,
+                json!({ "scope": "orders" }),
+            )
+            .unwrap();
+        assert_eq!(argument.resolve(&context), None);
+        context
+            .insert(
+                AUTHENTICATION_CLAIMS_CONTEXT_KEY,
+                json!({ "sub": "alice", "scope": "orders" }),
+            )
+            .unwrap();
+        assert_eq!(argument.resolve(&context), Some(json!("alice")));
+
+        let unbound = |operation_name| {
+            query
+                .context_variables(Some(operation_name), &schema)
+                .unwrap_err()
+                .into_iter()
+                .map(|path| path.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(unbound("Literal"), vec!["/me/mine"]);
+        assert_eq!(unbound("Omitted"), vec!["/me/orders"]);
+    }
+
+    #[test]
+    fn context_variables_of_interface_fields() {
+        let schema: Schema = "
+            directive @fromContext(key: String!, path: String) on ARGUMENT_DEFINITION
+
+            schema {
+                query: Root
+            }
+            type Root {
+                owner: Owner
+            }
+            interface Owner {
+                orders(owner: ID): [Order]
+            }
+            type User implements Owner {
+                orders(owner: ID @fromContext(key: \"apollo::authentication::jwt_claims\", path: \"sub\")): [Order]
+            }
+            type Order {
+                id: ID
+            }"
+        .parse()
+        .expect("could not parse schema");
+        let query = Query::parse("{ owner { orders { id } } }", &schema).unwrap();
+
+        let unbound = query.context_variables(None, &schema).unwrap_err();
+        assert_eq!(unbound, vec![Path::from("owner/orders")]);
+    }
+
+    #[test]
+    fn field_usage() {
+        let schema: Schema = "
+            type Query {
+                me: User
+                products(first: Int): [Product!]!
+            }
+            type User {
+                id: ID!
+                name: String
+            }
+            type Product {
+                name: String
+                reviews: [Review]
+            }
+            type Review {
+                author: User
+            }"
+        .parse()
+        .expect("could not parse schema");
+        let query = Query::parse(
+            "query Top { products { name ...Reviews } }
+            query Me { me { __typename userId: id name } }
+            fragment Reviews on Product { reviews { author { name } } }",
+            &schema,
+        )
+        .unwrap();
+        let usage = |operation_name, response: &Response| {
+            let (usage, root) = query.field_usage(Some(operation_name), response, &schema);
+            let mut usage = usage
+                .into_iter()
+                .flat_map(|(ty, fields)| {
+                    fields.into_iter().map(move |(field, usage)| {
+                        (format!("{}.{}", ty, field), usage.return_type, usage.count)
+                    })
+                })
+                .collect::<Vec<_>>();
+            usage.sort();
+            (usage, root)
+        };
+
+        let (top, root) = usage(
+            "Top",
+            &Response::builder()
+                .data(json!({
+                    "products": [
+                        { "name": "a", "reviews": [{ "author": { "name": "x" } }, { "author": null }] },
+                        { "name": "b", "reviews": null },
+                    ]
+                }))
+                .build(),
+        );
+        assert_eq!(
+            top,
+            vec![
+                ("Product.name".to_string(), "String".to_string(), 2),
+                ("Product.reviews".to_string(), "[Review]".to_string(), 2),
+                ("Query.products".to_string(), "[Product!]!".to_string(), 1),
+                ("Review.author".to_string(), "User".to_string(), 2),
+                ("User.name".to_string(), "String".to_string(), 1),
+            ]
+        );
+        let products = &root.children[0];
+        assert_eq!(products.response_name.as_deref(), Some("products"));
+        assert_eq!(products.children.len(), 2);
+        assert_eq!(products.children[1].index, Some(1));
+        assert_eq!(products.children[1].children[0].parent_type, "Product");
+
+        let (me, root) = usage(
+            "Me",
+            &Response::builder()
+                .data(json!({ "me": { "__typename": "User", "userId": "1", "name": null } }))
+                .errors(vec![Error {
+                    message: "no name".to_string(),
+                    path: Some(Path::from("me/name")),
+                    ..Default::default()
+                }])
+                .build(),
+        );
+        assert_eq!(
+            me,
+            vec![
+                ("Query.me".to_string(), "User".to_string(), 1),
+                ("User.id".to_string(), "ID!".to_string(), 1),
+                ("User.name".to_string(), "String".to_string(), 1),
+            ]
+        );
+        let user = &root.children[0].children;
+        assert_eq!(user[0].response_name.as_deref(), Some("userId"));
+        assert_eq!(user[0].original_field_name.as_deref(), Some("id"));
+        assert_eq!(user[1].errors, vec!["no name".to_string()]);
+    }
+
     #[test]
     fn reformat_response_data_field() {
         assert_format_response!(
@@ -894,6 +1841,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn validate_response_reports_the_invalid_path() {
+        let schema: Schema = "type Query {
+            me: User
+        }
+        type User {
+            id: ID!
+            name: String
+            reviews: [Review!]
+        }
+        type Review {
+            body: String
+            rating: Int!
+        }"
+        .parse()
+        .expect("could not parse schema");
+        let query = Query::parse("{ me { id name reviews { body rating } } }", &schema)
+            .expect("could not parse query");
+        let validate = |data: Value| {
+            query.validate_response(
+                &Response::builder().data(data).build(),
+                None,
+                &Object::default(),
+                &schema,
+            )
+        };
+
+        assert_eq!(
+            validate(json! {{
+                "me": {
+                    "id": "1",
+                    "name": null,
+                    "reviews": [{"body": "good", "rating": 5}],
+                },
+            }}),
+            Ok(())
+        );
+
+        // a merge that put an entity where a scalar was expected
+        assert_eq!(
+            validate(json! {{
+                "me": {
+                    "id": "1",
+                    "name": "Ada",
+                    "reviews": [
+                        {"body": "good", "rating": 5},
+                        {"body": "bad", "rating": {"id": "2"}},
+                    ],
+                },
+            }}),
+            Err(Path::from_slice(&["me", "reviews", "1", "rating"]))
+        );
+
+        // a merge that lost a non null field
+        assert_eq!(
+            validate(json! {{ "me": { "name": "Ada" } }}),
+            Err(Path::from_slice(&["me", "id"]))
+        );
+    }
+
     #[test]
     fn reformat_response_data_inline_fragment() {
         let schema = "type Query {
@@ -1095,6 +2102,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reformat_response_data_prunes_join_fields() {
+        // the query planner fetches `__typename` and key fields to join entities across
+        // subgraphs, they must not reach the client unless it selected them
+        assert_format_response!(
+            "type Query {
+                me: User
+            }
+            type User {
+                id: ID!
+                name: String
+                reviews: [Review]
+            }
+            type Review {
+                id: ID!
+                body: String
+                product: Product
+            }
+            type Product {
+                upc: String!
+                name: String
+            }
+            ",
+            "{ me { name reviews { body product { __typename name } } } }",
+            json! {{
+                "me": {
+                    "__typename": "User",
+                    "id": "1",
+                    "name": "Ada",
+                    "reviews": [
+                        {
+                            "__typename": "Review",
+                            "id": "2",
+                            "body": "great",
+                            "product": {"__typename": "Product", "upc": "3", "name": "Table"},
+                        },
+                    ],
+                },
+            }},
+            None,
+            json! {{
+                "me": {
+                    "name": "Ada",
+                    "reviews": [
+                        {
+                            "body": "great",
+                            "product": {"__typename": "Product", "name": "Table"},
+                        },
+                    ],
+                },
+            }},
+        );
+    }
+
     macro_rules! run_validation {
         ($schema:expr, $query:expr, $variables:expr $(,)?) => {{
             let variables = match $variables {
@@ -1169,7 +2230,7 @@ mod tests {
         assert_validation_error!(schema, "query($foo:Float){x}", json!({"foo":2}));
         assert_validation_error!(schema, "query($foo:Int!){x}", json!({}));
         assert_validation!(schema, "query($foo:[Int]){x}", json!({}));
-        assert_validation_error!(schema, "query($foo:[Int]){x}", json!({"foo":1}));
+        assert_validation!(schema, "query($foo:[Int]){x}", json!({"foo":1}));
         assert_validation_error!(schema, "query($foo:[Int]){x}", json!({"foo":"str"}));
         assert_validation_error!(schema, "query($foo:[Int]){x}", json!({"foo":{}}));
         assert_validation_error!(schema, "query($foo:[Int]!){x}", json!({}));
@@ -1283,6 +2344,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn variable_coercion() {
+        let schema: Schema = "input Filter { ids: [Int!] } type Query { x(f: Filter): String }"
+            .parse()
+            .unwrap();
+        let request = Request::builder()
+            .query(Some(
+                "query($f: Filter, $limit: Int = 10, $on: Boolean!) { x(f: $f) }".to_string(),
+            ))
+            .variables(
+                json!({ "f": { "ids": 3 }, "on": true })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .build();
+        let query = Query::parse(request.query.as_ref().unwrap(), &schema).unwrap();
+
+        assert_eq!(
+            Value::Object(query.coerce_variables(&request, &schema).unwrap()),
+            json!({ "f": { "ids": [3] }, "on": true, "limit": 10 })
+        );
+
+        // Strings are not coerced to other scalars.
+        let request = Request::builder()
+            .query(request.query.clone())
+            .variables(
+                json!({ "f": { "ids": "3" }, "on": "true" })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .build();
+        assert_eq!(
+            query
+                .coerce_variables(&request, &schema)
+                .unwrap_err()
+                .errors
+                .len(),
+            2
+        );
+    }
+
     #[test]
     fn filter_root_errors() {
         let schema = "type Query {
@@ -3793,4 +4897,53 @@ mod tests {
             }},
         );
     }
+
+    #[test]
+    fn subscriptions_are_resolved_by_the_subgraph_of_their_root_field() {
+        let schema: Schema = r#"
+        schema
+          @core(feature: "https://specs.apollo.dev/core/v0.1"),
+          @core(feature: "https://specs.apollo.dev/join/v0.1")
+        {
+          query: Query
+          subscription: Subscription
+        }
+        directive @core(feature: String!) repeatable on SCHEMA
+        directive @join__field(graph: join__Graph) on FIELD_DEFINITION
+        directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+
+        enum join__Graph {
+            ACCOUNTS @join__graph(name: "accounts" url: "http://localhost:4001/graphql")
+            REVIEWS @join__graph(name: "reviews" url: "http://localhost:4002/graphql")
+        }
+        type Query {
+          me: String @join__field(graph: ACCOUNTS)
+        }
+        type Subscription {
+          userWasCreated: String @join__field(graph: ACCOUNTS)
+          reviewAdded: String @join__field(graph: REVIEWS)
+        }"#
+        .parse()
+        .unwrap();
+
+        let query = Query::parse(
+            "subscription OnReview { ...review __typename }
+            fragment review on Subscription { reviewAdded }
+            subscription Both { userWasCreated reviewAdded }
+            query Me { me }",
+            &schema,
+        )
+        .unwrap();
+        assert!(query.is_subscription(Some("OnReview")));
+        assert!(!query.is_subscription(Some("Me")));
+        assert_eq!(
+            query.subscription_subgraph(Some("OnReview"), &schema),
+            Ok("reviews")
+        );
+        assert!(query.subscription_subgraph(Some("Both"), &schema).is_err());
+
+        let query = Query::parse("subscription { userWasCreated }", &schema).unwrap();
+        assert!(query.is_subscription(None));
+        assert_eq!(query.subscription_subgraph(None, &schema), Ok("accounts"));
+    }
 }
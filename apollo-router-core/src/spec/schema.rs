@@ -1,10 +1,12 @@
 //! GraphQL schema.
 
+use crate::fetch::OperationKind;
 use crate::*;
 use apollo_parser::ast;
 use http::Uri;
 use itertools::Itertools;
 use router_bridge::api_schema;
+use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet};
 
 /// A GraphQL schema.
@@ -18,6 +20,11 @@ pub struct Schema {
     pub(crate) input_types: HashMap<String, InputObjectType>,
     pub(crate) custom_scalars: HashSet<String>,
     pub(crate) enums: HashMap<String, HashSet<String>>,
+    pub(crate) authorization: Authorization,
+    pub(crate) costs: Costs,
+    pub(crate) context_arguments: ContextArguments,
+    pub(crate) subscription_fields: SubscriptionFields,
+    root_operation_types: HashMap<OperationKind, String>,
     api_schema: Option<Box<Schema>>,
 }
 
@@ -366,6 +373,31 @@ impl std::str::FromStr for Schema {
                 })
                 .collect();
 
+            let mut root_operation_types = default_root_operation_types();
+            // Spec: https://spec.graphql.org/draft/#sec-Root-Operation-Types
+            for definition in document.definitions() {
+                if let ast::Definition::SchemaDefinition(definition) = definition {
+                    for root in definition.root_operation_type_definitions() {
+                        let kind = match root.operation_type() {
+                            Some(op) if op.query_token().is_some() => OperationKind::Query,
+                            Some(op) if op.mutation_token().is_some() => OperationKind::Mutation,
+                            Some(op) if op.subscription_token().is_some() => {
+                                OperationKind::Subscription
+                            }
+                            _ => continue,
+                        };
+                        if let Some(name) = root.named_type().and_then(|named| named.name()) {
+                            root_operation_types.insert(kind, name.text().to_string());
+                        }
+                    }
+                }
+            }
+
+            let subscription_fields = SubscriptionFields::from_document(
+                &document,
+                &root_operation_types[&OperationKind::Subscription],
+            );
+
             Ok(Schema {
                 subtype_map,
                 string: schema.to_owned(),
@@ -375,6 +407,11 @@ impl std::str::FromStr for Schema {
                 interfaces,
                 custom_scalars,
                 enums,
+                authorization: Authorization::from_document(&document),
+                costs: Costs::from_document(&document),
+                context_arguments: ContextArguments::from_document(&document),
+                subscription_fields,
+                root_operation_types,
                 api_schema: None,
             })
         }
@@ -392,6 +429,16 @@ impl Schema {
         &self.string
     }
 
+    /// SHA-256 of the [`Schema`], in hexadecimal.
+    pub fn hash(&self) -> String {
+        hex::encode(Sha256::digest(self.string.as_bytes()))
+    }
+
+    /// Name of the root type of the operations of `kind`.
+    pub(crate) fn root_operation_type(&self, kind: OperationKind) -> &str {
+        &self.root_operation_types[&kind]
+    }
+
     pub(crate) fn is_subtype(&self, abstract_type: &str, maybe_subtype: &str) -> bool {
         self.subtype_map
             .get(abstract_type)
@@ -421,6 +468,11 @@ impl Schema {
             input_types: Default::default(),
             custom_scalars: Default::default(),
             enums: Default::default(),
+            authorization: Default::default(),
+            costs: Default::default(),
+            context_arguments: Default::default(),
+            subscription_fields: Default::default(),
+            root_operation_types: default_root_operation_types(),
             api_schema: None,
         }
     }
@@ -430,6 +482,15 @@ impl Schema {
     }
 }
 
+/// The root types of the operations of a schema without a schema definition.
+fn default_root_operation_types() -> HashMap<OperationKind, String> {
+    HashMap::from([
+        (OperationKind::Query, "Query".to_string()),
+        (OperationKind::Mutation, "Mutation".to_string()),
+        (OperationKind::Subscription, "Subscription".to_string()),
+    ])
+}
+
 #[derive(Debug)]
 pub(crate) struct InvalidObject;
 
@@ -534,6 +595,10 @@ macro_rules! implement_input_object_type_or_interface {
                     })
                     .map_err(|_| InvalidObject)
             }
+
+            pub(crate) fn fields(&self) -> impl Iterator<Item = (&String, &FieldType)> {
+                self.fields.iter()
+            }
         }
 
         $(
@@ -9,6 +9,7 @@ pub(crate) enum Selection {
         alias: Option<ByteString>,
         selection_set: Option<Vec<Selection>>,
         field_type: FieldType,
+        arguments: Vec<(String, Argument)>,
         skip: Skip,
         include: Include,
     },
@@ -43,6 +44,14 @@ impl Selection {
                     FieldType::String
                 } else if field_name.starts_with("__") {
                     FieldType::Introspection(field_name.clone())
+                } else if field_name == "_entities"
+                    && current_type.inner_type_name() == Some("Query")
+                {
+                    // The entities field that subgraphs add to their schema, used by the entity
+                    // fetches of query plans. Its entities are told apart by their inline fragments.
+                    FieldType::NonNull(Box::new(FieldType::List(Box::new(FieldType::Named(
+                        "_Entity".to_string(),
+                    )))))
                 } else {
                     current_type
                         .inner_type_name()
@@ -76,6 +85,28 @@ impl Selection {
                     })
                 };
 
+                let arguments = field
+                    .arguments()
+                    .iter()
+                    .flat_map(|arguments| arguments.arguments())
+                    .filter_map(|argument| {
+                        let name = argument.name()?.text().to_string();
+                        let value = match argument.value()? {
+                            Value::Variable(variable) => {
+                                Argument::Variable(variable.name()?.text().to_string())
+                            }
+                            Value::IntValue(value) => value
+                                .to_string()
+                                .trim()
+                                .parse()
+                                .map(Argument::Int)
+                                .unwrap_or(Argument::Literal),
+                            _ => Argument::Literal,
+                        };
+                        Some((name, value))
+                    })
+                    .collect();
+
                 let skip = field
                     .directives()
                     .map(|directives| {
@@ -104,6 +135,7 @@ impl Selection {
                     name: field_name.into(),
                     selection_set,
                     field_type,
+                    arguments,
                     skip,
                     include,
                 })
@@ -213,6 +245,17 @@ impl Selection {
     }
 }
 
+/// The value given to an argument of a field by an operation, as far as the router looks at it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Argument {
+    /// A variable of the operation.
+    Variable(String),
+    /// A non-negative integer, like the size of a list.
+    Int(u64),
+    /// Any other value written in the operation.
+    Literal,
+}
+
 pub(crate) fn parse_skip(directive: &ast::Directive) -> Option<Skip> {
     if directive
         .name()
@@ -0,0 +1,451 @@
+//! Signatures of operations, to log and report them without the values they carry.
+//!
+//! The signature of an operation is the operation and the fragments it spreads, printed on a
+//! single line. String and number literals become `""` and `0`, lists `[]` and objects `{}`, and
+//! selections and arguments are sorted, so that operations differing only in those share a
+//! signature. Variables may carry personal data too, and are replaced by HMACs of their values,
+//! keyed by a configured secret so that they cannot be matched against guessed values.
+
+use crate::Object;
+use apollo_parser::ast;
+use hmac::{Hmac, Mac};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Context key holding the [`OperationSignature`] of the operation of a request.
+pub const OPERATION_SIGNATURE_CONTEXT_KEY: &str = "apollo::operation_signature";
+
+/// The sanitized form of an operation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationSignature {
+    /// The operation, without its literals.
+    pub signature: String,
+    /// SHA-256 of the signature, in hexadecimal.
+    pub hash: String,
+    /// HMAC-SHA256 of the JSON value of each variable, keyed by the configured secret, in
+    /// hexadecimal, when variables are hashed.
+    pub variables: Option<BTreeMap<String, String>>,
+}
+
+/// How operations are sanitized.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OperationSanitizer {
+    /// Replace string, number, list and object literals. Enabled by default.
+    #[serde(default = "enabled")]
+    pub hide_literals: bool,
+
+    /// Sort the selections and arguments. Enabled by default.
+    #[serde(default = "enabled")]
+    pub sort_fields: bool,
+
+    /// Hash the values of the variables, which needs a `variables_secret`. Enabled by default.
+    #[serde(default = "enabled")]
+    pub hash_variables: bool,
+
+    /// Secret keying the hashes of the variables. Hashes made with different secrets differ.
+    #[serde(default)]
+    pub variables_secret: Option<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for OperationSanitizer {
+    fn default() -> Self {
+        Self {
+            hide_literals: true,
+            sort_fields: true,
+            hash_variables: true,
+            variables_secret: None,
+        }
+    }
+}
+
+impl OperationSanitizer {
+    /// The signature of the operation `operation_name` of `query`, or of its first operation,
+    /// with the hashes of `variables`.
+    ///
+    /// Returns `None` for documents that cannot be parsed or lack the operation.
+    pub fn sign(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Object,
+    ) -> Option<OperationSignature> {
+        let mut signature = self.signature(query, operation_name)?;
+        signature.variables = self.variable_hashes(variables);
+        Some(signature)
+    }
+
+    /// The signature of the operation `operation_name` of `query`, without variables, which does
+    /// not change from a request to the next.
+    pub fn signature(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+    ) -> Option<OperationSignature> {
+        let parsed = apollo_parser::Parser::new(query).parse();
+        if parsed.errors().len() > 0 {
+            return None;
+        }
+        let document = parsed.document();
+
+        let mut fragments = HashMap::new();
+        let mut operation = None;
+        for definition in document.definitions() {
+            match definition {
+                ast::Definition::FragmentDefinition(fragment) => {
+                    if let Some(name) = fragment.fragment_name().and_then(|name| name.name()) {
+                        fragments.insert(name.text().to_string(), fragment);
+                    }
+                }
+                ast::Definition::OperationDefinition(definition) if operation.is_none() => {
+                    let name = definition.name().map(|name| name.text().to_string());
+                    if operation_name.is_none() || name.as_deref() == operation_name {
+                        operation = Some(definition);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut printer = Printer {
+            sanitizer: self,
+            spread: BTreeSet::new(),
+        };
+        let mut signature = printer.operation(operation?);
+        let mut printed = BTreeMap::new();
+        while let Some(name) = printer
+            .spread
+            .iter()
+            .find(|name| !printed.contains_key(*name))
+            .cloned()
+        {
+            let fragment = fragments
+                .get(&name)
+                .map(|fragment| printer.fragment(&name, fragment.clone()))
+                .unwrap_or_default();
+            printed.insert(name, fragment);
+        }
+        for fragment in printed
+            .into_values()
+            .filter(|fragment| !fragment.is_empty())
+        {
+            signature.push(' ');
+            signature.push_str(&fragment);
+        }
+
+        Some(OperationSignature {
+            hash: hex::encode(Sha256::digest(signature.as_bytes())),
+            signature,
+            variables: None,
+        })
+    }
+
+    /// The hashes of `variables`, if they are hashed and a secret is configured.
+    pub fn variable_hashes(&self, variables: &Object) -> Option<BTreeMap<String, String>> {
+        let secret = self
+            .variables_secret
+            .as_ref()
+            .filter(|_| self.hash_variables)?;
+        Some(
+            variables
+                .iter()
+                .map(|(name, value)| {
+                    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                        .expect("HMAC accepts keys of any size; qed");
+                    mac.update(&serde_json::to_vec(value).unwrap_or_default());
+                    (
+                        name.as_str().to_string(),
+                        hex::encode(mac.finalize().into_bytes()),
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Prints the definitions of a document on a single line.
+struct Printer<'a> {
+    sanitizer: &'a OperationSanitizer,
+    /// Names of the fragments spread by what was printed so far.
+    spread: BTreeSet<String>,
+}
+
+impl<'a> Printer<'a> {
+    fn operation(&mut self, operation: ast::OperationDefinition) -> String {
+        let mut printed = match operation.operation_type() {
+            Some(op) if op.mutation_token().is_some() => "mutation".to_string(),
+            Some(op) if op.subscription_token().is_some() => "subscription".to_string(),
+            _ => "query".to_string(),
+        };
+        if let Some(name) = operation.name() {
+            printed.push(' ');
+            printed.push_str(&name.text().to_string());
+        }
+        let variables: Vec<String> = operation
+            .variable_definitions()
+            .iter()
+            .flat_map(|definitions| definitions.variable_definitions())
+            .filter_map(|definition| {
+                let name = definition.variable()?.name()?.text().to_string();
+                let ty = compact(&definition.ty()?.to_string());
+                Some(
+                    match definition.default_value().and_then(|value| value.value()) {
+                        Some(default) => format!("${}: {} = {}", name, ty, self.value(default)),
+                        None => format!("${}: {}", name, ty),
+                    },
+                )
+            })
+            .collect();
+        if !variables.is_empty() {
+            printed.push_str(&format!("({})", variables.join(", ")));
+        }
+        printed.push_str(&self.directives(operation.directives()));
+        if let Some(selection_set) = operation.selection_set() {
+            printed.push(' ');
+            printed.push_str(&self.selection_set(selection_set));
+        }
+        printed
+    }
+
+    fn fragment(&mut self, name: &str, fragment: ast::FragmentDefinition) -> String {
+        let type_condition = fragment
+            .type_condition()
+            .and_then(|condition| condition.named_type())
+            .and_then(|named_type| named_type.name())
+            .map(|name| name.text().to_string())
+            .unwrap_or_default();
+        let selection_set = fragment
+            .selection_set()
+            .map(|selection_set| self.selection_set(selection_set))
+            .unwrap_or_default();
+        format!(
+            "fragment {} on {}{} {}",
+            name,
+            type_condition,
+            self.directives(fragment.directives()),
+            selection_set
+        )
+    }
+
+    fn selection_set(&mut self, selection_set: ast::SelectionSet) -> String {
+        let mut selections: Vec<String> = selection_set
+            .selections()
+            .filter_map(|selection| self.selection(selection))
+            .collect();
+        if self.sanitizer.sort_fields {
+            selections.sort();
+        }
+        format!("{{ {} }}", selections.join(" "))
+    }
+
+    fn selection(&mut self, selection: ast::Selection) -> Option<String> {
+        Some(match selection {
+            ast::Selection::Field(field) => {
+                let mut printed = String::new();
+                if let Some(alias) = field.alias().and_then(|alias| alias.name()) {
+                    printed.push_str(&format!("{}: ", alias.text()));
+                }
+                printed.push_str(&field.name()?.text().to_string());
+                printed.push_str(&self.arguments(field.arguments()));
+                printed.push_str(&self.directives(field.directives()));
+                if let Some(selection_set) = field.selection_set() {
+                    printed.push(' ');
+                    printed.push_str(&self.selection_set(selection_set));
+                }
+                printed
+            }
+            ast::Selection::FragmentSpread(spread) => {
+                let name = spread.fragment_name()?.name()?.text().to_string();
+                let printed = format!("...{}{}", name, self.directives(spread.directives()));
+                self.spread.insert(name);
+                printed
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                let mut printed = "...".to_string();
+                if let Some(name) = fragment
+                    .type_condition()
+                    .and_then(|condition| condition.named_type())
+                    .and_then(|named_type| named_type.name())
+                {
+                    printed.push_str(&format!(" on {}", name.text()));
+                }
+                printed.push_str(&self.directives(fragment.directives()));
+                if let Some(selection_set) = fragment.selection_set() {
+                    printed.push(' ');
+                    printed.push_str(&self.selection_set(selection_set));
+                }
+                printed
+            }
+        })
+    }
+
+    fn arguments(&self, arguments: Option<ast::Arguments>) -> String {
+        let mut printed: Vec<String> = arguments
+            .iter()
+            .flat_map(|arguments| arguments.arguments())
+            .filter_map(|argument| {
+                Some(format!(
+                    "{}: {}",
+                    argument.name()?.text(),
+                    self.value(argument.value()?)
+                ))
+            })
+            .collect();
+        if self.sanitizer.sort_fields {
+            printed.sort();
+        }
+        if printed.is_empty() {
+            String::new()
+        } else {
+            format!("({})", printed.join(", "))
+        }
+    }
+
+    fn directives(&self, directives: Option<ast::Directives>) -> String {
+        directives
+            .iter()
+            .flat_map(|directives| directives.directives())
+            .filter_map(|directive| {
+                Some(format!(
+                    " @{}{}",
+                    directive.name()?.text(),
+                    self.arguments(directive.arguments())
+                ))
+            })
+            .collect()
+    }
+
+    fn value(&self, value: ast::Value) -> String {
+        let hide = self.sanitizer.hide_literals;
+        match value {
+            ast::Value::Variable(variable) => format!(
+                "${}",
+                variable
+                    .name()
+                    .map(|name| name.text().to_string())
+                    .unwrap_or_default()
+            ),
+            ast::Value::StringValue(_) if hide => "\"\"".to_string(),
+            ast::Value::IntValue(_) | ast::Value::FloatValue(_) if hide => "0".to_string(),
+            ast::Value::ListValue(_) if hide => "[]".to_string(),
+            ast::Value::ObjectValue(_) if hide => "{}".to_string(),
+            ast::Value::ListValue(list) => format!(
+                "[{}]",
+                list.values()
+                    .map(|value| self.value(value))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ast::Value::ObjectValue(object) => format!(
+                "{{{}}}",
+                object
+                    .object_fields()
+                    .filter_map(|field| Some(format!(
+                        "{}: {}",
+                        field.name()?.text(),
+                        self.value(field.value()?)
+                    )))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ast::Value::StringValue(value) => value.to_string().trim().to_string(),
+            ast::Value::IntValue(value) => value.to_string().trim().to_string(),
+            ast::Value::FloatValue(value) => value.to_string().trim().to_string(),
+            ast::Value::BooleanValue(value) => value.to_string().trim().to_string(),
+            ast::Value::NullValue(value) => value.to_string().trim().to_string(),
+            ast::Value::EnumValue(value) => value.to_string().trim().to_string(),
+        }
+    }
+}
+
+/// `text` without its whitespace, for the types of variables.
+fn compact(text: &str) -> String {
+    text.split_whitespace().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json_bytes::json;
+
+    #[test]
+    fn literals_are_hidden_and_selections_sorted() {
+        let sanitizer = OperationSanitizer {
+            variables_secret: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let signature = sanitizer
+            .sign(
+                r#"
+                query Search($term: String!, $first: Int = 10) {
+                    search(term: $term, first: 5, filter: { email: "ada@example.com" }) {
+                        name
+                        ... on User @include(if: true) { email }
+                        ...Ids
+                    }
+                }
+                fragment Ids on Node { id }"#,
+                Some("Search"),
+                json!({ "term": "ada" }).as_object().unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            signature.signature,
+            "query Search($term: String!, $first: Int = 0) { search(filter: {}, first: 0, \
+             term: $term) { ... on User @include(if: true) { email } ...Ids name } } \
+             fragment Ids on Node { id }"
+        );
+        assert_eq!(
+            signature.hash,
+            hex::encode(Sha256::digest(signature.signature.as_bytes()))
+        );
+        let variables = signature.variables.unwrap();
+        assert_eq!(
+            variables["term"],
+            "95d36267fea5cd39f8ab333678c6d2cf3fb6367df4576d33b70da3ca315b5ba0"
+        );
+
+        // Without a secret, variables are left out rather than hashed with a guessable key.
+        let unkeyed = OperationSanitizer::default()
+            .sign(
+                "{ me { name } }",
+                None,
+                json!({ "term": "ada" }).as_object().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(unkeyed.variables, None);
+    }
+
+    #[test]
+    fn operations_differing_in_literals_share_a_signature() {
+        let sanitizer = OperationSanitizer::default();
+        let sign = |query: &str| {
+            sanitizer
+                .sign(query, None, &Object::new())
+                .map(|signature| signature.hash)
+        };
+        assert_eq!(
+            sign("{ user(id: 1) { name email } }"),
+            sign("query {\n  user(id: 2) { email name }\n}")
+        );
+        assert_eq!(sign("{ user(id: "), None);
+
+        let verbatim = OperationSanitizer {
+            hide_literals: false,
+            sort_fields: false,
+            hash_variables: false,
+            variables_secret: None,
+        }
+        .sign("{ user(id: 1) { name email } }", None, &Object::new())
+        .unwrap();
+        assert_eq!(verbatim.signature, "query { user(id: 1) { name email } }");
+        assert_eq!(verbatim.variables, None);
+    }
+}
@@ -0,0 +1,116 @@
+//! The subgraphs resolving the fields of the subscription root type, declared in the supergraph
+//! with the `@join__field` and `@join__type` directives.
+
+use super::directives::{argument, is_named};
+use apollo_parser::ast;
+use std::collections::HashMap;
+
+/// The subgraph resolving each field of the subscription root type, by field name.
+#[derive(Debug, Default)]
+pub(crate) struct SubscriptionFields {
+    subgraphs: HashMap<String, String>,
+}
+
+impl SubscriptionFields {
+    pub(crate) fn from_document(document: &ast::Document, root_type: &str) -> Self {
+        let graphs = graph_names(document);
+        let mut subgraphs = HashMap::new();
+        for definition in document.definitions() {
+            let (name, directives, fields) = match definition {
+                ast::Definition::ObjectTypeDefinition(object) => (
+                    object.name(),
+                    object.directives(),
+                    object.fields_definition(),
+                ),
+                ast::Definition::ObjectTypeExtension(object) => (
+                    object.name(),
+                    object.directives(),
+                    object.fields_definition(),
+                ),
+                _ => continue,
+            };
+            if name.map(|name| name.text().to_string()).as_deref() != Some(root_type) {
+                continue;
+            }
+
+            // Fields without `@join__field` are resolved by the subgraph defining the type, when
+            // there is a single one.
+            let type_graphs = directives
+                .iter()
+                .flat_map(|directives| directives.directives())
+                .filter_map(|directive| graph(&directive, "join__type", &graphs))
+                .collect::<Vec<_>>();
+            for field in fields.iter().flat_map(|fields| fields.field_definitions()) {
+                let field_name = match field.name() {
+                    Some(field_name) => field_name.text().to_string(),
+                    None => continue,
+                };
+                let subgraph = field
+                    .directives()
+                    .iter()
+                    .flat_map(|directives| directives.directives())
+                    .find_map(|directive| graph(&directive, "join__field", &graphs))
+                    .or_else(|| match type_graphs.as_slice() {
+                        [subgraph] => Some(subgraph.clone()),
+                        _ => None,
+                    });
+                if let Some(subgraph) = subgraph {
+                    subgraphs.insert(field_name, subgraph);
+                }
+            }
+        }
+        Self { subgraphs }
+    }
+
+    /// The subgraph resolving the subscription field `name`.
+    pub(crate) fn subgraph(&self, name: &str) -> Option<&str> {
+        self.subgraphs.get(name).map(String::as_str)
+    }
+}
+
+/// Names of the subgraphs, by value of the `join__Graph` enum.
+fn graph_names(document: &ast::Document) -> HashMap<String, String> {
+    document
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::EnumTypeDefinition(enum_type)
+                if enum_type
+                    .name()
+                    .map(|name| name.text().to_string())
+                    .as_deref()
+                    == Some("join__Graph") =>
+            {
+                enum_type.enum_values_definition()
+            }
+            _ => None,
+        })
+        .flat_map(|values| values.enum_value_definitions())
+        .filter_map(|value| {
+            let key = value.enum_value()?.name()?.text().to_string();
+            let name = value
+                .directives()?
+                .directives()
+                .filter(|directive| is_named(directive, "join__graph"))
+                .find_map(|directive| match argument(&directive, "name")? {
+                    ast::Value::StringValue(name) => Some(String::from(name)),
+                    _ => None,
+                })?;
+            Some((key, name))
+        })
+        .collect()
+}
+
+/// The subgraph named by the `graph` argument of `directive`, if it is called `name`.
+fn graph(
+    directive: &ast::Directive,
+    name: &str,
+    graphs: &HashMap<String, String>,
+) -> Option<String> {
+    if !is_named(directive, name) {
+        return None;
+    }
+    match argument(directive, "graph")? {
+        ast::Value::EnumValue(value) => graphs.get(&value.name()?.text().to_string()).cloned(),
+        _ => None,
+    }
+}
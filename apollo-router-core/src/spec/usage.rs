@@ -0,0 +1,233 @@
+//! Fields resolved by the response of an operation, as reported to Apollo Studio.
+
+use crate::{FieldType, Fragments, Object, PathElement, Schema, Selection, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Context key holding the [`FieldUsage`] of the response of a request, by type and field name.
+///
+/// The router service only fills it in when the key is already present, so that requests nobody
+/// reports usage for do not pay for it.
+pub const FIELD_USAGE_CONTEXT_KEY: &str = "apollo::usage::fields";
+
+/// How a response uses a field.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldUsage {
+    /// Type of the field, as written in the schema, like `[Product!]`.
+    pub return_type: String,
+    /// Number of times the field was resolved by the response.
+    pub count: u64,
+}
+
+/// Fields resolved by a response, by type and field name.
+pub(crate) type Usage = HashMap<String, HashMap<String, FieldUsage>>;
+
+/// Context key holding the [`TraceNode`] at the root of the response of a request.
+///
+/// Like the field usage, it is only filled in when the key is already present.
+pub const TRACE_ROOT_CONTEXT_KEY: &str = "apollo::usage::trace_root";
+
+/// A node of the response, as reported in the traces sent to Apollo Studio: a field, an item of a
+/// list, or the root of the response.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceNode {
+    /// Name of the field in the response, for fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_name: Option<String>,
+    /// Index of the item in its list, for items.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    /// Name of the field in the schema, when it is aliased.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_field_name: Option<String>,
+    /// Type of the field, as written in the schema.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub return_type: String,
+    /// Type of the object the field was resolved on.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub parent_type: String,
+    /// Messages of the errors whose path ends at the node.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TraceNode>,
+}
+
+impl TraceNode {
+    /// Adds the error `message` to the deepest node of the response on `path`.
+    pub(crate) fn add_error(&mut self, path: &[PathElement], message: &str) {
+        let child = match path.first() {
+            Some(PathElement::Key(key)) => self
+                .children
+                .iter_mut()
+                .find(|child| child.response_name.as_deref() == Some(key.as_str())),
+            Some(PathElement::Index(index)) => self
+                .children
+                .iter_mut()
+                .find(|child| child.index == Some(*index as u32)),
+            Some(PathElement::Flatten) | None => None,
+        };
+        match child {
+            Some(child) => child.add_error(&path[1..], message),
+            None => self.errors.push(message.to_string()),
+        }
+    }
+}
+
+/// Walks the data of a response along the selections of its operation, counting the fields it
+/// resolved and building the tree of its nodes.
+///
+/// A field is counted once per object resolving it, however many times it is selected.
+pub(crate) struct ResponseUsage<'a> {
+    fragments: &'a Fragments,
+    schema: &'a Schema,
+    pub(crate) usage: Usage,
+}
+
+impl<'a> ResponseUsage<'a> {
+    pub(crate) fn new(fragments: &'a Fragments, schema: &'a Schema) -> Self {
+        Self {
+            fragments,
+            schema,
+            usage: HashMap::new(),
+        }
+    }
+
+    /// Walks `object`, of type `parent_type` unless it tells its `__typename`, adding the nodes of
+    /// its fields to `nodes`.
+    pub(crate) fn visit_object(
+        &mut self,
+        selection_set: &[Selection],
+        parent_type: &str,
+        object: &Object,
+        nodes: &mut Vec<TraceNode>,
+    ) {
+        let typename = object.get("__typename").and_then(Value::as_str);
+        let object_type = typename.unwrap_or(parent_type);
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    field_type,
+                    ..
+                } => {
+                    if name.as_str().starts_with("__") {
+                        continue;
+                    }
+                    let response_name = alias.as_ref().unwrap_or(name).as_str();
+                    // Skipped fields and fields of fragments on other types are not in the data.
+                    let value = match object.get(response_name) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let position = match nodes
+                        .iter()
+                        .position(|node| node.response_name.as_deref() == Some(response_name))
+                    {
+                        Some(position) => position,
+                        None => {
+                            let return_type = type_name(field_type);
+                            let field_usage = self
+                                .usage
+                                .entry(object_type.to_string())
+                                .or_default()
+                                .entry(name.as_str().to_string())
+                                .or_insert_with(|| FieldUsage {
+                                    return_type: return_type.clone(),
+                                    count: 0,
+                                });
+                            field_usage.count = field_usage.count.saturating_add(1);
+                            nodes.push(TraceNode {
+                                response_name: Some(response_name.to_string()),
+                                original_field_name: alias.as_ref().map(|_| name.to_string()),
+                                return_type,
+                                parent_type: object_type.to_string(),
+                                ..Default::default()
+                            });
+                            nodes.len() - 1
+                        }
+                    };
+                    if let (Some(selection_set), Some(ty)) =
+                        (selection_set, field_type.inner_type_name())
+                    {
+                        self.visit_value(selection_set, ty, value, &mut nodes[position].children);
+                    }
+                }
+                Selection::InlineFragment { fragment, .. } => {
+                    if self.applies(&fragment.type_condition, typename) {
+                        self.visit_object(
+                            &fragment.selection_set,
+                            &fragment.type_condition,
+                            object,
+                            nodes,
+                        );
+                    }
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    let fragments = self.fragments;
+                    if let Some(fragment) = fragments.get(name) {
+                        if self.applies(&fragment.type_condition, typename) {
+                            self.visit_object(
+                                &fragment.selection_set,
+                                &fragment.type_condition,
+                                object,
+                                nodes,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn visit_value(
+        &mut self,
+        selection_set: &[Selection],
+        parent_type: &str,
+        value: &Value,
+        nodes: &mut Vec<TraceNode>,
+    ) {
+        match value {
+            Value::Object(object) => self.visit_object(selection_set, parent_type, object, nodes),
+            Value::Array(items) => {
+                // The children of a list are its items, in order.
+                for (index, item) in items.iter().enumerate() {
+                    if nodes.len() == index {
+                        nodes.push(TraceNode {
+                            index: Some(index as u32),
+                            ..Default::default()
+                        });
+                    }
+                    self.visit_value(selection_set, parent_type, item, &mut nodes[index].children);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether a fragment on `type_condition` applies to an object of type `typename`. Without
+    /// the type of the object, the fields present in the data tell.
+    fn applies(&self, type_condition: &str, typename: Option<&str>) -> bool {
+        match typename {
+            Some(typename) => {
+                type_condition == typename || self.schema.is_subtype(type_condition, typename)
+            }
+            None => true,
+        }
+    }
+}
+
+fn type_name(field_type: &FieldType) -> String {
+    match field_type {
+        FieldType::Named(name) | FieldType::Introspection(name) => name.clone(),
+        FieldType::List(inner) => format!("[{}]", type_name(inner)),
+        FieldType::NonNull(inner) => format!("{}!", type_name(inner)),
+        FieldType::String => "String".to_string(),
+        FieldType::Int => "Int".to_string(),
+        FieldType::Float => "Float".to_string(),
+        FieldType::Id => "ID".to_string(),
+        FieldType::Boolean => "Boolean".to_string(),
+    }
+}
@@ -0,0 +1,247 @@
+//! Implementations of [`CacheStorage`].
+
+use crate::{CacheStorage, HealthCheck};
+use async_trait::async_trait;
+use derivative::Derivative;
+use moka::sync::Cache;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tower::BoxError;
+
+const DEFAULT_CAPACITY: u64 = 512;
+
+/// Where a cache keeps its entries.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum CacheStorageConfig {
+    /// In the memory of each router instance.
+    InMemory {
+        /// Number of entries kept. Defaults to 512.
+        #[serde(default = "default_capacity")]
+        capacity: u64,
+    },
+    /// In a Redis server, shared by the router instances using it.
+    Redis {
+        /// URL of the server, like `redis://127.0.0.1:6379`.
+        url: String,
+    },
+}
+
+fn default_capacity() -> u64 {
+    DEFAULT_CAPACITY
+}
+
+impl Default for CacheStorageConfig {
+    fn default() -> Self {
+        CacheStorageConfig::InMemory {
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl CacheStorageConfig {
+    /// Creates the storage, connecting to the server for shared storages.
+    pub async fn build(&self) -> Result<Arc<dyn CacheStorage>, BoxError> {
+        Ok(match self {
+            CacheStorageConfig::InMemory { capacity } => Arc::new(InMemoryStorage::new(*capacity)),
+            CacheStorageConfig::Redis { url } => Arc::new(RedisStorage::new(url).await?),
+        })
+    }
+}
+
+/// Storage in the memory of the router, evicting the least recently used entries.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct InMemoryStorage {
+    #[derivative(Debug = "ignore")]
+    cache: Cache<String, (Option<Instant>, Arc<Vec<u8>>)>,
+}
+
+impl InMemoryStorage {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            cache: Cache::builder().max_capacity(capacity).build(),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStorage for InMemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BoxError> {
+        let key = key.to_string();
+        match self.cache.get(&key) {
+            Some((Some(expires_at), _)) if expires_at <= Instant::now() => {
+                self.cache.invalidate(&key);
+                Ok(None)
+            }
+            Some((_, value)) => Ok(Some(value.as_ref().clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<(), BoxError> {
+        let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        self.cache
+            .insert(key.to_string(), (expires_at, Arc::new(value)));
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), BoxError> {
+        self.cache.invalidate(&key.to_string());
+        Ok(())
+    }
+}
+
+/// Storage in a Redis server.
+///
+/// Expiration is left to the server, with the `PX` option of `SET`.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct RedisStorage {
+    url: String,
+    #[derivative(Debug = "ignore")]
+    connection: redis::aio::ConnectionManager,
+}
+
+impl RedisStorage {
+    /// Connects to the server at `url`. The connection is reestablished whenever it is lost.
+    pub async fn new(url: &str) -> Result<Self, BoxError> {
+        let client = redis::Client::open(url)?;
+        let connection = redis::aio::ConnectionManager::new(client).await?;
+        Ok(Self {
+            url: url.to_string(),
+            connection,
+        })
+    }
+}
+
+#[async_trait]
+impl CacheStorage for RedisStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BoxError> {
+        let mut connection = self.connection.clone();
+        Ok(redis::cmd("GET")
+            .arg(key)
+            .query_async(&mut connection)
+            .await?)
+    }
+
+    async fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>, BoxError> {
+        // Redis rejects an MGET without keys.
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut connection = self.connection.clone();
+        Ok(redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut connection)
+            .await?)
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<(), BoxError> {
+        let mut connection = self.connection.clone();
+        let mut command = redis::cmd("SET");
+        command.arg(key).arg(value);
+        if let Some(ttl) = ttl {
+            // Redis rejects a zero expiration.
+            command.arg("PX").arg((ttl.as_millis() as u64).max(1));
+        }
+        command.query_async::<_, ()>(&mut connection).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), BoxError> {
+        let mut connection = self.connection.clone();
+        redis::cmd("DEL")
+            .arg(key)
+            .query_async::<_, ()>(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), BoxError> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PING")
+            .query_async::<_, ()>(&mut connection)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Checks that a storage can be reached.
+#[derive(Debug, Clone)]
+pub struct StorageHealthCheck {
+    name: String,
+    storage: Arc<dyn CacheStorage>,
+}
+
+impl StorageHealthCheck {
+    pub fn new(name: impl Into<String>, storage: Arc<dyn CacheStorage>) -> Self {
+        Self {
+            name: name.into(),
+            storage,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for StorageHealthCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> Result<(), BoxError> {
+        self.storage.ping().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_memory_entries_expire() {
+        let storage = InMemoryStorage::new(10);
+        storage
+            .put("a", b"1".to_vec(), Some(Duration::from_millis(20)))
+            .await
+            .unwrap();
+        storage.put("b", b"2".to_vec(), None).await.unwrap();
+        assert_eq!(storage.get("a").await.unwrap(), Some(b"1".to_vec()));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(storage.get("a").await.unwrap(), None);
+        assert_eq!(storage.get("b").await.unwrap(), Some(b"2".to_vec()));
+        assert_eq!(
+            storage
+                .get_multiple(&["a".to_string(), "b".to_string()])
+                .await
+                .unwrap(),
+            vec![None, Some(b"2".to_vec())]
+        );
+
+        storage.remove("b").await.unwrap();
+        assert_eq!(storage.get("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn in_memory_storages_are_healthy() {
+        let check = StorageHealthCheck::new("cache", Arc::new(InMemoryStorage::new(10)));
+        assert_eq!(check.name(), "cache");
+        assert!(check.check().await.is_ok());
+    }
+
+    #[test]
+    fn storages_are_configurable() {
+        assert!(matches!(
+            serde_yaml::from_str::<CacheStorageConfig>("in_memory: {}").unwrap(),
+            CacheStorageConfig::InMemory { capacity: 512 }
+        ));
+        assert!(matches!(
+            serde_yaml::from_str::<CacheStorageConfig>("redis:\n  url: redis://127.0.0.1:6379")
+                .unwrap(),
+            CacheStorageConfig::Redis { .. }
+        ));
+    }
+}
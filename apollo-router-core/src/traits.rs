@@ -2,6 +2,8 @@ use crate::prelude::graphql::*;
 use async_trait::async_trait;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
+use tower::BoxError;
 
 /// A cache resolution trait.
 ///
@@ -12,6 +14,44 @@ pub trait CacheResolver<K, V> {
     async fn retrieve(&self, key: K) -> Result<V, CacheResolverError>;
 }
 
+/// A key-value store caches can be kept in.
+///
+/// Values are opaque bytes, and entries may be evicted at any time, so every read may miss.
+/// Implementations backed by a shared store let several router instances share their caches.
+#[async_trait]
+pub trait CacheStorage: Send + Sync + Debug {
+    /// The value stored for the key, if any and not expired.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BoxError>;
+    /// The values stored for each of the keys, read in a single round trip by shared storages.
+    async fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>, BoxError> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(values)
+    }
+    /// Stores a value, for at most `ttl` if set.
+    async fn put(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<(), BoxError>;
+    /// Removes the value stored for the key, if any.
+    async fn remove(&self, key: &str) -> Result<(), BoxError>;
+    /// Checks that the storage can be reached. Storages in the router memory always can.
+    async fn ping(&self) -> Result<(), BoxError> {
+        Ok(())
+    }
+}
+
+/// A check of the health of something the router depends on, like a subgraph or a Redis server.
+///
+/// The router is only ready to serve requests while all its checks pass. Plugins register their
+/// checks with [`Plugin::health_checks`](crate::plugin::Plugin::health_checks).
+#[async_trait]
+pub trait HealthCheck: Send + Sync + Debug {
+    /// Name of the check, as shown by the `/health` endpoint.
+    fn name(&self) -> &str;
+    /// Whether the checked dependency is healthy, with the reason why not in the error.
+    async fn check(&self) -> Result<(), BoxError>;
+}
+
 /// A planner key.
 ///
 /// This type consists of a query string, an optional operation string and the
@@ -56,4 +96,6 @@ mod tests {
     use static_assertions::*;
 
     assert_obj_safe!(QueryPlanner);
+    assert_obj_safe!(CacheStorage);
+    assert_obj_safe!(HealthCheck);
 }
@@ -0,0 +1,250 @@
+//! Files of [GraphQL multipart requests](https://github.com/jaydenseric/graphql-multipart-request-spec).
+//!
+//! Files are not buffered: they stay in the body of the client request, which is read while the
+//! subgraph requests using them are sent. Each file is thus sent to a single subgraph, in the order
+//! of the client request, and files coming before those a subgraph request uses are skipped.
+
+use crate::Object;
+use bytes::Bytes;
+use http::HeaderValue;
+use indexmap::{IndexMap, IndexSet};
+use multer::Multipart;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tower::BoxError;
+
+/// The files of a multipart request, which the subgraph requests of its query plan send on.
+///
+/// Found in the extensions of the originating request.
+#[derive(Clone)]
+pub struct Uploads {
+    boundary: Arc<str>,
+    /// Paths of the variables using each file, by name of the file part.
+    map: Arc<IndexMap<String, Vec<String>>>,
+    /// The rest of the client request, starting after its `map` part.
+    files: Arc<Mutex<Multipart<'static>>>,
+}
+
+impl fmt::Debug for Uploads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Uploads")
+            .field("map", &self.map)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Uploads {
+    pub fn new(
+        boundary: impl Into<String>,
+        map: IndexMap<String, Vec<String>>,
+        files: Multipart<'static>,
+    ) -> Self {
+        Self {
+            boundary: boundary.into().into(),
+            map: Arc::new(map),
+            files: Arc::new(Mutex::new(files)),
+        }
+    }
+
+    /// The files used by `variables`, with the paths they are used at.
+    pub fn map_of(&self, variables: &Object) -> IndexMap<String, Vec<String>> {
+        self.map
+            .iter()
+            .filter_map(|(name, paths)| {
+                let paths: Vec<String> = paths
+                    .iter()
+                    .filter(|path| {
+                        path.strip_prefix("variables.")
+                            .and_then(|path| path.split('.').next())
+                            .map_or(false, |variable| variables.get(variable).is_some())
+                    })
+                    .cloned()
+                    .collect();
+                (!paths.is_empty()).then(|| (name.clone(), paths))
+            })
+            .collect()
+    }
+
+    /// A multipart body made of `operations`, `map` and the files it maps, with its content type.
+    pub fn body(
+        &self,
+        operations: Bytes,
+        map: IndexMap<String, Vec<String>>,
+    ) -> (HeaderValue, hyper::Body) {
+        // The boundary of the client can be kept, as it does not appear in the files nor in the
+        // variables the client sent.
+        let content_type =
+            HeaderValue::from_str(&format!("multipart/form-data; boundary={}", self.boundary))
+                .expect("the boundary was read from a header value; qed");
+        let (sender, body) = hyper::Body::channel();
+        let uploads = self.clone();
+        tokio::spawn(async move {
+            let mut sender = sender;
+            if let Err(err) = uploads.send(&mut sender, operations, map).await {
+                tracing::error!("could not send the uploaded files: {}", err);
+                sender.abort();
+            }
+        });
+        (content_type, body)
+    }
+
+    async fn send(
+        &self,
+        sender: &mut hyper::body::Sender,
+        operations: Bytes,
+        map: IndexMap<String, Vec<String>>,
+    ) -> Result<(), BoxError> {
+        let mut remaining: IndexSet<String> = map.keys().cloned().collect();
+        sender
+            .send_data(self.part_header("operations", None, None))
+            .await?;
+        sender.send_data(operations).await?;
+        sender
+            .send_data(self.part_header("map", None, None))
+            .await?;
+        sender.send_data(serde_json::to_vec(&map)?.into()).await?;
+
+        let mut files = self.files.lock().await;
+        while !remaining.is_empty() {
+            let mut field = files.next_field().await?.ok_or_else(|| {
+                format!(
+                    "the files {} are missing, or were already sent to another subgraph",
+                    remaining.iter().cloned().collect::<Vec<_>>().join(", ")
+                )
+            })?;
+            let name = field.name().unwrap_or_default().to_string();
+            if !remaining.remove(&name) {
+                while field.chunk().await?.is_some() {}
+                continue;
+            }
+            sender
+                .send_data(self.part_header(
+                    &name,
+                    field.file_name(),
+                    field.content_type().map(|mime| mime.as_ref()),
+                ))
+                .await?;
+            while let Some(chunk) = field.chunk().await? {
+                sender.send_data(chunk).await?;
+            }
+        }
+        sender
+            .send_data(format!("\r\n--{}--\r\n", self.boundary).into())
+            .await?;
+        Ok(())
+    }
+
+    /// The delimiter and headers starting a part, ending the previous one if any.
+    fn part_header(
+        &self,
+        name: &str,
+        file_name: Option<&str>,
+        content_type: Option<&str>,
+    ) -> Bytes {
+        let mut header = if name == "operations" {
+            format!("--{}\r\n", self.boundary)
+        } else {
+            format!("\r\n--{}\r\n", self.boundary)
+        };
+        header.push_str(&format!(
+            "Content-Disposition: form-data; name=\"{}\"",
+            escape(name)
+        ));
+        if let Some(file_name) = file_name {
+            header.push_str(&format!("; filename=\"{}\"", escape(file_name)));
+        }
+        header.push_str("\r\n");
+        if let Some(content_type) = content_type {
+            header.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        header.push_str("\r\n");
+        header.into()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use serde_json_bytes::json;
+
+    const BOUNDARY: &str = "boundary";
+
+    fn uploads(body: &'static str) -> Uploads {
+        let map = serde_json::from_str(r#"{ "0": ["variables.file"], "1": ["variables.other"] }"#)
+            .unwrap();
+        let files = Multipart::new(
+            stream::iter([Ok::<_, std::io::Error>(Bytes::from(
+                body.replace('\n', "\r\n"),
+            ))]),
+            BOUNDARY,
+        );
+        Uploads::new(BOUNDARY, map, files)
+    }
+
+    const FILES: &str = "--boundary
+Content-Disposition: form-data; name=\"0\"; filename=\"a.txt\"
+Content-Type: text/plain
+
+alpha
+--boundary
+Content-Disposition: form-data; name=\"1\"; filename=\"b.txt\"
+Content-Type: text/plain
+
+beta
+--boundary--
+";
+
+    #[test]
+    fn files_are_mapped_to_the_variables_using_them() {
+        let uploads = uploads(FILES);
+        let variables = json!({ "file": null, "unrelated": 1 });
+        let map = uploads.map_of(variables.as_object().unwrap());
+
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["0"], vec!["variables.file".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn files_are_streamed_to_the_subgraph() {
+        let uploads = uploads(FILES);
+        let variables = json!({ "other": null });
+        let map = uploads.map_of(variables.as_object().unwrap());
+
+        let (content_type, body) = uploads.body(Bytes::from_static(b"{}"), map);
+        assert_eq!(content_type, "multipart/form-data; boundary=boundary");
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        let mut sent = Multipart::new(stream::iter([Ok::<_, std::io::Error>(body)]), BOUNDARY);
+
+        let mut parts = Vec::new();
+        while let Some(field) = sent.next_field().await.unwrap() {
+            let name = field.name().unwrap().to_string();
+            parts.push((name, field.text().await.unwrap()));
+        }
+        assert_eq!(
+            parts,
+            vec![
+                ("operations".to_string(), "{}".to_string()),
+                (
+                    "map".to_string(),
+                    r#"{"1":["variables.other"]}"#.to_string()
+                ),
+                ("1".to_string(), "beta".to_string()),
+            ]
+        );
+
+        // The file was skipped on the way.
+        let variables = json!({ "file": null });
+        let map = uploads.map_of(variables.as_object().unwrap());
+        let (_, body) = uploads.body(Bytes::from_static(b"{}"), map);
+        assert!(hyper::body::to_bytes(body).await.is_err());
+    }
+}
@@ -0,0 +1,470 @@
+//! Admin endpoints, served on a listener of their own, to inspect and operate a running router.
+//!
+//! * `GET /config` answers with the configuration in use, its secrets redacted: values of keys
+//!   naming secrets, header values, and the credentials of URLs.
+//! * `GET /schema` answers with the hash of the schema in use.
+//! * `GET /plugins` answers with the names of the plugins in use.
+//! * `GET /version` answers with the version of the router and the commit it was built from.
+//! * `GET /caches` answers with the hits and misses of each cache since the process started.
+//! * `GET /metrics` answers with the telemetry metrics, when they are moved to this listener.
+//! * `POST /caches/invalidate` rebuilds the router from its configuration and schema, with empty
+//!   in-memory caches. Plans of recent operations are warmed up again if so configured.
+//! * `POST /schema/reload` reads the watched schema and configuration files again, as `SIGHUP`
+//!   does.
+//!
+//! The endpoints describing the router answer with a 503 status while no router is running.
+
+use crate::axum_http_server_factory::custom_plugin_handler;
+use crate::configuration::Configuration;
+use crate::{build_info, files, Event, FederatedServerError};
+use apollo_router_core::{cache_statistics, Handler, Plugins, Schema};
+use axum::extract::{Extension, Host};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use derivative::Derivative;
+use futures::channel::{mpsc, oneshot};
+use hyper::Body;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// Value replacing the secrets of the configuration.
+const REDACTED: &str = "[REDACTED]";
+
+/// Parts of configuration keys naming secrets.
+const SECRET_KEYS: [&str; 6] = [
+    "key",
+    "secret",
+    "password",
+    "token",
+    "authorization",
+    "credential",
+];
+
+/// What the admin endpoints know of the running router, shared with the state machine.
+#[derive(Debug, Default)]
+pub(crate) struct Admin {
+    running: RwLock<Option<RunningRouter>>,
+    /// Where reloads are sent, or `None` if the router cannot be reloaded.
+    events: Option<mpsc::UnboundedSender<Event>>,
+}
+
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+struct RunningRouter {
+    configuration: Value,
+    schema_hash: String,
+    plugins: Vec<String>,
+    /// The endpoint of the telemetry, when its metrics are served on this listener.
+    #[derivative(Debug = "ignore")]
+    metrics: Option<Handler>,
+}
+
+impl Admin {
+    pub(crate) fn new(events: mpsc::UnboundedSender<Event>) -> Self {
+        Self {
+            running: Default::default(),
+            events: Some(events),
+        }
+    }
+
+    /// Marks the router as running with `configuration`, `schema` and `plugins`.
+    pub(crate) fn running(
+        &self,
+        configuration: &Configuration,
+        schema: &Schema,
+        plugins: &Plugins,
+    ) {
+        let metrics = configuration
+            .server
+            .admin
+            .as_ref()
+            .filter(|admin| admin.metrics)
+            .and_then(|_| plugins.get("apollo.telemetry"))
+            .and_then(|telemetry| telemetry.custom_endpoint());
+        let mut configuration = serde_json::to_value(configuration).unwrap_or_default();
+        redact(&mut configuration);
+        *self.running.write().expect("lock poisoned") = Some(RunningRouter {
+            configuration,
+            schema_hash: schema.hash(),
+            plugins: plugins.keys().cloned().collect(),
+            metrics,
+        });
+    }
+
+    /// Marks the router as not running.
+    pub(crate) fn not_running(&self) {
+        *self.running.write().expect("lock poisoned") = None;
+    }
+
+    fn get(&self) -> Option<RunningRouter> {
+        self.running.read().expect("lock poisoned").clone()
+    }
+}
+
+/// Keys holding header values, such as those the headers plugin inserts.
+const HEADER_VALUE_KEYS: [&str; 2] = ["value", "default"];
+
+/// Replaces the strings held by keys naming secrets and the header values, at any depth, and
+/// removes the credentials of URLs.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                let key = key.to_lowercase();
+                if value.is_string()
+                    && (SECRET_KEYS.iter().any(|secret| key.contains(secret))
+                        || HEADER_VALUE_KEYS.contains(&key.as_str()))
+                {
+                    *value = Value::String(REDACTED.to_string());
+                } else if key == "headers" {
+                    redact_headers(value);
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        Value::String(string) => {
+            if let Some(url) = without_credentials(string) {
+                *string = url;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Redacts a `headers` setting, in which maps of header names to values are redacted whole.
+fn redact_headers(value: &mut Value) {
+    match value {
+        Value::Object(object) if object.values().all(Value::is_string) => {
+            object
+                .values_mut()
+                .for_each(|value| *value = Value::String(REDACTED.to_string()));
+        }
+        _ => redact(value),
+    }
+}
+
+/// The URL `string` without the user name and password it holds, if any.
+fn without_credentials(string: &str) -> Option<String> {
+    let mut url = Url::parse(string).ok()?;
+    if url.username().is_empty() && url.password().is_none() {
+        return None;
+    }
+    url.set_username("").ok()?;
+    url.set_password(None).ok()?;
+    Some(url.to_string())
+}
+
+fn not_running() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "error": "the router is not running" })),
+    )
+}
+
+async fn handle_config(Extension(admin): Extension<Arc<Admin>>) -> impl IntoResponse {
+    match admin.get() {
+        Some(running) => (StatusCode::OK, Json(running.configuration)),
+        None => not_running(),
+    }
+}
+
+async fn handle_schema(Extension(admin): Extension<Arc<Admin>>) -> impl IntoResponse {
+    match admin.get() {
+        Some(running) => (StatusCode::OK, Json(json!({ "hash": running.schema_hash }))),
+        None => not_running(),
+    }
+}
+
+async fn handle_plugins(Extension(admin): Extension<Arc<Admin>>) -> impl IntoResponse {
+    match admin.get() {
+        Some(running) => (StatusCode::OK, Json(json!(running.plugins))),
+        None => not_running(),
+    }
+}
+
+async fn handle_version() -> impl IntoResponse {
+    Json(build_info())
+}
+
+async fn handle_caches() -> impl IntoResponse {
+    Json(json!(cache_statistics()))
+}
+
+async fn handle_metrics(
+    Extension(admin): Extension<Arc<Admin>>,
+    host: Host,
+    request: Request<Body>,
+) -> Response {
+    match admin.get() {
+        Some(RunningRouter {
+            metrics: Some(handler),
+            ..
+        }) => custom_plugin_handler(host, request, handler)
+            .await
+            .into_response(),
+        Some(_) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "the metrics are not served on this listener" })),
+        )
+            .into_response(),
+        None => not_running().into_response(),
+    }
+}
+
+async fn handle_invalidate_caches(Extension(admin): Extension<Arc<Admin>>) -> impl IntoResponse {
+    let sent = admin
+        .events
+        .as_ref()
+        .map(|events| events.unbounded_send(Event::Reload).is_ok())
+        .unwrap_or(false);
+    if sent {
+        (StatusCode::ACCEPTED, Json(json!({ "status": "reloading" })))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "the router cannot be reloaded" })),
+        )
+    }
+}
+
+async fn handle_reload_schema() -> impl IntoResponse {
+    files::request_reload();
+    (StatusCode::ACCEPTED, Json(json!({ "status": "reloading" })))
+}
+
+/// A running listener of the admin endpoints.
+#[derive(Debug)]
+pub(crate) struct AdminServerHandle {
+    shutdown_sender: oneshot::Sender<()>,
+    server: JoinHandle<Result<(), hyper::Error>>,
+    /// The configured address, which may use port zero.
+    pub(crate) configured_address: SocketAddr,
+    /// The address actually listened on.
+    pub(crate) listen_address: SocketAddr,
+}
+
+impl AdminServerHandle {
+    /// Starts serving the admin endpoints on `listen`.
+    pub(crate) async fn start(
+        listen: SocketAddr,
+        admin: Arc<Admin>,
+    ) -> Result<Self, FederatedServerError> {
+        let listener = TcpListener::bind(listen)
+            .await
+            .map_err(FederatedServerError::ServerCreationError)?;
+        let listen_address = listener
+            .local_addr()
+            .map_err(FederatedServerError::ServerCreationError)?;
+        let listener = listener
+            .into_std()
+            .map_err(FederatedServerError::ServerCreationError)?;
+
+        let app = Router::new()
+            .route("/config", get(handle_config))
+            .route("/schema", get(handle_schema))
+            .route("/plugins", get(handle_plugins))
+            .route("/version", get(handle_version))
+            .route("/caches", get(handle_caches))
+            .route("/metrics", get(handle_metrics))
+            .route("/caches/invalidate", post(handle_invalidate_caches))
+            .route("/schema/reload", post(handle_reload_schema))
+            .layer(Extension(admin));
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let server = axum::Server::from_tcp(listener)
+            .map_err(|_| FederatedServerError::HttpServerLifecycleError)?
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async {
+                let _ = shutdown_receiver.await;
+            });
+        tracing::debug!("serving the admin endpoints on {}", listen_address);
+
+        Ok(Self {
+            shutdown_sender,
+            server: tokio::spawn(server),
+            configured_address: listen,
+            listen_address,
+        })
+    }
+
+    pub(crate) async fn shutdown(self) {
+        let _ = self.shutdown_sender.send(());
+        match self.server.await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::error!("the admin listener failed: {}", err),
+            Err(err) => tracing::error!("the admin listener panicked: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{AdminServer, Server};
+    use apollo_router_core::plugin::Plugin;
+    use apollo_router_core::{http_compat, DynPlugin, ResponseBody};
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use std::str::FromStr;
+    use tower::{BoxError, ServiceExt};
+
+    #[test]
+    fn secrets_are_redacted() {
+        let mut configuration = json!({
+            "telemetry": {
+                "apollo": { "apollo_key": "service:graph:abc", "endpoint": "https://example.com" },
+                "exporters": [{ "headers": { "Authorization": "Bearer abc", "x-api": "abc" } }],
+            },
+            "headers": {
+                "all": [
+                    { "insert": { "name": "x-api", "value": "abc" } },
+                    { "propagate": { "named": "x-tenant", "default": "abc" } },
+                ],
+            },
+            "response_cache": { "redis": { "urls": ["redis://:abc@cache:6379", "redis://cache"] } },
+            "server": { "listen": "127.0.0.1:4000" },
+        });
+        redact(&mut configuration);
+        assert_eq!(
+            configuration,
+            json!({
+                "telemetry": {
+                    "apollo": { "apollo_key": REDACTED, "endpoint": "https://example.com" },
+                    "exporters": [{ "headers": { "Authorization": REDACTED, "x-api": REDACTED } }],
+                },
+                "headers": {
+                    "all": [
+                        { "insert": { "name": "x-api", "value": REDACTED } },
+                        { "propagate": { "named": "x-tenant", "default": REDACTED } },
+                    ],
+                },
+                "response_cache": { "redis": { "urls": ["redis://cache:6379", "redis://cache"] } },
+                "server": { "listen": "127.0.0.1:4000" },
+            })
+        );
+    }
+
+    struct FakeTelemetry;
+
+    #[async_trait::async_trait]
+    impl Plugin for FakeTelemetry {
+        type Config = ();
+
+        async fn new(_config: Self::Config) -> Result<Self, BoxError> {
+            Ok(FakeTelemetry)
+        }
+
+        fn custom_endpoint(&self) -> Option<Handler> {
+            Some(Handler::new(
+                tower::service_fn(|req: http_compat::Request<Bytes>| async move {
+                    Ok::<_, BoxError>(http_compat::Response {
+                        inner: http::Response::builder()
+                            .body(ResponseBody::Text(format!(
+                                "metrics at {}",
+                                req.uri().path()
+                            )))
+                            .unwrap(),
+                    })
+                })
+                .boxed(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_can_be_moved_to_the_admin_listener() {
+        let (events, _receiver) = mpsc::unbounded();
+        let admin = Arc::new(Admin::new(events));
+        let server =
+            AdminServerHandle::start(SocketAddr::from_str("127.0.0.1:0").unwrap(), admin.clone())
+                .await
+                .unwrap();
+        let url = format!("http://{}/metrics", server.listen_address);
+        let schema: Schema = include_str!("testdata/supergraph.graphql").parse().unwrap();
+        let mut plugins = Plugins::default();
+        plugins.insert(
+            "apollo.telemetry".to_string(),
+            Box::new(FakeTelemetry) as Box<dyn DynPlugin>,
+        );
+
+        admin.running(&Configuration::builder().build(), &schema, &plugins);
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+
+        let configuration = Configuration::builder()
+            .server(
+                Server::builder()
+                    .admin(Some(AdminServer::builder().metrics(true).build()))
+                    .build(),
+            )
+            .build();
+        admin.running(&configuration, &schema, &plugins);
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.text().await.unwrap(), "metrics at /metrics");
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn endpoints_describe_and_reload_the_router() {
+        let (events, mut receiver) = mpsc::unbounded();
+        let admin = Arc::new(Admin::new(events));
+        let server =
+            AdminServerHandle::start(SocketAddr::from_str("127.0.0.1:0").unwrap(), admin.clone())
+                .await
+                .unwrap();
+        let url = |path: &str| format!("http://{}{}", server.listen_address, path);
+
+        let response = reqwest::get(url("/schema")).await.unwrap();
+        assert_eq!(response.status().as_u16(), 503);
+
+        let schema: Schema = include_str!("testdata/supergraph.graphql").parse().unwrap();
+        admin.running(
+            &Configuration::builder().build(),
+            &schema,
+            &Plugins::default(),
+        );
+        let body: Value = reqwest::get(url("/schema"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body, json!({ "hash": schema.hash() }));
+        let body: Value = reqwest::get(url("/config"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["server"]["listen"], "127.0.0.1:4000");
+        let body: Value = reqwest::get(url("/version"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["commit"], env!("APOLLO_ROUTER_GIT_COMMIT"));
+
+        let response = reqwest::Client::new()
+            .post(url("/caches/invalidate"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 202);
+        assert!(matches!(receiver.next().await, Some(Event::Reload)));
+
+        admin.not_running();
+        let response = reqwest::get(url("/plugins")).await.unwrap();
+        assert_eq!(response.status().as_u16(), 503);
+        server.shutdown().await;
+    }
+}
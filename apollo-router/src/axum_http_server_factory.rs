@@ -1,10 +1,22 @@
 //! Axum http server factory. Axum provides routing capability on top of Hyper HTTP.
-use crate::configuration::{Configuration, Cors, ListenAddr};
+use crate::batching::BatchEntry;
+use crate::build_info::server_header;
+use crate::client_ip::{client_ip, ClientIp};
+use crate::configuration::{
+    Configuration, Cors, Csrf, LandingPageContent, ListenAddr, Subscriptions, Uploads,
+};
+use crate::connection_limits::{ConnectionLimits, Expiry};
+use crate::correlation::{correlation_id, CorrelationId};
+use crate::deferred::{self, ConnectionSlots, DeferredLimits, DeferredSlot};
 use crate::http_server_factory::{HttpServerFactory, HttpServerHandle, Listener, NetworkStream};
+use crate::request_body::{self, Requests};
+use crate::subscriptions;
+use crate::tls::{MaybeTlsStream, TlsAcceptor};
 use crate::FederatedServerError;
 use apollo_router_core::ResponseBody;
-use apollo_router_core::{http_compat, Handler};
+use apollo_router_core::{http_compat, Handler, GRAPHQL_TRANSPORT_WS};
 use apollo_router_core::{prelude::*, DEFAULT_BUFFER_SIZE};
+use axum::extract::ws::{CloseFrame, Message, WebSocketUpgrade};
 use axum::extract::{Extension, Host, OriginalUri};
 use axum::http::{header::HeaderMap, StatusCode};
 use axum::response::*;
@@ -26,11 +38,12 @@ use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
 #[cfg(unix)]
 use tokio::net::UnixListener;
-use tokio::sync::Notify;
+use tokio::sync::{mpsc, Notify};
 use tower::buffer::Buffer;
-use tower::util::BoxService;
-use tower::MakeService;
+use tower::util::{BoxService, MapRequestLayer, MapResponseLayer};
 use tower::{BoxError, ServiceExt};
+use tower::{Layer, MakeService};
+use tower_http::compression::CompressionLayer;
 use tower_http::trace::{MakeSpan, TraceLayer};
 use tower_service::Service;
 use tracing::{Level, Span};
@@ -47,7 +60,7 @@ impl AxumHttpServerFactory {
     }
 }
 
-type BufferedService = Buffer<
+pub(crate) type BufferedService = Buffer<
     BoxService<
         http_compat::Request<graphql::Request>,
         http_compat::Response<ResponseBody>,
@@ -56,6 +69,9 @@ type BufferedService = Buffer<
     http_compat::Request<graphql::Request>,
 >;
 
+/// Name of the handler serving the supergraph SDL at the `supergraph_sdl_path`, if enabled.
+pub(crate) const SUPERGRAPH_SDL_HANDLER: &str = "apollo.supergraph_sdl";
+
 impl HttpServerFactory for AxumHttpServerFactory {
     type Future =
         Pin<Box<dyn Future<Output = Result<HttpServerHandle, FederatedServerError>> + Send>>;
@@ -65,7 +81,7 @@ impl HttpServerFactory for AxumHttpServerFactory {
         service: RS,
         configuration: Arc<Configuration>,
         listener: Option<Listener>,
-        plugin_handlers: HashMap<String, Handler>,
+        mut plugin_handlers: HashMap<String, Handler>,
     ) -> Self::Future
     where
         RS: Service<
@@ -80,7 +96,17 @@ impl HttpServerFactory for AxumHttpServerFactory {
         <RS as Service<http_compat::Request<apollo_router_core::Request>>>::Future:
             std::marker::Send,
     {
-        let boxed_service = Buffer::new(service.boxed(), DEFAULT_BUFFER_SIZE);
+        let error_status_codes = match error_status_codes(&configuration.server.error_status_codes)
+        {
+            Ok(error_status_codes) => error_status_codes,
+            Err(err) => return Box::pin(future::ready(Err(err))),
+        };
+        let boxed_service = Buffer::new(
+            service
+                .map_response(move |response| with_error_status(response, &error_status_codes))
+                .boxed(),
+            DEFAULT_BUFFER_SIZE,
+        );
         Box::pin(async move {
             let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
             let listen_address = configuration.server.listen.clone();
@@ -92,31 +118,60 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 .map(|cors_configuration| cors_configuration.into_layer())
                 .unwrap_or_else(|| Cors::builder().build().into_layer());
 
-            let mut router = Router::new()
-                .route(
-                    "/",
-                    get({
-                        let display_landing_page = configuration.server.landing_page;
-                        move |host: Host,
-                              service: Extension<BufferedService>,
-                              http_request: Request<Body>| {
-                            handle_get(host, service, http_request, display_landing_page)
-                        }
-                    })
-                    .post(handle_post),
-                )
-                .route(
-                    "/graphql",
-                    get({
-                        let display_landing_page = configuration.server.landing_page;
-                        move |host: Host,
-                              service: Extension<BufferedService>,
-                              http_request: Request<Body>| {
-                            handle_get(host, service, http_request, display_landing_page)
-                        }
-                    })
-                    .post(handle_post),
+            let landing_page = if configuration.server.landing_page {
+                Some(
+                    landing_page(&configuration.server.landing_page_content)
+                        .await
+                        .map_err(FederatedServerError::ServerCreationError)?,
                 )
+            } else {
+                None
+            };
+            let stream_responses = configuration.server.stream_responses;
+            let graphql_route = get({
+                let csrf = Arc::new(configuration.server.csrf.clone());
+                let subscriptions = Arc::new(configuration.server.subscriptions.clone());
+                move |host: Host,
+                      service: Extension<BufferedService>,
+                      slots: Extension<ConnectionSlots>,
+                      websocket: Option<WebSocketUpgrade>,
+                      http_request: Request<Body>| {
+                    handle_get(
+                        host,
+                        service,
+                        slots,
+                        websocket,
+                        http_request,
+                        landing_page.clone(),
+                        csrf.clone(),
+                        subscriptions.clone(),
+                        stream_responses,
+                    )
+                }
+            })
+            .post({
+                let settings = Arc::new(PostSettings {
+                    max_request_bytes: configuration.server.max_request_bytes,
+                    max_variables_bytes: configuration.server.max_variables_bytes,
+                    decompress: configuration.server.compression.requests,
+                    max_decompressed_bytes: configuration.server.compression.max_decompressed_bytes,
+                    uploads: configuration.server.uploads.clone(),
+                    batching: configuration.server.batching.clone(),
+                    csrf: configuration.server.csrf.clone(),
+                    stream_responses,
+                });
+                move |host: Host,
+                      service: Extension<BufferedService>,
+                      slots: Extension<ConnectionSlots>,
+                      http_request: Request<Body>| {
+                    handle_post(host, service, slots, http_request, settings.clone())
+                }
+            });
+            let mut router = Router::new().route("/", graphql_route.clone());
+            if configuration.server.graphql_path != "/" {
+                router = router.route(&configuration.server.graphql_path, graphql_route);
+            }
+            router = router
                 .layer(
                     TraceLayer::new_for_http()
                         .make_span_with(PropagatingMakeSpan::new())
@@ -134,10 +189,61 @@ impl HttpServerFactory for AxumHttpServerFactory {
                             }
                         }),
                 )
+                .layer(MapRequestLayer::new({
+                    let extractors = configuration.server.correlation_id_extractors();
+                    move |mut request: Request<Body>| {
+                        let id = correlation_id(request.headers(), &extractors);
+                        request.extensions_mut().insert(CorrelationId(id));
+                        request
+                    }
+                }))
                 .route("/.well-known/apollo/server-health", get(health_check))
                 .layer(Extension(boxed_service))
                 .layer(cors);
 
+            if configuration.server.expose_version {
+                router = router.layer(MapResponseLayer::new(|mut response: Response| {
+                    response.headers_mut().insert(
+                        http::header::SERVER,
+                        HeaderValue::from_static(server_header()),
+                    );
+                    response
+                }));
+            }
+
+            let metrics_on_admin = configuration
+                .server
+                .admin
+                .as_ref()
+                .map(|admin| admin.metrics)
+                .unwrap_or_default();
+            if metrics_on_admin {
+                // Served by the admin listener only.
+                plugin_handlers.remove("apollo.telemetry");
+            }
+            // Prometheus scrapers look for metrics at `/metrics` by default.
+            if let Some(handler) = plugin_handlers.get("apollo.telemetry") {
+                let handler = handler.clone();
+                router = router.route(
+                    "/metrics",
+                    get(move |host: Host, request_parts: Request<Body>| {
+                        custom_plugin_handler(host, request_parts, handler.clone())
+                    }),
+                );
+            }
+
+            if let (Some(path), Some(handler)) = (
+                &configuration.server.supergraph_sdl_path,
+                plugin_handlers.remove(SUPERGRAPH_SDL_HANDLER),
+            ) {
+                router = router.route(
+                    path,
+                    get(move |host: Host, request_parts: Request<Body>| {
+                        custom_plugin_handler(host, request_parts, handler.clone())
+                    }),
+                );
+            }
+
             for (plugin_name, handler) in plugin_handlers {
                 router = router.route(
                     &format!("/plugins/{}/*path", plugin_name),
@@ -156,7 +262,18 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 );
             }
 
+            if configuration.server.compression.responses {
+                router = router.layer(CompressionLayer::new());
+            }
+
             let svc = router.into_make_service();
+            let deferred_limits = DeferredLimits::from_server(&configuration.server);
+            let connection_limits = ConnectionLimits::from_server(&configuration.server);
+            let trusted_proxies = Arc::new(configuration.server.trusted_proxies.clone());
+            let tls = match &configuration.server.tls {
+                Some(tls) => Some(Arc::new(TlsAcceptor::new(tls)?)),
+                None => None,
+            };
 
             // if we received a TCP listener, reuse it, otherwise create a new one
             #[cfg_attr(not(unix), allow(unused_mut))]
@@ -185,6 +302,10 @@ impl HttpServerFactory for AxumHttpServerFactory {
             // accept future. If the channel received something or the sender
             // was dropped, we stop using the listener and send it back through
             // listener_receiver
+            // every connection holds a sender, so that the receiver is closed once they are all
+            // closed and the server loop is done
+            let (connections_sender, connections_receiver) = mpsc::channel::<()>(1);
+
             let server = async move {
                 tokio::pin!(shutdown_receiver);
 
@@ -199,6 +320,10 @@ impl HttpServerFactory for AxumHttpServerFactory {
                         res = listener.accept() => {
                             let mut svc = svc.clone();
                             let connection_shutdown = connection_shutdown.clone();
+                            let slots = Extension(deferred_limits.connection());
+                            let trusted_proxies = trusted_proxies.clone();
+                            let connection_guard = connections_sender.clone();
+                            let tls = tls.clone();
 
                             match res {
                                 Ok(res) => {
@@ -207,25 +332,66 @@ impl HttpServerFactory for AxumHttpServerFactory {
                                         max_open_file_warning = None;
                                     }
 
+                                    // dropping the stream closes the connection
+                                    let limits = match connection_limits.admit() {
+                                        Some(limits) => limits,
+                                        None => {
+                                            tracing::debug!("too many connections open, closing the new one");
+                                            continue;
+                                        }
+                                    };
+                                    let max_requests_per_connection = connection_limits.max_requests_per_connection();
+
                                     tokio::task::spawn(async move{
+                                        let _connection_guard = connection_guard;
                                         match res {
                                             NetworkStream::Tcp(stream) => {
                                                 // TODO: unwrap?
                                                 let app = svc.make_service(&stream).await.unwrap();
+                                                let app = slots.layer(app);
+                                                let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+                                                let app = MapRequestLayer::new(move |mut request: Request<Body>| {
+                                                    if let Some(peer) = peer {
+                                                        let ip = client_ip(peer, request.headers(), &trusted_proxies);
+                                                        request.extensions_mut().insert(ClientIp(ip));
+                                                    }
+                                                    request
+                                                })
+                                                .layer(app);
                                                 stream
                                                     .set_nodelay(true)
                                                     .expect(
                                                         "this should not fail unless the socket is invalid",
                                                     );
+                                                let stream = match tls {
+                                                    Some(tls) => match tls.accept(stream).await {
+                                                        Ok(stream) => MaybeTlsStream::Tls(Box::new(stream)),
+                                                        Err(err) => {
+                                                            tracing::debug!("TLS handshake failed: {}", err);
+                                                            return;
+                                                        }
+                                                    },
+                                                    None => MaybeTlsStream::Plain(stream),
+                                                };
                                                     let connection = Http::new()
                                                     .http1_keep_alive(true)
-                                                    .serve_connection(stream, app);
+                                                    .http2_max_concurrent_streams(max_requests_per_connection)
+                                                    .serve_connection(limits.stream(stream), limits.service(app));
 
                                                 tokio::pin!(connection);
                                                 tokio::select! {
                                                     // the connection finished first
                                                     _res = &mut connection => {
                                                     }
+                                                    expiry = limits.expired() => match expiry {
+                                                        Expiry::Idle => {
+                                                            connection.as_mut().graceful_shutdown();
+                                                            let _= connection.await;
+                                                        }
+                                                        Expiry::HeaderRead => {
+                                                            tracing::debug!("closing a connection too slow to send a request");
+                                                        }
+                                                    },
                                                     // the shutdown receiver was triggered first,
                                                     // so we tell the connection to do a graceful shutdown
                                                     // on the next request, then we wait for it to finish
@@ -241,15 +407,26 @@ impl HttpServerFactory for AxumHttpServerFactory {
                                             NetworkStream::Unix(stream) => {
                                                 // TODO: unwrap?
                                                 let app = svc.make_service(&stream).await.unwrap();
+                                                let app = slots.layer(app);
                                                 let connection = Http::new()
                                                 .http1_keep_alive(true)
-                                                .serve_connection(stream, app);
+                                                .http2_max_concurrent_streams(max_requests_per_connection)
+                                                .serve_connection(limits.stream(stream), limits.service(app));
 
                                                 tokio::pin!(connection);
                                                 tokio::select! {
                                                     // the connection finished first
                                                     _res = &mut connection => {
                                                     }
+                                                    expiry = limits.expired() => match expiry {
+                                                        Expiry::Idle => {
+                                                            connection.as_mut().graceful_shutdown();
+                                                            let _= connection.await;
+                                                        }
+                                                        Expiry::HeaderRead => {
+                                                            tracing::debug!("closing a connection too slow to send a request");
+                                                        }
+                                                    },
                                                     // the shutdown receiver was triggered first,
                                                     // so we tell the connection to do a graceful shutdown
                                                     // on the next request, then we wait for it to finish
@@ -354,11 +531,10 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 .map_err(|_| FederatedServerError::HttpServerLifecycleError)
                 .boxed();
 
-            Ok(HttpServerHandle::new(
-                shutdown_sender,
-                server_future,
-                actual_listen_address,
-            ))
+            Ok(
+                HttpServerHandle::new(shutdown_sender, server_future, actual_listen_address)
+                    .with_connections(connections_receiver, configuration.server.drain_timeout),
+            )
         })
     }
 }
@@ -369,7 +545,7 @@ struct CustomRejection {
     msg: String,
 }
 
-async fn custom_plugin_handler(
+pub(crate) async fn custom_plugin_handler(
     Host(host): Host,
     request: Request<Body>,
     handler: Handler,
@@ -408,20 +584,55 @@ async fn custom_plugin_handler(
     Ok::<_, String>(res)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_get(
     Host(host): Host,
     Extension(service): Extension<BufferedService>,
+    Extension(slots): Extension<ConnectionSlots>,
+    websocket: Option<WebSocketUpgrade>,
     http_request: Request<Body>,
-    display_landing_page: bool,
+    landing_page: Option<Bytes>,
+    csrf: Arc<Csrf>,
+    subscriptions: Arc<Subscriptions>,
+    stream_responses: bool,
 ) -> impl IntoResponse {
-    if http_request
-        .headers()
-        .get(&http::header::ACCEPT)
-        .map(prefers_html)
-        .unwrap_or_default()
-        && display_landing_page
-    {
-        return display_home_page().into_response();
+    if let Some(websocket) = websocket {
+        if !subscriptions.enabled {
+            return reject_websocket(websocket);
+        }
+        let (mut head, _) = http_request.into_parts();
+        head.uri = Uri::from_str(&format!("http://{}{}", host, head.uri))
+            .expect("the URL is already valid because it comes from axum; qed");
+        let connection_init_timeout = subscriptions.connection_init_timeout;
+        return websocket.protocols([GRAPHQL_TRANSPORT_WS]).on_upgrade(
+            move |mut socket| async move {
+                if socket.protocol().is_none() {
+                    let close = Message::Close(Some(CloseFrame {
+                        code: 4406,
+                        reason: "Subprotocol not acceptable".into(),
+                    }));
+                    if let Err(err) = socket.send(close).await {
+                        tracing::debug!("could not close the websocket connection: {}", err);
+                    }
+                    return;
+                }
+                subscriptions::serve(socket, service, head, connection_init_timeout).await
+            },
+        );
+    }
+
+    if let Some(landing_page) = landing_page.filter(|_| {
+        http_request
+            .headers()
+            .get(&http::header::ACCEPT)
+            .map(prefers_html)
+            .unwrap_or_default()
+    }) {
+        return Html(landing_page).into_response();
+    }
+
+    if !csrf.unsafe_disabled && !is_preflighted(http_request.headers(), &csrf.required_headers) {
+        return csrf_rejection(&csrf);
     }
 
     if let Some(request) = http_request
@@ -432,7 +643,7 @@ async fn handle_get(
         let mut http_request = http_request.map(|_| request);
         *http_request.uri_mut() = Uri::from_str(&format!("http://{}{}", host, http_request.uri()))
             .expect("the URL is already valid because it comes from axum; qed");
-        return run_graphql_request(service, http_request)
+        return run_graphql_request(service, &slots, http_request, stream_responses)
             .await
             .into_response();
     }
@@ -440,46 +651,292 @@ async fn handle_get(
     (StatusCode::BAD_REQUEST, "Invalid Graphql request").into_response()
 }
 
+/// How GraphQL requests sent with POST are read.
+struct PostSettings {
+    max_request_bytes: Option<usize>,
+    max_variables_bytes: Option<usize>,
+    decompress: bool,
+    max_decompressed_bytes: usize,
+    uploads: Uploads,
+    batching: Batching,
+    csrf: Csrf,
+    stream_responses: bool,
+}
+
 async fn handle_post(
     Host(host): Host,
-    OriginalUri(uri): OriginalUri,
-    Json(request): Json<graphql::Request>,
     Extension(service): Extension<BufferedService>,
-    header_map: HeaderMap,
+    Extension(slots): Extension<ConnectionSlots>,
+    http_request: Request<Body>,
+    settings: Arc<PostSettings>,
 ) -> impl IntoResponse {
-    let mut http_request = Request::post(
-        Uri::from_str(&format!("http://{}{}", host, uri))
-            .expect("the URL is already valid because it comes from axum; qed"),
-    )
-    .body(request)
-    .expect("body has already been parsed; qed");
-    *http_request.headers_mut() = header_map;
+    let boundary = if settings.uploads.enabled {
+        multipart_boundary(http_request.headers())
+    } else {
+        None
+    };
+    if boundary.is_none() && !has_json_content_type(http_request.headers()) {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected request with `Content-Type: application/json`",
+        )
+            .into_response();
+    }
+    // Multipart requests can be sent by HTML forms, without a CORS preflight.
+    if boundary.is_some()
+        && !settings.csrf.unsafe_disabled
+        && !is_preflighted(http_request.headers(), &settings.csrf.required_headers)
+    {
+        return csrf_rejection(&settings.csrf);
+    }
 
-    run_graphql_request(service, http_request)
+    let (mut head, body) = http_request.into_parts();
+    let original_uri = head
+        .extensions
+        .get::<OriginalUri>()
+        .map(|OriginalUri(uri)| uri.clone())
+        .unwrap_or_else(|| head.uri.clone());
+    head.uri = Uri::from_str(&format!("http://{}{}", host, original_uri))
+        .expect("the URL is already valid because it comes from axum; qed");
+
+    let body = match request_body::decode_body(
+        body,
+        head.headers.get(http::header::CONTENT_ENCODING),
+        settings.decompress,
+        settings.max_decompressed_bytes,
+    ) {
+        Ok(body) => body,
+        Err(err) => return err.into_response(),
+    };
+    let requests = match boundary {
+        Some(boundary) => request_body::read_multipart_request(
+            body,
+            boundary,
+            settings.max_request_bytes,
+            settings.max_variables_bytes,
+            settings.uploads.max_files,
+        )
+        .await
+        .map(|(request, uploads)| {
+            // Found by the subgraph requests using the files.
+            head.extensions.insert(uploads);
+            Requests::Single(request)
+        }),
+        None => {
+            request_body::read_requests(
+                body,
+                settings.max_request_bytes,
+                settings.max_variables_bytes,
+                &settings.batching,
+            )
+            .await
+        }
+    };
+    match requests {
+        Ok(Requests::Single(request)) => run_graphql_request(
+            service,
+            &slots,
+            Request::from_parts(head, request),
+            settings.stream_responses,
+        )
         .await
+        .into_response(),
+        Ok(Requests::Batch(requests)) => {
+            run_graphql_batch(
+                service,
+                &slots,
+                &head,
+                requests,
+                settings.batching.max_concurrency,
+            )
+            .await
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+fn csrf_rejection(csrf: &Csrf) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        format!(
+            "This operation has been blocked as a potential Cross-Site Request Forgery (CSRF). \
+            Please either specify a `Content-Type` header with a type other than \
+            application/x-www-form-urlencoded, multipart/form-data or text/plain, \
+            or provide one of the following headers: {}",
+            csrf.required_headers.join(", ")
+        ),
+    )
         .into_response()
 }
 
+/// Subscriptions are disabled: WebSocket connections are accepted only to be closed with a reason
+/// the client can display.
+fn reject_websocket(websocket: WebSocketUpgrade) -> Response {
+    websocket
+        .protocols(["graphql-transport-ws", "graphql-ws"])
+        .on_upgrade(|mut socket| async move {
+            let close = Message::Close(Some(CloseFrame {
+                // 1003: the endpoint cannot accept the kind of data it would receive.
+                code: 1003,
+                reason: "subscriptions are not supported by this router".into(),
+            }));
+            if let Err(err) = socket.send(close).await {
+                tracing::debug!("could not close the websocket connection: {}", err);
+            }
+        })
+}
+
+fn has_json_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(&http::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim();
+            mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+        })
+        .unwrap_or_default()
+}
+
+/// The boundary of a `multipart/form-data` request.
+fn multipart_boundary(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(&http::header::CONTENT_TYPE)?.to_str().ok()?;
+    multer::parse_boundary(content_type).ok()
+}
+
+/// Whether a browser would have sent a CORS preflight before the request, which is the case when
+/// it has a `Content-Type` that cannot be used by an HTML form, or one of the required headers.
+fn is_preflighted(headers: &HeaderMap, required_headers: &[String]) -> bool {
+    let non_simple_content_type = headers
+        .get(&http::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim().to_ascii_lowercase();
+            !matches!(
+                mime.as_str(),
+                "application/x-www-form-urlencoded" | "multipart/form-data" | "text/plain"
+            )
+        })
+        .unwrap_or_default();
+
+    non_simple_content_type
+        || required_headers
+            .iter()
+            .any(|header| headers.contains_key(header.as_str()))
+}
+
 fn display_home_page() -> Html<Bytes> {
     let html = Bytes::from_static(include_bytes!("../resources/index.html"));
     Html(html)
 }
 
+/// HTML of the landing page.
+async fn landing_page(content: &LandingPageContent) -> std::io::Result<Bytes> {
+    Ok(match content {
+        LandingPageContent::Default => display_home_page().0,
+        LandingPageContent::Sandbox => {
+            Bytes::from_static(include_bytes!("../resources/sandbox.html"))
+        }
+        LandingPageContent::Graphiql => {
+            Bytes::from_static(include_bytes!("../resources/graphiql.html"))
+        }
+        LandingPageContent::File(path) => Bytes::from(tokio::fs::read(path).await?),
+    })
+}
+
 async fn health_check() -> impl IntoResponse {
     Json(json!({ "status": "pass" }))
 }
 
 async fn run_graphql_request(
-    service: Buffer<
-        BoxService<
-            http_compat::Request<graphql::Request>,
-            http_compat::Response<ResponseBody>,
-            BoxError,
-        >,
-        http_compat::Request<graphql::Request>,
-    >,
+    service: BufferedService,
+    slots: &ConnectionSlots,
     http_request: Request<graphql::Request>,
+    stream_responses: bool,
 ) -> impl IntoResponse {
+    match call_graphql_service(service, slots, http_request).await {
+        Ok((response, slot)) => {
+            let response = if stream_responses {
+                response.into_streaming_response()
+            } else {
+                tracing::trace_span!("serialize_response").in_scope(|| response.into_response())
+            };
+            match slot {
+                Some(slot) => deferred::hold_until_sent(response, slot),
+                None => response,
+            }
+        }
+        Err(response) => response,
+    }
+}
+
+/// Runs the requests of a batch, `max_concurrency` at a time, answering with the array of their
+/// responses.
+async fn run_graphql_batch(
+    service: BufferedService,
+    slots: &ConnectionSlots,
+    head: &http::request::Parts,
+    requests: Vec<graphql::Request>,
+    max_concurrency: usize,
+) -> Response {
+    let size = requests.len();
+    let responses: Vec<ResponseBody> = futures::stream::iter(requests.into_iter().enumerate())
+        .map(|(index, request)| {
+            let mut http_request = Request::new(request);
+            *http_request.method_mut() = head.method.clone();
+            *http_request.uri_mut() = head.uri.clone();
+            *http_request.version_mut() = head.version;
+            *http_request.headers_mut() = head.headers.clone();
+            // Extensions cannot be cloned, only those the pipeline reads are carried over.
+            let extensions = http_request.extensions_mut();
+            extensions.insert(BatchEntry { index, size });
+            if let Some(client_ip) = head.extensions.get::<ClientIp>() {
+                extensions.insert(*client_ip);
+            }
+            if let Some(correlation_id) = head.extensions.get::<CorrelationId>() {
+                extensions.insert(correlation_id.clone());
+            }
+            call_graphql_service(service.clone(), slots, http_request)
+        })
+        .buffered(max_concurrency.max(1))
+        .map(|result| match result {
+            Ok((response, _slot)) => response.into_body(),
+            // Each request of the batch gets a response, even when it could not be executed.
+            Err(response) => ResponseBody::GraphQL(
+                graphql::Response::builder()
+                    .errors(vec![graphql::Error {
+                        message: format!(
+                            "the request could not be executed: {}",
+                            response.status()
+                        ),
+                        ..Default::default()
+                    }])
+                    .build(),
+            ),
+        })
+        .collect()
+        .await;
+    tracing::trace_span!("serialize_response").in_scope(|| Json(responses).into_response())
+}
+
+/// Runs a request through the router service, failures being answered with a response of their
+/// own. The slot of a deferred query is returned along with its response, to be held until the
+/// response has been sent.
+async fn call_graphql_service(
+    service: BufferedService,
+    slots: &ConnectionSlots,
+    http_request: Request<graphql::Request>,
+) -> Result<(http_compat::Response<ResponseBody>, Option<DeferredSlot>), Response> {
+    // Dropped along with this future if the client leaves.
+    let deferred_slot = match http_request.body().query.as_deref() {
+        Some(query) if deferred::is_deferred(query) => match slots.try_acquire() {
+            Ok(slot) => Some(slot),
+            Err(err) => return Err(err.into_response()),
+        },
+        _ => None,
+    };
+
     match service.ready_oneshot().await {
         Ok(mut service) => {
             let (head, body) = http_request.into_parts();
@@ -487,27 +944,66 @@ async fn run_graphql_request(
             service
                 .call(http_compat::Request::from_parts(head, body))
                 .await
-                .map(|response| {
-                    tracing::trace_span!("serialize_response").in_scope(|| response.into_response())
-                })
-                .unwrap_or_else(|e| {
-                    tracing::error!("router serivce call failed: {}", e);
+                .map(|response| (response, deferred_slot))
+                .map_err(|e| {
+                    tracing::error!("router service call failed: {}", e);
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
-                        "router service call failed",
+                        Json(
+                            graphql::Response::builder()
+                                .errors(vec![graphql::Error::from_box_error(&e)])
+                                .build(),
+                        ),
                     )
                         .into_response()
                 })
         }
         Err(e) => {
             tracing::error!("router service is not available to process request: {}", e);
-            (
+            Err((
                 StatusCode::SERVICE_UNAVAILABLE,
                 "router service is not available to process request",
             )
-                .into_response()
+                .into_response())
+        }
+    }
+}
+
+/// Parses the HTTP statuses of the error codes of the configuration.
+fn error_status_codes(
+    codes: &HashMap<String, u16>,
+) -> Result<HashMap<String, StatusCode>, FederatedServerError> {
+    codes
+        .iter()
+        .map(|(code, status)| {
+            StatusCode::from_u16(*status)
+                .map(|status| (code.clone(), status))
+                .map_err(|err| {
+                    FederatedServerError::ServerCreationError(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("invalid HTTP status for error code {}: {}", code, err),
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// Sets the status of a response from the `code` of its first error, when one is configured.
+fn with_error_status(
+    mut response: http_compat::Response<ResponseBody>,
+    error_status_codes: &HashMap<String, StatusCode>,
+) -> http_compat::Response<ResponseBody> {
+    if let ResponseBody::GraphQL(body) = response.body() {
+        if let Some(status) = body
+            .errors
+            .first()
+            .and_then(|error| error.code())
+            .and_then(|code| error_status_codes.get(code))
+        {
+            *response.status_mut() = *status;
         }
     }
+    response
 }
 
 fn prefers_html(accept_header: &HeaderValue) -> bool {
@@ -533,6 +1029,12 @@ impl PropagatingMakeSpan {
 
 impl<B> MakeSpan<B> for PropagatingMakeSpan {
     fn make_span(&mut self, request: &http::Request<B>) -> Span {
+        let correlation_id = request
+            .extensions()
+            .get::<CorrelationId>()
+            .map(|CorrelationId(id)| id.as_str())
+            .unwrap_or_default();
+
         // Before we make the span we need to attach span info that may have come in from the request.
         let context = global::get_text_map_propagator(|propagator| {
             propagator.extract(&opentelemetry_http::HeaderExtractor(request.headers()))
@@ -549,6 +1051,7 @@ impl<B> MakeSpan<B> for PropagatingMakeSpan {
                 method = %request.method(),
                 uri = %request.uri(),
                 version = ?request.version(),
+                correlation_id = correlation_id,
                 "otel.kind" = %SpanKind::Server,
                 "otel.status_code" = %opentelemetry::trace::StatusCode::Unset.as_str()
             )
@@ -560,6 +1063,7 @@ impl<B> MakeSpan<B> for PropagatingMakeSpan {
                 method = %request.method(),
                 uri = %request.uri(),
                 version = ?request.version(),
+                correlation_id = correlation_id,
                 "otel.kind" = %SpanKind::Server,
                 "otel.status_code" = %opentelemetry::trace::StatusCode::Unset.as_str()
             )
@@ -570,14 +1074,14 @@ impl<B> MakeSpan<B> for PropagatingMakeSpan {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::configuration::Cors;
+    use crate::configuration::{AdminServer, Cors};
     use apollo_router_core::http_compat::Request;
     use http::header::CONTENT_TYPE;
     use mockall::mock;
     use reqwest::header::{
-        ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
-        ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
-        ORIGIN,
+        ACCEPT, ACCEPT_ENCODING, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+        ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+        ACCESS_CONTROL_REQUEST_METHOD, CONTENT_ENCODING, ORIGIN,
     };
     use reqwest::redirect::Policy;
     use reqwest::{Client, Method, StatusCode};
@@ -661,6 +1165,9 @@ mod tests {
                                         .origins(vec!["http://studio".to_string()])
                                         .build(),
                                 ))
+                                .correlation_id_extractor(Some(|_: &HeaderMap| {
+                                    Some("correlation".to_string())
+                                }))
                                 .build(),
                         )
                         .build(),
@@ -810,80 +1317,410 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn response() -> Result<(), FederatedServerError> {
-        test_span::init();
-        let root_span = info_span!("root");
-        {
-            let _guard = root_span.enter();
-            let expected_response = graphql::Response::builder()
-                .data(json!({"response": "yay"}))
-                .build();
-            let example_response = expected_response.clone();
-            let mut expectations = MockRouterService::new();
-            expectations
-                .expect_service_call()
-                .times(2)
-                .returning(move |_| {
-                    let example_response = example_response.clone();
-                    Ok(http::Response::builder()
-                        .status(200)
-                        .body(ResponseBody::GraphQL(example_response))
-                        .unwrap()
-                        .into())
-                });
-            let (server, client) = init(expectations).await;
-            let url = format!("{}/graphql", server.listen_address());
-
-            // Post query
-            let response = client
-                .post(url.as_str())
-                .body(json!({ "query": "query" }).to_string())
-                .send()
-                .await
-                .unwrap()
-                .error_for_status()
-                .unwrap();
-
-            assert_eq!(
-                response.json::<graphql::Response>().await.unwrap(),
-                expected_response,
-            );
-
-            // Get query
-            let response = client
-                .get(url.as_str())
-                .query(&json!({ "query": "query" }))
-                .send()
-                .await
+    async fn responses_are_compressed() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations.expect_service_call().times(1).returning(|_| {
+            Ok(http::Response::builder()
+                .status(200)
+                .body(ResponseBody::GraphQL(
+                    graphql::Response::builder()
+                        .data(json!({"response": "yay"}))
+                        .build(),
+                ))
                 .unwrap()
-                .error_for_status()
-                .unwrap();
+                .into())
+        });
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .compression(
+                        crate::configuration::Compression::builder()
+                            .requests(true)
+                            .responses(true)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
 
-            assert_eq!(
-                response.json::<graphql::Response>().await.unwrap(),
-                expected_response,
-            );
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .header(ACCEPT_ENCODING, "gzip")
+            .header(CONTENT_ENCODING, "identity")
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_ENCODING).unwrap(),
+            HeaderValue::from_static("gzip")
+        );
 
-            server.shutdown().await?
-        }
-        insta::assert_json_snapshot!(test_span::get_spans_for_root(
-            &root_span.id().unwrap(),
-            &test_span::Filter::new(Level::INFO)
-        ));
-        Ok(())
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .header(CONTENT_ENCODING, "zstd")
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        server.shutdown().await
     }
 
     #[tokio::test]
-    async fn it_extracts_query_and_operation_name_on_get_requests(
-    ) -> Result<(), FederatedServerError> {
-        test_span::init();
-        let root_span = info_span!("root");
-        {
-            let _guard = root_span.enter();
-            let query = "query";
-            let expected_query = query;
-            let operation_name = "operationName";
-            let expected_operation_name = operation_name;
+    async fn error_codes_set_the_response_status() -> Result<(), FederatedServerError> {
+        let mut seq = mockall::Sequence::new();
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| {
+                let mut extensions = graphql::Object::new();
+                extensions.insert("code", "UNAUTHENTICATED".into());
+                Ok(http::Response::builder()
+                    .status(200)
+                    .body(ResponseBody::GraphQL(
+                        graphql::Response::builder()
+                            .errors(vec![graphql::Error {
+                                message: "missing token".to_string(),
+                                extensions,
+                                ..Default::default()
+                            }])
+                            .build(),
+                    ))
+                    .unwrap()
+                    .into())
+            });
+        expectations
+            .expect_service_call()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(BoxError::from("planner unavailable")));
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .error_status_codes(
+                        [("UNAUTHENTICATED".to_string(), 401)].into_iter().collect(),
+                    )
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Failures of the pipeline are answered with a GraphQL error.
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let response = response.json::<graphql::Response>().await.unwrap();
+        assert_eq!(response.errors[0].code(), Some("INTERNAL_SERVER_ERROR"));
+        server.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn multipart_requests_carry_their_files() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(1)
+            .returning(|request| {
+                assert!(request.extensions().get::<graphql::Uploads>().is_some());
+                Ok(http::Response::builder()
+                    .status(200)
+                    .body(ResponseBody::GraphQL(
+                        graphql::Response::builder()
+                            .data(json!({"response": "yay"}))
+                            .build(),
+                    ))
+                    .unwrap()
+                    .into())
+            });
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .uploads(
+                        crate::configuration::Uploads::builder()
+                            .enabled(true)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+        let body = "--b\r\n\
+            Content-Disposition: form-data; name=\"operations\"\r\n\r\n\
+            {\"query\":\"query\",\"variables\":{\"file\":null}}\r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"map\"\r\n\r\n\
+            {\"0\":[\"variables.file\"]}\r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"0\"; filename=\"a.txt\"\r\n\r\n\
+            alpha\r\n\
+            --b--\r\n";
+
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=b")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=b")
+            .header("apollo-require-preflight", "true")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        server.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn batches_are_answered_in_order() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(2)
+            .returning(|request| {
+                let entry = request.extensions().get::<BatchEntry>().copied().unwrap();
+                assert_eq!(entry.size, 2);
+                Ok(http::Response::builder()
+                    .status(200)
+                    .body(ResponseBody::GraphQL(
+                        graphql::Response::builder()
+                            .data(json!({
+                                "query": request.body().query.clone(),
+                                "index": entry.index,
+                            }))
+                            .build(),
+                    ))
+                    .unwrap()
+                    .into())
+            });
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .batching(
+                        crate::configuration::Batching::builder()
+                            .enabled(true)
+                            .max_concurrency(2)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!([{ "query": "{ a }" }, { "query": "{ b }" }]).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.json::<serde_json::Value>().await.unwrap(),
+            json!([
+                { "data": { "query": "{ a }", "index": 0 } },
+                { "data": { "query": "{ b }", "index": 1 } },
+            ])
+        );
+        server.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn requests_are_served_over_tls() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations.expect_service_call().times(1).returning(|_| {
+            Ok(http::Response::builder()
+                .status(200)
+                .body(ResponseBody::GraphQL(
+                    graphql::Response::builder()
+                        .data(json!({"response": "yay"}))
+                        .build(),
+                ))
+                .unwrap()
+                .into())
+        });
+        let testdata = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testdata/tls");
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .tls(Some(
+                        crate::configuration::Tls::builder()
+                            .certificate(testdata.join("server.crt"))
+                            .key(testdata.join("server.key"))
+                            .build(),
+                    ))
+                    .build(),
+            )
+            .build();
+        let (server, plain_client) = init_with_config(expectations, conf, HashMap::new()).await;
+        let port = match server.listen_address() {
+            ListenAddr::SocketAddr(addr) => addr.port(),
+            #[cfg(unix)]
+            ListenAddr::UnixSocket(_) => unreachable!(),
+        };
+
+        let ca = std::fs::read(testdata.join("ca.crt")).unwrap();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&ca).unwrap())
+            .build()
+            .unwrap();
+        let response = client
+            .post(format!("https://localhost:{}/graphql", port))
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+
+        assert!(plain_client
+            .post(format!("http://localhost:{}/graphql", port))
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .is_err());
+        server.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn slow_tls_handshakes_are_closed() -> Result<(), FederatedServerError> {
+        use tokio::io::AsyncReadExt;
+
+        let testdata = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testdata/tls");
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .tls(Some(
+                        crate::configuration::Tls::builder()
+                            .certificate(testdata.join("server.crt"))
+                            .key(testdata.join("server.key"))
+                            .handshake_timeout(Duration::from_millis(100))
+                            .build(),
+                    ))
+                    .build(),
+            )
+            .build();
+        let (server, _) = init_with_config(MockRouterService::new(), conf, HashMap::new()).await;
+        let address = match server.listen_address() {
+            ListenAddr::SocketAddr(addr) => *addr,
+            #[cfg(unix)]
+            ListenAddr::UnixSocket(_) => unreachable!(),
+        };
+
+        // The client connects without ever starting the handshake.
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("the connection should be closed");
+        assert!(matches!(read, Ok(0) | Err(_)));
+        server.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn response() -> Result<(), FederatedServerError> {
+        test_span::init();
+        let root_span = info_span!("root");
+        {
+            let _guard = root_span.enter();
+            let expected_response = graphql::Response::builder()
+                .data(json!({"response": "yay"}))
+                .build();
+            let example_response = expected_response.clone();
+            let mut expectations = MockRouterService::new();
+            expectations
+                .expect_service_call()
+                .times(2)
+                .returning(move |_| {
+                    let example_response = example_response.clone();
+                    Ok(http::Response::builder()
+                        .status(200)
+                        .body(ResponseBody::GraphQL(example_response))
+                        .unwrap()
+                        .into())
+                });
+            let (server, client) = init(expectations).await;
+            let url = format!("{}/graphql", server.listen_address());
+
+            // Post query
+            let response = client
+                .post(url.as_str())
+                .body(json!({ "query": "query" }).to_string())
+                .send()
+                .await
+                .unwrap()
+                .error_for_status()
+                .unwrap();
+
+            assert_eq!(
+                response.json::<graphql::Response>().await.unwrap(),
+                expected_response,
+            );
+
+            // Get query
+            let response = client
+                .get(url.as_str())
+                .query(&json!({ "query": "query" }))
+                .send()
+                .await
+                .unwrap()
+                .error_for_status()
+                .unwrap();
+
+            assert_eq!(
+                response.json::<graphql::Response>().await.unwrap(),
+                expected_response,
+            );
+
+            server.shutdown().await?
+        }
+        insta::assert_json_snapshot!(test_span::get_spans_for_root(
+            &root_span.id().unwrap(),
+            &test_span::Filter::new(Level::INFO)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_extracts_query_and_operation_name_on_get_requests(
+    ) -> Result<(), FederatedServerError> {
+        test_span::init();
+        let root_span = info_span!("root");
+        {
+            let _guard = root_span.enter();
+            let query = "query";
+            let expected_query = query;
+            let operation_name = "operationName";
+            let expected_operation_name = operation_name;
 
             let expected_response = graphql::Response::builder()
                 .data(json!({"response": "yay"}))
@@ -1080,6 +1917,62 @@ mod tests {
         server.shutdown().await
     }
 
+    #[tokio::test]
+    async fn cors_origin_patterns() -> Result<(), FederatedServerError> {
+        let expectations = MockRouterService::new();
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .cors(Some(
+                        Cors::builder()
+                            .origins(vec!["http://studio".to_string()])
+                            .match_origins(vec![r"https://[a-z]+\.example\.com".to_string()])
+                            .max_age(Some(Duration::from_secs(3600)))
+                            .build(),
+                    ))
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+        let url = format!("{}/graphql", server.listen_address());
+
+        for (origin, allowed) in [
+            ("http://studio", true),
+            ("https://app.example.com", true),
+            ("https://app.example.com.evil.net", false),
+            ("https://example.com", false),
+        ] {
+            let response = client
+                .request(Method::OPTIONS, &url)
+                .header(ORIGIN, origin)
+                .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response
+                    .headers()
+                    .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+                    .is_some(),
+                allowed,
+                "unexpected CORS answer for {}",
+                origin
+            );
+            if allowed {
+                assert_header!(
+                    &response,
+                    ACCESS_CONTROL_MAX_AGE,
+                    vec!["3600"],
+                    "Incorrect access control max age header"
+                );
+            }
+        }
+
+        server.shutdown().await
+    }
+
     #[tokio::test]
     #[cfg(unix)]
     async fn listening_to_unix_socket() {
@@ -1209,6 +2102,291 @@ Content-Type: application/json\r
         ));
     }
 
+    #[tokio::test]
+    async fn it_sends_the_server_header_when_enabled() {
+        let expectations = MockRouterService::new();
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .expose_version(true)
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+        let url = format!(
+            "{}/.well-known/apollo/server-health",
+            server.listen_address()
+        );
+
+        let response = client.get(url).send().await.unwrap();
+        assert_eq!(
+            response.headers().get(http::header::SERVER).unwrap(),
+            &format!("apollo-router/{}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_serves_graphql_on_the_configured_path() {
+        let mut expectations = MockRouterService::new();
+        expectations.expect_service_call().times(2).returning(|_| {
+            Ok(http::Response::builder()
+                .status(200)
+                .body(ResponseBody::GraphQL(graphql::Response::builder().build()))
+                .unwrap()
+                .into())
+        });
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .graphql_path("/api/graphql")
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+
+        for path in ["/", "/api/graphql"] {
+            let response = client
+                .post(format!("{}{}", server.listen_address(), path))
+                .body(json!({ "query": "query" }).to_string())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = client
+            .post(format!("{}/graphql", server.listen_address()))
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_blocks_get_requests_that_were_not_preflighted() {
+        let mut expectations = MockRouterService::new();
+        expectations.expect_service_call().times(2).returning(|_| {
+            Ok(http::Response::builder()
+                .status(200)
+                .body(ResponseBody::GraphQL(graphql::Response::builder().build()))
+                .unwrap()
+                .into())
+        });
+        let (server, _) = init(expectations).await;
+        let url = format!("{}/graphql", server.listen_address());
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(url.as_str())
+            .query(&json!({ "query": "{ me { name } }" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.text().await.unwrap().contains("CSRF"));
+
+        let response = client
+            .get(url.as_str())
+            .header(CONTENT_TYPE, "text/plain")
+            .query(&json!({ "query": "{ me { name } }" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = client
+            .get(url.as_str())
+            .header("apollo-require-preflight", "true")
+            .query(&json!({ "query": "{ me { name } }" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = client
+            .get(url.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .query(&json!({ "query": "{ me { name } }" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_closes_websocket_connections() {
+        use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let expectations = MockRouterService::new();
+        let (server, _) = init(expectations).await;
+        let url = format!("{}/graphql", server.listen_address()).replacen("http", "ws", 1);
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        match socket.next().await {
+            Some(Ok(Message::Close(Some(frame)))) => {
+                assert_eq!(frame.code, CloseCode::Unsupported);
+                assert_eq!(
+                    frame.reason,
+                    "subscriptions are not supported by this router"
+                );
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_sends_the_events_of_subscriptions_on_websockets() {
+        use apollo_router_core::{SubscriptionConnection, SubscriptionEvents};
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(1)
+            .withf(|req| {
+                let connection = req.extensions().get::<SubscriptionConnection>().unwrap();
+                assert_eq!(connection.init_payload, Some(json!({ "token": "secret" })));
+                assert_eq!(
+                    req.body().query.as_deref(),
+                    Some("subscription { reviewAdded { body } }")
+                );
+                true
+            })
+            .returning(|_| {
+                let (sender, receiver) = mpsc::channel(2);
+                for body in ["first", "second"] {
+                    sender
+                        .try_send(
+                            graphql::Response::builder()
+                                .data(json!({ "reviewAdded": { "body": body } }))
+                                .build(),
+                        )
+                        .unwrap();
+                }
+                let mut response: http_compat::Response<ResponseBody> = http::Response::builder()
+                    .status(200)
+                    .body(ResponseBody::GraphQL(graphql::Response::builder().build()))
+                    .unwrap()
+                    .into();
+                response
+                    .extensions_mut()
+                    .insert(SubscriptionEvents(receiver));
+                Ok(response)
+            });
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .subscriptions(
+                        crate::configuration::Subscriptions::builder()
+                            .enabled(true)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let (server, _) = init_with_config(expectations, conf, HashMap::new()).await;
+        let url = format!("{}/graphql", server.listen_address()).replacen("http", "ws", 1);
+        let mut request = url.into_client_request().unwrap();
+        request.headers_mut().insert(
+            "sec-websocket-protocol",
+            HeaderValue::from_static(GRAPHQL_TRANSPORT_WS),
+        );
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+        let messages = [
+            json!({ "type": "connection_init", "payload": { "token": "secret" } }),
+            json!({
+                "type": "subscribe",
+                "id": "review",
+                "payload": { "query": "subscription { reviewAdded { body } }" }
+            }),
+        ];
+        for message in messages {
+            socket
+                .send(Message::Text(message.to_string()))
+                .await
+                .unwrap();
+        }
+        let mut received = Vec::new();
+        while received.len() < 4 {
+            match socket.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    received.push(serde_json::from_str::<serde_json::Value>(&text).unwrap())
+                }
+                other => panic!("expected a message, got {:?}", other),
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                json!({ "type": "connection_ack" }),
+                json!({
+                    "type": "next",
+                    "id": "review",
+                    "payload": { "data": { "reviewAdded": { "body": "first" } } }
+                }),
+                json!({
+                    "type": "next",
+                    "id": "review",
+                    "payload": { "data": { "reviewAdded": { "body": "second" } } }
+                }),
+                json!({ "type": "complete", "id": "review" }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_resolves_the_client_ip() {
+        for (trusted_proxies, expected) in [
+            (vec![], "127.0.0.1"),
+            (vec!["127.0.0.1".parse().unwrap()], "203.0.113.7"),
+        ] {
+            let mut expectations = MockRouterService::new();
+            expectations
+                .expect_service_call()
+                .times(1)
+                .withf(move |req| {
+                    assert_eq!(
+                        req.extensions().get::<ClientIp>(),
+                        Some(&ClientIp(expected.parse().unwrap()))
+                    );
+                    true
+                })
+                .returning(|_| {
+                    Ok(http::Response::builder()
+                        .status(200)
+                        .body(ResponseBody::GraphQL(graphql::Response::builder().build()))
+                        .unwrap()
+                        .into())
+                });
+            let conf = Configuration::builder()
+                .server(
+                    crate::configuration::Server::builder()
+                        .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                        .trusted_proxies(trusted_proxies)
+                        .build(),
+                )
+                .build();
+            let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+
+            client
+                .post(format!("{}/graphql", server.listen_address()))
+                .header("x-forwarded-for", "203.0.113.7")
+                .body(json!({ "query": "query" }).to_string())
+                .send()
+                .await
+                .unwrap()
+                .error_for_status()
+                .unwrap();
+            server.shutdown().await.unwrap();
+        }
+    }
+
     #[test(tokio::test)]
     async fn it_send_bad_content_type() -> Result<(), FederatedServerError> {
         let query = "query";
@@ -1346,6 +2524,171 @@ Content-Type: application/json\r
         server.shutdown().await
     }
 
+    #[test(tokio::test)]
+    async fn it_serves_telemetry_metrics_at_the_root() -> Result<(), FederatedServerError> {
+        let expectations = MockRouterService::new();
+        let plugin_handler = Handler::new(
+            service_fn(|req: http_compat::Request<Bytes>| async move {
+                Ok::<_, BoxError>(http_compat::Response {
+                    inner: http::Response::builder()
+                        .status(StatusCode::OK)
+                        .body(ResponseBody::Text(req.uri().path().to_string()))
+                        .unwrap(),
+                })
+            })
+            .boxed(),
+        );
+        let mut plugin_handlers = HashMap::new();
+        plugin_handlers.insert("apollo.telemetry".to_string(), plugin_handler);
+
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, plugin_handlers).await;
+
+        let response = client
+            .get(&format!("{}/metrics", server.listen_address()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "/metrics");
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_leaves_metrics_moved_to_the_admin_listener_out() -> Result<(), FederatedServerError>
+    {
+        let expectations = MockRouterService::new();
+        let plugin_handler = Handler::new(
+            service_fn(|req: http_compat::Request<Bytes>| async move {
+                Ok::<_, BoxError>(http_compat::Response {
+                    inner: http::Response::builder()
+                        .status(StatusCode::OK)
+                        .body(ResponseBody::Text(req.uri().path().to_string()))
+                        .unwrap(),
+                })
+            })
+            .boxed(),
+        );
+        let mut plugin_handlers = HashMap::new();
+        plugin_handlers.insert("apollo.telemetry".to_string(), plugin_handler);
+
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .admin(Some(AdminServer::builder().metrics(true).build()))
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, plugin_handlers).await;
+
+        for path in ["/metrics", "/plugins/apollo.telemetry/metrics"] {
+            let response = client
+                .get(&format!("{}{}", server.listen_address(), path))
+                .send()
+                .await
+                .unwrap();
+            assert_ne!(response.status(), StatusCode::OK);
+        }
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_displays_the_configured_landing_page() -> Result<(), FederatedServerError> {
+        let page = std::env::temp_dir().join(format!("landing-page-{}.html", std::process::id()));
+        std::fs::write(&page, "<h1>Products graph</h1>").unwrap();
+
+        for (content, expected) in [
+            (
+                LandingPageContent::Sandbox,
+                Bytes::from_static(include_bytes!("../resources/sandbox.html")),
+            ),
+            (
+                LandingPageContent::File(page.clone()),
+                Bytes::from_static(b"<h1>Products graph</h1>"),
+            ),
+        ] {
+            let conf = Configuration::builder()
+                .server(
+                    crate::configuration::Server::builder()
+                        .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                        .landing_page_content(content)
+                        .build(),
+                )
+                .build();
+            let (server, client) =
+                init_with_config(MockRouterService::new(), conf, HashMap::new()).await;
+
+            let response = client
+                .get(&format!("{}/graphql", server.listen_address()))
+                .header(ACCEPT, "text/html")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.bytes().await.unwrap(), expected);
+            server.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn it_serves_the_supergraph_sdl() -> Result<(), FederatedServerError> {
+        let expectations = MockRouterService::new();
+        let sdl_handler = Handler::new(
+            service_fn(|_req: http_compat::Request<Bytes>| async move {
+                Ok::<_, BoxError>(http_compat::Response {
+                    inner: http::Response::builder()
+                        .status(StatusCode::OK)
+                        .body(ResponseBody::Text("type Query { me: String }".to_string()))
+                        .unwrap(),
+                })
+            })
+            .boxed(),
+        );
+        let mut plugin_handlers = HashMap::new();
+        plugin_handlers.insert(SUPERGRAPH_SDL_HANDLER.to_string(), sdl_handler);
+
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .supergraph_sdl_path(Some("/schema".to_string()))
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, plugin_handlers).await;
+
+        let response = client
+            .get(&format!("{}/schema", server.listen_address()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "type Query { me: String }");
+
+        let response = client
+            .get(&format!(
+                "{}/plugins/{}/schema",
+                server.listen_address(),
+                SUPERGRAPH_SDL_HANDLER
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        server.shutdown().await
+    }
+
     #[test(tokio::test)]
     async fn it_checks_the_shape_of_router_request() -> Result<(), FederatedServerError> {
         let mut expectations = MockRouterService::new();
@@ -0,0 +1,19 @@
+//! Client-side batching of GraphQL requests.
+//!
+//! Each request of a batch is executed on its own, and is marked with a [`BatchEntry`] request
+//! extension. Its index in the batch and the size of the batch are stored in the request context
+//! under [`BATCH_INDEX_CONTEXT_KEY`] and [`BATCH_SIZE_CONTEXT_KEY`], for plugins to tell batched
+//! requests apart.
+
+/// Context key holding the index of a request in its batch.
+pub const BATCH_INDEX_CONTEXT_KEY: &str = "apollo::batching::index";
+
+/// Context key holding the number of requests of the batch of a request.
+pub const BATCH_SIZE_CONTEXT_KEY: &str = "apollo::batching::size";
+
+/// The position of a request in its batch, stored in its extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchEntry {
+    pub index: usize,
+    pub size: usize,
+}
@@ -0,0 +1,31 @@
+//! Version and build information of the router, set at compile time by the build script.
+
+use serde::Serialize;
+
+/// Version and build information of the router.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    /// The version of the router crate.
+    pub version: &'static str,
+    /// The git commit the router was built from, or `unknown`.
+    pub commit: &'static str,
+    /// When the router was built, as an RFC 3339 timestamp.
+    pub build_time: &'static str,
+    /// The version of the compiler the router was built with.
+    pub rustc_version: &'static str,
+}
+
+/// Returns the version and build information of this router.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("APOLLO_ROUTER_GIT_COMMIT"),
+        build_time: env!("APOLLO_ROUTER_BUILD_TIME"),
+        rustc_version: env!("APOLLO_ROUTER_RUSTC_VERSION"),
+    }
+}
+
+/// The value of the `Server` header sent when `server.expose_version` is enabled.
+pub(crate) fn server_header() -> &'static str {
+    concat!("apollo-router/", env!("CARGO_PKG_VERSION"))
+}
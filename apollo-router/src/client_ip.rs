@@ -0,0 +1,168 @@
+//! Client IP resolution.
+//!
+//! The client IP of a request is the address of the connection, unless that address belongs to a
+//! trusted proxy. In that case, the addresses listed in the `Forwarded` header, or failing that
+//! in `X-Forwarded-For`, are walked from the closest hop back, and the first one that is not a
+//! trusted proxy is the client. Headers sent by anyone else are ignored, so they cannot be used
+//! to spoof an address.
+//!
+//! The resolved IP is stored as a [`ClientIp`] request extension, and in the request context
+//! under [`CLIENT_IP_CONTEXT_KEY`].
+
+use http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Context key holding the client IP of a request, as a string.
+pub const CLIENT_IP_CONTEXT_KEY: &str = "apollo::client_ip";
+
+/// The client IP of a request, stored in its extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Resolves the client IP of a request received from `peer`.
+pub(crate) fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    if !trusted_proxies.contains(&client) {
+        return client;
+    }
+
+    for hop in forwarded_for(headers).into_iter().rev() {
+        match hop {
+            Some(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            // An obfuscated or malformed hop: the last known address is as far as we can go.
+            None => break,
+        }
+    }
+    client
+}
+
+/// The hops listed by the `Forwarded` headers, or by the `X-Forwarded-For` ones when there is no
+/// `Forwarded` header, from the furthest to the closest.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded = headers.get_all(http::header::FORWARDED);
+    if forwarded.iter().next().is_some() {
+        forwarded
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim().trim_matches('"')))
+            })
+            .collect()
+    } else {
+        headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(|node| parse_node(node.trim()))
+            .collect()
+    }
+}
+
+/// Parses an address, possibly bracketed and followed by a port.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .and_then(|node| node.parse().ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    const PROXY: &str = "10.0.0.1";
+    const CLIENT: &str = "203.0.113.7";
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn direct_connections_use_the_peer_address() {
+        assert_eq!(client_ip(ip(CLIENT), &HeaderMap::new(), &[]), ip(CLIENT));
+        assert_eq!(
+            client_ip(ip(CLIENT), &HeaderMap::new(), &[ip(PROXY)]),
+            ip(CLIENT)
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_report_the_client() {
+        let trusted = [ip(PROXY), ip("10.0.0.2")];
+        assert_eq!(
+            client_ip(
+                ip(PROXY),
+                &headers("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.2"),
+                &trusted
+            ),
+            ip(CLIENT)
+        );
+        assert_eq!(
+            client_ip(
+                ip(PROXY),
+                &headers(
+                    "forwarded",
+                    "for=198.51.100.1, for=\"203.0.113.7:4711\";proto=https, for=10.0.0.2"
+                ),
+                &trusted
+            ),
+            ip(CLIENT)
+        );
+        assert_eq!(
+            client_ip(
+                ip(PROXY),
+                &headers("forwarded", "for=\"[2001:db8::1]:4711\""),
+                &trusted
+            ),
+            ip("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn headers_from_untrusted_sources_are_ignored() {
+        assert_eq!(
+            client_ip(
+                ip(CLIENT),
+                &headers("x-forwarded-for", "198.51.100.1"),
+                &[ip(PROXY)]
+            ),
+            ip(CLIENT)
+        );
+        assert_eq!(
+            client_ip(ip(CLIENT), &headers("x-forwarded-for", "198.51.100.1"), &[]),
+            ip(CLIENT)
+        );
+    }
+
+    #[test]
+    fn unknown_hops_stop_the_walk() {
+        assert_eq!(
+            client_ip(
+                ip(PROXY),
+                &headers("forwarded", "for=198.51.100.1, for=_hidden"),
+                &[ip(PROXY)]
+            ),
+            ip(PROXY)
+        );
+    }
+}
@@ -0,0 +1,774 @@
+//! Composition of subgraph schemas into a supergraph, for development.
+//!
+//! This covers the subset of Federation 1 that local development needs: entities declared with
+//! `@key` by one subgraph and extended by the others, `@external`, `@requires` and `@provides`
+//! fields, and value types merged from every subgraph. Root types must be named `Query`, `Mutation`
+//! and `Subscription`. Production supergraphs should still be composed with Rover or Apollo Studio.
+
+use apollo_parser::ast::{self, AstNode};
+use apollo_parser::Parser;
+use apollo_router_core::prelude::*;
+use displaydoc::Display as DisplayDoc;
+use indexmap::{IndexMap, IndexSet};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use url::Url;
+
+const ROOT_TYPES: [&str; 3] = ["Query", "Mutation", "Subscription"];
+
+/// Federation definitions of subgraph schemas, left out of the supergraph.
+const FEDERATION_TYPES: [&str; 5] = ["_Any", "_Entity", "_Service", "_FieldSet", "FieldSet"];
+
+/// Error types for composition.
+#[derive(Error, Debug, DisplayDoc)]
+pub enum CompositionError {
+    /// could not read the subgraphs configuration: {0}
+    ReadConfig(std::io::Error),
+
+    /// could not deserialize the subgraphs configuration: {0}
+    DeserializeConfig(serde_yaml::Error),
+
+    /// could not read the schema of subgraph {0}: {1}
+    ReadSubgraph(String, std::io::Error),
+
+    /// could not parse the schema of subgraph {0}: {1}
+    ParseSubgraph(String, String),
+
+    /// type {0} is not of the same kind in every subgraph
+    KindMismatch(String),
+
+    /// field {0}.{1} does not have the same type in every subgraph
+    FieldTypeMismatch(String, String),
+
+    /// entity {0} is extended but no subgraph defines it
+    MissingOwner(String),
+
+    /// no subgraph defines the Query type
+    MissingQuery,
+
+    /// the composed supergraph is invalid: {0}
+    InvalidSupergraph(graphql::SchemaError),
+}
+
+/// A subgraph whose schema is read from a local file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalSubgraph {
+    /// The name of the subgraph.
+    pub name: String,
+
+    /// The URL of the subgraph.
+    pub routing_url: Url,
+
+    /// The path of the subgraph schema.
+    pub path: PathBuf,
+}
+
+/// The subgraphs configuration, in the format of `rover supergraph compose`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubgraphsConfig {
+    subgraphs: IndexMap<String, SubgraphConfig>,
+}
+
+#[derive(Deserialize)]
+struct SubgraphConfig {
+    routing_url: Url,
+    schema: SubgraphSchemaConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubgraphSchemaConfig {
+    file: PathBuf,
+}
+
+/// Reads the subgraphs of a configuration file, with schema paths relative to that file.
+pub(crate) fn read_config(path: &Path) -> Result<Vec<LocalSubgraph>, CompositionError> {
+    let config = std::fs::read_to_string(path).map_err(CompositionError::ReadConfig)?;
+    let config: SubgraphsConfig =
+        serde_yaml::from_str(&config).map_err(CompositionError::DeserializeConfig)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    Ok(config
+        .subgraphs
+        .into_iter()
+        .map(|(name, subgraph)| LocalSubgraph {
+            name,
+            routing_url: subgraph.routing_url,
+            path: directory.join(subgraph.schema.file),
+        })
+        .collect())
+}
+
+/// Reads the schemas of the subgraphs and composes them into a supergraph.
+pub fn compose_files(subgraphs: &[LocalSubgraph]) -> Result<graphql::Schema, CompositionError> {
+    let subgraphs = subgraphs
+        .iter()
+        .map(|subgraph| {
+            std::fs::read_to_string(&subgraph.path)
+                .map(|sdl| (subgraph.name.clone(), subgraph.routing_url.clone(), sdl))
+                .map_err(|err| CompositionError::ReadSubgraph(subgraph.name.clone(), err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    compose(&subgraphs)?
+        .parse()
+        .map_err(CompositionError::InvalidSupergraph)
+}
+
+/// Composes the SDL of subgraphs, given with their name and URL, into a supergraph SDL.
+pub fn compose(subgraphs: &[(String, Url, String)]) -> Result<String, CompositionError> {
+    let mut composition = Composition::default();
+    for (name, _, sdl) in subgraphs {
+        composition.add(&graph_name(name), name, sdl)?;
+    }
+    composition.supergraph(subgraphs)
+}
+
+/// The value of the `join__Graph` enum for a subgraph.
+fn graph_name(subgraph: &str) -> String {
+    subgraph
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Composition {
+    types: BTreeMap<String, Type>,
+}
+
+enum Type {
+    Object(Object),
+    Enum(IndexSet<String>),
+    Union(IndexSet<String>),
+    Scalar,
+    Input(IndexMap<String, String>),
+}
+
+#[derive(Default)]
+struct Object {
+    interface: bool,
+    implements: IndexSet<String>,
+    owner: Option<String>,
+    keys: Vec<(String, String)>,
+    fields: IndexMap<String, Field>,
+}
+
+struct Field {
+    arguments: String,
+    ty: String,
+    graph: String,
+    requires: Option<String>,
+    provides: Option<String>,
+}
+
+/// The parts of object and interface definitions and extensions that composition needs.
+struct ObjectLike {
+    name: String,
+    interface: bool,
+    extension: bool,
+    directives: Option<ast::Directives>,
+    implements: Option<ast::ImplementsInterfaces>,
+    fields: Option<ast::FieldsDefinition>,
+}
+
+macro_rules! object_like {
+    ($definition:expr, $interface:expr, $extension:expr) => {
+        ObjectLike {
+            name: name($definition.name()),
+            interface: $interface,
+            extension: $extension,
+            directives: $definition.directives(),
+            implements: $definition.implements_interfaces(),
+            fields: $definition.fields_definition(),
+        }
+    };
+}
+
+impl Composition {
+    fn add(&mut self, graph: &str, subgraph: &str, sdl: &str) -> Result<(), CompositionError> {
+        let tree = Parser::new(sdl).parse();
+        let errors = tree
+            .errors()
+            .map(|err| err.message().to_string())
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(CompositionError::ParseSubgraph(
+                subgraph.to_string(),
+                errors.join(", "),
+            ));
+        }
+
+        for definition in tree.document().definitions() {
+            match definition {
+                ast::Definition::ObjectTypeDefinition(definition) => {
+                    self.add_object(graph, object_like!(definition, false, false))?
+                }
+                ast::Definition::ObjectTypeExtension(extension) => {
+                    self.add_object(graph, object_like!(extension, false, true))?
+                }
+                ast::Definition::InterfaceTypeDefinition(definition) => {
+                    self.add_object(graph, object_like!(definition, true, false))?
+                }
+                ast::Definition::InterfaceTypeExtension(extension) => {
+                    self.add_object(graph, object_like!(extension, true, true))?
+                }
+                ast::Definition::EnumTypeDefinition(definition) => {
+                    let values = definition
+                        .enum_values_definition()
+                        .into_iter()
+                        .flat_map(|values| values.enum_value_definitions())
+                        .filter_map(|value| value.enum_value().and_then(|value| value.name()))
+                        .map(|value| value.text().to_string());
+                    self.add_enum(name(definition.name()), values)?
+                }
+                ast::Definition::EnumTypeExtension(extension) => {
+                    let values = extension
+                        .enum_values_definition()
+                        .into_iter()
+                        .flat_map(|values| values.enum_value_definitions())
+                        .filter_map(|value| value.enum_value().and_then(|value| value.name()))
+                        .map(|value| value.text().to_string());
+                    self.add_enum(name(extension.name()), values)?
+                }
+                ast::Definition::UnionTypeDefinition(definition) => self.add_union(
+                    name(definition.name()),
+                    definition
+                        .union_member_types()
+                        .into_iter()
+                        .flat_map(|members| members.named_types())
+                        .map(|member| name(member.name())),
+                )?,
+                ast::Definition::UnionTypeExtension(extension) => self.add_union(
+                    name(extension.name()),
+                    extension
+                        .union_member_types()
+                        .into_iter()
+                        .flat_map(|members| members.named_types())
+                        .map(|member| name(member.name())),
+                )?,
+                ast::Definition::ScalarTypeDefinition(definition) => {
+                    let name = name(definition.name());
+                    if !FEDERATION_TYPES.contains(&name.as_str()) {
+                        match self.types.entry(name.clone()).or_insert(Type::Scalar) {
+                            Type::Scalar => {}
+                            _ => return Err(CompositionError::KindMismatch(name)),
+                        }
+                    }
+                }
+                ast::Definition::InputObjectTypeDefinition(definition) => self.add_input(
+                    name(definition.name()),
+                    definition
+                        .input_fields_definition()
+                        .into_iter()
+                        .flat_map(|fields| fields.input_value_definitions()),
+                )?,
+                ast::Definition::InputObjectTypeExtension(extension) => self.add_input(
+                    name(extension.name()),
+                    extension
+                        .input_fields_definition()
+                        .into_iter()
+                        .flat_map(|fields| fields.input_value_definitions()),
+                )?,
+                // Directive definitions are those of federation, and the root types are the
+                // default ones.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_object(&mut self, graph: &str, object: ObjectLike) -> Result<(), CompositionError> {
+        if FEDERATION_TYPES.contains(&object.name.as_str()) {
+            return Ok(());
+        }
+        let composed = match self.types.entry(object.name.clone()).or_insert_with(|| {
+            Type::Object(Object {
+                interface: object.interface,
+                ..Default::default()
+            })
+        }) {
+            Type::Object(composed) if composed.interface == object.interface => composed,
+            _ => return Err(CompositionError::KindMismatch(object.name)),
+        };
+
+        let extension = object.extension || !directives(&object.directives, "extends").is_empty();
+        let keys = directives(&object.directives, "key")
+            .iter()
+            .filter_map(|key| string_argument(key, "fields"))
+            .collect::<Vec<_>>();
+        if !keys.is_empty() && !extension && composed.owner.is_none() {
+            composed.owner = Some(graph.to_string());
+        }
+        for key in keys {
+            composed.keys.push((graph.to_string(), key));
+        }
+        composed.implements.extend(
+            object
+                .implements
+                .into_iter()
+                .flat_map(|implements| implements.named_types())
+                .map(|interface| name(interface.name())),
+        );
+
+        for field in object
+            .fields
+            .into_iter()
+            .flat_map(|fields| fields.field_definitions())
+        {
+            let field_name = name(field.name());
+            let field_directives = field.directives();
+            if !directives(&field_directives, "external").is_empty()
+                || field_name == "_service"
+                || field_name == "_entities"
+            {
+                continue;
+            }
+            let ty = field
+                .ty()
+                .map(|ty| ty.syntax().to_string())
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            if let Some(existing) = composed.fields.get(&field_name) {
+                if existing.ty != ty {
+                    return Err(CompositionError::FieldTypeMismatch(object.name, field_name));
+                }
+                continue;
+            }
+            let arguments = field
+                .arguments_definition()
+                .map(|arguments| {
+                    format!(
+                        "({})",
+                        arguments
+                            .input_value_definitions()
+                            .map(|argument| input_value(&argument))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })
+                .unwrap_or_default();
+            composed.fields.insert(
+                field_name,
+                Field {
+                    arguments,
+                    ty,
+                    graph: graph.to_string(),
+                    requires: directives(&field_directives, "requires")
+                        .first()
+                        .and_then(|requires| string_argument(requires, "fields")),
+                    provides: directives(&field_directives, "provides")
+                        .first()
+                        .and_then(|provides| string_argument(provides, "fields")),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn add_enum(
+        &mut self,
+        name: String,
+        values: impl Iterator<Item = String>,
+    ) -> Result<(), CompositionError> {
+        match self
+            .types
+            .entry(name.clone())
+            .or_insert_with(|| Type::Enum(Default::default()))
+        {
+            Type::Enum(composed) => composed.extend(values),
+            _ => return Err(CompositionError::KindMismatch(name)),
+        }
+        Ok(())
+    }
+
+    fn add_union(
+        &mut self,
+        name: String,
+        members: impl Iterator<Item = String>,
+    ) -> Result<(), CompositionError> {
+        if FEDERATION_TYPES.contains(&name.as_str()) {
+            return Ok(());
+        }
+        match self
+            .types
+            .entry(name.clone())
+            .or_insert_with(|| Type::Union(Default::default()))
+        {
+            Type::Union(composed) => composed.extend(members),
+            _ => return Err(CompositionError::KindMismatch(name)),
+        }
+        Ok(())
+    }
+
+    fn add_input(
+        &mut self,
+        name: String,
+        fields: impl Iterator<Item = ast::InputValueDefinition>,
+    ) -> Result<(), CompositionError> {
+        let composed = match self
+            .types
+            .entry(name.clone())
+            .or_insert_with(|| Type::Input(Default::default()))
+        {
+            Type::Input(composed) => composed,
+            _ => return Err(CompositionError::KindMismatch(name)),
+        };
+        for field in fields {
+            composed
+                .entry(self::name(field.name()))
+                .or_insert_with(|| input_value(&field));
+        }
+        Ok(())
+    }
+
+    fn supergraph(&self, subgraphs: &[(String, Url, String)]) -> Result<String, CompositionError> {
+        if !self.types.contains_key("Query") {
+            return Err(CompositionError::MissingQuery);
+        }
+
+        let mut sdl = String::from(
+            "schema\n  @core(feature: \"https://specs.apollo.dev/core/v0.1\"),\n  @core(feature: \"https://specs.apollo.dev/join/v0.1\")\n{\n",
+        );
+        for (root, operation) in ROOT_TYPES.iter().zip(["query", "mutation", "subscription"]) {
+            if self.types.contains_key(*root) {
+                let _ = writeln!(sdl, "  {}: {}", operation, root);
+            }
+        }
+        sdl.push_str(
+            r#"}
+
+directive @core(feature: String!) repeatable on SCHEMA
+
+directive @join__field(graph: join__Graph, requires: join__FieldSet, provides: join__FieldSet) on FIELD_DEFINITION
+
+directive @join__type(graph: join__Graph!, key: join__FieldSet) repeatable on OBJECT | INTERFACE
+
+directive @join__owner(graph: join__Graph!) on OBJECT | INTERFACE
+
+directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+
+scalar join__FieldSet
+
+enum join__Graph {
+"#,
+        );
+        for (name, url, _) in subgraphs {
+            let _ = writeln!(
+                sdl,
+                "  {} @join__graph(name: {:?} url: {:?})",
+                graph_name(name),
+                name,
+                url.as_str()
+            );
+        }
+        sdl.push_str("}\n");
+
+        for (name, ty) in &self.types {
+            sdl.push('\n');
+            match ty {
+                Type::Object(object) => self.write_object(&mut sdl, name, object)?,
+                Type::Enum(values) => {
+                    let _ = writeln!(sdl, "enum {} {{", name);
+                    for value in values {
+                        let _ = writeln!(sdl, "  {}", value);
+                    }
+                    sdl.push_str("}\n");
+                }
+                Type::Union(members) => {
+                    let members = members.iter().cloned().collect::<Vec<_>>();
+                    let _ = writeln!(sdl, "union {} = {}", name, members.join(" | "));
+                }
+                Type::Scalar => {
+                    let _ = writeln!(sdl, "scalar {}", name);
+                }
+                Type::Input(fields) => {
+                    let _ = writeln!(sdl, "input {} {{", name);
+                    for field in fields.values() {
+                        let _ = writeln!(sdl, "  {}", field);
+                    }
+                    sdl.push_str("}\n");
+                }
+            }
+        }
+        Ok(sdl)
+    }
+
+    fn write_object(
+        &self,
+        sdl: &mut String,
+        name: &str,
+        object: &Object,
+    ) -> Result<(), CompositionError> {
+        let entity = !object.keys.is_empty();
+        let root = ROOT_TYPES.contains(&name);
+        let _ = write!(
+            sdl,
+            "{} {}",
+            if object.interface {
+                "interface"
+            } else {
+                "type"
+            },
+            name
+        );
+        if !object.implements.is_empty() {
+            let implements = object.implements.iter().cloned().collect::<Vec<_>>();
+            let _ = write!(sdl, " implements {}", implements.join(" & "));
+        }
+        if entity {
+            let owner = object
+                .owner
+                .as_ref()
+                .ok_or_else(|| CompositionError::MissingOwner(name.to_string()))?;
+            let _ = write!(sdl, "\n  @join__owner(graph: {})", owner);
+            for (graph, key) in &object.keys {
+                let _ = write!(sdl, "\n  @join__type(graph: {}, key: {:?})", graph, key);
+            }
+            sdl.push('\n');
+        } else {
+            sdl.push(' ');
+        }
+        sdl.push_str("{\n");
+        for (field_name, field) in &object.fields {
+            let _ = write!(sdl, "  {}{}: {}", field_name, field.arguments, field.ty);
+            if entity || root {
+                let _ = write!(sdl, " @join__field(graph: {}", field.graph);
+                if let Some(requires) = &field.requires {
+                    let _ = write!(sdl, ", requires: {:?}", requires);
+                }
+                if let Some(provides) = &field.provides {
+                    let _ = write!(sdl, ", provides: {:?}", provides);
+                }
+                sdl.push(')');
+            }
+            sdl.push('\n');
+        }
+        sdl.push_str("}\n");
+        Ok(())
+    }
+}
+
+fn name(name: Option<ast::Name>) -> String {
+    name.expect("the node Name is not optional in the spec; qed")
+        .text()
+        .to_string()
+}
+
+fn directives(directives: &Option<ast::Directives>, name: &str) -> Vec<ast::Directive> {
+    directives
+        .iter()
+        .flat_map(|directives| directives.directives())
+        .filter(|directive| {
+            directive
+                .name()
+                .and_then(|n| n.ident_token())
+                .as_ref()
+                .map(|id| id.text())
+                == Some(name)
+        })
+        .collect()
+}
+
+fn string_argument(directive: &ast::Directive, name: &str) -> Option<String> {
+    directive
+        .arguments()
+        .into_iter()
+        .flat_map(|arguments| arguments.arguments())
+        .find(|argument| {
+            argument
+                .name()
+                .and_then(|n| n.ident_token())
+                .as_ref()
+                .map(|id| id.text())
+                == Some(name)
+        })
+        .and_then(|argument| match argument.value() {
+            Some(ast::Value::StringValue(value)) => Some(value.into()),
+            _ => None,
+        })
+}
+
+/// An argument or input field definition, without its directives.
+fn input_value(definition: &ast::InputValueDefinition) -> String {
+    let mut value = format!(
+        "{}: {}",
+        name(definition.name()),
+        definition
+            .ty()
+            .map(|ty| ty.syntax().to_string())
+            .unwrap_or_default()
+            .trim()
+    );
+    if let Some(default) = definition.default_value() {
+        let _ = write!(value, " {}", default.syntax().to_string().trim());
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const ACCOUNTS: &str = r#"
+        type Query {
+          me: User
+        }
+
+        type User @key(fields: "id") {
+          id: ID!
+          name: String
+        }
+    "#;
+
+    const REVIEWS: &str = r#"
+        type Review @key(fields: "id") {
+          id: ID!
+          body: String
+          author: User @provides(fields: "name")
+        }
+
+        extend type User @key(fields: "id") {
+          id: ID! @external
+          name: String @external
+          reviews(first: Int = 5): [Review]
+        }
+    "#;
+
+    fn subgraphs(reviews: &str) -> Vec<(String, Url, String)> {
+        vec![
+            (
+                "accounts".to_string(),
+                Url::parse("http://localhost:4001/graphql").unwrap(),
+                ACCOUNTS.to_string(),
+            ),
+            (
+                "reviews".to_string(),
+                Url::parse("http://localhost:4002/graphql").unwrap(),
+                reviews.to_string(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn entities_are_joined_across_subgraphs() {
+        let supergraph = compose(&subgraphs(REVIEWS)).unwrap();
+
+        assert!(supergraph.contains(
+            r#"  ACCOUNTS @join__graph(name: "accounts" url: "http://localhost:4001/graphql")"#
+        ));
+        assert!(supergraph.contains(
+            "type User\n  @join__owner(graph: ACCOUNTS)\n  @join__type(graph: ACCOUNTS, key: \"id\")\n  @join__type(graph: REVIEWS, key: \"id\")\n{\n  id: ID! @join__field(graph: ACCOUNTS)\n  name: String @join__field(graph: ACCOUNTS)\n  reviews(first: Int = 5): [Review] @join__field(graph: REVIEWS)\n}"
+        ));
+        assert!(
+            supergraph.contains("  author: User @join__field(graph: REVIEWS, provides: \"name\")")
+        );
+        let schema: graphql::Schema = supergraph.parse().unwrap();
+        assert_eq!(schema.subgraphs().count(), 2);
+    }
+
+    async fn plan(subgraphs: &[(String, Url, String)], query: &str) -> graphql::QueryPlanStats {
+        let schema: graphql::Schema = compose(subgraphs).unwrap().parse().unwrap();
+        let planner = graphql::BridgeQueryPlanner::new(Arc::new(schema))
+            .await
+            .unwrap();
+        planner
+            .get(query.to_string(), None, Default::default())
+            .await
+            .unwrap()
+            .stats()
+    }
+
+    #[tokio::test]
+    async fn composed_supergraphs_can_be_planned() {
+        let subgraphs = subgraphs(REVIEWS);
+        assert_eq!(
+            plan(&subgraphs, "{ me { name } }").await,
+            graphql::QueryPlanStats {
+                fetch_count: 1,
+                depth: 1,
+                subgraph_count: 1,
+            }
+        );
+        // The author names are provided by the reviews subgraph.
+        assert_eq!(
+            plan(
+                &subgraphs,
+                "{ me { name reviews { body author { name } } } }"
+            )
+            .await,
+            graphql::QueryPlanStats {
+                fetch_count: 2,
+                depth: 2,
+                subgraph_count: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn required_fields_are_fetched_first() {
+        let mut subgraphs = subgraphs(REVIEWS);
+        subgraphs.push((
+            "greetings".to_string(),
+            Url::parse("http://localhost:4003/graphql").unwrap(),
+            r#"
+                extend type User @key(fields: "id") {
+                  id: ID! @external
+                  name: String @external
+                  greeting: String @requires(fields: "name")
+                }
+            "#
+            .to_string(),
+        ));
+        assert_eq!(
+            plan(&subgraphs, "{ me { greeting } }").await,
+            graphql::QueryPlanStats {
+                fetch_count: 2,
+                depth: 2,
+                subgraph_count: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn conflicting_definitions_are_rejected() {
+        assert!(matches!(
+            compose(&subgraphs("enum User { ADMIN }")),
+            Err(CompositionError::KindMismatch(name)) if name == "User"
+        ));
+        assert!(matches!(
+            compose(&subgraphs("extend type User @key(fields: \"id\") { id: ID! @external name: Int }")),
+            Err(CompositionError::FieldTypeMismatch(ty, field)) if ty == "User" && field == "name"
+        ));
+    }
+
+    #[test]
+    fn subgraph_paths_are_relative_to_the_configuration() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("subgraphs.yaml");
+        std::fs::write(
+            &path,
+            "subgraphs:\n  accounts:\n    routing_url: http://localhost:4001/graphql\n    schema:\n      file: ./accounts.graphql\n",
+        )
+        .unwrap();
+        std::fs::write(directory.path().join("accounts.graphql"), ACCOUNTS).unwrap();
+
+        let subgraphs = read_config(&path).unwrap();
+        assert_eq!(subgraphs[0].name, "accounts");
+        assert_eq!(
+            subgraphs[0].path,
+            directory.path().join("./accounts.graphql")
+        );
+        assert!(compose_files(&subgraphs).is_ok());
+    }
+}
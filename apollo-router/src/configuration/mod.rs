@@ -2,23 +2,34 @@
 
 mod yaml;
 
+use crate::correlation::{
+    default_correlation_id_formats, CorrelationIdExtractor, CorrelationIdFormat,
+};
 use crate::subscriber::is_global_subscriber_set;
-use apollo_router_core::plugins;
+use apollo_router_core::{
+    plugins, CacheStorageConfig, GrpcSubgraphConfig, IntrospectionAllowlist, LoadBalancing,
+    RestSubgraphConfig, SubgraphAuthConfig, SubgraphClientConfig, SubgraphRouting,
+    SubgraphTlsConfig,
+};
 use derivative::Derivative;
 use displaydoc::Display;
 use envmnt::{ExpandOptions, ExpansionType};
 use itertools::Itertools;
 use jsonschema::{Draft, JSONSchema};
+use regex::Regex;
 use schemars::gen::{SchemaGenerator, SchemaSettings};
 use schemars::schema::{ObjectValidation, RootSchema, Schema, SchemaObject};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fmt;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use thiserror::Error;
 use tower_http::cors::{Any, CorsLayer, Origin};
 use typed_builder::TypedBuilder;
@@ -87,11 +98,37 @@ fn default_listen() -> ListenAddr {
     SocketAddr::from_str("127.0.0.1:4000").unwrap().into()
 }
 
+fn default_graphql_path() -> String {
+    String::from("/graphql")
+}
+
+fn default_drain_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
 impl Configuration {
     pub fn boxed(self) -> Box<Self> {
         Box::new(self)
     }
 
+    /// Development preset: enables introspection and the Apollo Sandbox landing page, returns
+    /// query plans when asked to, and passes subgraph errors on to clients. Plugins already
+    /// configured keep their configuration.
+    pub fn dev(mut self) -> Self {
+        self.server.introspection = true;
+        self.server.landing_page = true;
+        self.server.landing_page_content = LandingPageContent::Sandbox;
+
+        let plugins = self.plugins.plugins.get_or_insert_with(Map::new);
+        plugins
+            .entry("experimental.expose_query_plan")
+            .or_insert_with(|| Value::Object(Map::new()));
+        plugins
+            .entry("experimental.include_subgraph_errors")
+            .or_insert_with(|| json!({ "all": true }));
+        self
+    }
+
     pub fn plugins(&self) -> Map<String, Value> {
         let mut plugins = Vec::default();
 
@@ -223,7 +260,8 @@ impl JsonSchema for UserPlugins {
 }
 
 /// Configuration options pertaining to the http server component.
-#[derive(Debug, Clone, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[derive(Derivative, Clone, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[derivative(Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Server {
     /// The socket address and port to listen on
@@ -232,22 +270,276 @@ pub struct Server {
     #[builder(default_code = "default_listen()", setter(into))]
     pub listen: ListenAddr,
 
+    /// TLS termination on the listener, so that clients connect with `https`.
+    /// Not supported on Unix sockets.
+    #[serde(default)]
+    #[builder(default)]
+    pub tls: Option<Tls>,
+
+    /// Path on which GraphQL requests are served, in addition to `/`.
+    /// Defaults to /graphql
+    #[serde(default = "default_graphql_path")]
+    #[builder(default_code = "default_graphql_path()", setter(into))]
+    #[schemars(regex(pattern = "^/"))]
+    pub graphql_path: String,
+
+    /// HTTP status of the responses whose first error has the given `code` extension, by code.
+    #[serde(default)]
+    #[builder(default)]
+    pub error_status_codes: HashMap<String, u16>,
+
     /// Cross origin request headers.
     #[serde(default)]
     #[builder(default)]
     pub cors: Option<Cors>,
 
+    /// Cross-site request forgery prevention.
+    #[serde(default)]
+    #[builder(default)]
+    pub csrf: Csrf,
+
     /// introspection queries
     /// enabled by default
     #[serde(default = "default_introspection")]
     #[builder(default_code = "default_introspection()", setter(into))]
     pub introspection: bool,
 
+    /// Clients allowed to introspect the schema while `introspection` is disabled, identified by
+    /// a claim of the token verified by the authentication plugin.
+    #[serde(default)]
+    #[builder(default)]
+    pub introspection_allowlist: Option<IntrospectionAllowlist>,
+
     /// display landing page
     /// enabled by default
     #[serde(default = "default_landing_page")]
     #[builder(default_code = "default_landing_page()", setter(into))]
     pub landing_page: bool,
+
+    /// What the landing page shows. Defaults to the Apollo Router landing page.
+    #[serde(default)]
+    #[builder(default)]
+    pub landing_page_content: LandingPageContent,
+
+    /// Path on which the supergraph SDL is served. Not served by default.
+    #[serde(default)]
+    #[builder(default)]
+    #[schemars(regex(pattern = "^/"))]
+    pub supergraph_sdl_path: Option<String>,
+
+    /// Listener serving the `/health`, `/ready` and `/live` endpoints. Not started by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub health: Option<HealthServer>,
+
+    /// Listener serving the admin endpoints, to inspect the router and empty its caches or reload
+    /// its schema. Not started by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub admin: Option<AdminServer>,
+
+    /// How long the in-flight requests are waited for on shutdown, once the server stops
+    /// accepting connections. Defaults to 30s.
+    #[serde(with = "humantime_serde", default = "default_drain_timeout")]
+    #[schemars(with = "String")]
+    #[builder(default_code = "default_drain_timeout()")]
+    pub drain_timeout: Duration,
+
+    /// Limits on the connections of clients, so that a few slow or malicious clients cannot
+    /// exhaust the router.
+    #[serde(default)]
+    #[builder(default)]
+    pub connections: Connections,
+
+    /// Maximum size, in bytes, of the body of a GraphQL request sent with POST.
+    /// Requests going over it are answered with a 413 status without reading the rest of the body.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_request_bytes: Option<usize>,
+
+    /// Maximum size, in bytes, of the variables of a GraphQL request sent with POST.
+    /// Requests going over it are rejected while their body is still being received.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_variables_bytes: Option<usize>,
+
+    /// Maximum size, in bytes, of a subgraph response body once decompressed.
+    /// Fetches going over it fail with an error instead of buffering the rest of the response.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_subgraph_response_bytes: Option<usize>,
+
+    /// Connection pooling of the clients sending requests to subgraphs.
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraph_client: SubgraphClientConfig,
+
+    /// TLS settings of the connections to subgraphs, by subgraph name.
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraph_tls: SubgraphTlsConfig,
+
+    /// Credentials of the router with subgraphs served over GraphQL, by subgraph name.
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraph_auth: SubgraphAuthConfig,
+
+    /// Subgraphs served by REST APIs, with the mapping of their fetches to their endpoints, by
+    /// subgraph name.
+    #[serde(default)]
+    #[builder(default)]
+    pub rest_subgraphs: HashMap<String, RestSubgraphConfig>,
+
+    /// Subgraphs reached over gRPC instead of HTTP, by subgraph name.
+    #[serde(default)]
+    #[builder(default)]
+    pub grpc_subgraphs: HashMap<String, GrpcSubgraphConfig>,
+
+    /// Endpoints sharing the requests of subgraphs served over HTTP, with their weights, by
+    /// subgraph name.
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraph_routing: HashMap<String, SubgraphRouting>,
+
+    /// Endpoints balancing the requests of subgraphs served over HTTP, with their health checks,
+    /// by subgraph name.
+    #[serde(default)]
+    #[builder(default)]
+    pub load_balancing: HashMap<String, LoadBalancing>,
+
+    /// Maximum number of queries using `@defer` or `@stream` in flight across the router.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_deferred_queries: Option<usize>,
+
+    /// Maximum number of queries using `@defer` or `@stream` in flight on a single connection.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_deferred_queries_per_connection: Option<usize>,
+
+    /// Send the router version in the `Server` header of every response.
+    /// Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub expose_version: bool,
+
+    /// Serialize GraphQL responses as they are sent, in chunks, instead of buffering each of them
+    /// whole before sending it. Responses are still complete before they are sent, as subgraph
+    /// responses are read whole. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub stream_responses: bool,
+
+    /// Compression of request and response bodies, with gzip, brotli or deflate.
+    #[serde(default)]
+    #[builder(default)]
+    pub compression: Compression,
+
+    /// File uploads with GraphQL multipart requests.
+    #[serde(default)]
+    #[builder(default)]
+    pub uploads: Uploads,
+
+    /// Batches of GraphQL requests sent in a single POST request.
+    #[serde(default)]
+    #[builder(default)]
+    pub batching: Batching,
+
+    /// Subscriptions sent on WebSockets with the `graphql-transport-ws` protocol.
+    #[serde(default)]
+    #[builder(default)]
+    pub subscriptions: Subscriptions,
+
+    /// Correlation ID formats looked for in the request headers, in order.
+    /// A UUID is generated when none of them is found.
+    #[serde(default = "default_correlation_id_formats")]
+    #[builder(default_code = "default_correlation_id_formats()")]
+    pub correlation_id: Vec<CorrelationIdFormat>,
+
+    /// Addresses of the proxies trusted to report the client IP in the `Forwarded` or
+    /// `X-Forwarded-For` header. Without any, the client IP is the address of the connection.
+    #[serde(default)]
+    #[builder(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// Headers identifying the client that sent a request, whose name and version are stored in
+    /// the request context and reported by the telemetry.
+    #[serde(default)]
+    #[builder(default)]
+    pub client_awareness: ClientAwareness,
+
+    /// Number of query plans kept in cache, the least recently used being evicted first.
+    /// Defaults to the `ROUTER_PLAN_CACHE_LIMIT` environment variable, or 100. Hits and misses
+    /// are exported as `cache_hits_total` and `cache_misses_total`, with `cache="query_plan"`.
+    #[serde(default)]
+    #[builder(default)]
+    pub query_plan_cache_limit: Option<usize>,
+
+    /// Storage where persisted queries and query plans are kept, in addition to the in-memory
+    /// caches. A Redis storage lets router instances use the queries registered and the plans
+    /// computed by one another.
+    #[serde(default)]
+    #[builder(default)]
+    pub cache_storage: Option<CacheStorageConfig>,
+
+    /// Operations planned by a new router before it takes traffic, on startup and on every
+    /// reload.
+    #[serde(default)]
+    #[builder(default)]
+    pub warm_up: WarmUp,
+
+    /// Check the data of every response against its query before sending it, replacing data
+    /// that does not match with an `INTERNAL_RESPONSE_INVALID` error. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub validate_final_response: bool,
+
+    /// Record the time spent by each plugin in each stage of the requests, exported as the
+    /// `plugin_duration_seconds` histogram. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub plugin_timings: bool,
+
+    /// Custom correlation ID extractor, tried before the configured formats.
+    #[serde(skip)]
+    #[schemars(skip)]
+    #[builder(default)]
+    #[derivative(Debug = "ignore")]
+    pub correlation_id_extractor: Option<CorrelationIdExtractor>,
+}
+
+impl Server {
+    /// The correlation ID extractors to use, in order.
+    pub(crate) fn correlation_id_extractors(&self) -> Vec<CorrelationIdExtractor> {
+        self.correlation_id_extractor
+            .into_iter()
+            .chain(
+                self.correlation_id
+                    .iter()
+                    .map(CorrelationIdFormat::extractor),
+            )
+            .collect()
+    }
+}
+
+/// Page served to browsers on the GraphQL endpoints.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum LandingPageContent {
+    /// The Apollo Router landing page.
+    Default,
+    /// Apollo Sandbox, querying the router.
+    Sandbox,
+    /// GraphiQL, querying the router.
+    Graphiql,
+    /// An HTML file, read when the server starts.
+    File(PathBuf),
+}
+
+impl Default for LandingPageContent {
+    fn default() -> Self {
+        LandingPageContent::Default
+    }
 }
 
 /// Listening address.
@@ -295,6 +587,80 @@ impl fmt::Display for ListenAddr {
     }
 }
 
+/// Listener of the health endpoints, separate from the GraphQL one so that it is not exposed to
+/// clients.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HealthServer {
+    /// The socket address and port to listen on.
+    /// Defaults to 127.0.0.1:8088
+    #[serde(default = "default_health_listen")]
+    #[builder(default_code = "default_health_listen()")]
+    pub listen: SocketAddr,
+
+    /// How often the health checks are run, the endpoints answering with the outcome of the
+    /// last run. Defaults to 10s.
+    #[serde(with = "humantime_serde", default = "default_health_check_interval")]
+    #[schemars(with = "String")]
+    #[builder(default_code = "default_health_check_interval()")]
+    pub check_interval: Duration,
+}
+
+fn default_health_listen() -> SocketAddr {
+    SocketAddr::from_str("127.0.0.1:8088").unwrap()
+}
+
+fn default_health_check_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Listener of the admin endpoints. Anyone reaching it can read the configuration and trigger
+/// reloads, so it should only be reachable by operators.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AdminServer {
+    /// The socket address and port to listen on.
+    /// Defaults to 127.0.0.1:8089
+    #[serde(default = "default_admin_listen")]
+    #[builder(default_code = "default_admin_listen()")]
+    pub listen: SocketAddr,
+
+    /// Serve the telemetry metrics at `/metrics` on this listener instead of the GraphQL one, so
+    /// that clients cannot scrape them. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub metrics: bool,
+}
+
+fn default_admin_listen() -> SocketAddr {
+    SocketAddr::from_str("127.0.0.1:8089").unwrap()
+}
+
+/// Certificates of the GraphQL listener, in PEM files read again whenever they change.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// Path of the certificate chain, starting with the certificate of the router.
+    pub certificate: PathBuf,
+    /// Path of the private key of the certificate.
+    pub key: PathBuf,
+    /// Path of the certificates of the authorities signing client certificates.
+    /// When set, clients must present a certificate signed by one of them.
+    #[serde(default)]
+    #[builder(default)]
+    pub client_ca: Option<PathBuf>,
+    /// How long clients have to complete the TLS handshake before their connection is closed.
+    /// Defaults to 10s.
+    #[serde(with = "humantime_serde", default = "default_tls_handshake_timeout")]
+    #[schemars(with = "String")]
+    #[builder(default_code = "default_tls_handshake_timeout()")]
+    pub handshake_timeout: Duration,
+}
+
+fn default_tls_handshake_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
 /// Cross origin request configuration.
 #[derive(Debug, Clone, Deserialize, Serialize, TypedBuilder, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -330,12 +696,323 @@ pub struct Cors {
     #[builder(default_code = "default_origins()")]
     pub origins: Vec<String>,
 
+    /// Regular expressions matching further origins to allow requests from, like
+    /// `https://.*\.example\.com`. They must match the whole origin.
+    #[serde(default, deserialize_with = "deserialize_origin_patterns")]
+    #[builder(default)]
+    pub match_origins: Vec<String>,
+
+    /// How long browsers may cache the result of a preflight request, like `1h`.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    #[builder(default)]
+    pub max_age: Option<Duration>,
+
     /// Allowed request methods. Defaults to GET, POST, OPTIONS.
     #[serde(default = "default_cors_methods")]
     #[builder(default_code = "default_cors_methods()")]
     pub methods: Vec<String>,
 }
 
+/// Cross-site request forgery prevention.
+///
+/// Browsers send some requests, like a GET without custom headers, without asking for permission
+/// with a CORS preflight first, so any site could make its visitors send them. Such requests are
+/// rejected unless they carry one of the required headers.
+#[derive(Debug, Clone, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Csrf {
+    /// Set to true to accept requests that did not go through a CORS preflight.
+    #[serde(default)]
+    #[builder(default)]
+    pub unsafe_disabled: bool,
+
+    /// Headers any of which lets a request through.
+    /// Defaults to `x-apollo-operation-name` and `apollo-require-preflight`
+    #[serde(default = "default_csrf_required_headers")]
+    #[builder(default_code = "default_csrf_required_headers()")]
+    pub required_headers: Vec<String>,
+}
+
+impl Default for Csrf {
+    fn default() -> Self {
+        Csrf::builder().build()
+    }
+}
+
+/// Compression of bodies, enabled separately for each direction.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Compression {
+    /// Decompress request bodies according to their `Content-Encoding`, which must be `gzip`,
+    /// `br` or `deflate`. Requests in other encodings, or in any encoding without it, are rejected
+    /// with `415 Unsupported Media Type`. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub requests: bool,
+
+    /// Maximum size, in bytes, of a request body once decompressed, enforced as it is read.
+    /// Defaults to 10485760.
+    #[serde(default = "default_max_decompressed_bytes")]
+    #[builder(default_code = "default_max_decompressed_bytes()")]
+    pub max_decompressed_bytes: usize,
+
+    /// Compress responses in an encoding of the `Accept-Encoding` of the request. Incremental
+    /// `@defer` responses may then be held back until enough of them is compressed.
+    /// Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub responses: bool,
+
+    /// Ask subgraphs for compressed responses with `Accept-Encoding`, decompressing them as they
+    /// are read. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraphs: bool,
+}
+
+fn default_max_decompressed_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::builder().build()
+    }
+}
+
+/// File uploads following the GraphQL multipart request specification.
+///
+/// Files are streamed through to the subgraphs whose requests use them, in the order they were
+/// sent, without being buffered by the router.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema,
+)]
+#[serde(deny_unknown_fields)]
+pub struct Uploads {
+    /// Accept `multipart/form-data` requests. They go through the same CSRF prevention as GET
+    /// requests. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub enabled: bool,
+
+    /// Maximum number of files in a request. Unlimited by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_files: Option<usize>,
+}
+
+/// Identification of the clients from the headers of their requests.
+///
+/// Plugins find the client name and version in the request context, under
+/// [`CLIENT_NAME_CONTEXT_KEY`](apollo_router_core::CLIENT_NAME_CONTEXT_KEY) and
+/// [`CLIENT_VERSION_CONTEXT_KEY`](apollo_router_core::CLIENT_VERSION_CONTEXT_KEY).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAwareness {
+    /// Header holding the client name. Defaults to `apollographql-client-name`.
+    #[serde(default = "default_client_name_header")]
+    #[builder(default_code = "default_client_name_header()")]
+    pub name_header: String,
+
+    /// Header holding the client version. Defaults to `apollographql-client-version`.
+    #[serde(default = "default_client_version_header")]
+    #[builder(default_code = "default_client_version_header()")]
+    pub version_header: String,
+}
+
+fn default_client_name_header() -> String {
+    "apollographql-client-name".into()
+}
+
+fn default_client_version_header() -> String {
+    "apollographql-client-version".into()
+}
+
+impl Default for ClientAwareness {
+    fn default() -> Self {
+        ClientAwareness::builder().build()
+    }
+}
+
+/// Client-side batching: a JSON array of GraphQL requests, answered by an array of their
+/// responses in the same order.
+///
+/// Each request of a batch goes through the whole pipeline, plugins included, with its index and
+/// the size of the batch in its context.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Batching {
+    /// Accept batches of requests. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub enabled: bool,
+
+    /// Maximum number of requests in a batch. Unlimited by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_size: Option<usize>,
+
+    /// Number of requests of a batch executed concurrently. Defaults to 1, one after the other.
+    #[serde(default = "default_batch_concurrency")]
+    #[builder(default_code = "default_batch_concurrency()")]
+    pub max_concurrency: usize,
+}
+
+fn default_batch_concurrency() -> usize {
+    1
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching::builder().build()
+    }
+}
+
+/// Subscriptions, sent on to the subgraph resolving their root field over a WebSocket.
+///
+/// The selection of a subscription must be resolvable by that subgraph alone. The events go
+/// through the `subscription_service` of the plugins before they are sent to the client.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Subscriptions {
+    /// Answer subscriptions. Disabled by default, WebSocket connections being closed right away.
+    #[serde(default)]
+    #[builder(default)]
+    pub enabled: bool,
+
+    /// WebSocket URLs of the subgraphs, by subgraph name. Defaults to the URL of the subgraph
+    /// with the `ws` or `wss` scheme.
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraphs: HashMap<String, String>,
+
+    /// Time clients and subgraphs are given to initialise a connection. Defaults to 10s.
+    #[serde(with = "humantime_serde", default = "default_connection_init_timeout")]
+    #[schemars(with = "String")]
+    #[builder(default_code = "default_connection_init_timeout()")]
+    pub connection_init_timeout: Duration,
+
+    /// Have subgraphs post the events of their subscriptions to the router instead, with the
+    /// HTTP callback protocol.
+    #[serde(default)]
+    #[builder(default)]
+    pub callback: Option<SubscriptionCallback>,
+}
+
+fn default_connection_init_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Listener of the calls of subgraphs posting the events of subscriptions.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionCallback {
+    /// The socket address and port to listen on.
+    /// Defaults to 127.0.0.1:4005
+    #[serde(default = "default_callback_listen")]
+    #[builder(default_code = "default_callback_listen()")]
+    pub listen: SocketAddr,
+
+    /// URL the subgraphs reach the listener at, followed by the id of each subscription.
+    pub public_url: String,
+
+    /// Names of the subgraphs subscribed to with a callback. Defaults to all of them.
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraphs: Vec<String>,
+
+    /// Interval of the heartbeats of subgraphs, the subscription ending when they miss three in
+    /// a row. Disabled when zero. Defaults to 5s.
+    #[serde(with = "humantime_serde", default = "default_heartbeat_interval")]
+    #[schemars(with = "String")]
+    #[builder(default_code = "default_heartbeat_interval()")]
+    pub heartbeat_interval: Duration,
+}
+
+fn default_callback_listen() -> SocketAddr {
+    SocketAddr::from_str("127.0.0.1:4005").unwrap()
+}
+
+fn default_heartbeat_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Subscriptions::builder().build()
+    }
+}
+
+/// Limits on the connections of clients. All of them are disabled by default.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema,
+)]
+#[serde(deny_unknown_fields)]
+pub struct Connections {
+    /// Maximum number of connections open at once. Connections accepted over it are closed
+    /// right away.
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    #[builder(default)]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of requests in flight on an HTTP/2 connection. HTTP/1 connections answer
+    /// one request at a time.
+    #[serde(default)]
+    #[builder(default)]
+    pub max_requests_per_connection: Option<u32>,
+
+    /// Time a client is given to send the head of an HTTP/1 request, from its first byte, like
+    /// `10s`. The connection is closed when it runs out.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    #[builder(default)]
+    pub header_read_timeout: Option<Duration>,
+
+    /// Time after which a connection without any request in flight nor any traffic is closed,
+    /// like `60s`.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    #[builder(default)]
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Warm-up of the query plan cache of a new router.
+///
+/// The operations are planned again against the new schema, and those that are not valid
+/// anymore are skipped.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, TypedBuilder, JsonSchema,
+)]
+#[serde(deny_unknown_fields)]
+pub struct WarmUp {
+    /// Number of operations planned again among those the previous router planned most
+    /// recently. Defaults to 0.
+    #[serde(default)]
+    #[builder(default)]
+    pub recent_operations: usize,
+
+    /// Persisted query manifest, a JSON object mapping operation ids to their documents, whose
+    /// operations are all planned. None by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub manifest: Option<PathBuf>,
+
+    /// Also execute the introspection queries among the operations, so that their responses are
+    /// cached. Disabled by default.
+    #[serde(default)]
+    #[builder(default)]
+    pub introspection: bool,
+}
+
+fn default_csrf_required_headers() -> Vec<String> {
+    vec![
+        "x-apollo-operation-name".into(),
+        "apollo-require-preflight".into(),
+    ]
+}
+
 fn default_origins() -> Vec<String> {
     vec!["https://studio.apollographql.com".into()]
 }
@@ -352,6 +1029,28 @@ fn default_cors_methods() -> Vec<String> {
     vec!["GET".into(), "POST".into(), "OPTIONS".into()]
 }
 
+/// Rejects the `match_origins` that are not valid regular expressions, instead of ignoring them
+/// once the server is started.
+fn deserialize_origin_patterns<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    for pattern in &patterns {
+        origin_regex(pattern).map_err(|err| {
+            serde::de::Error::custom(format!(
+                "origin pattern '{}' is not valid: {}",
+                pattern, err
+            ))
+        })?;
+    }
+    Ok(patterns)
+}
+
+fn origin_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
 fn default_introspection() -> bool {
     true
 }
@@ -392,9 +1091,14 @@ impl Cors {
                         .ok()
                 }));
 
+        let cors = match self.max_age {
+            Some(max_age) => cors.max_age(max_age),
+            None => cors,
+        };
+
         if self.allow_any_origin.unwrap_or_default() {
             cors.allow_origin(Any)
-        } else {
+        } else if self.match_origins.is_empty() {
             cors.allow_origin(Origin::list(self.origins.into_iter().filter_map(
                 |origin| {
                     origin
@@ -403,6 +1107,26 @@ impl Cors {
                         .ok()
                 },
             )))
+        } else {
+            let match_origins: Vec<Regex> = self
+                .match_origins
+                .iter()
+                .filter_map(|pattern| {
+                    origin_regex(pattern)
+                        .map_err(|_| tracing::error!("origin pattern '{pattern}' is not valid"))
+                        .ok()
+                })
+                .collect();
+            let origins = self.origins;
+            cors.allow_origin(Origin::predicate(move |origin, _| {
+                origin
+                    .to_str()
+                    .map(|origin| {
+                        origins.iter().any(|allowed| allowed == origin)
+                            || match_origins.iter().any(|regex| regex.is_match(origin))
+                    })
+                    .unwrap_or_default()
+            }))
         }
     }
 }
@@ -636,6 +1360,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dev_preset() {
+        let configuration = serde_yaml::from_str::<Configuration>(
+            r#"
+server:
+  introspection: false
+plugins:
+  experimental.include_subgraph_errors:
+    subgraphs:
+      accounts: true
+"#,
+        )
+        .unwrap()
+        .dev();
+
+        assert!(configuration.server.introspection);
+        assert!(matches!(
+            configuration.server.landing_page_content,
+            LandingPageContent::Sandbox
+        ));
+        let plugins = configuration.plugins();
+        assert!(plugins.contains_key("experimental.expose_query_plan"));
+        assert_eq!(
+            plugins["experimental.include_subgraph_errors"],
+            json!({ "subgraphs": { "accounts": true } })
+        );
+    }
+
     #[test]
     fn cors_defaults() {
         let cors = Cors::builder().build();
@@ -650,6 +1402,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn invalid_origin_patterns_are_rejected() {
+        let error = Configuration::from_str(
+            r#"
+server:
+  cors:
+    match_origins:
+      - "https://[a-z.example.com"
+        "#,
+        )
+        .expect_err("should have resulted in an error");
+        assert!(error
+            .to_string()
+            .contains("origin pattern 'https://[a-z.example.com' is not valid"));
+    }
+
     #[test]
     fn line_precise_config_errors() {
         let error = validate_configuration(
@@ -666,6 +1434,19 @@ telemetry:
         insta::assert_snapshot!(error.to_string());
     }
 
+    #[test]
+    fn connections_must_be_allowed() {
+        let error = validate_configuration(
+            r#"
+server:
+  connections:
+    max_connections: 0
+        "#,
+        )
+        .expect_err("should have resulted in an error");
+        assert!(error.to_string().contains("max_connections"));
+    }
+
     #[test]
     fn validate_project_config_files() {
         let filename_matcher = Regex::from_str("((.+[.])?router\\.yaml)|(.+\\.mdx)").unwrap();
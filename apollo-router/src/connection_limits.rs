@@ -0,0 +1,474 @@
+//! Limits on the connections of clients.
+//!
+//! Connections accepted while `max_connections` are open are closed right away. An accepted
+//! connection is watched for the time it spends without a request in flight, a request being in
+//! flight until the whole body of its response is sent: it is closed gracefully once it stays
+//! silent for the idle timeout, and dropped when a client starts sending an HTTP/1 request but
+//! takes longer than the header read timeout to get its head to the router. HTTP/2 clients send
+//! frames of their own between requests, so their heads are not timed.
+//!
+//! The connections open are counted, and their count reported to the recorder set with
+//! [`set_connections_recorder`].
+
+use crate::configuration::Server;
+use futures::future::BoxFuture;
+use http::{HeaderMap, Response};
+use hyper::body::{HttpBody, SizeHint};
+use once_cell::sync::Lazy;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tower::Service;
+
+/// What HTTP/2 clients send first on a connection.
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Records the changes in the number of open connections.
+pub(crate) type ConnectionsRecorder = Arc<dyn Fn(i64) + Send + Sync>;
+
+/// Connections of clients open, across reloads.
+static OPEN_CONNECTIONS: AtomicI64 = AtomicI64::new(0);
+
+static RECORDER: Lazy<RwLock<Option<ConnectionsRecorder>>> = Lazy::new(Default::default);
+
+/// Sets where the changes in the number of open connections are recorded, replacing the previous
+/// recorder. The connections open already are recorded right away.
+pub(crate) fn set_connections_recorder(recorder: ConnectionsRecorder) {
+    let mut current = RECORDER.write().expect("lock poisoned");
+    recorder(OPEN_CONNECTIONS.load(Ordering::SeqCst));
+    *current = Some(recorder);
+}
+
+fn record_connections(change: i64) {
+    // Holding the lock so that a new recorder does not miss or count twice this change.
+    let recorder = RECORDER.read().expect("lock poisoned");
+    OPEN_CONNECTIONS.fetch_add(change, Ordering::SeqCst);
+    if let Some(recorder) = recorder.as_ref() {
+        recorder(change);
+    }
+}
+
+/// Router wide limits, from which each connection gets its own.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionLimits {
+    open: Option<Arc<Semaphore>>,
+    max_requests_per_connection: Option<u32>,
+    header_read_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+impl ConnectionLimits {
+    pub(crate) fn from_server(server: &Server) -> Self {
+        let connections = &server.connections;
+        Self {
+            open: connections
+                .max_connections
+                .map(|limit| Arc::new(Semaphore::new(limit))),
+            max_requests_per_connection: connections.max_requests_per_connection,
+            header_read_timeout: connections.header_read_timeout,
+            idle_timeout: connections.idle_timeout,
+        }
+    }
+
+    /// Admits a newly accepted connection, or returns `None` if too many are open already.
+    pub(crate) fn admit(&self) -> Option<ConnectionGuard> {
+        let permit = match &self.open {
+            Some(open) => Some(open.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        record_connections(1);
+        Some(ConnectionGuard {
+            _permit: permit,
+            activity: Default::default(),
+            header_read_timeout: self.header_read_timeout,
+            idle_timeout: self.idle_timeout,
+        })
+    }
+
+    /// Maximum number of requests in flight on an HTTP/2 connection.
+    pub(crate) fn max_requests_per_connection(&self) -> Option<u32> {
+        self.max_requests_per_connection
+    }
+}
+
+/// An open connection, counted against the router wide limit until it is dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    _permit: Option<OwnedSemaphorePermit>,
+    activity: Arc<Mutex<Activity>>,
+    header_read_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        record_connections(-1);
+    }
+}
+
+/// Why a connection should be closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Expiry {
+    /// Nothing happened on the connection for the idle timeout.
+    Idle,
+    /// The client took too long to send the head of a request.
+    HeaderRead,
+}
+
+#[derive(Debug)]
+struct Activity {
+    in_flight: usize,
+    /// Last time something was read or written while no request was in flight.
+    idle_since: Instant,
+    /// When the client started sending a request the router did not get yet.
+    reading_since: Option<Instant>,
+    /// Whether the connection speaks HTTP/2, known once the client sent something.
+    http2: Option<bool>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            in_flight: 0,
+            idle_since: Instant::now(),
+            reading_since: None,
+            http2: None,
+        }
+    }
+}
+
+impl ConnectionGuard {
+    /// Wraps the stream of the connection, to watch what the client sends.
+    pub(crate) fn stream<S>(&self, stream: S) -> WatchedStream<S> {
+        WatchedStream {
+            inner: stream,
+            activity: self.activity.clone(),
+        }
+    }
+
+    /// Wraps the service answering the requests of the connection, to know when some are in flight.
+    pub(crate) fn service<S>(&self, service: S) -> WatchedService<S> {
+        WatchedService {
+            inner: service,
+            activity: self.activity.clone(),
+        }
+    }
+
+    /// Resolves once the connection should be closed. Never resolves without timeouts.
+    pub(crate) async fn expired(&self) -> Expiry {
+        let check_interval = match (self.header_read_timeout, self.idle_timeout) {
+            (None, None) => return futures::future::pending().await,
+            (Some(header_read), Some(idle)) => header_read.min(idle),
+            (Some(timeout), None) | (None, Some(timeout)) => timeout,
+        };
+
+        loop {
+            let now = Instant::now();
+            let deadline = {
+                let activity = self.activity.lock().expect("lock poisoned");
+                if activity.in_flight > 0 {
+                    None
+                } else {
+                    let header_read = activity
+                        .reading_since
+                        .zip(self.header_read_timeout)
+                        .map(|(since, timeout)| (since + timeout, Expiry::HeaderRead));
+                    let idle = self
+                        .idle_timeout
+                        .map(|timeout| (activity.idle_since + timeout, Expiry::Idle));
+                    header_read
+                        .into_iter()
+                        .chain(idle)
+                        .min_by_key(|(at, _)| *at)
+                }
+            };
+            match deadline {
+                Some((at, expiry)) if at <= now => return expiry,
+                Some((at, _)) => tokio::time::sleep_until(at).await,
+                // Requests in flight are not timed, the connection is checked again later.
+                None => tokio::time::sleep(check_interval).await,
+            }
+        }
+    }
+}
+
+/// The stream of a connection, recording when the client sends something.
+pub(crate) struct WatchedStream<S> {
+    inner: S,
+    activity: Arc<Mutex<Activity>>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WatchedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            let mut activity = this.activity.lock().expect("lock poisoned");
+            if activity.http2.is_none() {
+                let read = &buf.filled()[filled..];
+                let compared = read.len().min(HTTP2_PREFACE.len());
+                activity.http2 = Some(read[..compared] == HTTP2_PREFACE[..compared]);
+            }
+            if activity.in_flight == 0 {
+                let now = Instant::now();
+                activity.idle_since = now;
+                if activity.http2 == Some(false) {
+                    activity.reading_since.get_or_insert(now);
+                }
+            }
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WatchedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            if written > 0 {
+                this.activity.lock().expect("lock poisoned").idle_since = Instant::now();
+            }
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// The service of a connection, recording the requests in flight.
+#[derive(Clone)]
+pub(crate) struct WatchedService<S> {
+    inner: S,
+    activity: Arc<Mutex<Activity>>,
+}
+
+impl<S, Request, B> Service<Request> for WatchedService<S>
+where
+    S: Service<Request, Response = Response<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<WatchedBody<B>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let in_flight = InFlight::new(self.activity.clone());
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(response.map(|body| WatchedBody {
+                inner: body,
+                _in_flight: in_flight,
+            }))
+        })
+    }
+}
+
+/// The body of a response, keeping its request in flight until it is sent or dropped, so that
+/// streamed responses are not cut by the idle timeout.
+pub(crate) struct WatchedBody<B> {
+    inner: B,
+    _in_flight: InFlight,
+}
+
+impl<B: HttpBody + Unpin> HttpBody for WatchedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A request in flight on a connection, until its response is sent or the client goes away.
+struct InFlight(Arc<Mutex<Activity>>);
+
+impl InFlight {
+    fn new(activity: Arc<Mutex<Activity>>) -> Self {
+        {
+            let mut activity = activity.lock().expect("lock poisoned");
+            activity.in_flight += 1;
+            activity.reading_since = None;
+        }
+        Self(activity)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut activity = self.0.lock().expect("lock poisoned");
+        activity.in_flight -= 1;
+        activity.idle_since = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Connections;
+    use hyper::Body;
+    use tokio::io::AsyncReadExt;
+    use tower::ServiceExt;
+
+    fn limits(connections: Connections) -> ConnectionLimits {
+        ConnectionLimits::from_server(&Server::builder().connections(connections).build())
+    }
+
+    #[test]
+    fn connections_over_the_limit_are_refused() {
+        let limits = limits(Connections::builder().max_connections(Some(1)).build());
+        let first = limits.admit();
+        assert!(first.is_some());
+        assert!(limits.admit().is_none());
+        drop(first);
+        assert!(limits.admit().is_some());
+    }
+
+    #[tokio::test]
+    async fn silent_connections_expire() {
+        let limits = limits(
+            Connections::builder()
+                .idle_timeout(Some(Duration::from_millis(50)))
+                .build(),
+        );
+        let connection = limits.admit().unwrap();
+        let start = Instant::now();
+        assert_eq!(connection.expired().await, Expiry::Idle);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn slow_request_heads_expire() {
+        let limits = limits(
+            Connections::builder()
+                .header_read_timeout(Some(Duration::from_millis(50)))
+                .idle_timeout(Some(Duration::from_secs(60)))
+                .build(),
+        );
+        let connection = limits.admit().unwrap();
+        let mut stream = connection.stream(&b"GET / HTTP/1.1\r\n"[..]);
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        let start = Instant::now();
+        assert_eq!(connection.expired().await, Expiry::HeaderRead);
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn requests_in_flight_are_not_timed() {
+        let limits = limits(
+            Connections::builder()
+                .idle_timeout(Some(Duration::from_millis(50)))
+                .build(),
+        );
+        let connection = limits.admit().unwrap();
+        let service = connection.service(tower::service_fn(|()| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, ()>(Response::new(Body::empty()))
+        }));
+
+        // The request is sent before the connection is watched, and the idle timeout only starts
+        // once it is answered.
+        let start = Instant::now();
+        // The response is dropped right away, its body being in flight until then.
+        let (answered, expiry) = tokio::join!(
+            async { service.oneshot(()).await.is_ok() },
+            connection.expired()
+        );
+        assert!(answered);
+        assert_eq!(expiry, Expiry::Idle);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn streamed_responses_are_not_timed() {
+        let limits = limits(
+            Connections::builder()
+                .idle_timeout(Some(Duration::from_millis(50)))
+                .build(),
+        );
+        let connection = limits.admit().unwrap();
+        let service = connection.service(tower::service_fn(|()| async {
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let _ = sender.send_data("done".into()).await;
+            });
+            Ok::<_, ()>(Response::new(body))
+        }));
+
+        // The idle timeout only starts once the whole body is sent.
+        let start = Instant::now();
+        let (body, expiry) = tokio::join!(
+            async {
+                let response = service.oneshot(()).await.unwrap();
+                hyper::body::to_bytes(response.into_body()).await.unwrap()
+            },
+            connection.expired()
+        );
+        assert_eq!(body, "done");
+        assert_eq!(expiry, Expiry::Idle);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn http2_connections_are_not_timed_while_reading() {
+        let limits = limits(
+            Connections::builder()
+                .header_read_timeout(Some(Duration::from_millis(50)))
+                .idle_timeout(Some(Duration::from_millis(200)))
+                .build(),
+        );
+        let connection = limits.admit().unwrap();
+        let mut stream = connection.stream(HTTP2_PREFACE);
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        assert_eq!(connection.expired().await, Expiry::Idle);
+    }
+}
@@ -0,0 +1,179 @@
+//! Correlation ID extraction.
+//!
+//! Every request handled by the router gets a correlation ID. It is taken from the first
+//! configured extractor that recognizes the incoming headers, or generated when none does.
+//! The ID is recorded on the request span and made available to the rest of the pipeline as a
+//! [`CorrelationId`] request extension, and in the request context under
+//! [`apollo_router_core::CORRELATION_ID_CONTEXT_KEY`].
+
+use http::HeaderMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Extracts a correlation ID from the headers of an incoming request.
+pub type CorrelationIdExtractor = fn(&HeaderMap) -> Option<String>;
+
+/// The correlation ID of a request, stored in its extensions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorrelationId(pub String);
+
+/// Built-in correlation ID formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CorrelationIdFormat {
+    AmazonTraceId,
+    CloudTraceContext,
+    Traceparent,
+}
+
+impl CorrelationIdFormat {
+    pub fn extractor(&self) -> CorrelationIdExtractor {
+        match self {
+            CorrelationIdFormat::AmazonTraceId => amazon_trace_id,
+            CorrelationIdFormat::CloudTraceContext => cloud_trace_context,
+            CorrelationIdFormat::Traceparent => traceparent,
+        }
+    }
+}
+
+pub(crate) fn default_correlation_id_formats() -> Vec<CorrelationIdFormat> {
+    vec![
+        CorrelationIdFormat::Traceparent,
+        CorrelationIdFormat::AmazonTraceId,
+        CorrelationIdFormat::CloudTraceContext,
+    ]
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// The `Root` of an AWS X-Ray `x-amzn-trace-id` header.
+pub fn amazon_trace_id(headers: &HeaderMap) -> Option<String> {
+    header(headers, "x-amzn-trace-id")?
+        .split(';')
+        .find_map(|field| field.trim().strip_prefix("Root="))
+        .filter(|root| !root.is_empty())
+        .map(ToString::to_string)
+}
+
+/// The trace ID of a Google Cloud `x-cloud-trace-context` header.
+pub fn cloud_trace_context(headers: &HeaderMap) -> Option<String> {
+    header(headers, "x-cloud-trace-context")?
+        .split(|c| c == '/' || c == ';')
+        .next()
+        .filter(|trace_id| !trace_id.is_empty() && trace_id.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(ToString::to_string)
+}
+
+/// The trace ID of a W3C trace context `traceparent` header.
+pub fn traceparent(headers: &HeaderMap) -> Option<String> {
+    let mut fields = header(headers, "traceparent")?.split('-');
+    let _version = fields.next()?;
+    fields
+        .next()
+        .filter(|trace_id| {
+            trace_id.len() == 32
+                && trace_id.chars().all(|c| c.is_ascii_hexdigit())
+                && trace_id.chars().any(|c| c != '0')
+        })
+        .map(ToString::to_string)
+}
+
+/// Returns the ID found by the first matching extractor, or a new UUID.
+pub(crate) fn correlation_id(headers: &HeaderMap, extractors: &[CorrelationIdExtractor]) -> String {
+    extractors
+        .iter()
+        .find_map(|extractor| extractor(headers))
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn header_map(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn extracts_amazon_trace_id() {
+        let headers = header_map(
+            "x-amzn-trace-id",
+            "Self=1-67891234-12456789abcdef012345678;Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1",
+        );
+        assert_eq!(
+            amazon_trace_id(&headers).as_deref(),
+            Some("1-5759e988-bd862e3fe1be46a994272793")
+        );
+        assert_eq!(amazon_trace_id(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn extracts_cloud_trace_context() {
+        let headers = header_map(
+            "x-cloud-trace-context",
+            "105445aa7843bc8bf206b12000100000/1;o=1",
+        );
+        assert_eq!(
+            cloud_trace_context(&headers).as_deref(),
+            Some("105445aa7843bc8bf206b12000100000")
+        );
+        let headers = header_map("x-cloud-trace-context", "not a trace");
+        assert_eq!(cloud_trace_context(&headers), None);
+    }
+
+    #[test]
+    fn extracts_traceparent() {
+        let headers = header_map(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        );
+        assert_eq!(
+            traceparent(&headers).as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert_eq!(
+            traceparent(&header_map(
+                "traceparent",
+                "00-00000000000000000000000000000000-b7ad6b7169203331-01"
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn first_matching_extractor_wins() {
+        let mut headers = header_map(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        );
+        headers.insert(
+            "x-amzn-trace-id",
+            HeaderValue::from_static("Root=1-5759e988-bd862e3fe1be46a994272793"),
+        );
+        let extractors = [amazon_trace_id as CorrelationIdExtractor, traceparent];
+        assert_eq!(
+            correlation_id(&headers, &extractors),
+            "1-5759e988-bd862e3fe1be46a994272793"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_a_uuid() {
+        let extractors: Vec<_> = default_correlation_id_formats()
+            .iter()
+            .map(CorrelationIdFormat::extractor)
+            .collect();
+        let id = correlation_id(&header_map("a", "correlation"), &extractors);
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+        assert_ne!(id, correlation_id(&HeaderMap::new(), &extractors));
+    }
+}
@@ -0,0 +1,244 @@
+//! Limits on the number of deferred queries in flight.
+//!
+//! A query using `@defer` or `@stream` holds a slot on its connection, and one shared by the whole
+//! router, until the body of its response has been sent. Slots are also given back when the client
+//! disconnects, as hyper then drops the future answering the request or the body being sent.
+
+use crate::configuration::Server;
+use apollo_router_core::prelude::*;
+use axum::body::{boxed, BoxBody, Bytes};
+use axum::response::{IntoResponse, Json, Response};
+use displaydoc::Display;
+use http::{HeaderMap, StatusCode};
+use hyper::body::{HttpBody, SizeHint};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Router wide limits, from which the slots of each connection are derived.
+#[derive(Clone, Debug)]
+pub(crate) struct DeferredLimits {
+    global: Option<(usize, Arc<Semaphore>)>,
+    per_connection: Option<usize>,
+}
+
+impl DeferredLimits {
+    pub(crate) fn new(global: Option<usize>, per_connection: Option<usize>) -> Self {
+        Self {
+            global: global.map(|limit| (limit, Arc::new(Semaphore::new(limit)))),
+            per_connection,
+        }
+    }
+
+    pub(crate) fn from_server(server: &Server) -> Self {
+        Self::new(
+            server.max_deferred_queries,
+            server.max_deferred_queries_per_connection,
+        )
+    }
+
+    /// Slots available to a newly accepted connection.
+    pub(crate) fn connection(&self) -> ConnectionSlots {
+        ConnectionSlots {
+            global: self.global.clone(),
+            connection: self
+                .per_connection
+                .map(|limit| (limit, Arc::new(Semaphore::new(limit)))),
+        }
+    }
+}
+
+/// Deferred query slots of a connection, stored in the extensions of its requests.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionSlots {
+    global: Option<(usize, Arc<Semaphore>)>,
+    connection: Option<(usize, Arc<Semaphore>)>,
+}
+
+impl ConnectionSlots {
+    /// Takes a slot for a deferred query, released when the returned guard is dropped.
+    pub(crate) fn try_acquire(&self) -> Result<DeferredSlot, TooManyDeferredQueries> {
+        let connection = acquire(&self.connection, TooManyDeferredQueries::Connection)?;
+        let global = acquire(&self.global, TooManyDeferredQueries::Global)?;
+        Ok(DeferredSlot {
+            _connection: connection,
+            _global: global,
+        })
+    }
+}
+
+fn acquire(
+    slots: &Option<(usize, Arc<Semaphore>)>,
+    error: fn(usize) -> TooManyDeferredQueries,
+) -> Result<Option<OwnedSemaphorePermit>, TooManyDeferredQueries> {
+    slots
+        .as_ref()
+        .map(|(limit, semaphore)| {
+            semaphore
+                .clone()
+                .try_acquire_owned()
+                .map_err(|_| error(*limit))
+        })
+        .transpose()
+}
+
+/// A deferred query in flight.
+pub(crate) struct DeferredSlot {
+    _connection: Option<OwnedSemaphorePermit>,
+    _global: Option<OwnedSemaphorePermit>,
+}
+
+/// Keeps `slot` in the body of `response`, to be given back once the body has been sent.
+pub(crate) fn hold_until_sent(response: Response, slot: DeferredSlot) -> Response {
+    response.map(|body| boxed(SlotBody { body, _slot: slot }))
+}
+
+/// A response body holding the slot of its query.
+struct SlotBody {
+    body: BoxBody,
+    _slot: DeferredSlot,
+}
+
+impl HttpBody for SlotBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.body).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.body).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Error returned when a deferred query goes over a limit.
+#[derive(Debug, Error, Display, PartialEq)]
+pub(crate) enum TooManyDeferredQueries {
+    /// too many deferred queries in flight on this connection (limit: {0})
+    Connection(usize),
+
+    /// too many deferred queries in flight (limit: {0})
+    Global(usize),
+}
+
+impl IntoResponse for TooManyDeferredQueries {
+    fn into_response(self) -> Response {
+        let response = graphql::Response::builder()
+            .errors(vec![graphql::Error {
+                message: self.to_string(),
+                ..Default::default()
+            }])
+            .build();
+        (StatusCode::TOO_MANY_REQUESTS, Json(response)).into_response()
+    }
+}
+
+/// Whether the query uses the `@defer` or `@stream` directive.
+pub(crate) fn is_deferred(query: &str) -> bool {
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' || c == '\r' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '@' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                if name == "defer" || name == "stream" {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deferred_queries_are_detected() {
+        assert!(is_deferred("{ me { id ... @defer { name } } }"));
+        assert!(is_deferred("{ reviews @stream(initialCount: 1) { id } }"));
+        assert!(!is_deferred("{ me { id deferred } }"));
+        assert!(!is_deferred("{ me(name: \"@defer\") { id @deferred } }"));
+        assert!(!is_deferred("# @defer\n{ me { id } }"));
+    }
+
+    #[test]
+    fn excess_deferred_queries_are_rejected_until_slots_are_freed() {
+        let limits = DeferredLimits::new(Some(3), Some(2));
+        let connection = limits.connection();
+
+        let first = connection.try_acquire().unwrap();
+        let _second = connection.try_acquire().unwrap();
+        assert_eq!(
+            connection.try_acquire().err(),
+            Some(TooManyDeferredQueries::Connection(2))
+        );
+
+        // The other connection has its own slots, but shares the router wide ones.
+        let other = limits.connection();
+        let _third = other.try_acquire().unwrap();
+        assert_eq!(
+            other.try_acquire().err(),
+            Some(TooManyDeferredQueries::Global(3))
+        );
+
+        drop(first);
+        let _fourth = connection.try_acquire().unwrap();
+        assert_eq!(
+            connection.try_acquire().err(),
+            Some(TooManyDeferredQueries::Connection(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn slots_are_held_until_the_body_is_sent() {
+        let connection = DeferredLimits::new(None, Some(1)).connection();
+        let slot = connection.try_acquire().unwrap();
+
+        let response = hold_until_sent("deferred".into_response(), slot);
+        assert!(connection.try_acquire().is_err());
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "deferred");
+        assert!(connection.try_acquire().is_ok());
+    }
+}
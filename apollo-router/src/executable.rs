@@ -2,15 +2,17 @@
 
 use crate::configuration::generate_config_schema;
 use crate::{
+    composition,
     configuration::Configuration,
     subscriber::{set_global_subscriber, RouterSubscriber},
     ApolloRouterBuilder, ConfigurationKind, SchemaKind, ShutdownKind,
 };
 use anyhow::{anyhow, Context, Result};
-use clap::{AppSettings, CommandFactory, Parser};
+use clap::{AppSettings, CommandFactory, Parser, Subcommand};
 use directories::ProjectDirs;
 use once_cell::sync::OnceCell;
 use std::ffi::OsStr;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 use std::{env, fmt};
@@ -37,6 +39,11 @@ pub struct Opt {
     #[clap(alias = "hr", long = "hot-reload", env = "ROUTER_HOT_RELOAD")]
     hot_reload: bool,
 
+    /// Development mode: enables introspection, the Apollo Sandbox landing page, query plans,
+    /// subgraph errors and hot reload. Not for production.
+    #[clap(long, env = "ROUTER_DEV")]
+    dev: bool,
+
     /// Configuration location relative to the project directory.
     #[clap(short, long = "config", parse(from_os_str), env)]
     configuration_path: Option<PathBuf>,
@@ -45,8 +52,21 @@ pub struct Opt {
     #[clap(short, long = "supergraph", parse(from_os_str), env)]
     supergraph_path: Option<PathBuf>,
 
+    /// Location of a subgraphs configuration, in the `rover supergraph compose` format, whose
+    /// subgraph schemas are composed into the supergraph. For development only.
+    #[clap(long = "subgraphs", parse(from_os_str), env)]
+    subgraphs_path: Option<PathBuf>,
+
+    /// URL of a supergraph schema, polled for changes every `--apollo-schema-poll-interval`.
+    #[clap(long, env)]
+    supergraph_url: Option<Url>,
+
+    /// Address to listen on, over the one of the configuration.
+    #[clap(long, env = "ROUTER_LISTEN")]
+    listen: Option<SocketAddr>,
+
     /// Prints the configuration schema.
-    #[clap(long)]
+    #[clap(long, hide = true)]
     schema: bool,
 
     /// Your Apollo key
@@ -61,13 +81,36 @@ pub struct Opt {
     #[clap(long, env)]
     apollo_schema_config_delivery_endpoint: Option<Url>,
 
-    /// The time between polls to Apollo uplink. Minimum 10s.
+    /// The time between polls to Apollo uplink or the supergraph URL. Minimum 10s.
     #[clap(long, default_value = "10s", parse(try_from_str = humantime::parse_duration), env)]
     apollo_schema_poll_interval: Duration,
 
     /// Display version and exit
     #[clap(parse(from_flag), long, short = 'V')]
     pub version: bool,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Configuration file utilities.
+    #[clap(subcommand)]
+    Config(ConfigCommands),
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Prints the JSON schema of the configuration file.
+    Schema,
+
+    /// Validates a configuration file against the configuration schema.
+    Validate {
+        /// The configuration file to validate.
+        #[clap(parse(from_os_str))]
+        path: PathBuf,
+    },
 }
 
 /// Wrapper so that structop can display the default config path in the help message.
@@ -143,10 +186,23 @@ pub async fn rt_main() -> Result<()> {
 
     copy_args_to_env();
 
-    if opt.schema {
-        let schema = generate_config_schema();
-        println!("{}", serde_json::to_string_pretty(&schema)?);
-        return Ok(());
+    match &opt.command {
+        Some(Commands::Config(ConfigCommands::Validate { path })) => {
+            ConfigurationKind::read_config(path)?;
+            println!("{} is valid", path.to_string_lossy());
+            return Ok(());
+        }
+        Some(Commands::Config(ConfigCommands::Schema)) => {
+            let schema = generate_config_schema();
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
+        }
+        None if opt.schema => {
+            let schema = generate_config_schema();
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
+        }
+        None => {}
     }
 
     // This is more complex than I'd like it to be. Really, we just want to pass
@@ -168,6 +224,11 @@ pub async fn rt_main() -> Result<()> {
 
     let current_directory = std::env::current_dir()?;
 
+    let hot_reload = opt.hot_reload || opt.dev;
+    if opt.dev {
+        tracing::warn!("running in development mode, do not use it in production");
+    }
+
     let configuration = opt
         .configuration_path
         .as_ref()
@@ -180,14 +241,31 @@ pub async fn rt_main() -> Result<()> {
 
             ConfigurationKind::File {
                 path,
-                watch: opt.hot_reload,
+                watch: hot_reload,
                 delay: None,
             }
         })
         .unwrap_or_else(|| ConfigurationKind::Instance(Configuration::builder().build().boxed()));
+    let configuration = match opt.listen {
+        Some(listen) => configuration.map(move |mut configuration| {
+            configuration.server.listen = listen.into();
+            configuration
+        }),
+        None => configuration,
+    };
+    let configuration = if opt.dev {
+        configuration.map(Configuration::dev)
+    } else {
+        configuration
+    };
 
-    let schema = match (opt.supergraph_path, opt.apollo_key) {
-        (Some(supergraph_path), _) => {
+    let schema = match (
+        opt.supergraph_path,
+        opt.subgraphs_path,
+        opt.supergraph_url,
+        opt.apollo_key,
+    ) {
+        (Some(supergraph_path), _, _, _) => {
             tracing::info!(
                 "{}@{}",
                 std::env!("CARGO_PKG_NAME"),
@@ -200,11 +278,43 @@ pub async fn rt_main() -> Result<()> {
             };
             SchemaKind::File {
                 path: supergraph_path,
-                watch: opt.hot_reload,
+                watch: hot_reload,
                 delay: None,
             }
         }
-        (None, Some(apollo_key)) => {
+        (None, Some(subgraphs_path), _, _) => {
+            tracing::info!(
+                "{}@{}",
+                std::env!("CARGO_PKG_NAME"),
+                std::env!("CARGO_PKG_VERSION")
+            );
+            let subgraphs_path = if subgraphs_path.is_relative() {
+                current_directory.join(subgraphs_path)
+            } else {
+                subgraphs_path
+            };
+            SchemaKind::Subgraphs {
+                subgraphs: composition::read_config(&subgraphs_path)?,
+                watch: hot_reload,
+                delay: None,
+            }
+        }
+        (None, None, Some(supergraph_url), _) => {
+            tracing::info!(
+                "{}@{}",
+                std::env!("CARGO_PKG_NAME"),
+                std::env!("CARGO_PKG_VERSION")
+            );
+            if opt.apollo_schema_poll_interval < Duration::from_secs(10) {
+                return Err(anyhow!("Supergraph poll interval must be at least 10s"));
+            }
+
+            SchemaKind::Url {
+                url: supergraph_url,
+                poll_interval: opt.apollo_schema_poll_interval,
+            }
+        }
+        (None, None, None, Some(apollo_key)) => {
             tracing::info!(
                 "{}@{}",
                 std::env!("CARGO_PKG_NAME"),
@@ -236,6 +346,15 @@ APOLLO ROUTER v{version}
 
       $ ./router --supergraph <file_path>
 
+  * Compose the schemas of local subgraphs with the '--subgraphs' option,
+    given a 'rover supergraph compose' configuration:
+
+      $ ./router --subgraphs <file_path>
+
+  * Poll a schema served over HTTP with the '--supergraph-url' option:
+
+      $ ./router --supergraph-url <url>
+
   * Fetch a registered schema from Apollo Studio by setting
     these environment variables:
 
@@ -293,3 +412,29 @@ fn copy_args_to_env() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_subcommands_are_parsed() {
+        let opt = Opt::try_parse_from(["router", "config", "validate", "router.yaml"]).unwrap();
+        assert!(matches!(
+            opt.command,
+            Some(Commands::Config(ConfigCommands::Validate { path })) if path == PathBuf::from("router.yaml")
+        ));
+
+        let opt = Opt::try_parse_from(["router", "config", "schema"]).unwrap();
+        assert!(matches!(
+            opt.command,
+            Some(Commands::Config(ConfigCommands::Schema))
+        ));
+    }
+
+    #[test]
+    fn listen_address_is_parsed() {
+        let opt = Opt::try_parse_from(["router", "--listen", "127.0.0.1:4001"]).unwrap();
+        assert_eq!(opt.listen, Some("127.0.0.1:4001".parse().unwrap()));
+    }
+}
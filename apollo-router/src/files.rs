@@ -1,8 +1,13 @@
 use futures::channel::mpsc;
 use futures::prelude::*;
 use hotwatch::Hotwatch;
+use once_cell::sync::Lazy;
 use std::path::PathBuf;
 use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Reloads of the watched files requested by the admin endpoints.
+static RELOAD_REQUESTS: Lazy<broadcast::Sender<()>> = Lazy::new(|| broadcast::channel(1).0);
 
 /// Creates a stream events whenever the file at the path has changes. The stream never terminates
 /// and must be dropped to finish watching.
@@ -57,6 +62,44 @@ pub(crate) fn watch(path: PathBuf, delay: Option<Duration>) -> impl Stream<Item
         .boxed()
 }
 
+/// Creates a stream of events whenever the process receives `SIGHUP` or a reload is requested, to
+/// reload watched files on demand, for instance where file system events are unreliable.
+#[cfg(unix)]
+pub(crate) fn hangups() -> impl Stream<Item = ()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let signals = match signal(SignalKind::hangup()) {
+        Ok(mut hangup) => stream::poll_fn(move |cx| hangup.poll_recv(cx)).boxed(),
+        Err(err) => {
+            tracing::error!("Failed to install the SIGHUP handler. {}", err);
+            stream::pending().boxed()
+        }
+    };
+    stream::select(signals, reload_requests())
+}
+
+/// There is no `SIGHUP` outside of unix: the stream only yields when a reload is requested.
+#[cfg(not(unix))]
+pub(crate) fn hangups() -> impl Stream<Item = ()> {
+    reload_requests()
+}
+
+/// Makes the streams of [`hangups`] yield, as if the process received `SIGHUP`.
+pub(crate) fn request_reload() {
+    // there is nothing to reload when no file is watched
+    let _ = RELOAD_REQUESTS.send(());
+}
+
+fn reload_requests() -> impl Stream<Item = ()> {
+    stream::unfold(RELOAD_REQUESTS.subscribe(), |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => Some(((), receiver)),
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    })
+    .boxed()
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -79,6 +122,31 @@ pub(crate) mod tests {
         assert!(futures::poll!(watch.next()).is_pending())
     }
 
+    #[cfg(unix)]
+    #[test(tokio::test)]
+    async fn hangup_triggers_a_reload() {
+        let mut hangups = hangups();
+        assert!(futures::poll!(hangups.next()).is_pending());
+
+        unsafe {
+            libc::kill(libc::getpid(), libc::SIGHUP);
+        }
+        tokio::time::timeout(Duration::from_secs(5), hangups.next())
+            .await
+            .expect("no reload after SIGHUP")
+            .unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn requested_reloads_are_delivered() {
+        let mut hangups = hangups();
+        request_reload();
+        tokio::time::timeout(Duration::from_secs(5), hangups.next())
+            .await
+            .expect("no reload after the request")
+            .unwrap();
+    }
+
     #[cfg(test)]
     pub(crate) fn create_temp_file() -> (PathBuf, File) {
         let path = temp_dir().join(format!("{}", uuid::Uuid::new_v4()));
@@ -0,0 +1,463 @@
+//! Health, readiness and liveness endpoints, served on a listener of their own.
+//!
+//! * `/live` answers with a 200 status as long as the router process is responsive.
+//! * `/ready` answers with a 200 status once the schema is loaded and every health check passes,
+//!   and with a 503 status otherwise, so that no traffic is sent to the router in the meantime.
+//! * `/health` answers like `/ready`, with the outcome of each check in the body.
+//!
+//! The checks are run in the background, every `check_interval` and whenever the schema changes,
+//! and the endpoints answer with the outcome of the last run, so that probes do not open
+//! connections to the subgraphs.
+//!
+//! The checks are the reachability of each subgraph, along with the checks the plugins declare
+//! with [`Plugin::health_checks`](apollo_router_core::plugin::Plugin::health_checks).
+
+use crate::FederatedServerError;
+use apollo_router_core::{HealthCheck, Plugins, Schema};
+use async_trait::async_trait;
+use axum::extract::Extension;
+use axum::http::{StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::channel::oneshot;
+use futures::future::join_all;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tower::BoxError;
+
+/// Time after which a check that has not completed fails.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether the router is ready, shared by the state machine and the health endpoints.
+#[derive(Debug)]
+pub(crate) struct Health {
+    state: RwLock<HealthState>,
+    /// Woken when the checks change, so that they are run again without waiting for the interval.
+    changed: Notify,
+}
+
+#[derive(Debug)]
+struct HealthState {
+    /// Checks of the running router, or `None` while no schema is loaded.
+    checks: Option<Arc<Vec<Arc<dyn HealthCheck>>>>,
+    /// Outcome of the last run of the checks, which the endpoints answer with.
+    report: HealthReport,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            state: RwLock::new(HealthState {
+                checks: None,
+                report: HealthReport::not_running(),
+            }),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl Health {
+    /// Marks the router as running, ready as long as `checks` pass.
+    pub(crate) fn running(&self, checks: Vec<Arc<dyn HealthCheck>>) {
+        self.state.write().expect("lock poisoned").checks = Some(Arc::new(checks));
+        self.changed.notify_one();
+    }
+
+    /// Marks the router as not running, without a schema loaded.
+    pub(crate) fn not_running(&self) {
+        let mut state = self.state.write().expect("lock poisoned");
+        state.checks = None;
+        state.report = HealthReport::not_running();
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Outcome of the last run of the checks.
+    fn report(&self) -> HealthReport {
+        self.state.read().expect("lock poisoned").report.clone()
+    }
+
+    /// Runs all the checks, concurrently, and keeps their outcome unless the checks changed in
+    /// the meantime.
+    async fn refresh(&self) {
+        let checks = self.state.read().expect("lock poisoned").checks.clone();
+        let report = match &checks {
+            Some(checks) => run(checks).await,
+            None => HealthReport::not_running(),
+        };
+
+        let mut state = self.state.write().expect("lock poisoned");
+        let unchanged = match (&state.checks, &checks) {
+            (Some(current), Some(checked)) => Arc::ptr_eq(current, checked),
+            (None, None) => true,
+            _ => false,
+        };
+        if unchanged {
+            state.report = report;
+        }
+    }
+
+    /// Runs the checks every `interval`, and whenever they change.
+    async fn check_periodically(&self, interval: Duration) {
+        loop {
+            self.refresh().await;
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = self.changed.notified() => {}
+            }
+        }
+    }
+}
+
+async fn run(checks: &[Arc<dyn HealthCheck>]) -> HealthReport {
+    let mut report = HealthReport::default();
+    report.insert("schema", Ok(()));
+
+    let results = join_all(checks.iter().map(|check| async move {
+        let result = match tokio::time::timeout(CHECK_TIMEOUT, check.check()).await {
+            Ok(result) => result,
+            Err(_) => Err("the check timed out".into()),
+        };
+        (check.name().to_string(), result)
+    }))
+    .await;
+    for (name, result) in results {
+        report.insert(&name, result);
+    }
+    report
+}
+
+/// The health checks of a running router: one for each subgraph, and those of the plugins.
+pub(crate) fn checks(plugins: &Plugins, schema: &Schema) -> Vec<Arc<dyn HealthCheck>> {
+    schema
+        .subgraphs()
+        .filter_map(|(name, url)| SubgraphHealthCheck::new(name, url))
+        .map(|check| Arc::new(check) as Arc<dyn HealthCheck>)
+        .chain(plugins.values().flat_map(|plugin| plugin.health_checks()))
+        .collect()
+}
+
+/// Checks that a subgraph accepts connections.
+#[derive(Debug)]
+struct SubgraphHealthCheck {
+    name: String,
+    address: String,
+}
+
+impl SubgraphHealthCheck {
+    fn new(name: &str, url: &Uri) -> Option<Self> {
+        let port = url.port_u16().unwrap_or(match url.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+        Some(Self {
+            name: format!("subgraph.{}", name),
+            address: format!("{}:{}", url.host()?, port),
+        })
+    }
+}
+
+#[async_trait]
+impl HealthCheck for SubgraphHealthCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> Result<(), BoxError> {
+        TcpStream::connect(&self.address).await?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Fail,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct CheckOutcome {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Body of the `/health` endpoint.
+#[derive(Clone, Debug, Serialize)]
+struct HealthReport {
+    status: Status,
+    checks: BTreeMap<String, CheckOutcome>,
+}
+
+impl Default for HealthReport {
+    fn default() -> Self {
+        Self {
+            status: Status::Pass,
+            checks: BTreeMap::new(),
+        }
+    }
+}
+
+impl HealthReport {
+    fn not_running() -> Self {
+        let mut report = Self::default();
+        report.insert("schema", Err("the schema is not loaded".into()));
+        report
+    }
+
+    fn insert(&mut self, name: &str, result: Result<(), BoxError>) {
+        let outcome = match result {
+            Ok(()) => CheckOutcome {
+                status: Status::Pass,
+                error: None,
+            },
+            Err(err) => {
+                self.status = Status::Fail;
+                CheckOutcome {
+                    status: Status::Fail,
+                    error: Some(err.to_string()),
+                }
+            }
+        };
+        self.checks.insert(name.to_string(), outcome);
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self.status {
+            Status::Pass => StatusCode::OK,
+            Status::Fail => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+async fn handle_health(Extension(health): Extension<Arc<Health>>) -> impl IntoResponse {
+    let report = health.report();
+    (report.status_code(), Json(json!(report)))
+}
+
+async fn handle_ready(Extension(health): Extension<Arc<Health>>) -> impl IntoResponse {
+    let report = health.report();
+    (
+        report.status_code(),
+        Json(json!({ "status": report.status })),
+    )
+}
+
+async fn handle_live() -> impl IntoResponse {
+    Json(json!({ "status": Status::Pass }))
+}
+
+/// A running listener of the health endpoints.
+#[derive(Debug)]
+pub(crate) struct HealthServerHandle {
+    shutdown_sender: oneshot::Sender<()>,
+    server: JoinHandle<Result<(), hyper::Error>>,
+    checker: JoinHandle<()>,
+    /// The configured address, which may use port zero.
+    pub(crate) configured_address: SocketAddr,
+    /// The address actually listened on.
+    pub(crate) listen_address: SocketAddr,
+    /// How often the checks are run.
+    pub(crate) check_interval: Duration,
+}
+
+impl HealthServerHandle {
+    /// Starts serving the health endpoints on `listen`, running the checks every
+    /// `check_interval`.
+    pub(crate) async fn start(
+        listen: SocketAddr,
+        check_interval: Duration,
+        health: Arc<Health>,
+    ) -> Result<Self, FederatedServerError> {
+        let listener = TcpListener::bind(listen)
+            .await
+            .map_err(FederatedServerError::ServerCreationError)?;
+        let listen_address = listener
+            .local_addr()
+            .map_err(FederatedServerError::ServerCreationError)?;
+        let listener = listener
+            .into_std()
+            .map_err(FederatedServerError::ServerCreationError)?;
+
+        let app = Router::new()
+            .route("/health", get(handle_health))
+            .route("/ready", get(handle_ready))
+            .route("/live", get(handle_live))
+            .layer(Extension(health.clone()));
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let server = axum::Server::from_tcp(listener)
+            .map_err(|_| FederatedServerError::HttpServerLifecycleError)?
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async {
+                let _ = shutdown_receiver.await;
+            });
+        tracing::debug!("serving the health endpoints on {}", listen_address);
+        let checker = tokio::spawn(async move { health.check_periodically(check_interval).await });
+
+        Ok(Self {
+            shutdown_sender,
+            server: tokio::spawn(server),
+            checker,
+            configured_address: listen,
+            listen_address,
+            check_interval,
+        })
+    }
+
+    pub(crate) async fn shutdown(self) {
+        self.checker.abort();
+        let _ = self.shutdown_sender.send(());
+        match self.server.await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::error!("the health listener failed: {}", err),
+            Err(err) => tracing::error!("the health listener panicked: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug)]
+    struct StaticCheck(Result<(), &'static str>);
+
+    #[async_trait]
+    impl HealthCheck for StaticCheck {
+        fn name(&self) -> &str {
+            "redis"
+        }
+
+        async fn check(&self) -> Result<(), BoxError> {
+            self.0.map_err(Into::into)
+        }
+    }
+
+    async fn get_json(address: SocketAddr, path: &str) -> (u16, Value) {
+        let response = reqwest::get(format!("http://{}{}", address, path))
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn readiness_follows_the_schema_and_the_checks() {
+        let health = Arc::new(Health::default());
+        let server = HealthServerHandle::start(
+            SocketAddr::from_str("127.0.0.1:0").unwrap(),
+            Duration::from_secs(3600),
+            health.clone(),
+        )
+        .await
+        .unwrap();
+        let address = server.listen_address;
+
+        assert_eq!(
+            get_json(address, "/live").await,
+            (200, json!({ "status": "pass" }))
+        );
+        assert_eq!(
+            get_json(address, "/ready").await,
+            (503, json!({ "status": "fail" }))
+        );
+
+        health.running(vec![Arc::new(StaticCheck(Err("connection refused")))]);
+        health.refresh().await;
+        assert_eq!(
+            get_json(address, "/health").await,
+            (
+                503,
+                json!({
+                    "status": "fail",
+                    "checks": {
+                        "redis": { "status": "fail", "error": "connection refused" },
+                        "schema": { "status": "pass" },
+                    }
+                })
+            )
+        );
+
+        health.running(vec![Arc::new(StaticCheck(Ok(())))]);
+        health.refresh().await;
+        assert_eq!(
+            get_json(address, "/ready").await,
+            (200, json!({ "status": "pass" }))
+        );
+
+        health.not_running();
+        assert_eq!(get_json(address, "/ready").await.0, 503);
+        server.shutdown().await;
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingCheck(AtomicUsize);
+
+    #[async_trait]
+    impl HealthCheck for CountingCheck {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        async fn check(&self) -> Result<(), BoxError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_run_on_the_interval_rather_than_per_request() {
+        let check = Arc::new(CountingCheck::default());
+        let health = Arc::new(Health::default());
+        health.running(vec![check.clone()]);
+        health.refresh().await;
+        for _ in 0..3 {
+            assert_eq!(health.report().status, Status::Pass);
+        }
+        assert_eq!(check.0.load(Ordering::SeqCst), 1);
+
+        let server = HealthServerHandle::start(
+            SocketAddr::from_str("127.0.0.1:0").unwrap(),
+            Duration::from_millis(20),
+            health.clone(),
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(check.0.load(Ordering::SeqCst) > 2);
+        server.shutdown().await;
+
+        let runs = check.0.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(check.0.load(Ordering::SeqCst), runs);
+    }
+
+    #[test]
+    fn subgraphs_are_checked_on_their_port() {
+        let check = SubgraphHealthCheck::new(
+            "products",
+            &Uri::from_str("https://products.example.com/graphql").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(check.name, "subgraph.products");
+        assert_eq!(check.address, "products.example.com:443");
+
+        let check = SubgraphHealthCheck::new(
+            "reviews",
+            &Uri::from_str("http://localhost:4002/graphql").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(check.address, "localhost:4002");
+    }
+}
@@ -9,7 +9,9 @@ use derivative::Derivative;
 use futures::channel::oneshot;
 use futures::prelude::*;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, pin::Pin};
+use tokio::sync::mpsc;
 use tower::BoxError;
 use tower::Service;
 
@@ -53,6 +55,10 @@ pub(crate) struct HttpServerHandle {
     /// The listen address that the server is actually listening on.
     /// If the socket address specified port zero the OS will assign a random free port.
     listen_address: ListenAddr,
+
+    /// Closed once every connection of the server is closed, with how long to wait for it on
+    /// shutdown.
+    connections: Option<(mpsc::Receiver<()>, Duration)>,
 }
 
 impl HttpServerHandle {
@@ -65,14 +71,29 @@ impl HttpServerHandle {
             shutdown_sender,
             server_future,
             listen_address,
+            connections: None,
         }
     }
 
+    /// Waits on shutdown for the connections to be closed, for at most `drain_timeout`.
+    ///
+    /// `connections` must be closed once the server and all its connections are done with, which
+    /// happens when they drop the senders of the channel.
+    pub(crate) fn with_connections(
+        mut self,
+        connections: mpsc::Receiver<()>,
+        drain_timeout: Duration,
+    ) -> Self {
+        self.connections = Some((connections, drain_timeout));
+        self
+    }
+
     pub(crate) async fn shutdown(self) -> Result<(), FederatedServerError> {
         if let Err(_err) = self.shutdown_sender.send(()) {
             tracing::error!("Failed to notify http thread of shutdown")
         };
         let _listener = self.server_future.await?;
+        Draining(self.connections).wait().await;
         #[cfg(unix)]
         {
             if let ListenAddr::UnixSocket(path) = self.listen_address {
@@ -82,13 +103,15 @@ impl HttpServerHandle {
         Ok(())
     }
 
+    /// Replaces the server with one serving `router`, returning it along with the connections of
+    /// the previous server, which keep answering the requests they received.
     pub(crate) async fn restart<RS, SF>(
         self,
         factory: &SF,
         router: RS,
         configuration: Arc<Configuration>,
         plugin_handlers: HashMap<String, Handler>,
-    ) -> Result<Self, FederatedServerError>
+    ) -> Result<(Self, Draining), FederatedServerError>
     where
         SF: HttpServerFactory,
         RS: Service<Request<graphql::Request>, Response = Response<ResponseBody>, Error = BoxError>
@@ -133,7 +156,7 @@ impl HttpServerHandle {
             .await?;
         tracing::debug!("restarted on {}", handle.listen_address());
 
-        Ok(handle)
+        Ok((handle, Draining(self.connections)))
     }
 
     pub(crate) fn listen_address(&self) -> &ListenAddr {
@@ -141,6 +164,27 @@ impl HttpServerHandle {
     }
 }
 
+/// The connections of a stopped server, until they are closed.
+pub(crate) struct Draining(Option<(mpsc::Receiver<()>, Duration)>);
+
+impl Draining {
+    /// Waits for the connections to be closed, for at most the drain timeout.
+    pub(crate) async fn wait(self) {
+        if let Some((mut connections, drain_timeout)) = self.0 {
+            tracing::debug!("waiting for the in-flight requests");
+            if tokio::time::timeout(drain_timeout, connections.recv())
+                .await
+                .is_err()
+            {
+                tracing::warn!(
+                    "connections still open after {:?}, closing them",
+                    drain_timeout
+                );
+            }
+        }
+    }
+}
+
 pub enum Listener {
     Tcp(tokio::net::TcpListener),
     #[cfg(unix)]
@@ -210,6 +254,44 @@ mod tests {
             .expect("Should have been send notification to shutdown");
     }
 
+    #[test(tokio::test)]
+    async fn shutdown_waits_for_the_connections() {
+        let (shutdown_sender, _shutdown_receiver) = oneshot::channel();
+        let listener = Listener::Tcp(tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let (connection, connections) = mpsc::channel(1);
+        let (closed_sender, closed_receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let _ = closed_sender.send(());
+            drop(connection);
+        });
+
+        HttpServerHandle::new(
+            shutdown_sender,
+            futures::future::ready(Ok(listener)).boxed(),
+            SocketAddr::from_str("127.0.0.1:0").unwrap().into(),
+        )
+        .with_connections(connections, Duration::from_secs(5))
+        .shutdown()
+        .await
+        .unwrap();
+        assert!(closed_receiver.now_or_never().is_some());
+
+        // connections open past the drain timeout do not prevent the shutdown
+        let (shutdown_sender, _shutdown_receiver) = oneshot::channel();
+        let listener = Listener::Tcp(tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let (_connection, connections) = mpsc::channel::<()>(1);
+        HttpServerHandle::new(
+            shutdown_sender,
+            futures::future::ready(Ok(listener)).boxed(),
+            SocketAddr::from_str("127.0.0.1:0").unwrap().into(),
+        )
+        .with_connections(connections, Duration::from_millis(10))
+        .shutdown()
+        .await
+        .unwrap();
+    }
+
     #[test(tokio::test)]
     #[cfg(unix)]
     async fn sanity_unix() {
@@ -2,24 +2,41 @@
 
 extern crate core;
 
+mod admin;
 mod axum_http_server_factory;
+pub mod batching;
+mod build_info;
+pub mod client_ip;
+pub mod composition;
 pub mod configuration;
+mod connection_limits;
+pub mod correlation;
+mod deferred;
 mod executable;
 mod files;
+mod health;
 mod http_server_factory;
 pub mod plugins;
 mod reload;
+mod request_body;
 mod router_factory;
+mod schema_url;
 mod state_machine;
 pub mod subscriber;
+mod subscription_callback;
+mod subscriptions;
+mod tls;
 
 use crate::configuration::validate_configuration;
 use crate::reload::Error as ReloadError;
-use crate::router_factory::{RouterServiceFactory, YamlRouterServiceFactory};
+use crate::router_factory::{PluginConstructor, RouterServiceFactory, YamlRouterServiceFactory};
 use crate::state_machine::StateMachine;
 use crate::Event::{NoMoreConfiguration, NoMoreSchema};
 use apollo_router_core::prelude::*;
+use apollo_router_core::{DynPlugin, Plugin};
 use axum_http_server_factory::AxumHttpServerFactory;
+pub use build_info::{build_info, BuildInfo};
+use composition::LocalSubgraph;
 use configuration::{Configuration, ListenAddr};
 use derivative::Derivative;
 use derive_more::{Display, From};
@@ -32,6 +49,7 @@ use std::fmt::{Display, Formatter};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 use thiserror::Error;
@@ -107,6 +125,21 @@ pub enum SchemaKind {
         path: PathBuf,
 
         /// `true` to watch the file for changes and hot apply them.
+        /// The file is also reloaded when the process receives `SIGHUP`.
+        watch: bool,
+
+        /// When watching, the delay to wait before applying the new schema.
+        delay: Option<Duration>,
+    },
+
+    /// Subgraph schema files composed into a supergraph, for development.
+    #[display(fmt = "Subgraphs")]
+    Subgraphs {
+        /// The subgraphs to compose.
+        subgraphs: Vec<LocalSubgraph>,
+
+        /// `true` to watch the subgraph schema files for changes and hot apply them.
+        /// The files are also reloaded when the process receives `SIGHUP`.
         watch: bool,
 
         /// When watching, the delay to wait before applying the new schema.
@@ -128,6 +161,16 @@ pub enum SchemaKind {
         /// The duration between polling
         poll_interval: Duration,
     },
+
+    /// A supergraph schema served over HTTP, polled for changes.
+    #[display(fmt = "Url")]
+    Url {
+        /// The URL of the supergraph schema.
+        url: Url,
+
+        /// The duration between polling
+        poll_interval: Duration,
+    },
 }
 
 impl From<graphql::Schema> for SchemaKind {
@@ -157,12 +200,15 @@ impl SchemaKind {
                     match ConfigurationKind::read_schema(&path) {
                         Ok(schema) => {
                             if watch {
-                                files::watch(path.to_owned(), delay)
-                                    .filter_map(move |_| {
-                                        future::ready(ConfigurationKind::read_schema(&path).ok())
-                                    })
-                                    .map(|schema| UpdateSchema(Box::new(schema)))
-                                    .boxed()
+                                stream::select(
+                                    files::watch(path.to_owned(), delay),
+                                    files::hangups(),
+                                )
+                                .filter_map(move |_| {
+                                    future::ready(ConfigurationKind::read_schema(&path).ok())
+                                })
+                                .map(|schema| UpdateSchema(Box::new(schema)))
+                                .boxed()
                             } else {
                                 stream::once(future::ready(UpdateSchema(Box::new(schema)))).boxed()
                             }
@@ -174,6 +220,38 @@ impl SchemaKind {
                     }
                 }
             }
+            SchemaKind::Subgraphs {
+                subgraphs,
+                watch,
+                delay,
+            } => match composition::compose_files(&subgraphs) {
+                Ok(schema) => {
+                    if watch {
+                        let changes = subgraphs
+                            .iter()
+                            .map(|subgraph| files::watch(subgraph.path.clone(), delay).boxed())
+                            .chain(std::iter::once(files::hangups().boxed()));
+                        stream::once(future::ready(schema))
+                            .chain(stream::select_all(changes).filter_map(move |_| {
+                                future::ready(
+                                    composition::compose_files(&subgraphs)
+                                        .map_err(|err| {
+                                            tracing::error!("Failed to compose schema: {}", err)
+                                        })
+                                        .ok(),
+                                )
+                            }))
+                            .map(|schema| UpdateSchema(Box::new(schema)))
+                            .boxed()
+                    } else {
+                        stream::once(future::ready(UpdateSchema(Box::new(schema)))).boxed()
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("Failed to compose schema: {}", err);
+                    stream::empty().boxed()
+                }
+            },
             SchemaKind::Registry {
                 apollo_key,
                 apollo_graph_ref,
@@ -198,6 +276,11 @@ impl SchemaKind {
                 })
                 .map(|schema| UpdateSchema(Box::new(schema)))
                 .boxed(),
+            SchemaKind::Url { url, poll_interval } => {
+                schema_url::stream_supergraph(url, poll_interval)
+                    .map(|schema| UpdateSchema(Box::new(schema)))
+                    .boxed()
+            }
         }
         .chain(stream::iter(vec![NoMoreSchema]))
     }
@@ -226,6 +309,7 @@ pub enum ConfigurationKind {
         path: PathBuf,
 
         /// `true` to watch the file for changes and hot apply them.
+        /// The file is also reloaded when the process receives `SIGHUP`.
         watch: bool,
 
         /// When watching, the delay to wait before applying the new configuration.
@@ -255,18 +339,21 @@ impl ConfigurationKind {
                     match ConfigurationKind::read_config(&path) {
                         Ok(configuration) => {
                             if watch {
-                                files::watch(path.to_owned(), delay)
-                                    .filter_map(move |_| {
-                                        future::ready(match ConfigurationKind::read_config(&path) {
-                                            Ok(config) => Some(config),
-                                            Err(err) => {
-                                                tracing::error!("{}", err);
-                                                None
-                                            }
-                                        })
+                                stream::select(
+                                    files::watch(path.to_owned(), delay),
+                                    files::hangups(),
+                                )
+                                .filter_map(move |_| {
+                                    future::ready(match ConfigurationKind::read_config(&path) {
+                                        Ok(config) => Some(config),
+                                        Err(err) => {
+                                            tracing::error!("{}", err);
+                                            None
+                                        }
                                     })
-                                    .map(|x| UpdateConfiguration(Box::new(x)))
-                                    .boxed()
+                                })
+                                .map(|x| UpdateConfiguration(Box::new(x)))
+                                .boxed()
                             } else {
                                 stream::once(future::ready(UpdateConfiguration(Box::new(
                                     configuration,
@@ -286,6 +373,23 @@ impl ConfigurationKind {
         .boxed()
     }
 
+    /// Applies `f` to every configuration of this source, for example to override some settings.
+    pub fn map<F>(self, f: F) -> ConfigurationKind
+    where
+        F: Fn(Configuration) -> Configuration + Send + 'static,
+    {
+        ConfigurationKind::Stream(
+            self.into_stream()
+                .filter_map(move |event| {
+                    future::ready(match event {
+                        UpdateConfiguration(configuration) => Some(f(*configuration)),
+                        _ => None,
+                    })
+                })
+                .boxed(),
+        )
+    }
+
     fn read_config(path: &Path) -> Result<Configuration, FederatedServerError> {
         let config = fs::read_to_string(path).map_err(FederatedServerError::ReadConfigError)?;
         validate_configuration(&config).map_err(FederatedServerError::ConfigError)?;
@@ -314,7 +418,7 @@ pub enum ShutdownKind {
     #[display(fmt = "Custom")]
     Custom(#[derivative(Debug = "ignore")] ShutdownFuture),
 
-    /// Watch for Ctl-C signal.
+    /// Watch for Ctl-C signal, and for SIGTERM on unix.
     #[display(fmt = "CtrlC")]
     CtrlC,
 }
@@ -325,18 +429,32 @@ impl ShutdownKind {
         match self {
             ShutdownKind::None => stream::pending::<Event>().boxed(),
             ShutdownKind::Custom(future) => future.map(|_| Shutdown).into_stream().boxed(),
-            ShutdownKind::CtrlC => async {
-                tokio::signal::ctrl_c()
-                    .await
-                    .expect("Failed to install CTRL+C signal handler");
-            }
-            .map(|_| Shutdown)
-            .into_stream()
-            .boxed(),
+            ShutdownKind::CtrlC => termination().map(|_| Shutdown).into_stream().boxed(),
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn termination() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate =
+        signal(SignalKind::terminate()).expect("Failed to install SIGTERM signal handler");
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result.expect("Failed to install CTRL+C signal handler")
         }
+        _ = terminate.recv() => {}
     }
 }
 
+#[cfg(not(unix))]
+async fn termination() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler");
+}
+
 /// Federated server takes requests and federates a response based on calls to subgraphs.
 ///
 /// # Examples
@@ -407,6 +525,9 @@ pub struct ApolloRouterBuilder<Factory = ()> {
     /// A future that when resolved will shut down the server.
     shutdown: Option<ShutdownKind>,
 
+    /// Plugins added with [`ApolloRouterBuilder::with_plugin`], in order.
+    plugins: Vec<(String, PluginConstructor)>,
+
     router_factory: Factory,
 }
 
@@ -426,7 +547,26 @@ impl ApolloRouterBuilder {
         self
     }
 
+    /// Adds a plugin, created by `factory` on startup and on every reload.
+    ///
+    /// The plugins added this way wrap each service after the plugins of the configuration, in the
+    /// order they are added: the first one sees requests first and responses last. A plugin added
+    /// under the name of another one makes the router fail to start.
+    pub fn with_plugin<P, F>(mut self, name: impl Into<String>, factory: F) -> Self
+    where
+        P: Plugin,
+        F: Fn() -> P + Send + Sync + 'static,
+    {
+        let constructor: PluginConstructor =
+            Arc::new(move || Box::new(factory()) as Box<dyn DynPlugin>);
+        self.plugins.push((name.into(), constructor));
+        self
+    }
+
     /// Use a custom RouterServiceFactory
+    ///
+    /// The plugins added with [`ApolloRouterBuilder::with_plugin`] are left out, the factory
+    /// creating its own.
     pub fn with_factory<RF>(self, router_factory: RF) -> ApolloRouterBuilder<RF>
     where
         RF: RouterServiceFactory,
@@ -435,6 +575,7 @@ impl ApolloRouterBuilder {
             configuration: self.configuration,
             schema: self.schema,
             shutdown: self.shutdown,
+            plugins: Vec::new(),
             router_factory,
         }
     }
@@ -446,7 +587,7 @@ impl ApolloRouterBuilder {
                 .expect("Configuration must be set on builder"),
             schema: self.schema.expect("Schema must be set on builder"),
             shutdown: self.shutdown.unwrap_or(ShutdownKind::CtrlC),
-            router_factory: YamlRouterServiceFactory::default(),
+            router_factory: YamlRouterServiceFactory::new(self.plugins),
         }
     }
 }
@@ -479,6 +620,9 @@ enum Event {
     /// There are no more updates to the schema
     NoMoreSchema,
 
+    /// The router should be rebuilt from its current configuration and schema, with empty caches.
+    Reload,
+
     /// The server should gracefully shutdown.
     Shutdown,
 }
@@ -604,6 +748,13 @@ impl Future for FederatedServerHandle {
     }
 }
 
+impl ApolloRouter<YamlRouterServiceFactory> {
+    /// Version and build information of this router.
+    pub fn build_info() -> BuildInfo {
+        build_info()
+    }
+}
+
 impl<RF> ApolloRouter<RF>
 where
     RF: RouterServiceFactory,
@@ -620,15 +771,18 @@ where
         let (state_listener, state_receiver) = mpsc::channel::<State>(1);
         let server_factory = AxumHttpServerFactory::new();
         let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let (admin_sender, admin_receiver) = mpsc::unbounded::<Event>();
         let event_stream = Self::generate_event_stream(
             self.shutdown,
             self.configuration,
             self.schema,
             shutdown_receiver,
+            admin_receiver,
         );
 
         let state_machine =
-            StateMachine::new(server_factory, Some(state_listener), self.router_factory);
+            StateMachine::new(server_factory, Some(state_listener), self.router_factory)
+                .with_admin_events(admin_sender);
         let result = spawn(async move { state_machine.process_events(event_stream).await })
             .map(|r| match r {
                 Ok(Ok(ok)) => Ok(ok),
@@ -652,6 +806,7 @@ where
         configuration: ConfigurationKind,
         schema: SchemaKind,
         shutdown_receiver: oneshot::Receiver<()>,
+        admin_receiver: mpsc::UnboundedReceiver<Event>,
     ) -> impl Stream<Item = Event> {
         // Chain is required so that the final shutdown message is sent.
         let messages = stream::select_all(vec![
@@ -659,6 +814,7 @@ where
             configuration.into_stream().boxed(),
             schema.into_stream().boxed(),
             shutdown_receiver.into_stream().map(|_| Shutdown).boxed(),
+            admin_receiver.boxed(),
         ])
         .take_while(|msg| future::ready(!matches!(msg, Shutdown)))
         .chain(stream::iter(vec![Shutdown]))
@@ -673,6 +829,7 @@ mod tests {
     use crate::files::tests::{create_temp_file, write_and_flush};
     use serde_json::to_string_pretty;
     use std::env::temp_dir;
+    use std::net::SocketAddr;
     use test_log::test;
 
     fn init_with_server() -> FederatedServerHandle {
@@ -798,6 +955,24 @@ mod tests {
         assert!(matches!(stream.next().await.unwrap(), NoMoreConfiguration));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn config_overrides() {
+        let listen: SocketAddr = "127.0.0.1:4001".parse().unwrap();
+        let mut stream = ConfigurationKind::Instance(Configuration::builder().build().boxed())
+            .map(move |mut configuration| {
+                configuration.server.listen = listen.into();
+                configuration
+            })
+            .into_stream();
+        match stream.next().await.unwrap() {
+            UpdateConfiguration(configuration) => {
+                assert_eq!(configuration.server.listen, listen.into())
+            }
+            _ => panic!("expected a configuration"),
+        }
+        assert!(matches!(stream.next().await.unwrap(), NoMoreConfiguration));
+    }
+
     #[test(tokio::test)]
     async fn schema_by_file_watching() {
         let (path, mut file) = create_temp_file();
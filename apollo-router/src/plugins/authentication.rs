@@ -0,0 +1,470 @@
+//! JSON Web Token authentication.
+//!
+//! Tokens are verified against the keys of a JSON Web Key Set, fetched in the background from
+//! startup on, retrying until it is, and then periodically so that key rotations are picked up.
+//! Fetches taking longer than 10 seconds fail.
+//! The router is not ready until the key set is fetched. A token must be signed with the
+//! algorithm of its key, and name the key in its `kid` unless the set holds a single key.
+//! Requests without a token are let through, leaving the decision to the subgraphs, while
+//! requests with an invalid token are rejected. The claims of valid tokens are stored in the request context under
+//! [`JWT_CLAIMS_CONTEXT_KEY`], where the `@authenticated` and `@requiresScopes` directives of
+//! the schema are checked against them.
+
+use apollo_router_core::{
+    register_plugin, HealthCheck, Object, Plugin, RouterRequest, RouterResponse, ServiceBuilderExt,
+};
+use http::StatusCode;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::ops::ControlFlow;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+/// Context key holding the claims of the verified token of a request.
+pub const JWT_CLAIMS_CONTEXT_KEY: &str = apollo_router_core::AUTHENTICATION_CLAIMS_CONTEXT_KEY;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    jwt: Jwt,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Jwt {
+    /// URL of the JSON Web Key Set holding the keys tokens are signed with.
+    jwks_url: url::Url,
+    /// How often the key set is fetched again. Defaults to 1m.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    refresh_interval: Option<Duration>,
+    /// Header holding the token. Defaults to `authorization`.
+    #[serde(default = "default_header_name")]
+    header_name: String,
+    /// Prefix of the token in the header value. Defaults to `Bearer`.
+    #[serde(default = "default_header_value_prefix")]
+    header_value_prefix: String,
+    /// Issuer that tokens must have been issued by.
+    #[serde(default)]
+    issuer: Option<String>,
+    /// Audience that tokens must have been issued for.
+    #[serde(default)]
+    audience: Option<String>,
+}
+
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// Delay before the first retry of a failed fetch of the key set, doubled up to the refresh
+/// interval on each failure.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Time the key set endpoint is given to answer.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn default_header_name() -> String {
+    String::from("authorization")
+}
+
+fn default_header_value_prefix() -> String {
+    String::from("Bearer")
+}
+
+/// The key set, until it could be fetched for the first time.
+type Keys = Arc<RwLock<Option<JwkSet>>>;
+
+struct Authentication {
+    config: Jwt,
+    keys: Keys,
+}
+
+#[async_trait::async_trait]
+impl Plugin for Authentication {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        let config = config.jwt;
+        let keys = Keys::default();
+
+        // The task only holds a weak reference, and stops once the plugin is dropped on reload.
+        // It is only upgraded once a fetch returns, to store the key set.
+        let refreshed = Arc::downgrade(&keys);
+        let url = config.jwks_url.clone();
+        let interval = config.refresh_interval.unwrap_or(DEFAULT_REFRESH_INTERVAL);
+        let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+        tokio::spawn(async move {
+            let mut retry_delay = MIN_RETRY_DELAY;
+            while refreshed.strong_count() > 0 {
+                let delay = match fetch_keys(&client, &url).await {
+                    Ok(set) => {
+                        match refreshed.upgrade() {
+                            Some(keys) => *keys.write().expect("lock poisoned") = Some(set),
+                            None => break,
+                        }
+                        retry_delay = MIN_RETRY_DELAY;
+                        interval
+                    }
+                    Err(err) => {
+                        // A key set fetched before is kept until a new one is.
+                        tracing::error!("could not fetch the JSON Web Key Set: {}", err);
+                        let delay = retry_delay;
+                        retry_delay = (retry_delay * 2).min(interval);
+                        delay
+                    }
+                };
+                tokio::time::sleep(delay).await;
+            }
+        });
+
+        Ok(Authentication { config, keys })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let config = self.config.clone();
+        let keys = self.keys.clone();
+
+        ServiceBuilder::new()
+            .checkpoint(move |req: RouterRequest| {
+                let token = match req
+                    .originating_request
+                    .headers()
+                    .get(config.header_name.as_str())
+                {
+                    Some(value) => match header_token(value, &config.header_value_prefix) {
+                        Some(token) => token,
+                        None => return unauthenticated(req, "malformed authorization header"),
+                    },
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+
+                let claims = match &*keys.read().expect("lock poisoned") {
+                    Some(keys) => verify(&token, keys, &config),
+                    None => Err("the JSON Web Key Set was not fetched yet".into()),
+                };
+                match claims {
+                    Ok(claims) => {
+                        req.context.insert(JWT_CLAIMS_CONTEXT_KEY, claims)?;
+                        Ok(ControlFlow::Continue(req))
+                    }
+                    Err(err) => {
+                        tracing::debug!("rejected JSON Web Token: {}", err);
+                        unauthenticated(req, "invalid JSON Web Token")
+                    }
+                }
+            })
+            .service(service)
+            .boxed()
+    }
+
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+        vec![Arc::new(KeysFetched(self.keys.clone()))]
+    }
+}
+
+/// Passes once the key set was fetched.
+#[derive(Debug)]
+struct KeysFetched(Keys);
+
+#[async_trait::async_trait]
+impl HealthCheck for KeysFetched {
+    fn name(&self) -> &str {
+        "authentication"
+    }
+
+    async fn check(&self) -> Result<(), BoxError> {
+        match &*self.0.read().expect("lock poisoned") {
+            Some(_) => Ok(()),
+            None => Err("the JSON Web Key Set was not fetched yet".into()),
+        }
+    }
+}
+
+async fn fetch_keys(client: &reqwest::Client, url: &url::Url) -> Result<JwkSet, BoxError> {
+    Ok(client
+        .get(url.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+fn header_token(value: &http::HeaderValue, prefix: &str) -> Option<String> {
+    let value = value.to_str().ok()?;
+    let token = if prefix.is_empty() {
+        value
+    } else {
+        value.strip_prefix(prefix)?.strip_prefix(' ')?
+    };
+    Some(token.trim().to_string())
+}
+
+fn verify(token: &str, keys: &JwkSet, config: &Jwt) -> Result<serde_json::Value, BoxError> {
+    let header = decode_header(token)?;
+    let jwk = match (&header.kid, keys.keys.as_slice()) {
+        (Some(kid), _) => keys.find(kid),
+        // Without a `kid`, only a key set holding a single key tells which key to use.
+        (None, [jwk]) => Some(jwk),
+        (None, _) => None,
+    }
+    .ok_or("no matching key in the JSON Web Key Set")?;
+    if !signs_with(jwk, header.alg) {
+        return Err(format!("the key does not sign with {:?}", header.alg).into());
+    }
+
+    let mut validation = Validation::new(header.alg);
+    if let Some(issuer) = &config.issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    if let Some(audience) = &config.audience {
+        validation.set_audience(&[audience]);
+    }
+    Ok(decode(token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims)
+}
+
+/// Whether tokens signed with `jwk` may use `algorithm`: the algorithm of the key if it has one,
+/// otherwise any algorithm for its type of key.
+fn signs_with(jwk: &Jwk, algorithm: Algorithm) -> bool {
+    match (jwk.common.algorithm, &jwk.algorithm) {
+        (Some(key_algorithm), _) => key_algorithm == algorithm,
+        (None, AlgorithmParameters::OctetKey(_)) => matches!(
+            algorithm,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ),
+        (None, AlgorithmParameters::RSA(_)) => matches!(
+            algorithm,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+        ),
+        (None, AlgorithmParameters::EllipticCurve(_)) => {
+            matches!(algorithm, Algorithm::ES256 | Algorithm::ES384)
+        }
+        (None, AlgorithmParameters::OctetKeyPair(_)) => algorithm == Algorithm::EdDSA,
+    }
+}
+
+fn unauthenticated(
+    req: RouterRequest,
+    message: &str,
+) -> Result<ControlFlow<RouterResponse, RouterRequest>, BoxError> {
+    let mut extensions = Object::default();
+    extensions.insert("code", "UNAUTHENTICATED".into());
+    let res = RouterResponse::builder()
+        .errors(vec![apollo_router_core::Error {
+            message: message.to_string(),
+            extensions,
+            ..Default::default()
+        }])
+        .status_code(StatusCode::UNAUTHORIZED)
+        .context(req.context)
+        .build()?;
+    Ok(ControlFlow::Break(res))
+}
+
+register_plugin!("apollo", "authentication", Authentication);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apollo_router_core::plugin::utils::test::MockRouterService;
+    use apollo_router_core::DynPlugin;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const SECRET: &[u8] = b"router-jwt-test-secret-0123456789";
+
+    fn key(kid: &str) -> serde_json::Value {
+        json!({
+            "kty": "oct",
+            "kid": kid,
+            "alg": "HS256",
+            "k": "cm91dGVyLWp3dC10ZXN0LXNlY3JldC0wMTIzNDU2Nzg5"
+        })
+    }
+
+    async fn serve_keys(keys: Vec<serde_json::Value>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let keys = json!({ "keys": keys });
+        let app = Router::new().route(
+            "/jwks.json",
+            get(move || {
+                let keys = keys.clone();
+                async move { Json(keys) }
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        format!("http://{}/jwks.json", address)
+    }
+
+    fn token(kid: &str, secret: &[u8]) -> String {
+        signed_token(
+            Header {
+                kid: Some(kid.to_string()),
+                ..Header::new(Algorithm::HS256)
+            },
+            secret,
+        )
+    }
+
+    fn signed_token(header: Header, secret: &[u8]) -> String {
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 3600;
+        encode(
+            &header,
+            &json!({ "sub": "alice", "exp": exp }),
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    async fn authentication(jwks_url: String) -> Box<dyn DynPlugin> {
+        apollo_router_core::plugins()
+            .get("apollo.authentication")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "jwt": { "jwks_url": jwks_url } }))
+            .await
+            .unwrap()
+    }
+
+    async fn call(authorization: Option<String>) -> RouterResponse {
+        call_with_keys(vec![key("router")], authorization).await
+    }
+
+    async fn call_with_keys(
+        keys: Vec<serde_json::Value>,
+        authorization: Option<String>,
+    ) -> RouterResponse {
+        let mut mock = MockRouterService::new();
+        mock.expect_call().returning(|req: RouterRequest| {
+            Ok(RouterResponse::fake_builder()
+                .context(req.context)
+                .build()
+                .unwrap())
+        });
+
+        let mut plugin = authentication(serve_keys(keys).await).await;
+        // The key set is fetched in the background.
+        let fetched = plugin.health_checks().remove(0);
+        while fetched.check().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let request = match authorization {
+            Some(authorization) => RouterRequest::fake_builder()
+                .header("authorization", authorization)
+                .build(),
+            None => RouterRequest::fake_builder().build(),
+        };
+        plugin
+            .router_service(BoxService::new(mock.build()))
+            .oneshot(request.unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn valid_tokens_expose_their_claims() {
+        let response = call(Some(format!("Bearer {}", token("router", SECRET)))).await;
+
+        assert_eq!(response.response.status(), StatusCode::OK);
+        let claims: serde_json::Value = response
+            .context
+            .get(JWT_CLAIMS_CONTEXT_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(claims["sub"], "alice");
+    }
+
+    #[tokio::test]
+    async fn invalid_tokens_are_rejected() {
+        for authorization in [
+            format!("Bearer {}", token("router", b"some other secret")),
+            format!("Bearer {}", token("unknown", SECRET)),
+            format!("Basic {}", token("router", SECRET)),
+            // The key only signs with HS256.
+            format!(
+                "Bearer {}",
+                signed_token(
+                    Header {
+                        kid: Some("router".to_string()),
+                        ..Header::new(Algorithm::HS384)
+                    },
+                    SECRET
+                )
+            ),
+        ] {
+            let response = call(Some(authorization)).await;
+            assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_without_a_kid_need_a_single_key() {
+        let authorization = format!("Bearer {}", signed_token(Header::default(), SECRET));
+
+        let response = call_with_keys(vec![key("router")], Some(authorization.clone())).await;
+        assert_eq!(response.response.status(), StatusCode::OK);
+
+        let response = call_with_keys(vec![key("router"), key("other")], Some(authorization)).await;
+        assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn startup_does_not_wait_for_the_key_set() {
+        // Nothing listens on the port once the listener is dropped.
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let plugin = authentication(format!("http://{}/jwks.json", address)).await;
+
+        assert!(plugin.health_checks()[0].check().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn hanging_key_set_fetches_do_not_keep_the_plugin_alive() {
+        // The listener accepts connections, but never answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = serde_json::from_value(json!({
+            "jwt": { "jwks_url": format!("http://{}/jwks.json", listener.local_addr().unwrap()) }
+        }))
+        .unwrap();
+        let plugin = Authentication::new(config).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let keys = Arc::downgrade(&plugin.keys);
+        drop(plugin);
+        assert!(keys.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_token_are_let_through() {
+        let response = call(None).await;
+
+        assert_eq!(response.response.status(), StatusCode::OK);
+        assert!(response
+            .context
+            .get::<_, serde_json::Value>(JWT_CLAIMS_CONTEXT_KEY)
+            .unwrap()
+            .is_none());
+    }
+}
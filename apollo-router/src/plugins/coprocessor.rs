@@ -0,0 +1,549 @@
+//! Customization via an external HTTP service.
+//!
+//! At each configured stage, the headers, body and context of the request or response are POSTed
+//! as JSON to the coprocessor, which answers with the changes to make:
+//!
+//! ```json
+//! {
+//!   "control": "continue",
+//!   "headers": { "x-user": ["alice"] },
+//!   "body": { "query": "{ me { name } }" },
+//!   "context": { "user": "alice" }
+//! }
+//! ```
+//!
+//! Every field of the answer is optional. Headers and body replace those of the request or
+//! response, context entries are added to the context, and a `control` of `{ "break": 401 }`
+//! ends the request right away, with that status and the returned body as the response.
+
+use apollo_router_core::{
+    http_compat, register_plugin, Context, Plugin, Response, ResponseBody, RouterRequest,
+    RouterResponse, ServiceBuilderExt, SubgraphRequest, SubgraphResponse, Value,
+};
+use http::header::{HeaderName, CONTENT_LENGTH};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+/// Version of the JSON exchanged with the coprocessor.
+const PROTOCOL_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// URL the stages are POSTed to.
+    url: url::Url,
+    /// Time after which a call to the coprocessor fails. Defaults to 1s.
+    #[serde(with = "humantime_serde", default = "default_timeout")]
+    #[schemars(with = "String")]
+    timeout: Duration,
+    /// Stages the coprocessor is called at.
+    stages: Vec<Stage>,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Stage {
+    RouterRequest,
+    RouterResponse,
+    SubgraphRequest,
+    SubgraphResponse,
+}
+
+/// What is sent to the coprocessor.
+#[derive(Debug, Serialize)]
+pub(crate) struct Externalized<'a> {
+    version: u8,
+    pub(crate) stage: Stage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subgraph: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_code: Option<u16>,
+    headers: BTreeMap<String, Vec<String>>,
+    body: serde_json::Value,
+    context: BTreeMap<String, Value>,
+}
+
+/// Whether the request carries on after a stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Control {
+    Continue,
+    Break(u16),
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Control::Continue
+    }
+}
+
+/// What the coprocessor answers with.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Mutations {
+    control: Control,
+    headers: Option<BTreeMap<String, Vec<String>>>,
+    body: Option<serde_json::Value>,
+    context: BTreeMap<String, Value>,
+}
+
+impl Mutations {
+    fn apply_headers(&mut self, headers: &mut HeaderMap) -> Result<(), BoxError> {
+        if let Some(mutated) = self.headers.take() {
+            headers.clear();
+            for (name, values) in mutated {
+                let name = HeaderName::try_from(name)?;
+                for value in values {
+                    headers.append(name.clone(), HeaderValue::try_from(value)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The returned body, if any. Its length is only known once it is serialized, so the
+    /// `Content-Length` of `headers` is removed.
+    fn take_body<T: DeserializeOwned>(
+        &mut self,
+        headers: &mut HeaderMap,
+    ) -> Result<Option<T>, BoxError> {
+        match self.body.take() {
+            Some(body) => {
+                headers.remove(CONTENT_LENGTH);
+                Ok(Some(serde_json::from_value(body)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn apply_context(&mut self, context: &Context) -> Result<(), BoxError> {
+        for (key, value) in std::mem::take(&mut self.context) {
+            context.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// The response ending the request, if the coprocessor asked for it.
+    fn break_response(&mut self) -> Result<Option<http::Response<Response>>, BoxError> {
+        let status = match self.control {
+            Control::Continue => return Ok(None),
+            Control::Break(status) => StatusCode::from_u16(status)?,
+        };
+        let body = match self.body.take() {
+            Some(body) => serde_json::from_value(body)?,
+            None => Response::default(),
+        };
+        let mut response = http::Response::builder().status(status).body(body)?;
+        self.apply_headers(response.headers_mut())?;
+        response.headers_mut().remove(CONTENT_LENGTH);
+        Ok(Some(response))
+    }
+}
+
+/// An extension the stages of requests are handed to, as JSON.
+#[async_trait::async_trait]
+pub(crate) trait Extension: Debug + Send + Sync {
+    /// Whether the extension is called at `stage`.
+    fn calls(&self, stage: Stage) -> bool;
+
+    async fn call(&self, externalized: Externalized<'_>) -> Result<Mutations, BoxError>;
+}
+
+#[derive(Debug)]
+struct CoprocessorClient {
+    client: reqwest::Client,
+    config: Config,
+}
+
+#[async_trait::async_trait]
+impl Extension for CoprocessorClient {
+    fn calls(&self, stage: Stage) -> bool {
+        self.config.stages.contains(&stage)
+    }
+
+    async fn call(&self, externalized: Externalized<'_>) -> Result<Mutations, BoxError> {
+        let stage = externalized.stage;
+        let response = self
+            .client
+            .post(self.config.url.clone())
+            .timeout(self.config.timeout)
+            .json(&externalized)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("coprocessor call failed at {:?}: {}", stage, err))?;
+        // An empty answer leaves everything as it is.
+        let bytes = response.bytes().await?;
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Mutations::default());
+        }
+        serde_json::from_slice(&bytes)
+            .map_err(|err| format!("invalid coprocessor answer at {:?}: {}", stage, err).into())
+    }
+}
+
+fn externalize_headers(headers: &HeaderMap) -> BTreeMap<String, Vec<String>> {
+    let mut externalized: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, value) in headers {
+        if let Ok(value) = value.to_str() {
+            externalized
+                .entry(name.as_str().to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+    }
+    externalized
+}
+
+fn externalize_context(context: &Context) -> BTreeMap<String, Value> {
+    context
+        .entries
+        .iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect()
+}
+
+#[derive(Debug)]
+struct Coprocessor {
+    client: Arc<dyn Extension>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for Coprocessor {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(Coprocessor {
+            client: Arc::new(CoprocessorClient {
+                client: reqwest::Client::new(),
+                config,
+            }),
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        externalized_router_service(self.client.clone(), service)
+    }
+
+    fn subgraph_service(
+        &mut self,
+        name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        externalized_subgraph_service(self.client.clone(), name, service)
+    }
+}
+
+/// Wraps the router service so that `extension` is called at the router stages.
+pub(crate) fn externalized_router_service(
+    extension: Arc<dyn Extension>,
+    service: BoxService<RouterRequest, RouterResponse, BoxError>,
+) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+    if !extension.calls(Stage::RouterRequest) && !extension.calls(Stage::RouterResponse) {
+        return service;
+    }
+    let service = ServiceBuilder::new().buffered().service(service);
+
+    tower::service_fn(move |mut req: RouterRequest| {
+        let extension = extension.clone();
+        let service = service.clone();
+        async move {
+            if extension.calls(Stage::RouterRequest) {
+                let mut mutations = extension
+                    .call(Externalized {
+                        version: PROTOCOL_VERSION,
+                        stage: Stage::RouterRequest,
+                        subgraph: None,
+                        status_code: None,
+                        headers: externalize_headers(req.originating_request.headers()),
+                        body: serde_json::to_value(req.originating_request.body())?,
+                        context: externalize_context(&req.context),
+                    })
+                    .await?;
+                mutations.apply_context(&req.context)?;
+                if let Some(response) = mutations.break_response()? {
+                    return Ok(router_response(response, req.context));
+                }
+                mutations.apply_headers(req.originating_request.headers_mut())?;
+                if let Some(body) = mutations.take_body(req.originating_request.headers_mut())? {
+                    *req.originating_request.body_mut() = body;
+                }
+            }
+
+            let mut res = service.oneshot(req).await?;
+
+            if extension.calls(Stage::RouterResponse) {
+                let mut mutations = extension
+                    .call(Externalized {
+                        version: PROTOCOL_VERSION,
+                        stage: Stage::RouterResponse,
+                        subgraph: None,
+                        status_code: Some(res.response.status().as_u16()),
+                        headers: externalize_headers(res.response.headers()),
+                        body: serde_json::to_value(res.response.body())?,
+                        context: externalize_context(&res.context),
+                    })
+                    .await?;
+                mutations.apply_context(&res.context)?;
+                if let Some(response) = mutations.break_response()? {
+                    return Ok(router_response(response, res.context));
+                }
+                mutations.apply_headers(res.response.headers_mut())?;
+                if let Some(body) = mutations.take_body(res.response.headers_mut())? {
+                    *res.response.body_mut() = body;
+                }
+            }
+            Ok::<_, BoxError>(res)
+        }
+    })
+    .boxed()
+}
+
+/// Wraps the service of the `name` subgraph so that `extension` is called at the subgraph stages.
+pub(crate) fn externalized_subgraph_service(
+    extension: Arc<dyn Extension>,
+    name: &str,
+    service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+    if !extension.calls(Stage::SubgraphRequest) && !extension.calls(Stage::SubgraphResponse) {
+        return service;
+    }
+    let name = Arc::new(name.to_string());
+    let service = ServiceBuilder::new().buffered().service(service);
+
+    tower::service_fn(move |mut req: SubgraphRequest| {
+        let extension = extension.clone();
+        let name = name.clone();
+        let service = service.clone();
+        async move {
+            if extension.calls(Stage::SubgraphRequest) {
+                let mut mutations = extension
+                    .call(Externalized {
+                        version: PROTOCOL_VERSION,
+                        stage: Stage::SubgraphRequest,
+                        subgraph: Some(name.as_str()),
+                        status_code: None,
+                        headers: externalize_headers(req.subgraph_request.headers()),
+                        body: serde_json::to_value(req.subgraph_request.body())?,
+                        context: externalize_context(&req.context),
+                    })
+                    .await?;
+                mutations.apply_context(&req.context)?;
+                if let Some(response) = mutations.break_response()? {
+                    return Ok(SubgraphResponse::new_from_response(
+                        response.into(),
+                        req.context,
+                    ));
+                }
+                mutations.apply_headers(req.subgraph_request.headers_mut())?;
+                if let Some(body) = mutations.take_body(req.subgraph_request.headers_mut())? {
+                    *req.subgraph_request.body_mut() = body;
+                }
+            }
+
+            let mut res = service.oneshot(req).await?;
+
+            if extension.calls(Stage::SubgraphResponse) {
+                let mut mutations = extension
+                    .call(Externalized {
+                        version: PROTOCOL_VERSION,
+                        stage: Stage::SubgraphResponse,
+                        subgraph: Some(name.as_str()),
+                        status_code: Some(res.response.status().as_u16()),
+                        headers: externalize_headers(res.response.headers()),
+                        body: serde_json::to_value(res.response.body())?,
+                        context: externalize_context(&res.context),
+                    })
+                    .await?;
+                mutations.apply_context(&res.context)?;
+                if let Some(response) = mutations.break_response()? {
+                    return Ok(SubgraphResponse::new_from_response(
+                        response.into(),
+                        res.context,
+                    ));
+                }
+                mutations.apply_headers(res.response.headers_mut())?;
+                if let Some(body) = mutations.take_body(res.response.headers_mut())? {
+                    *res.response.body_mut() = body;
+                }
+            }
+            Ok::<_, BoxError>(res)
+        }
+    })
+    .boxed()
+}
+
+fn router_response(response: http::Response<Response>, context: Context) -> RouterResponse {
+    RouterResponse {
+        response: http_compat::Response::from(response.map(ResponseBody::GraphQL)),
+        context,
+    }
+}
+
+register_plugin!("experimental", "coprocessor", Coprocessor);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apollo_router_core::plugin::utils::test::{MockRouterService, MockSubgraphService};
+    use apollo_router_core::DynPlugin;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Serves a coprocessor answering every call with `answer`, and keeping what it was sent.
+    async fn serve(answer: serde_json::Value) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let calls = received.clone();
+        let app = Router::new().route(
+            "/",
+            post(move |Json(call): Json<serde_json::Value>| {
+                calls.lock().unwrap().push(call);
+                let answer = answer.clone();
+                async move { Json(answer) }
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (format!("http://{}/", address), received)
+    }
+
+    async fn plugin(url: &str, stages: serde_json::Value) -> Box<dyn DynPlugin> {
+        apollo_router_core::plugins()
+            .get("experimental.coprocessor")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "url": url, "stages": stages }))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn router_requests_are_mutated() {
+        let (url, received) = serve(json!({
+            "headers": { "x-user": ["alice"] },
+            "body": { "query": "{ me { name } }" },
+            "context": { "user": "alice" }
+        }))
+        .await;
+
+        let mut mock = MockRouterService::new();
+        mock.expect_call().times(1).returning(|req: RouterRequest| {
+            assert_eq!(req.originating_request.headers()["x-user"], "alice");
+            assert_eq!(
+                req.originating_request.body().query.as_deref(),
+                Some("{ me { name } }")
+            );
+            Ok(RouterResponse::fake_builder()
+                .context(req.context)
+                .build()
+                .unwrap())
+        });
+
+        let response = plugin(&url, json!(["router_request"]))
+            .await
+            .router_service(BoxService::new(mock.build()))
+            .oneshot(
+                RouterRequest::fake_builder()
+                    .header("x-user", "bob")
+                    .query("{ topProducts { upc } }".to_string())
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response
+                .context
+                .get::<_, String>("user")
+                .unwrap()
+                .as_deref(),
+            Some("alice")
+        );
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["stage"], "router_request");
+        assert_eq!(received[0]["headers"]["x-user"], json!(["bob"]));
+        assert_eq!(received[0]["body"]["query"], "{ topProducts { upc } }");
+    }
+
+    #[tokio::test]
+    async fn subgraph_requests_can_be_ended_early() {
+        let (url, received) = serve(json!({
+            "control": { "break": 403 },
+            "body": { "errors": [{ "message": "forbidden" }] }
+        }))
+        .await;
+
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call().never();
+
+        let response = plugin(&url, json!(["subgraph_request", "subgraph_response"]))
+            .await
+            .subgraph_service("products", BoxService::new(mock.build()))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+
+        assert_eq!(response.response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.response.body().errors[0].message, "forbidden");
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["stage"], "subgraph_request");
+        assert_eq!(received[0]["subgraph"], "products");
+    }
+
+    #[tokio::test]
+    async fn replaced_bodies_lose_their_content_length() {
+        let (url, _) = serve(json!({ "body": { "data": { "name": "replaced" } } })).await;
+
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(1)
+            .returning(|req: SubgraphRequest| {
+                let mut response = SubgraphResponse::fake_builder()
+                    .data(serde_json_bytes::json!({ "name": "original" }))
+                    .context(req.context)
+                    .build();
+                response
+                    .response
+                    .headers_mut()
+                    .insert(CONTENT_LENGTH, HeaderValue::from_static("31"));
+                Ok(response)
+            });
+
+        let response = plugin(&url, json!(["subgraph_response"]))
+            .await
+            .subgraph_service("products", BoxService::new(mock.build()))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+
+        assert!(response.response.headers().get(CONTENT_LENGTH).is_none());
+        assert_eq!(
+            response.response.body().data,
+            Some(serde_json_bytes::json!({ "name": "replaced" }))
+        );
+    }
+}
@@ -1,5 +1,8 @@
 //! Router extension via plugins.
 
+pub mod authentication;
+pub mod coprocessor;
 pub mod override_url;
 pub mod rhai;
 pub mod telemetry;
+pub mod wasm;
@@ -7,7 +7,8 @@ use apollo_router_core::{
 };
 use apollo_router_core::{
     Context, Entries, ExecutionRequest, ExecutionResponse, QueryPlannerRequest,
-    QueryPlannerResponse, SubgraphRequest, SubgraphResponse,
+    QueryPlannerResponse, Response, SubgraphRequest, SubgraphResponse, SubscriptionRequest,
+    SubscriptionResponse,
 };
 use http::header::CONTENT_LENGTH;
 use http::HeaderMap;
@@ -15,7 +16,8 @@ use http::{header::HeaderName, HeaderValue};
 use rhai::serde::{from_dynamic, to_dynamic};
 use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json_bytes::ByteString;
 use tower::{util::BoxService, BoxError, ServiceExt};
 
@@ -48,6 +50,7 @@ macro_rules! service_handle_response {
                         $function_name,
                         response.context,
                         response.response.headers().clone(),
+                        Dynamic::UNIT,
                     ) {
                         Ok(res) => res,
                         Err(err) => {
@@ -181,12 +184,15 @@ impl Plugin for Rhai {
                         this.run_rhai_script(
                             FUNCTION_NAME_REQUEST,
                             request.context.clone(),
-                            request.originating_request.headers().clone()
+                            request.originating_request.headers().clone(),
+                            body_to_dynamic(request.originating_request.body()),
                         ),
                         request
                     );
+                    let body = handle_error!(body_from_dynamic(&rhai_context.body), request);
                     request.context = rhai_context.context;
                     *request.originating_request.headers_mut() = rhai_context.headers;
+                    *request.originating_request.body_mut() = body;
                     request
                         .originating_request
                         .headers_mut()
@@ -226,11 +232,28 @@ impl Plugin for Rhai {
                     return response;
                 }
                 if function_found {
-                    let rhai_context = match this.run_rhai_script(
-                        FUNCTION_NAME_RESPONSE,
-                        response.context,
-                        response.response.headers().clone(),
-                    ) {
+                    // only GraphQL bodies are given to the script
+                    let body = match response.response.body() {
+                        ResponseBody::GraphQL(body) => body_to_dynamic(body),
+                        _ => Dynamic::UNIT,
+                    };
+                    let is_graphql = !body.is::<()>();
+                    let result = this
+                        .run_rhai_script(
+                            FUNCTION_NAME_RESPONSE,
+                            response.context,
+                            response.response.headers().clone(),
+                            body,
+                        )
+                        .and_then(|rhai_context| {
+                            let body = if is_graphql {
+                                Some(body_from_dynamic(&rhai_context.body)?)
+                            } else {
+                                None
+                            };
+                            Ok((rhai_context, body))
+                        });
+                    let (rhai_context, body) = match result {
                         Ok(res) => res,
                         Err(err) => {
                             let context = Context::new();
@@ -243,6 +266,9 @@ impl Plugin for Rhai {
                     };
                     response.context = rhai_context.context;
                     *response.response.headers_mut() = rhai_context.headers;
+                    if let Some(body) = body {
+                        *response.response.body_mut() = ResponseBody::GraphQL(body);
+                    }
 
                     response.response.headers_mut().remove(CONTENT_LENGTH);
                 }
@@ -273,7 +299,8 @@ impl Plugin for Rhai {
                         this.run_rhai_script(
                             FUNCTION_NAME_REQUEST,
                             request.context.clone(),
-                            request.originating_request.headers().clone()
+                            request.originating_request.headers().clone(),
+                            Dynamic::UNIT,
                         ),
                         request
                     );
@@ -303,6 +330,7 @@ impl Plugin for Rhai {
                         FUNCTION_NAME_RESPONSE,
                         response.context,
                         HeaderMap::new(),
+                        Dynamic::UNIT,
                     ) {
                         Ok(res) => res,
                         Err(err) => {
@@ -347,7 +375,8 @@ impl Plugin for Rhai {
                         this.run_rhai_script(
                             FUNCTION_NAME_REQUEST,
                             request.context.clone(),
-                            request.originating_request.headers().clone()
+                            request.originating_request.headers().clone(),
+                            Dynamic::UNIT,
                         ),
                         request
                     );
@@ -389,12 +418,15 @@ impl Plugin for Rhai {
                         this.run_rhai_script(
                             FUNCTION_NAME_REQUEST,
                             request.context.clone(),
-                            request.subgraph_request.headers().clone()
+                            request.subgraph_request.headers().clone(),
+                            body_to_dynamic(request.subgraph_request.body()),
                         ),
                         request
                     );
+                    let body = handle_error!(body_from_dynamic(&rhai_context.body), request);
                     request.context = rhai_context.context;
                     *request.subgraph_request.headers_mut() = rhai_context.headers;
+                    *request.subgraph_request.body_mut() = body;
 
                     request
                         .subgraph_request
@@ -427,11 +459,18 @@ impl Plugin for Rhai {
                         .build();
                 }
                 if function_found {
-                    let rhai_context = match this.run_rhai_script(
-                        FUNCTION_NAME_RESPONSE,
-                        response.context,
-                        response.response.headers().clone(),
-                    ) {
+                    let result = this
+                        .run_rhai_script(
+                            FUNCTION_NAME_RESPONSE,
+                            response.context,
+                            response.response.headers().clone(),
+                            body_to_dynamic(response.response.body()),
+                        )
+                        .and_then(|rhai_context| {
+                            let body = body_from_dynamic(&rhai_context.body)?;
+                            Ok((rhai_context, body))
+                        });
+                    let (rhai_context, body) = match result {
                         Ok(res) => res,
                         Err(err) => {
                             let context = Context::new();
@@ -447,6 +486,7 @@ impl Plugin for Rhai {
                     };
                     response.context = rhai_context.context;
                     *response.response.headers_mut() = rhai_context.headers;
+                    *response.response.body_mut() = body;
                     response.response.headers_mut().remove(CONTENT_LENGTH);
                 }
 
@@ -456,6 +496,53 @@ impl Plugin for Rhai {
 
         service
     }
+
+    fn subscription_service(
+        &mut self,
+        service: BoxService<SubscriptionRequest, SubscriptionResponse, BoxError>,
+    ) -> BoxService<SubscriptionRequest, SubscriptionResponse, BoxError> {
+        const FUNCTION_NAME_EVENT: &str = "subscription_service_event";
+        if !self
+            .ast
+            .iter_fn_def()
+            .any(|fn_def| fn_def.name == FUNCTION_NAME_EVENT)
+        {
+            return service;
+        }
+        tracing::debug!("{} function found", FUNCTION_NAME_EVENT);
+        let this = self.clone();
+
+        service
+            .map_request(move |mut request: SubscriptionRequest| {
+                // The headers are those of the subscription request, and cannot be changed.
+                let result = this
+                    .run_rhai_script(
+                        FUNCTION_NAME_EVENT,
+                        request.context.clone(),
+                        request.originating_request.headers().clone(),
+                        body_to_dynamic(&request.event),
+                    )
+                    .and_then(|rhai_context| {
+                        let event = body_from_dynamic(&rhai_context.body)?;
+                        Ok((rhai_context, event))
+                    });
+                match result {
+                    Ok((rhai_context, event)) => {
+                        request.context = rhai_context.context;
+                        request.event = event;
+                    }
+                    Err(err) => {
+                        request.event = Response::builder()
+                            .errors(vec![Error::builder()
+                                .message(format!("RHAI plugin error: {}", err.as_str()))
+                                .build()])
+                            .build();
+                    }
+                }
+                request
+            })
+            .boxed()
+    }
 }
 
 impl RhaiObjectSetterGetter for Entries {
@@ -471,11 +558,24 @@ impl RhaiObjectSetterGetter for Entries {
 pub(crate) struct RhaiContext {
     headers: HeaderMap,
     context: Context,
+    /// The GraphQL body of the request or response, or `()` in the hooks without one.
+    body: Dynamic,
 }
 
 impl RhaiContext {
-    fn new(context: Context, headers: HeaderMap) -> Self {
-        Self { context, headers }
+    fn new(context: Context, headers: HeaderMap, body: Dynamic) -> Self {
+        Self {
+            context,
+            headers,
+            body,
+        }
+    }
+
+    fn get_body(&mut self) -> Dynamic {
+        self.body.clone()
+    }
+    fn set_body(&mut self, body: Dynamic) {
+        self.body = body;
     }
 
     fn get_headers(&mut self) -> Headers {
@@ -492,12 +592,23 @@ impl RhaiContext {
     }
 }
 
+/// A GraphQL body as seen by the scripts, as a map.
+fn body_to_dynamic<T: Serialize>(body: &T) -> Dynamic {
+    to_dynamic(body).unwrap_or(Dynamic::UNIT)
+}
+
+/// A GraphQL body as modified by the scripts.
+fn body_from_dynamic<T: DeserializeOwned>(body: &Dynamic) -> Result<T, String> {
+    from_dynamic(body).map_err(|err| format!("invalid body: {}", err))
+}
+
 impl Rhai {
     fn run_rhai_script(
         &self,
         function_name: &str,
         context: Context,
         headers: HeaderMap,
+        body: Dynamic,
     ) -> Result<RhaiContext, String> {
         let mut scope = Scope::new();
         let response: RhaiContext = self
@@ -506,7 +617,7 @@ impl Rhai {
                 &mut scope,
                 &self.ast,
                 function_name,
-                (RhaiContext::new(context, headers),),
+                (RhaiContext::new(context, headers, body),),
             )
             .map_err(|err| err.to_string())?;
 
@@ -540,7 +651,8 @@ impl Rhai {
                 "entries",
                 RhaiContext::get_entries,
                 RhaiContext::set_entries,
-            );
+            )
+            .register_get_set("body", RhaiContext::get_body, RhaiContext::set_body);
 
         engine
     }
@@ -555,7 +667,7 @@ mod tests {
 
     use apollo_router_core::{
         http_compat,
-        plugin::utils::test::{MockExecutionService, MockRouterService},
+        plugin::utils::test::{MockExecutionService, MockRouterService, MockSubgraphService},
         Context, DynPlugin, ResponseBody, RouterRequest, RouterResponse,
     };
     use serde_json::Value;
@@ -621,6 +733,95 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn rhai_plugin_rewrites_bodies() -> Result<(), BoxError> {
+        let mut dyn_plugin: Box<dyn DynPlugin> = apollo_router_core::plugins()
+            .get("experimental.rhai")
+            .expect("Plugin not found")
+            .create_instance(
+                &Value::from_str(r#"{"filename":"tests/fixtures/body.rhai"}"#).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut mock_service = MockRouterService::new();
+        mock_service
+            .expect_call()
+            .times(1)
+            .returning(move |req: RouterRequest| {
+                let body = req.originating_request.body();
+                assert_eq!(body.operation_name.as_deref(), Some("TopProducts"));
+                assert_eq!(body.variables.get("first"), Some(&3.into()));
+                RouterResponse::fake_builder().context(req.context).build()
+            });
+        let mut router_service = dyn_plugin.router_service(BoxService::new(mock_service.build()));
+        router_service
+            .ready()
+            .await?
+            .call(RouterRequest::fake_builder().build()?)
+            .await?;
+
+        let mut mock_service = MockSubgraphService::new();
+        mock_service
+            .expect_call()
+            .times(1)
+            .returning(move |req: SubgraphRequest| {
+                Ok(SubgraphResponse::fake_builder()
+                    .data(serde_json_bytes::json!({ "topProducts": [] }))
+                    .context(req.context)
+                    .build())
+            });
+        let mut subgraph_service =
+            dyn_plugin.subgraph_service("reviews", BoxService::new(mock_service.build()));
+        let subgraph_resp = subgraph_service
+            .ready()
+            .await?
+            .call(SubgraphRequest::fake_builder().build())
+            .await?;
+        assert_eq!(
+            subgraph_resp.response.body().data,
+            Some(serde_json_bytes::json!({ "topProducts": [], "reviewed": true }))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rhai_plugin_rewrites_subscription_events() -> Result<(), BoxError> {
+        let mut dyn_plugin: Box<dyn DynPlugin> = apollo_router_core::plugins()
+            .get("experimental.rhai")
+            .expect("Plugin not found")
+            .create_instance(
+                &Value::from_str(r#"{"filename":"tests/fixtures/body.rhai"}"#).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut subscription_service = dyn_plugin.subscription_service(
+            tower::service_fn(|request: SubscriptionRequest| async move {
+                Ok::<_, BoxError>(SubscriptionResponse::new(request.event, request.context))
+            })
+            .boxed(),
+        );
+        let response = subscription_service
+            .ready()
+            .await?
+            .call(
+                SubscriptionRequest::fake_builder()
+                    .event(
+                        Response::builder()
+                            .data(serde_json_bytes::json!({ "reviewAdded": { "id": "1" } }))
+                            .build(),
+                    )
+                    .build(),
+            )
+            .await?;
+        assert_eq!(
+            response.event.data,
+            Some(serde_json_bytes::json!({ "reviewAdded": { "id": "1", "checked": true } }))
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn rhai_plugin_execution_service_error() -> Result<(), BoxError> {
         let mut mock_service = MockExecutionService::new();
@@ -25,6 +25,14 @@ pub struct Config {
         default = "client_version_header_default"
     )]
     pub client_version_header: HeaderName,
+
+    /// Whether to report the fields resolved by each operation. Defaults to false.
+    #[serde(default)]
+    pub field_level_instrumentation: bool,
+
+    /// Whether to send a trace of each operation along with the statistics. Defaults to false.
+    #[serde(default)]
+    pub send_traces: bool,
 }
 
 fn client_name_header_default_str() -> &'static str {
@@ -51,6 +59,8 @@ impl Default for Config {
             apollo_graph_ref: None,
             client_name_header: client_name_header_default(),
             client_version_header: client_version_header_default(),
+            field_level_instrumentation: false,
+            send_traces: false,
         }
     }
 }
@@ -4,7 +4,7 @@ use crate::plugins::telemetry::metrics;
 use opentelemetry::sdk::Resource;
 use opentelemetry::{Array, KeyValue, Value};
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::time::Duration;
@@ -54,7 +54,60 @@ pub struct Metrics {
 #[derive(Clone, Default, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub struct MetricsCommon {
+    #[serde(default)]
     pub delay_interval: Duration,
+    /// Value of the `service.name` resource attribute of the exported metrics.
+    pub service_name: Option<String>,
+    /// Value of the `service.namespace` resource attribute of the exported metrics.
+    pub service_namespace: Option<String>,
+    /// Other resource attributes of the exported metrics, such as `deployment.environment`.
+    pub attributes: Option<BTreeMap<String, AttributeValue>>,
+    /// Values of the `client_name` attribute, the name of the client sending the request.
+    #[serde(default)]
+    pub client_name: AttributeValues,
+    /// Values of the `client_version` attribute, the version of the client sending the request.
+    #[serde(default)]
+    pub client_version: AttributeValues,
+    /// Values of the `operation` attribute, the name clients give their operations.
+    #[serde(default)]
+    pub operation: AttributeValues,
+}
+
+/// Values of a metric attribute taken from requests. Other values are recorded as `other`, so that
+/// clients cannot create an unbounded number of time series.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub struct AttributeValues {
+    /// Values recorded as they are. Defaults to the first `max_values` distinct values seen.
+    #[serde(default)]
+    pub allowed: Option<Vec<String>>,
+    /// Most distinct values recorded when `allowed` is not set. Defaults to 100.
+    #[serde(default = "default_max_values")]
+    pub max_values: usize,
+}
+
+fn default_max_values() -> usize {
+    100
+}
+
+impl Default for AttributeValues {
+    fn default() -> Self {
+        Self {
+            allowed: None,
+            max_values: default_max_values(),
+        }
+    }
+}
+
+impl MetricsCommon {
+    /// Resource attributes of the metrics pushed to collectors.
+    pub fn resource(&self) -> Vec<KeyValue> {
+        resource(
+            &self.service_name,
+            &self.service_namespace,
+            self.attributes.as_ref(),
+        )
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize, JsonSchema)]
@@ -207,23 +260,40 @@ impl From<&Trace> for opentelemetry::sdk::trace::Config {
             trace_config = trace_config.with_max_attributes_per_link(n);
         }
 
-        let mut resource_defaults = vec![];
-        if let Some(service_name) = &config.service_name {
-            resource_defaults.push(KeyValue::new(
-                opentelemetry_semantic_conventions::resource::SERVICE_NAME,
-                service_name.clone(),
-            ));
-        }
-        if let Some(service_namespace) = &config.service_namespace {
-            resource_defaults.push(KeyValue::new(
-                opentelemetry_semantic_conventions::resource::SERVICE_NAMESPACE,
-                service_namespace.clone(),
-            ));
-        }
-        let resource = Resource::new(resource_defaults).merge(&mut Resource::new(
-            config
-                .attributes
-                .clone()
+        let resource = Resource::new(resource(
+            &config.service_name,
+            &config.service_namespace,
+            config.attributes.as_ref(),
+        ));
+
+        trace_config = trace_config.with_resource(resource);
+        trace_config
+    }
+}
+
+/// Service name and namespace, overridden by the attributes with the same keys.
+fn resource(
+    service_name: &Option<String>,
+    service_namespace: &Option<String>,
+    attributes: Option<&BTreeMap<String, AttributeValue>>,
+) -> Vec<KeyValue> {
+    let mut resource_defaults = vec![];
+    if let Some(service_name) = service_name {
+        resource_defaults.push(KeyValue::new(
+            opentelemetry_semantic_conventions::resource::SERVICE_NAME,
+            service_name.clone(),
+        ));
+    }
+    if let Some(service_namespace) = service_namespace {
+        resource_defaults.push(KeyValue::new(
+            opentelemetry_semantic_conventions::resource::SERVICE_NAMESPACE,
+            service_namespace.clone(),
+        ));
+    }
+    Resource::new(resource_defaults)
+        .merge(&mut Resource::new(
+            attributes
+                .cloned()
                 .unwrap_or_default()
                 .iter()
                 .map(|(k, v)| {
@@ -233,11 +303,10 @@ impl From<&Trace> for opentelemetry::sdk::trace::Config {
                     )
                 })
                 .collect::<Vec<KeyValue>>(),
-        ));
-
-        trace_config = trace_config.with_resource(resource);
-        trace_config
-    }
+        ))
+        .iter()
+        .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+        .collect()
 }
 
 fn parent_based(sampler: opentelemetry::sdk::trace::Sampler) -> opentelemetry::sdk::trace::Sampler {
@@ -1,11 +1,11 @@
-use crate::plugins::telemetry::config::MetricsCommon;
+use crate::plugins::telemetry::config::{AttributeValues, MetricsCommon};
 use apollo_router_core::{http_compat, Handler, ResponseBody};
 use bytes::Bytes;
-use opentelemetry::metrics::{Counter, Meter, MeterProvider, Number, ValueRecorder};
+use opentelemetry::metrics::{Counter, Meter, MeterProvider, Number, UpDownCounter, ValueRecorder};
 use opentelemetry::KeyValue;
 use std::any::Any;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use tower::util::BoxService;
 use tower::BoxError;
 
@@ -69,6 +69,24 @@ pub struct BasicMetrics {
     pub http_requests_total: AggregateCounter<u64>,
     pub http_requests_error_total: AggregateCounter<u64>,
     pub http_requests_duration: AggregateValueRecorder<f64>,
+    pub plugin_duration: AggregateValueRecorder<f64>,
+    pub query_plan_fetches: AggregateValueRecorder<u64>,
+    pub query_plan_depth: AggregateValueRecorder<u64>,
+    pub query_plan_subgraphs: AggregateValueRecorder<u64>,
+    pub circuit_breaker_rejections_total: AggregateCounter<u64>,
+    pub deduplicated_requests_total: AggregateCounter<u64>,
+    pub subgraph_open_connections: AggregateValueRecorder<u64>,
+    pub subgraph_in_flight_requests: AggregateValueRecorder<u64>,
+    pub batch_size: AggregateValueRecorder<u64>,
+    pub coalesced_fetches_total: AggregateCounter<u64>,
+    pub stage_requests_total: AggregateCounter<u64>,
+    pub stage_errors_total: AggregateCounter<u64>,
+    pub stage_duration: AggregateValueRecorder<f64>,
+    pub requests_in_flight: AggregateUpDownCounter<i64>,
+    pub open_connections: AggregateUpDownCounter<i64>,
+    pub cache_hits_total: AggregateCounter<u64>,
+    pub cache_misses_total: AggregateCounter<u64>,
+    pub schema_updates_total: AggregateCounter<u64>,
 }
 
 impl BasicMetrics {
@@ -90,10 +108,184 @@ impl BasicMetrics {
                     .with_description("Total number of HTTP requests made.")
                     .init()
             }),
+            plugin_duration: meter.build_value_recorder(|m| {
+                m.f64_value_recorder("plugin_duration_seconds")
+                    .with_description("Time spent in each plugin, by pipeline stage.")
+                    .init()
+            }),
+            query_plan_fetches: meter.build_value_recorder(|m| {
+                m.u64_value_recorder("query_plan_fetches")
+                    .with_description("Number of subgraph fetches in a query plan.")
+                    .init()
+            }),
+            query_plan_depth: meter.build_value_recorder(|m| {
+                m.u64_value_recorder("query_plan_depth")
+                    .with_description(
+                        "Number of subgraph fetches a query plan runs one after the other.",
+                    )
+                    .init()
+            }),
+            query_plan_subgraphs: meter.build_value_recorder(|m| {
+                m.u64_value_recorder("query_plan_subgraphs")
+                    .with_description("Number of distinct subgraphs fetched from by a query plan.")
+                    .init()
+            }),
+            circuit_breaker_rejections_total: meter.build_counter(|m| {
+                m.u64_counter("circuit_breaker_rejections_total")
+                    .with_description(
+                        "Total number of subgraph requests failed right away by an open circuit breaker.",
+                    )
+                    .init()
+            }),
+            deduplicated_requests_total: meter.build_counter(|m| {
+                m.u64_counter("deduplicated_requests_total")
+                    .with_description(
+                        "Total number of subgraph requests answered by an identical request in flight.",
+                    )
+                    .init()
+            }),
+            subgraph_open_connections: meter.build_value_recorder(|m| {
+                m.u64_value_recorder("subgraph_open_connections")
+                    .with_description(
+                        "Connections open to a subgraph, idle or not, when its responses are received.",
+                    )
+                    .init()
+            }),
+            subgraph_in_flight_requests: meter.build_value_recorder(|m| {
+                m.u64_value_recorder("subgraph_in_flight_requests")
+                    .with_description(
+                        "Requests waiting for an answer of a subgraph when its responses are received.",
+                    )
+                    .init()
+            }),
+            batch_size: meter.build_value_recorder(|m| {
+                m.u64_value_recorder("batch_size")
+                    .with_description("Number of requests in the batches sent by clients.")
+                    .init()
+            }),
+            coalesced_fetches_total: meter.build_counter(|m| {
+                m.u64_counter("coalesced_fetches_total")
+                    .with_description(
+                        "Total number of entity fetches merged into the request of another fetch.",
+                    )
+                    .init()
+            }),
+            stage_requests_total: meter.build_counter(|m| {
+                m.u64_counter("stage_requests_total")
+                    .with_description("Total number of requests handled by each pipeline stage.")
+                    .init()
+            }),
+            stage_errors_total: meter.build_counter(|m| {
+                m.u64_counter("stage_errors_total")
+                    .with_description("Total number of requests failed by each pipeline stage.")
+                    .init()
+            }),
+            stage_duration: meter.build_value_recorder(|m| {
+                m.f64_value_recorder("stage_duration_seconds")
+                    .with_description("Time spent by requests in each pipeline stage.")
+                    .init()
+            }),
+            requests_in_flight: meter.build_up_down_counter(|m| {
+                m.i64_up_down_counter("requests_in_flight")
+                    .with_description("Number of requests being handled by each pipeline stage.")
+                    .init()
+            }),
+            open_connections: meter.build_up_down_counter(|m| {
+                m.i64_up_down_counter("http_open_connections")
+                    .with_description("Number of connections of clients open to the router.")
+                    .init()
+            }),
+            cache_hits_total: meter.build_counter(|m| {
+                m.u64_counter("cache_hits_total")
+                    .with_description("Total number of lookups answered by each cache.")
+                    .init()
+            }),
+            cache_misses_total: meter.build_counter(|m| {
+                m.u64_counter("cache_misses_total")
+                    .with_description("Total number of lookups missing from each cache.")
+                    .init()
+            }),
+            schema_updates_total: meter.build_counter(|m| {
+                m.u64_counter("schema_updates_total")
+                    .with_description("Total number of supergraph schema updates applied.")
+                    .init()
+            }),
         }
     }
 }
 
+/// Counts a request as in flight until dropped, so that requests cancelled by their client are
+/// not counted forever.
+pub struct InFlight {
+    counter: AggregateUpDownCounter<i64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl InFlight {
+    pub fn new(counter: &AggregateUpDownCounter<i64>, attributes: Vec<KeyValue>) -> Self {
+        counter.add(1, &attributes);
+        Self {
+            counter: counter.clone(),
+            attributes,
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.counter.add(-1, &self.attributes);
+    }
+}
+
+/// Values of a metric attribute taken from requests, bounded so that clients sending many
+/// different values cannot create an unbounded number of time series.
+#[derive(Clone)]
+pub struct LabelValues {
+    allowed: Option<Arc<HashSet<String>>>,
+    max_values: usize,
+    seen: Arc<Mutex<HashSet<String>>>,
+}
+
+impl LabelValues {
+    pub fn new(config: &AttributeValues) -> Self {
+        Self {
+            allowed: config
+                .allowed
+                .as_ref()
+                .map(|allowed| Arc::new(allowed.iter().cloned().collect())),
+            max_values: config.max_values,
+            seen: Default::default(),
+        }
+    }
+
+    /// `value` if it is allowed or among the first values seen, `other` otherwise.
+    pub fn label(&self, value: &str) -> String {
+        let recorded = match &self.allowed {
+            Some(allowed) => allowed.contains(value),
+            None => {
+                let mut seen = self.seen.lock().expect("lock poisoned");
+                if seen.contains(value) || seen.len() < self.max_values {
+                    seen.insert(value.to_string());
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if recorded {
+            value.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+}
+
+impl Default for LabelValues {
+    fn default() -> Self {
+        Self::new(&AttributeValues::default())
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct AggregateMeterProvider(Vec<Arc<dyn MeterProvider + Send + Sync + 'static>>);
 impl AggregateMeterProvider {
@@ -127,6 +319,13 @@ impl AggregateMeter {
         AggregateCounter(self.0.iter().map(|m| build(m)).collect())
     }
 
+    pub fn build_up_down_counter<T: Into<Number> + Copy>(
+        &self,
+        build: fn(&Meter) -> UpDownCounter<T>,
+    ) -> AggregateUpDownCounter<T> {
+        AggregateUpDownCounter(self.0.iter().map(|m| build(m)).collect())
+    }
+
     pub fn build_value_recorder<T: Into<Number> + Copy>(
         &self,
         build: fn(&Meter) -> ValueRecorder<T>,
@@ -148,6 +347,19 @@ where
     }
 }
 
+#[derive(Clone)]
+pub struct AggregateUpDownCounter<T: Into<Number> + Copy>(Vec<UpDownCounter<T>>);
+impl<T> AggregateUpDownCounter<T>
+where
+    T: Into<Number> + Copy,
+{
+    pub fn add(&self, value: T, attributes: &[KeyValue]) {
+        for counter in &self.0 {
+            counter.add(value, attributes)
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AggregateValueRecorder<T: Into<Number> + Copy>(Vec<ValueRecorder<T>>);
 impl<T> AggregateValueRecorder<T>
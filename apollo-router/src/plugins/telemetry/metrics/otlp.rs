@@ -32,7 +32,7 @@ impl MetricsConfigurator for super::super::otlp::Config {
     fn apply(
         &self,
         mut builder: MetricsBuilder,
-        _metrics_config: &MetricsCommon,
+        metrics_config: &MetricsCommon,
     ) -> Result<MetricsBuilder, BoxError> {
         let exporter: MetricExporterBuilder = self.exporter()?;
         match exporter.exporter {
@@ -41,6 +41,7 @@ impl MetricsConfigurator for super::super::otlp::Config {
                     .metrics(tokio::spawn, delayed_interval)
                     .with_exporter(exporter)
                     .with_aggregator_selector(selectors::simple::Selector::Exact)
+                    .with_resource(metrics_config.resource())
                     .build()?;
                 builder = builder.with_meter_provider(exporter.provider());
                 builder = builder.with_exporter(exporter);
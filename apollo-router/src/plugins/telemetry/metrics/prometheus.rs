@@ -25,13 +25,13 @@ impl MetricsConfigurator for Config {
     ) -> Result<MetricsBuilder, BoxError> {
         if self.enabled {
             let exporter = opentelemetry_prometheus::exporter().try_init()?;
-            builder = builder.with_custom_endpoint(
-                "/prometheus",
-                PrometheusService {
-                    registry: exporter.registry().clone(),
-                }
-                .boxed(),
-            );
+            let service = PrometheusService {
+                registry: exporter.registry().clone(),
+            };
+            // `/metrics` is also served at the root of the router, where scrapers look by default.
+            builder = builder
+                .with_custom_endpoint("/prometheus", service.clone().boxed())
+                .with_custom_endpoint("/metrics", service.boxed());
             builder = builder.with_meter_provider(exporter.provider()?);
             builder = builder.with_exporter(exporter);
         }
@@ -1,21 +1,31 @@
 //! Telemetry customization.
+use crate::batching::{BATCH_INDEX_CONTEXT_KEY, BATCH_SIZE_CONTEXT_KEY};
+use crate::connection_limits::set_connections_recorder;
 use crate::plugins::telemetry::config::{MetricsCommon, Trace};
 use crate::plugins::telemetry::metrics::{
-    AggregateMeterProvider, BasicMetrics, MetricsBuilder, MetricsConfigurator,
-    MetricsExporterHandle,
+    AggregateMeterProvider, BasicMetrics, InFlight, LabelValues, MetricsBuilder,
+    MetricsConfigurator, MetricsExporterHandle,
 };
 use crate::plugins::telemetry::tracing::TracingConfigurator;
 use crate::subscriber::replace_layer;
 use ::tracing::{info_span, Span};
+use apollo_router_core::circuit_breaker::CircuitOpen;
+use apollo_router_core::deduplication::Deduplicated;
+use apollo_router_core::plugin::timing::{set_timings_recorder, PluginTiming, Stage};
 use apollo_router_core::{
-    http_compat, register_plugin, ExecutionRequest, ExecutionResponse, Handler, Plugin,
-    QueryPlannerRequest, QueryPlannerResponse, ResponseBody, RouterRequest, RouterResponse,
-    ServiceBuilderExt, SubgraphRequest, SubgraphResponse,
+    http_compat, register_plugin, CacheLookups, Context, ExecutionRequest, ExecutionResponse,
+    Handler, Plugin, PoolUsage, QueryPlanStats, QueryPlannerRequest, QueryPlannerResponse,
+    ResponseBody, RoutedTo, RouterRequest, RouterResponse, Schema, ServiceBuilderExt,
+    SubgraphRequest, SubgraphResponse, CACHE_LOOKUPS, CLIENT_NAME_CONTEXT_KEY,
+    CLIENT_VERSION_CONTEXT_KEY, COALESCED_FETCHES_CONTEXT_KEY, FIELD_USAGE_CONTEXT_KEY,
+    TRACE_ROOT_CONTEXT_KEY,
 };
 use apollo_spaceport::server::ReportSpaceport;
 use bytes::Bytes;
-use futures::FutureExt;
-use http::{HeaderValue, StatusCode};
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
+use http::header::HeaderName;
+use http::StatusCode;
 use opentelemetry::propagation::TextMapPropagator;
 use opentelemetry::sdk::propagation::{
     BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator,
@@ -26,6 +36,7 @@ use opentelemetry::{global, KeyValue};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 use std::time::Instant;
 use tower::steer::Steer;
 use tower::util::BoxService;
@@ -40,6 +51,15 @@ mod tracing;
 
 pub static ROUTER_SPAN_NAME: &str = "router";
 
+/// Router span attribute holding the fields resolved by the response, as JSON.
+pub(crate) const FIELD_USAGE_ATTRIBUTE: &str = "apollo_private.field_usage";
+
+/// Router span attribute holding the root node of the response, as JSON.
+pub(crate) const TRACE_ROOT_ATTRIBUTE: &str = "apollo_private.trace_root";
+
+/// Context key holding the operation attribute of the query plan metrics.
+const OPERATION_LABEL: &str = "apollo::telemetry::operation";
+
 pub struct Telemetry {
     config: config::Conf,
     tracer_provider: Option<opentelemetry::sdk::trace::TracerProvider>,
@@ -49,6 +69,10 @@ pub struct Telemetry {
     // shutdown exporter.
     _metrics_exporters: Vec<MetricsExporterHandle>,
     meter_provider: AggregateMeterProvider,
+    metrics: BasicMetrics,
+    client_name_labels: LabelValues,
+    client_version_labels: LabelValues,
+    operation_labels: LabelValues,
     custom_endpoints: HashMap<String, Handler>,
     spaceport_shutdown: Option<futures::channel::oneshot::Sender<()>>,
 }
@@ -142,6 +166,19 @@ impl Plugin for Telemetry {
         opentelemetry::global::set_error_handler(handle_error)
             .expect("otel error handler lock poisoned, fatal");
         global::set_text_map_propagator(Self::create_propagator(&self.config));
+        // The timings of a request are only complete once the probes around this plugin are done.
+        let metrics = self.metrics.clone();
+        let connection_metrics = metrics.clone();
+        set_timings_recorder(Arc::new(move |timings: &[PluginTiming]| {
+            Self::record_plugin_timings(&metrics, timings)
+        }));
+        set_connections_recorder(Arc::new(move |change| {
+            connection_metrics.open_connections.add(change, &[])
+        }));
+    }
+
+    fn schema_changed(&mut self, _previous: &Schema, _schema: &Schema) {
+        self.metrics.schema_updates_total.add(1, &[]);
     }
 
     async fn new(mut config: Self::Config) -> Result<Self, BoxError> {
@@ -192,12 +229,22 @@ impl Plugin for Telemetry {
         // Don't add anything fallible after the tracer provider has been created.
         let tracer_provider = Self::create_tracer_provider(&config)?;
 
+        let metrics_common = config
+            .metrics
+            .as_ref()
+            .and_then(|metrics| metrics.common.clone())
+            .unwrap_or_default();
+        let meter_provider = builder.meter_provider();
         let plugin = Ok(Telemetry {
             spaceport_shutdown: shutdown_tx,
             tracer_provider: Some(tracer_provider),
             custom_endpoints: builder.custom_endpoints(),
             _metrics_exporters: builder.exporters(),
-            meter_provider: builder.meter_provider(),
+            metrics: BasicMetrics::new(&meter_provider),
+            meter_provider,
+            client_name_labels: LabelValues::new(&metrics_common.client_name),
+            client_version_labels: LabelValues::new(&metrics_common.client_version),
+            operation_labels: LabelValues::new(&metrics_common.operation),
             config,
         });
 
@@ -227,25 +274,59 @@ impl Plugin for Telemetry {
         service: BoxService<RouterRequest, RouterResponse, BoxError>,
     ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
         let metrics = BasicMetrics::new(&self.meter_provider);
+        let stage_metrics = metrics.clone();
+        let batch_metrics = metrics.clone();
+        let client_labels = (
+            self.client_name_labels.clone(),
+            self.client_version_labels.clone(),
+        );
+        let apollo = self.config.apollo.clone().unwrap_or_default();
+        let field_level_instrumentation = apollo.field_level_instrumentation;
+        let send_traces = apollo.send_traces;
         ServiceBuilder::new()
-            .instrument(Self::router_service_span(
-                self.config.apollo.clone().unwrap_or_default(),
-            ))
+            .instrument(Self::router_service_span(apollo))
+            .map_request(move |request: RouterRequest| {
+                Self::record_batch_size(&batch_metrics, &request.context);
+                if field_level_instrumentation {
+                    // Asks the router service for the fields resolved by the response.
+                    let _ = request
+                        .context
+                        .insert(FIELD_USAGE_CONTEXT_KEY, serde_json::json!({}));
+                }
+                if send_traces {
+                    // And for the nodes of the response, making up the traces.
+                    let _ = request
+                        .context
+                        .insert(TRACE_ROOT_CONTEXT_KEY, serde_json::json!({}));
+                }
+                request
+            })
+            .map_future(|f: BoxFuture<'static, Result<RouterResponse, BoxError>>| {
+                f.map(|r| {
+                    if let Ok(response) = &r {
+                        Self::record_field_usage(&response.context);
+                    }
+                    r
+                })
+            })
             .service(service)
             .map_future(move |f| {
                 let metrics = metrics.clone();
+                let client_labels = client_labels.clone();
                 // Using Instant because it is guaranteed to be monotonically increasing.
                 let now = Instant::now();
                 f.map(move |r: Result<RouterResponse, BoxError>| {
                     match &r {
                         Ok(response) => {
-                            metrics.http_requests_total.add(
-                                1,
-                                &[KeyValue::new(
-                                    "status",
-                                    response.response.status().as_u16().to_string(),
-                                )],
-                            );
+                            let mut attributes = vec![KeyValue::new(
+                                "status",
+                                response.response.status().as_u16().to_string(),
+                            )];
+                            attributes
+                                .extend(Self::client_attributes(&response.context, &client_labels));
+                            metrics.http_requests_total.add(1, &attributes);
+                            Self::record_cache_lookups(&metrics, &response.context);
+                            Self::record_coalesced_fetches(&metrics, &response.context);
                         }
                         Err(_) => {
                             metrics.http_requests_error_total.add(1, &[]);
@@ -257,6 +338,7 @@ impl Plugin for Telemetry {
                     r
                 })
             })
+            .map_future(move |f| Self::record_stage(&stage_metrics, Stage::Router, Vec::new(), f))
             .boxed()
     }
 
@@ -264,9 +346,37 @@ impl Plugin for Telemetry {
         &mut self,
         service: BoxService<QueryPlannerRequest, QueryPlannerResponse, BoxError>,
     ) -> BoxService<QueryPlannerRequest, QueryPlannerResponse, BoxError> {
+        let metrics = BasicMetrics::new(&self.meter_provider);
+        let stage_metrics = metrics.clone();
+        let operation_labels = self.operation_labels.clone();
         ServiceBuilder::new()
             .instrument(move |_| info_span!("query_planning", "otel.kind" = %SpanKind::Internal))
             .service(service)
+            .map_request(move |request: QueryPlannerRequest| {
+                let operation = request
+                    .originating_request
+                    .body()
+                    .operation_name
+                    .as_deref()
+                    .unwrap_or("anonymous");
+                let _ = request
+                    .context
+                    .insert(OPERATION_LABEL, operation_labels.label(operation));
+                request
+            })
+            .map_response(move |response: QueryPlannerResponse| {
+                let operation: String = response
+                    .context
+                    .get(OPERATION_LABEL)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                Self::record_query_plan(&metrics, response.query_plan.stats(), operation);
+                response
+            })
+            .map_future(move |f| {
+                Self::record_stage(&stage_metrics, Stage::QueryPlanning, Vec::new(), f)
+            })
             .boxed()
     }
 
@@ -274,9 +384,11 @@ impl Plugin for Telemetry {
         &mut self,
         service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
     ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        let metrics = BasicMetrics::new(&self.meter_provider);
         ServiceBuilder::new()
             .instrument(move |_| info_span!("execution", "otel.kind" = %SpanKind::Internal))
             .service(service)
+            .map_future(move |f| Self::record_stage(&metrics, Stage::Execution, Vec::new(), f))
             .boxed()
     }
 
@@ -286,7 +398,9 @@ impl Plugin for Telemetry {
         service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
     ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
         let metrics = BasicMetrics::new(&self.meter_provider);
+        let stage_metrics = metrics.clone();
         let subgraph_attribute = KeyValue::new("subgraph", name.to_string());
+        let stage_attributes = vec![subgraph_attribute.clone()];
         let name = name.to_owned();
         ServiceBuilder::new()
             .instrument(move |_| info_span!("subgraph", name = name.as_str(), "otel.kind" = %SpanKind::Client))
@@ -299,21 +413,47 @@ impl Plugin for Telemetry {
                 f.map(move |r| {
                     match &r {
                         Ok(response) => {
-                            metrics.http_requests_total.add(
-                                1,
-                                &[
-                                    KeyValue::new(
-                                        "status",
-                                        response.response.status().as_u16().to_string(),
-                                    ),
-                                    subgraph_attribute.clone(),
-                                ],
-                            );
+                            let mut attributes = vec![
+                                KeyValue::new(
+                                    "status",
+                                    response.response.status().as_u16().to_string(),
+                                ),
+                                subgraph_attribute.clone(),
+                            ];
+                            // Requests shared between several endpoints are counted by target.
+                            if let Some(RoutedTo(target)) =
+                                response.response.extensions().get::<RoutedTo>()
+                            {
+                                attributes.push(KeyValue::new("target", target.clone()));
+                            }
+                            metrics.http_requests_total.add(1, &attributes);
+                            if response.response.extensions().get::<Deduplicated>().is_some() {
+                                metrics
+                                    .deduplicated_requests_total
+                                    .add(1, &[subgraph_attribute.clone()]);
+                            }
+                            if let Some(usage) =
+                                response.response.extensions().get::<PoolUsage>()
+                            {
+                                metrics.subgraph_open_connections.record(
+                                    usage.open_connections as u64,
+                                    &[subgraph_attribute.clone()],
+                                );
+                                metrics.subgraph_in_flight_requests.record(
+                                    usage.in_flight_requests as u64,
+                                    &[subgraph_attribute.clone()],
+                                );
+                            }
                         }
-                        Err(_) => {
+                        Err(err) => {
                             metrics
                                 .http_requests_error_total
                                 .add(1, &[subgraph_attribute.clone()]);
+                            if err.is::<CircuitOpen>() {
+                                metrics
+                                    .circuit_breaker_rejections_total
+                                    .add(1, &[subgraph_attribute.clone()]);
+                            }
                         }
                     }
                     metrics
@@ -322,6 +462,9 @@ impl Plugin for Telemetry {
                     r
                 })
             })
+            .map_future(move |f| {
+                Self::record_stage(&stage_metrics, Stage::Subgraph, stage_attributes.clone(), f)
+            })
             .boxed()
     }
 
@@ -438,6 +581,121 @@ impl Telemetry {
         futures::executor::block_on(jh).expect("failed to replace tracer provider");
     }
 
+    fn record_plugin_timings(metrics: &BasicMetrics, timings: &[PluginTiming]) {
+        for timing in timings {
+            metrics.plugin_duration.record(
+                timing.duration,
+                &[
+                    KeyValue::new("plugin", timing.plugin.clone()),
+                    KeyValue::new("stage", timing.stage.as_str()),
+                ],
+            );
+        }
+    }
+
+    /// Records the field usage and the nodes of the response on the router span, which is the
+    /// current span while the response is being produced.
+    fn record_field_usage(context: &Context) {
+        for (key, attribute) in [
+            (FIELD_USAGE_CONTEXT_KEY, FIELD_USAGE_ATTRIBUTE),
+            (TRACE_ROOT_CONTEXT_KEY, TRACE_ROOT_ATTRIBUTE),
+        ] {
+            let value: Option<serde_json::Value> = context.get(key).ok().flatten();
+            if let Some(value) = value {
+                Span::current().record(attribute, &value.to_string().as_str());
+            }
+        }
+    }
+
+    fn record_cache_lookups(metrics: &BasicMetrics, context: &Context) {
+        let lookups: HashMap<String, CacheLookups> = context
+            .get(CACHE_LOOKUPS)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        for (cache, lookups) in lookups {
+            let attributes = [KeyValue::new("cache", cache)];
+            metrics.cache_hits_total.add(lookups.hits, &attributes);
+            metrics.cache_misses_total.add(lookups.misses, &attributes);
+        }
+    }
+
+    fn record_coalesced_fetches(metrics: &BasicMetrics, context: &Context) {
+        if let Ok(Some(coalesced)) = context.get::<_, u64>(COALESCED_FETCHES_CONTEXT_KEY) {
+            metrics.coalesced_fetches_total.add(coalesced, &[]);
+        }
+    }
+
+    /// Counts the requests of a pipeline stage, while they are in flight and once they are
+    /// answered, and records how long they took.
+    fn record_stage<F, T>(
+        metrics: &BasicMetrics,
+        stage: Stage,
+        mut attributes: Vec<KeyValue>,
+        f: F,
+    ) -> impl Future<Output = Result<T, BoxError>>
+    where
+        F: Future<Output = Result<T, BoxError>>,
+    {
+        attributes.push(KeyValue::new("stage", stage.as_str()));
+        let in_flight = InFlight::new(&metrics.requests_in_flight, attributes.clone());
+        let metrics = metrics.clone();
+        // Using Instant because it is guaranteed to be monotonically increasing.
+        let now = Instant::now();
+        f.map(move |r| {
+            drop(in_flight);
+            metrics.stage_requests_total.add(1, &attributes);
+            if r.is_err() {
+                metrics.stage_errors_total.add(1, &attributes);
+            }
+            metrics
+                .stage_duration
+                .record(now.elapsed().as_secs_f64(), &attributes);
+            r
+        })
+    }
+
+    /// The name and version of the client of a request, as metric attributes bounded by their
+    /// `labels`.
+    fn client_attributes(context: &Context, labels: &(LabelValues, LabelValues)) -> Vec<KeyValue> {
+        [
+            ("client_name", CLIENT_NAME_CONTEXT_KEY, &labels.0),
+            ("client_version", CLIENT_VERSION_CONTEXT_KEY, &labels.1),
+        ]
+        .into_iter()
+        .filter_map(|(name, key, labels)| {
+            context
+                .get::<_, String>(key)
+                .ok()
+                .flatten()
+                .map(|value| KeyValue::new(name, labels.label(&value)))
+        })
+        .collect()
+    }
+
+    fn record_query_plan(metrics: &BasicMetrics, stats: QueryPlanStats, operation: String) {
+        let attributes = [KeyValue::new("operation", operation)];
+        metrics
+            .query_plan_fetches
+            .record(stats.fetch_count as u64, &attributes);
+        metrics
+            .query_plan_depth
+            .record(stats.depth as u64, &attributes);
+        metrics
+            .query_plan_subgraphs
+            .record(stats.subgraph_count as u64, &attributes);
+    }
+
+    /// Records the size of a batch along with its first request.
+    fn record_batch_size(metrics: &BasicMetrics, context: &Context) {
+        if let (Ok(Some(0)), Ok(Some(size))) = (
+            context.get::<_, usize>(BATCH_INDEX_CONTEXT_KEY),
+            context.get::<_, u64>(BATCH_SIZE_CONTEXT_KEY),
+        ) {
+            metrics.batch_size.record(size, &[]);
+        }
+    }
+
     fn router_service_span(config: apollo::Config) -> impl Fn(&RouterRequest) -> Span + Clone {
         let client_name_header = config.client_name_header;
         let client_version_header = config.client_version_header;
@@ -451,21 +709,33 @@ impl Telemetry {
                 .operation_name
                 .clone()
                 .unwrap_or_default();
-            let client_name = headers
-                .get(&client_name_header)
-                .cloned()
-                .unwrap_or_else(|| HeaderValue::from_static(""));
-            let client_version = headers
-                .get(&client_version_header)
-                .cloned()
-                .unwrap_or_else(|| HeaderValue::from_static(""));
+            // The client identity found by the server comes first, the headers of the Apollo
+            // configuration being kept for the setups that relied on them.
+            let client = |key: &str, header: &HeaderName| {
+                request
+                    .context
+                    .get::<_, String>(key)
+                    .ok()
+                    .flatten()
+                    .or_else(|| {
+                        headers
+                            .get(header)
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string)
+                    })
+                    .unwrap_or_default()
+            };
+            let client_name = client(CLIENT_NAME_CONTEXT_KEY, &client_name_header);
+            let client_version = client(CLIENT_VERSION_CONTEXT_KEY, &client_version_header);
             let span = info_span!(
                 ROUTER_SPAN_NAME,
                 query = query.as_str(),
                 operation_name = operation_name.as_str(),
-                client_name = client_name.to_str().unwrap_or_default(),
-                client_version = client_version.to_str().unwrap_or_default(),
-                "otel.kind" = %SpanKind::Internal
+                client_name = client_name.as_str(),
+                client_version = client_version.as_str(),
+                "otel.kind" = %SpanKind::Internal,
+                apollo_private.field_usage = ::tracing::field::Empty,
+                apollo_private.trace_root = ::tracing::field::Empty
             );
             span
         }
@@ -490,6 +760,7 @@ register_plugin!("apollo", "telemetry", Telemetry);
 
 #[cfg(test)]
 mod tests {
+    use super::*;
 
     #[tokio::test]
     async fn plugin_registered() {
@@ -527,4 +798,145 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[test]
+    fn metrics_resources_are_configurable() {
+        let common: MetricsCommon = serde_json::from_value(serde_json::json!({
+            "service_name": "router",
+            "attributes": {
+                "deployment.environment": "staging",
+                "service.name": "gateway"
+            }
+        }))
+        .unwrap();
+
+        let mut resource: Vec<_> = common
+            .resource()
+            .into_iter()
+            .map(|kv| (kv.key.as_str().to_string(), kv.value.to_string()))
+            .collect();
+        resource.sort();
+        assert_eq!(
+            resource,
+            [
+                ("deployment.environment".to_string(), "staging".to_string()),
+                ("service.name".to_string(), "gateway".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_plan_metrics_are_recorded() {
+        let exporter = opentelemetry_prometheus::exporter().init();
+        let meter_provider = AggregateMeterProvider::new(vec![Arc::new(
+            exporter.provider().expect("prometheus provider"),
+        )]);
+        let metrics = BasicMetrics::new(&meter_provider);
+
+        Telemetry::record_query_plan(
+            &metrics,
+            QueryPlanStats {
+                fetch_count: 2,
+                depth: 2,
+                subgraph_count: 2,
+            },
+            "TopProducts".to_string(),
+        );
+
+        let fetches = exporter
+            .registry()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "query_plan_fetches")
+            .expect("fetch count histogram not found");
+        let metric = &fetches.get_metric()[0];
+        assert!(metric
+            .get_label()
+            .iter()
+            .any(|label| label.get_name() == "operation" && label.get_value() == "TopProducts"));
+        assert_eq!(metric.get_histogram().get_sample_count(), 1);
+        assert_eq!(metric.get_histogram().get_sample_sum(), 2.0);
+    }
+
+    #[test]
+    fn cache_lookups_are_counted_by_cache() {
+        let exporter = opentelemetry_prometheus::exporter().init();
+        let meter_provider = AggregateMeterProvider::new(vec![Arc::new(
+            exporter.provider().expect("prometheus provider"),
+        )]);
+        let metrics = BasicMetrics::new(&meter_provider);
+
+        let context = Context::new();
+        apollo_router_core::record_cache_lookups(&context, "query_plan", 1, 0);
+        apollo_router_core::record_cache_lookups(&context, "query_plan", 0, 1);
+        apollo_router_core::record_cache_lookups(&context, "query_plan", 1, 0);
+        Telemetry::record_cache_lookups(&metrics, &context);
+
+        let families = exporter.registry().gather();
+        let count = |name: &str| {
+            let family = families
+                .iter()
+                .find(|family| family.get_name() == name)
+                .expect("cache counter not found");
+            let metric = &family.get_metric()[0];
+            assert!(metric
+                .get_label()
+                .iter()
+                .any(|label| label.get_name() == "cache" && label.get_value() == "query_plan"));
+            metric.get_counter().get_value()
+        };
+        assert_eq!(count("cache_hits_total"), 2.0);
+        assert_eq!(count("cache_misses_total"), 1.0);
+    }
+
+    #[test]
+    fn operation_labels_are_bounded() {
+        let labels = LabelValues::default();
+        for i in 0..100 {
+            assert_eq!(labels.label(&format!("op{}", i)), format!("op{}", i));
+        }
+        assert_eq!(labels.label("op100"), "other");
+        assert_eq!(labels.label("op0"), "op0");
+    }
+
+    #[test]
+    fn operation_labels_can_be_allowed() {
+        let common: MetricsCommon = serde_json::from_value(serde_json::json!({
+            "operation": { "allowed": ["TopProducts"] }
+        }))
+        .unwrap();
+        let labels = LabelValues::new(&common.operation);
+        assert_eq!(labels.label("TopProducts"), "TopProducts");
+        assert_eq!(labels.label("Me"), "other");
+    }
+
+    #[test]
+    fn client_labels_are_allowed_or_bounded() {
+        let common: MetricsCommon = serde_json::from_value(serde_json::json!({
+            "client_name": { "allowed": ["web", "ios"] },
+            "client_version": { "max_values": 1 }
+        }))
+        .unwrap();
+        let labels = (
+            LabelValues::new(&common.client_name),
+            LabelValues::new(&common.client_version),
+        );
+        let attributes = |name: &str, version: &str| {
+            let context = Context::new();
+            context
+                .insert(CLIENT_NAME_CONTEXT_KEY, name.to_string())
+                .unwrap();
+            context
+                .insert(CLIENT_VERSION_CONTEXT_KEY, version.to_string())
+                .unwrap();
+            Telemetry::client_attributes(&context, &labels)
+                .into_iter()
+                .map(|kv| kv.value.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(attributes("web", "1.0"), ["web", "1.0"]);
+        assert_eq!(attributes("curl", "2.0"), ["other", "other"]);
+        assert_eq!(attributes("ios", "1.0"), ["ios", "1.0"]);
+    }
 }
@@ -14,6 +14,7 @@ impl TracingConfigurator for Config {
                 endpoint: Some(endpoint),
                 apollo_key: Some(key),
                 apollo_graph_ref: Some(reference),
+                send_traces,
                 ..
             } => {
                 tracing::debug!("configuring exporter to Spaceport");
@@ -26,6 +27,7 @@ impl TracingConfigurator for Config {
                     .with_spaceport_config(&Some(SpaceportConfig {
                         collector: endpoint.to_string(),
                     }))
+                    .with_traces(*send_traces)
                     .build_exporter()?;
                 builder.with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
             }
@@ -26,9 +26,12 @@
 //!     shutdown_tracer_provider(); // sending remaining spans
 //! }
 //! ```
-use crate::plugins::telemetry::ROUTER_SPAN_NAME;
+use crate::plugins::telemetry::{FIELD_USAGE_ATTRIBUTE, ROUTER_SPAN_NAME, TRACE_ROOT_ATTRIBUTE};
 use apollo_parser::{ast, Parser};
-use apollo_spaceport::report::{ContextualizedStats, QueryLatencyStats, StatsContext};
+use apollo_router_core::{FieldUsage, TraceNode};
+use apollo_spaceport::report::{
+    trace, ContextualizedStats, FieldStat, QueryLatencyStats, StatsContext, Trace, TypeStat,
+};
 use apollo_spaceport::{Reporter, ReporterGraph};
 use async_trait::async_trait;
 use derivative::Derivative;
@@ -99,6 +102,7 @@ pub struct PipelineBuilder {
     graph_config: Option<StudioGraph>,
     spaceport_config: Option<SpaceportConfig>,
     trace_config: Option<sdk::trace::Config>,
+    send_traces: bool,
 }
 
 /// Create a new apollo telemetry exporter pipeline builder.
@@ -113,6 +117,7 @@ impl Default for PipelineBuilder {
             graph_config: None,
             spaceport_config: None,
             trace_config: None,
+            send_traces: false,
         }
     }
 }
@@ -141,6 +146,12 @@ impl PipelineBuilder {
         self
     }
 
+    /// Send a trace of each operation along with the statistics.
+    pub fn with_traces(mut self, send_traces: bool) -> Self {
+        self.send_traces = send_traces;
+        self
+    }
+
     /// Install the apollo telemetry exporter pipeline with the recommended defaults.
     pub fn install_batch(mut self) -> Result<sdk::trace::Tracer, ApolloError> {
         let exporter = self.build_exporter()?;
@@ -253,7 +264,9 @@ impl PipelineBuilder {
         tracing::debug!("collector: {}", collector);
         tracing::debug!("graph: {:?}", graph);
 
-        Ok(Exporter::new(collector, graph))
+        let mut exporter = Exporter::new(collector, graph);
+        exporter.send_traces = self.send_traces;
+        Ok(exporter)
     }
 }
 
@@ -267,6 +280,7 @@ pub struct Exporter {
     graph: Option<StudioGraph>,
     reporter: tokio::sync::OnceCell<Reporter>,
     normalized_queries: HashMap<String, String>,
+    send_traces: bool,
 }
 
 impl Exporter {
@@ -277,6 +291,7 @@ impl Exporter {
             graph,
             reporter: tokio::sync::OnceCell::new(),
             normalized_queries: HashMap::new(),
+            send_traces: false,
         }
     }
 }
@@ -331,6 +346,9 @@ impl SpanExporter for Exporter {
         // After processing the batch, we consume the HashMap and send the generated reports
         // to the Reporter.
         let mut dh_map = HashMap::new();
+        // Field statistics, keyed like the duration histograms.
+        let mut field_stats_map: HashMap<_, HashMap<String, TypeStat>> = HashMap::new();
+        let mut traces = Vec::new();
         /*
          * Process the batch
          */
@@ -376,9 +394,36 @@ impl SpanExporter for Exporter {
                     .entry(query.as_str().to_string())
                     .or_insert_with(|| stats_report_key(operation_name, &query.as_str()));
 
+                if self.send_traces {
+                    traces.push((
+                        key.clone(),
+                        Trace {
+                            start_time: Some(span.start_time.into()),
+                            end_time: Some(span.end_time.into()),
+                            duration_ns: elapsed.as_nanos() as u64,
+                            client_name: client_name.clone(),
+                            client_version: client_version.clone(),
+                            root: span
+                                .attributes
+                                .get(&opentelemetry::Key::from_static_str(TRACE_ROOT_ATTRIBUTE))
+                                .and_then(|root| trace_root(&root.as_str())),
+                            ..Default::default()
+                        },
+                    ));
+                }
+
+                let report_key = (client_name, client_version, key.clone());
+                if let Some(usage) = span
+                    .attributes
+                    .get(&opentelemetry::Key::from_static_str(FIELD_USAGE_ATTRIBUTE))
+                {
+                    let per_type_stat = field_stats_map.entry(report_key.clone()).or_default();
+                    add_field_usage(per_type_stat, &usage.as_str());
+                }
+
                 // Retrieve DurationHistogram from our HashMap, or add a new one
                 let dh = dh_map
-                    .entry((client_name, client_version, key.clone()))
+                    .entry(report_key)
                     .or_insert_with(|| DurationHistogram::new(None));
                 dh.increment_duration(elapsed, 1);
             }
@@ -397,8 +442,10 @@ impl SpanExporter for Exporter {
         let graph: ReporterGraph = self.graph.as_ref().unwrap().into();
 
         // Report our consolidated statistics
-        for ((client_name, client_version, key), dh) in dh_map.into_iter() {
+        for (report_key, dh) in dh_map.into_iter() {
             tracing::debug!("reporting entries: {}", dh.entries);
+            let per_type_stat = field_stats_map.remove(&report_key).unwrap_or_default();
+            let (client_name, client_version, key) = report_key;
             let stats = ContextualizedStats {
                 context: Some(StatsContext {
                     client_name,
@@ -409,6 +456,7 @@ impl SpanExporter for Exporter {
                     request_count: dh.entries,
                     ..Default::default()
                 }),
+                per_type_stat,
                 ..Default::default()
             };
 
@@ -421,10 +469,77 @@ impl SpanExporter for Exporter {
             tracing::debug!("server response: {}", msg);
         }
 
+        for (key, trace) in traces {
+            reporter
+                .submit_trace(graph.clone(), key, trace)
+                .await
+                .map_err::<TraceError, _>(|e| e.to_string().into())?;
+        }
+
         Ok(())
     }
 }
 
+/// Adds the field usage recorded on a router span, as JSON, to the statistics of its operation.
+fn add_field_usage(per_type_stat: &mut HashMap<String, TypeStat>, usage: &str) {
+    let usage: HashMap<String, HashMap<String, FieldUsage>> = match serde_json::from_str(usage) {
+        Ok(usage) => usage,
+        Err(err) => {
+            tracing::debug!("could not read the field usage: {}", err);
+            return;
+        }
+    };
+    for (type_name, fields) in usage {
+        let type_stat = per_type_stat.entry(type_name).or_default();
+        for (field_name, usage) in fields {
+            let field_stat = type_stat
+                .per_field_stat
+                .entry(field_name)
+                .or_insert_with(|| FieldStat {
+                    return_type: usage.return_type,
+                    ..Default::default()
+                });
+            field_stat.observed_execution_count += usage.count;
+            field_stat.estimated_execution_count += usage.count as f64;
+        }
+    }
+}
+
+/// The root node of a trace, from the nodes of the response recorded on a router span, as JSON.
+fn trace_root(root: &str) -> Option<trace::Node> {
+    match serde_json::from_str::<TraceNode>(root) {
+        Ok(root) => Some(trace_node(root)),
+        Err(err) => {
+            tracing::debug!("could not read the trace nodes: {}", err);
+            None
+        }
+    }
+}
+
+fn trace_node(node: TraceNode) -> trace::Node {
+    let id = match (node.response_name, node.index) {
+        (Some(response_name), _) => Some(trace::node::Id::ResponseName(response_name)),
+        (None, Some(index)) => Some(trace::node::Id::Index(index)),
+        (None, None) => None,
+    };
+    trace::Node {
+        id,
+        original_field_name: node.original_field_name.unwrap_or_default(),
+        r#type: node.return_type,
+        parent_type: node.parent_type,
+        error: node
+            .errors
+            .into_iter()
+            .map(|message| trace::Error {
+                message,
+                ..Default::default()
+            })
+            .collect(),
+        child: node.children.into_iter().map(trace_node).collect(),
+        ..Default::default()
+    }
+}
+
 // Taken from TS implementation
 static GRAPHQL_PARSE_FAILURE: &str = "## GraphQLParseFailure\n";
 #[allow(dead_code)]
@@ -769,4 +884,33 @@ mod test {
         let key = stats_report_key(&op_name, query);
         assert_eq!(expected, key);
     }
+
+    #[test]
+    fn it_adds_up_field_usage() {
+        let mut per_type_stat = HashMap::new();
+        let usage = r#"{"Query":{"products":{"return_type":"[Product]","count":1}},"Product":{"name":{"return_type":"String","count":2}}}"#;
+        add_field_usage(&mut per_type_stat, usage);
+        add_field_usage(&mut per_type_stat, usage);
+        add_field_usage(&mut per_type_stat, "not json");
+
+        let products = &per_type_stat["Query"].per_field_stat["products"];
+        assert_eq!(products.return_type, "[Product]");
+        assert_eq!(products.observed_execution_count, 2);
+        let name = &per_type_stat["Product"].per_field_stat["name"];
+        assert_eq!(name.return_type, "String");
+        assert_eq!(name.observed_execution_count, 4);
+    }
+
+    #[test]
+    fn it_builds_the_trace_root() {
+        let root = r#"{"children":[{"response_name":"me","return_type":"User","parent_type":"Query","children":[{"response_name":"name","return_type":"String","parent_type":"User","errors":["no name"]}]},{"response_name":"products","return_type":"[Product]","parent_type":"Query","children":[{"index":0}]}]}"#;
+        let root = trace_root(root).unwrap();
+        assert_eq!(root.id, None);
+        let me = &root.child[0];
+        assert_eq!(me.id, Some(trace::node::Id::ResponseName("me".to_string())));
+        assert_eq!(me.r#type, "User");
+        assert_eq!(me.child[0].error[0].message, "no name");
+        assert_eq!(root.child[1].child[0].id, Some(trace::node::Id::Index(0)));
+        assert!(trace_root("not json").is_none());
+    }
 }
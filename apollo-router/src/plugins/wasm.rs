@@ -0,0 +1,347 @@
+//! Customization via WebAssembly modules.
+//!
+//! Each module is an in-process coprocessor: it is handed the same JSON as the
+//! [coprocessor](super::coprocessor) plugin, and answers with the same changes. A module exports:
+//! * its `memory`,
+//! * `alloc(len: i32) -> i32`, returning where `len` bytes of input may be written,
+//! * a function for each stage it handles, named `router_request`, `router_response`,
+//!   `subgraph_request` or `subgraph_response`, taking the pointer and length of the JSON input
+//!   and returning the pointer of its JSON answer in the high 32 bits and its length in the low 32
+//!   bits. An answer of length zero leaves everything as it is.
+//!
+//! Every call runs in a fresh instance, so that nothing is shared between requests, with bounded
+//! fuel and memory. Answers must lie within the memory of the module and be at most
+//! `max_answer_size` bytes long. Modules are compiled when the plugin is created, so changes to them are picked
+//! up when the configuration is reloaded.
+
+use super::coprocessor::{
+    externalized_router_service, externalized_subgraph_service, Extension, Externalized, Mutations,
+    Stage,
+};
+use apollo_router_core::{
+    register_plugin, Plugin, RouterRequest, RouterResponse, SubgraphRequest, SubgraphResponse,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tower::util::BoxService;
+use tower::BoxError;
+use wasmtime::{Engine, Linker, Module, Store, StoreLimitsBuilder};
+
+const STAGES: [Stage; 4] = [
+    Stage::RouterRequest,
+    Stage::RouterResponse,
+    Stage::SubgraphRequest,
+    Stage::SubgraphResponse,
+];
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Paths of the modules, called in this order on requests and in the reverse order on
+    /// responses.
+    modules: Vec<PathBuf>,
+    /// Fuel a call may consume, about one unit per instruction. Defaults to 10000000.
+    #[serde(default = "default_fuel")]
+    fuel: u64,
+    /// Size in bytes the memory of a module may grow to. Defaults to 16777216.
+    #[serde(default = "default_max_memory")]
+    max_memory: usize,
+    /// Size in bytes of the longest answer a module may give. Defaults to 1048576.
+    #[serde(default = "default_max_answer_size")]
+    max_answer_size: usize,
+}
+
+fn default_fuel() -> u64 {
+    10_000_000
+}
+
+fn default_max_memory() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_max_answer_size() -> usize {
+    1024 * 1024
+}
+
+fn hook_name(stage: Stage) -> &'static str {
+    match stage {
+        Stage::RouterRequest => "router_request",
+        Stage::RouterResponse => "router_response",
+        Stage::SubgraphRequest => "subgraph_request",
+        Stage::SubgraphResponse => "subgraph_response",
+    }
+}
+
+/// A compiled module, with the stages it exports a function for.
+#[derive(Clone)]
+struct WasmModule {
+    path: Arc<PathBuf>,
+    engine: Engine,
+    module: Module,
+    stages: Vec<Stage>,
+    fuel: u64,
+    max_memory: usize,
+    max_answer_size: usize,
+}
+
+impl std::fmt::Debug for WasmModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmModule")
+            .field("path", &self.path)
+            .field("stages", &self.stages)
+            .finish()
+    }
+}
+
+impl WasmModule {
+    fn new(engine: &Engine, path: PathBuf, config: &Config) -> Result<Self, BoxError> {
+        let module = Module::from_file(engine, &path)
+            .map_err(|err| format!("could not load {}: {}", path.display(), err))?;
+        let stages = STAGES
+            .into_iter()
+            .filter(|stage| {
+                module
+                    .exports()
+                    .any(|export| export.name() == hook_name(*stage))
+            })
+            .collect();
+        Ok(Self {
+            path: Arc::new(path),
+            engine: engine.clone(),
+            module,
+            stages,
+            fuel: config.fuel,
+            max_memory: config.max_memory,
+            max_answer_size: config.max_answer_size,
+        })
+    }
+
+    /// Runs the function of `stage` on `input`, in a fresh instance.
+    fn run(&self, stage: Stage, input: &[u8]) -> Result<Vec<u8>, BoxError> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.add_fuel(self.fuel)?;
+
+        let instance = Linker::new(&self.engine).instantiate(&mut store, &self.module)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("the module does not export its memory")?;
+        let alloc = instance.get_typed_func::<i32, i32, _>(&mut store, "alloc")?;
+        let hook = instance.get_typed_func::<(i32, i32), i64, _>(&mut store, hook_name(stage))?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        let input_ptr = ptr as u32 as usize;
+        if !fits(input_ptr, input.len(), memory.data_size(&store)) {
+            return Err("the input was allocated outside of the memory of the module".into());
+        }
+        memory.write(&mut store, input_ptr, input)?;
+        let answer = hook.call(&mut store, (ptr, len))? as u64;
+
+        let (answer_ptr, answer_len) = ((answer >> 32) as usize, (answer & 0xffff_ffff) as usize);
+        if answer_len > self.max_answer_size {
+            return Err(format!(
+                "the answer is {} bytes long, over the maximum of {}",
+                answer_len, self.max_answer_size
+            )
+            .into());
+        }
+        if !fits(answer_ptr, answer_len, memory.data_size(&store)) {
+            return Err("the answer lies outside of the memory of the module".into());
+        }
+        let mut output = vec![0; answer_len];
+        memory.read(&store, answer_ptr, &mut output)?;
+        Ok(output)
+    }
+}
+
+/// Whether `len` bytes starting at `ptr` are within a memory of `size` bytes.
+fn fits(ptr: usize, len: usize, size: usize) -> bool {
+    ptr.checked_add(len).map_or(false, |end| end <= size)
+}
+
+#[async_trait::async_trait]
+impl Extension for WasmModule {
+    fn calls(&self, stage: Stage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    async fn call(&self, externalized: Externalized<'_>) -> Result<Mutations, BoxError> {
+        let stage = externalized.stage;
+        let input = serde_json::to_vec(&externalized)?;
+        let module = self.clone();
+        // Modules run to completion, within their fuel, off the async runtime.
+        let output = tokio::task::spawn_blocking(move || module.run(stage, &input))
+            .await?
+            .map_err(|err| format!("{} failed at {:?}: {}", self.path.display(), stage, err))?;
+        if output.is_empty() {
+            return Ok(Mutations::default());
+        }
+        serde_json::from_slice(&output).map_err(|err| {
+            format!(
+                "invalid answer of {} at {:?}: {}",
+                self.path.display(),
+                stage,
+                err
+            )
+            .into()
+        })
+    }
+}
+
+/// An engine running modules within their fuel.
+fn engine() -> Result<Engine, BoxError> {
+    let mut engine_config = wasmtime::Config::new();
+    engine_config.consume_fuel(true);
+    Ok(Engine::new(&engine_config)?)
+}
+
+#[derive(Debug)]
+struct Wasm {
+    modules: Vec<Arc<WasmModule>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for Wasm {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        let engine = engine()?;
+        let mut modules = Vec::new();
+        for path in &config.modules {
+            modules.push(Arc::new(WasmModule::new(&engine, path.clone(), &config)?));
+        }
+        Ok(Wasm { modules })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        // The first module wraps the others, so that it sees requests first.
+        self.modules.iter().rev().fold(service, |service, module| {
+            externalized_router_service(module.clone(), service)
+        })
+    }
+
+    fn subgraph_service(
+        &mut self,
+        name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        self.modules.iter().rev().fold(service, |service, module| {
+            externalized_subgraph_service(module.clone(), name, service)
+        })
+    }
+}
+
+register_plugin!("experimental", "wasm", Wasm);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apollo_router_core::plugin::utils::test::{MockRouterService, MockSubgraphService};
+    use apollo_router_core::DynPlugin;
+    use serde_json::json;
+    use std::io::Write;
+    use tower::ServiceExt;
+
+    /// Answers router requests with a context entry, and never returns from subgraph requests.
+    const MODULE: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"context\":{\"wasm\":true}}")
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "router_request") (param i32 i32) (result i64)
+            (i64.const 25))
+          (func (export "router_response") (param i32 i32) (result i64)
+            (i64.const 0xfff800000010))
+          (func (export "subgraph_request") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0))
+          (func (export "subgraph_response") (param i32 i32) (result i64)
+            (i64.const 0x1000)))
+    "#;
+
+    fn module_file() -> tempfile::NamedTempFile {
+        let mut module = tempfile::Builder::new().suffix(".wat").tempfile().unwrap();
+        module.write_all(MODULE.as_bytes()).unwrap();
+        module
+    }
+
+    async fn plugin() -> (Box<dyn DynPlugin>, tempfile::NamedTempFile) {
+        let module = module_file();
+        let plugin = apollo_router_core::plugins()
+            .get("experimental.wasm")
+            .expect("Plugin not found")
+            .create_instance(&json!({ "modules": [module.path()], "fuel": 100000 }))
+            .await
+            .unwrap();
+        (plugin, module)
+    }
+
+    #[tokio::test]
+    async fn modules_mutate_requests() {
+        let mut mock = MockRouterService::new();
+        mock.expect_call().times(1).returning(|req: RouterRequest| {
+            Ok(RouterResponse::fake_builder()
+                .context(req.context)
+                .build()
+                .unwrap())
+        });
+
+        let (mut plugin, _module) = plugin().await;
+        let response = plugin
+            .router_service(BoxService::new(mock.build()))
+            .oneshot(RouterRequest::fake_builder().build().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.context.get::<_, bool>("wasm").unwrap(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn modules_run_out_of_fuel() {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call().never();
+
+        let (mut plugin, _module) = plugin().await;
+        let result = plugin
+            .subgraph_service("products", BoxService::new(mock.build()))
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn answers_are_bounded() {
+        let module = module_file();
+        let config: Config = serde_json::from_value(json!({
+            "modules": [module.path()],
+            "max_answer_size": 1024,
+        }))
+        .unwrap();
+        let module =
+            WasmModule::new(&engine().unwrap(), module.path().to_path_buf(), &config).unwrap();
+
+        assert_eq!(
+            module.run(Stage::RouterRequest, b"{}").unwrap(),
+            br#"{"context":{"wasm":true}}"#
+        );
+        let outside = module.run(Stage::RouterResponse, b"{}").unwrap_err();
+        assert!(outside.to_string().contains("outside"), "{}", outside);
+        let too_long = module.run(Stage::SubgraphResponse, b"{}").unwrap_err();
+        assert!(too_long.to_string().contains("maximum"), "{}", too_long);
+    }
+}
@@ -0,0 +1,700 @@
+//! Streaming parsing of GraphQL request bodies.
+//!
+//! Each chunk of the body is scanned for the configured limits as it is received, so that a
+//! request whose body or `variables` go over them is rejected without waiting for the rest. The
+//! body, bounded by those limits, is buffered and parsed once it has been received whole.
+//! Compressed bodies are decompressed as they stream in, so the limits apply to their
+//! decompressed size, on top of the limit on the decompressed size of any body, files included.
+//!
+//! Batches of requests are JSON arrays of them. Multipart requests are only read up to their files, which are streamed to the subgraphs.
+
+use crate::configuration::Batching;
+use apollo_router_core::prelude::*;
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use bytes::Bytes;
+use displaydoc::Display;
+use futures::{Stream, StreamExt, TryStreamExt};
+use http::{HeaderValue, StatusCode};
+use hyper::body::HttpBody;
+use hyper::Body;
+use indexmap::IndexMap;
+use serde_json_bytes::Value;
+use std::pin::Pin;
+use thiserror::Error;
+use tokio::io::AsyncRead;
+use tokio_util::io::{ReaderStream, StreamReader};
+use tower::BoxError;
+
+/// Error reading a GraphQL request body.
+#[derive(Debug, Error, Display)]
+pub(crate) enum RequestBodyError {
+    /// request body is larger than the limit of {0} bytes
+    BodyTooLarge(usize),
+
+    /// decompressed request body is larger than the limit of {0} bytes
+    DecompressedTooLarge(usize),
+
+    /// request variables are larger than the limit of {0} bytes
+    VariablesTooLarge(usize),
+
+    /// could not read the request body: {0}
+    Read(hyper::Error),
+
+    /// invalid GraphQL request: {0}
+    Parse(serde_json::Error),
+
+    /// unsupported `Content-Encoding`: {0}
+    UnsupportedEncoding(String),
+
+    /// invalid multipart request: {0}
+    Multipart(multer::Error),
+
+    /// invalid multipart request: the `{0}` part is missing
+    MissingPart(&'static str),
+
+    /// invalid multipart request: files can only be mapped to variables, not to `{0}`
+    InvalidFilePath(String),
+
+    /// request has more files than the limit of {0}
+    TooManyFiles(usize),
+
+    /// batches of requests are not accepted
+    BatchingDisabled,
+
+    /// batch has more requests than the limit of {0}
+    BatchTooLarge(usize),
+}
+
+impl IntoResponse for RequestBodyError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            RequestBodyError::BodyTooLarge(_)
+            | RequestBodyError::DecompressedTooLarge(_)
+            | RequestBodyError::VariablesTooLarge(_)
+            | RequestBodyError::TooManyFiles(_)
+            | RequestBodyError::BatchTooLarge(_) => {
+                let mut extensions = graphql::Object::default();
+                extensions.insert("code", "REQUEST_TOO_LARGE".into());
+                let response = graphql::Response::builder()
+                    .errors(vec![graphql::Error {
+                        message: self.to_string(),
+                        extensions,
+                        ..Default::default()
+                    }])
+                    .build();
+                return (StatusCode::PAYLOAD_TOO_LARGE, Json(response)).into_response();
+            }
+            RequestBodyError::Parse(error) if error.is_data() => StatusCode::UNPROCESSABLE_ENTITY,
+            RequestBodyError::UnsupportedEncoding(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RequestBodyError::Read(_)
+            | RequestBodyError::Parse(_)
+            | RequestBodyError::Multipart(_)
+            | RequestBodyError::MissingPart(_)
+            | RequestBodyError::InvalidFilePath(_)
+            | RequestBodyError::BatchingDisabled => StatusCode::BAD_REQUEST,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+impl RequestBodyError {
+    fn read(err: hyper::Error) -> Self {
+        match decompressed_limit(&err) {
+            Some(limit) => RequestBodyError::DecompressedTooLarge(limit),
+            None => RequestBodyError::Read(err),
+        }
+    }
+
+    fn multipart(err: multer::Error) -> Self {
+        match &err {
+            multer::Error::StreamReadFailed(source) => match decompressed_limit(source.as_ref()) {
+                Some(limit) => RequestBodyError::DecompressedTooLarge(limit),
+                None => RequestBodyError::Multipart(err),
+            },
+            _ => RequestBodyError::Multipart(err),
+        }
+    }
+}
+
+/// decompressed request body is larger than the limit of {0} bytes
+#[derive(Debug, Error, Display)]
+struct DecompressedTooLarge(usize);
+
+/// The limit of the decompressed body, if going over it is what `err` comes from.
+fn decompressed_limit(err: &(dyn std::error::Error + 'static)) -> Option<usize> {
+    let mut err = err;
+    loop {
+        if let Some(DecompressedTooLarge(limit)) = err.downcast_ref() {
+            return Some(*limit);
+        }
+        err = err.source()?;
+    }
+}
+
+/// Decompresses a body sent with `encoding`, as it is read, failing once it goes over
+/// `max_decompressed_bytes`.
+///
+/// Only bodies without encoding are accepted when `decompress` is disabled. Bodies in an encoding
+/// other than `gzip`, `br` or `deflate` are rejected.
+pub(crate) fn decode_body(
+    body: Body,
+    encoding: Option<&HeaderValue>,
+    decompress: bool,
+    max_decompressed_bytes: usize,
+) -> Result<Body, RequestBodyError> {
+    let encoding = match encoding {
+        Some(encoding) => String::from_utf8_lossy(encoding.as_bytes())
+            .trim()
+            .to_ascii_lowercase(),
+        None => return Ok(body),
+    };
+    if encoding == "identity" {
+        return Ok(body);
+    }
+    if !decompress {
+        return Err(RequestBodyError::UnsupportedEncoding(encoding));
+    }
+    let reader =
+        StreamReader::new(body.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)));
+    let decoder: Pin<Box<dyn AsyncRead + Send>> = match encoding.as_str() {
+        "gzip" | "x-gzip" => Box::pin(GzipDecoder::new(reader)),
+        "br" => Box::pin(BrotliDecoder::new(reader)),
+        "deflate" => Box::pin(ZlibDecoder::new(reader)),
+        _ => return Err(RequestBodyError::UnsupportedEncoding(encoding)),
+    };
+    let mut decompressed = 0;
+    let stream = ReaderStream::new(decoder).map(move |chunk| {
+        let chunk = chunk?;
+        decompressed += chunk.len();
+        if decompressed > max_decompressed_bytes {
+            return Err(BoxError::from(DecompressedTooLarge(max_decompressed_bytes)));
+        }
+        Ok(chunk)
+    });
+    Ok(Body::wrap_stream(stream))
+}
+
+/// A GraphQL request sent with POST, or a batch of them.
+#[derive(Debug)]
+pub(crate) enum Requests {
+    Single(graphql::Request),
+    Batch(Vec<graphql::Request>),
+}
+
+/// Reads and parses a GraphQL request, or a batch of them when `batching` allows it, enforcing
+/// `max_request_bytes` and `max_variables_bytes` while the body streams in.
+///
+/// The variables of all the requests of a batch count towards `max_variables_bytes`.
+pub(crate) async fn read_requests(
+    body: Body,
+    max_request_bytes: Option<usize>,
+    max_variables_bytes: Option<usize>,
+    batching: &Batching,
+) -> Result<Requests, RequestBodyError> {
+    if let Some(max_request_bytes) = max_request_bytes {
+        // A `Content-Length` over the limit is rejected before anything is read.
+        if body.size_hint().lower() > max_request_bytes as u64 {
+            return Err(RequestBodyError::BodyTooLarge(max_request_bytes));
+        }
+    }
+    let limits = BodyLimits::new(max_request_bytes, max_variables_bytes);
+    let values = match parse(body, limits, RequestBodyError::read).await? {
+        Value::Array(values) => values,
+        value => {
+            return graphql::Request::from_value(value)
+                .map(Requests::Single)
+                .map_err(RequestBodyError::Parse)
+        }
+    };
+
+    if !batching.enabled {
+        return Err(RequestBodyError::BatchingDisabled);
+    }
+    if let Some(max_size) = batching.max_size {
+        if values.len() > max_size {
+            return Err(RequestBodyError::BatchTooLarge(max_size));
+        }
+    }
+    values
+        .into_iter()
+        .map(graphql::Request::from_value)
+        .collect::<Result<_, _>>()
+        .map(Requests::Batch)
+        .map_err(RequestBodyError::Parse)
+}
+
+/// Reads a [GraphQL multipart request](https://github.com/jaydenseric/graphql-multipart-request-spec)
+/// up to its files, which are left in the body for the subgraph requests to send on.
+///
+/// The limits apply to the `operations` part, the files being streamed through.
+pub(crate) async fn read_multipart_request(
+    body: Body,
+    boundary: String,
+    max_request_bytes: Option<usize>,
+    max_variables_bytes: Option<usize>,
+    max_files: Option<usize>,
+) -> Result<(graphql::Request, graphql::Uploads), RequestBodyError> {
+    let mut multipart = multer::Multipart::new(body, boundary.clone());
+
+    let operations = next_part(&mut multipart, "operations").await?;
+    let limits = BodyLimits::new(max_request_bytes, max_variables_bytes);
+    let request =
+        graphql::Request::from_value(parse(operations, limits, RequestBodyError::multipart).await?)
+            .map_err(RequestBodyError::Parse)?;
+
+    let map = next_part(&mut multipart, "map")
+        .await?
+        .bytes()
+        .await
+        .map_err(RequestBodyError::multipart)?;
+    let map: IndexMap<String, Vec<String>> =
+        serde_json::from_slice(&map).map_err(RequestBodyError::Parse)?;
+    if let Some(max_files) = max_files {
+        if map.len() > max_files {
+            return Err(RequestBodyError::TooManyFiles(max_files));
+        }
+    }
+    if let Some(path) = map
+        .values()
+        .flatten()
+        .find(|path| !path.starts_with("variables."))
+    {
+        return Err(RequestBodyError::InvalidFilePath(path.clone()));
+    }
+
+    Ok((request, graphql::Uploads::new(boundary, map, multipart)))
+}
+
+async fn next_part(
+    multipart: &mut multer::Multipart<'static>,
+    name: &'static str,
+) -> Result<multer::Field<'static>, RequestBodyError> {
+    match multipart
+        .next_field()
+        .await
+        .map_err(RequestBodyError::multipart)?
+    {
+        Some(field) if field.name() == Some(name) => Ok(field),
+        _ => Err(RequestBodyError::MissingPart(name)),
+    }
+}
+
+/// Parses the JSON document streamed by `chunks`, enforcing `limits` on each chunk before it is
+/// buffered.
+async fn parse<E>(
+    chunks: impl Stream<Item = Result<Bytes, E>>,
+    mut limits: BodyLimits,
+    read_error: fn(E) -> RequestBodyError,
+) -> Result<Value, RequestBodyError> {
+    let mut body = Vec::new();
+    futures::pin_mut!(chunks);
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(read_error)?;
+        limits.check(&chunk)?;
+        body.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&body).map_err(RequestBodyError::Parse)
+}
+
+/// Enforces the limits of a GraphQL request on each chunk of its body received.
+struct BodyLimits {
+    received: usize,
+    scanner: VariablesScanner,
+    max_request_bytes: Option<usize>,
+    max_variables_bytes: Option<usize>,
+}
+
+impl BodyLimits {
+    fn new(max_request_bytes: Option<usize>, max_variables_bytes: Option<usize>) -> Self {
+        Self {
+            received: 0,
+            scanner: VariablesScanner::default(),
+            max_request_bytes,
+            max_variables_bytes,
+        }
+    }
+
+    fn check(&mut self, chunk: &[u8]) -> Result<(), RequestBodyError> {
+        self.received += chunk.len();
+        if let Some(max_request_bytes) = self.max_request_bytes {
+            if self.received > max_request_bytes {
+                return Err(RequestBodyError::BodyTooLarge(max_request_bytes));
+            }
+        }
+        if let Some(max_variables_bytes) = self.max_variables_bytes {
+            if self.scanner.feed(chunk) > max_variables_bytes {
+                return Err(RequestBodyError::VariablesTooLarge(max_variables_bytes));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Incrementally measures the top level `variables` member of a JSON object, or the sum of those
+/// of the objects of a batch.
+///
+/// This is not a validating parser: malformed documents are left to the final deserialization,
+/// the scanner only needs to know where the `variables` value starts and ends.
+#[derive(Default)]
+struct VariablesScanner {
+    depth: usize,
+    /// `true` when the document is an array of requests, one level deeper.
+    batched: bool,
+    in_string: bool,
+    escaped: bool,
+    /// `true` once the `:` following a top level key has been seen.
+    in_value: bool,
+    reading_key: bool,
+    key: Vec<u8>,
+    in_variables: bool,
+    variables_bytes: usize,
+}
+
+impl VariablesScanner {
+    /// Feeds the next chunk of the body, returning the size of the variables seen so far.
+    fn feed(&mut self, chunk: &[u8]) -> usize {
+        for &byte in chunk {
+            let top_level = if self.batched { 2 } else { 1 };
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                    self.reading_key = false;
+                } else if self.reading_key {
+                    self.key.push(byte);
+                }
+            } else {
+                match byte {
+                    b'"' => {
+                        self.in_string = true;
+                        self.reading_key = self.depth == top_level && !self.in_value;
+                        if self.reading_key {
+                            self.key.clear();
+                        }
+                    }
+                    b'[' if self.depth == 0 => {
+                        self.batched = true;
+                        self.depth += 1;
+                    }
+                    b'{' if self.depth + 1 == top_level => {
+                        // The next request of a batch.
+                        self.depth += 1;
+                        self.in_value = false;
+                    }
+                    b'{' | b'[' => self.depth += 1,
+                    b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+                    b':' if self.depth == top_level && !self.in_value => {
+                        self.in_value = true;
+                        self.in_variables = self.key == b"variables";
+                        continue;
+                    }
+                    b',' if self.depth == top_level => {
+                        self.in_value = false;
+                        self.in_variables = false;
+                    }
+                    _ => {}
+                }
+                if self.depth < top_level {
+                    self.in_variables = false;
+                }
+            }
+
+            if self.in_variables {
+                self.variables_bytes += 1;
+            }
+        }
+
+        self.variables_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::stream;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    async fn read_request(
+        body: Body,
+        max_request_bytes: Option<usize>,
+        max_variables_bytes: Option<usize>,
+    ) -> Result<graphql::Request, RequestBodyError> {
+        match read_requests(
+            body,
+            max_request_bytes,
+            max_variables_bytes,
+            &Batching::default(),
+        )
+        .await?
+        {
+            Requests::Single(request) => Ok(request),
+            Requests::Batch(_) => panic!("expected a single request"),
+        }
+    }
+
+    #[test]
+    fn scanner_measures_top_level_variables_only() {
+        let body = json!({
+            "query": "query($a: String) { a(a: $a) }",
+            "extensions": { "variables": "not these" },
+            "variables": { "a": "b\"}," },
+            "operationName": "A",
+        })
+        .to_string();
+
+        let mut scanner = VariablesScanner::default();
+        assert_eq!(
+            scanner.feed(body.as_bytes()),
+            json!({ "a": "b\"}," }).to_string().len()
+        );
+    }
+
+    #[test]
+    fn scanner_accepts_the_body_in_pieces() {
+        let body = json!({ "variables": { "a": [1, 2, 3] }, "query": "{ a }" }).to_string();
+
+        let mut scanner = VariablesScanner::default();
+        let measured = body
+            .as_bytes()
+            .chunks(3)
+            .map(|chunk| scanner.feed(chunk))
+            .last()
+            .unwrap();
+        assert_eq!(measured, json!({ "a": [1, 2, 3] }).to_string().len());
+    }
+
+    #[tokio::test]
+    async fn request_under_the_limit_is_parsed() {
+        let body = json!({ "query": "{ a }", "variables": { "a": 1 } }).to_string();
+
+        let request = read_request(Body::from(body), None, Some(64))
+            .await
+            .unwrap();
+        assert_eq!(request.query.as_deref(), Some("{ a }"));
+    }
+
+    #[tokio::test]
+    async fn requests_are_read_in_chunks() {
+        let body = json!([
+            { "query": "{ a }", "variables": { "a": 1 } },
+            { "query": "{ b }" },
+        ])
+        .to_string();
+        let chunks = body
+            .as_bytes()
+            .chunks(5)
+            .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+
+        let requests = read_requests(
+            Body::wrap_stream(stream::iter(chunks)),
+            None,
+            Some(64),
+            &Batching::builder().enabled(true).build(),
+        )
+        .await
+        .unwrap();
+
+        match requests {
+            Requests::Batch(requests) => {
+                let queries = requests
+                    .iter()
+                    .map(|request| request.query.as_deref())
+                    .collect::<Vec<_>>();
+                assert_eq!(queries, vec![Some("{ a }"), Some("{ b }")]);
+            }
+            Requests::Single(_) => panic!("expected a batch"),
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_variables_are_rejected_before_full_buffering() {
+        let polled = Arc::new(AtomicUsize::new(0));
+        let head = Bytes::from(r#"{"query":"{ a }","variables":{"a":""#);
+        let filler = Bytes::from(vec![b'x'; 1024]);
+        let chunks = std::iter::once(head)
+            .chain(std::iter::repeat(filler).take(1024))
+            .chain(std::iter::once(Bytes::from(r#""}}"#)))
+            .map({
+                let polled = polled.clone();
+                move |chunk| {
+                    polled.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, std::io::Error>(chunk)
+                }
+            });
+
+        let result = read_request(Body::wrap_stream(stream::iter(chunks)), None, Some(4096)).await;
+
+        assert!(matches!(
+            result,
+            Err(RequestBodyError::VariablesTooLarge(4096))
+        ));
+        // The 1MB of variables were not read past the limit.
+        assert!(polled.load(Ordering::SeqCst) < 10);
+    }
+
+    #[tokio::test]
+    async fn compressed_bodies_are_limited_once_decompressed() {
+        use async_compression::tokio::bufread::GzipEncoder;
+        use tokio::io::AsyncReadExt;
+
+        let body = json!({ "query": "{ a }", "variables": { "a": "x".repeat(1024) } }).to_string();
+        let mut compressed = Vec::new();
+        GzipEncoder::new(body.as_bytes())
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        let gzip = HeaderValue::from_static("gzip");
+
+        let decoded = decode_body(
+            Body::from(compressed.clone()),
+            Some(&gzip),
+            true,
+            body.len(),
+        )
+        .unwrap();
+        let request = read_request(decoded, None, None).await.unwrap();
+        assert_eq!(request.query.as_deref(), Some("{ a }"));
+
+        let decoded = decode_body(
+            Body::from(compressed.clone()),
+            Some(&gzip),
+            true,
+            body.len(),
+        )
+        .unwrap();
+        assert!(matches!(
+            read_request(decoded, Some(compressed.len() + 1), None).await,
+            Err(RequestBodyError::BodyTooLarge(_))
+        ));
+
+        // Without a limit on the request, the decompressed size is still limited.
+        let decoded = decode_body(
+            Body::from(compressed.clone()),
+            Some(&gzip),
+            true,
+            body.len() - 1,
+        )
+        .unwrap();
+        assert!(matches!(
+            read_request(decoded, None, None).await,
+            Err(RequestBodyError::DecompressedTooLarge(_))
+        ));
+
+        assert!(matches!(
+            decode_body(Body::from(compressed), Some(&gzip), false, body.len()),
+            Err(RequestBodyError::UnsupportedEncoding(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected() {
+        let body = json!({ "query": "{ a }", "variables": { "a": 1 } }).to_string();
+        let length = body.len();
+
+        assert!(read_request(Body::from(body.clone()), Some(length), None)
+            .await
+            .is_ok());
+        assert!(matches!(
+            read_request(Body::from(body.clone()), Some(length - 1), None).await,
+            Err(RequestBodyError::BodyTooLarge(_))
+        ));
+
+        // Without a known length, the body is measured as it is read.
+        let chunks = body
+            .into_bytes()
+            .chunks(4)
+            .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let response = read_request(Body::wrap_stream(stream::iter(chunks)), Some(8), None)
+            .await
+            .unwrap_err()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn multipart_requests_are_read_up_to_their_files() {
+        let body = "--boundary\r\n\
+            Content-Disposition: form-data; name=\"operations\"\r\n\r\n\
+            {\"query\":\"mutation($file: Upload) { upload(file: $file) }\",\"variables\":{\"file\":null}}\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"map\"\r\n\r\n\
+            {\"0\":[\"variables.file\"]}\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"0\"; filename=\"a.txt\"\r\n\r\n\
+            alpha\r\n\
+            --boundary--\r\n";
+
+        let (request, uploads) =
+            read_multipart_request(Body::from(body), "boundary".to_string(), None, None, None)
+                .await
+                .unwrap();
+        assert!(request.query.unwrap().contains("upload"));
+        assert_eq!(uploads.map_of(&request.variables).len(), 1);
+
+        assert!(matches!(
+            read_multipart_request(
+                Body::from(body),
+                "boundary".to_string(),
+                None,
+                None,
+                Some(0)
+            )
+            .await,
+            Err(RequestBodyError::TooManyFiles(0))
+        ));
+
+        let body = body.replace("variables.file", "query");
+        assert!(matches!(
+            read_multipart_request(Body::from(body), "boundary".to_string(), None, None, None)
+                .await,
+            Err(RequestBodyError::InvalidFilePath(_))
+        ));
+    }
+
+    #[test]
+    fn scanner_measures_the_variables_of_batches() {
+        let body = json!([
+            { "query": "{ a }", "variables": { "a": 1 } },
+            { "variables": { "b": [2] }, "query": "{ b }" },
+        ])
+        .to_string();
+
+        let mut scanner = VariablesScanner::default();
+        assert_eq!(
+            scanner.feed(body.as_bytes()),
+            json!({ "a": 1 }).to_string().len() + json!({ "b": [2] }).to_string().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn batches_are_read_when_enabled() {
+        let body = json!([{ "query": "{ a }" }, { "query": "{ b }" }]).to_string();
+
+        assert!(matches!(
+            read_requests(Body::from(body.clone()), None, None, &Batching::default()).await,
+            Err(RequestBodyError::BatchingDisabled)
+        ));
+
+        let batching = Batching::builder().enabled(true).build();
+        match read_requests(Body::from(body.clone()), None, None, &batching).await {
+            Ok(Requests::Batch(requests)) => assert_eq!(requests.len(), 2),
+            other => panic!("expected a batch, got {:?}", other),
+        }
+
+        let batching = Batching::builder().enabled(true).max_size(Some(1)).build();
+        assert!(matches!(
+            read_requests(Body::from(body), None, None, &batching).await,
+            Err(RequestBodyError::BatchTooLarge(1))
+        ));
+    }
+}
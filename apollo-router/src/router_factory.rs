@@ -1,14 +1,24 @@
+use crate::batching::{BatchEntry, BATCH_INDEX_CONTEXT_KEY, BATCH_SIZE_CONTEXT_KEY};
+use crate::client_ip::{ClientIp, CLIENT_IP_CONTEXT_KEY};
 use crate::configuration::{Configuration, ConfigurationError};
+use crate::correlation::CorrelationId;
 use apollo_router_core::prelude::*;
+use apollo_router_core::{
+    grpc_deadline, DynPlugin, GrpcSubgraphService, JournalEntry, PlanJournal, RestSubgraphService,
+    TowerSubgraphService, CLIENT_NAME_CONTEXT_KEY, CLIENT_VERSION_CONTEXT_KEY,
+    CORRELATION_ID_CONTEXT_KEY, GRPC_DEADLINE_CONTEXT_KEY,
+};
 use apollo_router_core::{
     http_compat::{Request, Response},
-    PluggableRouterServiceBuilder, Plugins, ResponseBody, Schema, ServiceBuilderExt,
+    PluggableRouterServiceBuilder, Plugins, ResponseBody, RouterRequest, Schema, ServiceBuilderExt,
 };
-use apollo_router_core::{DynPlugin, TowerSubgraphService};
 use envmnt::types::ExpandOptions;
 use envmnt::ExpansionType;
+use http::Uri;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
 use tower::buffer::Buffer;
 use tower::util::{BoxCloneService, BoxService};
@@ -40,9 +50,26 @@ pub trait RouterServiceFactory: Send + Sync + 'static {
     ) -> Result<(Self::RouterService, Plugins), BoxError>;
 }
 
+/// Creates a plugin added with [`ApolloRouterBuilder::with_plugin`](crate::ApolloRouterBuilder::with_plugin).
+pub(crate) type PluginConstructor = Arc<dyn Fn() -> Box<dyn DynPlugin> + Send + Sync>;
+
 /// Main implementation of the RouterService factory, supporting the extensions system
 #[derive(Default)]
-pub struct YamlRouterServiceFactory;
+pub struct YamlRouterServiceFactory {
+    /// Plugins created on every reload, after those of the configuration.
+    plugins: Vec<(String, PluginConstructor)>,
+    /// Operations planned by the routers created so far, most recent first.
+    journal: PlanJournal,
+}
+
+impl YamlRouterServiceFactory {
+    pub(crate) fn new(plugins: Vec<(String, PluginConstructor)>) -> Self {
+        Self {
+            plugins,
+            journal: PlanJournal::default(),
+        }
+    }
+}
 
 #[async_trait::async_trait]
 impl RouterServiceFactory for YamlRouterServiceFactory {
@@ -62,24 +89,227 @@ impl RouterServiceFactory for YamlRouterServiceFactory {
         if configuration.server.introspection {
             builder = builder.with_naive_introspection();
         }
+        if let Some(allowlist) = &configuration.server.introspection_allowlist {
+            builder = builder.with_introspection_allowlist(allowlist.clone());
+        }
+        if configuration.server.validate_final_response {
+            builder = builder.validate_final_response(true);
+        }
+        if configuration.server.plugin_timings {
+            builder = builder.with_plugin_timings(true);
+        }
+        if let Some(limit) = configuration.server.query_plan_cache_limit {
+            builder = builder.with_plan_cache_limit(limit);
+        }
+        if let Some(storage) = &configuration.server.cache_storage {
+            builder = builder.with_cache_storage(storage.build().await?);
+        }
+        let warm_up = &configuration.server.warm_up;
+        if warm_up.recent_operations > 0 {
+            builder = builder.with_plan_journal(self.journal.clone());
+        }
+        let mut operations = self.journal.recent(warm_up.recent_operations);
+        if let Some(manifest) = &warm_up.manifest {
+            operations.extend(read_manifest(manifest).await?);
+        }
+        if !operations.is_empty() {
+            builder = builder.with_warm_up(operations, warm_up.introspection);
+        }
+        let subscriptions = &configuration.server.subscriptions;
+        if subscriptions.enabled {
+            let endpoints = subscriptions
+                .subgraphs
+                .iter()
+                .map(|(name, url)| {
+                    Uri::from_str(url)
+                        .map(|url| (name.clone(), url))
+                        .map_err(|err| {
+                            BoxError::from(format!(
+                                "invalid WebSocket URL of subgraph {}: {}",
+                                name, err
+                            ))
+                        })
+                })
+                .collect::<Result<HashMap<_, _>, _>>()?;
+            builder = builder.with_subscriptions(endpoints, subscriptions.connection_init_timeout);
+            if let Some(callback) = &subscriptions.callback {
+                let url = Uri::from_str(&callback.public_url).map_err(|err| {
+                    BoxError::from(format!(
+                        "invalid public URL of subscription callbacks: {}",
+                        err
+                    ))
+                })?;
+                builder = builder.with_subscription_callback(
+                    url,
+                    callback.subgraphs.clone(),
+                    callback.heartbeat_interval,
+                );
+            }
+        }
 
+        let server = &configuration.server;
         for (name, _) in schema.subgraphs() {
-            let subgraph_service = BoxService::new(TowerSubgraphService::new(name.to_string()));
+            let tls = server.subgraph_tls.subgraph(name);
+            let invalid_tls = |err: BoxError| {
+                BoxError::from(format!(
+                    "invalid TLS settings of subgraph {}: {}",
+                    name, err
+                ))
+            };
+            if server.subgraph_auth.subgraphs.contains_key(name)
+                && (server.rest_subgraphs.contains_key(name)
+                    || server.grpc_subgraphs.contains_key(name))
+            {
+                return Err(BoxError::from(format!(
+                    "subgraph {} is not served over GraphQL and cannot be authenticated",
+                    name
+                )));
+            }
+            let subgraph_service = if let Some(mapping) = server.rest_subgraphs.get(name) {
+                let mut subgraph_service = RestSubgraphService::new(name, mapping.clone())
+                    .with_client_config(server.subgraph_client.clone());
+                if let Some(tls) = tls {
+                    subgraph_service = subgraph_service.with_tls(tls).map_err(invalid_tls)?;
+                }
+                BoxService::new(
+                    subgraph_service.with_max_response_bytes(server.max_subgraph_response_bytes),
+                )
+            } else if let Some(grpc) = server.grpc_subgraphs.get(name) {
+                let mut subgraph_service = GrpcSubgraphService::new(name, grpc.clone())
+                    .with_client_config(server.subgraph_client.clone());
+                if let Some(tls) = tls {
+                    subgraph_service = subgraph_service.with_tls(tls).map_err(invalid_tls)?;
+                }
+                BoxService::new(
+                    subgraph_service.with_max_response_bytes(server.max_subgraph_response_bytes),
+                )
+            } else {
+                let mut subgraph_service = TowerSubgraphService::with_config(
+                    name.to_string(),
+                    server.subgraph_client.clone(),
+                );
+                if let Some(tls) = tls {
+                    subgraph_service = subgraph_service.with_tls(tls).map_err(invalid_tls)?;
+                }
+                if let Some(auth) = server.subgraph_auth.subgraph(name) {
+                    subgraph_service = subgraph_service.with_auth(auth);
+                }
+                match (
+                    server.subgraph_routing.get(name),
+                    server.load_balancing.get(name),
+                ) {
+                    (Some(_), Some(_)) => {
+                        return Err(BoxError::from(format!(
+                            "subgraph {} cannot have both routing and load balancing",
+                            name
+                        )));
+                    }
+                    (Some(routing), None) => {
+                        subgraph_service =
+                            subgraph_service.with_routing(routing).map_err(|err| {
+                                BoxError::from(format!(
+                                    "invalid routing of subgraph {}: {}",
+                                    name, err
+                                ))
+                            })?;
+                    }
+                    (None, Some(load_balancing)) => {
+                        subgraph_service = subgraph_service
+                            .with_load_balancing(load_balancing)
+                            .map_err(|err| {
+                                BoxError::from(format!(
+                                    "invalid load balancing of subgraph {}: {}",
+                                    name, err
+                                ))
+                            })?;
+                    }
+                    (None, None) => {}
+                }
+                BoxService::new(
+                    subgraph_service
+                        .with_max_response_bytes(server.max_subgraph_response_bytes)
+                        .with_compression(server.compression.subgraphs),
+                )
+            };
 
             builder = builder.with_subgraph_service(name, subgraph_service);
         }
         // Process the plugins.
-        let plugins = process_plugins(configuration.clone()).await?;
+        let mut plugins = process_plugins(configuration.clone()).await?;
+        for (name, constructor) in &self.plugins {
+            if plugins.contains_key(name) {
+                return Err(BoxError::from(format!(
+                    "plugin {} is registered twice",
+                    name
+                )));
+            }
+            plugins.insert(name.clone(), constructor());
+        }
+        start_plugins(&mut plugins).await?;
 
         for (plugin_name, plugin) in plugins {
             builder = builder.with_dyn_plugin(plugin_name, plugin);
         }
 
         let (pluggable_router_service, mut plugins) = builder.build().await?;
+        let client_awareness = configuration.server.client_awareness.clone();
         let service = ServiceBuilder::new().buffered().service(
             pluggable_router_service
-                .map_request(|http_request: Request<apollo_router_core::Request>| {
-                    http_request.into()
+                .map_request(move |http_request: Request<apollo_router_core::Request>| {
+                    let client_ip = http_request.extensions().get::<ClientIp>().copied();
+                    let correlation_id = http_request.extensions().get::<CorrelationId>().cloned();
+                    let batch_entry = http_request.extensions().get::<BatchEntry>().copied();
+                    let grpc_deadline = grpc_deadline(http_request.headers());
+                    let client_header = |name: &str| {
+                        http_request
+                            .headers()
+                            .get(name)
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string)
+                    };
+                    let client_name = client_header(&client_awareness.name_header);
+                    let client_version = client_header(&client_awareness.version_header);
+                    let request = RouterRequest::from(http_request);
+                    for (key, value) in [
+                        (CLIENT_NAME_CONTEXT_KEY, client_name),
+                        (CLIENT_VERSION_CONTEXT_KEY, client_version),
+                    ] {
+                        if let Some(value) = value {
+                            if let Err(err) = request.context.insert(key, value) {
+                                tracing::error!("could not store the client identity: {}", err);
+                            }
+                        }
+                    }
+                    if let Some(ClientIp(ip)) = client_ip {
+                        if let Err(err) = request
+                            .context
+                            .insert(CLIENT_IP_CONTEXT_KEY, ip.to_string())
+                        {
+                            tracing::error!("could not store the client IP: {}", err);
+                        }
+                    }
+                    if let Some(CorrelationId(id)) = correlation_id {
+                        if let Err(err) = request.context.insert(CORRELATION_ID_CONTEXT_KEY, id) {
+                            tracing::error!("could not store the correlation ID: {}", err);
+                        }
+                    }
+                    if let Some(BatchEntry { index, size }) = batch_entry {
+                        if let Err(err) = request
+                            .context
+                            .insert(BATCH_INDEX_CONTEXT_KEY, index)
+                            .and_then(|_| request.context.insert(BATCH_SIZE_CONTEXT_KEY, size))
+                        {
+                            tracing::error!("could not store the batch entry: {}", err);
+                        }
+                    }
+                    if let Some(deadline) = grpc_deadline {
+                        if let Err(err) =
+                            request.context.insert(GRPC_DEADLINE_CONTEXT_KEY, deadline)
+                        {
+                            tracing::error!("could not store the gRPC deadline: {}", err);
+                        }
+                    }
+                    request
                 })
                 .map_response(|response| response.response)
                 .boxed_clone(),
@@ -99,9 +329,21 @@ impl RouterServiceFactory for YamlRouterServiceFactory {
     }
 }
 
-async fn process_plugins(
-    configuration: Arc<Configuration>,
-) -> Result<HashMap<String, Box<dyn DynPlugin>>, BoxError> {
+/// The operations of a persisted query manifest, a JSON object mapping ids to documents.
+async fn read_manifest(path: &Path) -> Result<Vec<JournalEntry>, BoxError> {
+    let manifest = tokio::fs::read_to_string(path).await.map_err(|err| {
+        BoxError::from(format!(
+            "could not read the warm-up manifest {}: {}",
+            path.display(),
+            err
+        ))
+    })?;
+    let manifest: HashMap<String, String> = serde_json::from_str(&manifest)?;
+    Ok(manifest.into_values().map(|query| (query, None)).collect())
+}
+
+/// Creates the plugins of the configuration, in its order.
+async fn process_plugins(configuration: Arc<Configuration>) -> Result<Plugins, BoxError> {
     let mut errors = Vec::new();
     let plugin_registry = apollo_router_core::plugins();
     let mut plugin_instances = Vec::with_capacity(configuration.plugins().len());
@@ -149,6 +391,26 @@ async fn process_plugins(
     }
 }
 
+/// Starts the plugins, shutting down the ones already started if one of them fails.
+async fn start_plugins(plugins: &mut Plugins) -> Result<(), BoxError> {
+    let mut started = Vec::with_capacity(plugins.len());
+    for (name, plugin) in plugins.iter_mut() {
+        tracing::debug!("starting plugin {}", name);
+        if let Err(err) = plugin.startup().await {
+            tracing::error!("plugin {} failed to start: {}", name, err);
+            for plugin in started {
+                plugin.shutdown().await;
+            }
+            return Err(BoxError::from(format!(
+                "plugin {} failed to start: {}",
+                name, err
+            )));
+        }
+        started.push(plugin);
+    }
+    Ok(())
+}
+
 fn expand_env_variables(configuration: &serde_json::Value) -> serde_json::Value {
     let mut configuration = configuration.clone();
     visit(&mut configuration);
@@ -174,15 +436,24 @@ fn visit(value: &mut serde_json::Value) {
 
 #[cfg(test)]
 mod test {
-    use crate::router_factory::RouterServiceFactory;
+    use crate::configuration::{ClientAwareness, Server, WarmUp};
+    use crate::correlation::CorrelationId;
+    use crate::router_factory::{PluginConstructor, RouterServiceFactory};
     use crate::{Configuration, YamlRouterServiceFactory};
-    use apollo_router_core::Schema;
+    use apollo_router_core::http_compat;
     use apollo_router_core::{register_plugin, Plugin};
+    use apollo_router_core::{DynPlugin, RouterRequest, RouterResponse, Schema, ServiceBuilderExt};
+    use apollo_router_core::{
+        CLIENT_NAME_CONTEXT_KEY, CLIENT_VERSION_CONTEXT_KEY, CORRELATION_ID_CONTEXT_KEY,
+    };
     use schemars::JsonSchema;
     use serde::Deserialize;
     use std::error::Error;
     use std::fmt;
-    use std::sync::Arc;
+    use std::ops::ControlFlow;
+    use std::sync::{Arc, Mutex};
+    use tower::util::BoxService;
+    use tower::{ServiceBuilder, ServiceExt};
     use tower_http::BoxError;
 
     #[derive(Debug)]
@@ -222,6 +493,8 @@ mod test {
         AlwaysStartsAndStopsPlugin
     );
 
+    register_plugin!("apollo.test.qualified", AlwaysStartsAndStopsPlugin);
+
     // Always fails to start plugin
 
     #[derive(Debug)]
@@ -243,6 +516,324 @@ mod test {
         AlwaysFailsToStartPlugin
     );
 
+    // Is created but fails on startup
+
+    #[derive(Debug)]
+    struct FailsOnStartupPlugin {}
+
+    #[async_trait::async_trait]
+    impl Plugin for FailsOnStartupPlugin {
+        type Config = Conf;
+
+        async fn new(configuration: Self::Config) -> Result<Self, BoxError> {
+            tracing::debug!("{}", configuration.name);
+            Ok(FailsOnStartupPlugin {})
+        }
+
+        async fn startup(&mut self) -> Result<(), BoxError> {
+            Err(BoxError::from("cannot connect"))
+        }
+    }
+
+    register_plugin!("apollo.test", "fails_on_startup", FailsOnStartupPlugin);
+
+    // Records the requests and responses going through it, added with `with_plugin`
+
+    #[derive(Debug)]
+    struct RecordingPlugin {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Plugin for RecordingPlugin {
+        type Config = ();
+
+        async fn new(_configuration: Self::Config) -> Result<Self, BoxError> {
+            Err(BoxError::from("only added programmatically"))
+        }
+
+        fn router_service(
+            &mut self,
+            service: BoxService<RouterRequest, RouterResponse, BoxError>,
+        ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+            let name = self.name;
+            let request_log = self.log.clone();
+            let response_log = self.log.clone();
+            service
+                .map_request(move |req: RouterRequest| {
+                    request_log
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} request", name));
+                    req
+                })
+                .map_response(move |res: RouterResponse| {
+                    response_log
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} response", name));
+                    res
+                })
+                .boxed()
+        }
+    }
+
+    // Answers every request, added with `with_plugin`
+
+    #[derive(Debug)]
+    struct AnsweringPlugin {}
+
+    #[async_trait::async_trait]
+    impl Plugin for AnsweringPlugin {
+        type Config = ();
+
+        async fn new(_configuration: Self::Config) -> Result<Self, BoxError> {
+            Ok(AnsweringPlugin {})
+        }
+
+        fn router_service(
+            &mut self,
+            service: BoxService<RouterRequest, RouterResponse, BoxError>,
+        ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+            ServiceBuilder::new()
+                .checkpoint(|req: RouterRequest| {
+                    Ok(ControlFlow::Break(
+                        RouterResponse::fake_builder()
+                            .context(req.context)
+                            .build()?,
+                    ))
+                })
+                .service(service)
+                .boxed()
+        }
+    }
+
+    // Records the client identity found in the context of each request
+
+    #[derive(Debug)]
+    struct ClientPlugin {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Plugin for ClientPlugin {
+        type Config = ();
+
+        async fn new(_configuration: Self::Config) -> Result<Self, BoxError> {
+            Err(BoxError::from("only added programmatically"))
+        }
+
+        fn router_service(
+            &mut self,
+            service: BoxService<RouterRequest, RouterResponse, BoxError>,
+        ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+            let log = self.log.clone();
+            service
+                .map_request(move |req: RouterRequest| {
+                    for key in [
+                        CLIENT_NAME_CONTEXT_KEY,
+                        CLIENT_VERSION_CONTEXT_KEY,
+                        CORRELATION_ID_CONTEXT_KEY,
+                    ] {
+                        let value: Option<String> = req.context.get(key).unwrap();
+                        log.lock().unwrap().push(value.unwrap_or_default());
+                    }
+                    req
+                })
+                .boxed()
+        }
+    }
+
+    fn recording(name: &'static str, log: &Arc<Mutex<Vec<String>>>) -> (String, PluginConstructor) {
+        let log = log.clone();
+        (
+            name.to_string(),
+            Arc::new(move || {
+                Box::new(RecordingPlugin {
+                    name,
+                    log: log.clone(),
+                }) as Box<dyn DynPlugin>
+            }),
+        )
+    }
+
+    fn answering() -> (String, PluginConstructor) {
+        (
+            "answering".to_string(),
+            Arc::new(|| Box::new(AnsweringPlugin {}) as Box<dyn DynPlugin>),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_added_plugins_wrap_services_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let schema: Schema = include_str!("testdata/supergraph.graphql").parse().unwrap();
+        let (service, plugins) = YamlRouterServiceFactory::new(vec![
+            recording("first", &log),
+            recording("second", &log),
+            answering(),
+        ])
+        .create(
+            Arc::new(Configuration::builder().build()),
+            Arc::new(schema),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            plugins.keys().collect::<Vec<_>>(),
+            ["first", "second", "answering"]
+        );
+
+        service
+            .oneshot(
+                http_compat::Request::fake_builder()
+                    .body(
+                        serde_json::from_value(serde_json::json!({ "query": "{ me { name } }" }))
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "first request",
+                "second request",
+                "second response",
+                "first response"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_identity_and_correlation_id_are_stored_in_the_context() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client_log = log.clone();
+        let schema: Schema = include_str!("testdata/supergraph.graphql").parse().unwrap();
+        let configuration = Configuration::builder()
+            .server(
+                Server::builder()
+                    .client_awareness(
+                        ClientAwareness::builder()
+                            .name_header("x-client".to_string())
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let (service, _) = YamlRouterServiceFactory::new(vec![
+            (
+                "client".to_string(),
+                Arc::new(move || {
+                    Box::new(ClientPlugin {
+                        log: client_log.clone(),
+                    }) as Box<dyn DynPlugin>
+                }),
+            ),
+            answering(),
+        ])
+        .create(Arc::new(configuration), Arc::new(schema), None)
+        .await
+        .unwrap();
+
+        let mut request = http_compat::Request::fake_builder()
+            .header("x-client", "ios")
+            .header("apollographql-client-version", "1.2.0")
+            .body(
+                serde_json::from_value(serde_json::json!({ "query": "{ me { name } }" })).unwrap(),
+            )
+            .build()
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(CorrelationId("abc".to_string()));
+        service.oneshot(request).await.unwrap();
+        assert_eq!(*log.lock().unwrap(), ["ios", "1.2.0", "abc"]);
+    }
+
+    #[tokio::test]
+    async fn test_planned_operations_are_journaled_for_the_warm_up() {
+        let schema: Arc<Schema> =
+            Arc::new(include_str!("testdata/supergraph.graphql").parse().unwrap());
+        let configuration = Arc::new(
+            Configuration::builder()
+                .server(
+                    Server::builder()
+                        .warm_up(WarmUp::builder().recent_operations(10).build())
+                        .build(),
+                )
+                .build(),
+        );
+        let mut factory = YamlRouterServiceFactory::default();
+        let (service, _) = factory
+            .create(configuration.clone(), schema.clone(), None)
+            .await
+            .unwrap();
+
+        service
+            .oneshot(
+                http_compat::Request::fake_builder()
+                    .body(
+                        serde_json::from_value(serde_json::json!({ "query": "{ me { name } }" }))
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            factory.journal.recent(10),
+            [("{ me { name } }".to_string(), None)]
+        );
+
+        // The next router plans the journaled operations again, and keeps journaling.
+        factory.create(configuration, schema, None).await.unwrap();
+        assert_eq!(factory.journal.recent(10).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_manifests_must_exist() {
+        let config = Configuration::builder()
+            .server(
+                Server::builder()
+                    .warm_up(
+                        WarmUp::builder()
+                            .manifest(Some("does/not/exist.json".into()))
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let error = create_service(config).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("could not read the warm-up manifest"));
+    }
+
+    #[tokio::test]
+    async fn test_added_plugins_must_have_distinct_names() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let schema: Schema = include_str!("testdata/supergraph.graphql").parse().unwrap();
+        let result =
+            YamlRouterServiceFactory::new(vec![recording("first", &log), recording("first", &log)])
+                .create(
+                    Arc::new(Configuration::builder().build()),
+                    Arc::new(schema),
+                    None,
+                )
+                .await;
+        assert!(result
+            .map(|_| ())
+            .unwrap_err()
+            .to_string()
+            .contains("plugin first is registered twice"));
+    }
+
     #[tokio::test]
     async fn test_yaml_no_extras() {
         let config = Configuration::builder().build();
@@ -264,6 +855,20 @@ mod test {
         assert!(service.is_ok())
     }
 
+    #[tokio::test]
+    async fn test_yaml_plugins_with_a_qualified_name() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            plugins:
+                apollo.test.qualified:
+                    name: albert
+        "#,
+        )
+        .unwrap();
+        let service = create_service(config).await;
+        assert!(service.is_ok())
+    }
+
     #[tokio::test]
     async fn test_yaml_plugins_always_fails_to_start() {
         let config: Configuration = serde_yaml::from_str(
@@ -294,6 +899,25 @@ mod test {
         assert!(service.is_err())
     }
 
+    #[tokio::test]
+    async fn test_yaml_plugins_fails_on_startup() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            plugins:
+                apollo.test.always_starts_and_stops:
+                    name: albert
+                apollo.test.fails_on_startup:
+                    name: albert
+        "#,
+        )
+        .unwrap();
+        let service = create_service(config).await;
+        assert!(service
+            .unwrap_err()
+            .to_string()
+            .contains("plugin apollo.test.fails_on_startup failed to start"))
+    }
+
     // This test must use the multi_thread tokio executor or the opentelemetry hang bug will
     // be encountered. (See https://github.com/open-telemetry/opentelemetry-rust/issues/536)
     #[tokio::test(flavor = "multi_thread")]
@@ -0,0 +1,192 @@
+//! Polling of a supergraph schema served over HTTP.
+//!
+//! The URL is polled on an interval, with the entity tag of the last download so that unchanged
+//! schemas are not downloaded again. A new schema is only sent to the state machine once the query
+//! planner accepts it, so that a supergraph that does not compose never replaces the running one.
+
+use apollo_router_core::prelude::*;
+use futures::prelude::*;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// The state of a supergraph URL between two polls.
+struct Poll {
+    client: reqwest::Client,
+    url: Url,
+    etag: Option<String>,
+    sdl: Option<String>,
+    first: bool,
+}
+
+/// Creates a stream of the supergraph schemas served at `url`, polled every `poll_interval`.
+///
+/// A schema is only sent when it changed since the last poll, and when it can be planned against.
+/// Download and validation errors are logged and the previous schema is kept. The stream never terminates and must
+/// be dropped to stop polling.
+pub(crate) fn stream_supergraph(
+    url: Url,
+    poll_interval: Duration,
+) -> impl Stream<Item = graphql::Schema> {
+    let poll = Poll {
+        client: reqwest::Client::new(),
+        url,
+        etag: None,
+        sdl: None,
+        first: true,
+    };
+    stream::unfold(poll, move |mut poll| async move {
+        loop {
+            if !poll.first {
+                tokio::time::sleep(poll_interval).await;
+            }
+            poll.first = false;
+
+            let sdl = match fetch(&poll.client, &poll.url, poll.etag.as_deref()).await {
+                Ok(Some((sdl, etag))) => {
+                    poll.etag = etag;
+                    sdl
+                }
+                Ok(None) => continue,
+                Err(err) => {
+                    tracing::error!("error downloading the schema from {}: {}", poll.url, err);
+                    continue;
+                }
+            };
+            if poll.sdl.as_ref() == Some(&sdl) {
+                continue;
+            }
+            let validated = validate(&sdl).await;
+            // Invalid schemas are not validated again until they change.
+            poll.sdl = Some(sdl);
+            match validated {
+                Ok(schema) => return Some((schema, poll)),
+                Err(err) => tracing::error!("invalid schema at {}: {}", poll.url, err),
+            }
+        }
+    })
+}
+
+/// Parses `sdl`, and checks that the query planner accepts it.
+async fn validate(sdl: &str) -> Result<graphql::Schema, String> {
+    let schema = Arc::new(
+        sdl.parse::<graphql::Schema>()
+            .map_err(|err| format!("{:?}", err))?,
+    );
+    graphql::BridgeQueryPlanner::new(schema.clone())
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(Arc::try_unwrap(schema).expect("the query planner does not keep the schema"))
+}
+
+/// Downloads the SDL served at `url` with its entity tag, or `None` when it matches `etag`.
+async fn fetch(
+    client: &reqwest::Client,
+    url: &Url,
+    etag: Option<&str>,
+) -> Result<Option<(String, Option<String>)>, reqwest::Error> {
+    let mut request = client.get(url.clone());
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    Ok(Some((response.text().await?, etag)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn unchanged_schemas_are_not_sent_again() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        let app = Router::new().route(
+            "/supergraph.graphql",
+            get(move |headers: HeaderMap| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if headers.get("if-none-match").is_some() {
+                        StatusCode::NOT_MODIFIED.into_response()
+                    } else {
+                        (
+                            [("etag", "\"v1\"")],
+                            include_str!("testdata/supergraph.graphql"),
+                        )
+                            .into_response()
+                    }
+                }
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let url = format!("http://{}/supergraph.graphql", address)
+            .parse()
+            .unwrap();
+        let mut stream = stream_supergraph(url, Duration::from_millis(10)).boxed();
+        assert!(stream.next().await.is_some());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), stream.next())
+                .await
+                .is_err()
+        );
+        assert!(polls.load(Ordering::SeqCst) > 1);
+    }
+
+    #[tokio::test]
+    async fn schemas_that_cannot_be_planned_are_not_sent() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        let app = Router::new().route(
+            "/supergraph.graphql",
+            get(move || {
+                let poll = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if poll < 2 {
+                        include_str!("testdata/invalid_supergraph.graphql")
+                    } else {
+                        include_str!("testdata/supergraph.graphql")
+                    }
+                }
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let url = format!("http://{}/supergraph.graphql", address)
+            .parse()
+            .unwrap();
+        let mut stream = stream_supergraph(url, Duration::from_millis(10)).boxed();
+        let schema = stream.next().await.unwrap();
+        assert_eq!(schema.as_str(), include_str!("testdata/supergraph.graphql"));
+        assert!(polls.load(Ordering::SeqCst) > 2);
+    }
+}
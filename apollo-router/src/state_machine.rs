@@ -1,18 +1,25 @@
+use super::admin::{Admin, AdminServerHandle};
+use super::axum_http_server_factory::SUPERGRAPH_SDL_HANDLER;
+use super::health::{self, Health, HealthServerHandle};
 use super::http_server_factory::{HttpServerFactory, HttpServerHandle};
 use super::router_factory::RouterServiceFactory;
 use super::state_machine::PrivateState::{Errored, Running, Startup, Stopped};
+use super::subscription_callback::CallbackServerHandle;
 use super::Event::{UpdateConfiguration, UpdateSchema};
 use super::FederatedServerError::{NoConfiguration, NoSchema};
 use super::{Event, FederatedServerError, State};
 use crate::configuration::Configuration;
 use apollo_router_core::Schema;
-use apollo_router_core::{prelude::*, Handler, Plugins};
+use apollo_router_core::{http_compat, prelude::*, Handler, Plugins, ResponseBody};
+use bytes::Bytes;
 use futures::channel::mpsc;
 use futures::prelude::*;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::pin::Pin;
 use std::sync::Arc;
-use Event::{NoMoreConfiguration, NoMoreSchema, Shutdown};
+use tower::{service_fn, BoxError, ServiceExt};
+use Event::{NoMoreConfiguration, NoMoreSchema, Reload, Shutdown};
 
 /// This state maintains private information that is not exposed to the user via state listener.
 #[derive(derivative::Derivative)]
@@ -52,7 +59,14 @@ impl<T> Display for PrivateState<T> {
 /// If config and schema are not supplied then the machine ends with an error.
 /// Once schema and config are obtained running state is entered.
 /// Config and schema updates will try to swap in the new values into the running state. In future we may trigger an http server restart if for instance socket address is encountered.
+/// Events are handled one at a time, so only one swap can happen at once. Schema updates that are
+/// superseded by another one already waiting are skipped, so that the latest schema wins.
+/// The schema and the caches derived from it live in the same router service, and are swapped together.
 /// At any point a shutdown event will cause the machine to try to get to stopped state.  
+/// The health endpoints are served as soon as a configuration enables them, and report the router
+/// as ready while it is running. The admin endpoints are served the same way, and reloads they
+/// request come back as events. So are the callbacks of subscriptions, which outlive the router
+/// that sent them.
 pub(crate) struct StateMachine<S, FA>
 where
     S: HttpServerFactory,
@@ -61,6 +75,11 @@ where
     http_server_factory: S,
     state_listener: Option<mpsc::Sender<State>>,
     router_factory: FA,
+    health: Arc<Health>,
+    health_server: Option<HealthServerHandle>,
+    admin: Arc<Admin>,
+    admin_server: Option<AdminServerHandle>,
+    callback_server: Option<CallbackServerHandle>,
 }
 
 impl<RS> From<&PrivateState<RS>> for State {
@@ -95,14 +114,27 @@ where
             http_server_factory,
             state_listener,
             router_factory,
+            health: Default::default(),
+            health_server: None,
+            admin: Default::default(),
+            admin_server: None,
+            callback_server: None,
         }
     }
 
+    /// Lets the admin endpoints reload the router by sending events to `events`, which must be
+    /// part of the events processed.
+    pub(crate) fn with_admin_events(mut self, events: mpsc::UnboundedSender<Event>) -> Self {
+        self.admin = Arc::new(Admin::new(events));
+        self
+    }
+
     pub(crate) async fn process_events(
         mut self,
-        mut messages: impl Stream<Item = Event> + Unpin,
+        messages: impl Stream<Item = Event> + Unpin,
     ) -> Result<(), FederatedServerError> {
         tracing::debug!("starting");
+        let mut messages = messages.peekable();
         let mut state = Startup {
             configuration: None,
             schema: None,
@@ -147,9 +179,20 @@ where
                 (Startup { .. }, Shutdown) => Stopped,
 
                 // Running: Handle shutdown.
-                (Running { server_handle, .. }, Shutdown) => {
+                (
+                    Running {
+                        server_handle,
+                        mut plugins,
+                        ..
+                    },
+                    Shutdown,
+                ) => {
                     tracing::debug!("shutting down");
-                    match server_handle.shutdown().await {
+                    let result = server_handle.shutdown().await;
+                    for plugin in plugins.values_mut() {
+                        plugin.shutdown().await;
+                    }
+                    match result {
                         Ok(_) => Stopped,
                         Err(err) => Errored(err),
                     }
@@ -164,8 +207,14 @@ where
                         server_handle,
                         plugins,
                     },
-                    UpdateSchema(new_schema),
+                    UpdateSchema(mut new_schema),
                 ) => {
+                    while let Some(UpdateSchema(latest_schema)) =
+                        Self::next_if_schema_update(&mut messages)
+                    {
+                        tracing::debug!("skipping superseded schema");
+                        new_schema = latest_schema;
+                    }
                     tracing::info!("reloading schema");
                     self.reload_server(
                         configuration,
@@ -209,6 +258,31 @@ where
                     .into_ok_or_err2()
                 }
 
+                // Running: Rebuild the router, emptying its caches
+                (
+                    Running {
+                        configuration,
+                        schema,
+                        router_service,
+                        server_handle,
+                        plugins,
+                    },
+                    Reload,
+                ) => {
+                    tracing::info!("reloading the router");
+                    self.reload_server(
+                        configuration,
+                        schema,
+                        router_service,
+                        server_handle,
+                        plugins,
+                        None,
+                        None,
+                    )
+                    .await
+                    .into_ok_or_err2()
+                }
+
                 // Anything else we don't care about
                 (state, message) => {
                     tracing::debug!("ignoring message transition {:?}", message);
@@ -216,6 +290,9 @@ where
                 }
             };
 
+            self.update_health(&new_state).await;
+            self.update_admin(&new_state).await;
+            self.update_callback(&new_state).await;
             let new_public_state = State::from(&new_state);
             if last_public_state != new_public_state {
                 <StateMachine<S, FA>>::notify_state_listener(&mut state_listener, new_public_state)
@@ -240,6 +317,135 @@ where
         }
     }
 
+    /// Starts, moves or stops the health listener as configured, and updates the readiness of the
+    /// router.
+    async fn update_health(
+        &mut self,
+        state: &PrivateState<<FA as RouterServiceFactory>::RouterService>,
+    ) {
+        let configuration = match state {
+            Startup { configuration, .. } => configuration.as_ref(),
+            Running { configuration, .. } => Some(&**configuration),
+            Stopped | Errored(_) => None,
+        };
+        let configured = configuration
+            .and_then(|configuration| configuration.server.health.as_ref())
+            .map(|health| (health.listen, health.check_interval));
+
+        if self
+            .health_server
+            .as_ref()
+            .map(|server| (server.configured_address, server.check_interval))
+            != configured
+        {
+            if let Some(server) = self.health_server.take() {
+                server.shutdown().await;
+            }
+            if let Some((listen, check_interval)) = configured {
+                match HealthServerHandle::start(listen, check_interval, self.health.clone()).await {
+                    Ok(server) => self.health_server = Some(server),
+                    Err(err) => tracing::error!("cannot serve the health endpoints: {}", err),
+                }
+            }
+        }
+
+        match state {
+            Running {
+                plugins, schema, ..
+            } => self.health.running(health::checks(plugins, schema)),
+            _ => self.health.not_running(),
+        }
+    }
+
+    /// Starts, moves or stops the admin listener as configured, and updates what it describes.
+    async fn update_admin(
+        &mut self,
+        state: &PrivateState<<FA as RouterServiceFactory>::RouterService>,
+    ) {
+        let configuration = match state {
+            Startup { configuration, .. } => configuration.as_ref(),
+            Running { configuration, .. } => Some(&**configuration),
+            Stopped | Errored(_) => None,
+        };
+        let listen = configuration
+            .and_then(|configuration| configuration.server.admin.as_ref())
+            .map(|admin| admin.listen);
+
+        if self
+            .admin_server
+            .as_ref()
+            .map(|server| server.configured_address)
+            != listen
+        {
+            if let Some(server) = self.admin_server.take() {
+                server.shutdown().await;
+            }
+            if let Some(listen) = listen {
+                match AdminServerHandle::start(listen, self.admin.clone()).await {
+                    Ok(server) => self.admin_server = Some(server),
+                    Err(err) => tracing::error!("cannot serve the admin endpoints: {}", err),
+                }
+            }
+        }
+
+        match state {
+            Running {
+                configuration,
+                schema,
+                plugins,
+                ..
+            } => self.admin.running(configuration, schema, plugins),
+            _ => self.admin.not_running(),
+        }
+    }
+
+    /// Starts, moves or stops the listener of subscription callbacks as configured.
+    async fn update_callback(
+        &mut self,
+        state: &PrivateState<<FA as RouterServiceFactory>::RouterService>,
+    ) {
+        let configuration = match state {
+            Startup { configuration, .. } => configuration.as_ref(),
+            Running { configuration, .. } => Some(&**configuration),
+            Stopped | Errored(_) => None,
+        };
+        let listen = configuration
+            .map(|configuration| &configuration.server.subscriptions)
+            .filter(|subscriptions| subscriptions.enabled)
+            .and_then(|subscriptions| subscriptions.callback.as_ref())
+            .map(|callback| callback.listen);
+
+        if self
+            .callback_server
+            .as_ref()
+            .map(|server| server.configured_address)
+            != listen
+        {
+            if let Some(server) = self.callback_server.take() {
+                server.shutdown().await;
+            }
+            if let Some(listen) = listen {
+                match CallbackServerHandle::start(listen).await {
+                    Ok(server) => self.callback_server = Some(server),
+                    Err(err) => tracing::error!("cannot take subscription callbacks: {}", err),
+                }
+            }
+        }
+    }
+
+    /// Returns the next message if it is a schema update that is already waiting.
+    fn next_if_schema_update(
+        messages: &mut stream::Peekable<impl Stream<Item = Event> + Unpin>,
+    ) -> Option<Event> {
+        let pending_schema = matches!(
+            Pin::new(&mut *messages).peek().now_or_never(),
+            Some(Some(UpdateSchema(_)))
+        );
+        pending_schema
+            .then(|| messages.next().now_or_never().flatten())
+            .flatten()
+    }
+
     async fn notify_state_listener(
         state_listener: &mut Option<mpsc::Sender<State>>,
         new_public_state: State,
@@ -273,15 +479,7 @@ where
                     tracing::error!("cannot create the router: {}", err);
                     Errored(FederatedServerError::ServiceCreationError(err))
                 })?;
-            let plugin_handlers: HashMap<String, Handler> = plugins
-                .iter()
-                .filter_map(|(plugin_name, plugin)| {
-                    (plugin_name.starts_with("apollo.") || plugin_name.starts_with("experimental."))
-                        .then(|| plugin.custom_endpoint())
-                        .flatten()
-                        .map(|h| (plugin_name.clone(), h))
-                })
-                .collect();
+            let plugin_handlers = handlers(&plugins, &configuration, &schema);
 
             let server_handle = self
                 .http_server_factory
@@ -330,19 +528,15 @@ where
             )
             .await
         {
-            Ok((new_router_service, plugins)) => {
-                let plugin_handlers: HashMap<String, Handler> = plugins
-                    .iter()
-                    .filter_map(|(plugin_name, plugin)| {
-                        (plugin_name.starts_with("apollo.")
-                            || plugin_name.starts_with("experimental."))
-                        .then(|| plugin.custom_endpoint())
-                        .flatten()
-                        .map(|handler| (plugin_name.clone(), handler))
-                    })
-                    .collect();
+            Ok((new_router_service, mut new_plugins)) => {
+                if new_schema.as_str() != schema.as_str() {
+                    for plugin in new_plugins.values_mut() {
+                        plugin.schema_changed(&schema, &new_schema);
+                    }
+                }
+                let plugin_handlers = handlers(&new_plugins, &new_configuration, &new_schema);
 
-                let server_handle = server_handle
+                let (server_handle, draining) = server_handle
                     .restart(
                         &self.http_server_factory,
                         new_router_service.clone(),
@@ -354,12 +548,22 @@ where
                         tracing::error!("cannot start the router: {}", err);
                         Errored(err)
                     })?;
+
+                // the previous plugins are done with once the connections of the previous server,
+                // which still use them, are closed
+                let mut previous_plugins = plugins;
+                tokio::spawn(async move {
+                    draining.wait().await;
+                    for plugin in previous_plugins.values_mut() {
+                        plugin.shutdown().await;
+                    }
+                });
                 Ok(Running {
                     configuration: new_configuration,
                     schema: new_schema,
                     router_service: new_router_service,
                     server_handle,
-                    plugins,
+                    plugins: new_plugins,
                 })
             }
             Err(err) => {
@@ -379,6 +583,43 @@ where
     }
 }
 
+/// Handlers of the endpoints of the plugins, along with the one serving the supergraph SDL when
+/// enabled.
+fn handlers(
+    plugins: &Plugins,
+    configuration: &Configuration,
+    schema: &Arc<Schema>,
+) -> HashMap<String, Handler> {
+    let mut handlers: HashMap<String, Handler> = plugins
+        .iter()
+        .filter_map(|(plugin_name, plugin)| {
+            (plugin_name.starts_with("apollo.") || plugin_name.starts_with("experimental."))
+                .then(|| plugin.custom_endpoint())
+                .flatten()
+                .map(|handler| (plugin_name.clone(), handler))
+        })
+        .collect();
+
+    if configuration.server.supergraph_sdl_path.is_some() {
+        let schema = schema.clone();
+        let sdl = service_fn(move |_request: http_compat::Request<Bytes>| {
+            let sdl = schema.as_str().to_string();
+            async move {
+                Ok::<_, BoxError>(http_compat::Response {
+                    inner: http::Response::builder()
+                        .header(http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                        .body(ResponseBody::Text(sdl))?,
+                })
+            }
+        });
+        handlers.insert(
+            SUPERGRAPH_SDL_HANDLER.to_string(),
+            Handler::new(sdl.boxed()),
+        );
+    }
+    handlers
+}
+
 trait ResultExt<T> {
     // Unstable method can be deleted in future
     fn into_ok_or_err2(self) -> T;
@@ -399,7 +640,7 @@ mod tests {
     use crate::http_server_factory::Listener;
     use crate::router_factory::RouterServiceFactory;
     use apollo_router_core::http_compat::{Request, Response};
-    use apollo_router_core::ResponseBody;
+    use apollo_router_core::{DynPlugin, Plugin, ResponseBody};
     use futures::channel::oneshot;
     use futures::future::BoxFuture;
     use mockall::{mock, Sequence};
@@ -529,6 +770,161 @@ mod tests {
         assert_eq!(shutdown_receivers.lock().unwrap().len(), 2);
     }
 
+    #[test(tokio::test)]
+    async fn reload_rebuilds_the_router() {
+        let router_factory = create_mock_router_factory(2);
+        let (server_factory, shutdown_receivers) = create_mock_server_factory(2);
+        assert!(matches!(
+            execute(
+                server_factory,
+                router_factory,
+                vec![
+                    UpdateConfiguration(Configuration::builder().build().boxed()),
+                    UpdateSchema(Box::new(example_schema())),
+                    Reload,
+                    Shutdown
+                ],
+                vec![
+                    State::Startup,
+                    State::Running {
+                        address: SocketAddr::from_str("127.0.0.1:4000").unwrap().into(),
+                        schema: example_schema().as_str().to_string()
+                    },
+                    State::Stopped
+                ]
+            )
+            .await,
+            Ok(()),
+        ));
+        assert_eq!(shutdown_receivers.lock().unwrap().len(), 2);
+    }
+
+    #[test(tokio::test)]
+    async fn concurrent_schema_reloads_apply_the_latest() {
+        let mut router_factory = MockMyRouterFactory::new();
+        let created_with = Arc::new(Mutex::new(Vec::new()));
+        let created_with_clone = created_with.clone();
+        router_factory.expect_create().times(2).returning(
+            move |_, schema: Arc<graphql::Schema>, _| {
+                created_with_clone
+                    .lock()
+                    .unwrap()
+                    .push(schema.as_str().to_string());
+                let mut router = MockMyRouter::new();
+                router.expect_clone().return_once(MockMyRouter::new);
+                Ok((router, Default::default()))
+            },
+        );
+        let (server_factory, shutdown_receivers) = create_mock_server_factory(2);
+        let superseded_schema = "type Query { me: String }";
+        let latest_schema = "type Query { you: String }";
+
+        assert!(matches!(
+            execute(
+                server_factory,
+                router_factory,
+                vec![
+                    UpdateConfiguration(Configuration::builder().build().boxed()),
+                    UpdateSchema(Box::new(example_schema())),
+                    UpdateSchema(Box::new(superseded_schema.parse().unwrap())),
+                    UpdateSchema(Box::new(latest_schema.parse().unwrap())),
+                    Shutdown
+                ],
+                vec![
+                    State::Startup,
+                    State::Running {
+                        address: SocketAddr::from_str("127.0.0.1:4000").unwrap().into(),
+                        schema: example_schema().as_str().to_string()
+                    },
+                    State::Running {
+                        address: SocketAddr::from_str("127.0.0.1:4000").unwrap().into(),
+                        schema: latest_schema.to_string(),
+                    },
+                    State::Stopped
+                ]
+            )
+            .await,
+            Ok(()),
+        ));
+        // The running schema is the one the serving router was created with.
+        assert_eq!(
+            *created_with.lock().unwrap(),
+            vec![
+                example_schema().as_str().to_string(),
+                latest_schema.to_string()
+            ]
+        );
+        assert_eq!(shutdown_receivers.lock().unwrap().len(), 2);
+    }
+
+    struct SchemaChanges(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl Plugin for SchemaChanges {
+        type Config = ();
+
+        async fn new(_config: Self::Config) -> Result<Self, BoxError> {
+            Ok(SchemaChanges(Default::default()))
+        }
+
+        fn schema_changed(&mut self, _previous: &graphql::Schema, schema: &graphql::Schema) {
+            self.0.lock().unwrap().push(schema.as_str().to_string());
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn plugins_are_notified_of_schema_changes() {
+        let mut router_factory = MockMyRouterFactory::new();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let changes_clone = changes.clone();
+        router_factory
+            .expect_create()
+            .times(3)
+            .returning(move |_, _, _| {
+                let mut router = MockMyRouter::new();
+                router.expect_clone().return_once(MockMyRouter::new);
+                let mut plugins = Plugins::new();
+                plugins.insert(
+                    "schema_changes".to_string(),
+                    Box::new(SchemaChanges(changes_clone.clone())) as Box<dyn DynPlugin>,
+                );
+                Ok((router, plugins))
+            });
+        let (server_factory, _) = create_mock_server_factory(3);
+        let minimal_schema = "type Query { me: String }";
+
+        execute(
+            server_factory,
+            router_factory,
+            vec![
+                UpdateConfiguration(Configuration::builder().build().boxed()),
+                UpdateSchema(Box::new(minimal_schema.parse().unwrap())),
+                UpdateSchema(Box::new(example_schema())),
+                UpdateConfiguration(Configuration::builder().build().boxed()),
+                Shutdown,
+            ],
+            vec![
+                State::Startup,
+                State::Running {
+                    address: SocketAddr::from_str("127.0.0.1:4000").unwrap().into(),
+                    schema: minimal_schema.to_string(),
+                },
+                State::Running {
+                    address: SocketAddr::from_str("127.0.0.1:4000").unwrap().into(),
+                    schema: example_schema().as_str().to_string(),
+                },
+                State::Stopped,
+            ],
+        )
+        .await
+        .unwrap();
+        // Only the schema update is notified, not the configuration reload.
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![example_schema().as_str().to_string()]
+        );
+    }
+
     #[test(tokio::test)]
     async fn startup_reload_configuration() {
         let router_factory = create_mock_router_factory(2);
@@ -0,0 +1,105 @@
+//! Listener of the calls of subgraphs posting the events of subscriptions, on a listener of its own.
+//!
+//! * `POST /:id` takes a message of the callback protocol for the subscription `id`, answered
+//!   with the status the protocol expects. Messages for another subscription than the one of the
+//!   path are rejected with a 400 status.
+
+use crate::FederatedServerError;
+use apollo_router_core::{handle_callback, CallbackMessage};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use futures::channel::oneshot;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+async fn handle_message(
+    Path(id): Path<String>,
+    Json(message): Json<CallbackMessage>,
+) -> StatusCode {
+    if message.id() != id {
+        return StatusCode::BAD_REQUEST;
+    }
+    handle_callback(message).await
+}
+
+/// The callback listener, serving until it is shut down.
+pub(crate) struct CallbackServerHandle {
+    shutdown_sender: oneshot::Sender<()>,
+    server: JoinHandle<Result<(), hyper::Error>>,
+    /// The configured address, which may use port zero.
+    pub(crate) configured_address: SocketAddr,
+    /// The address actually listened on.
+    pub(crate) listen_address: SocketAddr,
+}
+
+impl CallbackServerHandle {
+    /// Starts taking the calls of subgraphs on `listen`.
+    pub(crate) async fn start(listen: SocketAddr) -> Result<Self, FederatedServerError> {
+        let listener = TcpListener::bind(listen)
+            .await
+            .map_err(FederatedServerError::ServerCreationError)?;
+        let listen_address = listener
+            .local_addr()
+            .map_err(FederatedServerError::ServerCreationError)?;
+        let listener = listener
+            .into_std()
+            .map_err(FederatedServerError::ServerCreationError)?;
+
+        let app = Router::new().route("/:id", post(handle_message));
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let server = axum::Server::from_tcp(listener)
+            .map_err(|_| FederatedServerError::HttpServerLifecycleError)?
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async {
+                let _ = shutdown_receiver.await;
+            });
+        tracing::debug!("taking subscription callbacks on {}", listen_address);
+
+        Ok(Self {
+            shutdown_sender,
+            server: tokio::spawn(server),
+            configured_address: listen,
+            listen_address,
+        })
+    }
+
+    pub(crate) async fn shutdown(self) {
+        let _ = self.shutdown_sender.send(());
+        match self.server.await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::error!("the callback listener failed: {}", err),
+            Err(err) => tracing::error!("the callback listener panicked: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn calls_for_unknown_or_other_subscriptions_are_rejected() {
+        let server = CallbackServerHandle::start(SocketAddr::from_str("127.0.0.1:0").unwrap())
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let call = |path: &str, id: &str| {
+            client
+                .post(format!("http://{}/{}", server.listen_address, path))
+                .json(&json!({ "kind": "subscription", "action": "check", "id": id, "verifier": "abc" }))
+                .send()
+        };
+
+        let response = call("1", "1").await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+        let response = call("1", "2").await.unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+
+        server.shutdown().await;
+    }
+}
@@ -0,0 +1,274 @@
+//! Subscriptions on WebSockets, with the `graphql-transport-ws` protocol.
+//!
+//! The client initialises the connection once, then starts and stops operations identified by the
+//! ids it gives them. Each operation is a request of the router service, carrying the payload of
+//! the `connection_init` message, and is answered with the events of its subscription. Queries and
+//! mutations are answered with a single event.
+
+use crate::axum_http_server_factory::BufferedService;
+use crate::client_ip::ClientIp;
+use crate::correlation::CorrelationId;
+use apollo_router_core::prelude::*;
+use apollo_router_core::{
+    http_compat, ProtocolMessage, ResponseBody, SubscriptionConnection, SubscriptionEvents,
+};
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use futures::channel::oneshot;
+use futures::future;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use http::Method;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+
+/// Messages waiting to be sent to the client.
+const OUTGOING_BUFFER: usize = 64;
+
+/// Serves the operations of a client on `socket`, the WebSocket upgraded from the request `head`.
+pub(crate) async fn serve(
+    socket: WebSocket,
+    service: BufferedService,
+    head: http::request::Parts,
+    connection_init_timeout: Duration,
+) {
+    let (mut sink, mut stream) = socket.split();
+
+    let initialised = tokio::time::timeout(connection_init_timeout, async {
+        loop {
+            match receive(&mut stream).await {
+                Incoming::Message(ProtocolMessage::ConnectionInit { payload }) => {
+                    return Ok(payload)
+                }
+                Incoming::Message(ProtocolMessage::Ping { .. }) => {
+                    if send(&mut sink, &ProtocolMessage::Pong { payload: None })
+                        .await
+                        .is_err()
+                    {
+                        return Err(None);
+                    }
+                }
+                Incoming::Message(ProtocolMessage::Pong { .. }) => {}
+                Incoming::Message(ProtocolMessage::Subscribe { .. }) => {
+                    return Err(Some((4401, "Unauthorized".to_string())))
+                }
+                Incoming::Message(_) => return Err(Some((4400, "Invalid message".to_string()))),
+                Incoming::Invalid(reason) => return Err(Some((4400, reason))),
+                Incoming::Closed => return Err(None),
+            }
+        }
+    })
+    .await;
+    let init_payload = match initialised {
+        Ok(Ok(payload)) => payload,
+        Ok(Err(Some((code, reason)))) => return close(&mut sink, code, reason).await,
+        Ok(Err(None)) => return,
+        Err(_) => {
+            return close(
+                &mut sink,
+                4408,
+                "Connection initialisation timeout".to_string(),
+            )
+            .await
+        }
+    };
+    if send(&mut sink, &ProtocolMessage::ConnectionAck { payload: None })
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let head = Arc::new(head);
+    let connection = SubscriptionConnection { init_payload };
+    let (outgoing, mut outgoing_receiver) = mpsc::channel(OUTGOING_BUFFER);
+    let (done, mut done_receiver) = mpsc::unbounded_channel();
+    // The operations in progress, by id, dropping their sender cancelling them. The generation
+    // tells an operation apart from a later one given the same id.
+    let mut operations: HashMap<String, (u64, oneshot::Sender<()>)> = HashMap::new();
+    let mut generation = 0;
+    loop {
+        tokio::select! {
+            incoming = receive(&mut stream) => match incoming {
+                Incoming::Message(ProtocolMessage::Subscribe { id, payload }) => {
+                    if operations.contains_key(&id) {
+                        let reason = format!("Subscriber for {} already exists", id);
+                        return close(&mut sink, 4409, reason).await;
+                    }
+                    generation += 1;
+                    let (cancel, cancelled) = oneshot::channel();
+                    operations.insert(id.clone(), (generation, cancel));
+                    let request = operation_request(&head, payload, &connection);
+                    let operation = run(service.clone(), request, id.clone(), outgoing.clone());
+                    let done = done.clone();
+                    let operation_generation = generation;
+                    tokio::spawn(async move {
+                        futures::pin_mut!(operation);
+                        future::select(operation, cancelled).await;
+                        let _ = done.send((id, operation_generation));
+                    });
+                }
+                Incoming::Message(ProtocolMessage::Complete { id }) => {
+                    // Dropping the sender cancels the operation.
+                    operations.remove(&id);
+                }
+                Incoming::Message(ProtocolMessage::Ping { .. }) => {
+                    if send(&mut sink, &ProtocolMessage::Pong { payload: None }).await.is_err() {
+                        return;
+                    }
+                }
+                Incoming::Message(ProtocolMessage::Pong { .. }) => {}
+                Incoming::Message(ProtocolMessage::ConnectionInit { .. }) => {
+                    let reason = "Too many initialisation requests".to_string();
+                    return close(&mut sink, 4429, reason).await;
+                }
+                Incoming::Message(_) => {
+                    return close(&mut sink, 4400, "Invalid message".to_string()).await;
+                }
+                Incoming::Invalid(reason) => return close(&mut sink, 4400, reason).await,
+                Incoming::Closed => return,
+            },
+            Some(message) = outgoing_receiver.recv() => {
+                if send(&mut sink, &message).await.is_err() {
+                    return;
+                }
+            },
+            Some((id, operation_generation)) = done_receiver.recv() => {
+                if operations.get(&id).map(|(generation, _)| *generation) == Some(operation_generation) {
+                    operations.remove(&id);
+                }
+            },
+        }
+    }
+}
+
+enum Incoming {
+    Message(ProtocolMessage),
+    Invalid(String),
+    Closed,
+}
+
+async fn receive(stream: &mut SplitStream<WebSocket>) -> Incoming {
+    while let Some(message) = stream.next().await {
+        match message {
+            Ok(Message::Text(text)) => {
+                return match serde_json::from_str(&text) {
+                    Ok(message) => Incoming::Message(message),
+                    Err(err) => Incoming::Invalid(format!("Invalid message: {}", err)),
+                }
+            }
+            Ok(Message::Binary(_)) => {
+                return Incoming::Invalid("Binary messages are not supported".to_string())
+            }
+            Ok(Message::Close(_)) | Err(_) => return Incoming::Closed,
+            // WebSocket pings are answered by the socket itself.
+            Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => {}
+        }
+    }
+    Incoming::Closed
+}
+
+async fn send(
+    sink: &mut SplitSink<WebSocket, Message>,
+    message: &ProtocolMessage,
+) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("protocol messages are serializable");
+    sink.send(Message::Text(text)).await
+}
+
+async fn close(sink: &mut SplitSink<WebSocket, Message>, code: u16, reason: String) {
+    let close = Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }));
+    if let Err(err) = sink.send(close).await {
+        tracing::debug!("could not close the websocket connection: {}", err);
+    }
+}
+
+/// The request of an operation, with the head of the upgraded request.
+fn operation_request(
+    head: &http::request::Parts,
+    body: graphql::Request,
+    connection: &SubscriptionConnection,
+) -> http_compat::Request<graphql::Request> {
+    let mut request = http::Request::new(body);
+    // Mutations are allowed on the socket, unlike in requests sent with GET.
+    *request.method_mut() = Method::POST;
+    *request.uri_mut() = head.uri.clone();
+    *request.version_mut() = head.version;
+    *request.headers_mut() = head.headers.clone();
+    // Extensions cannot be cloned, only those the pipeline reads are carried over.
+    let extensions = request.extensions_mut();
+    extensions.insert(connection.clone());
+    if let Some(client_ip) = head.extensions.get::<ClientIp>() {
+        extensions.insert(*client_ip);
+    }
+    if let Some(correlation_id) = head.extensions.get::<CorrelationId>() {
+        extensions.insert(correlation_id.clone());
+    }
+    let (head, body) = request.into_parts();
+    http_compat::Request::from_parts(head, body)
+}
+
+/// Runs the operation `id`, sending its events to the client.
+async fn run(
+    service: BufferedService,
+    request: http_compat::Request<graphql::Request>,
+    id: String,
+    outgoing: mpsc::Sender<ProtocolMessage>,
+) {
+    let mut response = match service.oneshot(request).await {
+        Ok(response) => response,
+        Err(err) => {
+            tracing::error!("router service call failed: {}", err);
+            let _ = outgoing
+                .send(ProtocolMessage::Error {
+                    id,
+                    payload: vec![graphql::Error::from_box_error(&err)],
+                })
+                .await;
+            return;
+        }
+    };
+
+    if let Some(SubscriptionEvents(mut events)) = response.extensions_mut().remove() {
+        while let Some(event) = events.recv().await {
+            let next = ProtocolMessage::Next {
+                id: id.clone(),
+                payload: event,
+            };
+            if outgoing.send(next).await.is_err() {
+                return;
+            }
+        }
+    } else {
+        let message = match response.into_body() {
+            // Requests failing before they are executed end the operation with their errors.
+            ResponseBody::GraphQL(body) if body.data.is_none() && !body.errors.is_empty() => {
+                ProtocolMessage::Error {
+                    id: id.clone(),
+                    payload: body.errors,
+                }
+            }
+            ResponseBody::GraphQL(body) => ProtocolMessage::Next {
+                id: id.clone(),
+                payload: body,
+            },
+            _ => ProtocolMessage::Error {
+                id: id.clone(),
+                payload: vec![graphql::Error {
+                    message: "the operation was not answered with a GraphQL response".to_string(),
+                    ..Default::default()
+                }],
+            },
+        };
+        let failed = matches!(message, ProtocolMessage::Error { .. });
+        if outgoing.send(message).await.is_err() || failed {
+            return;
+        }
+    }
+    let _ = outgoing.send(ProtocolMessage::Complete { id }).await;
+}
@@ -0,0 +1,208 @@
+//! TLS termination on the GraphQL listener.
+//!
+//! Certificates are read when the server starts, and again whenever their files change, so that
+//! renewed certificates are used by new connections without restarting the router.
+
+use crate::configuration::Tls;
+use crate::files;
+use crate::FederatedServerError;
+use apollo_router_core::{read_certificates, read_private_key};
+use futures::prelude::*;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+
+/// Accepts TLS connections with the certificates currently on disk.
+pub(crate) struct TlsAcceptor {
+    current: Arc<RwLock<tokio_rustls::TlsAcceptor>>,
+    handshake_timeout: Duration,
+    watcher: JoinHandle<()>,
+}
+
+impl TlsAcceptor {
+    /// Reads the certificates of `config`, and watches their files for changes.
+    pub(crate) fn new(config: &Tls) -> Result<Self, FederatedServerError> {
+        let current = Arc::new(RwLock::new(acceptor(config)?));
+        let handshake_timeout = config.handshake_timeout;
+
+        let reloaded = current.clone();
+        let config = config.clone();
+        let mut changes = stream::select_all(
+            [
+                Some(&config.certificate),
+                Some(&config.key),
+                config.client_ca.as_ref(),
+            ]
+            .into_iter()
+            .flatten()
+            // Each watch first yields for the current file, which is already read.
+            .map(|path| files::watch(path.clone(), None).skip(1).boxed()),
+        );
+        let watcher = tokio::spawn(async move {
+            while changes.next().await.is_some() {
+                match acceptor(&config) {
+                    Ok(acceptor) => {
+                        *reloaded.write().expect("lock poisoned") = acceptor;
+                        tracing::info!("reloaded the TLS certificates");
+                    }
+                    // Connections keep being accepted with the previous certificates.
+                    Err(err) => tracing::error!("could not reload the TLS certificates: {}", err),
+                }
+            }
+        });
+
+        Ok(Self {
+            current,
+            handshake_timeout,
+            watcher,
+        })
+    }
+
+    /// Completes the TLS handshake, failing when the client takes longer than the handshake
+    /// timeout.
+    pub(crate) async fn accept(&self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        let acceptor = self.current.read().expect("lock poisoned").clone();
+        tokio::time::timeout(self.handshake_timeout, acceptor.accept(stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the TLS handshake timed out"))?
+    }
+}
+
+impl Drop for TlsAcceptor {
+    fn drop(&mut self) {
+        self.watcher.abort();
+    }
+}
+
+fn acceptor(config: &Tls) -> Result<tokio_rustls::TlsAcceptor, FederatedServerError> {
+    let invalid = |err: String| {
+        FederatedServerError::ServerCreationError(io::Error::new(io::ErrorKind::InvalidData, err))
+    };
+    let certificates =
+        read_certificates(&config.certificate).map_err(|err| invalid(err.to_string()))?;
+    let key = read_private_key(&config.key).map_err(|err| invalid(err.to_string()))?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(path).map_err(|err| invalid(err.to_string()))? {
+                roots.add(&certificate).map_err(|err| {
+                    invalid(format!(
+                        "invalid CA certificate in {}: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(certificates, key)
+        .map_err(|err| invalid(format!("invalid TLS certificate: {}", err)))?;
+    // Connections are served by hyper, which speaks both.
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// A TCP connection, over TLS when it is configured.
+pub(crate) enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            MaybeTlsStream::Plain(stream) => stream.is_write_vectored(),
+            MaybeTlsStream::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn testdata(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/testdata/tls")
+            .join(name)
+    }
+
+    #[tokio::test]
+    async fn certificates_are_read_from_pem_files() {
+        let config = Tls::builder()
+            .certificate(testdata("server.crt"))
+            .key(testdata("server.key"))
+            .client_ca(Some(testdata("ca.crt")))
+            .build();
+        assert!(TlsAcceptor::new(&config).is_ok());
+
+        let config = Tls::builder()
+            .certificate(testdata("server.key"))
+            .key(testdata("server.key"))
+            .build();
+        assert!(TlsAcceptor::new(&config).is_err());
+    }
+}
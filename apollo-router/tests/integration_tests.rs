@@ -236,6 +236,10 @@ async fn queries_should_work_over_post() {
 async fn service_errors_should_be_propagated() {
     let expected_error =apollo_router_core::Error {
         message :"value retrieval failed: couldn't plan query: query validation errors: UNKNOWN: Unknown operation named \"invalidOperationName\"".to_string(),
+        extensions: json!({ "code": "QUERY_PLANNING_FAILED" })
+            .as_object()
+            .unwrap()
+            .clone(),
         ..Default::default()
     };
 
@@ -508,6 +512,53 @@ async fn missing_variables() {
     );
 }
 
+#[tokio::test]
+async fn syntax_errors_are_reported() {
+    let request = graphql::Request::builder()
+        .query(Some("{ topProducts { name ! } }".to_string()))
+        .build();
+
+    let originating_request = http_compat::Request::fake_builder()
+        .method(Method::POST)
+        .body(request)
+        .build()
+        .expect("expecting valid request");
+
+    let (response, registry) = query_rust(originating_request.into()).await;
+
+    assert!(!response.errors.is_empty());
+    assert_eq!(
+        response.errors[0].extensions.get("code"),
+        Some(&"GRAPHQL_PARSE_FAILED".into())
+    );
+    assert_eq!(registry.totals(), hashmap! {});
+}
+
+#[tokio::test]
+async fn subscriptions_are_not_supported() {
+    let request = graphql::Request::builder()
+        .query(Some(
+            "subscription ReviewAdded { reviewAdded { body } }".to_string(),
+        ))
+        .operation_name(Some("ReviewAdded".to_string()))
+        .build();
+
+    let originating_request = http_compat::Request::fake_builder()
+        .method(Method::POST)
+        .body(request)
+        .build()
+        .expect("expecting valid request");
+
+    let (response, registry) = query_rust(originating_request.into()).await;
+
+    assert_eq!(1, response.errors.len());
+    assert_eq!(
+        response.errors[0].extensions.get("code"),
+        Some(&"OPERATION_NOT_SUPPORTED".into())
+    );
+    assert_eq!(registry.totals(), hashmap! {});
+}
+
 async fn query_node(request: &graphql::Request) -> Result<graphql::Response, graphql::FetchError> {
     Ok(reqwest::Client::new()
         .post("http://localhost:4100/graphql")